@@ -96,7 +96,7 @@ async fn main() -> Result<()> {
     println!("Restored prior context into a new agent instance.\n");
 
     let toon = restored_agent
-        .generate_toon(
+        .generate(
             session_id,
             "What codename did we pick and what did I share last?",
         )
@@ -35,6 +35,7 @@ impl Tool for CalculatorTool {
                 "required": ["operation", "a", "b"]
             }),
             examples: None,
+            output_schema: None,
         }
     }
 
@@ -75,6 +75,7 @@ impl Tool for WeatherTool {
                 "city": "Lisbon",
                 "unit": "celsius"
             })]),
+            output_schema: None,
         }
     }
 
@@ -0,0 +1,179 @@
+//! Procedural macros for rs-agent, re-exported by it; use them through
+//! `rs_agent::tools::tool`.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, Expr, FnArg, ItemFn, Lit, LitStr, Meta, Pat};
+
+/// Turns an async fn into a `Tool`.
+///
+/// ```ignore
+/// use rs_agent::tools::tool;
+///
+/// /// Returns the forecast for a city
+/// #[tool]
+/// async fn weather(city: String, days: Option<u32>) -> rs_agent::Result<String> {
+///     Ok(format!("Sunny in {} for {} days", city, days.unwrap_or(1)))
+/// }
+///
+/// agent.tools().register(Box::new(WeatherTool))?;
+/// ```
+///
+/// The fn stays as written; next to it the macro defines a unit struct
+/// named after it in `PascalCase` with a `Tool` suffix. The tool is named
+/// after the fn and described by its doc comment, and its input schema is
+/// generated with schemars from the parameter types, which must implement
+/// `Deserialize` and `JsonSchema`; `Option` parameters are optional. The fn
+/// may return anything implementing `IntoToolResponse`, such as `String`,
+/// `serde_json::Value`, `ToolResponse` or a `Result` of one.
+///
+/// `#[tool(name = "...", description = "...")]` overrides the name and
+/// description.
+#[proc_macro_attribute]
+pub fn tool(attr: TokenStream, item: TokenStream) -> TokenStream {
+    let function = parse_macro_input!(item as ItemFn);
+    let mut name = None;
+    let mut description = None;
+    let parser = syn::meta::parser(|meta| {
+        if meta.path.is_ident("name") {
+            name = Some(meta.value()?.parse::<LitStr>()?);
+            Ok(())
+        } else if meta.path.is_ident("description") {
+            description = Some(meta.value()?.parse::<LitStr>()?);
+            Ok(())
+        } else {
+            Err(meta.error("expected `name` or `description`"))
+        }
+    });
+    parse_macro_input!(attr with parser);
+    expand(function, name, description)
+        .unwrap_or_else(syn::Error::into_compile_error)
+        .into()
+}
+
+fn expand(
+    function: ItemFn,
+    name: Option<LitStr>,
+    description: Option<LitStr>,
+) -> syn::Result<proc_macro2::TokenStream> {
+    let sig = &function.sig;
+    if sig.asyncness.is_none() {
+        return Err(syn::Error::new_spanned(
+            sig.fn_token,
+            "#[tool] needs an async fn",
+        ));
+    }
+    if !sig.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(
+            &sig.generics,
+            "#[tool] fns cannot be generic",
+        ));
+    }
+
+    let mut params = Vec::new();
+    let mut types = Vec::new();
+    for input in &sig.inputs {
+        let FnArg::Typed(typed) = input else {
+            return Err(syn::Error::new_spanned(
+                input,
+                "#[tool] fns cannot take self",
+            ));
+        };
+        let Pat::Ident(pat) = typed.pat.as_ref() else {
+            return Err(syn::Error::new_spanned(
+                &typed.pat,
+                "#[tool] parameters must be plain names",
+            ));
+        };
+        params.push(pat.ident.clone());
+        types.push(typed.ty.as_ref().clone());
+    }
+
+    let function_name = &sig.ident;
+    let name = name.unwrap_or_else(|| LitStr::new(&function_name.to_string(), Span::call_site()));
+    let description = description
+        .unwrap_or_else(|| LitStr::new(&doc_comment(&function.attrs), Span::call_site()));
+    let vis = &function.vis;
+    let tool = format_ident!("{}Tool", pascal_case(&function_name.to_string()));
+    let args = format_ident!("__{}Args", tool);
+    let tool_doc = format!("Tool calling [`{}`]", function_name);
+
+    Ok(quote! {
+        #function
+
+        #[doc = #tool_doc]
+        #[derive(Debug, Clone, Copy, Default)]
+        #vis struct #tool;
+
+        #[doc(hidden)]
+        #[derive(
+            ::rs_agent::__private::serde::Deserialize,
+            ::rs_agent::__private::schemars::JsonSchema,
+        )]
+        #[serde(crate = "::rs_agent::__private::serde")]
+        #[schemars(crate = "::rs_agent::__private::schemars")]
+        struct #args {
+            #(#params: #types,)*
+        }
+
+        #[::rs_agent::__private::async_trait]
+        impl ::rs_agent::tools::Tool for #tool {
+            fn spec(&self) -> ::rs_agent::types::ToolSpec {
+                ::rs_agent::types::ToolSpec {
+                    name: #name.to_string(),
+                    description: #description.to_string(),
+                    input_schema: ::rs_agent::tools::typed::input_schema::<#args>(),
+                    examples: None,
+                    output_schema: None,
+                }
+            }
+
+            async fn invoke(
+                &self,
+                req: ::rs_agent::types::ToolRequest,
+            ) -> ::rs_agent::Result<::rs_agent::types::ToolResponse> {
+                let #args { #(#params),* } =
+                    ::rs_agent::tools::typed::parse_arguments(req.arguments)?;
+                ::rs_agent::tools::typed::IntoToolResponse::into_tool_response(
+                    #function_name(#(#params),*).await,
+                )
+            }
+        }
+    })
+}
+
+/// Joins the `///` lines of an item
+fn doc_comment(attrs: &[syn::Attribute]) -> String {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|attr| attr.path().is_ident("doc"))
+        .filter_map(|attr| match &attr.meta {
+            Meta::NameValue(doc) => match &doc.value {
+                Expr::Lit(lit) => match &lit.lit {
+                    Lit::Str(text) => {
+                        Some(text.value().trim().to_string()).filter(|line| !line.is_empty())
+                    }
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect();
+    lines.join(" ")
+}
+
+/// Turns `get_weather` into `GetWeather`
+fn pascal_case(name: &str) -> String {
+    name.split('_')
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let mut chars = part.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default()
+        })
+        .collect()
+}
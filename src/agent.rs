@@ -3,11 +3,14 @@
 //! This module provides the main Agent struct that coordinates LLM calls, memory,
 //! tool invocations, and UTCP integration. Matches the structure from go-agent's agent.go.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use anyhow::anyhow;
 use chrono::Utc;
+use futures::stream::{self, BoxStream, StreamExt};
 use futures::FutureExt;
 use rs_utcp::plugins::codemode::{CodeModeUtcp, CodemodeOrchestrator};
 use rs_utcp::providers::base::Provider as UtcpProvider;
@@ -15,20 +18,433 @@ use rs_utcp::providers::cli::CliProvider;
 use rs_utcp::tools::Tool as UtcpTool;
 use rs_utcp::tools::ToolInputOutputSchema;
 use rs_utcp::UtcpClientInterface;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
 use serde_json::{json, Value};
 use toon_format::encode_default;
+use tracing::Instrument;
 use uuid::Uuid;
 
 use crate::agent_orchestrators::{build_orchestrator, format_codemode_value, CodeModeTool};
 use crate::agent_tool::{ensure_agent_cli_transport, InProcessTool};
+use crate::aggregate::SubAgentOutput;
+use crate::blob::{BlobOffload, ExpandBlobTool};
+use crate::cancel::TurnLimit;
+use crate::checkpoint::CheckpointStore;
+use crate::context::{ContextComposer, ContextRequest, ContextStrategy};
+use crate::cost::{CostBudget, CostLedger, CostReport};
 use crate::error::{AgentError, Result};
-use crate::memory::{MemoryRecord, SessionMemory};
-use crate::models::LLM;
-use crate::tools::ToolCatalog;
+use crate::experiment::{Experiment, ExperimentArm};
+use crate::guardrail::{Scanned, StreamGuard, StreamScan, GUARDRAIL_VIOLATION_KEY};
+use crate::hooks::Hooks;
+use crate::idempotency::IdempotencyCache;
+use crate::language::LanguagePolicy;
+use crate::memory::importance::DEFAULT_IMPORTANCE;
+use crate::memory::{
+    mmr_rerank_with, Embedder, ImageQuery, ImportanceScorer, MemoryFilter, MemoryRecord,
+    SessionMemory, SOURCE_SESSION_KEY, TRUNCATED_KEY,
+};
+use crate::models::{
+    GenerationSettings, ModelLimitRegistry, ModelRoutingPolicy, PricingRegistry, Tokenizer,
+    TokenizerRegistry, LLM, MODEL_ROUTE_KEY,
+};
+use crate::plan::{ExecutionPlan, StepTarget};
+use crate::prompts::{PromptRegistry, PromptVersion};
+use crate::query::{RetrievalPlan, RetrievalPolicy};
+use crate::reload::OptionsWatcher;
+use crate::routing::{timed, RoutePath, RouteTimer, RoutingLog, RoutingReport};
+use crate::structured::{parse_structured, schema_instruction, DEFAULT_STRUCTURED_RETRIES};
+use crate::telemetry::{ModelCall, ModelUsage};
+use crate::tools::limits::ToolLoopTracker;
+use crate::tools::namespace::ToolNamespaces;
+use crate::tools::policy::{ToolCallContext, ToolPolicy};
+use crate::tools::select::{tools_instruction, ToolSelection, ToolSelector};
+use crate::tools::{
+    arguments_hash, ToolBatch, ToolCall, ToolCatalog, ToolLoopLimits, ToolOutputGuard,
+    ToolOutputProcessor, ToolRegistry, ToolSchemaFormat, ToolSchemaSnapshots,
+    DEFAULT_TOOL_PARALLELISM, LOCAL_PROVIDER, TOOL_ARGS_HASH_KEY, TOOL_LATENCY_MS_KEY,
+    TOOL_NAME_KEY, TOOL_PROVIDER_KEY, TOOL_STATUS_KEY,
+};
 use crate::types::{AgentOptions, AgentState, File, GenerationResponse, Message, Role, ToolRequest};
+use crate::types::{SubAgent, SubAgentDirectory, SubAgentInfo};
+use crate::utcp::UtcpDiscovery;
 
 const DEFAULT_SYSTEM_PROMPT: &str = "You are a helpful AI assistant. Provide concise, accurate answers and explain when you use tools.";
 
+/// How long prefetched retrievals stay usable
+const PREFETCH_TTL: Duration = Duration::from_secs(30);
+/// Share of the final input a prefetched prefix must cover to be reused
+const PREFETCH_MIN_COVERAGE: f32 = 0.75;
+/// Sessions `generate_batch` runs at once by default
+const DEFAULT_BATCH_CONCURRENCY: usize = 8;
+/// Chunks `generate_stream` receives between saves of the partial reply
+const DEFAULT_STREAM_SAVE_INTERVAL: usize = 16;
+/// Incremental checkpoints chained on one full checkpoint by default
+const DEFAULT_CHECKPOINT_CHAIN_LIMIT: usize = 16;
+
+/// Settings [`Agent::update_options`] can swap while the agent is serving
+#[derive(Clone)]
+pub(crate) struct RuntimeOptions {
+    system_prompt: String,
+    context_limit: usize,
+    pub(crate) tool_guard: Option<ToolOutputGuard>,
+    context_strategy: Option<Arc<dyn ContextStrategy>>,
+    model_routing: Option<Arc<ModelRoutingPolicy>>,
+    cost_budget: Option<CostBudget>,
+    tool_selection: Option<ToolSelection>,
+}
+
+impl From<AgentOptions> for RuntimeOptions {
+    fn from(options: AgentOptions) -> Self {
+        Self {
+            system_prompt: options
+                .system_prompt
+                .unwrap_or_else(|| DEFAULT_SYSTEM_PROMPT.to_string()),
+            context_limit: options.context_limit.unwrap_or(8192),
+            tool_guard: options.tool_guard,
+            context_strategy: options.context_strategy,
+            model_routing: options.model_routing,
+            cost_budget: options.cost_budget,
+            tool_selection: options.tool_selection,
+        }
+    }
+}
+
+/// Retrieval results fetched ahead of a generation call
+struct Prefetched {
+    input: String,
+    records: Vec<MemoryRecord>,
+    fetched_at: Instant,
+}
+
+impl Prefetched {
+    /// Returns true if these results can stand in for retrieving `input`
+    fn covers(&self, input: &str, now: Instant) -> bool {
+        let prefix = self.input.trim();
+        let input = input.trim();
+        now.duration_since(self.fetched_at) <= PREFETCH_TTL
+            && !prefix.is_empty()
+            && input.starts_with(prefix)
+            && prefix.len() as f32 >= input.len() as f32 * PREFETCH_MIN_COVERAGE
+    }
+}
+
+/// An input embedded for storage and for search, which differ for
+/// embedders that prefix or otherwise treat queries differently
+#[derive(Clone)]
+struct InputEmbedding {
+    document: Vec<f32>,
+    query: Vec<f32>,
+}
+
+/// Outcome of preparing a turn
+enum Turn<'a> {
+    /// CodeMode orchestration already answered, no model call needed
+    Answered(GenerationResponse),
+    Ready(Box<PreparedTurn<'a>>),
+}
+
+/// A turn ready for the model call
+struct PreparedTurn<'a> {
+    model: Arc<dyn LLM>,
+    messages: Vec<Message>,
+    files: Option<Vec<File>>,
+    attribution: Attribution<'a>,
+    budget: Option<CostBudget>,
+}
+
+/// What produced a response: prompt version, experiment arm and model route
+struct Attribution<'a> {
+    prompt: Option<PromptVersion>,
+    arm: Option<(&'a Arc<Experiment>, &'a ExperimentArm)>,
+    route: Option<&'static str>,
+    /// Names of the tools selected for the turn
+    tools: Vec<String>,
+}
+
+impl Attribution<'_> {
+    /// Tags `response` and records it with the experiment, returning the
+    /// metadata to store with the assistant turn
+    fn apply(self, response: &mut GenerationResponse) -> Option<HashMap<String, String>> {
+        let mut attribution = self.prompt.map(|p| p.metadata()).unwrap_or_default();
+        if let Some((experiment, arm)) = self.arm {
+            attribution.extend(experiment.metadata(arm));
+            experiment.record_response(&arm.name);
+        }
+        if let Some(name) = self.route {
+            attribution.insert(MODEL_ROUTE_KEY.to_string(), name.to_string());
+        }
+        if !self.tools.is_empty() {
+            attribution.insert(
+                crate::tools::SELECTED_TOOLS_KEY.to_string(),
+                serde_json::to_string(&self.tools).unwrap_or_default(),
+            );
+        }
+        if attribution.is_empty() {
+            return None;
+        }
+        response
+            .metadata
+            .get_or_insert_with(HashMap::new)
+            .extend(attribution.clone());
+        Some(attribution)
+    }
+}
+
+/// Assistant output of a streamed turn, saved to memory as it grows
+struct PartialTurn {
+    memory: Arc<SessionMemory>,
+    hooks: Vec<Arc<dyn Hooks>>,
+    record: MemoryRecord,
+    /// Chunks received since the last save
+    unsaved: usize,
+    /// Set once the final record is stored or the stream failed
+    finished: bool,
+    /// Tenant the turn runs on behalf of, for saves after it is dropped
+    tenant: Option<String>,
+}
+
+impl PartialTurn {
+    fn new(memory: Arc<SessionMemory>, hooks: Vec<Arc<dyn Hooks>>, session_id: &str) -> Self {
+        Self {
+            memory,
+            hooks,
+            record: MemoryRecord {
+                id: Uuid::new_v4(),
+                session_id: session_id.to_string(),
+                role: "assistant".to_string(),
+                content: String::new(),
+                importance: DEFAULT_IMPORTANCE,
+                timestamp: Utc::now(),
+                metadata: None,
+                embedding: None,
+                version: 0,
+                expires_at: None,
+            },
+            unsaved: 0,
+            finished: false,
+            tenant: crate::tenant::current(),
+        }
+    }
+
+    fn push(&mut self, chunk: &str) {
+        self.record.content.push_str(chunk);
+        self.unsaved += 1;
+    }
+
+    /// The record as saved before the model finishes
+    fn truncated(&self) -> MemoryRecord {
+        let mut record = self.record.clone();
+        record
+            .metadata
+            .get_or_insert_with(HashMap::new)
+            .insert(TRUNCATED_KEY.to_string(), "true".to_string());
+        record
+    }
+
+    /// Saves the output so far; failures only cost durability, not the turn
+    async fn save(&mut self) {
+        if self.record.content.is_empty() {
+            return;
+        }
+        let record = self.truncated();
+        if let Err(e) = store_hooked(&self.memory, &self.hooks, record).await {
+            tracing::warn!("Saving partial response failed: {}", e);
+        }
+        self.unsaved = 0;
+    }
+}
+
+/// Runs the memory hooks on `record`, then stores it
+async fn store_hooked(
+    memory: &SessionMemory,
+    hooks: &[Arc<dyn Hooks>],
+    mut record: MemoryRecord,
+) -> Result<()> {
+    for hooks in hooks {
+        hooks.on_memory_store(&mut record).await?;
+    }
+    memory.store(record).await
+}
+
+impl Drop for PartialTurn {
+    fn drop(&mut self) {
+        if self.finished || self.unsaved == 0 || self.record.content.is_empty() {
+            return;
+        }
+        // Cancelled mid-stream: keep what arrived since the last save
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        let (memory, hooks, record) = (
+            Arc::clone(&self.memory),
+            self.hooks.clone(),
+            self.truncated(),
+        );
+        let tenant = self.tenant.clone();
+        runtime.spawn(crate::tenant::scope(tenant, async move {
+            if let Err(e) = store_hooked(&memory, &hooks, record).await {
+                tracing::warn!("Saving cancelled response failed: {}", e);
+            }
+        }));
+    }
+}
+
+/// Model call of a streamed turn, charged for what it consumed however the
+/// stream ends: in full when it finishes, and for the prompt and the chunks
+/// streamed so far when it fails or is dropped
+struct StreamCall<'a> {
+    agent: &'a Agent,
+    session_id: String,
+    budget: Option<CostBudget>,
+    call: Option<ModelCall>,
+}
+
+impl StreamCall<'_> {
+    fn push(&mut self, chunk: &str) {
+        if let Some(call) = &mut self.call {
+            call.stream(chunk);
+        }
+    }
+
+    /// Charges the finished call for `reply`
+    fn finish(mut self, reply: &str) {
+        if let Some(call) = self.call.take() {
+            let usage = call.finish(Some(reply));
+            self.agent
+                .charge(&self.session_id, usage, self.budget.as_ref());
+        }
+    }
+}
+
+impl Drop for StreamCall<'_> {
+    fn drop(&mut self) {
+        if let Some(call) = self.call.take() {
+            let usage = call.interrupt();
+            self.agent
+                .charge(&self.session_id, Some(usage), self.budget.as_ref());
+        }
+    }
+}
+
+/// Adds structured provenance for filters and analytics to the metadata of
+/// a tool result
+fn tool_metadata(
+    tool_name: &str,
+    args_hash: String,
+    latency: Duration,
+    metadata: Option<HashMap<String, String>>,
+) -> HashMap<String, String> {
+    let mut metadata = metadata.unwrap_or_default();
+    let provider = metadata
+        .get("provider")
+        .cloned()
+        .unwrap_or_else(|| LOCAL_PROVIDER.to_string());
+    metadata.insert(TOOL_NAME_KEY.to_string(), tool_name.to_string());
+    metadata.insert(TOOL_ARGS_HASH_KEY.to_string(), args_hash);
+    metadata.insert(
+        TOOL_LATENCY_MS_KEY.to_string(),
+        latency.as_millis().to_string(),
+    );
+    metadata.insert(TOOL_PROVIDER_KEY.to_string(), provider);
+    metadata
+}
+
+/// Output of a streamed tool call, stored and routed once the stream ends
+struct PartialToolCall<'a> {
+    agent: &'a Agent,
+    session_id: String,
+    tool_name: String,
+    args_hash: String,
+    output: crate::types::ToolResponse,
+    started: Instant,
+    /// Tenant the call runs on behalf of, for saves after it is dropped
+    tenant: Option<String>,
+    /// Taken once the call is routed
+    timer: Option<RouteTimer>,
+}
+
+impl PartialToolCall<'_> {
+    /// Stores the complete output and routes the call
+    async fn finish(&mut self) -> Result<()> {
+        let Some(mut timer) = self.timer.take() else {
+            return Ok(());
+        };
+        let latency = self.started.elapsed();
+        timer.stage("tool", latency);
+        let stored_at = Instant::now();
+        let stored = self
+            .agent
+            .store_tool_result(
+                &self.session_id,
+                &self.tool_name,
+                self.args_hash.clone(),
+                latency,
+                &self.output,
+            )
+            .await;
+        timer.stage_since("store", stored_at);
+        let event = timer.finish(
+            RoutePath::Tool,
+            Some(self.tool_name.clone()),
+            stored.is_ok(),
+        );
+        self.agent.routing.record(&self.session_id, event);
+        stored
+    }
+}
+
+impl Drop for PartialToolCall<'_> {
+    fn drop(&mut self) {
+        let Some(mut timer) = self.timer.take() else {
+            return;
+        };
+        // Failed or dropped mid-stream: route the call as failed and keep
+        // what arrived
+        let latency = self.started.elapsed();
+        timer.stage("tool", latency);
+        let event = timer.finish(RoutePath::Tool, Some(self.tool_name.clone()), false);
+        self.agent.routing.record(&self.session_id, event);
+        if self.output.content.is_empty() {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        // Screened like a complete result; the output processors need the
+        // agent, so they are skipped
+        let output = match &self.agent.options().tool_guard {
+            Some(guard) => guard.guard(&self.tool_name, self.output.clone()),
+            None => self.output.clone(),
+        };
+        let mut metadata = tool_metadata(
+            &self.tool_name,
+            self.args_hash.clone(),
+            latency,
+            output.metadata,
+        );
+        metadata.insert(TRUNCATED_KEY.to_string(), "true".to_string());
+        let record = MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: self.session_id.clone(),
+            role: "tool".to_string(),
+            content: output.content,
+            importance: DEFAULT_IMPORTANCE,
+            timestamp: Utc::now(),
+            metadata: Some(metadata),
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        };
+        let (memory, hooks) = (Arc::clone(&self.agent.memory), self.agent.hooks.clone());
+        runtime.spawn(crate::tenant::scope(self.tenant.clone(), async move {
+            if let Err(e) = store_hooked(&memory, &hooks, record).await {
+                tracing::warn!("Saving interrupted tool output failed: {}", e);
+            }
+        }));
+    }
+}
+
 /// Main Agent orchestrator
 ///
 /// The Agent coordinates model calls, memory, tools, and sub-agents. It matches
@@ -36,11 +452,40 @@ const DEFAULT_SYSTEM_PROMPT: &str = "You are a helpful AI assistant. Provide con
 pub struct Agent {
     model: Arc<dyn LLM>,
     memory: Arc<SessionMemory>,
-    system_prompt: String,
-    context_limit: usize,
-    tool_catalog: Arc<ToolCatalog>,
+    options: parking_lot::RwLock<Arc<RuntimeOptions>>,
+    tool_catalog: Arc<dyn ToolRegistry>,
+    tool_schemas: ToolSchemaSnapshots,
+    tool_selector: Option<ToolSelector>,
+    utcp_discovery: Option<UtcpDiscovery>,
+    subagents: Option<Arc<dyn SubAgentDirectory>>,
     codemode: Option<Arc<CodeModeUtcp>>,
     codemode_orchestrator: Option<Arc<CodemodeOrchestrator>>,
+    prompt_registry: Option<(Arc<PromptRegistry>, String)>,
+    experiment: Option<Arc<Experiment>>,
+    blob_offload: Option<BlobOffload>,
+    importance_scorer: Option<Arc<dyn ImportanceScorer>>,
+    tool_output: Option<ToolOutputProcessor>,
+    tool_loop: Option<ToolLoopTracker>,
+    tool_policy: Option<Arc<ToolPolicy>>,
+    namespaces: Arc<ToolNamespaces>,
+    hooks: Vec<Arc<dyn Hooks>>,
+    retrieval: Option<(Arc<dyn Embedder>, usize)>,
+    retrieval_policy: Option<RetrievalPolicy>,
+    model_limits: ModelLimitRegistry,
+    tokenizers: TokenizerRegistry,
+    pricing: PricingRegistry,
+    language: Option<LanguagePolicy>,
+    stream_guard: Option<Arc<StreamGuard>>,
+    structured_retries: usize,
+    composer: ContextComposer,
+    restore_keep_last: Option<usize>,
+    checkpoint_chain_limit: usize,
+    batch_concurrency: usize,
+    stream_save_interval: usize,
+    prefetched: parking_lot::Mutex<HashMap<String, Prefetched>>,
+    routing: RoutingLog,
+    costs: CostLedger,
+    idempotency: IdempotencyCache,
 }
 
 impl Agent {
@@ -49,25 +494,440 @@ impl Agent {
         Self {
             model,
             memory,
-            system_prompt: options
-                .system_prompt
-                .unwrap_or_else(|| DEFAULT_SYSTEM_PROMPT.to_string()),
-            context_limit: options.context_limit.unwrap_or(8192),
+            options: parking_lot::RwLock::new(Arc::new(RuntimeOptions::from(options))),
             tool_catalog: Arc::new(ToolCatalog::new()),
+            tool_schemas: ToolSchemaSnapshots::new(),
+            tool_selector: None,
+            utcp_discovery: None,
+            subagents: None,
             codemode: None,
             codemode_orchestrator: None,
+            prompt_registry: None,
+            experiment: None,
+            blob_offload: None,
+            importance_scorer: None,
+            tool_output: None,
+            tool_loop: None,
+            tool_policy: None,
+            namespaces: Arc::new(ToolNamespaces::new()),
+            hooks: Vec::new(),
+            retrieval: None,
+            retrieval_policy: None,
+            model_limits: ModelLimitRegistry::new(),
+            tokenizers: TokenizerRegistry::new(),
+            pricing: PricingRegistry::new(),
+            language: None,
+            stream_guard: None,
+            structured_retries: DEFAULT_STRUCTURED_RETRIES,
+            composer: ContextComposer::default(),
+            restore_keep_last: None,
+            checkpoint_chain_limit: DEFAULT_CHECKPOINT_CHAIN_LIMIT,
+            batch_concurrency: DEFAULT_BATCH_CONCURRENCY,
+            stream_save_interval: DEFAULT_STREAM_SAVE_INTERVAL,
+            prefetched: parking_lot::Mutex::new(HashMap::new()),
+            routing: RoutingLog::default(),
+            costs: CostLedger::default(),
+            idempotency: IdempotencyCache::default(),
         }
     }
 
     /// Sets the system prompt
     pub fn with_system_prompt(mut self, prompt: impl Into<String>) -> Self {
-        self.system_prompt = prompt.into();
+        Arc::make_mut(self.options.get_mut()).system_prompt = prompt.into();
+        self
+    }
+
+    /// Swaps in new options while the agent keeps serving.
+    ///
+    /// Fields left `None` keep their current value; use
+    /// [`Agent::replace_options`] to turn settings off. Turns already running
+    /// finish with the options they started with; memory, tools and caches
+    /// are untouched.
+    pub fn update_options(&self, options: AgentOptions) {
+        let mut current = self.options.write();
+        let mut updated = RuntimeOptions::clone(&current);
+        if let Some(system_prompt) = options.system_prompt {
+            updated.system_prompt = system_prompt;
+        }
+        if let Some(context_limit) = options.context_limit {
+            updated.context_limit = context_limit;
+        }
+        if let Some(guard) = options.tool_guard {
+            updated.tool_guard = Some(guard);
+        }
+        if let Some(strategy) = options.context_strategy {
+            updated.context_strategy = Some(strategy);
+        }
+        if let Some(routing) = options.model_routing {
+            updated.model_routing = Some(routing);
+        }
+        if let Some(budget) = options.cost_budget {
+            updated.cost_budget = Some(budget);
+        }
+        if let Some(selection) = options.tool_selection {
+            updated.tool_selection = Some(selection);
+        }
+        *current = Arc::new(updated);
+    }
+
+    /// Replaces all options while the agent keeps serving.
+    ///
+    /// Unlike [`Agent::update_options`], fields left `None` are reset: the
+    /// system prompt and context limit to their defaults, and the guard,
+    /// strategy, routing, budget and tool selection to off.
+    pub fn replace_options(&self, options: AgentOptions) {
+        *self.options.write() = Arc::new(RuntimeOptions::from(options));
+    }
+
+    /// Replaces the options an options file can hold, keeping the context
+    /// strategy and model routing, which it can't
+    pub(crate) fn reload_options(&self, mut options: AgentOptions) {
+        let mut current = self.options.write();
+        options.context_strategy = current.context_strategy.clone();
+        options.model_routing = current.model_routing.clone();
+        *current = Arc::new(RuntimeOptions::from(options));
+    }
+
+    /// Reloads options from the JSON file at `path` whenever it changes.
+    ///
+    /// The file is checked every `interval` and holds the whole
+    /// configuration: settings missing from it are reset, as by
+    /// [`Agent::replace_options`], except the context strategy and model
+    /// routing, which files can't hold. A file that fails to parse is logged
+    /// and skipped. The watcher stops when the returned handle is dropped or
+    /// the agent is.
+    pub fn watch_options(
+        self: &Arc<Self>,
+        path: impl Into<PathBuf>,
+        interval: Duration,
+    ) -> OptionsWatcher {
+        OptionsWatcher::spawn(Arc::downgrade(self), path.into(), interval)
+    }
+
+    /// The options currently in effect
+    pub(crate) fn options(&self) -> Arc<RuntimeOptions> {
+        self.options.read().clone()
+    }
+
+    /// Sends each input to the fast or strong model of `policy`, by how
+    /// complex it looks.
+    ///
+    /// Experiment arms with their own model take precedence. Responses are
+    /// tagged with the route under `model_route`.
+    pub fn with_model_routing(mut self, policy: Arc<ModelRoutingPolicy>) -> Self {
+        Arc::make_mut(self.options.get_mut()).model_routing = Some(policy);
+        self
+    }
+
+    /// Resolves the system prompt per session from `registry`.
+    ///
+    /// The selected version is recorded in each response's metadata; the
+    /// static system prompt is used when the registry has no usable variant.
+    pub fn with_prompt_registry(
+        mut self,
+        registry: Arc<PromptRegistry>,
+        prompt_name: impl Into<String>,
+    ) -> Self {
+        self.prompt_registry = Some((registry, prompt_name.into()));
+        self
+    }
+
+    /// Runs every generation under `experiment`.
+    ///
+    /// Each session is assigned an arm whose prompt, model, and context limit
+    /// override the agent's own; responses are tagged with the experiment and
+    /// arm, and counted in the arm's results.
+    pub fn with_experiment(mut self, experiment: Arc<Experiment>) -> Self {
+        self.experiment = Some(experiment);
+        self
+    }
+
+    /// Offloads oversized tool outputs to a blob store before they reach memory.
+    ///
+    /// Memory keeps a preview plus a blob reference, and `blob.expand` is
+    /// registered so the model can fetch the full output when it needs it.
+    pub fn with_blob_offload(mut self, offload: BlobOffload) -> Self {
+        // Ignore duplicate registrations, as with codemode.run_code
+        let _ = self
+            .tool_catalog
+            .register(Box::new(ExpandBlobTool::new(offload.store())));
+        self.blob_offload = Some(offload);
+        self
+    }
+
+    /// Caps tool outputs at a token budget before they are fed back to the model.
+    ///
+    /// With blob offload too, the full output is offloaded first and only
+    /// the inline preview is capped, so nothing is lost that the model can't
+    /// expand.
+    pub fn with_tool_output_processor(mut self, processor: ToolOutputProcessor) -> Self {
+        self.tool_output = Some(processor);
+        self
+    }
+
+    /// Screens tool outputs for prompt injection before they reach memory.
+    ///
+    /// Runs first, so truncation, summarization and blob offload only ever
+    /// see screened output.
+    pub fn with_tool_output_guard(mut self, guard: ToolOutputGuard) -> Self {
+        Arc::make_mut(self.options.get_mut()).tool_guard = Some(guard);
+        self
+    }
+
+    /// Caps the tool calls `invoke_tool` accepts per session between two
+    /// user inputs, failing with `AgentError::ToolLoopDetected` past the
+    /// limits
+    pub fn with_tool_loop_limits(mut self, limits: ToolLoopLimits) -> Self {
+        self.tool_loop = Some(ToolLoopTracker::new(limits));
+        self
+    }
+
+    /// Checks each call of `invoke_tool` against `policy`, with the user
+    /// the session is assigned to; denied calls fail with
+    /// `AgentError::ToolDenied` without running the tool.
+    ///
+    /// CodeMode calls tools out of the policy's sight, so orchestration and
+    /// `codemode.run_code` are off in sessions where the policy could deny
+    /// anything.
+    pub fn with_tool_policy(mut self, policy: ToolPolicy) -> Self {
+        self.tool_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Enables and disables tool namespaces with `namespaces`, which agents
+    /// sharing a catalog may share too or each keep their own
+    pub fn with_tool_namespaces(mut self, namespaces: Arc<ToolNamespaces>) -> Self {
+        self.namespaces = namespaces;
+        self
+    }
+
+    /// Returns the tool namespace settings, to enable or disable namespaces
+    /// per session while the agent is serving
+    pub fn tool_namespaces(&self) -> &ToolNamespaces {
+        &self.namespaces
+    }
+
+    /// Returns false if the session's namespaces or the tool policy deny
+    /// every call of `tool_name` in `session_id`, whatever its arguments
+    pub fn may_call_tool(&self, session_id: &str, tool_name: &str) -> bool {
+        self.namespaces.is_enabled(session_id, tool_name)
+            && self.policy_may_call(session_id, tool_name)
+    }
+
+    /// Returns false if the tool policy denies every call of `tool_name` in
+    /// `session_id`, whatever its arguments
+    fn policy_may_call(&self, session_id: &str, tool_name: &str) -> bool {
+        if self.is_codemode_tool(tool_name) && !self.policy_allows_everything(session_id) {
+            return false;
+        }
+        self.tool_policy.as_ref().is_none_or(|policy| {
+            let user_id = self.memory.user_of(session_id);
+            // Without arguments only the rules apply, not the constraints
+            let call = ToolCallContext {
+                session_id,
+                user_id: user_id.as_deref(),
+                tool: tool_name,
+                arguments: &HashMap::new(),
+            };
+            policy.check(&call).is_ok()
+        })
+    }
+
+    /// Registers lifecycle hooks; hooks run in the order they were added
+    pub fn with_hooks(mut self, hooks: Arc<dyn Hooks>) -> Self {
+        self.hooks.push(hooks);
+        self
+    }
+
+    /// Adds up to `limit` semantically relevant memories to each prompt.
+    ///
+    /// The user input is embedded with `embedder` and searched against the
+    /// session's memory, plus the memory of the user bound to the session
+    /// (see [`SessionMemory::bind_user`]). This runs while the turn is being
+    /// stored and routed, so retrieval adds little to first-token latency.
+    /// See [`Agent::prefetch`] to start it before the input is even complete.
+    pub fn with_retrieval(mut self, embedder: Arc<dyn Embedder>, limit: usize) -> Self {
+        self.retrieval = Some((embedder, limit));
+        self
+    }
+
+    /// Lists only the tools relevant to each input in the system prompt.
+    ///
+    /// Tool descriptions and inputs are embedded with `embedder` and matched
+    /// as configured by `AgentOptions::tool_selection`; without that option
+    /// no tools are listed. Selection runs alongside retrieval, among the
+    /// tools the turn may call, and the response names the selection under
+    /// `SELECTED_TOOLS_KEY` for callers that send a provider tool payload.
+    pub fn with_tool_selector(mut self, embedder: Arc<dyn Embedder>) -> Self {
+        self.tool_selector = Some(ToolSelector::new(embedder));
+        self
+    }
+
+    /// Finds missing tools in a UTCP repository with `discovery`.
+    ///
+    /// Calling a tool that isn't registered first searches the repository
+    /// for its name and registers the tool of that exact name. With tool
+    /// selection, a turn where no registered tool is relevant searches for
+    /// the input and lists the relevant matches without registering them;
+    /// turns limited to some tools don't.
+    pub fn with_utcp_discovery(mut self, discovery: UtcpDiscovery) -> Self {
+        self.utcp_discovery = Some(discovery);
+        self
+    }
+
+    /// Sizes retrieval by what each input asks for, using
+    /// [`classify_query`](crate::query::classify_query).
+    ///
+    /// Math and unknown inputs skip retrieval, short factoids pull a small
+    /// top-k, and complex inputs over-fetch and keep a diverse set with MMR.
+    /// Without a policy every turn retrieves the full limit.
+    pub fn with_retrieval_policy(mut self, policy: RetrievalPolicy) -> Self {
+        self.retrieval_policy = Some(policy);
+        self
+    }
+
+    /// Overrides the built-in context and output limits of known models.
+    ///
+    /// The prompt budget is the configured context limit, capped at what the
+    /// model can read once a full response is reserved.
+    pub fn with_model_limits(mut self, limits: ModelLimitRegistry) -> Self {
+        self.model_limits = limits;
+        self
+    }
+
+    /// Overrides the built-in token prices of known models, used to compute
+    /// the cost of each generation
+    pub fn with_pricing(mut self, pricing: PricingRegistry) -> Self {
+        self.pricing = pricing;
+        self
+    }
+
+    /// Checks the language of each reply and, if it is not the one `policy`
+    /// expects, asks the model once more to answer in that language.
+    ///
+    /// Streamed replies have already been sent and are not checked.
+    pub fn with_language_policy(mut self, policy: LanguagePolicy) -> Self {
+        self.language = Some(policy);
+        self
+    }
+
+    /// Scans replies of [`Agent::generate_stream`] as they stream and stops
+    /// one that violates `guard`, ending the stream with its redaction
+    /// message instead of the rest of the output
+    pub fn with_stream_guard(mut self, guard: StreamGuard) -> Self {
+        self.stream_guard = Some(Arc::new(guard));
+        self
+    }
+
+    /// Sets how many times [`Agent::generate_typed`] sends an invalid reply
+    /// back to the model before failing
+    pub fn with_structured_retries(mut self, retries: usize) -> Self {
+        self.structured_retries = retries;
+        self
+    }
+
+    /// Chooses how tokens are counted for each model when fitting context
+    /// into the budget.
+    ///
+    /// The system prompt and user input are counted too, and the remaining
+    /// budget goes to memory.
+    pub fn with_tokenizers(mut self, tokenizers: TokenizerRegistry) -> Self {
+        self.tokenizers = tokenizers;
+        self
+    }
+
+    /// Controls how recent turns, summaries and recalled memories are
+    /// ordered and labeled in the prompt
+    pub fn with_context_composer(mut self, composer: ContextComposer) -> Self {
+        self.composer = composer;
+        self
+    }
+
+    /// Packs the prompt with `strategy` instead of the context composer,
+    /// e.g. [`SlidingWindow`](crate::context::SlidingWindow) or
+    /// [`SummarizeOlder`](crate::context::SummarizeOlder)
+    pub fn with_context_strategy(mut self, strategy: Arc<dyn ContextStrategy>) -> Self {
+        Arc::make_mut(self.options.get_mut()).context_strategy = Some(strategy);
+        self
+    }
+
+    /// Compacts restored history into a summary plus the last `keep_last`
+    /// turns.
+    ///
+    /// Requires a compactor on the session memory
+    /// ([`SessionMemory::with_compactor`]); without one, checkpoints are
+    /// restored in full.
+    pub fn with_restore_compaction(mut self, keep_last: usize) -> Self {
+        self.restore_keep_last = Some(keep_last);
+        self
+    }
+
+    /// Makes every `limit + 1`th checkpoint of
+    /// [`Agent::save_incremental_checkpoint`] a full one, bounding how many
+    /// checkpoints a restore reads
+    pub fn with_checkpoint_chain_limit(mut self, limit: usize) -> Self {
+        self.checkpoint_chain_limit = limit;
+        self
+    }
+
+    /// Bounds how many sessions [`Agent::generate_batch`] runs at once
+    pub fn with_batch_concurrency(mut self, limit: usize) -> Self {
+        self.batch_concurrency = limit.max(1);
         self
     }
 
-    /// Sets the tool catalog
-    pub fn with_tools(mut self, catalog: Arc<ToolCatalog>) -> Self {
+    /// Saves the partial reply of [`Agent::generate_stream`] every `chunks`
+    /// chunks; lower values lose less to a crash at the cost of more writes
+    pub fn with_stream_save_interval(mut self, chunks: usize) -> Self {
+        self.stream_save_interval = chunks.max(1);
+        self
+    }
+
+    /// Remembers the responses of turns with an idempotency key for
+    /// `window` instead of a day
+    pub fn with_idempotency_window(mut self, window: Duration) -> Self {
+        self.idempotency = IdempotencyCache::new(window);
+        self
+    }
+
+    /// Scores each stored memory with `scorer` instead of a flat default.
+    ///
+    /// Importance decides which history survives when the context budget is
+    /// tight, and which records a memory compactor may pin.
+    pub fn with_importance_scorer(mut self, scorer: Arc<dyn ImportanceScorer>) -> Self {
+        self.importance_scorer = Some(scorer);
+        self
+    }
+
+    /// Sets the tool registry, e.g. a shared `ToolCatalog` or a
+    /// `StaticToolCatalog`.
+    ///
+    /// Built-in tools enabled earlier, `blob.expand` and `codemode.run_code`,
+    /// are registered into it too.
+    pub fn with_tools(mut self, catalog: Arc<dyn ToolRegistry>) -> Self {
         self.tool_catalog = catalog;
+        if let Some(offload) = &self.blob_offload {
+            let _ = self
+                .tool_catalog
+                .register(Box::new(ExpandBlobTool::new(offload.store())));
+        }
+        if let Some(engine) = self.codemode.clone() {
+            self.set_codemode(engine);
+        }
+        self
+    }
+
+    /// Persists the tool payloads of [`Agent::tool_schemas`] under `dir`, so
+    /// a restarted process reuses them instead of formatting the catalog
+    /// again
+    pub fn with_tool_schema_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.tool_schemas = ToolSchemaSnapshots::new().with_dir(dir);
+        self
+    }
+
+    /// Sets the directory of specialist sub-agents this agent can delegate to
+    pub fn with_subagents(mut self, directory: Arc<dyn SubAgentDirectory>) -> Self {
+        self.subagents = Some(directory);
         self
     }
 
@@ -128,6 +988,16 @@ impl Agent {
         Ok(registered_tools)
     }
 
+    /// Searches the UTCP repository of
+    /// [`with_utcp_discovery`](Agent::with_utcp_discovery) for `query` and
+    /// registers the matches missing from the catalog, returning their names
+    pub async fn discover_tools(&self, query: &str) -> Result<Vec<String>> {
+        match &self.utcp_discovery {
+            Some(discovery) => discovery.discover(self.tool_catalog.as_ref(), query).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
     /// Registers UTCP tools into the agent's catalog without re-registering the provider.
     pub fn register_utcp_tools(
         &self,
@@ -313,211 +1183,3303 @@ impl Agent {
         Ok(response.content)
     }
 
-    /// Invokes a tool by name
-    pub async fn invoke_tool(
+    /// Generates a response with per-call overrides of the system prompt,
+    /// temperature, context limit, tools and retrieval.
+    ///
+    /// The overrides apply to this turn only; the agent's own options are
+    /// untouched, so concurrent turns can each use different settings. A
+    /// cancellation token or deadline stops the turn wherever it is, model
+    /// call and tool invocations included. A turn with an idempotency key
+    /// runs once per session and key; replays return the first response,
+    /// or an error if that turn failed, and a different input under the
+    /// same key is refused. With a tenant set, the turn's memory and tool
+    /// calls run on its behalf, see [`crate::tenant`].
+    pub async fn generate_with_options(
         &self,
         session_id: impl Into<String>,
-        tool_name: &str,
-        arguments: HashMap<String, serde_json::Value>,
-    ) -> Result<String> {
+        user_input: impl Into<String>,
+        options: crate::types::GenerateOptions,
+    ) -> Result<GenerationResponse> {
         let session_id = session_id.into();
+        let user_input = user_input.into();
+        let limit = TurnLimit::new(options.cancel.clone(), options.deadline);
+        let turn = limit.run(self.generate_turn(
+            session_id.clone(),
+            user_input.clone(),
+            None,
+            None,
+            &options,
+        ));
+        // Boxed, as the turn is too large to move around on the stack
+        let turn = crate::tenant::scope(options.tenant_id.clone(), Box::pin(turn));
+        match &options.idempotency_key {
+            Some(key) => {
+                self.idempotency
+                    .run(&session_id, key, &user_input, turn)
+                    .await
+            }
+            None => turn.await,
+        }
+    }
 
-        let request = ToolRequest {
-            session_id: session_id.clone(),
-            arguments,
+    /// Generates a reply and deserializes it into `T`.
+    ///
+    /// The JSON schema of `T` is added to the system prompt, and models with
+    /// JSON mode are constrained to JSON. The JSON is extracted from fences
+    /// or surrounding prose and repaired where possible; a reply that still
+    /// doesn't match the schema is sent back with the error, up to
+    /// [`with_structured_retries`](Agent::with_structured_retries) times,
+    /// before the turn fails with `AgentError::SchemaViolation`.
+    pub async fn generate_typed<T: DeserializeOwned + JsonSchema>(
+        &self,
+        session_id: impl Into<String>,
+        user_input: impl Into<String>,
+    ) -> Result<T> {
+        let schema = crate::structured::schema_for::<T>()?;
+        let options = crate::types::GenerateOptions {
+            response_schema: Some(schema.clone()),
+            ..Default::default()
         };
+        let response = self
+            .generate_with_options(session_id, user_input, options)
+            .await?;
+        parse_structured(&response.content, &schema)
+    }
 
-        let response = self.tool_catalog.invoke(tool_name, request).await?;
+    /// Generates responses for many `(session_id, input)` pairs, for offline
+    /// jobs such as backfills.
+    ///
+    /// Distinct inputs are embedded in one batch and the embeddings reused
+    /// for retrieval and storage. Turns of one session run in order; up to
+    /// the batch concurrency limit of sessions run at once. Results come back
+    /// in request order, and one failed turn doesn't stop the others.
+    pub async fn generate_batch(
+        &self,
+        requests: Vec<(String, String)>,
+    ) -> Vec<Result<GenerationResponse>> {
+        let embeddings = self.embed_batch(&requests).await;
 
-        // Store tool invocation in memory
-        self.store_memory(
-            &session_id,
-            "tool",
-            &format!("Called {}: {}", tool_name, response.content),
-            response.metadata,
-        )
-        .await?;
+        // Group turns by session, keeping request order within each
+        let mut sessions: Vec<(String, Vec<(usize, String)>)> = Vec::new();
+        let mut positions: HashMap<String, usize> = HashMap::new();
+        for (index, (session_id, input)) in requests.into_iter().enumerate() {
+            let position = *positions.entry(session_id.clone()).or_insert_with(|| {
+                sessions.push((session_id, Vec::new()));
+                sessions.len() - 1
+            });
+            sessions[position].1.push((index, input));
+        }
 
-        Ok(response.content)
+        let embeddings = &embeddings;
+        let mut results: Vec<(usize, Result<GenerationResponse>)> = stream::iter(sessions)
+            .map(|(session_id, turns)| async move {
+                let mut results = Vec::with_capacity(turns.len());
+                for (index, input) in turns {
+                    let embedding = embeddings.get(&input).cloned();
+                    let result = self
+                        .generate_turn(
+                            session_id.clone(),
+                            input,
+                            None,
+                            embedding,
+                            &Default::default(),
+                        )
+                        .await;
+                    results.push((index, result));
+                }
+                results
+            })
+            .buffer_unordered(self.batch_concurrency)
+            .flat_map(stream::iter)
+            .collect()
+            .await;
+
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Embeds the distinct inputs of a batch in one call, if retrieval is on,
+    /// plus a second call for their query embeddings if the embedder treats
+    /// queries differently.
+    ///
+    /// On failure each turn falls back to embedding its own input.
+    async fn embed_batch(&self, requests: &[(String, String)]) -> HashMap<String, InputEmbedding> {
+        let Some((embedder, _)) = &self.retrieval else {
+            return HashMap::new();
+        };
+
+        let mut inputs: Vec<String> = requests.iter().map(|(_, input)| input.clone()).collect();
+        inputs.sort();
+        inputs.dedup();
+        let embedded = async {
+            let documents = embedder.embed_batch(&inputs).await?;
+            let queries = if embedder.distinguishes_queries() {
+                embedder.embed_query_batch(&inputs).await?
+            } else {
+                documents.clone()
+            };
+            Ok::<_, AgentError>(documents.into_iter().zip(queries))
+        };
+        match embedded.await {
+            Ok(embeddings) => inputs
+                .into_iter()
+                .zip(embeddings)
+                .map(|(input, (document, query))| (input, InputEmbedding { document, query }))
+                .collect(),
+            Err(e) => {
+                tracing::warn!("Batch embedding failed: {}", e);
+                HashMap::new()
+            }
+        }
+    }
+
+    /// Invokes a tool by name.
+    ///
+    /// With [`with_tool_loop_limits`](Agent::with_tool_loop_limits), calls
+    /// past the turn's limits fail with `AgentError::ToolLoopDetected`
+    /// without running the tool, and with
+    /// [`with_tool_policy`](Agent::with_tool_policy) calls the policy
+    /// denies fail with `AgentError::ToolDenied`, as do calls of tools in
+    /// a namespace disabled for the session. The checks see the arguments
+    /// as the hooks left them. With
+    /// [`with_utcp_discovery`](Agent::with_utcp_discovery), a tool missing
+    /// from the catalog is looked up in the UTCP repository first.
+    pub async fn invoke_tool(
+        &self,
+        session_id: impl Into<String>,
+        tool_name: &str,
+        arguments: HashMap<String, serde_json::Value>,
+    ) -> Result<String> {
+        self.invoke_tool_with_options(session_id, tool_name, arguments, &Default::default())
+            .await
+    }
+
+    /// Invokes a tool by name under a turn's overrides.
+    ///
+    /// Like [`invoke_tool`](Agent::invoke_tool), except that the call is
+    /// also checked against the options' `allowed_tools`,
+    /// `enabled_namespaces` and `disabled_namespaces`, so tools run on
+    /// behalf of a turn obey the same limits as the turn itself, and run on
+    /// behalf of its tenant.
+    pub async fn invoke_tool_with_options(
+        &self,
+        session_id: impl Into<String>,
+        tool_name: &str,
+        arguments: HashMap<String, serde_json::Value>,
+        options: &crate::types::GenerateOptions,
+    ) -> Result<String> {
+        let call = self.invoke_tool_for_turn(session_id.into(), tool_name, arguments, options);
+        crate::tenant::scope(options.tenant_id.clone(), call).await
+    }
+
+    async fn invoke_tool_for_turn(
+        &self,
+        session_id: String,
+        tool_name: &str,
+        mut arguments: HashMap<String, serde_json::Value>,
+        options: &crate::types::GenerateOptions,
+    ) -> Result<String> {
+        let args_hash = self
+            .begin_tool_call(&session_id, tool_name, &mut arguments, options)
+            .await?;
+        let request = ToolRequest {
+            session_id: session_id.clone(),
+            arguments,
+            tenant_id: crate::tenant::current(),
+        };
+
+        let mut timer = RouteTimer::start();
+        let span = tracing::info_span!("agent.tool", session_id = %session_id, tool = tool_name);
+        let (response, latency) = timed(self.tool_catalog.invoke(tool_name, request))
+            .instrument(span)
+            .await;
+        timer.stage("tool", latency);
+        let mut response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                let event = timer.finish(RoutePath::Tool, Some(tool_name.to_string()), false);
+                self.routing.record(&session_id, event);
+                return Err(e);
+            }
+        };
+        for hooks in &self.hooks {
+            let hooked = hooks
+                .after_tool_call(&session_id, tool_name, &mut response)
+                .await;
+            if let Err(e) = hooked {
+                let event = timer.finish(RoutePath::Tool, Some(tool_name.to_string()), false);
+                self.routing.record(&session_id, event);
+                return Err(e);
+            }
+        }
+
+        let stored_at = Instant::now();
+        let stored = self
+            .store_tool_result(&session_id, tool_name, args_hash, latency, &response)
+            .await;
+        timer.stage_since("store", stored_at);
+        let event = timer.finish(RoutePath::Tool, Some(tool_name.to_string()), stored.is_ok());
+        self.routing.record(&session_id, event);
+        stored?;
+
+        Ok(response.content)
+    }
+
+    /// Invokes a tool by name, yielding its output as the tool streams it.
+    ///
+    /// The call goes through the same hooks and checks as
+    /// [`invoke_tool`](Agent::invoke_tool). Tools without a streaming path
+    /// yield their whole output at once. Each chunk goes through the
+    /// `after_tool_call` hooks and the tool output guard before it is
+    /// yielded; once the stream ends, the joined output is stored in the
+    /// session like any tool result. If the tool fails or the stream is
+    /// dropped early, what arrived is stored flagged with [`TRUNCATED_KEY`].
+    /// Either way the call shows up in the routing report.
+    pub async fn invoke_tool_stream(
+        &self,
+        session_id: impl Into<String>,
+        tool_name: &str,
+        mut arguments: HashMap<String, serde_json::Value>,
+    ) -> Result<BoxStream<'_, Result<String>>> {
+        let session_id = session_id.into();
+        let args_hash = self
+            .begin_tool_call(&session_id, tool_name, &mut arguments, &Default::default())
+            .await?;
+        let request = ToolRequest {
+            session_id: session_id.clone(),
+            arguments,
+            tenant_id: crate::tenant::current(),
+        };
+
+        let mut timer = RouteTimer::start();
+        let started = Instant::now();
+        let span = tracing::info_span!("agent.tool", session_id = %session_id, tool = tool_name);
+        let chunks = match self
+            .tool_catalog
+            .invoke_stream(tool_name, request)
+            .instrument(span)
+            .await
+        {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                timer.stage_since("tool", started);
+                let event = timer.finish(RoutePath::Tool, Some(tool_name.to_string()), false);
+                self.routing.record(&session_id, event);
+                return Err(e);
+            }
+        };
+
+        let call = PartialToolCall {
+            agent: self,
+            session_id,
+            tool_name: tool_name.to_string(),
+            args_hash,
+            output: crate::types::ToolResponse {
+                content: String::new(),
+                metadata: None,
+            },
+            started,
+            timer: Some(timer),
+            tenant: crate::tenant::current(),
+        };
+        let stream = stream::unfold(Some((chunks, call)), move |state| async move {
+            let (mut chunks, mut call) = state?;
+            match chunks.next().await {
+                Some(Ok(mut chunk)) => {
+                    for hooks in &self.hooks {
+                        let hooked = hooks
+                            .after_tool_call(&call.session_id, &call.tool_name, &mut chunk)
+                            .await;
+                        if let Err(e) = hooked {
+                            return Some((Err(e), None));
+                        }
+                    }
+                    call.output.content.push_str(&chunk.content);
+                    if chunk.metadata.is_some() {
+                        call.output.metadata = chunk.metadata.clone();
+                    }
+                    let content = match &self.options().tool_guard {
+                        Some(guard) => guard.guard(&call.tool_name, chunk).content,
+                        None => chunk.content,
+                    };
+                    Some((Ok(content), Some((chunks, call))))
+                }
+                Some(Err(e)) => Some((Err(e), None)),
+                None => call.finish().await.err().map(|e| (Err(e), None)),
+            }
+        });
+        let tenant = crate::tenant::current();
+        Ok(crate::tenant::scope_stream(tenant, stream.boxed()))
+    }
+
+    /// Runs the hooks and checks ahead of a tool call, returning the hash
+    /// of the arguments as the hooks left them. `overrides` are the tool
+    /// limits of the turn the call runs for.
+    async fn begin_tool_call(
+        &self,
+        session_id: &str,
+        tool_name: &str,
+        arguments: &mut HashMap<String, serde_json::Value>,
+        overrides: &crate::types::GenerateOptions,
+    ) -> Result<String> {
+        for hooks in &self.hooks {
+            hooks
+                .before_tool_call(session_id, tool_name, arguments)
+                .await?;
+        }
+        if let Some(policy) = &self.tool_policy {
+            let user_id = self.memory.user_of(session_id);
+            policy.check(&ToolCallContext {
+                session_id,
+                user_id: user_id.as_deref(),
+                tool: tool_name,
+                arguments,
+            })?;
+        }
+        if self.is_codemode_tool(tool_name) && !self.policy_allows_everything(session_id) {
+            return Err(AgentError::ToolDenied(format!(
+                "{} could call tools the policy denies",
+                tool_name
+            )));
+        }
+        let listed = overrides
+            .allowed_tools
+            .as_ref()
+            .is_none_or(|allowed| allowed.iter().any(|name| name == tool_name));
+        if !listed {
+            return Err(AgentError::ToolDenied(format!(
+                "{} is not allowed for this turn",
+                tool_name
+            )));
+        }
+        let enabled = self.namespaces.is_enabled_with(
+            session_id,
+            tool_name,
+            overrides.enabled_namespaces.as_deref(),
+            overrides.disabled_namespaces.as_deref(),
+        );
+        if !enabled {
+            return Err(AgentError::ToolDenied(format!(
+                "{} is in a disabled namespace",
+                tool_name
+            )));
+        }
+        let args_hash = arguments_hash(arguments);
+        if let Some(tool_loop) = &self.tool_loop {
+            tool_loop.check(session_id, tool_name, &args_hash)?;
+        }
+        if let Some(discovery) = &self.utcp_discovery {
+            if self.tool_catalog.get(tool_name).is_none() {
+                // Only the tool called is registered; without it the call
+                // fails as ToolNotFound
+                let catalog = self.tool_catalog.as_ref();
+                if let Err(e) = discovery.discover_exact(catalog, tool_name).await {
+                    tracing::warn!(tool = tool_name, "UTCP tool discovery failed: {}", e);
+                }
+            }
+        }
+        Ok(args_hash)
+    }
+
+    /// Invokes several tools at once and returns every result in call order.
+    ///
+    /// At most eight calls run at once, as with
+    /// [`ToolCatalog::invoke_many`], and one failing call doesn't fail the
+    /// others. Each failure is stored in the session as a tool record marked
+    /// with `TOOL_STATUS_KEY`, so the model sees which calls failed and why
+    /// on its next turn.
+    pub async fn invoke_tools(&self, session_id: &str, calls: Vec<ToolCall>) -> ToolBatch {
+        let runs = calls.into_iter().map(|call| async move {
+            let result = self
+                .invoke_tool(session_id, &call.name, call.arguments.clone())
+                .await;
+            if let Err(e) = &result {
+                let metadata = HashMap::from([
+                    (TOOL_NAME_KEY.to_string(), call.name.clone()),
+                    (
+                        TOOL_ARGS_HASH_KEY.to_string(),
+                        arguments_hash(&call.arguments),
+                    ),
+                    (TOOL_STATUS_KEY.to_string(), "error".to_string()),
+                ]);
+                let content = format!("Tool {} failed: {}", call.name, e);
+                if let Err(e) = self
+                    .store_memory(session_id, "tool", &content, Some(metadata))
+                    .await
+                {
+                    tracing::warn!("Failed to store tool failure: {}", e);
+                }
+            }
+            (call, result)
+        });
+        // Unordered so a slow call doesn't hold up later ones
+        let mut results: Vec<_> = stream::iter(runs.enumerate())
+            .map(|(index, run)| run.map(move |run| (index, run)))
+            .buffer_unordered(DEFAULT_TOOL_PARALLELISM)
+            .collect()
+            .await;
+        results.sort_by_key(|(index, _)| *index);
+        ToolBatch::new(results.into_iter().map(|(_, run)| run).collect())
+    }
+
+    /// Runs `calls` with [`invoke_tools`](Agent::invoke_tools), then
+    /// generates a reply to `user_input` that can draw on their results.
+    ///
+    /// The response metadata lists how each call went under
+    /// `TOOL_CALLS_KEY`, whether or not some of them failed.
+    pub async fn generate_with_tools(
+        &self,
+        session_id: impl Into<String>,
+        user_input: impl Into<String>,
+        calls: Vec<ToolCall>,
+    ) -> Result<GenerationResponse> {
+        let session_id = session_id.into();
+        let batch = self.invoke_tools(&session_id, calls).await;
+        let mut response = self
+            .generate_with_options(session_id, user_input, Default::default())
+            .await?;
+        response
+            .metadata
+            .get_or_insert_with(HashMap::new)
+            .extend(batch.metadata());
+        Ok(response)
+    }
+
+    /// Screens, shrinks and stores a tool result with its provenance
+    async fn store_tool_result(
+        &self,
+        session_id: &str,
+        tool_name: &str,
+        args_hash: String,
+        latency: Duration,
+        response: &crate::types::ToolResponse,
+    ) -> Result<()> {
+        // Screen and shrink what enters memory; the caller still gets the raw output
+        let mut stored = match &self.options().tool_guard {
+            Some(guard) => guard.guard(tool_name, response.clone()),
+            None => response.clone(),
+        };
+        // Offload first so the blob keeps the full output, then shrink only
+        // what stays inline
+        let reduce = |response: crate::types::ToolResponse| async move {
+            match &self.tool_output {
+                Some(processor) => processor.process(tool_name, response).await,
+                None => Ok(response),
+            }
+        };
+        stored = match &self.blob_offload {
+            Some(offload) => offload.offload_with(stored, reduce).await?,
+            None => reduce(stored).await?,
+        };
+
+        let metadata = tool_metadata(tool_name, args_hash, latency, stored.metadata);
+        self.store_memory(session_id, "tool", &stored.content, Some(metadata))
+            .await
+    }
+
+    /// Starts retrieval for input the user is still typing.
+    ///
+    /// The next generation for `session_id` reuses these results if its input
+    /// extends `partial_input` without changing much, and retrieves afresh
+    /// otherwise. Does nothing unless retrieval is configured.
+    pub async fn prefetch(
+        &self,
+        session_id: impl Into<String>,
+        partial_input: impl Into<String>,
+    ) -> Result<()> {
+        let session_id = session_id.into();
+        let partial_input = partial_input.into();
+        let (embedder, limit) = match &self.retrieval {
+            Some((embedder, limit)) => (embedder, *limit),
+            None => return Ok(()),
+        };
+
+        let embedding = embedder.embed_query(&partial_input).await?;
+        let records = self
+            .search_memories(&session_id, None, &partial_input, embedding, limit)
+            .await?;
+
+        self.prefetched.lock().insert(
+            session_id,
+            Prefetched {
+                input: partial_input,
+                records,
+                fetched_at: Instant::now(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Finds memories relevant to `input`, preferring prefetched results and
+    /// reusing the query `embedding` of `input` if the caller already has one.
+    ///
+    /// `limit` overrides the configured retrieval limit, and `scope` searches
+    /// that thread path instead of the session.
+    async fn retrieve_relevant(
+        &self,
+        session_id: &str,
+        input: &str,
+        embedding: Option<Vec<f32>>,
+        limit: Option<usize>,
+        scope: Option<&str>,
+    ) -> Result<Vec<MemoryRecord>> {
+        let (embedder, limit) = match &self.retrieval {
+            Some((embedder, configured)) => (embedder, limit.unwrap_or(*configured)),
+            None => return Ok(Vec::new()),
+        };
+        if limit == 0 {
+            return Ok(Vec::new());
+        }
+        let plan = match &self.retrieval_policy {
+            Some(policy) => policy.plan(input, limit),
+            None => RetrievalPlan::TopK(limit),
+        };
+
+        // Prefetched results cover the session, not a wider scope, and only
+        // stand in for a search if they hold as many records as it would
+        let wanted = match plan {
+            RetrievalPlan::Skip => 0,
+            RetrievalPlan::TopK(k) => k,
+            RetrievalPlan::Diverse { candidates, .. } => candidates,
+        };
+        let prefetched = self.prefetched.lock().remove(session_id);
+        if let Some(prefetched) = prefetched.filter(|_| scope.is_none()) {
+            if prefetched.covers(input, Instant::now()) && prefetched.records.len() >= wanted {
+                let mut records = prefetched.records;
+                match plan {
+                    RetrievalPlan::Skip => records.clear(),
+                    RetrievalPlan::TopK(k) => records.truncate(k),
+                    RetrievalPlan::Diverse { candidates, limit } => {
+                        records.truncate(candidates);
+                        let embedding = match embedding {
+                            Some(embedding) => embedding,
+                            None => embedder.embed_query(input).await?,
+                        };
+                        let policy = self.retrieval_policy.unwrap_or_default();
+                        return Ok(mmr_rerank_with(&embedding, records, limit, policy.mmr()));
+                    }
+                }
+                return Ok(records);
+            }
+        }
+        if plan == RetrievalPlan::Skip {
+            return Ok(Vec::new());
+        }
+
+        let embedding = match embedding {
+            Some(embedding) => embedding,
+            None => embedder.embed_query(input).await?,
+        };
+        match plan {
+            RetrievalPlan::Skip => Ok(Vec::new()),
+            RetrievalPlan::TopK(k) => {
+                self.search_memories(session_id, scope, input, embedding, k)
+                    .await
+            }
+            RetrievalPlan::Diverse { candidates, limit } => {
+                let records = self
+                    .search_memories(session_id, scope, input, embedding.clone(), candidates)
+                    .await?;
+                let policy = self.retrieval_policy.unwrap_or_default();
+                Ok(mmr_rerank_with(&embedding, records, limit, policy.mmr()))
+            }
+        }
+    }
+
+    /// Searches the session, its semantic tier and image memories if they
+    /// are kept, and, if it is bound to a user, that user's memory, then
+    /// reranks the combined candidates if the memory has a reranker.
+    ///
+    /// With a thread `scope` only that thread is searched.
+    async fn search_memories(
+        &self,
+        session_id: &str,
+        scope: Option<&str>,
+        query: &str,
+        embedding: Vec<f32>,
+        limit: usize,
+    ) -> Result<Vec<MemoryRecord>> {
+        if let Some(scope) = scope {
+            let fetch = self.memory.candidate_limit(limit);
+            let records = self
+                .memory
+                .search_scope(scope, embedding, fetch, &MemoryFilter::default())
+                .await?;
+            return self.memory.rerank(query, records, limit).await;
+        }
+
+        // The short-term window is replayed as it is, so look past its
+        // records for `limit` older ones
+        let recent: HashSet<Uuid> = self
+            .memory
+            .retrieve_recent(session_id)
+            .await?
+            .into_iter()
+            .map(|r| r.id)
+            .collect();
+        let wanted = limit + recent.len();
+        let user_id = self.memory.user_of(session_id);
+        let mut records = if user_id.is_none()
+            && !self.memory.has_semantic_tier()
+            && !self.memory.has_image_memory()
+        {
+            self.memory
+                .search_with_query(session_id, query, embedding, wanted)
+                .await?
+        } else {
+            let filter = MemoryFilter::default();
+            let fetch = self.memory.candidate_limit(wanted);
+            let (session, user, semantic, images) = futures::join!(
+                self.memory.search(session_id, embedding.clone(), fetch),
+                async {
+                    match &user_id {
+                        Some(user_id) => {
+                            self.memory
+                                .search_user(user_id, embedding.clone(), fetch, &filter)
+                                .await
+                        }
+                        None => Ok(Vec::new()),
+                    }
+                },
+                async {
+                    if self.memory.has_semantic_tier() {
+                        self.memory
+                            .search_semantic(session_id, embedding.clone(), fetch, &filter)
+                            .await
+                    } else {
+                        Ok(Vec::new())
+                    }
+                },
+                async {
+                    let query = ImageQuery::Text(query.to_string());
+                    self.memory.search(session_id, query, fetch).await
+                },
+            );
+
+            // Facts learned in this session are already found by the session search
+            let mut records = session?;
+            records.extend(user?.into_iter().filter(|r| {
+                r.metadata
+                    .as_ref()
+                    .and_then(|m| m.get(SOURCE_SESSION_KEY))
+                    .is_none_or(|source| source != session_id)
+            }));
+            records.extend(semantic?);
+            // Image search failing shouldn't cost the turn its text memories
+            records.extend(images.unwrap_or_else(|e| {
+                tracing::warn!(session_id, "Image memory search failed: {}", e);
+                Vec::new()
+            }));
+            self.memory.rerank(query, records, wanted).await?
+        };
+        records.retain(|r| !recent.contains(&r.id));
+        records.truncate(limit);
+        Ok(records)
+    }
+
+    /// Caps a requested prompt budget at what `model` can take
+    fn context_budget(&self, model: &dyn LLM, requested: usize) -> usize {
+        match self.model_limits.lookup(model.model_name()) {
+            Some(limits) => requested.min(limits.input_budget()),
+            None => model.capabilities().clamp_context(requested),
+        }
+    }
+
+    /// Picks the registry prompt serving this session, if one is configured
+    fn select_prompt(&self, session_id: &str) -> Option<PromptVersion> {
+        let (registry, name) = self.prompt_registry.as_ref()?;
+        registry.select(name, session_id)
     }
 
     /// Builds the prompt with system message and context
-    async fn build_prompt(&self, session_id: &str, user_input: &str) -> Result<Vec<Message>> {
+    #[allow(clippy::too_many_arguments)]
+    async fn build_prompt(
+        &self,
+        session_id: &str,
+        user_input: &str,
+        system_prompt: &str,
+        context_limit: usize,
+        relevant: &[MemoryRecord],
+        tokenizer: &dyn Tokenizer,
+        strategy: Option<&dyn ContextStrategy>,
+    ) -> Result<Vec<Message>> {
         let mut messages = Vec::new();
 
-        // Add system prompt if set
-        if !self.system_prompt.is_empty() {
+        // Add system prompt if set
+        if !system_prompt.is_empty() {
+            messages.push(Message {
+                role: Role::System,
+                content: system_prompt.to_string(),
+                metadata: None,
+            });
+        }
+
+        // Lay out recent turns, summaries and recalled memories in what the
+        // system prompt and input leave of the budget
+        let reserved = tokenizer.count_tokens(system_prompt) + tokenizer.count_tokens(user_input);
+        let recent_memories = self.memory.retrieve_recent(session_id).await?;
+        let request = ContextRequest {
+            session_id,
+            recent: &recent_memories,
+            relevant,
+            budget: context_limit.saturating_sub(reserved),
+            tokenizer,
+        };
+        let strategy = strategy.unwrap_or(&self.composer);
+        messages.extend(strategy.pack(request).await?);
+
+        // Add current user input
+        messages.push(Message {
+            role: Role::User,
+            content: user_input.to_string(),
+            metadata: None,
+        });
+
+        Ok(messages)
+    }
+
+    async fn generate_internal(
+        &self,
+        session_id: String,
+        user_input: String,
+        files: Option<Vec<File>>,
+    ) -> Result<GenerationResponse> {
+        self.generate_turn(session_id, user_input, files, None, &Default::default())
+            .await
+    }
+
+    /// Runs one turn; `embedding` is the input's embedding if already known
+    #[tracing::instrument(name = "agent.generate", skip_all, fields(session_id = %session_id))]
+    async fn generate_turn(
+        &self,
+        session_id: String,
+        user_input: String,
+        files: Option<Vec<File>>,
+        embedding: Option<InputEmbedding>,
+        overrides: &crate::types::GenerateOptions,
+    ) -> Result<GenerationResponse> {
+        let mut timer = RouteTimer::start();
+        let turn = match self
+            .prepare_turn(
+                &session_id,
+                &user_input,
+                files,
+                embedding,
+                overrides,
+                &mut timer,
+            )
+            .await?
+        {
+            Turn::Answered(response) => {
+                let event = timer.finish(RoutePath::Codemode, None, true);
+                self.routing.record(&session_id, event);
+                return Ok(response);
+            }
+            Turn::Ready(turn) => *turn,
+        };
+
+        // Generate response
+        let schema = overrides.response_schema.as_ref();
+        let settings = GenerationSettings {
+            temperature: overrides.temperature,
+            json_mode: schema.is_some() && turn.model.capabilities().json_mode,
+        };
+        let mut retry = (schema.is_some() || self.language.is_some())
+            .then(|| (turn.messages.clone(), turn.files.clone()));
+        let (response, elapsed) = timed(self.call_model(
+            &session_id,
+            &turn.model,
+            turn.messages,
+            turn.files,
+            &settings,
+            turn.budget.as_ref(),
+        ))
+        .await;
+        timer.stage("model", elapsed);
+        let mut response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                let event = timer.finish(RoutePath::Generation, None, false);
+                self.routing.record(&session_id, event);
+                return Err(e);
+            }
+        };
+
+        // Ask again, with the reason, while the reply doesn't match the
+        // requested schema, or once if it is in the wrong language
+        let mut schema_retries = self.structured_retries;
+        let mut language_checked = false;
+        while let Some((messages, files)) = &mut retry {
+            let correction = match schema {
+                Some(schema) => match parse_structured::<Value>(&response.content, schema) {
+                    Ok(_) => None,
+                    Err(e) if schema_retries > 0 => {
+                        schema_retries -= 1;
+                        Some(crate::structured::correction(&e))
+                    }
+                    Err(e) => {
+                        let event = timer.finish(RoutePath::Generation, None, false);
+                        self.routing.record(&session_id, event);
+                        return Err(e);
+                    }
+                },
+                None if !language_checked => {
+                    language_checked = true;
+                    self.language
+                        .as_ref()
+                        .and_then(|policy| policy.correction(&user_input, &response.content))
+                }
+                None => None,
+            };
+            let Some(correction) = correction else {
+                break;
+            };
+
+            let retried_at = Instant::now();
+            messages.push(Message {
+                role: Role::Assistant,
+                content: response.content.clone(),
+                metadata: None,
+            });
+            messages.push(Message {
+                role: Role::User,
+                content: correction,
+                metadata: None,
+            });
+            let retried = self
+                .call_model(
+                    &session_id,
+                    &turn.model,
+                    messages.clone(),
+                    files.clone(),
+                    &settings,
+                    turn.budget.as_ref(),
+                )
+                .await;
+            timer.stage_since("reprompt", retried_at);
+            match retried {
+                Ok(retried) => response = retried,
+                Err(e) if schema.is_some() => {
+                    let event = timer.finish(RoutePath::Generation, None, false);
+                    self.routing.record(&session_id, event);
+                    return Err(e);
+                }
+                Err(e) => {
+                    tracing::warn!("Language re-prompt failed: {}", e);
+                    break;
+                }
+            }
+        }
+        for hooks in &self.hooks {
+            let hooked = hooks.on_llm_response(&session_id, &mut response).await;
+            if let Err(e) = hooked {
+                let event = timer.finish(RoutePath::Generation, None, false);
+                self.routing.record(&session_id, event);
+                return Err(e);
+            }
+        }
+        let metadata = turn.attribution.apply(&mut response);
+
+        // Store assistant response in memory
+        let stored_at = Instant::now();
+        let stored = self
+            .store_memory(&session_id, "assistant", &response.content, metadata)
+            .await;
+        timer.stage_since("store", stored_at);
+        let event = timer.finish(RoutePath::Generation, None, stored.is_ok());
+        self.routing.record(&session_id, event);
+        stored?;
+
+        Ok(response)
+    }
+
+    /// Streams the response to `user_input` as text chunks.
+    ///
+    /// The assistant turn is saved to memory while it streams, flagged with
+    /// [`TRUNCATED_KEY`] until the model finishes: every
+    /// [`with_stream_save_interval`](Agent::with_stream_save_interval)
+    /// chunks, when the model fails mid-stream, and when the stream is
+    /// dropped early. After a crash or cancel the partial reply is still in
+    /// memory, marked as interrupted, so the next turn can acknowledge and
+    /// complete it. With [`with_stream_guard`](Agent::with_stream_guard),
+    /// text is only yielded once the guard has checked it, so the guard's
+    /// trailing window reaches the caller a little later.
+    pub async fn generate_stream(
+        &self,
+        session_id: impl Into<String>,
+        user_input: impl Into<String>,
+    ) -> Result<BoxStream<'_, Result<String>>> {
+        let session_id = session_id.into();
+        let mut timer = RouteTimer::start();
+        let turn = match self
+            .prepare_turn(
+                &session_id,
+                &user_input.into(),
+                None,
+                None,
+                &Default::default(),
+                &mut timer,
+            )
+            .instrument(
+                tracing::info_span!("agent.generate", session_id = %session_id, stream = true),
+            )
+            .await?
+        {
+            Turn::Answered(response) => {
+                let event = timer.finish(RoutePath::Codemode, None, true);
+                self.routing.record(&session_id, event);
+                return Ok(stream::once(async move { Ok(response.content) }).boxed());
+            }
+            Turn::Ready(turn) => *turn,
+        };
+        // The model stage runs until the stream ends
+        let model_at = Instant::now();
+        let call = ModelCall::start(
+            &session_id,
+            turn.model.model_name(),
+            self.tokenizers.lookup(turn.model.model_name()),
+            &turn.messages,
+        );
+        let chunks = match turn
+            .model
+            .generate_stream(turn.messages, turn.files)
+            .instrument(call.span())
+            .await
+        {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                call.finish(None);
+                timer.stage_since("model", model_at);
+                let event = timer.finish(RoutePath::Generation, None, false);
+                self.routing.record(&session_id, event);
+                return Err(e);
+            }
+        };
+
+        let partial = PartialTurn::new(Arc::clone(&self.memory), self.hooks.clone(), &session_id);
+        let call = StreamCall {
+            agent: self,
+            session_id: session_id.clone(),
+            budget: turn.budget,
+            call: Some(call),
+        };
+        let scan = StreamScan::default();
+        let state = Some((chunks, partial, turn.attribution, timer, call, scan));
+        // Each step yields what may be released so far, possibly nothing
+        let stream = stream::unfold(state, move |state| async move {
+            let (mut chunks, mut partial, attribution, mut timer, mut call, mut scan) = state?;
+            match chunks.next().await {
+                Some(Ok(chunk)) => {
+                    partial.push(&chunk);
+                    call.push(&chunk);
+                    let released = match &self.stream_guard {
+                        Some(guard) => match scan.scan(guard, &partial.record.content).await {
+                            Scanned::Release(text) => text,
+                            Scanned::Violation(rule) => {
+                                let turn = (partial, timer, call);
+                                let redacted = self.stop_stream(guard, &rule, turn, model_at).await;
+                                return Some((vec![redacted], None));
+                            }
+                        },
+                        None => chunk,
+                    };
+                    if partial.unsaved >= self.stream_save_interval {
+                        partial.save().await;
+                    }
+                    let state = (chunks, partial, attribution, timer, call, scan);
+                    let released = (!released.is_empty()).then_some(Ok(released));
+                    Some((released.into_iter().collect(), Some(state)))
+                }
+                Some(Err(e)) => {
+                    // Charges what streamed before the failure
+                    drop(call);
+                    timer.stage_since("model", model_at);
+                    partial.save().await;
+                    partial.finished = true;
+                    let event = timer.finish(RoutePath::Generation, None, false);
+                    self.routing.record(&partial.record.session_id, event);
+                    Some((vec![Err(e)], None))
+                }
+                None => {
+                    // Text the guard held back goes out once it passed
+                    let mut rest = Vec::new();
+                    if let Some(guard) = &self.stream_guard {
+                        match scan.finish(guard, &partial.record.content).await {
+                            Scanned::Release(text) if text.is_empty() => {}
+                            Scanned::Release(text) => rest.push(Ok(text)),
+                            Scanned::Violation(rule) => {
+                                let turn = (partial, timer, call);
+                                let redacted = self.stop_stream(guard, &rule, turn, model_at).await;
+                                return Some((vec![redacted], None));
+                            }
+                        }
+                    }
+                    call.finish(&partial.record.content);
+                    timer.stage_since("model", model_at);
+                    partial.finished = true;
+                    let mut response = GenerationResponse {
+                        content: partial.record.content.clone(),
+                        metadata: None,
+                    };
+                    let mut record = partial.record.clone();
+                    let stored_at = Instant::now();
+                    let stored = async {
+                        for hooks in &self.hooks {
+                            hooks
+                                .on_llm_response(&record.session_id, &mut response)
+                                .await?;
+                        }
+                        record.metadata = attribution.apply(&mut response);
+                        record.content = response.content;
+                        self.store_enriched(record, None).await
+                    }
+                    .await;
+                    timer.stage_since("store", stored_at);
+                    let event = timer.finish(RoutePath::Generation, None, stored.is_ok());
+                    self.routing.record(&partial.record.session_id, event);
+                    if let Err(e) = stored {
+                        rest.push(Err(e));
+                    }
+                    (!rest.is_empty()).then_some((rest, None))
+                }
+            }
+        });
+        let stream = stream.flat_map(stream::iter).boxed();
+        let tenant = crate::tenant::current();
+        Ok(crate::tenant::scope_stream(tenant, stream))
+    }
+
+    /// Ends a streamed reply that violated `guard`'s `rule`. Dropping the
+    /// model's stream aborts generation; the session keeps the redaction
+    /// message in place of what was streamed so far.
+    async fn stop_stream(
+        &self,
+        guard: &StreamGuard,
+        rule: &str,
+        (mut partial, mut timer, call): (PartialTurn, RouteTimer, StreamCall<'_>),
+        model_at: Instant,
+    ) -> Result<String> {
+        let session_id = partial.record.session_id.clone();
+        call.finish(&partial.record.content);
+        timer.stage_since("model", model_at);
+        tracing::warn!(session_id = %session_id, rule, "Stopped a reply that violated a guardrail");
+
+        partial.finished = true;
+        let mut record = partial.record.clone();
+        record.content = guard.redaction().to_string();
+        record
+            .metadata
+            .get_or_insert_with(HashMap::new)
+            .insert(GUARDRAIL_VIOLATION_KEY.to_string(), rule.to_string());
+        let stored_at = Instant::now();
+        let stored = self.store_enriched(record, None).await;
+        timer.stage_since("store", stored_at);
+        let event = timer.finish(RoutePath::Generation, None, false);
+        self.routing.record(&session_id, event);
+        stored.map(|()| guard.redaction().to_string())
+    }
+
+    /// Runs a turn up to the model call: stores the input, retrieves
+    /// context and builds the prompt. Fields set in `overrides` win over the
+    /// agent's options and the experiment arm.
+    async fn prepare_turn(
+        &self,
+        session_id: &str,
+        user_input: &str,
+        files: Option<Vec<File>>,
+        embedding: Option<InputEmbedding>,
+        overrides: &crate::types::GenerateOptions,
+        timer: &mut RouteTimer,
+    ) -> Result<Turn<'_>> {
+        // New user input starts a new turn of tool calls
+        if let Some(tool_loop) = &self.tool_loop {
+            tool_loop.reset(session_id);
+        }
+        let has_files = files.as_ref().map(|f| !f.is_empty()).unwrap_or(false);
+
+        // Hold one snapshot of the options for the whole turn
+        let options = self.options();
+        self.costs.check(session_id, options.cost_budget.as_ref())?;
+
+        // Resolve the experiment arm, which overrides prompt, model, and context
+        let arm = self
+            .experiment
+            .as_ref()
+            .and_then(|e| e.assign(session_id).map(|arm| (e, arm)));
+        let prompt = match overrides.system_prompt {
+            Some(_) => None,
+            None => arm
+                .and_then(|(_, arm)| arm.prompt.clone())
+                .or_else(|| self.select_prompt(session_id)),
+        };
+
+        // An arm's own model wins over routing
+        let arm_model = arm.and_then(|(_, arm)| arm.model.clone());
+        let route = match (&arm_model, &options.model_routing) {
+            (None, Some(routing)) => Some(routing.route(user_input)),
+            _ => None,
+        };
+        let model = arm_model
+            .or_else(|| route.as_ref().map(|(_, model)| Arc::clone(model)))
+            .unwrap_or_else(|| Arc::clone(&self.model));
+
+        // Reject unsupported input before anything is stored
+        let capabilities = model.capabilities();
+        if has_files {
+            capabilities.require_vision(model.model_name())?;
+        }
+        let context_limit = self.context_budget(
+            model.as_ref(),
+            overrides
+                .context_limit
+                .or_else(|| arm.and_then(|(_, arm)| arm.context_limit))
+                .unwrap_or(options.context_limit),
+        );
+        // CodeMode answers in prose, so structured turns go to the model
+        let allowed = self.turn_allowed_tools(session_id, overrides);
+        let orchestrate = !has_files
+            && overrides.response_schema.is_none()
+            && self.tools_allowed(allowed.as_deref())
+            && self.policy_allows_everything(session_id);
+
+        // Remember images so later turns can recall them
+        if has_files && self.memory.has_image_memory() {
+            for file in files.iter().flatten() {
+                if let Err(e) = self.memory.store_image(session_id, file, user_input).await {
+                    tracing::warn!(session_id, "Storing image memory failed: {}", e);
+                }
+            }
+        }
+
+        // Store the user message, try CodeMode orchestration, and retrieve
+        // relevant memories concurrently
+        let (document, query) = match embedding {
+            Some(embedding) => (Some(embedding.document), Some(embedding.query)),
+            None => (None, None),
+        };
+        let limit = overrides.retrieval_limit;
+        let scope = overrides.retrieval_scope.as_deref();
+        let (stored, routed, relevant, selected) = futures::join!(
+            timed(self.store_embedded(session_id, "user", user_input, None, document)),
+            timed(async {
+                if orchestrate {
+                    self.try_codemode_orchestration(session_id, user_input)
+                        .await
+                } else {
+                    Ok(None)
+                }
+            }),
+            timed(self.retrieve_relevant(session_id, user_input, query, limit, scope)),
+            timed(self.turn_tools(
+                user_input,
+                options.tool_selection,
+                |name| {
+                    allowed
+                        .as_ref()
+                        .is_none_or(|allowed| allowed.iter().any(|a| a == name))
+                        && self.policy_may_call(session_id, name)
+                },
+                allowed.is_none(),
+            )),
+        );
+        let (stored, store_time) = stored;
+        let (routed, codemode_time) = routed;
+        let (relevant, retrieval_time) = relevant;
+        let (selected, selection_time) = selected;
+        timer.stage("store_input", store_time);
+        if orchestrate {
+            timer.stage("codemode", codemode_time);
+        }
+        timer.stage("retrieval", retrieval_time);
+        if self.tool_selector.is_some() && options.tool_selection.is_some() {
+            timer.stage("tool_selection", selection_time);
+        }
+        stored?;
+
+        if let Some((content, metadata)) = routed? {
+            let stored_at = Instant::now();
+            self.store_memory(session_id, "assistant", &content, metadata.clone())
+                .await?;
+            timer.stage_since("store", stored_at);
+
+            return Ok(Turn::Answered(GenerationResponse { content, metadata }));
+        }
+
+        // Retrieval only enriches the prompt; don't fail the turn over it
+        let relevant = relevant.unwrap_or_else(|e| {
+            tracing::warn!("Memory retrieval failed: {}", e);
+            Vec::new()
+        });
+
+        // Build prompt with context
+        let prompt_at = Instant::now();
+        let mut system_prompt = overrides
+            .system_prompt
+            .as_deref()
+            .unwrap_or_else(|| {
+                prompt
+                    .as_ref()
+                    .map_or(options.system_prompt.as_str(), |p| p.template.as_str())
+            })
+            .to_string();
+        // Selection only narrows the prompt; don't fail the turn over it
+        let selected = selected.unwrap_or_else(|e| {
+            tracing::warn!("Tool selection failed: {}", e);
+            Vec::new()
+        });
+        if !selected.is_empty() {
+            system_prompt = format!("{}\n\n{}", system_prompt, tools_instruction(&selected));
+        }
+        let selected: Vec<String> = selected.into_iter().map(|spec| spec.name).collect();
+        if let Some(schema) = &overrides.response_schema {
+            system_prompt = format!("{}\n\n{}", system_prompt, schema_instruction(schema));
+        }
+        let mut messages = self
+            .build_prompt(
+                session_id,
+                user_input,
+                &system_prompt,
+                context_limit,
+                &relevant,
+                self.tokenizers.lookup(model.model_name()).as_ref(),
+                options.context_strategy.as_deref(),
+            )
+            .await?;
+        for hooks in &self.hooks {
+            hooks.on_prompt_built(session_id, &mut messages).await?;
+        }
+        timer.stage_since("prompt", prompt_at);
+
+        Ok(Turn::Ready(Box::new(PreparedTurn {
+            model,
+            messages,
+            files,
+            attribution: Attribution {
+                prompt,
+                arm,
+                route: route.map(|(name, _)| name),
+                tools: selected,
+            },
+            budget: options.cost_budget,
+        })))
+    }
+
+    /// Returns true unless the tool policy could deny some call in
+    /// `session_id`. CodeMode runs UTCP tools the policy never sees one by
+    /// one, so it only runs when this holds.
+    fn policy_allows_everything(&self, session_id: &str) -> bool {
+        self.tool_policy.as_ref().is_none_or(|policy| {
+            let user_id = self.memory.user_of(session_id);
+            policy.allows_everything(session_id, user_id.as_deref())
+        })
+    }
+
+    fn is_codemode_tool(&self, name: &str) -> bool {
+        self.codemode
+            .as_ref()
+            .is_some_and(|engine| engine.tool().name == name)
+    }
+
+    /// Selects the tools to list in a turn's prompt among those `offered`,
+    /// searching the UTCP repository if `discover` is set and none is
+    /// relevant; none unless tool selection is configured. Tools found there
+    /// are listed but only registered once called.
+    async fn turn_tools(
+        &self,
+        user_input: &str,
+        selection: Option<ToolSelection>,
+        offered: impl Fn(&str) -> bool,
+        discover: bool,
+    ) -> Result<Vec<crate::types::ToolSpec>> {
+        let (Some(selector), Some(selection)) = (&self.tool_selector, selection) else {
+            return Ok(Vec::new());
+        };
+        selector.sync(self.tool_catalog.as_ref());
+        let mut specs = self.tool_catalog.specs();
+        specs.retain(|spec| offered(&spec.name));
+        let selected = selector.select(&specs, user_input, &selection).await?;
+        if !selected.is_empty() || !discover {
+            return Ok(selected);
+        }
+        // No registered tool fits, so look for one in the UTCP repository
+        let Some(discovery) = &self.utcp_discovery else {
+            return Ok(selected);
+        };
+        let mut found = discovery
+            .search_specs(self.tool_catalog.as_ref(), user_input)
+            .await?;
+        found.retain(|spec| offered(&spec.name));
+        if found.is_empty() {
+            return Ok(selected);
+        }
+        selector.select(&found, user_input, &selection).await
+    }
+
+    /// Names the registered tools a turn may use, or `None` for all of
+    /// them, by `allowed_tools`, the enabled namespaces and, for CodeMode,
+    /// the tool policy
+    fn turn_allowed_tools(
+        &self,
+        session_id: &str,
+        overrides: &crate::types::GenerateOptions,
+    ) -> Option<Vec<String>> {
+        let specs = self.tool_catalog.specs();
+        let registered = specs.len();
+        let allowed: Vec<String> = specs
+            .into_iter()
+            .map(|spec| spec.name)
+            .filter(|name| {
+                overrides
+                    .allowed_tools
+                    .as_ref()
+                    .is_none_or(|allowed| allowed.contains(name))
+            })
+            .filter(|name| {
+                self.namespaces.is_enabled_with(
+                    session_id,
+                    name,
+                    overrides.enabled_namespaces.as_deref(),
+                    overrides.disabled_namespaces.as_deref(),
+                )
+            })
+            .filter(|name| {
+                !self.is_codemode_tool(name) || self.policy_allows_everything(session_id)
+            })
+            .collect();
+        (allowed.len() < registered).then_some(allowed)
+    }
+
+    /// Returns true unless `allowed` leaves out a registered tool
+    fn tools_allowed(&self, allowed: Option<&[String]>) -> bool {
+        allowed.is_none_or(|allowed| {
+            self.tool_catalog
+                .specs()
+                .iter()
+                .all(|spec| allowed.contains(&spec.name))
+        })
+    }
+
+    fn set_codemode(&mut self, engine: Arc<CodeModeUtcp>) {
+        self.codemode = Some(engine.clone());
+        // Expose codemode.run_code as a tool; ignore duplicate registrations
+        let _ = self
+            .tool_catalog
+            .register(Box::new(CodeModeTool::new(engine)));
+    }
+
+    async fn try_codemode_orchestration(
+        &self,
+        _session_id: &str,
+        user_input: &str,
+    ) -> Result<Option<(String, Option<HashMap<String, String>>)>> {
+        let orchestrator = match self.codemode_orchestrator.as_ref() {
+            Some(o) => o,
+            None => return Ok(None),
+        };
+
+        let value = orchestrator
+            .call_prompt(user_input)
+            .await
+            .map_err(|e| AgentError::Other(e.to_string()))?;
+
+        if let Some(v) = value {
+            let content = format_codemode_value(&v);
+            let metadata = Some(HashMap::from([(
+                "source".to_string(),
+                "codemode_orchestrator".to_string(),
+            )]));
+            return Ok(Some((content, metadata)));
+        }
+
+        Ok(None)
+    }
+
+    /// Stores a memory record
+    async fn store_memory(
+        &self,
+        session_id: &str,
+        role: &str,
+        content: &str,
+        metadata: Option<HashMap<String, String>>,
+    ) -> Result<()> {
+        self.store_embedded(session_id, role, content, metadata, None)
+            .await
+    }
+
+    /// Stores a memory record, reusing `embedding` of `content` if the caller
+    /// already has one
+    async fn store_embedded(
+        &self,
+        session_id: &str,
+        role: &str,
+        content: &str,
+        metadata: Option<HashMap<String, String>>,
+        embedding: Option<Vec<f32>>,
+    ) -> Result<()> {
+        let record = MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: session_id.to_string(),
+            role: role.to_string(),
+            content: content.to_string(),
+            importance: DEFAULT_IMPORTANCE,
+            timestamp: Utc::now(),
+            metadata,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        };
+        self.store_enriched(record, embedding).await
+    }
+
+    /// Embeds and scores `record`, then stores it
+    async fn store_enriched(
+        &self,
+        mut record: MemoryRecord,
+        mut embedding: Option<Vec<f32>>,
+    ) -> Result<()> {
+        if !self.hooks.is_empty() {
+            let content = record.content.clone();
+            for hooks in &self.hooks {
+                hooks.on_memory_store(&mut record).await?;
+            }
+            // An embedding of the original content no longer fits
+            if record.content != content {
+                embedding = None;
+            }
+        }
+
+        // Embed records so later turns can retrieve them
+        if let Some(embedding) = embedding {
+            record.embedding = Some(embedding);
+        } else if let Some((embedder, _)) = &self.retrieval {
+            match embedder.embed(&record.content).await {
+                Ok(embedding) => record.embedding = Some(embedding),
+                Err(e) => tracing::warn!("Embedding memory failed: {}", e),
+            }
+        }
+
+        if let Some(scorer) = &self.importance_scorer {
+            // A failing scorer shouldn't lose the turn; keep the default
+            match scorer.score(&record).await {
+                Ok(importance) => record.importance = importance.clamp(0.0, 1.0),
+                Err(e) => tracing::warn!("Importance scoring failed: {}", e),
+            }
+        }
+
+        self.memory.store(record).await
+    }
+
+    /// Labels a stored turn, e.g. with its intent, sentiment or resolution
+    /// status, for analytics and fine-tuning datasets.
+    ///
+    /// Labels set earlier are kept unless `labels` replaces them, and a
+    /// `null` value removes one. Find labeled turns with
+    /// `MemoryFilter::with_label` and `SessionMemory::retrieve_filtered`.
+    pub async fn annotate(
+        &self,
+        session_id: &str,
+        record_id: Uuid,
+        labels: HashMap<String, Value>,
+    ) -> Result<MemoryRecord> {
+        self.memory.annotate(session_id, record_id, labels).await
+    }
+
+    /// Flushes memory to persistent store
+    pub async fn flush(&self, _session_id: &str) -> Result<()> {
+        self.memory.flush().await
+    }
+
+    /// Returns the tool registry
+    pub fn tools(&self) -> Arc<dyn ToolRegistry> {
+        Arc::clone(&self.tool_catalog)
+    }
+
+    /// Returns the registered tools formatted for `format`'s API.
+    ///
+    /// The payload is computed once per catalog and reused until a tool is
+    /// registered, replaced or removed.
+    pub async fn tool_schemas(&self, format: ToolSchemaFormat) -> Arc<Value> {
+        self.tool_schemas
+            .payload(format, self.tool_catalog.as_ref())
+            .await
+    }
+
+    /// Returns the registered tools `session_id` may call that are relevant
+    /// to `query`, most relevant first.
+    ///
+    /// Tools in disabled namespaces or denied by the tool policy are left
+    /// out, as by [`may_call_tool`](Agent::may_call_tool). With
+    /// [`with_tool_selector`](Agent::with_tool_selector) and
+    /// `AgentOptions::tool_selection`, these are at most `top_k` tools scoring
+    /// at least `min_score`; otherwise all of them, in registration order.
+    /// The UTCP repository is never searched.
+    pub async fn select_tools(
+        &self,
+        session_id: &str,
+        query: &str,
+    ) -> Result<Vec<crate::types::ToolSpec>> {
+        let offered = |name: &str| self.may_call_tool(session_id, name);
+        match self.options().tool_selection {
+            Some(selection) if self.tool_selector.is_some() => {
+                self.turn_tools(query, Some(selection), offered, false)
+                    .await
+            }
+            _ => {
+                let mut specs = self.tool_catalog.specs();
+                specs.retain(|spec| offered(&spec.name));
+                Ok(specs)
+            }
+        }
+    }
+
+    /// Returns [`select_tools`](Agent::select_tools) for `query` formatted
+    /// for `format`'s API, to offer a model only the relevant tools
+    pub async fn tool_schemas_for(
+        &self,
+        format: ToolSchemaFormat,
+        session_id: &str,
+        query: &str,
+    ) -> Result<Value> {
+        Ok(format.format(&self.select_tools(session_id, query).await?))
+    }
+
+    /// Returns the sub-agent directory, if one is configured
+    pub fn subagents(&self) -> Option<Arc<dyn SubAgentDirectory>> {
+        self.subagents.clone()
+    }
+
+    /// Looks up a registered sub-agent by name
+    pub fn subagent(&self, name: &str) -> Option<Arc<dyn SubAgent>> {
+        self.subagents.as_ref()?.lookup(name)
+    }
+
+    /// Runs the sub-agent registered as `name` on `input`.
+    ///
+    /// The delegation is logged but not attributed to a session; use
+    /// [`delegate_for`](Agent::delegate_for) to count it in a session's
+    /// routing report.
+    pub async fn delegate(&self, name: &str, input: impl Into<String>) -> Result<String> {
+        self.run_subagent(None, name, input.into()).await
+    }
+
+    /// Runs the sub-agent registered as `name` on `input` on behalf of
+    /// `session_id`
+    pub async fn delegate_for(
+        &self,
+        session_id: &str,
+        name: &str,
+        input: impl Into<String>,
+    ) -> Result<String> {
+        self.run_subagent(Some(session_id), name, input.into())
+            .await
+    }
+
+    /// Runs several sub-agents at once on behalf of `session_id`, each on
+    /// its own input, and returns their outputs in request order.
+    ///
+    /// Replies are parsed as JSON where possible, ready for
+    /// [`join_results`](crate::aggregate::join_results) and the other
+    /// combinators in [`aggregate`](crate::aggregate).
+    pub async fn delegate_all(
+        &self,
+        session_id: &str,
+        requests: Vec<(String, String)>,
+    ) -> Vec<SubAgentOutput> {
+        let runs = requests.into_iter().map(|(name, input)| async move {
+            let reply = self.run_subagent(Some(session_id), &name, input).await;
+            SubAgentOutput::from_reply(name, reply)
+        });
+        futures::future::join_all(runs).await
+    }
+
+    async fn run_subagent(
+        &self,
+        session_id: Option<&str>,
+        name: &str,
+        input: String,
+    ) -> Result<String> {
+        let subagent = self
+            .subagent(name)
+            .ok_or_else(|| AgentError::AgentNotFound(name.to_string()))?;
+        let mut timer = RouteTimer::start();
+        let (result, elapsed) = timed(subagent.run(input)).await;
+        timer.stage("subagent", elapsed);
+        let event = timer.finish(RoutePath::SubAgent, Some(subagent.name()), result.is_ok());
+        match session_id {
+            Some(session_id) => self.routing.record(session_id, event),
+            None => crate::routing::emit(None, &event),
+        }
+        result
+    }
+
+    /// Returns which paths handled `session_id`'s requests and how long
+    /// each stage took
+    pub fn routing_report(&self, session_id: &str) -> RoutingReport {
+        self.routing.report(session_id)
+    }
+
+    /// Forgets the routing report of `session_id`
+    pub fn clear_routing_report(&self, session_id: &str) {
+        self.routing.clear(session_id);
+    }
+
+    /// Forgets the idempotency keys of `session_id`, so replays run again
+    pub fn clear_idempotency_keys(&self, session_id: &str) {
+        self.idempotency.clear(session_id);
+    }
+
+    /// Returns the tokens and cost of `session_id`'s generations
+    pub fn cost_report(&self, session_id: &str) -> CostReport {
+        self.costs.session(session_id)
+    }
+
+    /// Returns the tokens and cost of every generation of this agent
+    pub fn total_cost_report(&self) -> CostReport {
+        self.costs.agent()
+    }
+
+    /// Forgets the spending of `session_id`, which also lifts its session
+    /// budget; the agent total is kept
+    pub fn clear_cost_report(&self, session_id: &str) {
+        self.costs.clear(session_id);
+    }
+
+    /// Calls `model` in an `llm.generate` span and charges the call to
+    /// `session_id`
+    async fn call_model(
+        &self,
+        session_id: &str,
+        model: &Arc<dyn LLM>,
+        messages: Vec<Message>,
+        files: Option<Vec<File>>,
+        settings: &GenerationSettings,
+        budget: Option<&CostBudget>,
+    ) -> Result<GenerationResponse> {
+        let call = ModelCall::start(
+            session_id,
+            model.model_name(),
+            self.tokenizers.lookup(model.model_name()),
+            &messages,
+        );
+        let response = model
+            .generate_with(messages, files, settings)
+            .instrument(call.span())
+            .await;
+        let usage = call.finish(response.as_ref().ok().map(|r| r.content.as_str()));
+        self.charge(session_id, usage, budget);
+        response
+    }
+
+    /// Prices the tokens of a model call and adds them to the spending of
+    /// `session_id`; models without a known price cost nothing
+    fn charge(&self, session_id: &str, usage: Option<ModelUsage>, budget: Option<&CostBudget>) {
+        let Some(usage) = usage else {
+            return;
+        };
+        let cost = self.pricing.lookup(&usage.model).map_or(0.0, |pricing| {
+            pricing.cost(usage.input_tokens, usage.output_tokens)
+        });
+        self.costs.record(
+            session_id,
+            &usage.model,
+            usage.input_tokens,
+            usage.output_tokens,
+            cost,
+            budget,
+        );
+    }
+
+    /// Checks that `plan` is well formed and every step binds to a tool or
+    /// sub-agent this agent has
+    pub fn check_plan(&self, plan: &ExecutionPlan) -> Result<()> {
+        plan.validate()?;
+        for step in &plan.steps {
+            match &step.target {
+                StepTarget::Tool { name } => {
+                    if self.tool_catalog.get(name).is_none() {
+                        return Err(AgentError::ToolNotFound(name.clone()));
+                    }
+                }
+                StepTarget::SubAgent { name } => {
+                    if self.subagent(name).is_none() {
+                        return Err(AgentError::AgentNotFound(name.clone()));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `plan` on behalf of `session_id`, with `input` standing for
+    /// `{{input}}` in step arguments.
+    ///
+    /// Stages run one after another and the steps of a stage concurrently.
+    /// Tool steps get their rendered arguments; sub-agent steps get their
+    /// rendered `input` argument, or the plan's input without one. Returns
+    /// the output of the final step, or the labeled outputs of several;
+    /// the first failing step fails the run.
+    pub async fn run_plan(
+        &self,
+        session_id: &str,
+        plan: &ExecutionPlan,
+        input: impl Into<String>,
+    ) -> Result<String> {
+        self.check_plan(plan)?;
+        let input = input.into();
+        let mut outputs = HashMap::from([(crate::plan::PLAN_INPUT.to_string(), input.clone())]);
+        for stage in plan.stages()? {
+            let runs = stage.iter().map(|step| {
+                let mut arguments = step.render_arguments(&outputs);
+                let input = &input;
+                async move {
+                    match &step.target {
+                        StepTarget::Tool { name } => {
+                            self.invoke_tool(session_id, name, arguments).await
+                        }
+                        StepTarget::SubAgent { name } => {
+                            let input = match arguments.remove(crate::plan::PLAN_INPUT) {
+                                Some(serde_json::Value::String(text)) => text,
+                                Some(other) => other.to_string(),
+                                None => input.clone(),
+                            };
+                            self.run_subagent(Some(session_id), name, input).await
+                        }
+                    }
+                }
+            });
+            let results = futures::future::try_join_all(runs).await?;
+            for (step, output) in stage.iter().zip(results) {
+                outputs.insert(step.id.clone(), output);
+            }
+        }
+        let finals = plan.final_steps();
+        Ok(match finals.as_slice() {
+            [step] => outputs.remove(&step.id).unwrap_or_default(),
+            finals => crate::plan::join_labeled(
+                finals
+                    .iter()
+                    .map(|step| (step.id.as_str(), outputs[&step.id].as_str())),
+            ),
+        })
+    }
+
+    /// Describes what the agent can do: its tools and sub-agents, one per
+    /// line, in registration order
+    pub fn capability_description(&self) -> String {
+        let mut sections = Vec::new();
+
+        let tools: Vec<String> = self
+            .tool_catalog
+            .specs()
+            .into_iter()
+            .map(|spec| format!("- {}: {}", spec.name, spec.description))
+            .collect();
+        if !tools.is_empty() {
+            sections.push(format!("Tools:\n{}", tools.join("\n")));
+        }
+
+        let subagents: Vec<String> = self
+            .subagent_infos()
+            .into_iter()
+            .map(|info| format!("- {}: {}", info.name, info.description))
+            .collect();
+        if !subagents.is_empty() {
+            sections.push(format!("Sub-agents:\n{}", subagents.join("\n")));
+        }
+
+        sections.join("\n\n")
+    }
+
+    fn subagent_infos(&self) -> Vec<SubAgentInfo> {
+        self.subagents
+            .as_ref()
+            .map(|directory| {
+                directory
+                    .all()
+                    .iter()
+                    .map(|subagent| SubAgentInfo::of(subagent.as_ref()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Checkpoints the agent state for persistence
+    pub async fn checkpoint(&self, session_id: &str) -> Result<Vec<u8>> {
+        let recent = self.memory.retrieve_recent(session_id).await?;
+        let state = self.checkpoint_state(recent, None);
+        serde_json::to_vec(&state).map_err(AgentError::SerializationError)
+    }
+
+    fn checkpoint_state(
+        &self,
+        short_term: Vec<MemoryRecord>,
+        parent: Option<crate::types::CheckpointParent>,
+    ) -> AgentState {
+        AgentState {
+            version: crate::types::AGENT_STATE_VERSION,
+            system_prompt: self.options().system_prompt.clone(),
+            short_term,
+            joined_spaces: None,
+            subagents: self.subagents.as_ref().map(|_| self.subagent_infos()),
+            timestamp: Utc::now(),
+            parent,
+            record_ids: None,
+        }
+    }
+
+    /// Restores agent state from checkpoint.
+    ///
+    /// Records are deduplicated by id and those already in the backing store
+    /// are skipped, so restoring the same checkpoint twice does not duplicate
+    /// history. Checkpoints of older rs-agent versions
+    /// are migrated first; newer ones fail with
+    /// [`AgentError::UnsupportedVersion`].
+    ///
+    /// Incremental checkpoints need their parents and fail with
+    /// [`AgentError::InvalidState`]; restore them with
+    /// [`Agent::restore_latest`] or [`Agent::restore_from`].
+    pub async fn restore(&self, _session_id: &str, data: &[u8]) -> Result<()> {
+        let state = AgentState::from_checkpoint(data)?;
+        if state.parent.is_some() {
+            return Err(AgentError::InvalidState(
+                "Incremental checkpoint needs its checkpointer to be restored".to_string(),
+            ));
+        }
+        self.restore_state(state).await
+    }
+
+    async fn restore_state(&self, state: AgentState) -> Result<()> {
+        // Sub-agents are code, not state; flag the ones this agent lacks
+        for info in state.subagents.iter().flatten() {
+            if self.subagent(&info.name).is_none() {
+                tracing::warn!(
+                    subagent = %info.name,
+                    "Checkpoint references a sub-agent that is not registered"
+                );
+            }
+        }
+
+        self.memory
+            .restore(state.short_term, self.restore_keep_last)
+            .await?;
+        Ok(())
+    }
+
+    /// Checkpoints `session_id` into `checkpointer`, returning the checkpoint id
+    /// to pass to [`restore_from`](Agent::restore_from)
+    pub async fn checkpoint_to(
+        &self,
+        checkpointer: &dyn CheckpointStore,
+        session_id: &str,
+    ) -> Result<String> {
+        let data = self.checkpoint(session_id).await?;
+        checkpointer.save(session_id, &data).await
+    }
+
+    /// Checkpoints only the records of `session_id` added or updated since
+    /// its newest checkpoint in `checkpointer`, returning the checkpoint id.
+    ///
+    /// The checkpoint names its parent by id and content hash, and restoring
+    /// it with [`Agent::restore_latest`] or [`Agent::restore_from`] replays
+    /// the chain from the last full checkpoint. It also lists the ids of all
+    /// the session's records, so records gone since the parent stay gone
+    /// after a restore. A full checkpoint is taken
+    /// instead when the session has none yet, when the chain reaches
+    /// [`with_checkpoint_chain_limit`](Agent::with_checkpoint_chain_limit),
+    /// or when it is broken, e.g. by [`CheckpointStore::prune`].
+    pub async fn save_incremental_checkpoint(
+        &self,
+        checkpointer: &dyn CheckpointStore,
+        session_id: &str,
+    ) -> Result<String> {
+        let Some(latest) = checkpointer.list(session_id).await?.pop() else {
+            return self.checkpoint_to(checkpointer, session_id).await;
+        };
+        let Some(data) = checkpointer.load(session_id, &latest.id).await? else {
+            return self.checkpoint_to(checkpointer, session_id).await;
+        };
+        let parent = crate::types::CheckpointParent {
+            id: latest.id,
+            hash: crate::blob::content_hash(&data),
+        };
+        let base = match self
+            .resolve_checkpoint(checkpointer, session_id, &data)
+            .await
+        {
+            Ok((_, depth)) if depth >= self.checkpoint_chain_limit => None,
+            Ok((base, _)) => Some(base),
+            Err(e) => {
+                tracing::warn!(
+                    "Checkpoint chain is broken, taking a full checkpoint: {}",
+                    e
+                );
+                None
+            }
+        };
+        let Some(base) = base else {
+            return self.checkpoint_to(checkpointer, session_id).await;
+        };
+
+        let known: HashMap<Uuid, u64> = base
+            .short_term
+            .iter()
+            .map(|record| (record.id, record.version))
+            .collect();
+        let recent = self.memory.retrieve_recent(session_id).await?;
+        let record_ids = recent.iter().map(|record| record.id).collect();
+        let added = recent
+            .into_iter()
+            .filter(|record| known.get(&record.id) != Some(&record.version))
+            .collect();
+        let mut state = self.checkpoint_state(added, Some(parent));
+        // Lets restores drop the records deleted since the parent
+        state.record_ids = Some(record_ids);
+        let data = serde_json::to_vec(&state).map_err(AgentError::SerializationError)?;
+        checkpointer.save(session_id, &data).await
+    }
+
+    /// Parses checkpoint `data` and replays it onto its chain of parents,
+    /// returning the full state and how many incremental checkpoints it took
+    async fn resolve_checkpoint(
+        &self,
+        checkpointer: &dyn CheckpointStore,
+        session_id: &str,
+        data: &[u8],
+    ) -> Result<(AgentState, usize)> {
+        let mut state = AgentState::from_checkpoint(data)?;
+        let mut deltas = Vec::new();
+        while let Some(parent) = state.parent.take() {
+            let data = checkpointer
+                .load(session_id, &parent.id)
+                .await?
+                .ok_or_else(|| {
+                    AgentError::InvalidState(format!("Parent checkpoint {} is missing", parent.id))
+                })?;
+            if crate::blob::content_hash(&data) != parent.hash {
+                return Err(AgentError::InvalidState(format!(
+                    "Parent checkpoint {} does not match its hash",
+                    parent.id
+                )));
+            }
+            deltas.push(std::mem::replace(
+                &mut state,
+                AgentState::from_checkpoint(&data)?,
+            ));
+        }
+
+        let depth = deltas.len();
+        for delta in deltas.into_iter().rev() {
+            for record in delta.short_term {
+                match state
+                    .short_term
+                    .iter_mut()
+                    .find(|known| known.id == record.id)
+                {
+                    Some(known) => *known = record,
+                    None => state.short_term.push(record),
+                }
+            }
+            if let Some(ids) = &delta.record_ids {
+                let mut records: HashMap<Uuid, MemoryRecord> = state
+                    .short_term
+                    .drain(..)
+                    .map(|record| (record.id, record))
+                    .collect();
+                state.short_term = ids.iter().filter_map(|id| records.remove(id)).collect();
+            }
+            state.system_prompt = delta.system_prompt;
+            state.subagents = delta.subagents;
+            state.timestamp = delta.timestamp;
+        }
+        Ok((state, depth))
+    }
+
+    /// Restores the newest checkpoint of `session_id` from `checkpointer`.
+    ///
+    /// Returns false if the session has no checkpoint yet.
+    pub async fn restore_latest(
+        &self,
+        checkpointer: &dyn CheckpointStore,
+        session_id: &str,
+    ) -> Result<bool> {
+        match checkpointer.latest(session_id).await? {
+            Some(data) => {
+                let (state, _) = self
+                    .resolve_checkpoint(checkpointer, session_id, &data)
+                    .await?;
+                self.restore_state(state).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Restores the checkpoint `id` of `session_id` from `checkpointer`, e.g.
+    /// an older one picked from [`CheckpointStore::list`].
+    ///
+    /// Returns false if there is no such checkpoint.
+    pub async fn restore_from(
+        &self,
+        checkpointer: &dyn CheckpointStore,
+        session_id: &str,
+        id: &str,
+    ) -> Result<bool> {
+        match checkpointer.load(session_id, id).await? {
+            Some(data) => {
+                let (state, _) = self
+                    .resolve_checkpoint(checkpointer, session_id, &data)
+                    .await?;
+                self.restore_state(state).await?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InMemoryStore;
+    use crate::tools::policy::{ToolRule, ToolTarget};
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Embeds texts mentioning an order number near each other
+    struct OrderEmbedder;
+
+    #[async_trait]
+    impl Embedder for OrderEmbedder {
+        async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+            Ok(if text.contains("ORD") {
+                vec![1.0, 0.0]
+            } else {
+                vec![0.0, 1.0]
+            })
+        }
+    }
+
+    /// Replies with the prompt it was given
+    struct PromptEchoLLM;
+
+    #[async_trait]
+    impl LLM for PromptEchoLLM {
+        async fn generate(
+            &self,
+            messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<GenerationResponse> {
+            let prompt = messages
+                .iter()
+                .map(|m| m.content.as_str())
+                .collect::<Vec<_>>()
+                .join("\n");
+            Ok(GenerationResponse {
+                content: prompt,
+                metadata: None,
+            })
+        }
+
+        fn model_name(&self) -> &str {
+            "prompt-echo"
+        }
+    }
+
+    /// Model that accepts neither images nor long prompts
+    struct TextOnlyLLM;
+
+    #[async_trait]
+    impl LLM for TextOnlyLLM {
+        async fn generate(
+            &self,
+            _messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<GenerationResponse> {
+            Ok(GenerationResponse {
+                content: "ok".to_string(),
+                metadata: None,
+            })
+        }
+
+        fn model_name(&self) -> &str {
+            "text-only"
+        }
+
+        fn capabilities(&self) -> crate::models::Capabilities {
+            crate::models::Capabilities {
+                max_context_tokens: Some(1024),
+                ..Default::default()
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_files_rejected_without_vision() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(
+            Arc::new(TextOnlyLLM),
+            memory.clone(),
+            AgentOptions::default(),
+        );
+
+        let file = File {
+            mime_type: "image/png".to_string(),
+            data: vec![0; 4],
+        };
+        let err = agent
+            .generate_with_files("s", "What is this?", vec![file])
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AgentError::ConfigError(_)));
+        assert!(memory.retrieve_recent("s").await.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_prefetch_coverage() {
+        let now = Instant::now();
+        let prefetched = Prefetched {
+            input: "where is my order".to_string(),
+            records: Vec::new(),
+            fetched_at: now,
+        };
+        assert!(prefetched.covers("where is my order?", now));
+        assert!(!prefetched.covers("where is my order and when does it ship", now));
+        assert!(!prefetched.covers("cancel my order", now));
+        assert!(!prefetched.covers("where is my order?", now + PREFETCH_TTL * 2));
+    }
+
+    #[tokio::test]
+    async fn test_retrieval_recalls_memories_outside_window() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 2));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default())
+            .with_system_prompt("")
+            .with_retrieval(Arc::new(OrderEmbedder), 1);
+
+        agent
+            .generate_internal("s".to_string(), "My order is ORD-17".to_string(), None)
+            .await
+            .unwrap();
+        agent
+            .generate_internal("s".to_string(), "Nice weather".to_string(), None)
+            .await
+            .unwrap();
+
+        agent.prefetch("s", "Where is ORD").await.unwrap();
+        let response = agent
+            .generate_internal("s".to_string(), "Where is ORD-17?".to_string(), None)
+            .await
+            .unwrap();
+        assert!(response.content.starts_with(
+            "Relevant memories from earlier in this session:\n- user: My order is ORD-17"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_short_prefetch_is_searched_again() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 1));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default())
+            .with_retrieval(Arc::new(OrderEmbedder), 1);
+        for content in ["My order is ORD-17", "ORD-18 shipped", "Noted"] {
+            agent
+                .store_memory("s", "user", content, None)
+                .await
+                .unwrap();
+        }
+
+        agent.prefetch("s", "Where is ORD").await.unwrap();
+        let records = agent
+            .retrieve_relevant("s", "Where is ORD-17?", None, Some(2), None)
+            .await
+            .unwrap();
+        assert_eq!(records.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_retrieval_policy_sizes_retrieval_by_query_type() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 1));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default())
+            .with_retrieval(Arc::new(OrderEmbedder), 3)
+            .with_retrieval_policy(RetrievalPolicy::new().with_factoid_limit(1));
+        agent
+            .store_memory("s", "user", "My order is ORD-17", None)
+            .await
+            .unwrap();
+        agent
+            .store_memory("s", "user", "Nice weather", None)
+            .await
+            .unwrap();
+        // Push both out of the short-term window so retrieval can recall them
+        agent
+            .store_memory("s", "assistant", "Noted", None)
+            .await
+            .unwrap();
+
+        let math = agent
+            .retrieve_relevant("s", "What is 17 + 1?", None, None, None)
+            .await;
+        assert!(math.unwrap().is_empty());
+        let unknown = agent
+            .retrieve_relevant("s", "Hello", None, None, None)
+            .await;
+        assert!(unknown.unwrap().is_empty());
+
+        let factoid = agent
+            .retrieve_relevant("s", "What is ORD 17?", None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(factoid.len(), 1);
+        assert_eq!(factoid[0].content, "My order is ORD-17");
+
+        let complex = agent
+            .retrieve_relevant("s", "Explain how ORD shipping works", None, None, None)
+            .await
+            .unwrap();
+        assert_eq!(complex.len(), 2);
+        assert_eq!(complex[0].content, "My order is ORD-17");
+    }
+
+    #[tokio::test]
+    async fn test_restore_latest_checkpoint() {
+        let checkpointer = crate::checkpoint::InMemoryCheckpointer::new();
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default());
+        agent
+            .generate_internal("s".to_string(), "Remember ORD-17".to_string(), None)
+            .await
+            .unwrap();
+        agent.checkpoint_to(&checkpointer, "s").await.unwrap();
+
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let restored = Agent::new(
+            Arc::new(PromptEchoLLM),
+            memory.clone(),
+            AgentOptions::default(),
+        );
+        assert!(!restored
+            .restore_latest(&checkpointer, "other")
+            .await
+            .unwrap());
+        assert!(restored.restore_latest(&checkpointer, "s").await.unwrap());
+        assert_eq!(memory.retrieve_recent("s").await.unwrap().len(), 2);
+
+        let id = agent.checkpoint_to(&checkpointer, "s").await.unwrap();
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let restored = Agent::new(
+            Arc::new(PromptEchoLLM),
+            memory.clone(),
+            AgentOptions::default(),
+        );
+        assert!(restored
+            .restore_from(&checkpointer, "s", &id)
+            .await
+            .unwrap());
+        assert_eq!(memory.retrieve_recent("s").await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_incremental_checkpoints_store_only_new_records() {
+        let checkpointer = crate::checkpoint::InMemoryCheckpointer::new();
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 8));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default())
+            .with_checkpoint_chain_limit(1);
+        let mut ids = Vec::new();
+        for input in ["Remember ORD-17", "And ORD-18", "And ORD-19"] {
+            agent
+                .generate_internal("s".to_string(), input.to_string(), None)
+                .await
+                .unwrap();
+            let id = agent
+                .save_incremental_checkpoint(&checkpointer, "s")
+                .await
+                .unwrap();
+            ids.push(id);
+        }
+
+        let load = |id: String| {
+            let checkpointer = &checkpointer;
+            async move {
+                let data = checkpointer.load("s", &id).await.unwrap().unwrap();
+                AgentState::from_checkpoint(&data).unwrap()
+            }
+        };
+        let delta = load(ids[1].clone()).await;
+        assert_eq!(delta.parent.as_ref().unwrap().id, ids[0]);
+        assert_eq!(delta.short_term.len(), 2);
+        // The chain limit makes the third checkpoint a full one
+        let full = load(ids[2].clone()).await;
+        assert!(full.parent.is_none());
+        assert_eq!(full.short_term.len(), 6);
+
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 8));
+        let restored = Agent::new(
+            Arc::new(PromptEchoLLM),
+            memory.clone(),
+            AgentOptions::default(),
+        );
+        assert!(restored
+            .restore_from(&checkpointer, "s", &ids[1])
+            .await
+            .unwrap());
+        let recent = memory.retrieve_recent("s").await.unwrap();
+        assert_eq!(recent.len(), 4);
+        assert_eq!(recent[2].content, "And ORD-18");
+
+        let data = checkpointer.load("s", &ids[1]).await.unwrap().unwrap();
+        assert!(matches!(
+            restored.restore("s", &data).await,
+            Err(AgentError::InvalidState(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_incremental_checkpoints_keep_deletions() {
+        let checkpointer = crate::checkpoint::InMemoryCheckpointer::new();
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 8));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default());
+        agent
+            .generate_internal("s".to_string(), "Remember ORD-17".to_string(), None)
+            .await
+            .unwrap();
+        let base_id = agent.checkpoint_to(&checkpointer, "s").await.unwrap();
+        let base = checkpointer.load("s", &base_id).await.unwrap().unwrap();
+
+        // A delta taken after the user turn was deleted
+        let kept = AgentState::from_checkpoint(&base).unwrap().short_term[1].id;
+        let mut delta = agent.checkpoint_state(
+            Vec::new(),
+            Some(crate::types::CheckpointParent {
+                id: base_id,
+                hash: crate::blob::content_hash(&base),
+            }),
+        );
+        delta.record_ids = Some(vec![kept]);
+        let data = serde_json::to_vec(&delta).unwrap();
+
+        let (state, depth) = agent
+            .resolve_checkpoint(&checkpointer, "s", &data)
+            .await
+            .unwrap();
+        assert_eq!(depth, 1);
+        let ids: Vec<Uuid> = state.short_term.iter().map(|record| record.id).collect();
+        assert_eq!(ids, [kept]);
+    }
+
+    #[tokio::test]
+    async fn test_restore_migrates_versions() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(
+            Arc::new(PromptEchoLLM),
+            memory.clone(),
+            AgentOptions::default(),
+        );
+
+        // Written before states were versioned
+        let legacy = br#"{"system_prompt": "", "short_term": [{
+            "id": "9f1c2a4e-1b7d-4c1e-8d2a-0a6b3c5d7e9f", "session_id": "s", "role": "user",
+            "content": "hi", "importance": 0.5, "timestamp": "2024-05-01T12:00:00Z"
+        }], "timestamp": "2024-05-01T12:00:00Z"}"#;
+        agent.restore("s", legacy).await.unwrap();
+        assert_eq!(memory.retrieve_recent("s").await.unwrap().len(), 1);
+
+        let data = agent.checkpoint("s").await.unwrap();
+        let mut state: serde_json::Value = serde_json::from_slice(&data).unwrap();
+        assert_eq!(state["version"], crate::types::AGENT_STATE_VERSION);
+        state["version"] = (crate::types::AGENT_STATE_VERSION + 1).into();
+        assert!(matches!(
+            agent
+                .restore("s", &serde_json::to_vec(&state).unwrap())
+                .await,
+            Err(AgentError::UnsupportedVersion(_))
+        ));
+    }
+
+    struct UpperTool;
+
+    #[async_trait]
+    impl crate::tools::Tool for UpperTool {
+        fn spec(&self) -> crate::types::ToolSpec {
+            crate::types::ToolSpec {
+                name: "upper".to_string(),
+                description: "Uppercases the input".to_string(),
+                input_schema: serde_json::json!({"type": "object"}),
+                examples: None,
+                output_schema: None,
+            }
+        }
+
+        async fn invoke(&self, req: ToolRequest) -> Result<crate::types::ToolResponse> {
+            let input = req.arguments.get("input").and_then(|v| v.as_str());
+            Ok(crate::types::ToolResponse {
+                content: input.unwrap_or_default().to_uppercase(),
+                metadata: None,
+            })
+        }
+    }
+
+    #[test]
+    fn test_with_tools_keeps_builtin_tools() {
+        use crate::blob::{InMemoryBlobStore, EXPAND_BLOB_TOOL};
+
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let offload = BlobOffload::new(Arc::new(InMemoryBlobStore::new()));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default())
+            .with_blob_offload(offload)
+            .with_tools(Arc::new(crate::catalog::StaticToolCatalog::new()));
+
+        let specs = agent.tools().specs();
+        assert!(specs.iter().any(|spec| spec.name == EXPAND_BLOB_TOOL));
+    }
+
+    #[tokio::test]
+    async fn test_tool_selection_lists_relevant_tools_in_prompt() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let options = AgentOptions {
+            tool_selection: Some(ToolSelection::new().with_min_score(0.5)),
+            ..AgentOptions::default()
+        };
+        let policy = ToolPolicy::new()
+            .with_rule(ToolRule::deny(ToolTarget::name("upper")).for_session("denied"));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, options)
+            .with_tool_selector(Arc::new(OrderEmbedder))
+            .with_tool_policy(policy);
+        agent.tools().register(Box::new(UpperTool)).unwrap();
+
+        let reply = agent
+            .generate_internal("s".to_string(), "Uppercase this".to_string(), None)
+            .await
+            .unwrap();
+        assert!(reply.content.contains("- upper: Uppercases the input"));
+        // A fresh session, so the echoed history doesn't list the tool again
+        let reply = agent
+            .generate_internal("t".to_string(), "Where is ORD-17?".to_string(), None)
+            .await
+            .unwrap();
+        assert!(!reply.content.contains("- upper:"));
+        assert!(agent
+            .select_tools("s", "Track ORD-17")
+            .await
+            .unwrap()
+            .is_empty());
+
+        let reply = agent
+            .generate_with_options("s", "Uppercase this", Default::default())
+            .await
+            .unwrap();
+        let selected = &reply.metadata.unwrap()[crate::tools::SELECTED_TOOLS_KEY];
+        assert_eq!(selected, r#"["upper"]"#);
+        assert_eq!(
+            agent
+                .select_tools("s", "Uppercase this")
+                .await
+                .unwrap()
+                .len(),
+            1
+        );
+        assert!(agent
+            .select_tools("denied", "Uppercase this")
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tool_results_record_provenance() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(
+            Arc::new(PromptEchoLLM),
+            memory.clone(),
+            AgentOptions::default(),
+        );
+        agent.tool_catalog.register(Box::new(UpperTool)).unwrap();
+
+        let mut arguments = HashMap::new();
+        arguments.insert("input".to_string(), serde_json::json!("hi"));
+        let hash = arguments_hash(&arguments);
+        agent.invoke_tool("s", "upper", arguments).await.unwrap();
+
+        let records = memory.retrieve_recent("s").await.unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].content, "HI");
+        let metadata = records[0].metadata.as_ref().unwrap();
+        assert_eq!(metadata[TOOL_NAME_KEY], "upper");
+        assert_eq!(metadata[TOOL_ARGS_HASH_KEY], hash);
+        assert_eq!(metadata[TOOL_PROVIDER_KEY], LOCAL_PROVIDER);
+        assert!(metadata[TOOL_LATENCY_MS_KEY].parse::<u64>().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_generate_with_tools_reports_partial_failures() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 8));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default());
+        agent.tool_catalog.register(Box::new(UpperTool)).unwrap();
+
+        let calls = vec![
+            ToolCall::new("upper").with_argument("input", "hi"),
+            ToolCall::new("missing"),
+        ];
+        let response = agent
+            .generate_with_tools("s", "Summarize", calls)
+            .await
+            .unwrap();
+
+        // The model saw the successful result and the failure
+        assert!(response.content.contains("HI"));
+        assert!(response.content.contains("Tool missing failed"));
+        let statuses: Vec<crate::tools::ToolCallStatus> =
+            serde_json::from_str(&response.metadata.unwrap()[crate::tools::TOOL_CALLS_KEY])
+                .unwrap();
+        assert!(statuses[0].ok);
+        assert!(!statuses[1].ok);
+        assert!(statuses[1].error.as_deref().unwrap().contains("missing"));
+    }
+
+    /// Adds a prompt rule, redacts memory and blocks one tool
+    struct PolicyHooks;
+
+    #[async_trait]
+    impl Hooks for PolicyHooks {
+        async fn on_prompt_built(
+            &self,
+            _session_id: &str,
+            messages: &mut Vec<Message>,
+        ) -> Result<()> {
             messages.push(Message {
                 role: Role::System,
-                content: self.system_prompt.clone(),
+                content: "Be brief.".to_string(),
                 metadata: None,
             });
+            Ok(())
         }
 
-        // Retrieve recent conversation history
-        let recent_memories = self.memory.retrieve_recent(session_id).await?;
+        async fn before_tool_call(
+            &self,
+            _session_id: &str,
+            tool_name: &str,
+            _arguments: &mut HashMap<String, serde_json::Value>,
+        ) -> Result<()> {
+            if tool_name == "upper" {
+                return Err(AgentError::ToolError("upper is not allowed".to_string()));
+            }
+            Ok(())
+        }
 
-        // Add context from memory (limited by context_limit)
-        let mut token_count = 0;
-        for record in recent_memories.iter().rev() {
-            // Simple token estimation (4 chars ≈ 1 token)
-            let estimated_tokens = record.content.len() / 4;
-            if token_count + estimated_tokens > self.context_limit {
-                break;
+        async fn on_memory_store(&self, record: &mut MemoryRecord) -> Result<()> {
+            record.content = record.content.replace("secret", "[redacted]");
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_hooks_edit_prompt_memory_and_block_tools() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(
+            Arc::new(PromptEchoLLM),
+            memory.clone(),
+            AgentOptions::default(),
+        )
+        .with_hooks(Arc::new(PolicyHooks));
+        agent.tool_catalog.register(Box::new(UpperTool)).unwrap();
+
+        let response = agent
+            .generate_with_options("s", "my secret", Default::default())
+            .await
+            .unwrap();
+        assert!(response.content.ends_with("Be brief."));
+        let records = memory.retrieve_recent("s").await.unwrap();
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().all(|r| !r.content.contains("secret")));
+
+        assert!(matches!(
+            agent.invoke_tool("s", "upper", HashMap::new()).await,
+            Err(AgentError::ToolError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_tool_loop_limits_reset_on_user_input() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default())
+            .with_tool_loop_limits(ToolLoopLimits::new().with_max_identical_calls(2));
+        agent.tool_catalog.register(Box::new(UpperTool)).unwrap();
+
+        let arguments = HashMap::from([("input".to_string(), serde_json::json!("hi"))]);
+        for _ in 0..2 {
+            agent
+                .invoke_tool("s", "upper", arguments.clone())
+                .await
+                .unwrap();
+        }
+        assert!(matches!(
+            agent.invoke_tool("s", "upper", arguments.clone()).await,
+            Err(AgentError::ToolLoopDetected(_))
+        ));
+
+        agent.generate("s", "thanks").await.unwrap();
+        agent.invoke_tool("s", "upper", arguments).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_tool_policy_denies_per_user() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        memory.bind_user("guest-session", "guest");
+        let policy = ToolPolicy::new()
+            .with_rule(ToolRule::deny(ToolTarget::name("upper")).for_user("guest"));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default())
+            .with_tool_policy(policy);
+        agent.tool_catalog.register(Box::new(UpperTool)).unwrap();
+
+        let arguments = HashMap::from([("input".to_string(), serde_json::json!("hi"))]);
+        assert!(matches!(
+            agent
+                .invoke_tool("guest-session", "upper", arguments.clone())
+                .await,
+            Err(AgentError::ToolDenied(_))
+        ));
+        assert_eq!(
+            agent.invoke_tool("s", "upper", arguments).await.unwrap(),
+            "HI"
+        );
+    }
+
+    struct NamespacedUpperTool;
+
+    #[async_trait]
+    impl crate::tools::Tool for NamespacedUpperTool {
+        fn spec(&self) -> crate::types::ToolSpec {
+            crate::types::ToolSpec {
+                name: "text.upper".to_string(),
+                ..UpperTool.spec()
             }
+        }
 
-            messages.push(Message {
-                role: match record.role.as_str() {
-                    "user" => Role::User,
-                    "assistant" => Role::Assistant,
-                    "tool" => Role::Tool,
-                    _ => Role::User,
-                },
-                content: record.content.clone(),
-                metadata: record.metadata.clone(),
-            });
+        async fn invoke(&self, req: ToolRequest) -> Result<crate::types::ToolResponse> {
+            UpperTool.invoke(req).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_calls_follow_turn_overrides() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let namespaces = Arc::new(ToolNamespaces::new().with_disabled("text"));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default())
+            .with_tool_namespaces(namespaces);
+        agent
+            .tool_catalog
+            .register(Box::new(NamespacedUpperTool))
+            .unwrap();
+
+        let arguments = HashMap::from([("input".to_string(), serde_json::json!("hi"))]);
+        assert!(matches!(
+            agent
+                .invoke_tool("s", "text.upper", arguments.clone())
+                .await,
+            Err(AgentError::ToolDenied(_))
+        ));
+        let enabled = crate::types::GenerateOptions {
+            enabled_namespaces: Some(vec!["text".to_string()]),
+            ..Default::default()
+        };
+        let output = agent
+            .invoke_tool_with_options("s", "text.upper", arguments.clone(), &enabled)
+            .await
+            .unwrap();
+        assert_eq!(output, "HI");
 
-            token_count += estimated_tokens;
+        let narrowed = crate::types::GenerateOptions {
+            allowed_tools: Some(vec!["other".to_string()]),
+            ..enabled
+        };
+        assert!(matches!(
+            agent
+                .invoke_tool_with_options("s", "text.upper", arguments, &narrowed)
+                .await,
+            Err(AgentError::ToolDenied(_))
+        ));
+    }
+
+    struct Researcher;
+
+    #[async_trait]
+    impl SubAgent for Researcher {
+        fn name(&self) -> String {
+            "researcher".to_string()
         }
 
-        // Add current user input
-        messages.push(Message {
-            role: Role::User,
-            content: user_input.to_string(),
-            metadata: None,
-        });
+        fn description(&self) -> String {
+            "Finds sources".to_string()
+        }
 
-        Ok(messages)
+        async fn run(&self, input: String) -> Result<String> {
+            Ok(format!("sources for {}", input))
+        }
     }
 
-    async fn generate_internal(
-        &self,
-        session_id: String,
-        user_input: String,
-        files: Option<Vec<File>>,
-    ) -> Result<GenerationResponse> {
-        // Store user message in memory
-        self.store_memory(&session_id, "user", &user_input, None)
-            .await?;
+    #[tokio::test]
+    async fn test_subagents_in_description_and_checkpoint() {
+        let directory = Arc::new(crate::catalog::StaticSubAgentDirectory::new());
+        directory.register(Arc::new(Researcher)).unwrap();
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default())
+            .with_subagents(directory);
+        agent.tools().register(Box::new(UpperTool)).unwrap();
 
-        // Try CodeMode orchestration before invoking the primary model
-        let has_files = files.as_ref().map(|f| !f.is_empty()).unwrap_or(false);
-        if !has_files {
-            if let Some((content, metadata)) = self
-                .try_codemode_orchestration(&session_id, &user_input)
-                .await?
-            {
-                self.store_memory(&session_id, "assistant", &content, metadata.clone())
-                    .await?;
+        assert_eq!(
+            agent.capability_description(),
+            "Tools:\n- upper: Uppercases the input\n\nSub-agents:\n- researcher: Finds sources"
+        );
+        assert_eq!(
+            agent.delegate("Researcher", "rust").await.unwrap(),
+            "sources for rust"
+        );
+        assert!(matches!(
+            agent.delegate("writer", "x").await,
+            Err(AgentError::AgentNotFound(_))
+        ));
 
-                return Ok(GenerationResponse { content, metadata });
-            }
+        let data = agent.checkpoint("s").await.unwrap();
+        let state = AgentState::from_checkpoint(&data).unwrap();
+        assert_eq!(
+            state.subagents.unwrap(),
+            [SubAgentInfo {
+                name: "researcher".to_string(),
+                description: "Finds sources".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_check_plan_binds_tools_and_subagents() {
+        let directory = Arc::new(crate::catalog::StaticSubAgentDirectory::new());
+        directory.register(Arc::new(Researcher)).unwrap();
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default())
+            .with_subagents(directory);
+        agent.tools().register(Box::new(UpperTool)).unwrap();
+
+        let plan = ExecutionPlan::new("Shout the sources")
+            .with_step(crate::plan::PlanStep::subagent("find", "researcher"))
+            .with_step(
+                crate::plan::PlanStep::tool("shout", "upper")
+                    .with_argument("input", "{{find}}")
+                    .after("find"),
+            );
+        agent.check_plan(&plan).unwrap();
+
+        let unbound = plan.with_step(crate::plan::PlanStep::tool("post", "slack"));
+        assert!(matches!(
+            agent.check_plan(&unbound),
+            Err(AgentError::ToolNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_run_plan_feeds_outputs_forward() {
+        let directory = Arc::new(crate::catalog::StaticSubAgentDirectory::new());
+        directory.register(Arc::new(Researcher)).unwrap();
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default())
+            .with_subagents(directory);
+        agent.tools().register(Box::new(UpperTool)).unwrap();
+
+        let plan = ExecutionPlan::new("Shout the sources")
+            .with_step(crate::plan::PlanStep::subagent("find", "researcher"))
+            .with_step(
+                crate::plan::PlanStep::tool("shout", "upper")
+                    .with_argument("input", "{{find}}")
+                    .after("find"),
+            );
+        assert_eq!(
+            agent.run_plan("s", &plan, "rust").await.unwrap(),
+            "SOURCES FOR RUST"
+        );
+
+        let plan = crate::flow::flow()
+            .parallel(["researcher", "researcher"])
+            .reduce("researcher")
+            .compile("Compare")
+            .unwrap();
+        assert_eq!(
+            agent.run_plan("s", &plan, "rust").await.unwrap(),
+            "sources for researcher:\nsources for rust\n\nresearcher:\nsources for rust"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delegate_all_keeps_request_order() {
+        let directory = Arc::new(crate::catalog::StaticSubAgentDirectory::new());
+        directory.register(Arc::new(Researcher)).unwrap();
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default())
+            .with_subagents(directory);
+
+        let outputs = agent
+            .delegate_all(
+                "s",
+                vec![
+                    ("missing".to_string(), "rust".to_string()),
+                    ("researcher".to_string(), "rust".to_string()),
+                ],
+            )
+            .await;
+        assert!(matches!(
+            outputs[0].result,
+            Err(AgentError::AgentNotFound(_))
+        ));
+        let (name, reply) = crate::aggregate::first_success(outputs).unwrap();
+        assert_eq!(name, "researcher");
+        assert_eq!(reply, json!("sources for rust"));
+    }
+
+    /// Echoes the prompt, but streams a reply that breaks off
+    struct BrokenStreamLLM;
+
+    #[async_trait]
+    impl LLM for BrokenStreamLLM {
+        async fn generate(
+            &self,
+            messages: Vec<Message>,
+            files: Option<Vec<File>>,
+        ) -> Result<GenerationResponse> {
+            PromptEchoLLM.generate(messages, files).await
         }
 
-        // Build prompt with context
-        let messages = self.build_prompt(&session_id, &user_input).await?;
+        async fn generate_stream(
+            &self,
+            _messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<BoxStream<'static, Result<String>>> {
+            let chunks = vec![
+                Ok("Step one, ".to_string()),
+                Ok("step two".to_string()),
+                Err(AgentError::ModelError("connection reset".to_string())),
+            ];
+            Ok(stream::iter(chunks).boxed())
+        }
 
-        // Generate response
-        let response = self.model.generate(messages, files).await?;
+        fn model_name(&self) -> &str {
+            "broken-stream"
+        }
+    }
 
-        // Store assistant response in memory
-        self.store_memory(&session_id, "assistant", &response.content, None)
-            .await?;
+    #[tokio::test]
+    async fn test_interrupted_stream_is_kept_for_next_turn() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 8));
+        let agent = Agent::new(
+            Arc::new(BrokenStreamLLM),
+            memory.clone(),
+            AgentOptions::default(),
+        )
+        .with_system_prompt("");
 
-        Ok(response)
+        let chunks: Vec<Result<String>> = agent
+            .generate_stream("s", "How do I start?")
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks[2].is_err());
+
+        let recent = memory.retrieve_recent("s").await.unwrap();
+        assert_eq!(recent[1].content, "Step one, step two");
+        assert_eq!(recent[1].metadata.as_ref().unwrap()[TRUNCATED_KEY], "true");
+        // The failed stream is charged for what it produced
+        let report = agent.cost_report("s");
+        assert_eq!(report.total.generations, 1);
+        assert!(report.total.output_tokens > 0);
+
+        let response = agent
+            .generate_internal("s".to_string(), "Go on".to_string(), None)
+            .await
+            .unwrap();
+        assert!(response
+            .content
+            .contains("Step one, step two\n[This reply was interrupted before it finished.]"));
+
+        // Dropping the stream early keeps what already arrived
+        let mut stream = agent.generate_stream("c", "How do I start?").await.unwrap();
+        stream.next().await.unwrap().unwrap();
+        drop(stream);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        let recent = memory.retrieve_recent("c").await.unwrap();
+        assert_eq!(recent[1].content, "Step one, ");
+        assert_eq!(agent.cost_report("c").total.generations, 1);
     }
 
-    fn set_codemode(&mut self, engine: Arc<CodeModeUtcp>) {
-        self.codemode = Some(engine.clone());
-        // Expose codemode.run_code as a tool; ignore duplicate registrations
-        let _ = self
-            .tool_catalog
-            .register(Box::new(CodeModeTool::new(engine)));
+    /// Streams a reply that leaks a card number
+    struct LeakyStreamLLM;
+
+    #[async_trait]
+    impl LLM for LeakyStreamLLM {
+        async fn generate(
+            &self,
+            messages: Vec<Message>,
+            files: Option<Vec<File>>,
+        ) -> Result<GenerationResponse> {
+            PromptEchoLLM.generate(messages, files).await
+        }
+
+        async fn generate_stream(
+            &self,
+            _messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<BoxStream<'static, Result<String>>> {
+            let chunks = [
+                "Your card is ",
+                "4111 1111 ",
+                "1111 1111",
+                ", keep it safe.",
+            ];
+            Ok(stream::iter(chunks.map(|chunk| Ok(chunk.to_string()))).boxed())
+        }
+
+        fn model_name(&self) -> &str {
+            "leaky-stream"
+        }
     }
 
-    async fn try_codemode_orchestration(
-        &self,
-        _session_id: &str,
-        user_input: &str,
-    ) -> Result<Option<(String, Option<HashMap<String, String>>)>> {
-        let orchestrator = match self.codemode_orchestrator.as_ref() {
-            Some(o) => o,
-            None => return Ok(None),
+    #[tokio::test]
+    async fn test_stream_guard_stops_violating_reply() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 8));
+        let guard = StreamGuard::new()
+            .with_pattern("card_number", r"\b(?:\d[ -]?){13,16}\b")
+            .unwrap()
+            .with_redaction("[redacted]");
+        let agent = Agent::new(
+            Arc::new(LeakyStreamLLM),
+            memory.clone(),
+            AgentOptions::default(),
+        )
+        .with_stream_guard(guard.with_window(16));
+
+        let chunks: Vec<String> = agent
+            .generate_stream("s", "What's my card number?")
+            .await
+            .unwrap()
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        // The held-back window kept every digit from going out
+        assert_eq!(chunks, ["Your ca", "[redacted]"]);
+
+        let recent = memory.retrieve_recent("s").await.unwrap();
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[1].content, "[redacted]");
+        let metadata = recent[1].metadata.as_ref().unwrap();
+        assert_eq!(metadata[GUARDRAIL_VIOLATION_KEY], "card_number");
+        assert!(!metadata.contains_key(TRUNCATED_KEY));
+    }
+
+    #[tokio::test]
+    async fn test_model_routing_tags_route() {
+        use crate::models::ModelRoute;
+
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let policy = ModelRoutingPolicy::new(
+            ModelRoute::new(Arc::new(PromptEchoLLM)),
+            ModelRoute::new(Arc::new(TextOnlyLLM)),
+        );
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default())
+            .with_model_routing(Arc::new(policy));
+
+        let response = agent
+            .generate_internal(
+                "s".to_string(),
+                "Explain how lifetimes work".to_string(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.content, "ok");
+        assert_eq!(response.metadata.unwrap()[MODEL_ROUTE_KEY], "strong");
+    }
+
+    #[tokio::test]
+    async fn test_generate_options_apply_to_one_call() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default())
+            .with_system_prompt("Be brief.");
+
+        let options = crate::types::GenerateOptions {
+            system_prompt: Some("Answer in French.".to_string()),
+            temperature: Some(0.1),
+            ..Default::default()
         };
+        let response = agent
+            .generate_with_options("s", "Hello", options)
+            .await
+            .unwrap();
+        assert!(response.content.starts_with("Answer in French.\n"));
 
-        let value = orchestrator
-            .call_prompt(user_input)
+        let response = agent
+            .generate_internal("s".to_string(), "Hello again".to_string(), None)
             .await
-            .map_err(|e| AgentError::Other(e.to_string()))?;
+            .unwrap();
+        assert!(response.content.starts_with("Be brief.\n"));
+    }
 
-        if let Some(v) = value {
-            let content = format_codemode_value(&v);
-            let metadata = Some(HashMap::from([(
-                "source".to_string(),
-                "codemode_orchestrator".to_string(),
-            )]));
-            return Ok(Some((content, metadata)));
-        }
+    #[tokio::test]
+    async fn test_routing_report_counts_paths() {
+        let directory = Arc::new(crate::catalog::StaticSubAgentDirectory::new());
+        directory.register(Arc::new(Researcher)).unwrap();
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, AgentOptions::default())
+            .with_subagents(directory);
 
-        Ok(None)
+        agent.generate("s", "hello").await.unwrap();
+        agent.delegate_for("s", "researcher", "rust").await.unwrap();
+        agent.delegate("researcher", "untracked").await.unwrap();
+
+        let report = agent.routing_report("s");
+        assert_eq!(report.requests(), 2);
+        let generation = report.path(RoutePath::Generation).unwrap();
+        assert_eq!(generation.failures, 0);
+        assert!(generation.stages.contains_key("model"));
+        assert_eq!(report.recent[1].target.as_deref(), Some("researcher"));
+
+        agent.clear_routing_report("s");
+        assert_eq!(agent.routing_report("s").requests(), 0);
     }
 
-    /// Stores a memory record
-    async fn store_memory(
-        &self,
-        session_id: &str,
-        role: &str,
-        content: &str,
-        metadata: Option<HashMap<String, String>>,
-    ) -> Result<()> {
-        let record = MemoryRecord {
-            id: Uuid::new_v4(),
-            session_id: session_id.to_string(),
-            role: role.to_string(),
-            content: content.to_string(),
-            importance: 0.5, // Default importance
-            timestamp: Utc::now(),
-            metadata,
-            embedding: None,
+    #[tokio::test]
+    async fn test_session_budget_stops_spending() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let options = AgentOptions {
+            cost_budget: Some(CostBudget::new().with_session_limits(None, Some(0.01))),
+            ..AgentOptions::default()
         };
+        let pricing = PricingRegistry::new().with_pricing(
+            "prompt-echo",
+            crate::models::ModelPricing::new(10_000.0, 0.0),
+        );
+        let agent = Agent::new(Arc::new(PromptEchoLLM), memory, options).with_pricing(pricing);
 
-        self.memory.store(record).await
+        agent.generate("s", "hello there").await.unwrap();
+        let report = agent.cost_report("s");
+        assert_eq!(report.total.generations, 1);
+        assert!(report.cost() >= 0.01);
+        assert!(matches!(
+            agent.generate("s", "again").await,
+            Err(AgentError::BudgetExceeded(_))
+        ));
+
+        // Other sessions keep their own budget
+        agent.generate("t", "hi").await.unwrap();
+        assert_eq!(agent.total_cost_report().total.generations, 2);
     }
 
-    /// Flushes memory to persistent store
-    pub async fn flush(&self, _session_id: &str) -> Result<()> {
-        self.memory.flush().await
+    /// Model that answers in English until told which language to use
+    #[derive(Default)]
+    struct EnglishLLM {
+        calls: AtomicUsize,
     }
 
-    /// Returns the tool catalog
-    pub fn tools(&self) -> Arc<ToolCatalog> {
-        Arc::clone(&self.tool_catalog)
+    #[async_trait]
+    impl LLM for EnglishLLM {
+        async fn generate(
+            &self,
+            messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<GenerationResponse> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            let asked = messages.last().map(|m| m.content.as_str()).unwrap_or("");
+            let content = if asked.contains("Spanish") {
+                "La capital de Francia es París y es famosa por la torre"
+            } else {
+                "The capital of France is Paris and it is famous for the tower"
+            };
+            Ok(GenerationResponse {
+                content: content.to_string(),
+                metadata: None,
+            })
+        }
+
+        fn model_name(&self) -> &str {
+            "english"
+        }
     }
 
-    /// Checkpoints the agent state for persistence
-    pub async fn checkpoint(&self, session_id: &str) -> Result<Vec<u8>> {
-        let recent = self.memory.retrieve_recent(session_id).await?;
+    #[tokio::test]
+    async fn test_language_policy_reprompts_once() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let model = Arc::new(EnglishLLM::default());
+        let agent = Agent::new(model.clone(), memory, AgentOptions::default())
+            .with_language_policy(LanguagePolicy::match_user());
 
-        let state = AgentState {
-            system_prompt: self.system_prompt.clone(),
-            short_term: recent,
-            joined_spaces: None,
-            timestamp: Utc::now(),
+        let question = "¿Cuál es la capital de Francia y por qué es famosa?";
+        let response = agent
+            .generate_with_options("s", question, Default::default())
+            .await
+            .unwrap();
+        assert!(response.content.starts_with("La capital"));
+        assert_eq!(model.calls.load(Ordering::SeqCst), 2);
+
+        // Replies already in the user's language are kept
+        agent
+            .generate("s", "What is the capital of France and why is it famous?")
+            .await
+            .unwrap();
+        assert_eq!(model.calls.load(Ordering::SeqCst), 3);
+    }
+
+    /// Model that never answers
+    struct HungLLM;
+
+    #[async_trait]
+    impl LLM for HungLLM {
+        async fn generate(
+            &self,
+            _messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<GenerationResponse> {
+            std::future::pending().await
+        }
+
+        fn model_name(&self) -> &str {
+            "hung"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_generate_stops_at_deadline_or_cancel() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(Arc::new(HungLLM), memory, AgentOptions::default());
+
+        let options = crate::types::GenerateOptions {
+            deadline: Some(Instant::now() + Duration::from_millis(20)),
+            ..Default::default()
+        };
+        let result = agent.generate_with_options("s", "hello", options).await;
+        assert!(matches!(result, Err(AgentError::DeadlineExceeded(_))));
+
+        let token = crate::cancel::CancellationToken::new();
+        let options = crate::types::GenerateOptions {
+            cancel: Some(token.clone()),
+            ..Default::default()
+        };
+        let cancel = async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            token.cancel();
+        };
+        let (result, _) = tokio::join!(agent.generate_with_options("s", "hello", options), cancel);
+        assert!(matches!(result, Err(AgentError::Cancelled(_))));
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key_replays_the_first_response() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 8));
+        let model = Arc::new(EnglishLLM::default());
+        let agent = Agent::new(model.clone(), memory.clone(), AgentOptions::default());
+        let options = || crate::types::GenerateOptions {
+            idempotency_key: Some("req-1".to_string()),
+            ..Default::default()
         };
 
-        serde_json::to_vec(&state).map_err(|e| AgentError::SerializationError(e))
+        let first = agent
+            .generate_with_options("s", "hello", options())
+            .await
+            .unwrap();
+        let replay = agent
+            .generate_with_options("s", "hello", options())
+            .await
+            .unwrap();
+        assert_eq!(replay.content, first.content);
+        assert_eq!(
+            replay.metadata.unwrap()[crate::idempotency::IDEMPOTENT_REPLAY_KEY],
+            "true"
+        );
+        assert_eq!(model.calls.load(Ordering::SeqCst), 1);
+        assert_eq!(memory.retrieve_recent("s").await.unwrap().len(), 2);
     }
 
-    /// Restores agent state from checkpoint
-    pub async fn restore(&self, _session_id: &str, data: &[u8]) -> Result<()> {
-        let state: AgentState =
-            serde_json::from_slice(data).map_err(|e| AgentError::SerializationError(e))?;
+    /// Model that leaves out a field until told what was wrong
+    #[derive(Default)]
+    struct SloppyJsonLLM {
+        calls: AtomicUsize,
+    }
 
-        // Restore memories
-        for record in state.short_term {
-            self.memory.store(record).await?;
+    #[async_trait]
+    impl LLM for SloppyJsonLLM {
+        async fn generate(
+            &self,
+            messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<GenerationResponse> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            assert!(messages[0].content.contains("JSON schema"));
+            let corrected = messages
+                .last()
+                .is_some_and(|m| m.content.contains("priority"));
+            let content = if corrected {
+                "```json\n{\"title\": \"Login fails\", \"priority\": 2}\n```"
+            } else {
+                "Here it is: {\"title\": \"Login fails\"}"
+            };
+            Ok(GenerationResponse {
+                content: content.to_string(),
+                metadata: None,
+            })
         }
 
-        Ok(())
+        fn model_name(&self) -> &str {
+            "sloppy-json"
+        }
+    }
+
+    #[derive(Debug, serde::Deserialize, JsonSchema)]
+    struct Ticket {
+        title: String,
+        priority: u8,
+    }
+
+    #[tokio::test]
+    async fn test_generate_typed_retries_with_the_error() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let model = Arc::new(SloppyJsonLLM::default());
+        let agent = Agent::new(model.clone(), memory.clone(), AgentOptions::default());
+
+        let ticket: Ticket = agent.generate_typed("s", "File a ticket").await.unwrap();
+        assert_eq!(ticket.title, "Login fails");
+        assert_eq!(ticket.priority, 2);
+        assert_eq!(model.calls.load(Ordering::SeqCst), 2);
+
+        let strict =
+            Agent::new(model.clone(), memory, AgentOptions::default()).with_structured_retries(0);
+        assert!(matches!(
+            strict.generate_typed::<Ticket>("t", "File a ticket").await,
+            Err(AgentError::SchemaViolation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_generate_batch_keeps_request_order() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 8));
+        let agent = Agent::new(
+            Arc::new(PromptEchoLLM),
+            memory.clone(),
+            AgentOptions::default(),
+        )
+        .with_system_prompt("")
+        .with_retrieval(Arc::new(OrderEmbedder), 1)
+        .with_batch_concurrency(2);
+
+        let requests = vec![
+            ("a".to_string(), "first in a".to_string()),
+            ("b".to_string(), "only in b".to_string()),
+            ("a".to_string(), "second in a".to_string()),
+        ];
+        let results = agent.generate_batch(requests).await;
+
+        let contents: Vec<String> = results.into_iter().map(|r| r.unwrap().content).collect();
+        assert!(contents[0].ends_with("first in a"));
+        assert!(contents[1].ends_with("only in b"));
+        assert!(contents[2].ends_with("second in a"));
+
+        let history = memory.retrieve_recent("a").await.unwrap();
+        let turns: Vec<&str> = history.iter().map(|r| r.content.as_str()).collect();
+        assert_eq!(turns.len(), 4);
+        assert_eq!(turns[0], "first in a");
+        assert_eq!(turns[2], "second in a");
+        assert!(history[0].embedding.is_some());
     }
 }
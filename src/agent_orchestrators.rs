@@ -40,6 +40,7 @@ impl CodeModeTool {
             description: schema.description,
             input_schema,
             examples: None,
+            output_schema: None,
         }
     }
 }
@@ -57,7 +58,12 @@ impl Tool for CodeModeTool {
             .and_then(|v| v.as_str())
             .ok_or_else(|| AgentError::ToolError("codemode.run_code requires `code`".into()))?;
 
-        let timeout = req.arguments.get("timeout").and_then(|v| v.as_u64());
+        let mut timeout = req.arguments.get("timeout").and_then(|v| v.as_u64());
+        // Scripts can't be interrupted, so they must end by the turn's deadline
+        if let Some(remaining) = crate::cancel::remaining() {
+            let remaining = remaining.as_millis().max(1) as u64;
+            timeout = Some(timeout.map_or(remaining, |timeout| timeout.min(remaining)));
+        }
 
         let result = self
             .engine
@@ -0,0 +1,267 @@
+//! Combining the outputs of several sub-agents.
+//!
+//! Orchestrators often fan a request out to several sub-agents and then
+//! need one answer. [`SubAgentOutput`] holds what one sub-agent returned,
+//! parsed as JSON when it replied with JSON, and the combinators here turn a
+//! set of them into a result: [`join_results`] needs every one to succeed,
+//! [`first_success`] takes the first that did, and [`weighted_merge`]
+//! blends structured replies field by field. Outputs can be checked against
+//! a JSON schema first with [`SubAgentOutput::validate`], or merged with
+//! [`weighted_merge_with_schema`].
+
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Number, Value};
+
+use crate::error::{AgentError, Result};
+use crate::schema::validate_schema;
+
+/// What one sub-agent returned
+#[derive(Debug)]
+pub struct SubAgentOutput<T = Value> {
+    /// Name of the sub-agent
+    pub name: String,
+    pub result: Result<T>,
+}
+
+impl<T> SubAgentOutput<T> {
+    pub fn new(name: impl Into<String>, result: Result<T>) -> Self {
+        Self {
+            name: name.into(),
+            result,
+        }
+    }
+}
+
+impl SubAgentOutput<Value> {
+    /// Wraps a sub-agent's text reply, parsed as JSON if it is JSON.
+    ///
+    /// A reply inside a ```` ```json ```` fence is unwrapped first; any other
+    /// text becomes a JSON string.
+    pub fn from_reply(name: impl Into<String>, reply: Result<String>) -> Self {
+        Self::new(name, reply.map(|reply| parse_reply(&reply)))
+    }
+
+    /// Deserializes the output into `T`; a mismatch becomes the output's error
+    pub fn parse<T: DeserializeOwned>(self) -> SubAgentOutput<T> {
+        let result = self
+            .result
+            .and_then(|value| serde_json::from_value(value).map_err(AgentError::from));
+        SubAgentOutput::new(self.name, result)
+    }
+
+    /// Checks the output against a JSON `schema`; a violation becomes the
+    /// output's error
+    pub fn validate(self, schema: &Value) -> Self {
+        let result = self
+            .result
+            .and_then(|value| validate_schema(&value, schema).map(|_| value));
+        Self::new(self.name, result)
+    }
+}
+
+fn parse_reply(reply: &str) -> Value {
+    let trimmed = reply.trim();
+    let unfenced = trimmed
+        .strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+        .map(|body| body.split_once('\n').map_or("", |(_, body)| body))
+        .unwrap_or(trimmed);
+    serde_json::from_str(unfenced).unwrap_or_else(|_| Value::String(reply.to_string()))
+}
+
+/// Returns every output by sub-agent name, or the first failure in output
+/// order
+pub fn join_results<T>(
+    outputs: impl IntoIterator<Item = SubAgentOutput<T>>,
+) -> Result<Vec<(String, T)>> {
+    outputs
+        .into_iter()
+        .map(|output| match output.result {
+            Ok(value) => Ok((output.name, value)),
+            Err(e) => {
+                tracing::warn!(subagent = %output.name, "Sub-agent failed: {}", e);
+                Err(e)
+            }
+        })
+        .collect()
+}
+
+/// Returns the first successful output with its sub-agent's name.
+///
+/// Fails with every sub-agent's error if none succeeded.
+pub fn first_success<T>(
+    outputs: impl IntoIterator<Item = SubAgentOutput<T>>,
+) -> Result<(String, T)> {
+    let mut failures = Vec::new();
+    for output in outputs {
+        match output.result {
+            Ok(value) => return Ok((output.name, value)),
+            Err(e) => failures.push(format!("{}: {}", output.name, e)),
+        }
+    }
+    Err(AgentError::Other(if failures.is_empty() {
+        "No sub-agent outputs".to_string()
+    } else {
+        format!("No sub-agent succeeded ({})", failures.join("; "))
+    }))
+}
+
+/// Blends successful outputs into one value, each counting by its weight.
+///
+/// Numbers are averaged by weight, objects are merged key by key, and any
+/// other value, arrays included, goes to the weighted vote: the value with
+/// the most weight behind it wins, ties going to the earliest. Failed
+/// outputs and those with a weight of zero or less are left out.
+pub fn weighted_merge(outputs: impl IntoIterator<Item = (SubAgentOutput, f64)>) -> Result<Value> {
+    let values: Vec<(Value, f64)> = outputs
+        .into_iter()
+        .filter(|(_, weight)| *weight > 0.0)
+        .filter_map(|(output, weight)| match output.result {
+            Ok(value) => Some((value, weight)),
+            Err(e) => {
+                tracing::warn!(subagent = %output.name, "Skipping failed sub-agent: {}", e);
+                None
+            }
+        })
+        .collect();
+    if values.is_empty() {
+        return Err(AgentError::Other(
+            "No sub-agent outputs to merge".to_string(),
+        ));
+    }
+    let weighted: Vec<(&Value, f64)> = values
+        .iter()
+        .map(|(value, weight)| (value, *weight))
+        .collect();
+    Ok(merge_values(&weighted))
+}
+
+/// Like [`weighted_merge`], but only merges outputs that match `schema`
+/// and checks the merged value against it too
+pub fn weighted_merge_with_schema(
+    outputs: impl IntoIterator<Item = (SubAgentOutput, f64)>,
+    schema: &Value,
+) -> Result<Value> {
+    let validated = outputs
+        .into_iter()
+        .map(|(output, weight)| (output.validate(schema), weight));
+    let merged = weighted_merge(validated)?;
+    validate_schema(&merged, schema)?;
+    Ok(merged)
+}
+
+fn merge_values(values: &[(&Value, f64)]) -> Value {
+    if values.iter().all(|(value, _)| value.is_number()) {
+        let total: f64 = values.iter().map(|(_, weight)| weight).sum();
+        let sum: f64 = values
+            .iter()
+            .map(|(value, weight)| value.as_f64().unwrap_or_default() * weight)
+            .sum();
+        if let Some(mean) = Number::from_f64(sum / total) {
+            return Value::Number(mean);
+        }
+    }
+
+    if values.iter().all(|(value, _)| value.is_object()) {
+        let mut keys: Vec<&String> = Vec::new();
+        for (value, _) in values {
+            for key in value.as_object().into_iter().flat_map(Map::keys) {
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+        }
+        let merged = keys
+            .into_iter()
+            .map(|key| {
+                let fields: Vec<(&Value, f64)> = values
+                    .iter()
+                    .filter_map(|(value, weight)| value.get(key).map(|field| (field, *weight)))
+                    .collect();
+                (key.clone(), merge_values(&fields))
+            })
+            .collect();
+        return Value::Object(merged);
+    }
+
+    let mut votes: Vec<(&Value, f64)> = Vec::new();
+    for (value, weight) in values {
+        match votes.iter_mut().find(|(candidate, _)| candidate == value) {
+            Some((_, total)) => *total += weight,
+            None => votes.push((*value, *weight)),
+        }
+    }
+    let mut winner = votes[0];
+    for vote in votes.into_iter().skip(1) {
+        if vote.1 > winner.1 {
+            winner = vote;
+        }
+    }
+    winner.0.clone()
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn failed(name: &str) -> SubAgentOutput {
+        SubAgentOutput::new(name, Err(AgentError::ModelError("down".to_string())))
+    }
+
+    #[test]
+    fn test_join_and_first_success() {
+        let reply = SubAgentOutput::from_reply("a", Ok("```json\n{\"n\": 1}\n```".to_string()));
+        assert_eq!(reply.result.as_ref().unwrap(), &json!({"n": 1}));
+
+        let outputs = vec![failed("a"), SubAgentOutput::new("b", Ok(json!(2)))];
+        assert!(join_results(outputs).is_err());
+
+        let outputs = vec![failed("a"), SubAgentOutput::new("b", Ok(json!(2)))];
+        assert_eq!(first_success(outputs).unwrap(), ("b".to_string(), json!(2)));
+        assert!(first_success(vec![failed("a")]).is_err());
+
+        #[derive(serde::Deserialize)]
+        struct Count {
+            n: u32,
+        }
+        let typed = SubAgentOutput::new("a", Ok(json!({"n": 3}))).parse::<Count>();
+        let joined = join_results(vec![typed]).unwrap();
+        assert_eq!(joined[0].1.n, 3);
+    }
+
+    #[test]
+    fn test_weighted_merge_validates_against_schema() {
+        let schema = json!({
+            "type": "object",
+            "required": ["score", "label"],
+            "properties": {
+                "score": {"type": "number"},
+                "label": {"type": "string", "enum": ["spam", "ham"]}
+            }
+        });
+        let outputs = vec![
+            (
+                SubAgentOutput::new("a", Ok(json!({"score": 0.9, "label": "spam"}))),
+                1.0,
+            ),
+            (
+                SubAgentOutput::new("b", Ok(json!({"score": 0.3, "label": "ham"}))),
+                3.0,
+            ),
+            (
+                SubAgentOutput::new("c", Ok(json!({"score": 1.0, "label": "eggs"}))),
+                10.0,
+            ),
+            (failed("d"), 5.0),
+        ];
+        let merged = weighted_merge_with_schema(outputs, &schema).unwrap();
+        assert_eq!(merged["label"], "ham");
+        assert!((merged["score"].as_f64().unwrap() - 0.45).abs() < 1e-9);
+
+        let invalid = json!({"score": "high"});
+        assert!(matches!(
+            validate_schema(&invalid, &schema),
+            Err(AgentError::SchemaViolation(_))
+        ));
+    }
+}
@@ -0,0 +1,403 @@
+//! Content-addressable storage for large tool outputs.
+//!
+//! Tool responses above a size threshold are written to a [`BlobStore`] keyed
+//! by the SHA-256 of their content. Memory keeps only a short preview plus the
+//! blob reference, and the agent can read the full output back on demand
+//! through the built-in [`ExpandBlobTool`].
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::error::{AgentError, Result};
+use crate::tools::Tool;
+use crate::types::{ToolRequest, ToolResponse, ToolSpec};
+
+/// Metadata key holding the blob reference of an offloaded tool output
+pub const BLOB_REF_KEY: &str = "blob_ref";
+/// Metadata key holding the full size in bytes of an offloaded tool output
+pub const BLOB_SIZE_KEY: &str = "blob_size";
+/// Name under which [`ExpandBlobTool`] is registered
+pub const EXPAND_BLOB_TOOL: &str = "blob.expand";
+
+/// Outputs larger than this many bytes are offloaded by default
+pub const DEFAULT_BLOB_THRESHOLD: usize = 4096;
+/// Bytes of an offloaded output kept inline by default
+pub const DEFAULT_PREVIEW_LEN: usize = 512;
+
+/// Storage for immutable blobs addressed by content hash
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    /// Stores `data` and returns its hash; storing identical data again is a no-op
+    async fn put(&self, data: &[u8]) -> Result<String>;
+
+    /// Returns the blob with the given hash, if present
+    async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Removes a blob, returning whether it existed
+    async fn delete(&self, hash: &str) -> Result<bool>;
+}
+
+/// Returns the hex-encoded SHA-256 of `data`
+pub fn content_hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// Rejects anything that isn't a hash produced by [`content_hash`]
+fn validate_hash(hash: &str) -> Result<()> {
+    if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Ok(())
+    } else {
+        Err(AgentError::MemoryError(format!(
+            "Invalid blob reference: {}",
+            hash
+        )))
+    }
+}
+
+/// Process-local blob store
+#[derive(Default)]
+pub struct InMemoryBlobStore {
+    blobs: parking_lot::RwLock<HashMap<String, Arc<Vec<u8>>>>,
+}
+
+impl InMemoryBlobStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl BlobStore for InMemoryBlobStore {
+    async fn put(&self, data: &[u8]) -> Result<String> {
+        let hash = content_hash(data);
+        self.blobs
+            .write()
+            .entry(hash.clone())
+            .or_insert_with(|| Arc::new(data.to_vec()));
+        Ok(hash)
+    }
+
+    async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.blobs.read().get(hash).map(|data| data.to_vec()))
+    }
+
+    async fn delete(&self, hash: &str) -> Result<bool> {
+        Ok(self.blobs.write().remove(hash).is_some())
+    }
+}
+
+/// Blob store keeping one file per blob under a root directory.
+///
+/// Files are sharded by the first two hex digits of their hash and written
+/// through a temporary file, so readers never observe a partial blob.
+pub struct FileBlobStore {
+    root: PathBuf,
+}
+
+impl FileBlobStore {
+    /// Creates a store rooted at `root`, creating the directory if needed
+    pub async fn new(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        tokio::fs::create_dir_all(&root).await?;
+        Ok(Self { root })
+    }
+
+    fn path(&self, hash: &str) -> PathBuf {
+        self.root.join(&hash[..2]).join(hash)
+    }
+}
+
+#[async_trait]
+impl BlobStore for FileBlobStore {
+    async fn put(&self, data: &[u8]) -> Result<String> {
+        let hash = content_hash(data);
+        let path = self.path(&hash);
+        if tokio::fs::try_exists(&path).await? {
+            return Ok(hash);
+        }
+
+        let dir = self.root.join(&hash[..2]);
+        tokio::fs::create_dir_all(&dir).await?;
+        let tmp = dir.join(format!("{}.{}.tmp", hash, uuid::Uuid::new_v4()));
+        tokio::fs::write(&tmp, data).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(hash)
+    }
+
+    async fn get(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        validate_hash(hash)?;
+        match tokio::fs::read(self.path(hash)).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, hash: &str) -> Result<bool> {
+        validate_hash(hash)?;
+        match tokio::fs::remove_file(self.path(hash)).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Moves oversized tool outputs into a [`BlobStore`]
+#[derive(Clone)]
+pub struct BlobOffload {
+    store: Arc<dyn BlobStore>,
+    threshold: usize,
+    preview_len: usize,
+}
+
+impl BlobOffload {
+    /// Offloads outputs above [`DEFAULT_BLOB_THRESHOLD`] bytes into `store`
+    pub fn new(store: Arc<dyn BlobStore>) -> Self {
+        Self {
+            store,
+            threshold: DEFAULT_BLOB_THRESHOLD,
+            preview_len: DEFAULT_PREVIEW_LEN,
+        }
+    }
+
+    /// Sets the size in bytes above which outputs are offloaded
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Sets how many bytes of an offloaded output stay inline
+    pub fn with_preview_len(mut self, preview_len: usize) -> Self {
+        self.preview_len = preview_len;
+        self
+    }
+
+    /// Returns the backing store
+    pub fn store(&self) -> Arc<dyn BlobStore> {
+        Arc::clone(&self.store)
+    }
+
+    /// Replaces an oversized response's content with a preview and blob reference.
+    ///
+    /// Responses at or below the threshold are returned unchanged.
+    pub async fn offload(&self, response: ToolResponse) -> Result<ToolResponse> {
+        self.offload_with(response, futures::future::ok).await
+    }
+
+    /// Offloads like [`BlobOffload::offload`], passing what stays inline
+    /// through `reduce`: the preview of an offloaded response, or the whole
+    /// of one below the threshold.
+    ///
+    /// The blob keeps the full output, so shrinking the inline part, e.g.
+    /// with a `ToolOutputProcessor`, loses nothing the agent can't expand.
+    pub async fn offload_with<F, Fut>(
+        &self,
+        response: ToolResponse,
+        reduce: F,
+    ) -> Result<ToolResponse>
+    where
+        F: FnOnce(ToolResponse) -> Fut,
+        Fut: Future<Output = Result<ToolResponse>>,
+    {
+        let size = response.content.len();
+        if size <= self.threshold {
+            return reduce(response).await;
+        }
+
+        let hash = self.store.put(response.content.as_bytes()).await?;
+
+        let mut end = self.preview_len.min(size);
+        while !response.content.is_char_boundary(end) {
+            end -= 1;
+        }
+        let mut response = reduce(ToolResponse {
+            content: response.content[..end].to_string(),
+            metadata: response.metadata,
+        })
+        .await?;
+        response.content = format!(
+            "{}\n[truncated {} of {} bytes; call {} with ref \"{}\" for the full output]",
+            response.content,
+            size - end,
+            size,
+            EXPAND_BLOB_TOOL,
+            hash
+        );
+
+        let metadata = response.metadata.get_or_insert_with(HashMap::new);
+        metadata.insert(BLOB_REF_KEY.to_string(), hash);
+        metadata.insert(BLOB_SIZE_KEY.to_string(), size.to_string());
+        Ok(response)
+    }
+}
+
+/// Built-in tool that reads an offloaded output back from the blob store
+pub struct ExpandBlobTool {
+    store: Arc<dyn BlobStore>,
+}
+
+impl ExpandBlobTool {
+    pub fn new(store: Arc<dyn BlobStore>) -> Self {
+        Self { store }
+    }
+}
+
+#[async_trait]
+impl Tool for ExpandBlobTool {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: EXPAND_BLOB_TOOL.to_string(),
+            description: "Returns the full content of a truncated tool output".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "ref": {
+                        "type": "string",
+                        "description": "Blob reference from the truncated output"
+                    },
+                    "offset": {
+                        "type": "integer",
+                        "description": "Byte offset to start reading from"
+                    },
+                    "length": {
+                        "type": "integer",
+                        "description": "Maximum number of bytes to return"
+                    }
+                },
+                "required": ["ref"]
+            }),
+            examples: None,
+            output_schema: None,
+        }
+    }
+
+    async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse> {
+        let hash = req
+            .arguments
+            .get("ref")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| AgentError::ToolError("missing 'ref'".to_string()))?;
+        validate_hash(hash).map_err(|e| AgentError::ToolError(e.to_string()))?;
+
+        let data =
+            self.store.get(hash).await?.ok_or_else(|| {
+                AgentError::ToolError(format!("Unknown blob reference: {}", hash))
+            })?;
+
+        let arg = |key: &str| {
+            req.arguments
+                .get(key)
+                .and_then(|v| v.as_u64())
+                .map(|v| v as usize)
+        };
+        let start = arg("offset").unwrap_or(0).min(data.len());
+        let end = arg("length").map_or(data.len(), |len| (start + len).min(data.len()));
+
+        Ok(ToolResponse {
+            content: String::from_utf8_lossy(&data[start..end]).into_owned(),
+            metadata: Some(HashMap::from([
+                (BLOB_REF_KEY.to_string(), hash.to_string()),
+                (BLOB_SIZE_KEY.to_string(), data.len().to_string()),
+            ])),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_offload_and_expand_round_trip() {
+        let store: Arc<dyn BlobStore> = Arc::new(InMemoryBlobStore::new());
+        let offload = BlobOffload::new(store.clone())
+            .with_threshold(16)
+            .with_preview_len(4);
+
+        let small = offload
+            .offload(ToolResponse {
+                content: "short".to_string(),
+                metadata: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(small.content, "short");
+
+        let full = "x".repeat(100);
+        let large = offload
+            .offload(ToolResponse {
+                content: full.clone(),
+                metadata: None,
+            })
+            .await
+            .unwrap();
+        assert!(large
+            .content
+            .starts_with("xxxx\n[truncated 96 of 100 bytes"));
+        let hash = large.metadata.unwrap()[BLOB_REF_KEY].clone();
+        assert_eq!(hash, content_hash(full.as_bytes()));
+
+        let expanded = ExpandBlobTool::new(store)
+            .invoke(ToolRequest {
+                session_id: "test".to_string(),
+                arguments: HashMap::from([("ref".to_string(), json!(hash))]),
+                tenant_id: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(expanded.content, full);
+    }
+
+    #[tokio::test]
+    async fn test_offload_reduces_only_the_preview() {
+        let store: Arc<dyn BlobStore> = Arc::new(InMemoryBlobStore::new());
+        let offload = BlobOffload::new(store.clone())
+            .with_threshold(16)
+            .with_preview_len(8);
+        let shorten = |mut response: ToolResponse| async move {
+            response.content.truncate(2);
+            Ok(response)
+        };
+
+        let full = "abcdefghij".repeat(10);
+        let large = offload
+            .offload_with(
+                ToolResponse {
+                    content: full.clone(),
+                    metadata: None,
+                },
+                shorten,
+            )
+            .await
+            .unwrap();
+        assert!(large.content.starts_with("ab\n[truncated 92 of 100 bytes"));
+        let hash = &large.metadata.unwrap()[BLOB_REF_KEY];
+        assert_eq!(store.get(hash).await.unwrap().unwrap(), full.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_file_store_is_content_addressed() {
+        let root = std::env::temp_dir().join(format!("rs-agent-blobs-{}", uuid::Uuid::new_v4()));
+        let store = FileBlobStore::new(&root).await.unwrap();
+
+        let hash = store.put(b"payload").await.unwrap();
+        assert_eq!(store.put(b"payload").await.unwrap(), hash);
+        assert_eq!(store.get(&hash).await.unwrap().unwrap(), b"payload");
+        assert!(store.get("../../etc/passwd").await.is_err());
+
+        assert!(store.delete(&hash).await.unwrap());
+        assert!(store.get(&hash).await.unwrap().is_none());
+
+        tokio::fs::remove_dir_all(root).await.unwrap();
+    }
+}
@@ -0,0 +1,118 @@
+//! Cancellation and deadlines for a turn.
+//!
+//! `GenerateOptions::cancel` and `GenerateOptions::deadline` bound how long
+//! `Agent::generate_with_options` may run. When the token is cancelled or
+//! the deadline passes, the turn's future is dropped, which aborts in-flight
+//! provider HTTP requests and tool invocations, and the call fails with
+//! [`AgentError::Cancelled`] or [`AgentError::DeadlineExceeded`]. Work that
+//! can't be interrupted by dropping it, such as CodeMode scripts, reads the
+//! time left with [`remaining`] and caps its own timeout.
+
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+pub use tokio_util::sync::CancellationToken;
+
+use crate::error::{AgentError, Result};
+
+tokio::task_local! {
+    static CURRENT: TurnLimit;
+}
+
+/// When a turn must stop: once its token is cancelled, at its deadline, or
+/// whichever comes first
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TurnLimit {
+    cancel: Option<CancellationToken>,
+    deadline: Option<Instant>,
+}
+
+impl TurnLimit {
+    pub(crate) fn new(cancel: Option<CancellationToken>, deadline: Option<Instant>) -> Self {
+        Self { cancel, deadline }
+    }
+
+    /// Returns how long until the deadline, if there is one
+    pub(crate) fn remaining(&self) -> Option<Duration> {
+        self.deadline
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Runs `future` until it completes, the token is cancelled or the
+    /// deadline passes, whichever comes first.
+    ///
+    /// Tools running inside `future` see this limit through [`remaining`]
+    /// and [`current_token`].
+    pub(crate) async fn run<T>(&self, future: impl Future<Output = Result<T>>) -> Result<T> {
+        if self.cancel.is_none() && self.deadline.is_none() {
+            return future.await;
+        }
+
+        let cancelled = async {
+            match &self.cancel {
+                Some(token) => token.cancelled().await,
+                None => std::future::pending().await,
+            }
+        };
+        let expired = async {
+            match self.deadline {
+                Some(deadline) => {
+                    tokio::time::sleep_until(tokio::time::Instant::from_std(deadline)).await
+                }
+                None => std::future::pending().await,
+            }
+        };
+        tokio::select! {
+            biased;
+            _ = cancelled => Err(AgentError::Cancelled("generation was cancelled".into())),
+            _ = expired => Err(AgentError::DeadlineExceeded(
+                "generation ran past its deadline".into(),
+            )),
+            result = CURRENT.scope(self.clone(), future) => result,
+        }
+    }
+}
+
+/// Returns how long the current turn has left, if it has a deadline.
+///
+/// Tools can use this to bound work that dropping the turn wouldn't stop;
+/// outside a turn, or in a turn without a deadline, it returns `None`.
+pub fn remaining() -> Option<Duration> {
+    CURRENT.try_with(TurnLimit::remaining).ok().flatten()
+}
+
+/// Returns the cancellation token of the current turn, if it has one
+pub fn current_token() -> Option<CancellationToken> {
+    CURRENT
+        .try_with(|limit| limit.cancel.clone())
+        .ok()
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_turns_stop_on_cancel_or_deadline() {
+        let token = CancellationToken::new();
+        let limit = TurnLimit::new(Some(token.clone()), None);
+        token.cancel();
+        let result = limit.run(std::future::pending::<Result<()>>()).await;
+        assert!(matches!(result, Err(AgentError::Cancelled(_))));
+
+        let deadline = Instant::now() + Duration::from_millis(20);
+        let limit = TurnLimit::new(None, Some(deadline));
+        let result = limit.run(std::future::pending::<Result<()>>()).await;
+        assert!(matches!(result, Err(AgentError::DeadlineExceeded(_))));
+
+        // Work inside the turn sees the time it has left
+        let limit = TurnLimit::new(None, Some(Instant::now() + Duration::from_secs(60)));
+        let left = limit.run(async { Ok(remaining()) }).await.unwrap().unwrap();
+        assert!(left > Duration::from_secs(30));
+        assert!(remaining().is_none());
+
+        let unbounded = TurnLimit::default();
+        assert_eq!(unbounded.run(async { Ok(1) }).await.unwrap(), 1);
+    }
+}
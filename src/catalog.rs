@@ -2,32 +2,113 @@
 //!
 //! This module provides the default in-memory catalog implementations for both
 //! tools and sub-agents, matching the structure from go-agent's catalog.go.
+//! Locks are parking_lot locks, which never poison, so a tool that panics
+//! while the catalog is in use cannot take the catalog down with it.
 
 use std::collections::HashMap;
-use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
 
 use crate::error::{AgentError, Result};
-use crate::tools::Tool;
+use crate::tools::{next_generation, Tool, ToolRegistry};
 use crate::types::{SubAgent, SubAgentDirectory, ToolSpec};
 
+/// Lock contention counters of a catalog, as returned by `lock_metrics`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LockMetrics {
+    /// Number of lock acquisitions
+    pub acquisitions: u64,
+    /// Acquisitions that had to wait for another holder
+    pub contended: u64,
+    /// Total time spent waiting in contended acquisitions
+    pub wait: Duration,
+}
+
+/// Counts acquisitions of a catalog's locks
+#[derive(Default)]
+struct LockCounters {
+    acquisitions: AtomicU64,
+    contended: AtomicU64,
+    wait_nanos: AtomicU64,
+}
+
+impl LockCounters {
+    fn read<'a, T>(&self, lock: &'a RwLock<T>) -> RwLockReadGuard<'a, T> {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if let Some(guard) = lock.try_read() {
+            return guard;
+        }
+        let started = self.start_wait();
+        let guard = lock.read();
+        self.record_wait(started);
+        guard
+    }
+
+    fn write<'a, T>(&self, lock: &'a RwLock<T>) -> RwLockWriteGuard<'a, T> {
+        self.acquisitions.fetch_add(1, Ordering::Relaxed);
+        if let Some(guard) = lock.try_write() {
+            return guard;
+        }
+        let started = self.start_wait();
+        let guard = lock.write();
+        self.record_wait(started);
+        guard
+    }
+
+    /// Counts the contention up front, so it is visible while still waiting
+    fn start_wait(&self) -> Instant {
+        self.contended.fetch_add(1, Ordering::Relaxed);
+        Instant::now()
+    }
+
+    fn record_wait(&self, started: Instant) {
+        let waited = u64::try_from(started.elapsed().as_nanos()).unwrap_or(u64::MAX);
+        self.wait_nanos.fetch_add(waited, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> LockMetrics {
+        LockMetrics {
+            acquisitions: self.acquisitions.load(Ordering::Relaxed),
+            contended: self.contended.load(Ordering::Relaxed),
+            wait: Duration::from_nanos(self.wait_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// Registered tools with their specs keyed by name, plus their registration
+/// order. Kept behind one lock so readers and writers can't deadlock on the
+/// order they take several in.
+#[derive(Default)]
+struct ToolEntries {
+    tools: HashMap<String, (Arc<dyn Tool>, ToolSpec)>,
+    order: Vec<String>,
+    generation: u64,
+}
+
 /// StaticToolCatalog is the default in-memory implementation of a tool registry.
 /// It maintains tools in registration order and provides thread-safe lookup.
 pub struct StaticToolCatalog {
-    tools: RwLock<HashMap<String, Arc<dyn Tool>>>,
-    specs: RwLock<HashMap<String, ToolSpec>>,
-    order: RwLock<Vec<String>>,
+    entries: RwLock<ToolEntries>,
+    locks: LockCounters,
 }
 
 impl StaticToolCatalog {
     /// Creates a new empty catalog
     pub fn new() -> Self {
         Self {
-            tools: RwLock::new(HashMap::new()),
-            specs: RwLock::new(HashMap::new()),
-            order: RwLock::new(Vec::new()),
+            entries: RwLock::new(ToolEntries::default()),
+            locks: LockCounters::default(),
         }
     }
 
+    /// Returns how often the catalog's lock was taken and waited on
+    pub fn lock_metrics(&self) -> LockMetrics {
+        self.locks.snapshot()
+    }
+
     /// Register a tool in the catalog using a lower-cased key.
     /// Duplicate names return an error.
     pub fn register(&self, tool: Arc<dyn Tool>) -> Result<()> {
@@ -38,59 +119,126 @@ impl StaticToolCatalog {
             return Err(AgentError::ToolError("tool name is empty".into()));
         }
 
-        let mut tools = self.tools.write().unwrap();
-        let mut specs = self.specs.write().unwrap();
-        let mut order = self.order.write().unwrap();
+        let mut entries = self.locks.write(&self.entries);
 
-        if tools.contains_key(&key) {
+        if entries.tools.contains_key(&key) {
             return Err(AgentError::ToolError(format!(
                 "tool {} already registered",
                 spec.name
             )));
         }
 
-        tools.insert(key.clone(), tool);
-        specs.insert(key.clone(), spec);
-        order.push(key);
+        entries.tools.insert(key.clone(), (tool, spec));
+        entries.order.push(key);
+        entries.generation = next_generation();
 
         Ok(())
     }
 
-    /// Lookup a tool and its specification by name
-    pub fn lookup(&self, name: &str) -> Option<(Arc<dyn Tool>, ToolSpec)> {
+    /// Removes a tool, returning whether it was registered
+    pub fn unregister(&self, name: &str) -> bool {
         let key = name.to_lowercase().trim().to_string();
 
-        let tools = self.tools.read().unwrap();
-        let specs = self.specs.read().unwrap();
+        let mut entries = self.locks.write(&self.entries);
 
-        if let Some(tool) = tools.get(&key) {
-            if let Some(spec) = specs.get(&key) {
-                return Some((Arc::clone(tool), spec.clone()));
+        if entries.tools.remove(&key).is_none() {
+            return false;
+        }
+        entries.order.retain(|registered| *registered != key);
+        entries.generation = next_generation();
+        true
+    }
+
+    /// Swaps in `tool` for the one registered under its name, keeping its
+    /// position; fails with `AgentError::ToolNotFound` if there is none
+    pub fn replace(&self, tool: Arc<dyn Tool>) -> Result<()> {
+        let spec = tool.spec();
+        let key = spec.name.to_lowercase().trim().to_string();
+
+        let mut entries = self.locks.write(&self.entries);
+
+        match entries.tools.get_mut(&key) {
+            Some(entry) => {
+                *entry = (tool, spec);
+                entries.generation = next_generation();
+                Ok(())
             }
+            None => Err(AgentError::ToolNotFound(spec.name)),
         }
+    }
+
+    /// Registers every tool of `other`, in its order, returning how many.
+    /// If any name is empty or already registered, nothing is merged.
+    pub fn merge(&self, other: &dyn ToolRegistry) -> Result<usize> {
+        // Taken before locking, so merging a catalog into itself can't deadlock
+        let incoming: Vec<(String, ToolSpec, Arc<dyn Tool>)> = other
+            .tools()
+            .into_iter()
+            .map(|tool| {
+                let spec = tool.spec();
+                (spec.name.to_lowercase().trim().to_string(), spec, tool)
+            })
+            .collect();
 
-        None
+        let mut entries = self.locks.write(&self.entries);
+
+        for (index, (key, spec, _)) in incoming.iter().enumerate() {
+            if key.is_empty() {
+                return Err(AgentError::ToolError("tool name is empty".into()));
+            }
+            let repeated = incoming[..index]
+                .iter()
+                .any(|(earlier, _, _)| earlier == key);
+            if entries.tools.contains_key(key) || repeated {
+                return Err(AgentError::ToolError(format!(
+                    "tool {} already registered",
+                    spec.name
+                )));
+            }
+        }
+
+        let count = incoming.len();
+        for (key, spec, tool) in incoming {
+            entries.tools.insert(key.clone(), (tool, spec));
+            entries.order.push(key);
+        }
+        if count > 0 {
+            entries.generation = next_generation();
+        }
+
+        Ok(count)
+    }
+
+    /// Lookup a tool and its specification by name
+    pub fn lookup(&self, name: &str) -> Option<(Arc<dyn Tool>, ToolSpec)> {
+        let key = name.to_lowercase().trim().to_string();
+
+        let entries = self.locks.read(&self.entries);
+        entries
+            .tools
+            .get(&key)
+            .map(|(tool, spec)| (Arc::clone(tool), spec.clone()))
     }
 
     /// Returns a snapshot of all tool specifications in registration order
     pub fn specs(&self) -> Vec<ToolSpec> {
-        let order = self.order.read().unwrap();
-        let specs = self.specs.read().unwrap();
+        let entries = self.locks.read(&self.entries);
 
-        order
+        entries
+            .order
             .iter()
-            .filter_map(|key| specs.get(key).cloned())
+            .filter_map(|key| entries.tools.get(key).map(|(_, spec)| spec.clone()))
             .collect()
     }
 
     /// Returns all registered tools in order
     pub fn tools(&self) -> Vec<Arc<dyn Tool>> {
-        let order = self.order.read().unwrap();
-        let tools = self.tools.read().unwrap();
+        let entries = self.locks.read(&self.entries);
 
-        order
+        entries
+            .order
             .iter()
-            .filter_map(|key| tools.get(key).map(Arc::clone))
+            .filter_map(|key| entries.tools.get(key).map(|(tool, _)| Arc::clone(tool)))
             .collect()
     }
 }
@@ -101,21 +249,71 @@ impl Default for StaticToolCatalog {
     }
 }
 
+impl ToolRegistry for StaticToolCatalog {
+    fn register(&self, tool: Box<dyn Tool>) -> Result<()> {
+        StaticToolCatalog::register(self, Arc::from(tool))
+    }
+
+    fn unregister(&self, name: &str) -> bool {
+        StaticToolCatalog::unregister(self, name)
+    }
+
+    fn replace(&self, tool: Box<dyn Tool>) -> Result<()> {
+        StaticToolCatalog::replace(self, Arc::from(tool))
+    }
+
+    fn merge(&self, other: &dyn ToolRegistry) -> Result<usize> {
+        StaticToolCatalog::merge(self, other)
+    }
+
+    fn get(&self, name: &str) -> Option<Arc<dyn Tool>> {
+        self.lookup(name).map(|(tool, _)| tool)
+    }
+
+    fn generation(&self) -> Option<u64> {
+        Some(self.locks.read(&self.entries).generation)
+    }
+
+    fn tools(&self) -> Vec<Arc<dyn Tool>> {
+        StaticToolCatalog::tools(self)
+    }
+
+    fn lookup(&self, name: &str) -> Option<ToolSpec> {
+        StaticToolCatalog::lookup(self, name).map(|(_, spec)| spec)
+    }
+
+    fn specs(&self) -> Vec<ToolSpec> {
+        StaticToolCatalog::specs(self)
+    }
+}
+
 /// StaticSubAgentDirectory is the default SubAgentDirectory implementation.
 /// It maintains sub-agents in registration order and provides thread-safe lookup.
 pub struct StaticSubAgentDirectory {
-    subagents: RwLock<HashMap<String, Arc<dyn SubAgent>>>,
-    order: RwLock<Vec<String>>,
+    entries: RwLock<SubAgentEntries>,
+    locks: LockCounters,
+}
+
+/// Registered sub-agents keyed by name, plus their registration order
+#[derive(Default)]
+struct SubAgentEntries {
+    subagents: HashMap<String, Arc<dyn SubAgent>>,
+    order: Vec<String>,
 }
 
 impl StaticSubAgentDirectory {
     /// Creates a new empty directory
     pub fn new() -> Self {
         Self {
-            subagents: RwLock::new(HashMap::new()),
-            order: RwLock::new(Vec::new()),
+            entries: RwLock::new(SubAgentEntries::default()),
+            locks: LockCounters::default(),
         }
     }
+
+    /// Returns how often the directory's lock was taken and waited on
+    pub fn lock_metrics(&self) -> LockMetrics {
+        self.locks.snapshot()
+    }
 }
 
 impl Default for StaticSubAgentDirectory {
@@ -134,18 +332,17 @@ impl SubAgentDirectory for StaticSubAgentDirectory {
             return Err(AgentError::Other("sub-agent name is empty".into()));
         }
 
-        let mut subagents = self.subagents.write().unwrap();
-        let mut order = self.order.write().unwrap();
+        let mut entries = self.locks.write(&self.entries);
 
-        if subagents.contains_key(&key) {
+        if entries.subagents.contains_key(&key) {
             return Err(AgentError::Other(format!(
                 "sub-agent {} already registered",
                 name
             )));
         }
 
-        subagents.insert(key.clone(), subagent);
-        order.push(key);
+        entries.subagents.insert(key.clone(), subagent);
+        entries.order.push(key);
 
         Ok(())
     }
@@ -153,18 +350,18 @@ impl SubAgentDirectory for StaticSubAgentDirectory {
     /// Lookup a sub-agent by name
     fn lookup(&self, name: &str) -> Option<Arc<dyn SubAgent>> {
         let key = name.to_lowercase().trim().to_string();
-        let subagents = self.subagents.read().unwrap();
-        subagents.get(&key).map(Arc::clone)
+        let entries = self.locks.read(&self.entries);
+        entries.subagents.get(&key).map(Arc::clone)
     }
 
     /// Returns all registered sub-agents in registration order
     fn all(&self) -> Vec<Arc<dyn SubAgent>> {
-        let order = self.order.read().unwrap();
-        let subagents = self.subagents.read().unwrap();
+        let entries = self.locks.read(&self.entries);
 
-        order
+        entries
+            .order
             .iter()
-            .filter_map(|key| subagents.get(key).map(Arc::clone))
+            .filter_map(|key| entries.subagents.get(key).map(Arc::clone))
             .collect()
     }
 }
@@ -187,6 +384,7 @@ mod tests {
                 description: "Test tool".into(),
                 input_schema: serde_json::json!({}),
                 examples: None,
+                output_schema: None,
             }
         }
 
@@ -268,4 +466,31 @@ mod tests {
         dir.register(sa1).unwrap();
         assert!(dir.register(sa2).is_err());
     }
+
+    #[test]
+    fn catalog_survives_panics_and_counts_contention() {
+        let catalog = Arc::new(StaticToolCatalog::new());
+        let panicking = Arc::clone(&catalog);
+        let result = std::thread::spawn(move || {
+            let _entries = panicking.entries.write();
+            panic!("tool panicked");
+        })
+        .join();
+        assert!(result.is_err());
+
+        let entries = catalog.locks.write(&catalog.entries);
+        let reader = Arc::clone(&catalog);
+        let handle = std::thread::spawn(move || reader.specs().len());
+        // Contention is counted before the reader blocks
+        while catalog.lock_metrics().contended == 0 {
+            std::thread::yield_now();
+        }
+        drop(entries);
+        assert_eq!(handle.join().unwrap(), 0);
+
+        let metrics = catalog.lock_metrics();
+        assert_eq!(metrics.acquisitions, 2);
+        assert_eq!(metrics.contended, 1);
+        assert!(metrics.wait > Duration::ZERO);
+    }
 }
@@ -0,0 +1,644 @@
+//! Durable storage for agent checkpoints.
+//!
+//! [`Agent::checkpoint`](crate::Agent::checkpoint) serializes a session into
+//! bytes; a [`CheckpointStore`] keeps those bytes somewhere that outlives the
+//! process, so a stateless container can pick a session up where another left
+//! off with [`Agent::restore_latest`](crate::Agent::restore_latest).
+//! [`InMemoryCheckpointer`] keeps them in-process and [`FileCheckpointer`] in
+//! a local directory; with the `object-store` feature,
+//! `ObjectStoreCheckpointer` writes them to S3, GCS, Azure or any other
+//! backend of the `object_store` crate, and with the `postgres` feature
+//! `PostgresCheckpointer` keeps them in a table.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::error::{AgentError, Result};
+
+/// Describes one stored checkpoint
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckpointInfo {
+    /// Identifier passed to [`CheckpointStore::load`]; ids sort by creation time
+    pub id: String,
+    pub session_id: String,
+    pub created_at: DateTime<Utc>,
+    /// Size of the checkpoint in bytes
+    pub size: usize,
+}
+
+/// Storage for serialized agent checkpoints, grouped by session
+#[async_trait]
+pub trait CheckpointStore: Send + Sync {
+    /// Stores a checkpoint of `session_id` and returns its id
+    async fn save(&self, session_id: &str, data: &[u8]) -> Result<String>;
+
+    /// Returns the checkpoint with the given id, if present
+    async fn load(&self, session_id: &str, id: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Lists the checkpoints of `session_id`, oldest first
+    async fn list(&self, session_id: &str) -> Result<Vec<CheckpointInfo>>;
+
+    /// Removes a checkpoint, returning whether it existed
+    async fn delete(&self, session_id: &str, id: &str) -> Result<bool>;
+
+    /// Returns the most recent checkpoint of `session_id`, if any
+    async fn latest(&self, session_id: &str) -> Result<Option<Vec<u8>>> {
+        match self.list(session_id).await?.pop() {
+            Some(info) => self.load(session_id, &info.id).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Deletes all but the newest `keep` checkpoints of `session_id`,
+    /// returning how many were removed
+    async fn prune(&self, session_id: &str, keep: usize) -> Result<usize> {
+        let checkpoints = self.list(session_id).await?;
+        let excess = checkpoints.len().saturating_sub(keep);
+        let mut removed = 0;
+        for info in &checkpoints[..excess] {
+            if self.delete(session_id, &info.id).await? {
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Returns a timestamped checkpoint id that sorts after every id issued
+/// before it by this process
+fn checkpoint_id() -> String {
+    static LAST_MICROS: AtomicI64 = AtomicI64::new(0);
+    let now = Utc::now().timestamp_micros();
+    let last = LAST_MICROS
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |last| {
+            Some(now.max(last + 1))
+        })
+        .unwrap_or(now);
+    let micros = now.max(last + 1);
+    let timestamp = DateTime::from_timestamp_micros(micros).unwrap_or_else(Utc::now);
+    format!(
+        "{}-{}",
+        timestamp.format("%Y%m%dT%H%M%S%.6fZ"),
+        &uuid::Uuid::new_v4().simple().to_string()[..8]
+    )
+}
+
+/// Checkpoints of a session with their data, oldest first
+type SessionCheckpoints = Vec<(CheckpointInfo, Vec<u8>)>;
+
+/// Process-local checkpointer, mainly for tests and single-process setups
+#[derive(Default)]
+pub struct InMemoryCheckpointer {
+    sessions: parking_lot::RwLock<HashMap<String, SessionCheckpoints>>,
+}
+
+impl InMemoryCheckpointer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for InMemoryCheckpointer {
+    async fn save(&self, session_id: &str, data: &[u8]) -> Result<String> {
+        let info = CheckpointInfo {
+            id: checkpoint_id(),
+            session_id: session_id.to_string(),
+            created_at: Utc::now(),
+            size: data.len(),
+        };
+        let id = info.id.clone();
+        self.sessions
+            .write()
+            .entry(session_id.to_string())
+            .or_default()
+            .push((info, data.to_vec()));
+        Ok(id)
+    }
+
+    async fn load(&self, session_id: &str, id: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .sessions
+            .read()
+            .get(session_id)
+            .and_then(|checkpoints| {
+                checkpoints
+                    .iter()
+                    .find(|(info, _)| info.id == id)
+                    .map(|(_, data)| data.clone())
+            }))
+    }
+
+    async fn list(&self, session_id: &str) -> Result<Vec<CheckpointInfo>> {
+        Ok(self
+            .sessions
+            .read()
+            .get(session_id)
+            .map(|checkpoints| checkpoints.iter().map(|(info, _)| info.clone()).collect())
+            .unwrap_or_default())
+    }
+
+    async fn delete(&self, session_id: &str, id: &str) -> Result<bool> {
+        let mut sessions = self.sessions.write();
+        let Some(checkpoints) = sessions.get_mut(session_id) else {
+            return Ok(false);
+        };
+        let before = checkpoints.len();
+        checkpoints.retain(|(info, _)| info.id != id);
+        Ok(checkpoints.len() < before)
+    }
+}
+
+const CHECKPOINT_EXTENSION: &str = ".json";
+
+/// Checkpoint store writing one file per checkpoint to a local directory.
+///
+/// Checkpoints live at `{root}/{session_id}/{id}.json`, with characters
+/// other than ASCII letters, digits, `-` and `_` in the session id
+/// percent-encoded so every session is exactly one directory. Checkpoint
+/// ids other than those `save` hands out, such as ones with path
+/// separators, are rejected. Files are written to a temporary name and
+/// renamed, so readers never see a partial checkpoint.
+pub struct FileCheckpointer {
+    root: PathBuf,
+}
+
+impl FileCheckpointer {
+    /// Stores checkpoints under `root`, creating it on first save
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Returns the directory all checkpoints are written under
+    pub fn root(&self) -> &std::path::Path {
+        &self.root
+    }
+
+    fn session_dir(&self, session_id: &str) -> PathBuf {
+        let mut name = String::with_capacity(session_id.len());
+        for byte in session_id.bytes() {
+            if byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_' {
+                name.push(byte as char);
+            } else {
+                name.push_str(&format!("%{:02X}", byte));
+            }
+        }
+        self.root.join(name)
+    }
+
+    /// Fails with [`AgentError::ConfigError`] for an id that could name a
+    /// file outside the session directory
+    fn file_path(&self, session_id: &str, id: &str) -> Result<PathBuf> {
+        let valid = !id.is_empty()
+            && !id.starts_with('.')
+            && id
+                .bytes()
+                .all(|byte| byte.is_ascii_alphanumeric() || matches!(byte, b'-' | b'_' | b'.'));
+        if !valid {
+            return Err(AgentError::ConfigError(format!(
+                "Invalid checkpoint id: {}",
+                id
+            )));
+        }
+        Ok(self
+            .session_dir(session_id)
+            .join(format!("{}{}", id, CHECKPOINT_EXTENSION)))
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for FileCheckpointer {
+    async fn save(&self, session_id: &str, data: &[u8]) -> Result<String> {
+        let id = checkpoint_id();
+        let dir = self.session_dir(session_id);
+        tokio::fs::create_dir_all(&dir).await?;
+
+        let path = self.file_path(session_id, &id)?;
+        let partial = dir.join(format!(".{}.partial", id));
+        tokio::fs::write(&partial, data).await?;
+        tokio::fs::rename(&partial, &path).await?;
+        Ok(id)
+    }
+
+    async fn load(&self, session_id: &str, id: &str) -> Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.file_path(session_id, id)?).await {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn list(&self, session_id: &str) -> Result<Vec<CheckpointInfo>> {
+        let mut entries = match tokio::fs::read_dir(self.session_dir(session_id)).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut checkpoints = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let file_name = entry.file_name();
+            let Some(id) = file_name
+                .to_str()
+                .and_then(|name| name.strip_suffix(CHECKPOINT_EXTENSION))
+            else {
+                continue;
+            };
+            let metadata = entry.metadata().await?;
+            checkpoints.push(CheckpointInfo {
+                id: id.to_string(),
+                session_id: session_id.to_string(),
+                created_at: metadata
+                    .modified()
+                    .map(DateTime::<Utc>::from)
+                    .unwrap_or_else(|_| Utc::now()),
+                size: metadata.len() as usize,
+            });
+        }
+        checkpoints.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(checkpoints)
+    }
+
+    async fn delete(&self, session_id: &str, id: &str) -> Result<bool> {
+        match tokio::fs::remove_file(self.file_path(session_id, id)?).await {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[cfg(feature = "object-store")]
+pub use object::ObjectStoreCheckpointer;
+
+#[cfg(feature = "object-store")]
+mod object {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use futures::TryStreamExt;
+    use object_store::path::Path;
+    use object_store::{ObjectStore, PutPayload};
+
+    use super::{checkpoint_id, CheckpointInfo, CheckpointStore, CHECKPOINT_EXTENSION};
+    use crate::error::{AgentError, Result};
+
+    fn storage_error(e: object_store::Error) -> AgentError {
+        AgentError::MemoryError(format!("Checkpoint storage failed: {}", e))
+    }
+
+    /// Checkpoint store writing to an `object_store` bucket.
+    ///
+    /// Each agent owns a prefix, and each checkpoint is one object at
+    /// `{prefix}/{session_id}/{id}.json`, so listing a session is a single
+    /// prefix listing and deployments sharing a bucket never collide.
+    pub struct ObjectStoreCheckpointer {
+        store: Arc<dyn ObjectStore>,
+        prefix: Path,
+    }
+
+    impl ObjectStoreCheckpointer {
+        /// Stores checkpoints in `store` under `prefix`, e.g. `agents/support-bot`
+        pub fn new(store: Arc<dyn ObjectStore>, prefix: impl AsRef<str>) -> Self {
+            Self {
+                store,
+                prefix: Path::from(prefix.as_ref()),
+            }
+        }
+
+        /// Returns the prefix all checkpoints are written under
+        pub fn prefix(&self) -> &Path {
+            &self.prefix
+        }
+
+        fn session_path(&self, session_id: &str) -> Path {
+            // `child` escapes delimiters, so a session id is always one segment
+            self.prefix.child(session_id)
+        }
+
+        fn object_path(&self, session_id: &str, id: &str) -> Path {
+            self.session_path(session_id)
+                .child(format!("{}{}", id, CHECKPOINT_EXTENSION))
+        }
+    }
+
+    #[async_trait]
+    impl CheckpointStore for ObjectStoreCheckpointer {
+        async fn save(&self, session_id: &str, data: &[u8]) -> Result<String> {
+            let id = checkpoint_id();
+            self.store
+                .put(
+                    &self.object_path(session_id, &id),
+                    PutPayload::from(data.to_vec()),
+                )
+                .await
+                .map_err(storage_error)?;
+            Ok(id)
+        }
+
+        async fn load(&self, session_id: &str, id: &str) -> Result<Option<Vec<u8>>> {
+            let result = match self.store.get(&self.object_path(session_id, id)).await {
+                Ok(result) => result,
+                Err(object_store::Error::NotFound { .. }) => return Ok(None),
+                Err(e) => return Err(storage_error(e)),
+            };
+            let bytes = result.bytes().await.map_err(storage_error)?;
+            Ok(Some(bytes.to_vec()))
+        }
+
+        async fn list(&self, session_id: &str) -> Result<Vec<CheckpointInfo>> {
+            let objects: Vec<_> = self
+                .store
+                .list(Some(&self.session_path(session_id)))
+                .try_collect()
+                .await
+                .map_err(storage_error)?;
+
+            let mut checkpoints: Vec<CheckpointInfo> = objects
+                .into_iter()
+                .filter_map(|meta| {
+                    let id = meta
+                        .location
+                        .filename()?
+                        .strip_suffix(CHECKPOINT_EXTENSION)?
+                        .to_string();
+                    Some(CheckpointInfo {
+                        id,
+                        session_id: session_id.to_string(),
+                        created_at: meta.last_modified,
+                        size: meta.size,
+                    })
+                })
+                .collect();
+            // Not every backend lists in key order
+            checkpoints.sort_by(|a, b| a.id.cmp(&b.id));
+            Ok(checkpoints)
+        }
+
+        async fn delete(&self, session_id: &str, id: &str) -> Result<bool> {
+            let path = self.object_path(session_id, id);
+            match self.store.head(&path).await {
+                Ok(_) => {}
+                Err(object_store::Error::NotFound { .. }) => return Ok(false),
+                Err(e) => return Err(storage_error(e)),
+            }
+            self.store.delete(&path).await.map_err(storage_error)?;
+            Ok(true)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use object_store::memory::InMemory;
+
+        #[tokio::test]
+        async fn test_object_store_layout_and_latest() {
+            let store = Arc::new(InMemory::new());
+            let checkpointer = ObjectStoreCheckpointer::new(store.clone(), "agents/support");
+
+            let first = checkpointer.save("chat/1", b"one").await.unwrap();
+            let second = checkpointer.save("chat/1", b"two").await.unwrap();
+            assert!(first < second);
+
+            let objects: Vec<_> = store
+                .list(Some(&Path::from("agents/support")))
+                .try_collect()
+                .await
+                .unwrap();
+            assert_eq!(objects.len(), 2);
+            assert_eq!(objects[0].location.parts().count(), 4);
+
+            assert_eq!(
+                checkpointer.latest("chat/1").await.unwrap().unwrap(),
+                b"two"
+            );
+            assert!(checkpointer.delete("chat/1", &second).await.unwrap());
+            assert_eq!(
+                checkpointer.latest("chat/1").await.unwrap().unwrap(),
+                b"one"
+            );
+            assert!(checkpointer.latest("chat/2").await.unwrap().is_none());
+        }
+    }
+}
+
+#[cfg(feature = "postgres")]
+pub use self::postgres::PostgresCheckpointer;
+
+#[cfg(feature = "postgres")]
+mod postgres {
+    use async_trait::async_trait;
+    use chrono::{DateTime, Utc};
+    use sqlx::PgPool;
+
+    use super::{checkpoint_id, CheckpointInfo, CheckpointStore};
+    use crate::error::{AgentError, Result};
+    use crate::memory::postgres::check_table_name;
+
+    fn storage_error(e: sqlx::Error) -> AgentError {
+        AgentError::MemoryError(format!("Checkpoint storage failed: {}", e))
+    }
+
+    /// Checkpoint store keeping checkpoints as rows of a Postgres table.
+    ///
+    /// Rows are keyed by `(session_id, id)`, so listing a session and
+    /// pruning it are each a single query.
+    pub struct PostgresCheckpointer {
+        pool: PgPool,
+        table: String,
+    }
+
+    impl PostgresCheckpointer {
+        /// Connects to `database_url` and creates the `agent_checkpoints`
+        /// table if needed
+        pub async fn connect(database_url: &str) -> Result<Self> {
+            let pool = PgPool::connect(database_url).await.map_err(|e| {
+                AgentError::MemoryError(format!("Failed to connect to PostgreSQL: {}", e))
+            })?;
+            let checkpointer = Self::new(pool);
+            checkpointer.create_table().await?;
+            Ok(checkpointer)
+        }
+
+        /// Uses an existing pool, e.g. the one of the memory store; call
+        /// [`PostgresCheckpointer::create_table`] once before first use
+        pub fn new(pool: PgPool) -> Self {
+            Self {
+                pool,
+                table: "agent_checkpoints".to_string(),
+            }
+        }
+
+        /// Sets the table name; it must be a lowercase SQL identifier
+        pub fn with_table(mut self, table: impl Into<String>) -> Self {
+            self.table = table.into();
+            self
+        }
+
+        /// Creates the checkpoint table if it doesn't exist
+        pub async fn create_table(&self) -> Result<()> {
+            let sql = format!(
+                "CREATE TABLE IF NOT EXISTS {} (
+                    session_id TEXT NOT NULL,
+                    id TEXT NOT NULL,
+                    data BYTEA NOT NULL,
+                    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+                    PRIMARY KEY (session_id, id)
+                )",
+                self.table()?
+            );
+            sqlx::query(&sql)
+                .execute(&self.pool)
+                .await
+                .map_err(storage_error)?;
+            Ok(())
+        }
+
+        fn table(&self) -> Result<&str> {
+            check_table_name(&self.table)?;
+            Ok(&self.table)
+        }
+    }
+
+    #[async_trait]
+    impl CheckpointStore for PostgresCheckpointer {
+        async fn save(&self, session_id: &str, data: &[u8]) -> Result<String> {
+            let id = checkpoint_id();
+            let sql = format!(
+                "INSERT INTO {} (session_id, id, data) VALUES ($1, $2, $3)",
+                self.table()?
+            );
+            sqlx::query(&sql)
+                .bind(session_id)
+                .bind(&id)
+                .bind(data)
+                .execute(&self.pool)
+                .await
+                .map_err(storage_error)?;
+            Ok(id)
+        }
+
+        async fn load(&self, session_id: &str, id: &str) -> Result<Option<Vec<u8>>> {
+            let sql = format!(
+                "SELECT data FROM {} WHERE session_id = $1 AND id = $2",
+                self.table()?
+            );
+            let row: Option<(Vec<u8>,)> = sqlx::query_as(&sql)
+                .bind(session_id)
+                .bind(id)
+                .fetch_optional(&self.pool)
+                .await
+                .map_err(storage_error)?;
+            Ok(row.map(|(data,)| data))
+        }
+
+        async fn list(&self, session_id: &str) -> Result<Vec<CheckpointInfo>> {
+            let sql = format!(
+                "SELECT id, created_at, octet_length(data) FROM {} \
+                 WHERE session_id = $1 ORDER BY id",
+                self.table()?
+            );
+            let rows: Vec<(String, DateTime<Utc>, i32)> = sqlx::query_as(&sql)
+                .bind(session_id)
+                .fetch_all(&self.pool)
+                .await
+                .map_err(storage_error)?;
+            Ok(rows
+                .into_iter()
+                .map(|(id, created_at, size)| CheckpointInfo {
+                    id,
+                    session_id: session_id.to_string(),
+                    created_at,
+                    size: size as usize,
+                })
+                .collect())
+        }
+
+        async fn delete(&self, session_id: &str, id: &str) -> Result<bool> {
+            let sql = format!(
+                "DELETE FROM {} WHERE session_id = $1 AND id = $2",
+                self.table()?
+            );
+            let result = sqlx::query(&sql)
+                .bind(session_id)
+                .bind(id)
+                .execute(&self.pool)
+                .await
+                .map_err(storage_error)?;
+            Ok(result.rows_affected() > 0)
+        }
+
+        async fn prune(&self, session_id: &str, keep: usize) -> Result<usize> {
+            let table = self.table()?;
+            let sql = format!(
+                "DELETE FROM {table} WHERE session_id = $1 AND id NOT IN \
+                 (SELECT id FROM {table} WHERE session_id = $1 ORDER BY id DESC LIMIT $2)"
+            );
+            let result = sqlx::query(&sql)
+                .bind(session_id)
+                .bind(keep as i64)
+                .execute(&self.pool)
+                .await
+                .map_err(storage_error)?;
+            Ok(result.rows_affected() as usize)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_latest_and_prune() {
+        let checkpointer = InMemoryCheckpointer::new();
+        assert!(checkpointer.latest("s").await.unwrap().is_none());
+
+        for data in [b"one", b"two", b"3rd"] {
+            checkpointer.save("s", data).await.unwrap();
+        }
+        assert_eq!(checkpointer.latest("s").await.unwrap().unwrap(), b"3rd");
+
+        assert_eq!(checkpointer.prune("s", 1).await.unwrap(), 2);
+        let remaining = checkpointer.list("s").await.unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].size, 3);
+    }
+
+    #[tokio::test]
+    async fn test_file_checkpointer_round_trip() {
+        let root = std::env::temp_dir().join(format!("checkpoints-{}", uuid::Uuid::new_v4()));
+        let checkpointer = FileCheckpointer::new(&root);
+        assert!(checkpointer.list("chat/1").await.unwrap().is_empty());
+
+        let first = checkpointer.save("chat/1", b"one").await.unwrap();
+        checkpointer.save("chat/1", b"two").await.unwrap();
+        assert!(root.join("chat%2F1").is_dir());
+        assert_eq!(
+            checkpointer.latest("chat/1").await.unwrap().unwrap(),
+            b"two"
+        );
+        assert_eq!(
+            checkpointer.load("chat/1", &first).await.unwrap().unwrap(),
+            b"one"
+        );
+
+        assert_eq!(checkpointer.prune("chat/1", 1).await.unwrap(), 1);
+        assert!(checkpointer.load("chat/1", &first).await.unwrap().is_none());
+        assert!(!checkpointer.delete("chat/1", &first).await.unwrap());
+
+        for id in ["../../escape", "..", "a/b", ""] {
+            assert!(matches!(
+                checkpointer.load("chat/1", id).await,
+                Err(AgentError::ConfigError(_))
+            ));
+        }
+
+        let _ = tokio::fs::remove_dir_all(&root).await;
+    }
+}
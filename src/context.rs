@@ -0,0 +1,759 @@
+//! Prompt context assembly.
+//!
+//! Each turn draws on several kinds of memory: the recent conversation,
+//! summaries left by compaction, facts recalled from the user's long-term
+//! memory, the session's semantic tier, and older turns of the session found
+//! by retrieval. [`ContextComposer`] decides how they share the token budget,
+//! where they sit in the prompt, and how they are labeled.
+//!
+//! The composer is the default [`ContextStrategy`]; [`SlidingWindow`],
+//! [`SummarizeOlder`], [`ImportancePriority`] and [`RelevanceRetrieval`]
+//! pack the prompt in other ways.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::error::Result;
+use crate::memory::compaction::SUMMARY_ROLE;
+use crate::memory::Compactor;
+use crate::memory::{semantic_scope, MemoryRecord, SOURCE_SESSION_KEY, TRUNCATED_KEY};
+use crate::models::{HeuristicTokenizer, Tokenizer};
+use crate::tools::TOOL_NAME_KEY;
+use crate::types::{Message, Role};
+
+/// Newest turns replayed by default whatever their importance, so the
+/// model always sees the exchange it is answering
+pub const DEFAULT_RECENT_TURNS: usize = 2;
+
+/// A kind of memory the composer places in the prompt
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ContextSection {
+    /// Summaries that replaced compacted history
+    Summaries,
+    /// Records recalled from the user's long-term memory
+    Facts,
+    /// Facts and summaries from this session's semantic tier
+    Semantic,
+    /// Older turns of this session found by retrieval
+    Recalled,
+    /// The recent conversation, replayed as chat turns
+    Recent,
+}
+
+impl ContextSection {
+    fn default_header(self) -> &'static str {
+        match self {
+            ContextSection::Summaries => "Summaries of earlier conversation:",
+            ContextSection::Facts => "What you know about this user from earlier sessions:",
+            ContextSection::Semantic => "Key facts from this conversation:",
+            ContextSection::Recalled => "Relevant memories from earlier in this session:",
+            ContextSection::Recent => "",
+        }
+    }
+}
+
+/// Interleaves and labels memory sections in the prompt.
+///
+/// Sections are filled in order until the context budget runs out, and
+/// appear in the prompt in the same order: those listed before
+/// [`ContextSection::Recent`] precede the replayed conversation as system
+/// messages, those after it follow the conversation, right before the new
+/// user input. Sections left out of the order are not used. A section with
+/// its own budget never takes more than that share.
+#[derive(Debug, Clone)]
+pub struct ContextComposer {
+    order: Vec<ContextSection>,
+    headers: HashMap<ContextSection, String>,
+    budgets: HashMap<ContextSection, usize>,
+    provenance: bool,
+    recent_turns: usize,
+}
+
+impl Default for ContextComposer {
+    fn default() -> Self {
+        Self {
+            order: vec![
+                ContextSection::Summaries,
+                ContextSection::Facts,
+                ContextSection::Semantic,
+                ContextSection::Recalled,
+                ContextSection::Recent,
+            ],
+            headers: HashMap::new(),
+            budgets: HashMap::new(),
+            provenance: false,
+            recent_turns: DEFAULT_RECENT_TURNS,
+        }
+    }
+}
+
+impl ContextComposer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets which sections are used and in what order
+    pub fn with_order(mut self, order: impl IntoIterator<Item = ContextSection>) -> Self {
+        self.order = Vec::new();
+        for section in order {
+            if !self.order.contains(&section) {
+                self.order.push(section);
+            }
+        }
+        self
+    }
+
+    /// Replaces the heading that introduces a section
+    pub fn with_header(mut self, section: ContextSection, header: impl Into<String>) -> Self {
+        self.headers.insert(section, header.into());
+        self
+    }
+
+    /// Caps the tokens a section may use, so one tier can't crowd out the
+    /// others
+    pub fn with_section_budget(mut self, section: ContextSection, tokens: usize) -> Self {
+        self.budgets.insert(section, tokens);
+        self
+    }
+
+    /// Tags each recalled line with the session and time it came from
+    pub fn with_provenance(mut self, enabled: bool) -> Self {
+        self.provenance = enabled;
+        self
+    }
+
+    /// Always replays the newest `turns` turns that fit, before the most
+    /// important of the older ones
+    pub fn with_recent_turns(mut self, turns: usize) -> Self {
+        self.recent_turns = turns;
+        self
+    }
+
+    /// Builds the context messages for one turn of `session_id`.
+    ///
+    /// `recent` is the short-term window, oldest first; `relevant` holds
+    /// retrieval results from this session and the user's long-term memory.
+    /// The system prompt and the new user input are not included. Tokens
+    /// are estimated with [`HeuristicTokenizer`].
+    pub fn compose(
+        &self,
+        session_id: &str,
+        recent: &[MemoryRecord],
+        relevant: &[MemoryRecord],
+        budget: usize,
+    ) -> Vec<Message> {
+        self.compose_with(session_id, recent, relevant, budget, &HeuristicTokenizer)
+    }
+
+    /// Like [`ContextComposer::compose`], counting tokens with `tokenizer`
+    pub fn compose_with(
+        &self,
+        session_id: &str,
+        recent: &[MemoryRecord],
+        relevant: &[MemoryRecord],
+        budget: usize,
+        tokenizer: &dyn Tokenizer,
+    ) -> Vec<Message> {
+        let recent_ids: HashSet<_> = recent.iter().map(|r| r.id).collect();
+        let semantic_session = semantic_scope(session_id);
+        let older: Vec<&MemoryRecord> = relevant
+            .iter()
+            .filter(|r| !recent_ids.contains(&r.id))
+            .collect();
+
+        let mut remaining = budget;
+        let mut before = Vec::new();
+        let mut turns = Vec::new();
+        let mut after = Vec::new();
+        let mut seen_recent = false;
+
+        for &section in &self.order {
+            let mut allowance = self
+                .budgets
+                .get(&section)
+                .map_or(remaining, |&cap| cap.min(remaining));
+            let granted = allowance;
+            let candidates: Vec<&MemoryRecord> = match section {
+                ContextSection::Summaries => {
+                    recent.iter().filter(|r| r.role == SUMMARY_ROLE).collect()
+                }
+                ContextSection::Facts => older
+                    .iter()
+                    .copied()
+                    .filter(|r| r.session_id != session_id && r.session_id != semantic_session)
+                    .collect(),
+                ContextSection::Semantic => older
+                    .iter()
+                    .copied()
+                    .filter(|r| r.session_id == semantic_session)
+                    .collect(),
+                ContextSection::Recalled => older
+                    .iter()
+                    .copied()
+                    .filter(|r| r.session_id == session_id)
+                    .collect(),
+                ContextSection::Recent => {
+                    seen_recent = true;
+                    let conversation: Vec<&MemoryRecord> =
+                        recent.iter().filter(|r| r.role != SUMMARY_ROLE).collect();
+                    turns =
+                        select_turns(&conversation, self.recent_turns, &mut allowance, tokenizer)
+                            .into_iter()
+                            .map(turn_message)
+                            .collect();
+                    remaining -= granted - allowance;
+                    continue;
+                }
+            };
+
+            let mut lines = Vec::new();
+            for record in candidates {
+                let tokens = tokenizer.count_tokens(&record.content);
+                if tokens > allowance {
+                    continue;
+                }
+                allowance -= tokens;
+                lines.push(self.line(section, record));
+            }
+            remaining -= granted - allowance;
+            if lines.is_empty() {
+                continue;
+            }
+
+            let header = self
+                .headers
+                .get(&section)
+                .map_or(section.default_header(), String::as_str);
+            let message = Message {
+                role: Role::System,
+                content: format!("{}\n{}", header, lines.join("\n")),
+                metadata: None,
+            };
+            if seen_recent {
+                after.push(message);
+            } else {
+                before.push(message);
+            }
+        }
+
+        before.into_iter().chain(turns).chain(after).collect()
+    }
+
+    fn line(&self, section: ContextSection, record: &MemoryRecord) -> String {
+        let body = match section {
+            ContextSection::Summaries => record.content.clone(),
+            _ => match tool_name(record) {
+                Some(tool) => format!("{} {}: {}", record.role, tool, record.content),
+                None => format!("{}: {}", record.role, record.content),
+            },
+        };
+        if !self.provenance {
+            return format!("- {}", body);
+        }
+
+        let source = record
+            .metadata
+            .as_ref()
+            .and_then(|m| m.get(SOURCE_SESSION_KEY))
+            .unwrap_or(&record.session_id);
+        format!(
+            "- [{}, {}] {}",
+            source,
+            record.timestamp.format("%Y-%m-%d %H:%M"),
+            body
+        )
+    }
+}
+
+/// Fills the budget with the newest `recent` turns, then the most important
+/// older ones, preferring newer ones on ties, and returns them in
+/// conversation order
+fn select_turns<'a>(
+    conversation: &[&'a MemoryRecord],
+    recent: usize,
+    remaining: &mut usize,
+    tokenizer: &dyn Tokenizer,
+) -> Vec<&'a MemoryRecord> {
+    let split = conversation.len().saturating_sub(recent);
+    let mut ranked: Vec<(usize, &MemoryRecord)> =
+        conversation.iter().copied().enumerate().collect();
+    ranked[..split].sort_by(|(ia, a), (ib, b)| {
+        b.importance
+            .partial_cmp(&a.importance)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(ib.cmp(ia))
+    });
+
+    // The kept turns go first, newest first
+    ranked[split..].reverse();
+    ranked.rotate_left(split);
+
+    let mut selected = Vec::new();
+    for (index, record) in ranked {
+        let tokens = tokenizer.count_tokens(&record.content);
+        if tokens > *remaining {
+            continue;
+        }
+        *remaining -= tokens;
+        selected.push(index);
+    }
+    selected.sort_unstable();
+    selected
+        .into_iter()
+        .map(|index| conversation[index])
+        .collect()
+}
+
+/// Returns the tool that produced `record`, if it is a tool result
+fn tool_name(record: &MemoryRecord) -> Option<&str> {
+    record
+        .metadata
+        .as_ref()
+        .and_then(|m| m.get(TOOL_NAME_KEY))
+        .map(String::as_str)
+}
+
+/// Appended to a turn that was cut off, so the model can acknowledge it and
+/// pick up where it stopped
+const TRUNCATED_NOTE: &str = "\n[This reply was interrupted before it finished.]";
+
+fn turn_message(record: &MemoryRecord) -> Message {
+    // Name the tool so the model knows what the output answers
+    let mut content = match tool_name(record) {
+        Some(tool) => format!("{}: {}", tool, record.content),
+        None => record.content.clone(),
+    };
+    let truncated = record
+        .metadata
+        .as_ref()
+        .is_some_and(|m| m.get(TRUNCATED_KEY).is_some_and(|v| v == "true"));
+    if truncated {
+        content.push_str(TRUNCATED_NOTE);
+    }
+
+    Message {
+        role: match record.role.as_str() {
+            "user" => Role::User,
+            "assistant" => Role::Assistant,
+            "tool" => Role::Tool,
+            _ => Role::User,
+        },
+        content,
+        metadata: record.metadata.clone(),
+    }
+}
+
+/// What a [`ContextStrategy`] packs into the prompt for one turn
+pub struct ContextRequest<'a> {
+    pub session_id: &'a str,
+    /// The short-term window, oldest first
+    pub recent: &'a [MemoryRecord],
+    /// Retrieval results, best first
+    pub relevant: &'a [MemoryRecord],
+    /// Tokens available for context
+    pub budget: usize,
+    pub tokenizer: &'a dyn Tokenizer,
+}
+
+/// Decides which memories fill the prompt and how they are laid out.
+///
+/// Returns the context messages only; the agent adds the system prompt and
+/// the new user input around them.
+#[async_trait]
+pub trait ContextStrategy: Send + Sync {
+    /// Short name for logs and debugging
+    fn name(&self) -> &str;
+
+    async fn pack(&self, request: ContextRequest<'_>) -> Result<Vec<Message>>;
+}
+
+#[async_trait]
+impl ContextStrategy for ContextComposer {
+    fn name(&self) -> &str {
+        "composer"
+    }
+
+    async fn pack(&self, request: ContextRequest<'_>) -> Result<Vec<Message>> {
+        Ok(self.compose_with(
+            request.session_id,
+            request.recent,
+            request.relevant,
+            request.budget,
+            request.tokenizer,
+        ))
+    }
+}
+
+/// Replays the newest turns that fit and nothing else
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SlidingWindow;
+
+#[async_trait]
+impl ContextStrategy for SlidingWindow {
+    fn name(&self) -> &str {
+        "sliding_window"
+    }
+
+    async fn pack(&self, request: ContextRequest<'_>) -> Result<Vec<Message>> {
+        let mut remaining = request.budget;
+        let start = window_start(request.recent, &mut remaining, request.tokenizer);
+        Ok(request.recent[start..].iter().map(window_message).collect())
+    }
+}
+
+/// Replays the newest turns and then the most important older ones that
+/// fit, in conversation order
+#[derive(Debug, Clone, Copy)]
+pub struct ImportancePriority {
+    recent_turns: usize,
+}
+
+impl Default for ImportancePriority {
+    fn default() -> Self {
+        Self {
+            recent_turns: DEFAULT_RECENT_TURNS,
+        }
+    }
+}
+
+impl ImportancePriority {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Always replays the newest `turns` turns that fit
+    pub fn with_recent_turns(mut self, turns: usize) -> Self {
+        self.recent_turns = turns;
+        self
+    }
+}
+
+#[async_trait]
+impl ContextStrategy for ImportancePriority {
+    fn name(&self) -> &str {
+        "importance_priority"
+    }
+
+    async fn pack(&self, request: ContextRequest<'_>) -> Result<Vec<Message>> {
+        let records: Vec<&MemoryRecord> = request.recent.iter().collect();
+        let mut remaining = request.budget;
+        Ok(select_turns(
+            &records,
+            self.recent_turns,
+            &mut remaining,
+            request.tokenizer,
+        )
+        .into_iter()
+        .map(window_message)
+        .collect())
+    }
+}
+
+/// Replays the newest turns that fit and condenses the older ones into a
+/// summary with a [`Compactor`].
+///
+/// Part of the budget is held back for the summary. If summarizing fails the
+/// turn goes ahead with the window alone.
+pub struct SummarizeOlder {
+    compactor: Arc<Compactor>,
+    summary_share: f32,
+}
+
+impl SummarizeOlder {
+    /// Holds back a quarter of the budget for the summary
+    pub fn new(compactor: Arc<Compactor>) -> Self {
+        Self {
+            compactor,
+            summary_share: 0.25,
+        }
+    }
+
+    /// Sets the share of the budget held back for the summary
+    pub fn with_summary_share(mut self, share: f32) -> Self {
+        self.summary_share = share.clamp(0.0, 1.0);
+        self
+    }
+}
+
+#[async_trait]
+impl ContextStrategy for SummarizeOlder {
+    fn name(&self) -> &str {
+        "summarize_older"
+    }
+
+    async fn pack(&self, request: ContextRequest<'_>) -> Result<Vec<Message>> {
+        let reserved = (request.budget as f32 * self.summary_share) as usize;
+        let mut remaining = request.budget - reserved;
+        let start = window_start(request.recent, &mut remaining, request.tokenizer);
+        let mut messages: Vec<Message> =
+            request.recent[start..].iter().map(window_message).collect();
+        if start == 0 {
+            return Ok(messages);
+        }
+
+        match self.compactor.summarize(&request.recent[..start]).await {
+            Ok(summary)
+                if request.tokenizer.count_tokens(&summary.content) <= remaining + reserved =>
+            {
+                messages.insert(0, window_message(&summary));
+            }
+            Ok(_) => tracing::warn!("Summary of older turns exceeds the context budget"),
+            Err(e) => tracing::warn!("Summarizing older turns failed: {}", e),
+        }
+        Ok(messages)
+    }
+}
+
+/// Puts the best retrieval results first and fills the rest of the budget
+/// with the newest turns
+pub struct RelevanceRetrieval {
+    relevant_share: f32,
+    header: String,
+}
+
+impl Default for RelevanceRetrieval {
+    fn default() -> Self {
+        Self {
+            relevant_share: 0.5,
+            header: "Relevant memories:".to_string(),
+        }
+    }
+}
+
+impl RelevanceRetrieval {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Caps the share of the budget retrieval results may use
+    pub fn with_relevant_share(mut self, share: f32) -> Self {
+        self.relevant_share = share.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Replaces the heading that introduces the retrieval results
+    pub fn with_header(mut self, header: impl Into<String>) -> Self {
+        self.header = header.into();
+        self
+    }
+}
+
+#[async_trait]
+impl ContextStrategy for RelevanceRetrieval {
+    fn name(&self) -> &str {
+        "relevance_retrieval"
+    }
+
+    async fn pack(&self, request: ContextRequest<'_>) -> Result<Vec<Message>> {
+        let recent_ids: HashSet<_> = request.recent.iter().map(|r| r.id).collect();
+        let cap = (request.budget as f32 * self.relevant_share) as usize;
+        let mut allowance = cap;
+        let mut lines = Vec::new();
+        for record in request
+            .relevant
+            .iter()
+            .filter(|r| !recent_ids.contains(&r.id))
+        {
+            let tokens = request.tokenizer.count_tokens(&record.content);
+            if tokens > allowance {
+                continue;
+            }
+            allowance -= tokens;
+            lines.push(format!("- {}: {}", record.role, record.content));
+        }
+
+        let mut remaining = request.budget - (cap - allowance);
+        let start = window_start(request.recent, &mut remaining, request.tokenizer);
+        let mut messages = Vec::new();
+        if !lines.is_empty() {
+            messages.push(Message {
+                role: Role::System,
+                content: format!("{}\n{}", self.header, lines.join("\n")),
+                metadata: None,
+            });
+        }
+        messages.extend(request.recent[start..].iter().map(window_message));
+        Ok(messages)
+    }
+}
+
+/// Index of the oldest record in the run of newest records that fits
+fn window_start(
+    records: &[MemoryRecord],
+    remaining: &mut usize,
+    tokenizer: &dyn Tokenizer,
+) -> usize {
+    let mut start = records.len();
+    for (index, record) in records.iter().enumerate().rev() {
+        let tokens = tokenizer.count_tokens(&record.content);
+        if tokens > *remaining {
+            break;
+        }
+        *remaining -= tokens;
+        start = index;
+    }
+    start
+}
+
+/// Replays a record as a chat turn, or a summary as a system message
+fn window_message(record: &MemoryRecord) -> Message {
+    if record.role == SUMMARY_ROLE {
+        Message {
+            role: Role::System,
+            content: record.content.clone(),
+            metadata: None,
+        }
+    } else {
+        turn_message(record)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn record(session_id: &str, role: &str, content: &str) -> MemoryRecord {
+        MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: session_id.to_string(),
+            role: role.to_string(),
+            content: content.to_string(),
+            importance: 0.5,
+            timestamp: Utc::now(),
+            metadata: None,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn test_default_composition_labels_sections() {
+        let recent = vec![
+            record("s", SUMMARY_ROLE, "They asked about invoices."),
+            record("s", "user", "And refunds?"),
+            record("s", "assistant", "Refunds take 5 days."),
+        ];
+        let relevant = vec![
+            record("user:u1", "user", "I prefer email contact."),
+            record("s", "user", "My order is #4417."),
+            recent[1].clone(),
+        ];
+
+        let messages = ContextComposer::new().compose("s", &recent, &relevant, 1000);
+
+        assert_eq!(messages.len(), 5);
+        assert_eq!(
+            messages[0].content,
+            "Summaries of earlier conversation:\n- They asked about invoices."
+        );
+        assert!(messages[1]
+            .content
+            .ends_with("- user: I prefer email contact."));
+        assert!(messages[2].content.ends_with("- user: My order is #4417."));
+        assert_eq!(messages[3].role, Role::User);
+        assert_eq!(messages[4].content, "Refunds take 5 days.");
+    }
+
+    #[test]
+    fn test_section_budget_and_semantic_tier() {
+        let recent = vec![record("s", "user", "Where is my parcel?")];
+        let relevant = vec![
+            record(&semantic_scope("s"), "user", "Their order number is #4417."),
+            record("s", "user", "an older turn that is long enough to matter"),
+            record("s", "user", "another older turn that is long enough too"),
+        ];
+
+        let composer = ContextComposer::new().with_section_budget(ContextSection::Recalled, 12);
+        let messages = composer.compose("s", &recent, &relevant, 1000);
+
+        assert_eq!(messages.len(), 3);
+        assert_eq!(
+            messages[0].content,
+            "Key facts from this conversation:\n- user: Their order number is #4417."
+        );
+        assert_eq!(messages[1].content.lines().count(), 2);
+        assert_eq!(messages[2].content, "Where is my parcel?");
+    }
+
+    #[test]
+    fn test_order_headers_and_provenance() {
+        let recent = vec![record("s", "user", "Hello")];
+        let relevant = vec![record("s", "assistant", "Earlier reply")];
+
+        let composer = ContextComposer::new()
+            .with_order([ContextSection::Recent, ContextSection::Recalled])
+            .with_header(ContextSection::Recalled, "## Recalled")
+            .with_provenance(true);
+        let messages = composer.compose("s", &recent, &relevant, 1000);
+
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].content, "Hello");
+        assert!(messages[1].content.starts_with("## Recalled\n- [s, "));
+        assert!(messages[1].content.ends_with("] assistant: Earlier reply"));
+    }
+
+    #[tokio::test]
+    async fn test_strategies_pack_differently() {
+        let mut recent = vec![
+            record("s", "user", "an old question that is fairly long"),
+            record("s", "assistant", "ok"),
+            record("s", "user", "the newest question asked"),
+        ];
+        recent[0].importance = 0.9;
+        let relevant = vec![record("s", "user", "My order is #4417.")];
+        fn request<'a>(
+            recent: &'a [MemoryRecord],
+            relevant: &'a [MemoryRecord],
+            budget: usize,
+        ) -> ContextRequest<'a> {
+            ContextRequest {
+                session_id: "s",
+                recent,
+                relevant,
+                budget,
+                tokenizer: &HeuristicTokenizer,
+            }
+        }
+
+        let window = SlidingWindow
+            .pack(request(&recent, &relevant, 7))
+            .await
+            .unwrap();
+        let contents: Vec<&str> = window.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["ok", "the newest question asked"]);
+
+        // The newest turns are kept however unimportant
+        let important = ImportancePriority::new()
+            .pack(request(&recent, &relevant, 8))
+            .await
+            .unwrap();
+        let contents: Vec<&str> = important.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["ok", "the newest question asked"]);
+        // Without kept turns the important old turn wins over the newest one
+        let important = ImportancePriority::new()
+            .with_recent_turns(0)
+            .pack(request(&recent, &relevant, 8))
+            .await
+            .unwrap();
+        let contents: Vec<&str> = important.iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["an old question that is fairly long", "ok"]);
+
+        let retrieval = RelevanceRetrieval::new()
+            .pack(request(&recent, &relevant, 12))
+            .await
+            .unwrap();
+        assert_eq!(
+            retrieval[0].content,
+            "Relevant memories:\n- user: My order is #4417."
+        );
+        assert_eq!(
+            retrieval.last().unwrap().content,
+            "the newest question asked"
+        );
+    }
+}
@@ -0,0 +1,226 @@
+//! Spending of the agent and budgets that cap it.
+//!
+//! Model calls that return a reply are priced from their token counts with
+//! the agent's [`PricingRegistry`](crate::models::PricingRegistry) and
+//! added to a [`CostReport`] for their session and one for the whole agent;
+//! so are streams that fail or are dropped partway, for their prompt and
+//! the chunks that arrived. Calls that fail without a reply are not
+//! charged, and models without a known price add tokens but no cost. A
+//! [`CostBudget`] in `AgentOptions` sets soft limits, which log a warning
+//! when spending crosses them, and hard limits, which make further turns
+//! fail with [`AgentError::BudgetExceeded`] before anything is stored or
+//! sent. A hard limit is checked before each turn, so the turn that crosses
+//! it still completes.
+
+use std::collections::{BTreeMap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AgentError, Result};
+
+/// Spending limits in US dollars; `None` leaves a limit unset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct CostBudget {
+    /// Warns once a session has spent this much
+    #[serde(default)]
+    pub session_soft: Option<f64>,
+    /// Refuses turns of a session that has spent this much
+    #[serde(default)]
+    pub session_hard: Option<f64>,
+    /// Warns once the agent has spent this much across sessions
+    #[serde(default)]
+    pub agent_soft: Option<f64>,
+    /// Refuses every turn once the agent has spent this much
+    #[serde(default)]
+    pub agent_hard: Option<f64>,
+}
+
+impl CostBudget {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Warns when a session's spending reaches `soft` and refuses its turns
+    /// at `hard`
+    pub fn with_session_limits(mut self, soft: Option<f64>, hard: Option<f64>) -> Self {
+        self.session_soft = soft;
+        self.session_hard = hard;
+        self
+    }
+
+    /// Warns when the agent's total spending reaches `soft` and refuses all
+    /// turns at `hard`
+    pub fn with_agent_limits(mut self, soft: Option<f64>, hard: Option<f64>) -> Self {
+        self.agent_soft = soft;
+        self.agent_hard = hard;
+        self
+    }
+}
+
+/// Tokens and cost of a set of generations
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CostSummary {
+    pub generations: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// US dollars; generations of unpriced models add tokens but no cost
+    pub cost: f64,
+}
+
+impl CostSummary {
+    fn add(&mut self, input_tokens: usize, output_tokens: usize, cost: f64) {
+        self.generations += 1;
+        self.input_tokens += input_tokens as u64;
+        self.output_tokens += output_tokens as u64;
+        self.cost += cost;
+    }
+}
+
+/// Spending of a session or of the whole agent
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct CostReport {
+    pub total: CostSummary,
+    /// Spending per model id
+    pub models: BTreeMap<String, CostSummary>,
+}
+
+impl CostReport {
+    /// Returns the total cost in US dollars
+    pub fn cost(&self) -> f64 {
+        self.total.cost
+    }
+
+    fn add(&mut self, model: &str, input_tokens: usize, output_tokens: usize, cost: f64) {
+        self.total.add(input_tokens, output_tokens, cost);
+        self.models
+            .entry(model.to_string())
+            .or_default()
+            .add(input_tokens, output_tokens, cost);
+    }
+}
+
+/// Accumulates spending per session and for the agent
+#[derive(Default)]
+pub(crate) struct CostLedger {
+    sessions: parking_lot::Mutex<HashMap<String, CostReport>>,
+    agent: parking_lot::Mutex<CostReport>,
+}
+
+impl CostLedger {
+    /// Fails if `session_id` or the agent has reached a hard limit
+    pub(crate) fn check(&self, session_id: &str, budget: Option<&CostBudget>) -> Result<()> {
+        let Some(budget) = budget else {
+            return Ok(());
+        };
+        if let Some(limit) = budget.agent_hard {
+            let spent = self.agent.lock().cost();
+            if spent >= limit {
+                return Err(AgentError::BudgetExceeded(format!(
+                    "agent spent ${:.4} of its ${:.4} budget",
+                    spent, limit
+                )));
+            }
+        }
+        if let Some(limit) = budget.session_hard {
+            let spent = self.session(session_id).cost();
+            if spent >= limit {
+                return Err(AgentError::BudgetExceeded(format!(
+                    "session {} spent ${:.4} of its ${:.4} budget",
+                    session_id, spent, limit
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a generation, warning if it crosses a soft limit
+    pub(crate) fn record(
+        &self,
+        session_id: &str,
+        model: &str,
+        input_tokens: usize,
+        output_tokens: usize,
+        cost: f64,
+        budget: Option<&CostBudget>,
+    ) {
+        let session_spent = {
+            let mut sessions = self.sessions.lock();
+            let report = sessions.entry(session_id.to_string()).or_default();
+            report.add(model, input_tokens, output_tokens, cost);
+            report.cost()
+        };
+        let agent_spent = {
+            let mut agent = self.agent.lock();
+            agent.add(model, input_tokens, output_tokens, cost);
+            agent.cost()
+        };
+
+        let Some(budget) = budget else {
+            return;
+        };
+        let crossed = |limit: Option<f64>, spent: f64| {
+            limit.is_some_and(|limit| spent >= limit && spent - cost < limit)
+        };
+        if crossed(budget.session_soft, session_spent) {
+            tracing::warn!(
+                session_id,
+                spent = session_spent,
+                "Session passed its soft cost budget"
+            );
+        }
+        if crossed(budget.agent_soft, agent_spent) {
+            tracing::warn!(spent = agent_spent, "Agent passed its soft cost budget");
+        }
+    }
+
+    pub(crate) fn session(&self, session_id: &str) -> CostReport {
+        self.sessions
+            .lock()
+            .get(session_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn agent(&self) -> CostReport {
+        self.agent.lock().clone()
+    }
+
+    pub(crate) fn clear(&self, session_id: &str) {
+        self.sessions.lock().remove(session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ledger_aggregates_and_enforces_hard_limits() {
+        let ledger = CostLedger::default();
+        let budget = CostBudget::new()
+            .with_session_limits(Some(0.5), Some(1.0))
+            .with_agent_limits(None, Some(1.5));
+
+        ledger.record("a", "gpt-4o", 100, 50, 0.75, Some(&budget));
+        ledger.check("a", Some(&budget)).unwrap();
+        ledger.record("a", "gpt-4o-mini", 10, 5, 0.25, Some(&budget));
+        assert!(matches!(
+            ledger.check("a", Some(&budget)),
+            Err(AgentError::BudgetExceeded(_))
+        ));
+        ledger.check("b", Some(&budget)).unwrap();
+        ledger.check("a", None).unwrap();
+
+        let report = ledger.session("a");
+        assert_eq!(report.total.generations, 2);
+        assert_eq!(report.total.input_tokens, 110);
+        assert_eq!(report.models["gpt-4o"].output_tokens, 50);
+
+        // The agent limit covers every session
+        ledger.record("b", "gpt-4o", 10, 10, 0.5, Some(&budget));
+        assert!(ledger.check("c", Some(&budget)).is_err());
+        ledger.clear("a");
+        assert_eq!(ledger.session("a").total.generations, 0);
+        assert_eq!(ledger.agent().total.generations, 3);
+    }
+}
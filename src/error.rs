@@ -1,5 +1,7 @@
 use thiserror::Error;
 
+use crate::schema::SchemaError;
+
 /// Error types for the agent framework
 #[derive(Error, Debug)]
 pub enum AgentError {
@@ -33,6 +35,48 @@ pub enum AgentError {
     #[error("Invalid state: {0}")]
     InvalidState(String),
 
+    #[error("Version conflict: {0}")]
+    VersionConflict(String),
+
+    #[error("Tenant violation: {0}")]
+    TenantViolation(String),
+
+    #[error("Unsupported version: {0}")]
+    UnsupportedVersion(String),
+
+    #[error("Tool loop detected: {0}")]
+    ToolLoopDetected(String),
+
+    #[error("Tool timed out: {0}")]
+    ToolTimeout(String),
+
+    #[error("Tool denied: {0}")]
+    ToolDenied(String),
+
+    #[error("Budget exceeded: {0}")]
+    BudgetExceeded(String),
+
+    #[error("Cancelled: {0}")]
+    Cancelled(String),
+
+    #[error("Deadline exceeded: {0}")]
+    DeadlineExceeded(String),
+
+    #[error("Schema violation: {0}")]
+    SchemaViolation(String),
+
+    #[error("Invalid arguments for tool {tool}: {}", join_errors(.errors))]
+    InvalidToolArguments {
+        tool: String,
+        errors: Vec<SchemaError>,
+    },
+
+    #[error("Invalid output from tool {tool}: {}", join_errors(.errors))]
+    InvalidToolOutput {
+        tool: String,
+        errors: Vec<SchemaError>,
+    },
+
     #[error("Other error: {0}")]
     Other(String),
 
@@ -41,3 +85,32 @@ pub enum AgentError {
 }
 
 pub type Result<T> = std::result::Result<T, AgentError>;
+
+fn join_errors(errors: &[SchemaError]) -> String {
+    errors
+        .iter()
+        .map(ToString::to_string)
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+impl AgentError {
+    /// Returns true for failures that may clear up on a retry, such as an
+    /// unreachable backend, as opposed to ones the same request would hit
+    /// again, such as a record that doesn't serialize or a version conflict.
+    ///
+    /// Backends report most failures as `MemoryError`, so those count as
+    /// transient.
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            AgentError::ModelError(_)
+                | AgentError::MemoryError(_)
+                | AgentError::IoError(_)
+                | AgentError::UtcpError(_)
+                | AgentError::DeadlineExceeded(_)
+                | AgentError::ToolTimeout(_)
+                | AgentError::Other(_)
+        )
+    }
+}
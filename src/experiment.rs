@@ -0,0 +1,289 @@
+//! A/B experiments over prompts, models, and retrieval settings.
+//!
+//! An [`Experiment`] holds weighted [`ExperimentArm`]s. Sessions are assigned
+//! to an arm deterministically, so a conversation stays in the same arm across
+//! turns and restarts. Responses produced under an experiment are tagged with
+//! the experiment and arm names, and metrics reported by an evaluation harness
+//! are aggregated per arm for comparison.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::{AgentError, Result};
+use crate::models::LLM;
+use crate::prompts::{weighted_pick, PromptVersion};
+
+/// Metadata key holding the experiment that produced a response
+pub const EXPERIMENT_KEY: &str = "experiment";
+/// Metadata key holding the experiment arm that produced a response
+pub const EXPERIMENT_ARM_KEY: &str = "experiment_arm";
+
+/// One treatment in an experiment.
+///
+/// Unset fields fall back to the agent's own configuration, so an arm with
+/// nothing overridden acts as the control.
+#[derive(Clone)]
+pub struct ExperimentArm {
+    pub name: String,
+    /// Relative share of sessions routed to this arm; 0 disables it
+    pub weight: u32,
+    /// System prompt served instead of the agent's prompt or registry selection
+    pub prompt: Option<PromptVersion>,
+    /// Model called instead of the agent's primary model
+    pub model: Option<Arc<dyn LLM>>,
+    /// Token budget for retrieved history instead of the agent's context limit
+    pub context_limit: Option<usize>,
+}
+
+impl ExperimentArm {
+    /// Creates an arm with weight 1 and no overrides
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            weight: 1,
+            prompt: None,
+            model: None,
+            context_limit: None,
+        }
+    }
+
+    /// Sets the routing weight
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    /// Serves a specific prompt version in this arm
+    pub fn with_prompt(mut self, prompt: PromptVersion) -> Self {
+        self.prompt = Some(prompt);
+        self
+    }
+
+    /// Routes this arm's generations to `model`
+    pub fn with_model(mut self, model: Arc<dyn LLM>) -> Self {
+        self.model = Some(model);
+        self
+    }
+
+    /// Overrides the retrieval context budget
+    pub fn with_context_limit(mut self, context_limit: usize) -> Self {
+        self.context_limit = Some(context_limit);
+        self
+    }
+}
+
+impl std::fmt::Debug for ExperimentArm {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExperimentArm")
+            .field("name", &self.name)
+            .field("weight", &self.weight)
+            .field("prompt", &self.prompt)
+            .field("model", &self.model.as_ref().map(|m| m.model_name()))
+            .field("context_limit", &self.context_limit)
+            .finish()
+    }
+}
+
+/// Running aggregate of one metric
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MetricSummary {
+    pub count: u64,
+    pub sum: f64,
+    pub min: f64,
+    pub max: f64,
+}
+
+impl MetricSummary {
+    fn new(value: f64) -> Self {
+        Self {
+            count: 1,
+            sum: value,
+            min: value,
+            max: value,
+        }
+    }
+
+    fn add(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+
+    /// Arithmetic mean of the recorded values
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 {
+            0.0
+        } else {
+            self.sum / self.count as f64
+        }
+    }
+}
+
+/// Everything recorded for one arm
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ArmResults {
+    /// Responses generated under this arm
+    pub responses: u64,
+    pub metrics: HashMap<String, MetricSummary>,
+}
+
+/// Weighted set of arms with deterministic session assignment
+pub struct Experiment {
+    name: String,
+    arms: Vec<ExperimentArm>,
+    results: parking_lot::RwLock<HashMap<String, ArmResults>>,
+}
+
+impl Experiment {
+    /// Creates an experiment with no arms
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            arms: Vec::new(),
+            results: parking_lot::RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Adds an arm, replacing any existing arm with the same name
+    pub fn with_arm(mut self, arm: ExperimentArm) -> Self {
+        self.arms.retain(|a| a.name != arm.name);
+        self.arms.push(arm);
+        self
+    }
+
+    /// Returns the experiment name
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Returns all arms in registration order
+    pub fn arms(&self) -> &[ExperimentArm] {
+        &self.arms
+    }
+
+    /// Looks up an arm by name
+    pub fn arm(&self, name: &str) -> Option<&ExperimentArm> {
+        self.arms.iter().find(|a| a.name == name)
+    }
+
+    /// Assigns `session_id` to an arm.
+    ///
+    /// The choice is weighted and depends only on the experiment name, the
+    /// session id, and the arm set, so it is stable across processes.
+    pub fn assign(&self, session_id: &str) -> Option<&ExperimentArm> {
+        weighted_pick(
+            &self.arms,
+            |a| a.weight,
+            &format!("{}:{}", self.name, session_id),
+        )
+    }
+
+    /// Attribution metadata recorded alongside responses from `arm`
+    pub fn metadata(&self, arm: &ExperimentArm) -> HashMap<String, String> {
+        HashMap::from([
+            (EXPERIMENT_KEY.to_string(), self.name.clone()),
+            (EXPERIMENT_ARM_KEY.to_string(), arm.name.clone()),
+        ])
+    }
+
+    /// Counts a response generated under `arm`
+    pub fn record_response(&self, arm: &str) {
+        self.results
+            .write()
+            .entry(arm.to_string())
+            .or_default()
+            .responses += 1;
+    }
+
+    /// Adds a metric observation to `arm`
+    pub fn record(&self, arm: &str, metric: &str, value: f64) -> Result<()> {
+        if self.arm(arm).is_none() {
+            return Err(AgentError::ConfigError(format!(
+                "Experiment {} has no arm {}",
+                self.name, arm
+            )));
+        }
+
+        let mut results = self.results.write();
+        results
+            .entry(arm.to_string())
+            .or_default()
+            .metrics
+            .entry(metric.to_string())
+            .and_modify(|summary| summary.add(value))
+            .or_insert_with(|| MetricSummary::new(value));
+        Ok(())
+    }
+
+    /// Adds a metric observation to whichever arm serves `session_id`
+    pub fn record_for_session(&self, session_id: &str, metric: &str, value: f64) -> Result<()> {
+        let arm = self.assign(session_id).ok_or_else(|| {
+            AgentError::ConfigError(format!("Experiment {} has no active arms", self.name))
+        })?;
+        self.record(&arm.name, metric, value)
+    }
+
+    /// Returns the aggregated results keyed by arm name
+    pub fn results(&self) -> HashMap<String, ArmResults> {
+        self.results.read().clone()
+    }
+
+    /// Discards everything recorded so far
+    pub fn reset(&self) {
+        self.results.write().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn experiment() -> Experiment {
+        Experiment::new("tone")
+            .with_arm(ExperimentArm::new("control"))
+            .with_arm(ExperimentArm::new("short").with_context_limit(512))
+    }
+
+    #[test]
+    fn test_assignment_is_sticky_and_weighted() {
+        let experiment = experiment();
+        let first = experiment.assign("session-1").unwrap().name.clone();
+        assert_eq!(experiment.assign("session-1").unwrap().name, first);
+
+        let arms: std::collections::HashSet<String> = (0..50)
+            .filter_map(|i| experiment.assign(&format!("session-{}", i)))
+            .map(|a| a.name.clone())
+            .collect();
+        assert_eq!(arms.len(), 2);
+
+        let experiment = experiment.with_arm(ExperimentArm::new("short").with_weight(0));
+        for i in 0..20 {
+            let arm = experiment.assign(&format!("session-{}", i)).unwrap();
+            assert_eq!(arm.name, "control");
+        }
+    }
+
+    #[test]
+    fn test_metrics_aggregate_per_arm() {
+        let experiment = experiment();
+        experiment.record("control", "score", 1.0).unwrap();
+        experiment.record("control", "score", 0.0).unwrap();
+        experiment.record("short", "score", 0.5).unwrap();
+        experiment.record_response("control");
+        assert!(experiment.record("missing", "score", 1.0).is_err());
+
+        let results = experiment.results();
+        let control = &results["control"];
+        assert_eq!(control.responses, 1);
+        assert_eq!(control.metrics["score"].count, 2);
+        assert_eq!(control.metrics["score"].mean(), 0.5);
+        assert_eq!(control.metrics["score"].max, 1.0);
+        assert_eq!(results["short"].metrics["score"].mean(), 0.5);
+
+        experiment.reset();
+        assert!(experiment.results().is_empty());
+    }
+}
@@ -0,0 +1,164 @@
+//! Fluent construction of multi-agent plans.
+//!
+//! Common topologies (pipelines, fan-out to several sub-agents, a judge
+//! combining their answers) take a line each:
+//!
+//! ```text
+//! flow().step("researcher").parallel(["optimist", "skeptic"]).reduce("judge")
+//! ```
+//!
+//! [`Flow::compile`] turns this into an [`ExecutionPlan`] of sub-agent
+//! steps, wiring each stage's input to the outputs of the stage before it,
+//! so it can be checked, shown for approval and run with
+//! [`Agent::run_plan`](crate::Agent::run_plan) like any other plan.
+
+use crate::error::{AgentError, Result};
+use crate::plan::{join_labeled, ExecutionPlan, PlanStep, PLAN_INPUT};
+
+/// Starts an empty [`Flow`]
+pub fn flow() -> Flow {
+    Flow::new()
+}
+
+/// Stages of sub-agents, each run on the outputs of the stage before
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Flow {
+    stages: Vec<Vec<String>>,
+}
+
+impl Flow {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Runs sub-agent `name` on the output of the previous stage, or on the
+    /// flow's input if it comes first
+    pub fn step(mut self, name: impl Into<String>) -> Self {
+        self.stages.push(vec![name.into()]);
+        self
+    }
+
+    /// Runs the sub-agents `names` concurrently, each on the output of the
+    /// previous stage; a name may repeat to sample one agent several times
+    pub fn parallel<I, S>(mut self, names: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.stages
+            .push(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Runs sub-agent `name` on every output of the previous stage, each
+    /// labeled with the agent that produced it.
+    ///
+    /// The same as [`Flow::step`], which also reads all outputs of a
+    /// parallel stage; this name says what the step is for.
+    pub fn reduce(self, name: impl Into<String>) -> Self {
+        self.step(name)
+    }
+
+    /// Compiles the flow into a plan for `goal`.
+    ///
+    /// Step ids are the stage number and agent name, e.g. `2.skeptic`.
+    /// Fails with `AgentError::InvalidState` if the flow or one of its
+    /// parallel stages is empty.
+    pub fn compile(&self, goal: impl Into<String>) -> Result<ExecutionPlan> {
+        if self.stages.is_empty() || self.stages.iter().any(Vec::is_empty) {
+            return Err(AgentError::InvalidState(
+                "Flow has an empty stage".to_string(),
+            ));
+        }
+        let mut plan = ExecutionPlan::new(goal);
+        // (agent name, step id) of the previous stage
+        let mut previous: Vec<(String, String)> = Vec::new();
+        for (index, stage) in self.stages.iter().enumerate() {
+            let input = match previous.as_slice() {
+                [] => placeholder(PLAN_INPUT),
+                [(_, id)] => placeholder(id),
+                many => {
+                    let placeholders: Vec<(&str, String)> = many
+                        .iter()
+                        .map(|(name, id)| (name.as_str(), placeholder(id)))
+                        .collect();
+                    join_labeled(placeholders.iter().map(|(name, p)| (*name, p.as_str())))
+                }
+            };
+            let mut current: Vec<(String, String)> = Vec::new();
+            for name in stage {
+                let mut id = format!("{}.{}", index + 1, name);
+                let mut copy = 1;
+                while current.iter().any(|(_, other)| *other == id) {
+                    copy += 1;
+                    id = format!("{}.{}#{}", index + 1, name, copy);
+                }
+                let step = previous.iter().fold(
+                    PlanStep::subagent(id.clone(), name.clone())
+                        .with_argument(PLAN_INPUT, input.clone()),
+                    |step, (_, dependency)| step.after(dependency.clone()),
+                );
+                plan = plan.with_step(step);
+                current.push((name.clone(), id));
+            }
+            previous = current;
+        }
+        plan.validate()?;
+        Ok(plan)
+    }
+}
+
+fn placeholder(id: &str) -> String {
+    format!("{{{{{}}}}}", id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flow_compiles_stages_into_dependent_steps() {
+        let plan = flow()
+            .step("researcher")
+            .parallel(["writer", "writer"])
+            .reduce("judge")
+            .compile("Write it twice, keep the best")
+            .unwrap();
+
+        let stages: Vec<Vec<&str>> = plan
+            .stages()
+            .unwrap()
+            .iter()
+            .map(|stage| stage.iter().map(|step| step.id.as_str()).collect())
+            .collect();
+        assert_eq!(
+            stages,
+            [
+                vec!["1.researcher"],
+                vec!["2.writer", "2.writer#2"],
+                vec!["3.judge"]
+            ]
+        );
+        assert_eq!(
+            plan.step("1.researcher").unwrap().arguments[PLAN_INPUT],
+            "{{input}}"
+        );
+        assert_eq!(
+            plan.step("2.writer#2").unwrap().arguments[PLAN_INPUT],
+            "{{1.researcher}}"
+        );
+        let judge = plan.step("3.judge").unwrap();
+        assert_eq!(judge.depends_on, ["2.writer", "2.writer#2"]);
+        assert_eq!(
+            judge.arguments[PLAN_INPUT],
+            "writer:\n{{2.writer}}\n\nwriter:\n{{2.writer#2}}"
+        );
+
+        assert!(flow().compile("nothing").is_err());
+        assert!(flow()
+            .step("a")
+            .parallel(Vec::<String>::new())
+            .compile("gap")
+            .is_err());
+    }
+}
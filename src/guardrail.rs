@@ -0,0 +1,265 @@
+//! Guardrails for streamed replies.
+//!
+//! A streamed reply reaches the user chunk by chunk, so checking it once it
+//! is complete comes too late. A [`StreamGuard`] scans the stream as it
+//! arrives and holds back its trailing `window` bytes, where a match may
+//! still be completing, so text is only released once its regex rules have
+//! seen everything a match could span. With a [`Moderator`], text is also
+//! moderated before release, in steps of `moderation_interval` bytes. On a
+//! violation the agent stops reading from the model, which aborts
+//! generation, and ends the stream with a redaction message; the session
+//! remembers that message, not the output.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use regex::Regex;
+
+use crate::error::{AgentError, Result};
+
+/// Metadata key naming the rule a redacted reply violated
+pub const GUARDRAIL_VIOLATION_KEY: &str = "guardrail_violation";
+
+/// Message that replaces a stopped reply unless configured otherwise
+pub const DEFAULT_REDACTION: &str =
+    "[This response was stopped because it violated a content policy.]";
+
+/// Content check run on the streamed reply, e.g. a moderation endpoint or
+/// classifier model
+#[async_trait]
+pub trait Moderator: Send + Sync {
+    /// Returns the name of the policy `text` violates, if any
+    async fn moderate(&self, text: &str) -> Result<Option<String>>;
+}
+
+/// Rules a streamed reply must keep to
+pub struct StreamGuard {
+    rules: Vec<(String, Regex)>,
+    moderator: Option<Arc<dyn Moderator>>,
+    window: usize,
+    moderation_interval: usize,
+    redaction: String,
+}
+
+impl Default for StreamGuard {
+    fn default() -> Self {
+        Self {
+            rules: Vec::new(),
+            moderator: None,
+            window: 512,
+            moderation_interval: 256,
+            redaction: DEFAULT_REDACTION.to_string(),
+        }
+    }
+}
+
+impl StreamGuard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops the reply when `pattern` matches; `name` is recorded under
+    /// [`GUARDRAIL_VIOLATION_KEY`]. Fails if `pattern` isn't a valid regex.
+    pub fn with_pattern(mut self, name: impl Into<String>, pattern: &str) -> Result<Self> {
+        let regex = Regex::new(pattern)
+            .map_err(|e| AgentError::ConfigError(format!("Invalid guardrail pattern: {}", e)))?;
+        self.rules.push((name.into(), regex));
+        Ok(self)
+    }
+
+    /// Stops the reply when `moderator` flags it.
+    ///
+    /// Moderation errors are logged and the reply continues, so an outage
+    /// of the moderation service doesn't fail every stream.
+    pub fn with_moderator(mut self, moderator: Arc<dyn Moderator>) -> Self {
+        self.moderator = Some(moderator);
+        self
+    }
+
+    /// Sets how many trailing bytes of the reply are held back until more
+    /// arrives; it must cover the longest text a pattern can match. The
+    /// window before released text is also sent to the moderator as context.
+    pub fn with_window(mut self, bytes: usize) -> Self {
+        self.window = bytes;
+        self
+    }
+
+    /// Moderates and releases the reply each time this many bytes are
+    /// ready, trading latency for fewer moderation calls
+    pub fn with_moderation_interval(mut self, bytes: usize) -> Self {
+        self.moderation_interval = bytes.max(1);
+        self
+    }
+
+    /// Ends a stopped stream with `message` instead of [`DEFAULT_REDACTION`]
+    pub fn with_redaction(mut self, message: impl Into<String>) -> Self {
+        self.redaction = message.into();
+        self
+    }
+
+    /// Returns the message that replaces a stopped reply
+    pub fn redaction(&self) -> &str {
+        &self.redaction
+    }
+
+    /// Returns the first regex rule `text` violates
+    pub fn check(&self, text: &str) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|(_, regex)| regex.is_match(text))
+            .map(|(name, _)| name.as_str())
+    }
+
+    async fn moderate(&self, text: &str) -> Option<String> {
+        let moderator = self.moderator.as_ref()?;
+        match moderator.moderate(text).await {
+            Ok(violation) => violation,
+            Err(e) => {
+                tracing::warn!("Moderation failed, continuing the stream: {}", e);
+                None
+            }
+        }
+    }
+}
+
+/// What a [`StreamScan`] lets through after a chunk
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Scanned {
+    /// Text that passed every check and may be sent on, possibly empty
+    Release(String),
+    /// Name of the rule the reply violates
+    Violation(String),
+}
+
+/// Progress of a [`StreamGuard`] through one reply
+#[derive(Debug, Default)]
+pub(crate) struct StreamScan {
+    // Length of the reply when the regex rules last ran
+    checked: usize,
+    // Length of the reply already released
+    released: usize,
+}
+
+impl StreamScan {
+    /// Checks `reply` after a chunk arrived, releasing what no match can
+    /// reach any more
+    pub(crate) async fn scan(&mut self, guard: &StreamGuard, reply: &str) -> Scanned {
+        if let Some(rule) = self.check(guard, reply) {
+            return Scanned::Violation(rule);
+        }
+        let end = floor_char_boundary(reply, reply.len().saturating_sub(guard.window));
+        let ready = end.saturating_sub(self.released);
+        if ready == 0 || (guard.moderator.is_some() && ready < guard.moderation_interval) {
+            return Scanned::Release(String::new());
+        }
+        self.release(guard, reply, end).await
+    }
+
+    /// Releases the rest of `reply` once it is complete
+    pub(crate) async fn finish(&mut self, guard: &StreamGuard, reply: &str) -> Scanned {
+        if let Some(rule) = self.check(guard, reply) {
+            return Scanned::Violation(rule);
+        }
+        self.release(guard, reply, reply.len()).await
+    }
+
+    /// Runs the regex rules over what arrived since they last ran, plus the
+    /// window before it
+    fn check(&mut self, guard: &StreamGuard, reply: &str) -> Option<String> {
+        if self.checked == reply.len() {
+            return None;
+        }
+        let start = floor_char_boundary(reply, self.checked.saturating_sub(guard.window));
+        self.checked = reply.len();
+        guard.check(&reply[start..]).map(str::to_string)
+    }
+
+    /// Moderates `reply` up to `end` and releases it
+    async fn release(&mut self, guard: &StreamGuard, reply: &str, end: usize) -> Scanned {
+        let start = self.released;
+        if start == end {
+            return Scanned::Release(String::new());
+        }
+        let context = floor_char_boundary(reply, start.saturating_sub(guard.window));
+        if let Some(rule) = guard.moderate(&reply[context..end]).await {
+            return Scanned::Violation(rule);
+        }
+        self.released = end;
+        Scanned::Release(reply[start..end].to_string())
+    }
+}
+
+/// Returns `index`, moved back to the nearest char boundary of `text`
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct KeywordModerator;
+
+    #[async_trait]
+    impl Moderator for KeywordModerator {
+        async fn moderate(&self, text: &str) -> Result<Option<String>> {
+            Ok(text.contains("forbidden").then(|| "keyword".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scan_holds_back_text_a_match_may_still_reach() {
+        let guard = StreamGuard::new()
+            .with_pattern("card_number", r"\b(?:\d[ -]?){13,16}\b")
+            .unwrap()
+            .with_window(24);
+        assert!(StreamGuard::new().with_pattern("bad", "(").is_err());
+
+        let mut scan = StreamScan::default();
+        let mut reply = String::new();
+        let mut released = String::new();
+        let mut violation = None;
+        for chunk in ["Your card is 4111 11", "11 1111 1111 thanks"] {
+            reply.push_str(chunk);
+            match scan.scan(&guard, &reply).await {
+                Scanned::Release(text) => released.push_str(&text),
+                Scanned::Violation(rule) => {
+                    violation = Some(rule);
+                    break;
+                }
+            }
+        }
+        assert_eq!(violation.as_deref(), Some("card_number"));
+        // Nothing of the card number got out before it was complete
+        assert!(!released.contains('4'));
+    }
+
+    #[tokio::test]
+    async fn test_scan_moderates_before_release() {
+        let guard = StreamGuard::new()
+            .with_moderator(Arc::new(KeywordModerator))
+            .with_window(4)
+            .with_moderation_interval(1_000);
+
+        // Too little is ready to moderate, so nothing is released yet
+        let mut scan = StreamScan::default();
+        let reply = "This is forbidden.";
+        assert_eq!(
+            scan.scan(&guard, reply).await,
+            Scanned::Release(String::new())
+        );
+        assert_eq!(
+            scan.finish(&guard, reply).await,
+            Scanned::Violation("keyword".to_string())
+        );
+
+        let mut scan = StreamScan::default();
+        assert_eq!(
+            scan.finish(&guard, "All fine.").await,
+            Scanned::Release("All fine.".to_string())
+        );
+    }
+}
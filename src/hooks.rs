@@ -0,0 +1,71 @@
+//! Lifecycle hooks for the agent.
+//!
+//! A [`Hooks`] implementation registered with `Agent::with_hooks` sees the
+//! prompt before it goes to the model, the model's response, every tool
+//! call and result, and every record written to memory. Each method can
+//! inspect or rewrite what it is given, for logging, redaction or prompt
+//! tweaks, and can return an error to stop the operation, for policy
+//! enforcement. Every method does nothing by default, so an implementation
+//! only overrides what it needs. Several hooks run in registration order.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+
+use crate::error::Result;
+use crate::memory::MemoryRecord;
+use crate::types::{GenerationResponse, Message, ToolResponse};
+
+/// Callbacks at each step of a turn
+#[async_trait]
+pub trait Hooks: Send + Sync {
+    /// Called with the finished prompt just before the model call
+    async fn on_prompt_built(&self, session_id: &str, messages: &mut Vec<Message>) -> Result<()> {
+        let _ = (session_id, messages);
+        Ok(())
+    }
+
+    /// Called with the model's response before it is stored or returned.
+    ///
+    /// For streamed replies the client already has the chunks, so changes
+    /// only reach memory.
+    async fn on_llm_response(
+        &self,
+        session_id: &str,
+        response: &mut GenerationResponse,
+    ) -> Result<()> {
+        let _ = (session_id, response);
+        Ok(())
+    }
+
+    /// Called before `Agent::invoke_tool` runs a tool; an error skips the
+    /// call
+    async fn before_tool_call(
+        &self,
+        session_id: &str,
+        tool_name: &str,
+        arguments: &mut HashMap<String, serde_json::Value>,
+    ) -> Result<()> {
+        let _ = (session_id, tool_name, arguments);
+        Ok(())
+    }
+
+    /// Called with a tool's result before it is screened, stored and
+    /// returned
+    async fn after_tool_call(
+        &self,
+        session_id: &str,
+        tool_name: &str,
+        response: &mut ToolResponse,
+    ) -> Result<()> {
+        let _ = (session_id, tool_name, response);
+        Ok(())
+    }
+
+    /// Called before a record is embedded and stored; an error keeps it
+    /// out of memory
+    async fn on_memory_store(&self, record: &mut MemoryRecord) -> Result<()> {
+        let _ = record;
+        Ok(())
+    }
+}
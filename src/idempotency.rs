@@ -0,0 +1,293 @@
+//! Idempotency keys for turns.
+//!
+//! Upstream services retry HTTP requests that timed out or failed in
+//! transit, and a retried turn would store the user input twice and run its
+//! tools again. A turn given `GenerateOptions::idempotency_key` runs at most
+//! once per session and key: a replay gets the original response, marked
+//! with [`IDEMPOTENT_REPLAY_KEY`], and a replay that arrives while the
+//! original is still running waits for it. A turn that failed or was
+//! cancelled may have stored its input or run tools already, so its key is
+//! spent as well and replays get an error naming what happened;
+//! `Agent::clear_idempotency_keys` lets them run again. Reusing a key for
+//! a different request is refused. Keys are kept in process memory for the
+//! configured window, and never forgotten while their turn is running.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use sha2::{Digest, Sha256};
+use tokio::sync::watch;
+
+use crate::error::{AgentError, Result};
+use crate::types::GenerationResponse;
+
+/// Response metadata key set to `"true"` on a replayed response
+pub const IDEMPOTENT_REPLAY_KEY: &str = "idempotent_replay";
+
+/// How long keys are remembered unless configured otherwise
+pub const DEFAULT_IDEMPOTENCY_WINDOW: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Finished keys remembered at most; the oldest are forgotten first
+const MAX_KEYS: usize = 10_000;
+
+/// Where the turn of a key is
+#[derive(Clone)]
+enum Outcome {
+    Running,
+    Done(GenerationResponse),
+    Failed(String),
+}
+
+struct Entry {
+    created: Instant,
+    request: [u8; 32],
+    outcome: Arc<watch::Sender<Outcome>>,
+}
+
+impl Entry {
+    fn running(&self) -> bool {
+        matches!(*self.outcome.borrow(), Outcome::Running)
+    }
+}
+
+/// Marks the turn failed if it is dropped before it finishes
+struct Running(Arc<watch::Sender<Outcome>>);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        self.0.send_if_modified(|outcome| match outcome {
+            Outcome::Running => {
+                *outcome = Outcome::Failed("cancelled before it finished".to_string());
+                true
+            }
+            _ => false,
+        });
+    }
+}
+
+/// Responses of keyed turns by session and key
+pub(crate) struct IdempotencyCache {
+    window: Duration,
+    entries: parking_lot::Mutex<HashMap<(String, String), Entry>>,
+}
+
+impl Default for IdempotencyCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_IDEMPOTENCY_WINDOW)
+    }
+}
+
+impl IdempotencyCache {
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            entries: parking_lot::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs `turn` unless `key` was already used in `session_id`, in which
+    /// case the earlier outcome is returned instead. `request` identifies
+    /// what the turn was asked; reusing the key for another request fails.
+    pub(crate) async fn run<F>(
+        &self,
+        session_id: &str,
+        key: &str,
+        request: &str,
+        turn: F,
+    ) -> Result<GenerationResponse>
+    where
+        F: std::future::Future<Output = Result<GenerationResponse>>,
+    {
+        let request: [u8; 32] = Sha256::digest(request.as_bytes()).into();
+        let mut outcome = match self.claim(session_id, key, request)? {
+            Ok(running) => {
+                let result = turn.await;
+                running.0.send_replace(match &result {
+                    Ok(response) => Outcome::Done(response.clone()),
+                    Err(e) => Outcome::Failed(e.to_string()),
+                });
+                return result;
+            }
+            Err(outcome) => outcome,
+        };
+        let outcome = outcome
+            .wait_for(|outcome| !matches!(outcome, Outcome::Running))
+            .await
+            .map(|outcome| (*outcome).clone())
+            .unwrap_or_else(|_| Outcome::Failed("forgotten before it finished".to_string()));
+        match outcome {
+            Outcome::Done(mut response) => {
+                response
+                    .metadata
+                    .get_or_insert_with(HashMap::new)
+                    .insert(IDEMPOTENT_REPLAY_KEY.to_string(), "true".to_string());
+                Ok(response)
+            }
+            Outcome::Failed(error) => Err(AgentError::InvalidState(format!(
+                "Turn with idempotency key {} failed: {}",
+                key, error
+            ))),
+            Outcome::Running => unreachable!("waited for the turn to finish"),
+        }
+    }
+
+    /// Claims `key` for a new turn, or returns the outcome of the turn that
+    /// already claimed it
+    fn claim(
+        &self,
+        session_id: &str,
+        key: &str,
+        request: [u8; 32],
+    ) -> Result<std::result::Result<Running, watch::Receiver<Outcome>>> {
+        let mut entries = self.entries.lock();
+        let now = Instant::now();
+        entries
+            .retain(|_, entry| entry.running() || now.duration_since(entry.created) < self.window);
+
+        let id = (session_id.to_string(), key.to_string());
+        if let Some(entry) = entries.get(&id) {
+            if entry.request != request {
+                return Err(AgentError::InvalidState(format!(
+                    "Idempotency key {} was already used for a different request",
+                    key
+                )));
+            }
+            return Ok(Err(entry.outcome.subscribe()));
+        }
+
+        let finished = entries.values().filter(|entry| !entry.running()).count();
+        if finished >= MAX_KEYS {
+            let oldest = entries
+                .iter()
+                .filter(|(_, entry)| !entry.running())
+                .min_by_key(|(_, entry)| entry.created)
+                .map(|(id, _)| id.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        let outcome = Arc::new(watch::channel(Outcome::Running).0);
+        entries.insert(
+            id,
+            Entry {
+                created: now,
+                request,
+                outcome: Arc::clone(&outcome),
+            },
+        );
+        Ok(Ok(Running(outcome)))
+    }
+
+    /// Forgets every key of `session_id`
+    pub(crate) fn clear(&self, session_id: &str) {
+        self.entries
+            .lock()
+            .retain(|(session, _), _| session != session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AgentError;
+
+    fn reply(content: &str) -> Result<GenerationResponse> {
+        Ok(GenerationResponse {
+            content: content.to_string(),
+            metadata: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_keys_replay_the_first_outcome() {
+        let cache = IdempotencyCache::default();
+        let first = cache
+            .run("s", "k", "hi", async { reply("first") })
+            .await
+            .unwrap();
+        assert_eq!(first.content, "first");
+        assert!(first.metadata.is_none());
+
+        let replay = cache
+            .run("s", "k", "hi", async { reply("second") })
+            .await
+            .unwrap();
+        assert_eq!(replay.content, "first");
+        assert_eq!(replay.metadata.unwrap()[IDEMPOTENT_REPLAY_KEY], "true");
+
+        // A key names one request
+        let reused = cache.run("s", "k", "bye", async { reply("bye") }).await;
+        assert!(matches!(reused, Err(AgentError::InvalidState(_))));
+
+        // Keys are scoped to their session
+        let other = cache
+            .run("t", "k", "hi", async { reply("other") })
+            .await
+            .unwrap();
+        assert_eq!(other.content, "other");
+
+        cache.clear("s");
+        let rerun = cache
+            .run("s", "k", "hi", async { reply("again") })
+            .await
+            .unwrap();
+        assert_eq!(rerun.content, "again");
+    }
+
+    #[tokio::test]
+    async fn test_failed_and_cancelled_turns_spend_their_key() {
+        let cache = IdempotencyCache::default();
+        let failed = cache
+            .run("s", "failed", "hi", async {
+                Err(AgentError::ModelError("down".to_string()))
+            })
+            .await;
+        assert!(matches!(failed, Err(AgentError::ModelError(_))));
+        let replay = cache
+            .run("s", "failed", "hi", async { reply("ran again") })
+            .await;
+        assert!(matches!(replay, Err(AgentError::InvalidState(e)) if e.contains("down")));
+
+        let cancelled = tokio::time::timeout(
+            Duration::from_millis(10),
+            cache.run("s", "cancelled", "hi", std::future::pending()),
+        )
+        .await;
+        assert!(cancelled.is_err());
+        let replay = cache
+            .run("s", "cancelled", "hi", async { reply("ran again") })
+            .await;
+        assert!(matches!(replay, Err(AgentError::InvalidState(e)) if e.contains("cancelled")));
+    }
+
+    #[tokio::test]
+    async fn test_running_keys_outlive_the_window() {
+        let cache = Arc::new(IdempotencyCache::new(Duration::ZERO));
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let running = tokio::spawn({
+            let cache = Arc::clone(&cache);
+            async move {
+                cache
+                    .run("s", "k", "hi", async {
+                        released.await.ok();
+                        reply("first")
+                    })
+                    .await
+            }
+        });
+        while cache.entries.lock().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        // Claiming another key expires finished keys but not running ones
+        cache
+            .run("s", "other", "hi", async { reply("other") })
+            .await
+            .unwrap();
+        let id = ("s".to_string(), "k".to_string());
+        assert!(cache.entries.lock().contains_key(&id));
+        release.send(()).unwrap();
+        assert_eq!(running.await.unwrap().unwrap().content, "first");
+    }
+}
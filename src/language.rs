@@ -0,0 +1,285 @@
+//! Response language detection and enforcement.
+//!
+//! Smaller models often drift into English when the conversation is in
+//! another language. [`detect_language`] guesses the language of a text
+//! from its script and, for Latin-script text, from common function words;
+//! a [`LanguagePolicy`] set with `Agent::with_language_policy` uses it to
+//! notice replies in the wrong language and ask the model once more.
+
+use std::cmp::Reverse;
+
+/// Languages told apart by common function words, with those words
+const STOPWORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "is", "are", "of", "to", "in", "that", "it", "you", "for", "with",
+            "this", "was", "have", "not", "what", "be",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "la", "los", "las", "de", "que", "y", "en", "es", "por", "para", "con", "una",
+            "no", "se", "del", "como", "pero",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "la", "les", "de", "des", "et", "est", "que", "un", "une", "pour", "dans", "pas",
+            "vous", "je", "ce", "avec", "sur",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "das", "und", "ist", "nicht", "ich", "sie", "mit", "ein", "eine", "zu",
+            "den", "auf", "für", "sich", "auch", "wie",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "la", "che", "di", "e", "è", "un", "una", "per", "non", "sono", "con", "del",
+            "gli", "della", "come", "anche", "ma",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "o", "a", "os", "as", "de", "que", "e", "é", "um", "uma", "para", "com", "não", "do",
+            "da", "em", "você", "mas",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "de", "het", "een", "en", "van", "is", "niet", "dat", "ik", "je", "met", "voor", "op",
+            "zijn", "ook", "maar", "wat", "er",
+        ],
+    ),
+    (
+        "pl",
+        &[
+            "i", "w", "nie", "na", "się", "że", "jest", "to", "z", "do", "jak", "co", "ale", "czy",
+            "dla", "jestem", "tak", "są",
+        ],
+    ),
+];
+
+/// Function-word hits a Latin-script text needs before it is classified
+const MIN_STOPWORD_HITS: usize = 2;
+
+/// Returns the ISO 639-1 code of the language `text` is most likely in, or
+/// `None` if it is too short or ambiguous to tell.
+///
+/// Fenced code blocks are ignored. Non-Latin scripts are recognized by
+/// script alone (Cyrillic as `ru`, or `uk` with Ukrainian letters; Han
+/// without kana as `zh`); Latin-script text is scored against English,
+/// Spanish, French, German, Italian, Portuguese, Dutch and Polish.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    let prose = strip_code_blocks(text);
+
+    let mut scripts: [usize; 11] = [0; 11];
+    for c in prose.chars().filter(|c| c.is_alphabetic()) {
+        let script = match c as u32 {
+            0x0400..=0x04FF => 1,
+            0x0370..=0x03FF => 2,
+            0x0600..=0x06FF => 3,
+            0x0590..=0x05FF => 4,
+            0xAC00..=0xD7AF | 0x1100..=0x11FF => 5,
+            0x3040..=0x30FF => 6,
+            0x4E00..=0x9FFF => 7,
+            0x0E00..=0x0E7F => 8,
+            0x0900..=0x097F => 9,
+            _ if c.is_ascii_alphabetic() || ('\u{00C0}'..='\u{024F}').contains(&c) => 0,
+            _ => 10,
+        };
+        scripts[script] += 1;
+    }
+    let (script, count) = scripts
+        .iter()
+        .enumerate()
+        .take(10)
+        .max_by_key(|(_, count)| **count)?;
+    if *count == 0 {
+        return None;
+    }
+
+    match script {
+        0 => detect_latin(&prose),
+        1 if prose.chars().any(|c| "іїєґІЇЄҐ".contains(c)) => Some("uk"),
+        1 => Some("ru"),
+        2 => Some("el"),
+        3 => Some("ar"),
+        4 => Some("he"),
+        5 => Some("ko"),
+        // Japanese mixes kana with Han characters
+        6 => Some("ja"),
+        7 if scripts[6] > 0 => Some("ja"),
+        7 => Some("zh"),
+        8 => Some("th"),
+        _ => Some("hi"),
+    }
+}
+
+/// Scores Latin-script `text` by the function words of each language
+fn detect_latin(text: &str) -> Option<&'static str> {
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+
+    let mut scores: Vec<(&'static str, usize)> = STOPWORDS
+        .iter()
+        .map(|(language, stopwords)| {
+            let hits = words
+                .iter()
+                .filter(|word| stopwords.contains(&word.as_str()))
+                .count();
+            (*language, hits)
+        })
+        .collect();
+    scores.sort_by_key(|&(_, hits)| Reverse(hits));
+
+    match scores.as_slice() {
+        [(language, best), (_, second), ..] if *best >= MIN_STOPWORD_HITS && best > second => {
+            Some(*language)
+        }
+        _ => None,
+    }
+}
+
+/// Drops the contents of ``` fenced blocks, which are code, not prose
+fn strip_code_blocks(text: &str) -> String {
+    text.split("```").step_by(2).collect::<Vec<_>>().join(" ")
+}
+
+/// Returns the English name of the language with ISO 639-1 `code`, or the
+/// code itself if it isn't known
+pub fn language_name(code: &str) -> &str {
+    match code {
+        "en" => "English",
+        "es" => "Spanish",
+        "fr" => "French",
+        "de" => "German",
+        "it" => "Italian",
+        "pt" => "Portuguese",
+        "nl" => "Dutch",
+        "pl" => "Polish",
+        "ru" => "Russian",
+        "uk" => "Ukrainian",
+        "el" => "Greek",
+        "ar" => "Arabic",
+        "he" => "Hebrew",
+        "ko" => "Korean",
+        "ja" => "Japanese",
+        "zh" => "Chinese",
+        "th" => "Thai",
+        "hi" => "Hindi",
+        _ => code,
+    }
+}
+
+/// Which language replies must be in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LanguagePolicy {
+    // ISO 639-1 code; `None` follows the user's language
+    locale: Option<String>,
+}
+
+impl LanguagePolicy {
+    /// Replies in the language of each user input, when it can be detected
+    pub fn match_user() -> Self {
+        Self { locale: None }
+    }
+
+    /// Always replies in `locale`, e.g. `"de"` or `"pt-BR"`; only the
+    /// language part of a locale is enforced
+    pub fn locale(locale: impl AsRef<str>) -> Self {
+        let language = locale
+            .as_ref()
+            .split(['-', '_'])
+            .next()
+            .unwrap_or_default()
+            .to_lowercase();
+        Self {
+            locale: Some(language),
+        }
+    }
+
+    /// Returns the language a reply to `input` must be in, if known
+    pub fn expected(&self, input: &str) -> Option<String> {
+        match &self.locale {
+            Some(locale) => Some(locale.clone()),
+            None => detect_language(input).map(str::to_string),
+        }
+    }
+
+    /// Returns the follow-up asking for `reply` again, if it is in a
+    /// different language than a reply to `input` must be in.
+    ///
+    /// Replies whose language can't be detected, such as code or very short
+    /// answers, are accepted.
+    pub fn correction(&self, input: &str, reply: &str) -> Option<String> {
+        let expected = self.expected(input)?;
+        let found = detect_language(reply)?;
+        if found == expected {
+            return None;
+        }
+        let name = language_name(&expected);
+        Some(format!(
+            "Your previous answer was not in {name}. Give the same answer again, written \
+             entirely in {name}."
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detects_scripts_and_latin_languages() {
+        assert_eq!(
+            detect_language("The cat is on the mat and it is happy"),
+            Some("en")
+        );
+        assert_eq!(
+            detect_language("¿Dónde está el baño? Es para mi hija"),
+            Some("es")
+        );
+        assert_eq!(detect_language("Ich weiß nicht, wie das geht"), Some("de"));
+        assert_eq!(
+            detect_language("Je ne sais pas, c'est pour vous"),
+            Some("fr")
+        );
+        assert_eq!(detect_language("Привет, как дела?"), Some("ru"));
+        assert_eq!(detect_language("東京は日本の首都です"), Some("ja"));
+        assert_eq!(detect_language("北京是中国的首都"), Some("zh"));
+        assert_eq!(detect_language("ok"), None);
+        assert_eq!(detect_language("```\nlet x = the_value;\n```"), None);
+    }
+
+    #[test]
+    fn test_policy_asks_for_the_expected_language() {
+        let policy = LanguagePolicy::match_user();
+        let question = "¿Cuál es la capital de Francia y por qué es famosa?";
+        assert!(policy
+            .correction(question, "La capital es París, que es famosa por la torre")
+            .is_none());
+        let correction = policy
+            .correction(
+                question,
+                "The capital is Paris and it is famous for the tower",
+            )
+            .unwrap();
+        assert!(correction.contains("Spanish"));
+        assert!(policy.correction("ok", "The answer is yes").is_none());
+
+        let german = LanguagePolicy::locale("de-AT");
+        assert_eq!(german.expected("hello"), Some("de".to_string()));
+    }
+}
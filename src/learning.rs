@@ -0,0 +1,463 @@
+//! Learning a user's lasting preferences from their sessions.
+//!
+//! [`PreferenceLearner`] periodically reads the sessions bound to each user
+//! (see `SessionMemory::bind_user`), asks a model which stable preferences
+//! and facts they reveal, and stores those with enough confidence in the
+//! user's long-term memory, where retrieval finds them in later sessions.
+//! Every learned fact records its kind, confidence and the sessions it came
+//! from, and [`PreferenceLearner::learned`] and
+//! [`PreferenceLearner::forget`] let the user or an operator review and
+//! remove them.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::error::Result;
+use crate::memory::{Embedder, MemoryFilter, MemoryRecord, SessionMemory};
+use crate::models::LLM;
+use crate::structured::{generate_structured, DEFAULT_STRUCTURED_RETRIES};
+
+/// Role of learned-fact records in a user's memory
+pub const LEARNED_ROLE: &str = "learned";
+/// Metadata key holding the [`FactKind`] of a learned fact
+pub const FACT_KIND_KEY: &str = "fact_kind";
+/// Metadata key holding the model's confidence in a learned fact
+pub const CONFIDENCE_KEY: &str = "confidence";
+/// Metadata key listing the comma-separated sessions a fact was learned from
+pub const LEARNED_FROM_KEY: &str = "learned_from";
+
+/// Learned facts listed for review at most
+const MAX_LEARNED_FACTS: usize = 1_000;
+
+const LEARNING_INSTRUCTIONS: &str = "You maintain a profile of a user from their \
+conversations with an assistant. Find stable preferences and lasting facts about the user \
+that would still hold in a future conversation, such as preferred units, languages, tools, \
+formats, or their role and location. Skip one-off requests and anything about the assistant. \
+Don't repeat facts already known. Give each a confidence between 0 and 1 and the ids of the \
+sessions that support it; reply with an empty list if there is nothing new.";
+
+/// What a learned fact is about
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum FactKind {
+    /// How the user likes things done, e.g. "Prefers metric units"
+    Preference,
+    /// Something true about the user, e.g. "Works as a nurse in Oslo"
+    Fact,
+}
+
+impl FactKind {
+    /// Returns the name stored under [`FACT_KIND_KEY`]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FactKind::Preference => "preference",
+            FactKind::Fact => "fact",
+        }
+    }
+}
+
+/// What the model reports for one analysis
+#[derive(Debug, Deserialize, JsonSchema)]
+struct Findings {
+    facts: Vec<Finding>,
+}
+
+#[derive(Debug, Deserialize, JsonSchema)]
+struct Finding {
+    kind: FactKind,
+    statement: String,
+    confidence: f32,
+    #[serde(default)]
+    sessions: Vec<String>,
+}
+
+/// A preference or fact learned about a user
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LearnedFact {
+    /// Id of the memory record holding the fact; pass it to
+    /// [`PreferenceLearner::forget`]
+    pub id: Uuid,
+    pub kind: FactKind,
+    pub statement: String,
+    pub confidence: f32,
+    /// Sessions the fact was learned from
+    pub sessions: Vec<String>,
+    pub learned_at: DateTime<Utc>,
+}
+
+impl LearnedFact {
+    /// Reads a learned fact back from its memory record
+    pub fn from_record(record: &MemoryRecord) -> Option<Self> {
+        if record.role != LEARNED_ROLE {
+            return None;
+        }
+        let metadata = record.metadata.as_ref();
+        let kind = match metadata
+            .and_then(|m| m.get(FACT_KIND_KEY))
+            .map(String::as_str)
+        {
+            Some("fact") => FactKind::Fact,
+            _ => FactKind::Preference,
+        };
+        let confidence = metadata
+            .and_then(|m| m.get(CONFIDENCE_KEY))
+            .and_then(|c| c.parse().ok())
+            .unwrap_or(record.importance);
+        let sessions = metadata
+            .and_then(|m| m.get(LEARNED_FROM_KEY))
+            .map(|sessions| sessions.split(',').map(str::to_string).collect())
+            .unwrap_or_default();
+        Some(Self {
+            id: record.id,
+            kind,
+            statement: record.content.clone(),
+            confidence,
+            sessions,
+            learned_at: record.timestamp,
+        })
+    }
+}
+
+/// Mines users' sessions for lasting preferences and facts
+pub struct PreferenceLearner {
+    memory: Arc<SessionMemory>,
+    model: Arc<dyn LLM>,
+    embedder: Option<Arc<dyn Embedder>>,
+    min_confidence: f32,
+    history_limit: usize,
+    retries: usize,
+    // Session -> timestamp of the newest record already analyzed
+    analyzed: parking_lot::Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+impl PreferenceLearner {
+    /// Learns from the sessions bound to users in `memory` with `model`
+    pub fn new(memory: Arc<SessionMemory>, model: Arc<dyn LLM>) -> Self {
+        Self {
+            memory,
+            model,
+            embedder: None,
+            min_confidence: 0.7,
+            history_limit: 50,
+            retries: DEFAULT_STRUCTURED_RETRIES,
+            analyzed: parking_lot::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Keeps only facts the model is at least this confident in
+    pub fn with_min_confidence(mut self, min_confidence: f32) -> Self {
+        self.min_confidence = min_confidence;
+        self
+    }
+
+    /// Reads at most this many of the newest records per session and run
+    pub fn with_history_limit(mut self, limit: usize) -> Self {
+        self.history_limit = limit;
+        self
+    }
+
+    /// Sets how many times an invalid analysis is sent back to the model
+    pub fn with_retries(mut self, retries: usize) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Embeds learned facts so retrieval can find them by similarity
+    pub fn with_embedder(mut self, embedder: Arc<dyn Embedder>) -> Self {
+        self.embedder = Some(embedder);
+        self
+    }
+
+    /// Analyzes what was said in `user_id`'s sessions since the last run and
+    /// stores the new facts found, returning them.
+    ///
+    /// Does nothing, without calling the model, if the sessions have no new
+    /// records.
+    pub async fn learn(&self, user_id: &str) -> Result<Vec<LearnedFact>> {
+        let mut transcript = String::new();
+        let mut newest = HashMap::new();
+        for session_id in self.memory.sessions_of(user_id) {
+            let since = self.analyzed.lock().get(&session_id).copied();
+            let page = self
+                .memory
+                .retrieve_page(&session_id, None, self.history_limit)
+                .await?;
+            let mut records: Vec<MemoryRecord> = page
+                .records
+                .into_iter()
+                .filter(|r| r.role == "user" || r.role == "assistant")
+                .filter(|r| since.is_none_or(|since| r.timestamp > since))
+                .collect();
+            let Some(latest) = records.iter().map(|r| r.timestamp).max() else {
+                continue;
+            };
+            records.sort_by_key(|r| r.timestamp);
+
+            transcript.push_str(&format!("Session {}:\n", session_id));
+            for record in &records {
+                transcript.push_str(&format!("{}: {}\n", record.role, record.content));
+            }
+            transcript.push('\n');
+            newest.insert(session_id, latest);
+        }
+        if newest.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let known = self.learned(user_id).await?;
+        let mut prompt = LEARNING_INSTRUCTIONS.to_string();
+        if !known.is_empty() {
+            prompt.push_str("\n\nAlready known:\n");
+            for fact in &known {
+                prompt.push_str(&format!("- {}\n", fact.statement));
+            }
+        }
+        let findings: Findings =
+            generate_structured(self.model.as_ref(), &prompt, &transcript, self.retries).await?;
+
+        let mut seen: HashSet<String> = known
+            .iter()
+            .map(|fact| fact.statement.trim().to_lowercase())
+            .collect();
+        let mut learned = Vec::new();
+        for finding in findings.facts {
+            let statement = finding.statement.trim();
+            if finding.confidence < self.min_confidence
+                || statement.is_empty()
+                || !seen.insert(statement.to_lowercase())
+            {
+                continue;
+            }
+            // Only attribute facts to sessions that were actually analyzed
+            let mut sessions: Vec<String> = finding
+                .sessions
+                .into_iter()
+                .filter(|session| newest.contains_key(session))
+                .collect();
+            if sessions.is_empty() {
+                sessions = newest.keys().cloned().collect();
+                sessions.sort();
+            }
+            learned.push(
+                self.store(
+                    user_id,
+                    finding.kind,
+                    statement,
+                    finding.confidence,
+                    sessions,
+                )
+                .await?,
+            );
+        }
+
+        self.analyzed.lock().extend(newest);
+        Ok(learned)
+    }
+
+    async fn store(
+        &self,
+        user_id: &str,
+        kind: FactKind,
+        statement: &str,
+        confidence: f32,
+        sessions: Vec<String>,
+    ) -> Result<LearnedFact> {
+        let embedding = match &self.embedder {
+            Some(embedder) => Some(embedder.embed(statement).await?),
+            None => None,
+        };
+        let metadata = HashMap::from([
+            (FACT_KIND_KEY.to_string(), kind.as_str().to_string()),
+            (CONFIDENCE_KEY.to_string(), confidence.to_string()),
+            (LEARNED_FROM_KEY.to_string(), sessions.join(",")),
+        ]);
+        let record = MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: sessions[0].clone(),
+            role: LEARNED_ROLE.to_string(),
+            content: statement.to_string(),
+            importance: confidence.clamp(0.0, 1.0),
+            timestamp: Utc::now(),
+            metadata: Some(metadata),
+            embedding,
+            version: 0,
+            expires_at: None,
+        };
+        let mut fact = LearnedFact::from_record(&record).expect("learned record");
+        fact.id = self.memory.store_for_user(user_id, record).await?;
+        Ok(fact)
+    }
+
+    /// Returns every fact learned about `user_id`, newest first
+    pub async fn learned(&self, user_id: &str) -> Result<Vec<LearnedFact>> {
+        let records = self
+            .memory
+            .retrieve_user(
+                user_id,
+                MAX_LEARNED_FACTS,
+                &MemoryFilter::new().with_role(LEARNED_ROLE),
+            )
+            .await?;
+        Ok(records
+            .iter()
+            .filter_map(LearnedFact::from_record)
+            .collect())
+    }
+
+    /// Removes the learned fact `id` of `user_id`, returning whether it was
+    /// found.
+    ///
+    /// The record expires at once, so reads stop returning it and the next
+    /// `purge_expired` deletes it from the store.
+    pub async fn forget(&self, user_id: &str, id: Uuid) -> Result<bool> {
+        let records = self
+            .memory
+            .retrieve_user(
+                user_id,
+                MAX_LEARNED_FACTS,
+                &MemoryFilter::new().with_role(LEARNED_ROLE),
+            )
+            .await?;
+        let Some(mut record) = records.into_iter().find(|r| r.id == id) else {
+            return Ok(false);
+        };
+        record.expires_at = Some(Utc::now());
+        self.memory.update(record).await?;
+        Ok(true)
+    }
+
+    /// Runs [`learn`](Self::learn) for every bound user each `interval`
+    /// until the returned task is dropped or the learner is.
+    pub fn spawn(self: &Arc<Self>, interval: Duration) -> LearningTask {
+        let learner = Arc::downgrade(self);
+        LearningTask {
+            handle: tokio::spawn(run(learner, interval)),
+        }
+    }
+}
+
+async fn run(learner: Weak<PreferenceLearner>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let Some(learner) = learner.upgrade() else {
+            return;
+        };
+        for user_id in learner.memory.users() {
+            match learner.learn(&user_id).await {
+                Ok(facts) if !facts.is_empty() => {
+                    tracing::info!(user_id = %user_id, learned = facts.len(), "Learned user facts");
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!(user_id = %user_id, "Preference learning failed: {}", e),
+            }
+        }
+    }
+}
+
+/// Background task running a [`PreferenceLearner`].
+///
+/// Stops when dropped.
+pub struct LearningTask {
+    handle: JoinHandle<()>,
+}
+
+impl Drop for LearningTask {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InMemoryStore;
+    use crate::types::{File, GenerationResponse, Message};
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Reports one confident preference and one guess
+    #[derive(Default)]
+    struct AnalystLLM {
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl LLM for AnalystLLM {
+        async fn generate(
+            &self,
+            messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<GenerationResponse> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            assert!(messages[1]
+                .content
+                .contains("Session s1:\nuser: Use Celsius"));
+            Ok(GenerationResponse {
+                content: r#"{"facts": [
+                    {"kind": "preference", "statement": "Prefers Celsius", "confidence": 0.9, "sessions": ["s1"]},
+                    {"kind": "fact", "statement": "Might own a cat", "confidence": 0.3}
+                ]}"#
+                .to_string(),
+                metadata: None,
+            })
+        }
+
+        fn model_name(&self) -> &str {
+            "analyst"
+        }
+    }
+
+    fn turn(session_id: &str, role: &str, content: &str) -> MemoryRecord {
+        MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: session_id.to_string(),
+            role: role.to_string(),
+            content: content.to_string(),
+            importance: 0.5,
+            timestamp: Utc::now(),
+            metadata: None,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_learns_reviews_and_forgets_facts() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 10));
+        memory.bind_user("s1", "alice");
+        memory.bind_user("s2", "alice");
+        memory
+            .store(turn("s1", "user", "Use Celsius, always."))
+            .await
+            .unwrap();
+        memory
+            .store(turn("s2", "user", "Any news on my order?"))
+            .await
+            .unwrap();
+        let model = Arc::new(AnalystLLM::default());
+        let learner = PreferenceLearner::new(memory, model.clone());
+
+        let learned = learner.learn("alice").await.unwrap();
+        assert_eq!(learned.len(), 1);
+        assert_eq!(learned[0].kind, FactKind::Preference);
+        assert_eq!(learned[0].sessions, ["s1"]);
+        assert_eq!(learner.learned("alice").await.unwrap(), learned);
+
+        // Nothing new was said, so the model isn't asked again
+        assert!(learner.learn("alice").await.unwrap().is_empty());
+        assert_eq!(model.calls.load(Ordering::SeqCst), 1);
+
+        assert!(learner.forget("alice", learned[0].id).await.unwrap());
+        assert!(learner.learned("alice").await.unwrap().is_empty());
+        assert!(!learner.forget("alice", learned[0].id).await.unwrap());
+    }
+}
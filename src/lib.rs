@@ -25,42 +25,154 @@
 pub mod agent;
 pub mod agent_orchestrators;
 pub mod agent_tool;
+pub mod aggregate;
+pub mod blob;
+pub mod cancel;
 pub mod catalog;
+pub mod checkpoint;
+pub mod context;
+pub mod cost;
 pub mod error;
+pub mod experiment;
+pub mod flow;
+pub mod guardrail;
 pub mod helpers;
+pub mod hooks;
+pub mod idempotency;
+pub mod language;
+pub mod learning;
+pub mod loaders;
+pub mod mcp;
 pub mod memory;
 pub mod models;
+pub mod plan;
+pub mod prompts;
 pub mod query;
+pub mod reload;
+pub mod routing;
+pub mod schema;
+pub mod secrets;
+pub mod server;
+pub mod structured;
+pub mod telemetry;
+pub mod tenant;
+pub mod testing;
 pub mod tools;
 pub mod types;
 pub mod utcp;
 
 // Re-export commonly used types
 pub use agent::Agent;
-pub use catalog::{StaticSubAgentDirectory, StaticToolCatalog};
+pub use aggregate::{
+    first_success, join_results, weighted_merge, weighted_merge_with_schema, SubAgentOutput,
+};
+pub use blob::{BlobOffload, BlobStore, FileBlobStore, InMemoryBlobStore};
+pub use cancel::CancellationToken;
+pub use catalog::{LockMetrics, StaticSubAgentDirectory, StaticToolCatalog};
+pub use checkpoint::{CheckpointInfo, CheckpointStore, FileCheckpointer, InMemoryCheckpointer};
+pub use context::{
+    ContextComposer, ContextRequest, ContextSection, ContextStrategy, ImportancePriority,
+    RelevanceRetrieval, SlidingWindow, SummarizeOlder,
+};
+pub use cost::{CostBudget, CostReport, CostSummary};
 pub use error::{AgentError, Result};
-pub use memory::{mmr_rerank, InMemoryStore, MemoryRecord, MemoryStore, SessionMemory};
-pub use models::LLM;
+pub use experiment::{Experiment, ExperimentArm};
+pub use flow::{flow, Flow};
+pub use guardrail::{Moderator, StreamGuard};
+pub use hooks::Hooks;
+pub use idempotency::IDEMPOTENT_REPLAY_KEY;
+pub use language::{detect_language, LanguagePolicy};
+pub use learning::{FactKind, LearnedFact, LearningTask, PreferenceLearner};
+pub use loaders::{Document, DocumentLoader, MarkdownLoader};
+pub use mcp::McpServer;
+pub use memory::{
+    label_key, labels_of, mmr_rerank, mmr_rerank_with, BufferConfig, BufferedStore, CacheConfig,
+    CacheStats, CachedStore, CohereReranker, DegradableStore, DegradationConfig, Embedder,
+    EmbeddingConfig, HeuristicScorer, HnswParams, ImageEmbedder, ImageMemory, ImageQuery,
+    ImportanceScorer, InMemoryStore, LlmReranker, LlmScorer, MemoryFilter, MemoryHealth,
+    MemoryPage, MemoryQuery, MemoryRecord, MemoryStore, MemoryTier, MmrConfig, PrefixedEmbedder,
+    PromotionRules, Reranker, SessionMemory, SimilarityMetric, VertexEmbedder, WalStore,
+};
+pub use models::{
+    AdaptiveThrottle, Capabilities, FileUploader, GenerationSettings, HeuristicTokenizer,
+    ModelPricing, ModelRoute, ModelRoutingPolicy, PricingRegistry, RateLimitInfo, Tokenizer,
+    TokenizerRegistry, UploadManager, UploadedFile, LLM, MODEL_ROUTE_KEY,
+};
+pub use plan::{ExecutionPlan, PlanStep, StepTarget, PLAN_INPUT};
+pub use prompts::{PromptRegistry, PromptVersion};
+pub use query::{RetrievalPlan, RetrievalPolicy};
+pub use reload::OptionsWatcher;
+pub use routing::{PathStats, RoutePath, RoutingEvent, RoutingReport};
 pub use rs_utcp::plugins::codemode::{CodeModeArgs, CodeModeUtcp, CodemodeOrchestrator};
-pub use tools::{Tool, ToolCatalog};
+pub use schema::{schema_errors, validate_schema, SchemaError};
+pub use secrets::{
+    resolve_utcp_auth, EnvSecrets, FileSecrets, SecretsChain, SecretsProvider, StaticSecrets,
+};
+pub use server::{spawn_reply_stream, DEFAULT_STREAM_BUFFER};
+pub use structured::{extract_json, generate_structured, parse_structured, schema_for};
+pub use tenant::TenantGuard;
+pub use testing::{ChaosConfig, ChaosLLM, ChaosStats, ChaosStore};
+pub use tools::{
+    arguments_hash, ArgumentConstraint, FileReadTool, FileWriteTool, FsSandbox, ListDirTool,
+    OpenApiLoader, OpenApiTool, ShellTool, Tool, ToolBatch, ToolCall, ToolCallEvent,
+    ToolCallStatus, ToolCatalog, ToolLayer, ToolLimits, ToolLoopLimits, ToolNamespaces, ToolPolicy,
+    ToolRegistry, ToolRule, ToolSchemaFormat, ToolSchemaSnapshots, ToolSelection, ToolSelector,
+    ToolStream, ToolTarget, ToolUsage, TOOL_ARGS_HASH_KEY, TOOL_CALLS_KEY, TOOL_LATENCY_MS_KEY,
+    TOOL_NAME_KEY, TOOL_PROVIDER_KEY, TOOL_STATUS_KEY,
+};
 pub use types::{
-    AgentOptions, AgentState, File, GenerationResponse, Message, Role, SubAgent,
-    SubAgentDirectory, ToolRequest, ToolResponse, ToolSpec,
+    AgentOptions, AgentState, CheckpointParent, File, GenerateOptions, GenerationResponse, Message,
+    Role, SubAgent, SubAgentDirectory, SubAgentInfo, ToolRequest, ToolResponse, ToolSpec,
+    AGENT_STATE_VERSION,
 };
+pub use utcp::{UtcpDiscovery, UtcpHub};
 
 // Re-export memory backends
 #[cfg(feature = "postgres")]
-pub use memory::PostgresStore;
+pub use memory::{PostgresConfig, PostgresStore};
 
 #[cfg(feature = "qdrant")]
-pub use memory::QdrantStore;
+pub use memory::{QdrantConfig, QdrantStore};
 
 #[cfg(feature = "mongodb")]
 pub use memory::MongoStore;
 
+#[cfg(feature = "surrealdb")]
+pub use memory::SurrealStore;
+
+#[cfg(feature = "bedrock")]
+pub use memory::BedrockEmbedder;
+
+#[cfg(feature = "otel")]
+pub use telemetry::{init_otlp, OtelConfig, OtelGuard};
+
+#[cfg(feature = "object-store")]
+pub use checkpoint::ObjectStoreCheckpointer;
+
+#[cfg(feature = "postgres")]
+pub use checkpoint::PostgresCheckpointer;
+
+// Re-export document loaders
+#[cfg(feature = "scraper")]
+pub use loaders::HtmlLoader;
+
+#[cfg(feature = "pdf-extract")]
+pub use loaders::PdfLoader;
+
+// Re-export the browser tool
+#[cfg(feature = "browser")]
+pub use tools::BrowserTool;
+
+// Re-export tokenizers
+#[cfg(feature = "tiktoken")]
+pub use models::TiktokenTokenizer;
+
+#[cfg(feature = "hf-tokenizers")]
+pub use models::HfTokenizer;
+
 // Re-export LLM providers
 #[cfg(feature = "gemini")]
-pub use models::GeminiLLM;
+pub use models::{GeminiFiles, GeminiLLM};
 
 #[cfg(feature = "ollama")]
 pub use models::OllamaLLM;
@@ -69,7 +181,17 @@ pub use models::OllamaLLM;
 pub use models::AnthropicLLM;
 
 #[cfg(feature = "openai")]
-pub use models::OpenAILLM;
+pub use models::{OpenAIFiles, OpenAILLM};
+
+// Lets macro-generated code name this crate as `::rs_agent`, here too
+extern crate self as rs_agent;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use async_trait::async_trait;
+    pub use schemars;
+    pub use serde;
+}
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,491 @@
+//! Document loaders for Markdown, HTML and PDF.
+//!
+//! A [`DocumentLoader`] turns a file path or URL into clean text plus
+//! metadata, ready to be chunked, embedded and stored. [`MarkdownLoader`] is
+//! always available; `HtmlLoader` needs the `scraper` feature and `PdfLoader`
+//! the `pdf-extract` feature. [`Document::into_record`] converts a loaded
+//! document into a [`MemoryRecord`] for any memory store.
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::Utc;
+use uuid::Uuid;
+
+use crate::blob::content_hash;
+use crate::error::{AgentError, Result};
+use crate::memory::MemoryRecord;
+
+/// Role of memory records created from documents
+pub const DOCUMENT_ROLE: &str = "document";
+/// Metadata key holding the path or URL a document was loaded from
+pub const DOCUMENT_SOURCE_KEY: &str = "source";
+/// Metadata key holding the source format, e.g. `markdown`
+pub const DOCUMENT_FORMAT_KEY: &str = "format";
+/// Metadata key holding the document title, when one was found
+pub const DOCUMENT_TITLE_KEY: &str = "title";
+/// Metadata key holding the heading of a Markdown section
+pub const DOCUMENT_SECTION_KEY: &str = "section";
+/// Metadata key holding the SHA-256 of the document text, for deduplication
+pub const DOCUMENT_HASH_KEY: &str = "content_hash";
+
+/// Clean text extracted from a source, with its metadata
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    pub content: String,
+    pub metadata: HashMap<String, String>,
+}
+
+impl Document {
+    /// Creates a document from `content` loaded from `source` in `format`
+    pub fn new(content: String, source: &str, format: &str) -> Self {
+        let mut metadata = HashMap::new();
+        metadata.insert(DOCUMENT_SOURCE_KEY.to_string(), source.to_string());
+        metadata.insert(DOCUMENT_FORMAT_KEY.to_string(), format.to_string());
+        metadata.insert(
+            DOCUMENT_HASH_KEY.to_string(),
+            content_hash(content.as_bytes()),
+        );
+        Self { content, metadata }
+    }
+
+    /// Sets a metadata entry
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Returns the document title, if the loader found one
+    pub fn title(&self) -> Option<&str> {
+        self.metadata.get(DOCUMENT_TITLE_KEY).map(String::as_str)
+    }
+
+    /// Converts the document into a memory record of `session_id`
+    pub fn into_record(self, session_id: impl Into<String>) -> MemoryRecord {
+        MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: session_id.into(),
+            role: DOCUMENT_ROLE.to_string(),
+            content: self.content,
+            importance: 0.5,
+            timestamp: Utc::now(),
+            metadata: Some(self.metadata),
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        }
+    }
+}
+
+/// Turns raw files into [`Document`]s
+#[async_trait]
+pub trait DocumentLoader: Send + Sync {
+    /// Extracts documents from `data`, which was read from `source`
+    fn parse(&self, data: &[u8], source: &str) -> Result<Vec<Document>>;
+
+    /// Reads `source`, an `http(s)` URL or a file path, and parses it
+    async fn load(&self, source: &str) -> Result<Vec<Document>> {
+        let data = read_source(source).await?;
+        self.parse(&data, source)
+    }
+}
+
+/// Reads the bytes behind a URL or file path
+async fn read_source(source: &str) -> Result<Vec<u8>> {
+    if !(source.starts_with("http://") || source.starts_with("https://")) {
+        return Ok(tokio::fs::read(source).await?);
+    }
+
+    let response = reqwest::get(source)
+        .await
+        .map_err(|e| AgentError::Other(format!("Failed to fetch {}: {}", source, e)))?;
+    if !response.status().is_success() {
+        return Err(AgentError::Other(format!(
+            "Failed to fetch {}: {}",
+            source,
+            response.status()
+        )));
+    }
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| AgentError::Other(format!("Failed to read {}: {}", source, e)))?;
+    Ok(bytes.to_vec())
+}
+
+fn utf8(data: &[u8], source: &str) -> Result<String> {
+    String::from_utf8(data.to_vec())
+        .map_err(|_| AgentError::Other(format!("{} is not valid UTF-8", source)))
+}
+
+/// Trims every line and collapses runs of blank lines into one
+fn tidy(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines().map(str::trim_end) {
+        let blank = line.trim().is_empty();
+        if blank && matches!(lines.last(), None | Some(&"")) {
+            continue;
+        }
+        lines.push(if blank { "" } else { line });
+    }
+    lines.join("\n").trim().to_string()
+}
+
+/// Loader for Markdown files, stripping formatting down to plain text
+#[derive(Debug, Clone, Default)]
+pub struct MarkdownLoader {
+    sections: bool,
+}
+
+impl MarkdownLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Emits one document per heading section instead of one per file
+    pub fn with_sections(mut self, enabled: bool) -> Self {
+        self.sections = enabled;
+        self
+    }
+}
+
+#[async_trait]
+impl DocumentLoader for MarkdownLoader {
+    fn parse(&self, data: &[u8], source: &str) -> Result<Vec<Document>> {
+        let text = utf8(data, source)?;
+        let mut title = None;
+        // (heading, lines) per section; the first has no heading
+        let mut sections: Vec<(Option<String>, Vec<String>)> = vec![(None, Vec::new())];
+        let mut in_code = false;
+
+        for line in strip_front_matter(&text).lines() {
+            let trimmed = line.trim_start();
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                in_code = !in_code;
+                continue;
+            }
+            if in_code {
+                sections.last_mut().unwrap().1.push(line.to_string());
+                continue;
+            }
+            if is_rule(trimmed) {
+                continue;
+            }
+            if let Some(heading) = heading(trimmed) {
+                let heading = strip_inline(heading);
+                if trimmed.starts_with("# ") && title.is_none() {
+                    title = Some(heading.clone());
+                }
+                if self.sections {
+                    sections.push((Some(heading), Vec::new()));
+                } else {
+                    sections.last_mut().unwrap().1.push(heading);
+                }
+                continue;
+            }
+            let line = trimmed.trim_start_matches('>').trim_start();
+            sections.last_mut().unwrap().1.push(strip_inline(line));
+        }
+
+        let documents = sections
+            .into_iter()
+            .filter_map(|(heading, lines)| {
+                let content = tidy(&lines.join("\n"));
+                if content.is_empty() {
+                    return None;
+                }
+                let mut document = Document::new(content, source, "markdown");
+                if let Some(title) = &title {
+                    document = document.with_metadata(DOCUMENT_TITLE_KEY, title.clone());
+                }
+                if let Some(heading) = heading {
+                    document = document.with_metadata(DOCUMENT_SECTION_KEY, heading);
+                }
+                Some(document)
+            })
+            .collect();
+        Ok(documents)
+    }
+}
+
+fn strip_front_matter(text: &str) -> &str {
+    let Some(rest) = text.strip_prefix("---\n") else {
+        return text;
+    };
+    match rest.find("\n---\n") {
+        Some(end) => &rest[end + 5..],
+        None => text,
+    }
+}
+
+fn is_rule(line: &str) -> bool {
+    let line = line.trim();
+    line.len() >= 3
+        && ['-', '*', '_']
+            .iter()
+            .any(|&rule| line.chars().all(|c| c == rule || c == ' '))
+}
+
+fn heading(line: &str) -> Option<&str> {
+    let hashes = line.chars().take_while(|c| *c == '#').count();
+    if (1..=6).contains(&hashes) && line[hashes..].starts_with(' ') {
+        Some(line[hashes..].trim().trim_end_matches('#').trim_end())
+    } else {
+        None
+    }
+}
+
+/// Replaces links and images with their text and drops emphasis markers
+fn strip_inline(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut rest = line;
+    while let Some(c) = rest.chars().next() {
+        if c == '[' || (c == '!' && rest[1..].starts_with('[')) {
+            let open = if c == '!' { 2 } else { 1 };
+            if let Some((text, len)) = link(&rest[open..]) {
+                out.push_str(&strip_inline(text));
+                rest = &rest[open + len..];
+                continue;
+            }
+        }
+        if rest.starts_with("__") || rest.starts_with("~~") {
+            rest = &rest[2..];
+            continue;
+        }
+        if !matches!(c, '*' | '`') {
+            out.push(c);
+        }
+        rest = &rest[c.len_utf8()..];
+    }
+    out
+}
+
+/// Parses `text](target)` and returns the text and the length consumed
+fn link(rest: &str) -> Option<(&str, usize)> {
+    let close = rest.find("](")?;
+    let end = rest[close + 2..].find(')')?;
+    Some((&rest[..close], close + 2 + end + 1))
+}
+
+#[cfg(feature = "scraper")]
+pub use html::HtmlLoader;
+
+#[cfg(feature = "scraper")]
+mod html {
+    use async_trait::async_trait;
+    use scraper::{ElementRef, Html, Selector};
+
+    use super::{tidy, utf8, Document, DocumentLoader, DOCUMENT_TITLE_KEY};
+    use crate::error::{AgentError, Result};
+
+    /// Elements whose text is never content
+    const SKIPPED: &[&str] = &["script", "style", "noscript", "template", "head"];
+    /// Elements that start a new line of text
+    const BLOCKS: &[&str] = &[
+        "p",
+        "div",
+        "br",
+        "li",
+        "tr",
+        "h1",
+        "h2",
+        "h3",
+        "h4",
+        "h5",
+        "h6",
+        "pre",
+        "blockquote",
+        "section",
+        "article",
+        "header",
+        "footer",
+        "table",
+    ];
+
+    /// Loader for HTML pages, keeping visible text and the page title
+    #[derive(Debug, Clone, Default)]
+    pub struct HtmlLoader {
+        selector: Option<Selector>,
+    }
+
+    impl HtmlLoader {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Keeps only the text inside elements matching `css`, e.g. `article`
+        pub fn with_selector(mut self, css: &str) -> Result<Self> {
+            let selector = Selector::parse(css)
+                .map_err(|e| AgentError::ConfigError(format!("Invalid selector {}: {}", css, e)))?;
+            self.selector = Some(selector);
+            Ok(self)
+        }
+    }
+
+    #[async_trait]
+    impl DocumentLoader for HtmlLoader {
+        fn parse(&self, data: &[u8], source: &str) -> Result<Vec<Document>> {
+            let page = Html::parse_document(&utf8(data, source)?);
+            let title = Selector::parse("title")
+                .ok()
+                .and_then(|title| page.select(&title).next())
+                .map(|title| title.text().collect::<String>().trim().to_string())
+                .filter(|title| !title.is_empty());
+
+            let roots: Vec<ElementRef> = match &self.selector {
+                Some(selector) => page.select(selector).collect(),
+                None => vec![page.root_element()],
+            };
+            let text = roots
+                .into_iter()
+                .map(visible_text)
+                .collect::<Vec<_>>()
+                .join("\n\n");
+            let content = tidy(&text);
+            if content.is_empty() {
+                return Ok(Vec::new());
+            }
+
+            let mut document = Document::new(content, source, "html");
+            if let Some(title) = title {
+                document = document.with_metadata(DOCUMENT_TITLE_KEY, title);
+            }
+            Ok(vec![document])
+        }
+    }
+
+    fn visible_text(root: ElementRef) -> String {
+        let mut text = String::new();
+        for node in root.descendants() {
+            let hidden = node.ancestors().chain(std::iter::once(node)).any(|n| {
+                n.value()
+                    .as_element()
+                    .is_some_and(|e| SKIPPED.contains(&e.name()))
+            });
+            if hidden {
+                continue;
+            }
+            if let Some(element) = node.value().as_element() {
+                if BLOCKS.contains(&element.name()) && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+            } else if let Some(fragment) = node.value().as_text() {
+                let words: Vec<&str> = fragment.split_whitespace().collect();
+                if !words.is_empty() {
+                    if !text.is_empty() && !text.ends_with('\n') {
+                        text.push(' ');
+                    }
+                    text.push_str(&words.join(" "));
+                }
+            }
+        }
+        text
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn test_html_visible_text() {
+            let page = br#"<html><head><title> Returns </title><style>p{}</style></head>
+                <body><nav>Home</nav><article><h1>Refunds</h1><p>Within <b>30</b> days.</p>
+                <script>track()</script></article></body></html>"#;
+
+            let all = HtmlLoader::new().parse(page, "page.html").unwrap();
+            assert_eq!(all[0].content, "Home\nRefunds\nWithin 30 days.");
+            assert_eq!(all[0].title(), Some("Returns"));
+
+            let article = HtmlLoader::new()
+                .with_selector("article")
+                .unwrap()
+                .parse(page, "page.html")
+                .unwrap();
+            assert_eq!(article[0].content, "Refunds\nWithin 30 days.");
+            assert!(HtmlLoader::new().with_selector("[").is_err());
+        }
+    }
+}
+
+#[cfg(feature = "pdf-extract")]
+pub use pdf::PdfLoader;
+
+#[cfg(feature = "pdf-extract")]
+mod pdf {
+    use async_trait::async_trait;
+
+    use super::{tidy, Document, DocumentLoader};
+    use crate::error::{AgentError, Result};
+
+    /// Loader for PDF files, extracting their text layer
+    #[derive(Debug, Clone, Default)]
+    pub struct PdfLoader;
+
+    impl PdfLoader {
+        pub fn new() -> Self {
+            Self
+        }
+    }
+
+    #[async_trait]
+    impl DocumentLoader for PdfLoader {
+        fn parse(&self, data: &[u8], source: &str) -> Result<Vec<Document>> {
+            // pdf-extract panics on some malformed files instead of erroring
+            let extracted = std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(data))
+                .map_err(|_| AgentError::Other(format!("Failed to parse PDF {}", source)))?
+                .map_err(|e| AgentError::Other(format!("Failed to parse PDF {}: {}", source, e)))?;
+
+            let content = tidy(&extracted);
+            if content.is_empty() {
+                return Ok(Vec::new());
+            }
+            Ok(vec![Document::new(content, source, "pdf")])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUIDE: &str =
+        "---\nlayout: doc\n---\n# Returns *guide*\n\nSee the [policy](https://example.com) \
+for **details**.\n\n---\n\n## Refunds\n\n> Refunds take `5` days.\n\n```\nlet x = 1;\n```\n";
+
+    #[test]
+    fn test_markdown_is_stripped_to_text() {
+        let documents = MarkdownLoader::new()
+            .parse(GUIDE.as_bytes(), "guide.md")
+            .unwrap();
+        assert_eq!(documents.len(), 1);
+        assert_eq!(
+            documents[0].content,
+            "Returns guide\n\nSee the policy for details.\n\nRefunds\n\nRefunds take 5 days.\n\nlet x = 1;"
+        );
+        assert_eq!(documents[0].title(), Some("Returns guide"));
+
+        let record = documents[0].clone().into_record("kb");
+        assert_eq!(record.role, DOCUMENT_ROLE);
+        assert_eq!(record.metadata.unwrap()[DOCUMENT_SOURCE_KEY], "guide.md");
+    }
+
+    #[tokio::test]
+    async fn test_markdown_sections_from_file() {
+        let path = std::env::temp_dir().join(format!("loader-{}.md", Uuid::new_v4()));
+        tokio::fs::write(&path, GUIDE).await.unwrap();
+
+        let documents = MarkdownLoader::new()
+            .with_sections(true)
+            .load(path.to_str().unwrap())
+            .await
+            .unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+
+        let sections: Vec<_> = documents
+            .iter()
+            .map(|d| d.metadata[DOCUMENT_SECTION_KEY].as_str())
+            .collect();
+        assert_eq!(sections, ["Returns guide", "Refunds"]);
+        assert_eq!(documents[1].content, "Refunds take 5 days.\n\nlet x = 1;");
+    }
+}
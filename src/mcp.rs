@@ -0,0 +1,662 @@
+//! Serving an agent over the Model Context Protocol.
+//!
+//! [`McpServer`] makes an agent callable from MCP clients such as Claude
+//! Desktop or Cursor. It lists the agent's registered tools plus a `chat`
+//! tool that runs a turn of the agent, and answers `tools/call` through
+//! `Agent::invoke_tool`, so hooks and tool policies apply as for the
+//! agent's own calls. [`McpServer::serve_stdio`] speaks newline-delimited
+//! JSON-RPC on stdin and stdout, the transport editors launch local servers
+//! with; with the `axum` feature, [`McpServer::router`] serves the SSE
+//! transport over HTTP.
+//!
+//! Every call runs in an agent session derived from the connection it
+//! came on, so one client can't continue another's conversation, and the
+//! tools listed are those the connection's session may call. With
+//! [`McpServer::with_tenant_mapper`], calls also run on behalf of the
+//! connection's tenant, for agents guarded by a `TenantGuard`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+
+use crate::agent::Agent;
+use crate::error::Result;
+use crate::types::ToolSpec;
+
+/// MCP revision answered when the client asks for one this server lacks
+pub const MCP_PROTOCOL_VERSION: &str = "2024-11-05";
+/// Name of the tool running a turn of the agent
+pub const CHAT_TOOL: &str = "chat";
+
+/// Revisions whose tool methods this server speaks unchanged
+const SUPPORTED_VERSIONS: [&str; 3] = ["2024-11-05", "2025-03-26", "2025-06-18"];
+
+// JSON-RPC error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Maps the connection a call came on and the `session_id` it asks for,
+/// if any, to the agent session it runs in, or `None` to reject the call
+pub type SessionMapper = Arc<dyn Fn(&str, Option<&str>) -> Option<String> + Send + Sync>;
+
+/// Maps the connection a call came on to the tenant it runs on behalf of
+pub type TenantMapper = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+/// Decides which of the agent's tools the server exposes
+pub type McpToolFilter = Arc<dyn Fn(&ToolSpec) -> bool + Send + Sync>;
+
+/// Authenticates an SSE request by its headers, returning who is calling
+/// or `None` to refuse it
+#[cfg(feature = "axum")]
+pub type McpAuthorizer = Arc<dyn Fn(&axum::http::HeaderMap) -> Option<String> + Send + Sync>;
+
+/// An open SSE connection
+#[cfg(feature = "axum")]
+struct SseSession {
+    sender: tokio::sync::mpsc::Sender<Value>,
+    // Caller the authorizer admitted, if any
+    caller: Option<String>,
+    // Connection its calls run under
+    connection: String,
+}
+
+/// MCP server exposing an agent's tools and a `chat` tool.
+///
+/// Calls over stdio run in the server's session, `mcp` by default. A
+/// `session_id` argument picks a session under the connection's, `mcp/<id>`
+/// for stdio, unless [`with_session_mapper`](McpServer::with_session_mapper)
+/// maps it otherwise; it is never passed on to tools.
+pub struct McpServer {
+    agent: Arc<Agent>,
+    name: String,
+    session_id: String,
+    chat: bool,
+    sessions: Option<SessionMapper>,
+    tenants: Option<TenantMapper>,
+    filter: Option<McpToolFilter>,
+    #[cfg(feature = "axum")]
+    authorizer: Option<McpAuthorizer>,
+    #[cfg(feature = "axum")]
+    sse_sessions: parking_lot::Mutex<HashMap<String, SseSession>>,
+}
+
+impl McpServer {
+    /// Serves `agent` and its tools
+    pub fn new(agent: Arc<Agent>) -> Self {
+        Self {
+            agent,
+            name: "rs-agent".to_string(),
+            session_id: "mcp".to_string(),
+            chat: true,
+            sessions: None,
+            tenants: None,
+            filter: None,
+            #[cfg(feature = "axum")]
+            authorizer: None,
+            #[cfg(feature = "axum")]
+            sse_sessions: parking_lot::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Names the server to clients
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Runs stdio calls without a `session_id` argument in `session_id`,
+    /// and SSE connections under it
+    pub fn with_session_id(mut self, session_id: impl Into<String>) -> Self {
+        self.session_id = session_id.into();
+        self
+    }
+
+    /// Picks the agent session of each call with `mapper`, given the
+    /// connection's session and the `session_id` argument, if any.
+    /// Returning `None` rejects the call.
+    pub fn with_session_mapper(
+        mut self,
+        mapper: impl Fn(&str, Option<&str>) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.sessions = Some(Arc::new(mapper));
+        self
+    }
+
+    /// Runs each call on behalf of the tenant `mapper` returns for the
+    /// connection it came on, or of no tenant for `None`
+    pub fn with_tenant_mapper(
+        mut self,
+        mapper: impl Fn(&str) -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.tenants = Some(Arc::new(mapper));
+        self
+    }
+
+    /// Only exposes the tools `filter` accepts; others are neither listed
+    /// nor callable
+    pub fn with_tool_filter(
+        mut self,
+        filter: impl Fn(&ToolSpec) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Leaves out the `chat` tool, serving the registered tools only
+    pub fn without_chat(mut self) -> Self {
+        self.chat = false;
+        self
+    }
+
+    /// Serves MCP on stdin and stdout until stdin closes.
+    ///
+    /// Stdout carries the protocol, so nothing else may write to it; send
+    /// logs to stderr.
+    pub async fn serve_stdio(&self) -> Result<()> {
+        self.serve(BufReader::new(tokio::io::stdin()), tokio::io::stdout())
+            .await
+    }
+
+    /// Serves MCP as newline-delimited JSON-RPC until `reader` ends.
+    ///
+    /// Requests are handled concurrently, so a long `chat` turn doesn't hold
+    /// up pings or other calls; replies are written as they complete.
+    pub async fn serve<R, W>(&self, reader: R, mut writer: W) -> Result<()>
+    where
+        R: AsyncBufRead + Unpin,
+        W: AsyncWrite + Unpin,
+    {
+        let mut lines = reader.lines();
+        let mut pending = FuturesUnordered::new();
+        let mut open = true;
+        while open || !pending.is_empty() {
+            tokio::select! {
+                line = lines.next_line(), if open => match line? {
+                    Some(line) if line.trim().is_empty() => {}
+                    Some(line) => pending.push(self.handle_line(line)),
+                    None => open = false,
+                },
+                Some(reply) = pending.next(), if !pending.is_empty() => {
+                    if let Some(reply) = reply {
+                        writer.write_all(reply.to_string().as_bytes()).await?;
+                        writer.write_all(b"\n").await?;
+                        writer.flush().await?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn handle_line(&self, line: String) -> Option<Value> {
+        match serde_json::from_str(&line) {
+            Ok(message) => self.handle(message).await,
+            Err(e) => Some(error_reply(Value::Null, PARSE_ERROR, e.to_string())),
+        }
+    }
+
+    /// Answers one JSON-RPC message on the server's own connection;
+    /// notifications get no reply
+    pub async fn handle(&self, message: Value) -> Option<Value> {
+        self.handle_on(&self.session_id, message).await
+    }
+
+    /// Answers one JSON-RPC message that came on `connection`
+    async fn handle_on(&self, connection: &str, message: Value) -> Option<Value> {
+        let Some(method) = message["method"].as_str() else {
+            // Replies to server requests, which this server never sends, are dropped
+            let is_reply = message.get("result").is_some() || message.get("error").is_some();
+            return match message.get("id") {
+                Some(id) if !is_reply => Some(error_reply(
+                    id.clone(),
+                    INVALID_REQUEST,
+                    "missing method".to_string(),
+                )),
+                _ => None,
+            };
+        };
+        let id = message.get("id")?.clone();
+        let params = &message["params"];
+        let result = match method {
+            "initialize" => Ok(self.initialize(params)),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({ "tools": self.list_tools(connection) })),
+            "tools/call" => self.call_tool(connection, params).await,
+            other => Err((METHOD_NOT_FOUND, format!("Unknown method: {}", other))),
+        };
+        Some(match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err((code, message)) => error_reply(id, code, message),
+        })
+    }
+
+    fn initialize(&self, params: &Value) -> Value {
+        let requested = params["protocolVersion"].as_str();
+        let version = requested
+            .filter(|v| SUPPORTED_VERSIONS.contains(v))
+            .unwrap_or(MCP_PROTOCOL_VERSION);
+        json!({
+            "protocolVersion": version,
+            "capabilities": {"tools": {"listChanged": false}},
+            "serverInfo": {"name": self.name, "version": env!("CARGO_PKG_VERSION")},
+        })
+    }
+
+    /// Returns true if the server exposes `spec` at all
+    fn exposes(&self, spec: &ToolSpec) -> bool {
+        self.filter.as_ref().is_none_or(|filter| filter(spec))
+    }
+
+    /// Lists the exposed tools `connection`'s session may call
+    fn list_tools(&self, connection: &str) -> Vec<Value> {
+        let mut tools = Vec::new();
+        if self.chat {
+            tools.push(json!({
+                "name": CHAT_TOOL,
+                "description": "Sends a message to the agent and returns its reply",
+                "inputSchema": {
+                    "type": "object",
+                    "properties": {
+                        "message": {"type": "string", "description": "Message to the agent"},
+                        "session_id": {
+                            "type": "string",
+                            "description": "Conversation to continue; the connection's by default"
+                        }
+                    },
+                    "required": ["message"]
+                }
+            }));
+        }
+        for tool in self.agent.tools().tools() {
+            let spec = tool.spec();
+            if self.chat && spec.name == CHAT_TOOL {
+                continue;
+            }
+            if !self.exposes(&spec) || !self.agent.may_call_tool(connection, &spec.name) {
+                continue;
+            }
+            tools.push(json!({
+                "name": spec.name,
+                "description": spec.description,
+                "inputSchema": spec.input_schema,
+            }));
+        }
+        tools
+    }
+
+    /// Picks the agent session a call on `connection` runs in
+    fn session_for(&self, connection: &str, requested: Option<&str>) -> Option<String> {
+        let requested = requested.filter(|id| !id.trim().is_empty());
+        match &self.sessions {
+            Some(mapper) => mapper(connection, requested),
+            None => Some(match requested {
+                Some(id) => format!("{}/{}", connection, id),
+                None => connection.to_string(),
+            }),
+        }
+    }
+
+    async fn call_tool(
+        &self,
+        connection: &str,
+        params: &Value,
+    ) -> std::result::Result<Value, (i64, String)> {
+        let name = params["name"]
+            .as_str()
+            .ok_or((INVALID_PARAMS, "missing tool name".to_string()))?;
+        let mut arguments: HashMap<String, Value> = match &params["arguments"] {
+            Value::Null => HashMap::new(),
+            Value::Object(fields) => fields.clone().into_iter().collect(),
+            _ => return Err((INVALID_PARAMS, "arguments must be an object".to_string())),
+        };
+        // The session is the server's to pick, not an argument of the tool
+        let requested = arguments.remove("session_id");
+        let session_id = self
+            .session_for(connection, requested.as_ref().and_then(Value::as_str))
+            .ok_or((INVALID_PARAMS, "session not allowed".to_string()))?;
+        let options = crate::types::GenerateOptions {
+            tenant_id: self.tenants.as_ref().and_then(|mapper| mapper(connection)),
+            ..Default::default()
+        };
+
+        let outcome = if self.chat && name == CHAT_TOOL {
+            let Some(Value::String(message)) = arguments.remove("message") else {
+                return Err((INVALID_PARAMS, "missing 'message'".to_string()));
+            };
+            self.agent
+                .generate_with_options(session_id, message, options)
+                .await
+                .map(|response| response.content)
+        } else {
+            let exposed = self
+                .agent
+                .tools()
+                .get(name)
+                .is_some_and(|tool| self.exposes(&tool.spec()));
+            if !exposed {
+                return Err((INVALID_PARAMS, format!("Unknown tool: {}", name)));
+            }
+            self.agent
+                .invoke_tool_with_options(session_id, name, arguments, &options)
+                .await
+        };
+        // Tool failures are results the model should see, not protocol errors
+        let (text, is_error) = match outcome {
+            Ok(text) => (text, false),
+            Err(e) => (e.to_string(), true),
+        };
+        Ok(json!({
+            "content": [{"type": "text", "text": text}],
+            "isError": is_error,
+        }))
+    }
+}
+
+fn error_reply(id: Value, code: i64, message: String) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+#[cfg(feature = "axum")]
+mod sse_transport {
+    use std::collections::HashMap;
+    use std::convert::Infallible;
+    use std::sync::Arc;
+
+    use axum::extract::{Query, State};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::sse::{Event, KeepAlive, Sse};
+    use axum::response::{IntoResponse, Response};
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use futures::stream::{self, StreamExt};
+    use serde_json::Value;
+    use tokio::sync::mpsc;
+
+    use super::{McpServer, SseSession};
+    use crate::server::DEFAULT_STREAM_BUFFER;
+
+    /// Forgets an SSE session once its stream is dropped
+    struct SessionGuard {
+        server: Arc<McpServer>,
+        id: String,
+    }
+
+    impl Drop for SessionGuard {
+        fn drop(&mut self) {
+            self.server.sse_sessions.lock().remove(&self.id);
+        }
+    }
+
+    impl McpServer {
+        /// Authenticates SSE requests with `authorizer`, which returns who
+        /// is calling from the request headers or `None` to refuse it with
+        /// `401`. A caller's connections run in the session
+        /// `<session_id>/<caller>`, and only the caller who opened an SSE
+        /// session may post to it.
+        pub fn with_authorizer(
+            mut self,
+            authorizer: impl Fn(&HeaderMap) -> Option<String> + Send + Sync + 'static,
+        ) -> Self {
+            self.authorizer = Some(Arc::new(authorizer));
+            self
+        }
+
+        /// Serves the MCP SSE transport: `GET /sse` opens a session and
+        /// `POST /messages?session_id=...` sends it requests, whose replies
+        /// arrive as `message` events. Nest the router to serve it under a
+        /// prefix.
+        ///
+        /// Anyone who can reach the router can call the exposed tools, so
+        /// set [`with_authorizer`](McpServer::with_authorizer) unless it is
+        /// only reachable by trusted clients. Without one, each SSE
+        /// connection gets a session of its own.
+        pub fn router(self: Arc<Self>) -> Router {
+            Router::new()
+                .route("/sse", get(open_session))
+                .route("/messages", post(post_message))
+                .with_state(self)
+        }
+
+        /// Returns who is calling, `Ok(None)` without an authorizer, or
+        /// `Err` if the authorizer refuses the request
+        fn authorize(&self, headers: &HeaderMap) -> Result<Option<String>, StatusCode> {
+            match &self.authorizer {
+                Some(authorizer) => authorizer(headers)
+                    .map(Some)
+                    .ok_or(StatusCode::UNAUTHORIZED),
+                None => Ok(None),
+            }
+        }
+    }
+
+    async fn open_session(State(server): State<Arc<McpServer>>, headers: HeaderMap) -> Response {
+        let caller = match server.authorize(&headers) {
+            Ok(caller) => caller,
+            Err(status) => return status.into_response(),
+        };
+        let id = uuid::Uuid::new_v4().to_string();
+        let connection = format!("{}/{}", server.session_id, caller.as_deref().unwrap_or(&id));
+        let (sender, rx) = mpsc::channel(DEFAULT_STREAM_BUFFER);
+        let session = SseSession {
+            sender,
+            caller,
+            connection,
+        };
+        server.sse_sessions.lock().insert(id.clone(), session);
+        // Relative, so it resolves next to /sse wherever the router is nested
+        let endpoint = Event::default()
+            .event("endpoint")
+            .data(format!("messages?session_id={}", id));
+        let guard = SessionGuard { server, id };
+        let messages = stream::unfold((rx, guard), |(mut rx, guard)| async move {
+            let reply = rx.recv().await?;
+            let event = Event::default().event("message").data(reply.to_string());
+            Some((Ok::<_, Infallible>(event), (rx, guard)))
+        });
+        let events = stream::once(async { Ok::<_, Infallible>(endpoint) }).chain(messages);
+        Sse::new(events)
+            .keep_alive(KeepAlive::default())
+            .into_response()
+    }
+
+    async fn post_message(
+        State(server): State<Arc<McpServer>>,
+        Query(query): Query<HashMap<String, String>>,
+        headers: HeaderMap,
+        Json(message): Json<Value>,
+    ) -> StatusCode {
+        let caller = match server.authorize(&headers) {
+            Ok(caller) => caller,
+            Err(status) => return status,
+        };
+        let session = query.get("session_id").and_then(|id| {
+            let sessions = server.sse_sessions.lock();
+            let session = sessions.get(id)?;
+            Some((
+                session.sender.clone(),
+                session.caller.clone(),
+                session.connection.clone(),
+            ))
+        });
+        let Some((sender, owner, connection)) = session else {
+            return StatusCode::NOT_FOUND;
+        };
+        if owner != caller {
+            return StatusCode::FORBIDDEN;
+        }
+        tokio::spawn(async move {
+            if let Some(reply) = server.handle_on(&connection, message).await {
+                let _ = sender.send(reply).await;
+            }
+        });
+        StatusCode::ACCEPTED
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{InMemoryStore, SessionMemory};
+    use crate::models::LLM;
+    use crate::tools::policy::{ToolPolicy, ToolRule, ToolTarget};
+    use crate::tools::Tool;
+    use crate::types::{
+        AgentOptions, File, GenerationResponse, Message, ToolRequest, ToolResponse, ToolSpec,
+    };
+    use async_trait::async_trait;
+
+    struct FixedLLM;
+
+    #[async_trait]
+    impl LLM for FixedLLM {
+        async fn generate(
+            &self,
+            _messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<GenerationResponse> {
+            Ok(GenerationResponse {
+                content: "Hello from the agent".to_string(),
+                metadata: None,
+            })
+        }
+
+        fn model_name(&self) -> &str {
+            "fixed"
+        }
+    }
+
+    struct UpperTool;
+
+    #[async_trait]
+    impl Tool for UpperTool {
+        fn spec(&self) -> ToolSpec {
+            ToolSpec {
+                name: "upper".to_string(),
+                description: "Uppercases the input".to_string(),
+                input_schema: json!({"type": "object"}),
+                examples: None,
+                output_schema: None,
+            }
+        }
+
+        async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse> {
+            let input = req.arguments.get("input").and_then(Value::as_str);
+            Ok(ToolResponse {
+                content: input.unwrap_or_default().to_uppercase(),
+                metadata: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serves_tools_and_chat_over_json_rpc() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 8));
+        let agent = Arc::new(Agent::new(
+            Arc::new(FixedLLM),
+            memory,
+            AgentOptions::default(),
+        ));
+        agent.tools().register(Box::new(UpperTool)).unwrap();
+        let server = McpServer::new(agent);
+
+        let requests = [
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize",
+                "params": {"protocolVersion": "2025-03-26", "capabilities": {}}}),
+            json!({"jsonrpc": "2.0", "method": "notifications/initialized"}),
+            json!({"jsonrpc": "2.0", "id": 2, "method": "tools/list"}),
+            json!({"jsonrpc": "2.0", "id": 3, "method": "tools/call",
+                "params": {"name": "upper", "arguments": {"input": "hi"}}}),
+            json!({"jsonrpc": "2.0", "id": 4, "method": "tools/call",
+                "params": {"name": "chat", "arguments": {"message": "Hi"}}}),
+            json!({"jsonrpc": "2.0", "id": 5, "method": "tools/call",
+                "params": {"name": "missing"}}),
+        ];
+        let mut input = requests
+            .iter()
+            .map(Value::to_string)
+            .collect::<Vec<_>>()
+            .join("\n");
+        input.push_str("\nnot json\n");
+        let mut output = Vec::new();
+        server.serve(input.as_bytes(), &mut output).await.unwrap();
+
+        let replies: HashMap<String, Value> = String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap())
+            .map(|reply| (reply["id"].to_string(), reply))
+            .collect();
+        assert_eq!(replies.len(), 6);
+        assert_eq!(replies["1"]["result"]["protocolVersion"], "2025-03-26");
+        let names: Vec<&str> = replies["2"]["result"]["tools"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|tool| tool["name"].as_str())
+            .collect();
+        assert_eq!(names, ["chat", "upper"]);
+        assert_eq!(replies["3"]["result"]["content"][0]["text"], "HI");
+        assert_eq!(replies["3"]["result"]["isError"], false);
+        assert_eq!(
+            replies["4"]["result"]["content"][0]["text"],
+            "Hello from the agent"
+        );
+        assert_eq!(replies["5"]["error"]["code"], INVALID_PARAMS);
+        assert_eq!(replies["null"]["error"]["code"], PARSE_ERROR);
+    }
+
+    struct KeysTool;
+
+    #[async_trait]
+    impl Tool for KeysTool {
+        fn spec(&self) -> ToolSpec {
+            ToolSpec {
+                name: "admin.keys".to_string(),
+                ..UpperTool.spec()
+            }
+        }
+
+        async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse> {
+            let mut keys: Vec<String> = req.arguments.into_keys().collect();
+            keys.sort();
+            Ok(ToolResponse {
+                content: format!("{}:{}", req.session_id, keys.join(",")),
+                metadata: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scopes_sessions_to_the_connection_and_lists_callable_tools() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 8));
+        let policy = ToolPolicy::new()
+            .with_rule(ToolRule::deny(ToolTarget::name("admin.keys")).for_session("mcp"));
+        let agent = Arc::new(
+            Agent::new(Arc::new(FixedLLM), memory, AgentOptions::default())
+                .with_tool_policy(policy),
+        );
+        agent.tools().register(Box::new(UpperTool)).unwrap();
+        agent.tools().register(Box::new(KeysTool)).unwrap();
+        let server = McpServer::new(Arc::clone(&agent)).without_chat();
+
+        let call = json!({"jsonrpc": "2.0", "id": 1, "method": "tools/call",
+            "params": {"name": "admin.keys", "arguments": {"session_id": "other", "x": 1}}});
+        let reply = server.handle(call.clone()).await.unwrap();
+        assert_eq!(reply["result"]["content"][0]["text"], "mcp/other:x");
+
+        let list = json!({"jsonrpc": "2.0", "id": 2, "method": "tools/list"});
+        let reply = server.handle(list).await.unwrap();
+        assert_eq!(reply["result"]["tools"].as_array().unwrap().len(), 1);
+        assert_eq!(reply["result"]["tools"][0]["name"], "upper");
+
+        let server = McpServer::new(agent)
+            .with_session_mapper(|_, requested| requested.is_none().then(|| "fixed".to_string()));
+        let reply = server.handle(call).await.unwrap();
+        assert_eq!(reply["error"]["code"], INVALID_PARAMS);
+    }
+}
@@ -0,0 +1,377 @@
+//! Write-behind buffering for memory stores.
+//!
+//! [`BufferedStore`] takes writes off the hot path: `store` only queues the
+//! record, and a background task writes queued records to the wrapped store
+//! with [`MemoryStore::store_batch`] once a batch fills up or the flush
+//! interval passes. Reads, updates and purges drain the queue first, so they
+//! always observe earlier writes. Drains are served even while the wrapped
+//! store is failing and the queue is full, and give up after a timeout.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::stream::BoxStream;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::{Instant, MissedTickBehavior};
+use uuid::Uuid;
+
+use crate::error::{AgentError, Result};
+use crate::memory::{export_pages, MemoryFilter, MemoryPage, MemoryRecord, MemoryStore};
+
+/// Batching and backpressure settings for [`BufferedStore`]
+#[derive(Debug, Clone)]
+pub struct BufferConfig {
+    batch_size: usize,
+    interval: Duration,
+    capacity: usize,
+    drain_timeout: Duration,
+}
+
+impl Default for BufferConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 64,
+            interval: Duration::from_millis(500),
+            capacity: 1024,
+            drain_timeout: Duration::from_secs(30),
+        }
+    }
+}
+
+impl BufferConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Writes queued records once this many are waiting
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Writes queued records at least this often
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Bounds how many records may wait to be written; `store` waits for
+    /// room once the bound is reached
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Bounds how long reads, updates and `flush` wait for the queue to be
+    /// written before failing, 30 seconds by default
+    pub fn with_drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Returns the configured batch size
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Returns the configured flush interval
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Returns the configured queue capacity
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the configured drain timeout
+    pub fn drain_timeout(&self) -> Duration {
+        self.drain_timeout
+    }
+}
+
+/// Asks the writer to write everything queued and report the outcome
+type Drain = oneshot::Sender<Result<()>>;
+
+/// A [`MemoryStore`] wrapper that queues writes and stores them in batches.
+///
+/// Batches that fail to write are kept and retried on the next interval;
+/// the error is returned to whoever is waiting on a drain or `flush`. Queued
+/// records are written one last time when the store is dropped. Must be
+/// created inside a Tokio runtime.
+pub struct BufferedStore {
+    inner: Arc<dyn MemoryStore>,
+    writes: mpsc::Sender<MemoryRecord>,
+    drains: mpsc::UnboundedSender<Drain>,
+    config: BufferConfig,
+}
+
+impl BufferedStore {
+    /// Buffers writes to `inner` with the default configuration
+    pub fn new(inner: Arc<dyn MemoryStore>) -> Self {
+        Self::with_config(inner, BufferConfig::default())
+    }
+
+    /// Buffers writes to `inner` with the given configuration
+    pub fn with_config(inner: Arc<dyn MemoryStore>, config: BufferConfig) -> Self {
+        let (writes, queued) = mpsc::channel(config.capacity);
+        let (drains, requests) = mpsc::unbounded_channel();
+        tokio::spawn(run(Arc::clone(&inner), config.clone(), queued, requests));
+        Self {
+            inner,
+            writes,
+            drains,
+            config,
+        }
+    }
+
+    /// Returns the buffering configuration
+    pub fn config(&self) -> &BufferConfig {
+        &self.config
+    }
+
+    /// Writes every queued record to the wrapped store
+    async fn drain(&self) -> Result<()> {
+        let (ack, done) = oneshot::channel();
+        self.drains.send(ack).map_err(|_| worker_stopped())?;
+        match tokio::time::timeout(self.config.drain_timeout, done).await {
+            Ok(outcome) => outcome.map_err(|_| worker_stopped())?,
+            Err(_) => Err(AgentError::MemoryError(format!(
+                "Buffered store did not drain within {:?}",
+                self.config.drain_timeout
+            ))),
+        }
+    }
+}
+
+fn worker_stopped() -> AgentError {
+    AgentError::MemoryError("Buffered store writer has stopped".to_string())
+}
+
+/// Background writer owning the queue
+async fn run(
+    inner: Arc<dyn MemoryStore>,
+    config: BufferConfig,
+    mut queued: mpsc::Receiver<MemoryRecord>,
+    mut drains: mpsc::UnboundedReceiver<Drain>,
+) {
+    let mut pending: Vec<MemoryRecord> = Vec::new();
+    let mut ticker = tokio::time::interval_at(Instant::now() + config.interval, config.interval);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    loop {
+        tokio::select! {
+            // While failed batches fill the queue, leave new writes waiting
+            // in the channel so callers feel the backpressure
+            record = queued.recv(), if pending.len() < config.capacity => match record {
+                Some(record) => {
+                    pending.push(record);
+                    if pending.len() >= config.batch_size {
+                        if let Err(e) = write(inner.as_ref(), &mut pending).await {
+                            tracing::warn!("Buffered memory write failed: {}", e);
+                        }
+                    }
+                }
+                None => {
+                    if let Err(e) = write(inner.as_ref(), &mut pending).await {
+                        tracing::warn!(
+                            dropped = pending.len(),
+                            "Buffered memory write failed on shutdown: {}",
+                            e
+                        );
+                    }
+                    return;
+                }
+            },
+            // Served regardless of the queue, so a failing store fails
+            // drains instead of blocking them
+            Some(ack) = drains.recv() => {
+                // Writes sent before the drain are already in the channel;
+                // the queue may briefly hold up to twice its capacity
+                while let Ok(record) = queued.try_recv() {
+                    pending.push(record);
+                }
+                let _ = ack.send(write(inner.as_ref(), &mut pending).await);
+            }
+            _ = ticker.tick() => {
+                if let Err(e) = write(inner.as_ref(), &mut pending).await {
+                    tracing::warn!("Buffered memory write failed: {}", e);
+                }
+            }
+        }
+    }
+}
+
+/// Stores `pending` as one batch, keeping it for a retry on failure
+async fn write(inner: &dyn MemoryStore, pending: &mut Vec<MemoryRecord>) -> Result<()> {
+    if pending.is_empty() {
+        return Ok(());
+    }
+    let batch = std::mem::take(pending);
+    if let Err(e) = inner.store_batch(batch.clone()).await {
+        *pending = batch;
+        return Err(e);
+    }
+    Ok(())
+}
+
+#[async_trait::async_trait]
+impl MemoryStore for BufferedStore {
+    async fn store(&self, record: MemoryRecord) -> Result<()> {
+        self.writes.send(record).await.map_err(|_| worker_stopped())
+    }
+
+    async fn store_batch(&self, records: Vec<MemoryRecord>) -> Result<()> {
+        for record in records {
+            self.store(record).await?;
+        }
+        Ok(())
+    }
+
+    async fn retrieve(&self, session_id: &str, limit: usize) -> Result<Vec<MemoryRecord>> {
+        self.retrieve_filtered(session_id, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn retrieve_filtered(
+        &self,
+        session_id: &str,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.drain().await?;
+        self.inner
+            .retrieve_filtered(session_id, limit, filter)
+            .await
+    }
+
+    async fn retrieve_page(
+        &self,
+        session_id: &str,
+        cursor: Option<&str>,
+        page_size: usize,
+    ) -> Result<MemoryPage> {
+        self.drain().await?;
+        self.inner
+            .retrieve_page(session_id, cursor, page_size)
+            .await
+    }
+
+    fn export<'a>(&'a self, session_id: &'a str) -> BoxStream<'a, Result<MemoryRecord>> {
+        export_pages(self, session_id)
+    }
+
+    async fn search(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.search_filtered(session_id, query_embedding, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn search_filtered(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.drain().await?;
+        self.inner
+            .search_filtered(session_id, query_embedding, limit, filter)
+            .await
+    }
+
+    async fn get(&self, session_id: &str, id: Uuid) -> Result<Option<MemoryRecord>> {
+        self.drain().await?;
+        self.inner.get(session_id, id).await
+    }
+
+    async fn update(&self, record: MemoryRecord) -> Result<u64> {
+        self.drain().await?;
+        self.inner.update(record).await
+    }
+
+    async fn purge_expired(&self) -> Result<usize> {
+        self.drain().await?;
+        self.inner.purge_expired().await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.drain().await?;
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InMemoryStore;
+    use crate::testing::{ChaosConfig, ChaosStore};
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn record(i: usize) -> MemoryRecord {
+        MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: "test".to_string(),
+            role: "user".to_string(),
+            content: format!("message {}", i),
+            importance: 0.5,
+            timestamp: Utc::now(),
+            metadata: None,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_queued_until_drained() {
+        let inner = Arc::new(InMemoryStore::new());
+        let config = BufferConfig::new()
+            .with_batch_size(10)
+            .with_interval(Duration::from_secs(3600));
+        let buffered = BufferedStore::with_config(inner.clone(), config);
+
+        for i in 0..3 {
+            buffered.store(record(i)).await.unwrap();
+        }
+
+        tokio::task::yield_now().await;
+        assert!(inner.retrieve("test", 10).await.unwrap().is_empty());
+
+        // Reads drain the queue, so they see every earlier write
+        assert_eq!(buffered.retrieve("test", 10).await.unwrap().len(), 3);
+        assert_eq!(inner.retrieve("test", 10).await.unwrap().len(), 3);
+        buffered.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drain_fails_instead_of_blocking_while_store_is_down() {
+        let chaos = Arc::new(ChaosStore::new(
+            Arc::new(InMemoryStore::new()),
+            ChaosConfig::new().with_error_rate(1.0),
+        ));
+        let config = BufferConfig::new()
+            .with_batch_size(2)
+            .with_capacity(2)
+            .with_interval(Duration::from_secs(3600));
+        let buffered = BufferedStore::with_config(chaos.clone(), config);
+
+        // Fills the queue, then leaves one more write waiting in the channel
+        for i in 0..3 {
+            buffered.store(record(i)).await.unwrap();
+        }
+        tokio::task::yield_now().await;
+
+        let flushed = tokio::time::timeout(Duration::from_secs(5), buffered.flush()).await;
+        assert!(flushed.expect("drain blocked").is_err());
+
+        chaos.set_config(ChaosConfig::new());
+        buffered.flush().await.unwrap();
+        assert_eq!(chaos.retrieve("test", 10).await.unwrap().len(), 3);
+    }
+}
@@ -0,0 +1,518 @@
+//! In-process vector cache in front of a remote memory store.
+//!
+//! Every turn searches the session's memories, and with Postgres or Qdrant
+//! each search is a network round trip. [`CachedStore`] keeps an
+//! [`InMemoryStore`] per active session instead: the first search or
+//! retrieve of a session loads all its records from the backend, later ones
+//! are answered in-process from the HNSW index. Writes go to the backend
+//! first and are then applied to the cached copy, so the cache never serves
+//! a record the backend doesn't have. Updates are applied by version, so a
+//! late one never replaces a newer copy; a store over a cached record, whose
+//! new version only the backend knows, drops the session's cache instead.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::stream::{BoxStream, TryStreamExt};
+use uuid::Uuid;
+
+use crate::error::Result;
+use crate::memory::{
+    export_pages, InMemoryStore, MemoryFilter, MemoryPage, MemoryRecord, MemoryStore,
+    SimilarityMetric,
+};
+
+/// Limits and ranking of the cache of a [`CachedStore`]
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    max_sessions: usize,
+    max_records: usize,
+    idle_ttl: Option<Duration>,
+    metric: SimilarityMetric,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_sessions: 64,
+            max_records: 10_000,
+            idle_ttl: Some(Duration::from_secs(15 * 60)),
+            metric: SimilarityMetric::default(),
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Caches at most this many sessions, evicting the least recently used
+    pub fn with_max_sessions(mut self, max_sessions: usize) -> Self {
+        self.max_sessions = max_sessions.max(1);
+        self
+    }
+
+    /// Leaves sessions with more records than this uncached
+    pub fn with_max_records(mut self, max_records: usize) -> Self {
+        self.max_records = max_records;
+        self
+    }
+
+    /// Drops a session's cache after it goes unused this long; `None` keeps
+    /// it until evicted
+    pub fn with_idle_ttl(mut self, idle_ttl: Option<Duration>) -> Self {
+        self.idle_ttl = idle_ttl;
+        self
+    }
+
+    /// Ranks cached searches by `metric`; match the backend's metric
+    pub fn with_metric(mut self, metric: SimilarityMetric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Returns the maximum number of cached sessions
+    pub fn max_sessions(&self) -> usize {
+        self.max_sessions
+    }
+
+    /// Returns the maximum number of records of a cached session
+    pub fn max_records(&self) -> usize {
+        self.max_records
+    }
+
+    /// Returns how long an unused session stays cached
+    pub fn idle_ttl(&self) -> Option<Duration> {
+        self.idle_ttl
+    }
+
+    /// Returns the similarity metric of cached searches
+    pub fn metric(&self) -> SimilarityMetric {
+        self.metric
+    }
+}
+
+/// Cache counters of a [`CachedStore`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Reads answered from an already loaded session
+    pub hits: u64,
+    /// Reads that loaded the session or went to the backend
+    pub misses: u64,
+    /// Sessions loaded from the backend
+    pub hydrations: u64,
+}
+
+struct CachedSession {
+    // None once the session outgrew `max_records`; reads then go to the backend
+    records: Option<Arc<InMemoryStore>>,
+    last_used: Instant,
+}
+
+#[derive(Default)]
+struct CacheState {
+    sessions: HashMap<String, CachedSession>,
+    // Sessions being loaded, and whether a write hit them meanwhile
+    hydrating: HashMap<String, bool>,
+}
+
+/// A [`MemoryStore`] wrapper caching the records of active sessions
+/// in-process.
+///
+/// Meant for backends that are the only writer of their sessions' records:
+/// writes made to the backend directly are not seen until the session is
+/// evicted or [`invalidate`](CachedStore::invalidate)d. Paging and export
+/// always read the backend.
+pub struct CachedStore {
+    inner: Arc<dyn MemoryStore>,
+    config: CacheConfig,
+    state: parking_lot::Mutex<CacheState>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    hydrations: AtomicU64,
+}
+
+impl CachedStore {
+    /// Caches `inner` with the default configuration
+    pub fn new(inner: Arc<dyn MemoryStore>) -> Self {
+        Self::with_config(inner, CacheConfig::default())
+    }
+
+    /// Caches `inner` with the given configuration
+    pub fn with_config(inner: Arc<dyn MemoryStore>, config: CacheConfig) -> Self {
+        Self {
+            inner,
+            config,
+            state: parking_lot::Mutex::new(CacheState::default()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            hydrations: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns the cache configuration
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    /// Returns the cache counters
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            hydrations: self.hydrations.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Returns true if `session_id` is cached
+    pub fn is_cached(&self, session_id: &str) -> bool {
+        self.state
+            .lock()
+            .sessions
+            .get(session_id)
+            .is_some_and(|session| session.records.is_some())
+    }
+
+    /// Drops the cache of `session_id`; the next read loads it again
+    pub fn invalidate(&self, session_id: &str) {
+        let mut state = self.state.lock();
+        state.sessions.remove(session_id);
+        if let Some(dirty) = state.hydrating.get_mut(session_id) {
+            *dirty = true;
+        }
+    }
+
+    /// Drops every cached session
+    pub fn clear(&self) {
+        let mut state = self.state.lock();
+        state.sessions.clear();
+        state.hydrating.values_mut().for_each(|dirty| *dirty = true);
+    }
+
+    /// Returns the cache of `session_id`, loading it on first access, or
+    /// `None` if reads must go to the backend
+    async fn cached(&self, session_id: &str) -> Result<Option<Arc<InMemoryStore>>> {
+        let cached = {
+            let mut state = self.state.lock();
+            let fresh = state.sessions.get(session_id).map(|session| {
+                self.config
+                    .idle_ttl
+                    .is_none_or(|ttl| session.last_used.elapsed() < ttl)
+            });
+            match fresh {
+                Some(true) => {
+                    let session = state.sessions.get_mut(session_id).expect("checked above");
+                    session.last_used = Instant::now();
+                    Some(session.records.clone())
+                }
+                // Another read is loading it; don't wait for that
+                _ if state.hydrating.contains_key(session_id) => Some(None),
+                fresh => {
+                    if fresh.is_some() {
+                        state.sessions.remove(session_id);
+                    }
+                    state.hydrating.insert(session_id.to_string(), false);
+                    None
+                }
+            }
+        };
+        if let Some(records) = cached {
+            return Ok(self.count(records));
+        }
+
+        let hydrated = self.hydrate(session_id).await;
+        let mut state = self.state.lock();
+        let dirty = state.hydrating.remove(session_id).unwrap_or(true);
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        let records = hydrated?;
+        self.hydrations.fetch_add(1, Ordering::Relaxed);
+        if dirty {
+            // A write raced the load, so the snapshot may be stale
+            return Ok(None);
+        }
+
+        state.sessions.insert(
+            session_id.to_string(),
+            CachedSession {
+                records: records.clone(),
+                last_used: Instant::now(),
+            },
+        );
+        while state.sessions.len() > self.config.max_sessions {
+            let oldest = state
+                .sessions
+                .iter()
+                .min_by_key(|(_, session)| session.last_used)
+                .map(|(id, _)| id.clone());
+            match oldest {
+                Some(oldest) => state.sessions.remove(&oldest),
+                None => break,
+            };
+        }
+        Ok(records)
+    }
+
+    fn count(&self, records: Option<Arc<InMemoryStore>>) -> Option<Arc<InMemoryStore>> {
+        let counter = if records.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        records
+    }
+
+    /// Loads every record of `session_id`, or `None` if there are too many
+    async fn hydrate(&self, session_id: &str) -> Result<Option<Arc<InMemoryStore>>> {
+        let mut records = Vec::new();
+        let mut exported = self.inner.export(session_id);
+        while let Some(record) = exported.try_next().await? {
+            if records.len() == self.config.max_records {
+                return Ok(None);
+            }
+            records.push(record);
+        }
+
+        let store = InMemoryStore::new().with_metric(self.config.metric);
+        store.store_batch(records).await?;
+        Ok(Some(Arc::new(store)))
+    }
+
+    /// Applies records already written to the backend to the cache, one id
+    /// at a time. `updated` records carry the version the backend gave them
+    /// and replace older copies only; stored ones are only added, and a
+    /// cached copy of one drops the session's cache.
+    fn apply(&self, records: Vec<MemoryRecord>, updated: bool) {
+        for record in records {
+            let session_id = record.session_id.clone();
+            let cached = {
+                let mut state = self.state.lock();
+                if let Some(dirty) = state.hydrating.get_mut(&session_id) {
+                    *dirty = true;
+                }
+                state
+                    .sessions
+                    .get(&session_id)
+                    .and_then(|session| session.records.clone())
+            };
+            let Some(cached) = cached else {
+                continue;
+            };
+            let version = record.version;
+            let applied = cached.put_unless(record, true, |existing| {
+                !updated || existing.version >= version
+            });
+            if !applied && !updated {
+                self.invalidate(&session_id);
+            }
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl MemoryStore for CachedStore {
+    async fn store(&self, record: MemoryRecord) -> Result<()> {
+        self.inner.store(record.clone()).await?;
+        self.apply(vec![record], false);
+        Ok(())
+    }
+
+    async fn retrieve(&self, session_id: &str, limit: usize) -> Result<Vec<MemoryRecord>> {
+        self.retrieve_filtered(session_id, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn retrieve_filtered(
+        &self,
+        session_id: &str,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        match self.cached(session_id).await? {
+            Some(cached) => cached.retrieve_filtered(session_id, limit, filter).await,
+            None => {
+                self.inner
+                    .retrieve_filtered(session_id, limit, filter)
+                    .await
+            }
+        }
+    }
+
+    async fn retrieve_page(
+        &self,
+        session_id: &str,
+        cursor: Option<&str>,
+        page_size: usize,
+    ) -> Result<MemoryPage> {
+        self.inner
+            .retrieve_page(session_id, cursor, page_size)
+            .await
+    }
+
+    fn export<'a>(&'a self, session_id: &'a str) -> BoxStream<'a, Result<MemoryRecord>> {
+        export_pages(self, session_id)
+    }
+
+    async fn search(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.search_filtered(session_id, query_embedding, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn search_filtered(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        match self.cached(session_id).await? {
+            Some(cached) => {
+                cached
+                    .search_filtered(session_id, query_embedding, limit, filter)
+                    .await
+            }
+            None => {
+                self.inner
+                    .search_filtered(session_id, query_embedding, limit, filter)
+                    .await
+            }
+        }
+    }
+
+    async fn get(&self, session_id: &str, id: Uuid) -> Result<Option<MemoryRecord>> {
+        self.inner.get(session_id, id).await
+    }
+
+    async fn update(&self, mut record: MemoryRecord) -> Result<u64> {
+        let version = self.inner.update(record.clone()).await?;
+        record.version = version;
+        self.apply(vec![record], true);
+        Ok(version)
+    }
+
+    async fn purge_expired(&self) -> Result<usize> {
+        let purged = self.inner.purge_expired().await?;
+        let cached: Vec<Arc<InMemoryStore>> = self
+            .state
+            .lock()
+            .sessions
+            .values()
+            .filter_map(|session| session.records.clone())
+            .collect();
+        // The backend is purged; a cache failing to follow is no reason to
+        // fail the call
+        for cached in cached {
+            if let Err(e) = cached.purge_expired().await {
+                tracing::warn!("Purging cached memories failed: {}", e);
+            }
+        }
+        Ok(purged)
+    }
+
+    async fn store_batch(&self, records: Vec<MemoryRecord>) -> Result<()> {
+        self.inner.store_batch(records.clone()).await?;
+        self.apply(records, false);
+        Ok(())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn record(session_id: &str, content: &str) -> MemoryRecord {
+        MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: session_id.to_string(),
+            role: "user".to_string(),
+            content: content.to_string(),
+            importance: 0.5,
+            timestamp: Utc::now(),
+            metadata: None,
+            embedding: Some(vec![1.0, 0.0]),
+            version: 0,
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sessions_hydrate_once_and_follow_writes() {
+        let inner = Arc::new(InMemoryStore::new());
+        inner.store(record("a", "from before")).await.unwrap();
+        let cached =
+            CachedStore::with_config(inner.clone(), CacheConfig::new().with_max_sessions(1));
+        let filter = MemoryFilter::default();
+
+        let found = cached
+            .search_filtered("a", vec![1.0, 0.0], 10, &filter)
+            .await
+            .unwrap();
+        assert_eq!(found.len(), 1);
+        assert!(cached.is_cached("a"));
+
+        // Writes through the cache show up; writes around it don't
+        let mut written = record("a", "new");
+        cached.store(written.clone()).await.unwrap();
+        written.content = "edited".to_string();
+        assert_eq!(cached.update(written).await.unwrap(), 1);
+        inner.store(record("a", "behind its back")).await.unwrap();
+        let found = cached.retrieve("a", 10).await.unwrap();
+        assert_eq!(found.len(), 2);
+        assert!(found
+            .iter()
+            .any(|r| r.content == "edited" && r.version == 1));
+
+        let stats = cached.stats();
+        assert_eq!((stats.hits, stats.misses, stats.hydrations), (1, 1, 1));
+
+        // A second session evicts the first
+        cached
+            .search_filtered("b", vec![1.0, 0.0], 10, &filter)
+            .await
+            .unwrap();
+        assert!(!cached.is_cached("a"));
+        assert_eq!(cached.retrieve("a", 10).await.unwrap().len(), 3);
+        assert_eq!(cached.stats().hydrations, 3);
+    }
+
+    #[tokio::test]
+    async fn test_applies_writes_by_version() {
+        let inner = Arc::new(InMemoryStore::new());
+        let cached = CachedStore::new(inner.clone());
+        let mut written = record("a", "v0");
+        cached.store(written.clone()).await.unwrap();
+        cached.retrieve("a", 10).await.unwrap();
+
+        written.content = "v1".to_string();
+        let stale = MemoryRecord {
+            content: "late".to_string(),
+            ..written.clone()
+        };
+        cached.update(written.clone()).await.unwrap();
+        // An update arriving after a newer one is ignored
+        cached.apply(vec![stale], true);
+        let found = cached.retrieve("a", 10).await.unwrap();
+        assert_eq!((found[0].content.as_str(), found[0].version), ("v1", 1));
+
+        // The backend bumps the version of a store over the record, so the
+        // cache can't follow and lets go of the session
+        cached.store(written).await.unwrap();
+        assert!(!cached.is_cached("a"));
+        let found = cached.retrieve("a", 10).await.unwrap();
+        assert_eq!(found[0].version, 2);
+    }
+}
@@ -0,0 +1,138 @@
+//! LLM-driven compaction of session history.
+//!
+//! Instead of silently dropping the oldest short-term records once a session
+//! outgrows its context window, a [`Compactor`] asks a model to condense them
+//! into one summary record that takes their place. Long-term stores keep the
+//! original records; only the short-term view is compacted.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::error::{AgentError, Result};
+use crate::memory::MemoryRecord;
+use crate::models::LLM;
+use crate::tenant::TENANT_METADATA_KEY;
+use crate::types::{Message, Role};
+
+/// Role given to summary records produced by compaction
+pub const SUMMARY_ROLE: &str = "summary";
+/// Metadata key holding how many records a summary replaced
+pub const SUMMARIZED_COUNT_KEY: &str = "summarized_count";
+
+/// Records condensed per compaction unless configured otherwise
+pub const DEFAULT_COMPACTION_BATCH: usize = 8;
+
+const SUMMARY_PREFIX: &str = "Summary of earlier conversation:";
+
+const SUMMARY_INSTRUCTIONS: &str = "Summarize the conversation excerpt below for your own future reference. \
+Keep facts, decisions, user preferences, open questions, and tool results that later turns may rely on. \
+Write plain prose without preamble.";
+
+/// Summarizes batches of memory records with a model
+pub struct Compactor {
+    model: Arc<dyn LLM>,
+    batch_size: usize,
+    pin_importance: Option<f32>,
+}
+
+impl Compactor {
+    /// Creates a compactor condensing [`DEFAULT_COMPACTION_BATCH`] records at a time
+    pub fn new(model: Arc<dyn LLM>) -> Self {
+        Self {
+            model,
+            batch_size: DEFAULT_COMPACTION_BATCH,
+            pin_importance: None,
+        }
+    }
+
+    /// Sets how many of the oldest records are condensed per compaction
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(2);
+        self
+    }
+
+    /// Keeps records with at least this importance verbatim instead of summarizing them
+    pub fn with_pin_importance(mut self, importance: f32) -> Self {
+        self.pin_importance = Some(importance);
+        self
+    }
+
+    /// Returns the configured batch size
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    /// Returns true if `record` is important enough to be exempt from compaction
+    pub fn is_pinned(&self, record: &MemoryRecord) -> bool {
+        self.pin_importance
+            .is_some_and(|threshold| record.importance >= threshold)
+    }
+
+    /// Condenses `records` (oldest first) into a single summary record.
+    ///
+    /// The summary inherits the newest timestamp and highest importance of the
+    /// batch so it sorts and ranks where the originals did.
+    pub async fn summarize(&self, records: &[MemoryRecord]) -> Result<MemoryRecord> {
+        let last = records
+            .last()
+            .ok_or_else(|| AgentError::MemoryError("Nothing to summarize".to_string()))?;
+
+        let transcript = records
+            .iter()
+            .map(|r| format!("{}: {}", r.role, r.content))
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let messages = vec![
+            Message {
+                role: Role::System,
+                content: SUMMARY_INSTRUCTIONS.to_string(),
+                metadata: None,
+            },
+            Message {
+                role: Role::User,
+                content: transcript,
+                metadata: None,
+            },
+        ];
+        let response = self.model.generate(messages, None).await?;
+
+        // Re-summarized summaries count every record they originally replaced
+        let summarized: usize = records
+            .iter()
+            .map(|r| {
+                r.metadata
+                    .as_ref()
+                    .and_then(|m| m.get(SUMMARIZED_COUNT_KEY))
+                    .and_then(|count| count.parse::<usize>().ok())
+                    .unwrap_or(1)
+            })
+            .sum();
+
+        let mut metadata =
+            HashMap::from([(SUMMARIZED_COUNT_KEY.to_string(), summarized.to_string())]);
+        // Keep the tenant stamp so tenant-scoped reads still accept the summary
+        if let Some(tenant) = last
+            .metadata
+            .as_ref()
+            .and_then(|m| m.get(TENANT_METADATA_KEY))
+        {
+            metadata.insert(TENANT_METADATA_KEY.to_string(), tenant.clone());
+        }
+
+        Ok(MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: last.session_id.clone(),
+            role: SUMMARY_ROLE.to_string(),
+            content: format!("{}\n{}", SUMMARY_PREFIX, response.content.trim()),
+            importance: records.iter().map(|r| r.importance).fold(0.0, f32::max),
+            timestamp: last.timestamp,
+            metadata: Some(metadata),
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        })
+    }
+}
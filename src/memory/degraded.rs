@@ -0,0 +1,643 @@
+//! Graceful degradation when the long-term store is down.
+//!
+//! [`DegradableStore`] keeps an agent serving while its backing store (say,
+//! Postgres) is unreachable. The first failure that looks like an outage
+//! switches it to degraded mode: writes are queued for replay, recent-memory
+//! reads and searches are answered from the queued writes so the agent
+//! carries on with the short-term cache of
+//! [`SessionMemory`](super::SessionMemory) and what it wrote meanwhile, and
+//! a [`MemoryHealth`] event is broadcast. Once the retry interval passes,
+//! the next call replays the queue; if that succeeds the store is healthy
+//! again. Failures that would recur on retry, such as a record the store
+//! rejects, are returned instead, and queued writes failing that way are
+//! set aside as [`DeadLetter`]s rather than blocking the queue.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::stream::BoxStream;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+use crate::error::{AgentError, Result};
+use crate::memory::{
+    cosine_similarity, export_pages, MemoryFilter, MemoryPage, MemoryRecord, MemoryStore,
+};
+
+/// Health transitions of a [`DegradableStore`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MemoryHealth {
+    /// The store failed; memory is short-term only until it recovers
+    Degraded { error: String },
+    /// The store is back and the queued writes were replayed
+    Recovered { replayed: usize },
+    /// The store rejected a queued write for good; it was set aside
+    DeadLettered { id: Uuid, error: String },
+}
+
+/// A queued write the store rejected with a permanent error
+#[derive(Debug, Clone)]
+pub struct DeadLetter {
+    pub record: MemoryRecord,
+    pub error: String,
+}
+
+/// Retry and queueing settings for [`DegradableStore`]
+#[derive(Debug, Clone)]
+pub struct DegradationConfig {
+    retry_interval: Duration,
+    queue_capacity: usize,
+    classify: fn(&AgentError) -> bool,
+}
+
+impl Default for DegradationConfig {
+    fn default() -> Self {
+        Self {
+            retry_interval: Duration::from_secs(5),
+            queue_capacity: 1024,
+            classify: AgentError::is_transient,
+        }
+    }
+}
+
+impl DegradationConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Waits this long after a failure before trying the store again
+    pub fn with_retry_interval(mut self, retry_interval: Duration) -> Self {
+        self.retry_interval = retry_interval;
+        self
+    }
+
+    /// Bounds how many writes are queued, and how many dead letters are
+    /// kept; the oldest are dropped beyond it
+    pub fn with_queue_capacity(mut self, queue_capacity: usize) -> Self {
+        self.queue_capacity = queue_capacity.max(1);
+        self
+    }
+
+    /// Decides which errors mean the store is down, `AgentError::is_transient`
+    /// by default. Other errors fail the call, or dead-letter a queued write,
+    /// without degrading.
+    pub fn with_classifier(mut self, is_transient: fn(&AgentError) -> bool) -> Self {
+        self.classify = is_transient;
+        self
+    }
+
+    /// Returns the configured retry interval
+    pub fn retry_interval(&self) -> Duration {
+        self.retry_interval
+    }
+
+    /// Returns the configured queue capacity
+    pub fn queue_capacity(&self) -> usize {
+        self.queue_capacity
+    }
+
+    /// Returns true if `error` means the store is down
+    pub fn is_transient(&self, error: &AgentError) -> bool {
+        (self.classify)(error)
+    }
+}
+
+#[derive(Default)]
+struct State {
+    // When the store last failed, while degraded
+    failed_at: Option<Instant>,
+    queue: VecDeque<MemoryRecord>,
+    dead_letters: VecDeque<DeadLetter>,
+}
+
+/// A [`MemoryStore`] wrapper that degrades to short-term memory instead of
+/// failing while the wrapped store is down.
+///
+/// Updates are compare-and-swap and can't be queued, so they still fail
+/// while degraded, as do page reads, which would otherwise pass an outage
+/// off as the end of the history. Replays hold off writes, so queued
+/// records always land before newer ones. `flush` replays the queue right
+/// away and reports whether that worked.
+pub struct DegradableStore {
+    inner: Arc<dyn MemoryStore>,
+    config: DegradationConfig,
+    state: parking_lot::Mutex<State>,
+    // Writes share it; replays take it alone
+    gate: tokio::sync::RwLock<()>,
+    events: broadcast::Sender<MemoryHealth>,
+}
+
+impl DegradableStore {
+    /// Wraps `inner` with the default configuration
+    pub fn new(inner: Arc<dyn MemoryStore>) -> Self {
+        Self::with_config(inner, DegradationConfig::default())
+    }
+
+    /// Wraps `inner` with the given configuration
+    pub fn with_config(inner: Arc<dyn MemoryStore>, config: DegradationConfig) -> Self {
+        let (events, _) = broadcast::channel(16);
+        Self {
+            inner,
+            config,
+            state: parking_lot::Mutex::new(State::default()),
+            gate: tokio::sync::RwLock::new(()),
+            events,
+        }
+    }
+
+    /// Returns the degradation configuration
+    pub fn config(&self) -> &DegradationConfig {
+        &self.config
+    }
+
+    /// Subscribes to health transitions
+    pub fn subscribe(&self) -> broadcast::Receiver<MemoryHealth> {
+        self.events.subscribe()
+    }
+
+    /// Returns whether the store is running on short-term memory only
+    pub fn is_degraded(&self) -> bool {
+        self.state.lock().failed_at.is_some()
+    }
+
+    /// Returns how many writes wait for replay
+    pub fn queued(&self) -> usize {
+        self.state.lock().queue.len()
+    }
+
+    /// Returns the queued writes the store rejected for good, oldest first
+    pub fn dead_letters(&self) -> Vec<DeadLetter> {
+        self.state.lock().dead_letters.iter().cloned().collect()
+    }
+
+    /// Removes and returns the dead letters, e.g. to repair and store them
+    pub fn take_dead_letters(&self) -> Vec<DeadLetter> {
+        self.state.lock().dead_letters.drain(..).collect()
+    }
+
+    /// Writes queued records to the wrapped store, leaving degraded mode on
+    /// success. Returns how many records were replayed.
+    pub async fn replay(&self) -> Result<usize> {
+        let _exclusive = self.gate.write().await;
+        self.replay_locked().await
+    }
+
+    /// Replays the queue; the caller holds the gate exclusively, so
+    /// nothing is queued meanwhile
+    async fn replay_locked(&self) -> Result<usize> {
+        let batch: Vec<MemoryRecord> = self.state.lock().queue.iter().cloned().collect();
+        let mut replayed = batch.len();
+        if batch.is_empty() {
+            self.recover(0);
+            return Ok(0);
+        }
+        match self.inner.store_batch(batch.clone()).await {
+            Ok(()) => self.state.lock().queue.clear(),
+            Err(e) if self.config.is_transient(&e) => {
+                self.degrade(&e);
+                return Err(e);
+            }
+            Err(_) => {
+                // Some record is poison; replay one at a time to set it aside
+                for record in batch {
+                    match self.inner.store(record.clone()).await {
+                        Ok(()) => {}
+                        Err(e) if self.config.is_transient(&e) => {
+                            self.degrade(&e);
+                            return Err(e);
+                        }
+                        Err(e) => {
+                            replayed -= 1;
+                            self.dead_letter(record, &e);
+                        }
+                    }
+                    self.state.lock().queue.pop_front();
+                }
+            }
+        }
+        self.recover(replayed);
+        Ok(replayed)
+    }
+
+    /// Returns true while the store failed too recently to be retried
+    fn waiting(&self) -> bool {
+        let state = self.state.lock();
+        state
+            .failed_at
+            .is_some_and(|failed_at| failed_at.elapsed() < self.config.retry_interval)
+    }
+
+    fn has_queue(&self) -> bool {
+        !self.state.lock().queue.is_empty()
+    }
+
+    /// Runs `call` against the wrapped store, returning `None` instead of
+    /// failing while the store is down
+    async fn try_inner<T>(&self, call: impl Future<Output = Result<T>>) -> Result<Option<T>> {
+        if self.waiting() {
+            return Ok(None);
+        }
+        // With nothing to replay, the call itself probes the store
+        if self.has_queue() && self.replay().await.is_err() {
+            return Ok(None);
+        }
+
+        match call.await {
+            Ok(value) => {
+                self.recover(0);
+                Ok(Some(value))
+            }
+            Err(e) if self.config.is_transient(&e) => {
+                self.degrade(&e);
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Enters (or stays in) degraded mode after `error`
+    fn degrade(&self, error: &AgentError) {
+        let entered = self
+            .state
+            .lock()
+            .failed_at
+            .replace(Instant::now())
+            .is_none();
+        if entered {
+            tracing::warn!(
+                "Memory store unavailable, using short-term memory: {}",
+                error
+            );
+            let _ = self.events.send(MemoryHealth::Degraded {
+                error: error.to_string(),
+            });
+        }
+    }
+
+    /// Leaves degraded mode once nothing waits for replay
+    fn recover(&self, replayed: usize) {
+        let recovered = {
+            let mut state = self.state.lock();
+            state.queue.is_empty() && state.failed_at.take().is_some()
+        };
+        if recovered {
+            tracing::info!(replayed, "Memory store recovered");
+            let _ = self.events.send(MemoryHealth::Recovered { replayed });
+        }
+    }
+
+    fn enqueue(&self, records: Vec<MemoryRecord>) {
+        let mut state = self.state.lock();
+        state.queue.extend(records);
+        let overflow = state.queue.len().saturating_sub(self.config.queue_capacity);
+        if overflow > 0 {
+            state.queue.drain(..overflow);
+            tracing::warn!(dropped = overflow, "Memory replay queue full");
+        }
+    }
+
+    /// Sets aside a queued `record` the store rejected with `error`
+    fn dead_letter(&self, record: MemoryRecord, error: &AgentError) {
+        tracing::warn!(id = %record.id, "Memory store rejected a queued write: {}", error);
+        let _ = self.events.send(MemoryHealth::DeadLettered {
+            id: record.id,
+            error: error.to_string(),
+        });
+        let mut state = self.state.lock();
+        state.dead_letters.push_back(DeadLetter {
+            record,
+            error: error.to_string(),
+        });
+        if state.dead_letters.len() > self.config.queue_capacity {
+            state.dead_letters.pop_front();
+        }
+    }
+
+    /// Queued records of `session_id` matching `filter`, newest first
+    fn queued_for(&self, session_id: &str, filter: &MemoryFilter) -> Vec<MemoryRecord> {
+        let now = chrono::Utc::now();
+        let state = self.state.lock();
+        state
+            .queue
+            .iter()
+            .rev()
+            .filter(|r| r.session_id == session_id && !r.is_expired_at(now) && filter.matches(r))
+            .cloned()
+            .collect()
+    }
+}
+
+#[async_trait::async_trait]
+impl MemoryStore for DegradableStore {
+    async fn store(&self, record: MemoryRecord) -> Result<()> {
+        self.store_batch(vec![record]).await
+    }
+
+    async fn store_batch(&self, records: Vec<MemoryRecord>) -> Result<()> {
+        // Writes go ahead together, but wait for the queue to be replayed
+        // first so they land after it
+        let shared = self.gate.read().await;
+        let (_shared, exclusive) = if self.has_queue() {
+            drop(shared);
+            (None, Some(self.gate.write().await))
+        } else {
+            (Some(shared), None)
+        };
+        if self.waiting() {
+            self.enqueue(records);
+            return Ok(());
+        }
+        if exclusive.is_some() && self.has_queue() && self.replay_locked().await.is_err() {
+            self.enqueue(records);
+            return Ok(());
+        }
+
+        match self.inner.store_batch(records.clone()).await {
+            Ok(()) => {
+                self.recover(0);
+                Ok(())
+            }
+            Err(e) if self.config.is_transient(&e) => {
+                self.degrade(&e);
+                self.enqueue(records);
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn retrieve(&self, session_id: &str, limit: usize) -> Result<Vec<MemoryRecord>> {
+        self.retrieve_filtered(session_id, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn retrieve_filtered(
+        &self,
+        session_id: &str,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        let retrieved = self
+            .try_inner(self.inner.retrieve_filtered(session_id, limit, filter))
+            .await?;
+        Ok(retrieved.unwrap_or_else(|| {
+            let mut queued = self.queued_for(session_id, filter);
+            queued.truncate(limit);
+            queued
+        }))
+    }
+
+    async fn retrieve_page(
+        &self,
+        session_id: &str,
+        cursor: Option<&str>,
+        page_size: usize,
+    ) -> Result<MemoryPage> {
+        self.try_inner(self.inner.retrieve_page(session_id, cursor, page_size))
+            .await?
+            .ok_or_else(|| {
+                AgentError::MemoryError(
+                    "Memory store is degraded; history pages are unavailable".to_string(),
+                )
+            })
+    }
+
+    fn export<'a>(&'a self, session_id: &'a str) -> BoxStream<'a, Result<MemoryRecord>> {
+        export_pages(self, session_id)
+    }
+
+    async fn search(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.search_filtered(session_id, query_embedding, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn search_filtered(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        let found = self
+            .try_inner(self.inner.search_filtered(
+                session_id,
+                query_embedding.clone(),
+                limit,
+                filter,
+            ))
+            .await?;
+        if let Some(found) = found {
+            return Ok(found);
+        }
+        let mut scored: Vec<(f32, MemoryRecord)> = self
+            .queued_for(session_id, filter)
+            .into_iter()
+            .filter_map(|r| {
+                let score = cosine_similarity(r.embedding.as_deref()?, &query_embedding);
+                Some((score, r))
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        Ok(scored.into_iter().take(limit).map(|(_, r)| r).collect())
+    }
+
+    async fn get(&self, session_id: &str, id: Uuid) -> Result<Option<MemoryRecord>> {
+        let found = self.try_inner(self.inner.get(session_id, id)).await?;
+        Ok(found.unwrap_or_else(|| {
+            self.queued_for(session_id, &MemoryFilter::default())
+                .into_iter()
+                .find(|record| record.id == id)
+        }))
+    }
+
+    async fn update(&self, record: MemoryRecord) -> Result<u64> {
+        // A version conflict is not an outage, so updates bypass `try_inner`
+        if self.is_degraded() {
+            return Err(AgentError::MemoryError(
+                "Memory store is degraded; updates are unavailable".to_string(),
+            ));
+        }
+        self.inner.update(record).await
+    }
+
+    async fn purge_expired(&self) -> Result<usize> {
+        Ok(self
+            .try_inner(self.inner.purge_expired())
+            .await?
+            .unwrap_or_default())
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.replay().await?;
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    use super::*;
+    use crate::memory::InMemoryStore;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    /// In-memory store that can be switched off
+    struct FlakyStore {
+        inner: InMemoryStore,
+        down: AtomicBool,
+    }
+
+    impl FlakyStore {
+        fn check(&self) -> Result<()> {
+            if self.down.load(Ordering::SeqCst) {
+                return Err(AgentError::MemoryError("connection refused".to_string()));
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl MemoryStore for FlakyStore {
+        async fn store(&self, record: MemoryRecord) -> Result<()> {
+            self.check()?;
+            if record.content == "poison" {
+                return Err(AgentError::InvalidState("malformed record".to_string()));
+            }
+            self.inner.store(record).await
+        }
+
+        async fn retrieve(&self, session_id: &str, limit: usize) -> Result<Vec<MemoryRecord>> {
+            self.check()?;
+            self.inner.retrieve(session_id, limit).await
+        }
+
+        async fn retrieve_page(
+            &self,
+            session_id: &str,
+            cursor: Option<&str>,
+            page_size: usize,
+        ) -> Result<MemoryPage> {
+            self.check()?;
+            self.inner
+                .retrieve_page(session_id, cursor, page_size)
+                .await
+        }
+
+        async fn search(
+            &self,
+            session_id: &str,
+            query_embedding: Vec<f32>,
+            limit: usize,
+        ) -> Result<Vec<MemoryRecord>> {
+            self.search_filtered(session_id, query_embedding, limit, &MemoryFilter::default())
+                .await
+        }
+
+        async fn search_filtered(
+            &self,
+            session_id: &str,
+            query_embedding: Vec<f32>,
+            limit: usize,
+            filter: &MemoryFilter,
+        ) -> Result<Vec<MemoryRecord>> {
+            self.check()?;
+            self.inner
+                .search_filtered(session_id, query_embedding, limit, filter)
+                .await
+        }
+
+        async fn update(&self, record: MemoryRecord) -> Result<u64> {
+            self.check()?;
+            self.inner.update(record).await
+        }
+
+        async fn purge_expired(&self) -> Result<usize> {
+            self.check()?;
+            self.inner.purge_expired().await
+        }
+
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    fn record(content: &str) -> MemoryRecord {
+        MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: "s".to_string(),
+            role: "user".to_string(),
+            content: content.to_string(),
+            importance: 0.5,
+            timestamp: Utc::now(),
+            metadata: None,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queues_writes_while_down_and_replays() {
+        let flaky = Arc::new(FlakyStore {
+            inner: InMemoryStore::new(),
+            down: AtomicBool::new(true),
+        });
+        let config = DegradationConfig::new().with_retry_interval(Duration::ZERO);
+        let store = DegradableStore::with_config(flaky.clone(), config);
+        let mut events = store.subscribe();
+
+        store.store(record("hello")).await.unwrap();
+        assert!(store.is_degraded());
+        assert_eq!(store.queued(), 1);
+        assert!(matches!(
+            events.try_recv(),
+            Ok(MemoryHealth::Degraded { .. })
+        ));
+
+        // Reads fall back to the queue, but pages can't
+        assert_eq!(store.retrieve("s", 10).await.unwrap().len(), 1);
+        assert!(store.retrieve_page("s", None, 10).await.is_err());
+
+        flaky.down.store(false, Ordering::SeqCst);
+        assert_eq!(store.retrieve("s", 10).await.unwrap().len(), 1);
+        assert!(!store.is_degraded());
+        assert_eq!(
+            events.try_recv().unwrap(),
+            MemoryHealth::Recovered { replayed: 1 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dead_letters_rejected_writes_on_replay() {
+        let flaky = Arc::new(FlakyStore {
+            inner: InMemoryStore::new(),
+            down: AtomicBool::new(true),
+        });
+        let config = DegradationConfig::new().with_retry_interval(Duration::ZERO);
+        let store = DegradableStore::with_config(flaky.clone(), config);
+        for content in ["first", "poison", "last"] {
+            store.store(record(content)).await.unwrap();
+        }
+        assert_eq!(store.queued(), 3);
+
+        flaky.down.store(false, Ordering::SeqCst);
+        assert_eq!(store.replay().await.unwrap(), 2);
+        assert!(!store.is_degraded());
+        let dead = store.take_dead_letters();
+        assert_eq!(dead.len(), 1);
+        assert_eq!(dead[0].record.content, "poison");
+
+        // Rejections outside a replay fail the call without degrading
+        assert!(store.store(record("poison")).await.is_err());
+        assert!(!store.is_degraded());
+        assert_eq!(store.retrieve("s", 10).await.unwrap().len(), 2);
+    }
+}
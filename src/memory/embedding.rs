@@ -0,0 +1,645 @@
+//! Text embedding providers for semantic retrieval.
+//!
+//! [`VertexEmbedder`] calls Vertex AI, `BedrockEmbedder` (feature `bedrock`)
+//! calls Titan or Cohere models on AWS Bedrock, and `FastEmbedder` (feature
+//! `memory`) runs locally. Match the store's vector size to the model's
+//! output, e.g. `PostgresConfig::with_dimension(768)` for Vertex defaults.
+//! [`PrefixedEmbedder`] adds the query and document prefixes and the L2
+//! normalization that models such as E5 expect.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::error::{AgentError, Result};
+
+/// Turns text into embedding vectors for memory search
+#[async_trait]
+pub trait Embedder: Send + Sync {
+    /// Embeds a single text
+    async fn embed(&self, text: &str) -> Result<Vec<f32>>;
+
+    /// Embeds several texts, returning one vector per text in order.
+    ///
+    /// The default embeds them one at a time; override it when the backend
+    /// can embed a batch in one call.
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for text in texts {
+            embeddings.push(self.embed(text).await?);
+        }
+        Ok(embeddings)
+    }
+
+    /// Embeds a search query; `embed` is used for the documents searched.
+    ///
+    /// The default embeds queries like documents. Override it, together with
+    /// [`Embedder::distinguishes_queries`], for models that embed them
+    /// differently.
+    async fn embed_query(&self, text: &str) -> Result<Vec<f32>> {
+        self.embed(text).await
+    }
+
+    /// Embeds several search queries, returning one vector per text in order
+    async fn embed_query_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for text in texts {
+            embeddings.push(self.embed_query(text).await?);
+        }
+        Ok(embeddings)
+    }
+
+    /// Returns true if `embed_query` and `embed` give different vectors for
+    /// the same text, so one embedding can't serve both
+    fn distinguishes_queries(&self) -> bool {
+        false
+    }
+}
+
+/// Prefixes and normalization applied by [`PrefixedEmbedder`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EmbeddingConfig {
+    query_prefix: String,
+    document_prefix: String,
+    normalize: bool,
+}
+
+impl EmbeddingConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The `query: ` and `passage: ` prefixes and normalized vectors of
+    /// E5-style models
+    pub fn e5() -> Self {
+        Self::new()
+            .with_query_prefix("query: ")
+            .with_document_prefix("passage: ")
+            .with_normalization(true)
+    }
+
+    /// Prepends `prefix` to every search query
+    pub fn with_query_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.query_prefix = prefix.into();
+        self
+    }
+
+    /// Prepends `prefix` to every stored text
+    pub fn with_document_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.document_prefix = prefix.into();
+        self
+    }
+
+    /// Scales every vector to unit length, so dot product equals cosine
+    pub fn with_normalization(mut self, normalize: bool) -> Self {
+        self.normalize = normalize;
+        self
+    }
+
+    /// Returns the query prefix
+    pub fn query_prefix(&self) -> &str {
+        &self.query_prefix
+    }
+
+    /// Returns the document prefix
+    pub fn document_prefix(&self) -> &str {
+        &self.document_prefix
+    }
+
+    /// Returns whether vectors are normalized
+    pub fn normalize(&self) -> bool {
+        self.normalize
+    }
+}
+
+/// An [`Embedder`] wrapper that prefixes queries and documents and can L2
+/// normalize the vectors.
+///
+/// Wrap the embedder once and hand the wrapper to the agent, the semantic
+/// tier and any ingestion code, so queries and stored texts are always
+/// embedded the same way.
+pub struct PrefixedEmbedder {
+    inner: Arc<dyn Embedder>,
+    config: EmbeddingConfig,
+}
+
+impl PrefixedEmbedder {
+    /// Wraps `inner` with the default configuration, which changes nothing
+    pub fn new(inner: Arc<dyn Embedder>) -> Self {
+        Self::with_config(inner, EmbeddingConfig::default())
+    }
+
+    /// Wraps `inner` with the given configuration
+    pub fn with_config(inner: Arc<dyn Embedder>, config: EmbeddingConfig) -> Self {
+        Self { inner, config }
+    }
+
+    /// Returns the embedding configuration
+    pub fn config(&self) -> &EmbeddingConfig {
+        &self.config
+    }
+
+    fn prefixed(prefix: &str, texts: &[String]) -> Vec<String> {
+        texts
+            .iter()
+            .map(|text| format!("{}{}", prefix, text))
+            .collect()
+    }
+
+    fn finish(&self, mut embedding: Vec<f32>) -> Vec<f32> {
+        if self.config.normalize {
+            let norm = embedding.iter().map(|x| x * x).sum::<f32>().sqrt();
+            if norm > 0.0 {
+                embedding.iter_mut().for_each(|x| *x /= norm);
+            }
+        }
+        embedding
+    }
+}
+
+#[async_trait]
+impl Embedder for PrefixedEmbedder {
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let text = format!("{}{}", self.config.document_prefix, text);
+        Ok(self.finish(self.inner.embed(&text).await?))
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let texts = Self::prefixed(&self.config.document_prefix, texts);
+        let embeddings = self.inner.embed_batch(&texts).await?;
+        Ok(embeddings.into_iter().map(|e| self.finish(e)).collect())
+    }
+
+    async fn embed_query(&self, text: &str) -> Result<Vec<f32>> {
+        let text = format!("{}{}", self.config.query_prefix, text);
+        Ok(self.finish(self.inner.embed_query(&text).await?))
+    }
+
+    async fn embed_query_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let texts = Self::prefixed(&self.config.query_prefix, texts);
+        let embeddings = self.inner.embed_query_batch(&texts).await?;
+        Ok(embeddings.into_iter().map(|e| self.finish(e)).collect())
+    }
+
+    fn distinguishes_queries(&self) -> bool {
+        self.config.query_prefix != self.config.document_prefix
+            || self.inner.distinguishes_queries()
+    }
+}
+
+const DEFAULT_VERTEX_MODEL: &str = "text-embedding-005";
+/// Texts Vertex AI accepts in one predict request
+const VERTEX_BATCH_LIMIT: usize = 250;
+
+#[derive(Deserialize)]
+struct VertexResponse {
+    predictions: Vec<VertexPrediction>,
+}
+
+#[derive(Deserialize)]
+struct VertexPrediction {
+    embeddings: VertexEmbedding,
+}
+
+#[derive(Deserialize)]
+struct VertexEmbedding {
+    values: Vec<f32>,
+}
+
+/// Embeddings from Vertex AI, so traffic stays inside Google Cloud.
+///
+/// Authenticates with an OAuth access token, e.g. from
+/// `gcloud auth print-access-token` or the metadata server. Tokens expire;
+/// hand fresh ones to [`VertexEmbedder::set_access_token`].
+pub struct VertexEmbedder {
+    endpoint: String,
+    access_token: parking_lot::RwLock<String>,
+    task_type: Option<String>,
+    query_task_type: Option<String>,
+    dimensions: Option<usize>,
+    client: reqwest::Client,
+}
+
+impl VertexEmbedder {
+    /// Uses `text-embedding-005` in `project` and `location` (e.g. `us-central1`)
+    pub fn new(
+        project: impl AsRef<str>,
+        location: impl AsRef<str>,
+        access_token: impl Into<String>,
+    ) -> Self {
+        Self::with_model(project, location, DEFAULT_VERTEX_MODEL, access_token)
+    }
+
+    /// Uses another publisher model, e.g. `text-multilingual-embedding-002`
+    pub fn with_model(
+        project: impl AsRef<str>,
+        location: impl AsRef<str>,
+        model: impl AsRef<str>,
+        access_token: impl Into<String>,
+    ) -> Self {
+        let location = location.as_ref();
+        Self {
+            endpoint: format!(
+                "https://{location}-aiplatform.googleapis.com/v1/projects/{}/locations/{location}/publishers/google/models/{}:predict",
+                project.as_ref(),
+                model.as_ref()
+            ),
+            access_token: parking_lot::RwLock::new(access_token.into()),
+            task_type: None,
+            query_task_type: None,
+            dimensions: None,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Sends requests to a different endpoint, such as a private one
+    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    /// Sets the task type, e.g. `RETRIEVAL_DOCUMENT` or `RETRIEVAL_QUERY`
+    pub fn with_task_type(mut self, task_type: impl Into<String>) -> Self {
+        self.task_type = Some(task_type.into());
+        self
+    }
+
+    /// Sets the task type of search queries, e.g. `RETRIEVAL_QUERY`, while
+    /// stored texts keep the one from [`VertexEmbedder::with_task_type`]
+    pub fn with_query_task_type(mut self, task_type: impl Into<String>) -> Self {
+        self.query_task_type = Some(task_type.into());
+        self
+    }
+
+    /// Truncates embeddings to `dimensions` on the server
+    pub fn with_dimensions(mut self, dimensions: usize) -> Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+
+    /// Replaces the access token, e.g. after refreshing it
+    pub fn set_access_token(&self, access_token: impl Into<String>) {
+        *self.access_token.write() = access_token.into();
+    }
+
+    /// Task type for queries if `query`, for stored texts otherwise
+    fn task_type(&self, query: bool) -> Option<&str> {
+        match &self.query_task_type {
+            Some(task_type) if query => Some(task_type),
+            _ => self.task_type.as_deref(),
+        }
+    }
+
+    fn request_body(&self, texts: &[String], task_type: Option<&str>) -> serde_json::Value {
+        let instances: Vec<serde_json::Value> = texts
+            .iter()
+            .map(|text| match task_type {
+                Some(task_type) => serde_json::json!({ "content": text, "task_type": task_type }),
+                None => serde_json::json!({ "content": text }),
+            })
+            .collect();
+        let mut body = serde_json::json!({ "instances": instances });
+        if let Some(dimensions) = self.dimensions {
+            body["parameters"] = serde_json::json!({ "outputDimensionality": dimensions });
+        }
+        body
+    }
+
+    async fn predict(&self, texts: &[String], query: bool) -> Result<Vec<Vec<f32>>> {
+        let token = self.access_token.read().clone();
+        let response = self
+            .client
+            .post(&self.endpoint)
+            .bearer_auth(token)
+            .json(&self.request_body(texts, self.task_type(query)))
+            .send()
+            .await
+            .map_err(|e| AgentError::ModelError(format!("Vertex AI embedding error: {}", e)))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AgentError::ModelError(format!(
+                "Vertex AI embedding error {}: {}",
+                status, text
+            )));
+        }
+
+        let parsed: VertexResponse = response
+            .json()
+            .await
+            .map_err(|e| AgentError::ModelError(format!("Failed to parse response: {}", e)))?;
+        Ok(parsed
+            .predictions
+            .into_iter()
+            .map(|p| p.embeddings.values)
+            .collect())
+    }
+}
+
+impl VertexEmbedder {
+    async fn predict_one(&self, text: &str, query: bool) -> Result<Vec<f32>> {
+        self.predict(&[text.to_string()], query)
+            .await?
+            .into_iter()
+            .next()
+            .ok_or_else(|| AgentError::ModelError("Embedding returned no vector".to_string()))
+    }
+
+    async fn predict_all(&self, texts: &[String], query: bool) -> Result<Vec<Vec<f32>>> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for chunk in texts.chunks(VERTEX_BATCH_LIMIT) {
+            embeddings.extend(self.predict(chunk, query).await?);
+        }
+        Ok(embeddings)
+    }
+}
+
+#[async_trait]
+impl Embedder for VertexEmbedder {
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        self.predict_one(text, false).await
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        self.predict_all(texts, false).await
+    }
+
+    async fn embed_query(&self, text: &str) -> Result<Vec<f32>> {
+        self.predict_one(text, true).await
+    }
+
+    async fn embed_query_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        self.predict_all(texts, true).await
+    }
+
+    fn distinguishes_queries(&self) -> bool {
+        self.query_task_type.is_some()
+    }
+}
+
+#[cfg(feature = "bedrock")]
+pub use self::bedrock_impl::BedrockEmbedder;
+
+#[cfg(feature = "bedrock")]
+mod bedrock_impl {
+    use async_trait::async_trait;
+    use aws_sdk_bedrockruntime::primitives::Blob;
+    use aws_sdk_bedrockruntime::Client;
+    use serde::Deserialize;
+
+    use super::Embedder;
+    use crate::error::{AgentError, Result};
+
+    const DEFAULT_BEDROCK_MODEL: &str = "amazon.titan-embed-text-v2:0";
+    /// Texts Cohere on Bedrock accepts in one request
+    const COHERE_BATCH_LIMIT: usize = 96;
+
+    #[derive(Deserialize)]
+    struct TitanResponse {
+        embedding: Vec<f32>,
+    }
+
+    #[derive(Deserialize)]
+    struct CohereResponse {
+        embeddings: Vec<Vec<f32>>,
+    }
+
+    /// Embeddings from AWS Bedrock, so traffic stays inside AWS.
+    ///
+    /// Defaults to Titan Text Embeddings V2 (1024 dimensions). Model ids
+    /// containing `cohere.` (e.g. `cohere.embed-english-v3`) use the Cohere
+    /// request format and embed batches in one call.
+    pub struct BedrockEmbedder {
+        client: Client,
+        model_id: String,
+        dimensions: Option<usize>,
+        input_type: String,
+    }
+
+    impl BedrockEmbedder {
+        /// Uses credentials and region from the standard AWS environment
+        pub async fn from_env() -> Self {
+            let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+            Self::new(Client::new(&config))
+        }
+
+        pub fn new(client: Client) -> Self {
+            Self {
+                client,
+                model_id: DEFAULT_BEDROCK_MODEL.to_string(),
+                dimensions: None,
+                input_type: "search_document".to_string(),
+            }
+        }
+
+        /// Uses another model or inference profile id
+        pub fn with_model(mut self, model_id: impl Into<String>) -> Self {
+            self.model_id = model_id.into();
+            self
+        }
+
+        /// Output size for Titan V2 (256, 512 or 1024)
+        pub fn with_dimensions(mut self, dimensions: usize) -> Self {
+            self.dimensions = Some(dimensions);
+            self
+        }
+
+        /// Cohere input type, e.g. `search_query` for query-side embedders
+        pub fn with_input_type(mut self, input_type: impl Into<String>) -> Self {
+            self.input_type = input_type.into();
+            self
+        }
+
+        fn is_cohere(&self) -> bool {
+            self.model_id.contains("cohere.")
+        }
+
+        async fn invoke(&self, body: serde_json::Value) -> Result<Vec<u8>> {
+            let output = self
+                .client
+                .invoke_model()
+                .model_id(&self.model_id)
+                .content_type("application/json")
+                .accept("application/json")
+                .body(Blob::new(serde_json::to_vec(&body)?))
+                .send()
+                .await
+                .map_err(|e| AgentError::ModelError(format!("Bedrock embedding error: {}", e)))?;
+            Ok(output.body.into_inner())
+        }
+
+        async fn embed_cohere(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            let body = serde_json::json!({
+                "texts": texts,
+                "input_type": self.input_type,
+            });
+            let data = self.invoke(body).await?;
+            let parsed: CohereResponse = serde_json::from_slice(&data)?;
+            Ok(parsed.embeddings)
+        }
+    }
+
+    #[async_trait]
+    impl Embedder for BedrockEmbedder {
+        async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+            if self.is_cohere() {
+                return self
+                    .embed_cohere(&[text.to_string()])
+                    .await?
+                    .into_iter()
+                    .next()
+                    .ok_or_else(|| {
+                        AgentError::ModelError("Embedding returned no vector".to_string())
+                    });
+            }
+
+            let mut body = serde_json::json!({ "inputText": text });
+            if let Some(dimensions) = self.dimensions {
+                body["dimensions"] = serde_json::json!(dimensions);
+            }
+            let data = self.invoke(body).await?;
+            let parsed: TitanResponse = serde_json::from_slice(&data)?;
+            Ok(parsed.embedding)
+        }
+
+        async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            let mut embeddings = Vec::with_capacity(texts.len());
+            if self.is_cohere() {
+                for chunk in texts.chunks(COHERE_BATCH_LIMIT) {
+                    embeddings.extend(self.embed_cohere(chunk).await?);
+                }
+            } else {
+                // Titan embeds one text per request
+                for text in texts {
+                    embeddings.push(self.embed(text).await?);
+                }
+            }
+            Ok(embeddings)
+        }
+    }
+}
+
+#[cfg(feature = "memory")]
+pub use self::fastembed_impl::FastEmbedder;
+
+#[cfg(feature = "memory")]
+mod fastembed_impl {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
+
+    use super::Embedder;
+    use crate::error::{AgentError, Result};
+
+    /// Local ONNX embeddings via fastembed.
+    ///
+    /// Defaults to all-MiniLM-L6-v2, whose 384 dimensions match the default
+    /// vector size of the persistent stores.
+    pub struct FastEmbedder {
+        model: Arc<TextEmbedding>,
+    }
+
+    impl FastEmbedder {
+        /// Loads the default model, downloading it on first use
+        pub fn new() -> Result<Self> {
+            Self::with_model(EmbeddingModel::AllMiniLML6V2)
+        }
+
+        /// Loads the given fastembed model
+        pub fn with_model(model: EmbeddingModel) -> Result<Self> {
+            let model = TextEmbedding::try_new(InitOptions::new(model))
+                .map_err(|e| AgentError::ConfigError(format!("Embedding model: {}", e)))?;
+            Ok(Self {
+                model: Arc::new(model),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl Embedder for FastEmbedder {
+        async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+            // Inference is CPU-bound; keep it off the async workers
+            let model = Arc::clone(&self.model);
+            let text = text.to_string();
+            let embeddings = tokio::task::spawn_blocking(move || model.embed(vec![text], None))
+                .await
+                .map_err(|e| AgentError::Other(e.to_string()))?
+                .map_err(|e| AgentError::ModelError(format!("Embedding failed: {}", e)))?;
+
+            embeddings
+                .into_iter()
+                .next()
+                .ok_or_else(|| AgentError::ModelError("Embedding returned no vector".to_string()))
+        }
+
+        async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            let model = Arc::clone(&self.model);
+            let texts = texts.to_vec();
+            tokio::task::spawn_blocking(move || model.embed(texts, None))
+                .await
+                .map_err(|e| AgentError::Other(e.to_string()))?
+                .map_err(|e| AgentError::ModelError(format!("Embedding failed: {}", e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vertex_request_and_response() {
+        let embedder = VertexEmbedder::new("proj", "europe-west4", "token")
+            .with_task_type("RETRIEVAL_DOCUMENT")
+            .with_query_task_type("RETRIEVAL_QUERY")
+            .with_dimensions(256);
+        assert!(embedder.endpoint.starts_with(
+            "https://europe-west4-aiplatform.googleapis.com/v1/projects/proj/locations/europe-west4/"
+        ));
+
+        let body = embedder.request_body(&["hello".to_string()], embedder.task_type(true));
+        assert_eq!(body["instances"][0]["content"], "hello");
+        assert_eq!(body["instances"][0]["task_type"], "RETRIEVAL_QUERY");
+        assert_eq!(embedder.task_type(false), Some("RETRIEVAL_DOCUMENT"));
+        assert_eq!(body["parameters"]["outputDimensionality"], 256);
+
+        let response: VertexResponse = serde_json::from_str(
+            r#"{"predictions": [{"embeddings": {"values": [0.5, -1.0], "statistics": {}}}]}"#,
+        )
+        .unwrap();
+        assert_eq!(response.predictions[0].embeddings.values, vec![0.5, -1.0]);
+    }
+
+    /// Embeds a text as its length along one axis
+    struct LengthEmbedder;
+
+    #[async_trait]
+    impl Embedder for LengthEmbedder {
+        async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+            Ok(vec![text.len() as f32, 0.0])
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prefixed_embedder_prefixes_and_normalizes() {
+        let plain = PrefixedEmbedder::with_config(
+            Arc::new(LengthEmbedder),
+            EmbeddingConfig::new()
+                .with_query_prefix("query: ")
+                .with_document_prefix("passage: "),
+        );
+        assert!(plain.distinguishes_queries());
+        assert_eq!(plain.embed_query("ab").await.unwrap(), vec![9.0, 0.0]);
+        assert_eq!(
+            plain.embed_batch(&["ab".to_string()]).await.unwrap()[0][0],
+            11.0
+        );
+
+        let e5 = PrefixedEmbedder::with_config(Arc::new(LengthEmbedder), EmbeddingConfig::e5());
+        assert_eq!(e5.embed("ab").await.unwrap(), vec![1.0, 0.0]);
+        assert!(!PrefixedEmbedder::new(Arc::new(LengthEmbedder)).distinguishes_queries());
+    }
+}
@@ -0,0 +1,414 @@
+//! Hierarchical navigable small world (HNSW) graphs for approximate
+//! nearest-neighbour search.
+//!
+//! Each record is a node linked to its closest neighbours on a stack of
+//! layers; sparse upper layers route a query towards its region and the
+//! dense bottom layer refines it, so lookups visit a few hundred nodes rather
+//! than every embedding. Similarity defaults to cosine, computed as the dot
+//! product of vectors normalized on insert; see [`Hnsw::with_metric`].
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+use uuid::Uuid;
+
+use super::{dot_similarity, euclidean_similarity, SimilarityMetric};
+
+/// Upper bound on graph layers, far above what realistic sizes reach
+const MAX_LEVEL: usize = 16;
+
+/// Construction and search parameters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HnswParams {
+    /// Links per node on the upper layers; the bottom layer keeps twice as many
+    pub m: usize,
+    /// Candidate list size while inserting; higher builds a better graph
+    pub ef_construction: usize,
+    /// Candidate list size while searching; higher trades speed for recall
+    pub ef_search: usize,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        Self {
+            m: 16,
+            ef_construction: 100,
+            ef_search: 64,
+        }
+    }
+}
+
+struct Node {
+    id: Uuid,
+    vector: Vec<f32>,
+    /// Neighbour node indices, one list per layer the node lives on
+    links: Vec<Vec<usize>>,
+    deleted: bool,
+}
+
+/// Similarity paired with a node index, ordered by similarity with ties
+/// going to the older (lower) index
+#[derive(Clone, Copy)]
+struct Scored(f32, usize);
+
+impl PartialEq for Scored {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(other.1.cmp(&self.1))
+    }
+}
+
+/// An HNSW graph over embeddings keyed by record id.
+///
+/// Removed records stay in the graph as routing-only tombstones until they
+/// outnumber live ones, at which point the graph is rebuilt.
+pub struct Hnsw {
+    params: HnswParams,
+    metric: SimilarityMetric,
+    nodes: Vec<Node>,
+    live: HashMap<Uuid, usize>,
+    entry: Option<usize>,
+    top_level: usize,
+}
+
+impl Hnsw {
+    pub fn new(params: HnswParams) -> Self {
+        Self {
+            params,
+            metric: SimilarityMetric::default(),
+            nodes: Vec::new(),
+            live: HashMap::new(),
+            entry: None,
+            top_level: 0,
+        }
+    }
+
+    /// Compares embeddings by `metric` instead of cosine similarity.
+    ///
+    /// Set this before inserting; vectors already in the graph were linked
+    /// by the previous metric.
+    pub fn with_metric(mut self, metric: SimilarityMetric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Returns the number of searchable records
+    pub fn len(&self) -> usize {
+        self.live.len()
+    }
+
+    /// Returns true if no records are searchable
+    pub fn is_empty(&self) -> bool {
+        self.live.is_empty()
+    }
+
+    /// Adds or replaces the embedding of `id`
+    pub fn insert(&mut self, id: Uuid, vector: &[f32]) {
+        self.remove(id);
+
+        let index = self.nodes.len();
+        let level = random_level(id, self.params.m);
+        self.nodes.push(Node {
+            id,
+            vector: self.prepare(vector),
+            links: vec![Vec::new(); level + 1],
+            deleted: false,
+        });
+        self.live.insert(id, index);
+
+        let Some(mut entry) = self.entry else {
+            self.entry = Some(index);
+            self.top_level = level;
+            return;
+        };
+
+        let query = self.nodes[index].vector.clone();
+        for layer in (level + 1..=self.top_level).rev() {
+            entry = self.search_layer(&query, &[entry], 1, layer)[0].1;
+        }
+
+        let mut entries = vec![entry];
+        for layer in (0..=level.min(self.top_level)).rev() {
+            let candidates =
+                self.search_layer(&query, &entries, self.params.ef_construction, layer);
+            let max_links = self.max_links(layer);
+            let neighbours: Vec<usize> = candidates
+                .iter()
+                .take(max_links)
+                .map(|scored| scored.1)
+                .collect();
+
+            for &neighbour in &neighbours {
+                self.nodes[neighbour].links[layer].push(index);
+                if self.nodes[neighbour].links[layer].len() > max_links {
+                    self.prune(neighbour, layer, max_links);
+                }
+            }
+            self.nodes[index].links[layer] = neighbours;
+            entries = candidates.into_iter().map(|scored| scored.1).collect();
+        }
+
+        if level > self.top_level {
+            self.entry = Some(index);
+            self.top_level = level;
+        }
+    }
+
+    /// Removes `id` from search results, returning whether it was present
+    pub fn remove(&mut self, id: Uuid) -> bool {
+        let Some(index) = self.live.remove(&id) else {
+            return false;
+        };
+        self.nodes[index].deleted = true;
+
+        if self.live.is_empty() {
+            *self = self.emptied();
+        } else if self.nodes.len() > 2 * self.live.len() {
+            self.rebuild();
+        }
+        true
+    }
+
+    /// Returns up to `k` `(id, similarity)` pairs closest to `query`, best first.
+    ///
+    /// `ef` widens the candidate list beyond the configured `ef_search`.
+    pub fn search(&self, query: &[f32], k: usize, ef: usize) -> Vec<(Uuid, f32)> {
+        let Some(mut entry) = self.entry else {
+            return Vec::new();
+        };
+
+        let query = self.prepare(query);
+        for layer in (1..=self.top_level).rev() {
+            entry = self.search_layer(&query, &[entry], 1, layer)[0].1;
+        }
+
+        let ef = ef.max(self.params.ef_search).max(k);
+        self.search_layer(&query, &[entry], ef, 0)
+            .into_iter()
+            .filter(|scored| !self.nodes[scored.1].deleted)
+            .take(k)
+            .map(|Scored(similarity, index)| (self.nodes[index].id, similarity))
+            .collect()
+    }
+
+    fn max_links(&self, layer: usize) -> usize {
+        if layer == 0 {
+            self.params.m * 2
+        } else {
+            self.params.m
+        }
+    }
+
+    /// Best-first search of one layer, returning up to `ef` nodes best first
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entries: &[usize],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<Scored> {
+        let mut visited: HashSet<usize> = entries.iter().copied().collect();
+        let mut candidates: BinaryHeap<Scored> = BinaryHeap::new();
+        let mut best: BinaryHeap<Reverse<Scored>> = BinaryHeap::new();
+
+        for &entry in entries {
+            let scored = Scored(self.score(query, &self.nodes[entry].vector), entry);
+            candidates.push(scored);
+            best.push(Reverse(scored));
+            if best.len() > ef {
+                best.pop();
+            }
+        }
+
+        while let Some(current) = candidates.pop() {
+            let worst = best.peek().map_or(f32::NEG_INFINITY, |r| r.0 .0);
+            if current.0 < worst && best.len() >= ef {
+                break;
+            }
+
+            let Some(links) = self.nodes[current.1].links.get(layer) else {
+                continue;
+            };
+            for &neighbour in links {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let scored = Scored(self.score(query, &self.nodes[neighbour].vector), neighbour);
+                let worst = best.peek().map_or(f32::NEG_INFINITY, |r| r.0 .0);
+                if best.len() < ef || scored.0 > worst {
+                    candidates.push(scored);
+                    best.push(Reverse(scored));
+                    if best.len() > ef {
+                        best.pop();
+                    }
+                }
+            }
+        }
+
+        let mut result: Vec<Scored> = best.into_iter().map(|r| r.0).collect();
+        result.sort_unstable_by(|a, b| b.cmp(a));
+        result
+    }
+
+    /// Keeps only the `max_links` closest neighbours of `index` on `layer`
+    fn prune(&mut self, index: usize, layer: usize, max_links: usize) {
+        let vector = &self.nodes[index].vector;
+        let mut scored: Vec<Scored> = self.nodes[index].links[layer]
+            .iter()
+            .map(|&n| Scored(self.score(vector, &self.nodes[n].vector), n))
+            .collect();
+        scored.sort_unstable_by(|a, b| b.cmp(a));
+        scored.truncate(max_links);
+        self.nodes[index].links[layer] = scored.into_iter().map(|s| s.1).collect();
+    }
+
+    /// An empty graph with the same parameters and metric
+    fn emptied(&self) -> Self {
+        Self::new(self.params).with_metric(self.metric)
+    }
+
+    /// Brings a vector into the form nodes are stored in
+    fn prepare(&self, vector: &[f32]) -> Vec<f32> {
+        match self.metric {
+            SimilarityMetric::Cosine => normalize(vector),
+            SimilarityMetric::Dot | SimilarityMetric::Euclidean => vector.to_vec(),
+        }
+    }
+
+    /// Similarity of two prepared vectors
+    fn score(&self, a: &[f32], b: &[f32]) -> f32 {
+        match self.metric {
+            // Both sides are normalized, so the dot product is the cosine
+            SimilarityMetric::Cosine | SimilarityMetric::Dot => dot_similarity(a, b),
+            SimilarityMetric::Euclidean => euclidean_similarity(a, b),
+        }
+    }
+
+    /// Rebuilds the graph from live nodes, dropping tombstones
+    fn rebuild(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        *self = self.emptied();
+        for node in nodes.into_iter().filter(|n| !n.deleted) {
+            self.insert(node.id, &node.vector);
+        }
+    }
+}
+
+/// Draws a node's top layer from an exponential distribution, seeded by its
+/// id so the same records always build the same graph
+fn random_level(id: Uuid, m: usize) -> usize {
+    let bits = (id.as_u128() as u64) & ((1 << 53) - 1);
+    let uniform = (bits.max(1)) as f64 / (1u64 << 53) as f64;
+    let scale = 1.0 / (m.max(2) as f64).ln();
+    ((-uniform.ln() * scale) as usize).min(MAX_LEVEL)
+}
+
+fn normalize(vector: &[f32]) -> Vec<f32> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        vector.to_vec()
+    } else {
+        vector.iter().map(|x| x / norm).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Deterministic pseudo-random vectors
+    fn vectors(count: usize, dim: usize) -> Vec<Vec<f32>> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..count)
+            .map(|_| {
+                (0..dim)
+                    .map(|_| {
+                        state ^= state << 13;
+                        state ^= state >> 7;
+                        state ^= state << 17;
+                        (state % 2000) as f32 / 1000.0 - 1.0
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_search_recall_against_exact() {
+        let data = vectors(2000, 16);
+        let ids: Vec<Uuid> = data.iter().map(|_| Uuid::new_v4()).collect();
+        let mut graph = Hnsw::new(HnswParams::default());
+        for (id, vector) in ids.iter().zip(&data) {
+            graph.insert(*id, vector);
+        }
+
+        let mut hits = 0;
+        let queries = vectors(20, 16);
+        for query in &queries {
+            let normalized = normalize(query);
+            let mut exact: Vec<(Uuid, f32)> = ids
+                .iter()
+                .zip(&data)
+                .map(|(id, v)| (*id, dot_similarity(&normalized, &normalize(v))))
+                .collect();
+            exact.sort_by(|a, b| b.1.total_cmp(&a.1));
+            let truth: HashSet<Uuid> = exact.iter().take(10).map(|(id, _)| *id).collect();
+
+            hits += graph
+                .search(query, 10, 0)
+                .iter()
+                .filter(|(id, _)| truth.contains(id))
+                .count();
+        }
+        // Approximate, but should find nearly all true neighbours
+        assert!(hits >= 180, "recall too low: {}/200", hits);
+    }
+
+    #[test]
+    fn test_ties_prefer_older_nodes() {
+        let mut graph = Hnsw::new(HnswParams::default());
+        let older = Uuid::new_v4();
+        let newer = Uuid::new_v4();
+        graph.insert(older, &[1.0, 0.0]);
+        graph.insert(newer, &[1.0, 0.0]);
+
+        let ids: Vec<Uuid> = graph
+            .search(&[1.0, 0.0], 2, 0)
+            .iter()
+            .map(|h| h.0)
+            .collect();
+        assert_eq!(ids, vec![older, newer]);
+        assert_eq!(graph.search(&[1.0, 0.0], 1, 0)[0].0, older);
+    }
+
+    #[test]
+    fn test_remove_and_replace() {
+        let mut graph = Hnsw::new(HnswParams::default());
+        let a = Uuid::new_v4();
+        let b = Uuid::new_v4();
+        graph.insert(a, &[1.0, 0.0]);
+        graph.insert(b, &[0.0, 1.0]);
+
+        assert_eq!(graph.search(&[1.0, 0.1], 1, 0)[0].0, a);
+        graph.insert(a, &[0.0, -1.0]);
+        assert_eq!(graph.search(&[1.0, 0.1], 1, 0)[0].0, b);
+
+        assert!(graph.remove(b));
+        assert!(!graph.remove(b));
+        assert_eq!(graph.len(), 1);
+        assert_eq!(graph.search(&[1.0, 0.1], 5, 0).len(), 1);
+    }
+}
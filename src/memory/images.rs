@@ -0,0 +1,181 @@
+//! Image memories.
+//!
+//! With [`ImageMemory`] configured, image attachments outlive the turn they
+//! were sent in. The bytes go to a [`BlobStore`], and a record pointing at
+//! them goes to the session's [`image_scope`], with an embedding from an
+//! [`ImageEmbedder`]. CLIP-style models embed images and text into one
+//! space, so a text query such as "the diagram I sent last week" finds the
+//! diagram, and so does a similar image. [`SessionMemory::search`] takes
+//! either as an [`ImageQuery`], keeping hits that score at least
+//! [`ImageMemory::with_min_score`].
+//!
+//! Image vectors rarely share the dimension of text embeddings. They live in
+//! their own scope, and [`ImageMemory::with_store`] keeps them in a separate
+//! store for backends that fix one vector size per table.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::blob::BlobStore;
+use crate::error::Result;
+use crate::memory::{reserved_scope, MemoryStore};
+use crate::types::File;
+
+/// Role of image memory records
+pub const IMAGE_ROLE: &str = "image";
+/// Metadata key holding the blob reference of a stored image
+pub const IMAGE_REF_KEY: &str = "image_ref";
+/// Metadata key holding the MIME type of a stored image
+pub const IMAGE_MIME_KEY: &str = "image_mime";
+/// Similarity an image memory needs to be found, by default
+pub const DEFAULT_IMAGE_MIN_SCORE: f32 = 0.2;
+
+/// Returns the store session id holding the image memories of `session_id`
+pub fn image_scope(session_id: &str) -> String {
+    reserved_scope("image", session_id)
+}
+
+/// Returns true if `file` is an image
+pub fn is_image(file: &File) -> bool {
+    file.mime_type.starts_with("image/")
+}
+
+/// Embeds images and text into one vector space, as CLIP models do
+#[async_trait]
+pub trait ImageEmbedder: Send + Sync {
+    /// Embeds an image
+    async fn embed_image(&self, image: &File) -> Result<Vec<f32>>;
+
+    /// Embeds a text query into the image space
+    async fn embed_text(&self, text: &str) -> Result<Vec<f32>>;
+}
+
+/// What to look for among image memories
+#[derive(Debug, Clone)]
+pub enum ImageQuery {
+    /// Images matching a description
+    Text(String),
+    /// Images similar to this one
+    Image(File),
+}
+
+/// Where image memories are embedded and kept
+pub struct ImageMemory {
+    pub(crate) embedder: Arc<dyn ImageEmbedder>,
+    pub(crate) blobs: Arc<dyn BlobStore>,
+    pub(crate) store: Option<Box<dyn MemoryStore>>,
+    pub(crate) min_score: f32,
+}
+
+impl ImageMemory {
+    /// Embeds images with `embedder` and keeps their bytes in `blobs`;
+    /// records go to the session memory's own store
+    pub fn new(embedder: Arc<dyn ImageEmbedder>, blobs: Arc<dyn BlobStore>) -> Self {
+        Self {
+            embedder,
+            blobs,
+            store: None,
+            min_score: DEFAULT_IMAGE_MIN_SCORE,
+        }
+    }
+
+    /// Keeps image records in `store` instead of the session memory's store
+    pub fn with_store(mut self, store: Box<dyn MemoryStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Leaves out images less similar to a query than `min_score`
+    pub fn with_min_score(mut self, min_score: f32) -> Self {
+        self.min_score = min_score;
+        self
+    }
+
+    /// Embeds `query` into the image space
+    pub async fn embed_query(&self, query: &ImageQuery) -> Result<Vec<f32>> {
+        match query {
+            ImageQuery::Text(text) => self.embedder.embed_text(text).await,
+            ImageQuery::Image(image) => self.embedder.embed_image(image).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blob::InMemoryBlobStore;
+    use crate::memory::{InMemoryStore, SessionMemory};
+
+    /// Puts images and texts about diagrams on one axis, everything else on
+    /// another
+    struct AxisEmbedder;
+
+    fn axis(topic: &str) -> Vec<f32> {
+        if topic.contains("diagram") {
+            vec![1.0, 0.0]
+        } else {
+            vec![0.0, 1.0]
+        }
+    }
+
+    #[async_trait]
+    impl ImageEmbedder for AxisEmbedder {
+        async fn embed_image(&self, image: &File) -> Result<Vec<f32>> {
+            Ok(axis(&String::from_utf8_lossy(&image.data)))
+        }
+
+        async fn embed_text(&self, text: &str) -> Result<Vec<f32>> {
+            Ok(axis(text))
+        }
+    }
+
+    fn png(data: &str) -> File {
+        File {
+            mime_type: "image/png".to_string(),
+            data: data.as_bytes().to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_images_are_found_by_description_or_similar_image() {
+        let images = ImageMemory::new(Arc::new(AxisEmbedder), Arc::new(InMemoryBlobStore::new()));
+        let memory =
+            SessionMemory::new(Box::new(InMemoryStore::new()), 4).with_image_memory(images);
+
+        memory
+            .store_image("s", &png("architecture diagram"), "Here is our setup")
+            .await
+            .unwrap()
+            .unwrap();
+        memory
+            .store_image("s", &png("holiday photo"), "Look at this")
+            .await
+            .unwrap()
+            .unwrap();
+        let text = File {
+            mime_type: "text/plain".to_string(),
+            data: b"notes".to_vec(),
+        };
+        assert!(memory.store_image("s", &text, "").await.unwrap().is_none());
+        assert!(memory.retrieve_recent("s").await.unwrap().is_empty());
+
+        let query = ImageQuery::Text("the diagram I sent last week".to_string());
+        let found = memory.search("s", query, 5).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(
+            found[0].content,
+            "Image (image/png) sent with: Here is our setup"
+        );
+        let image = memory.load_image(&found[0]).await.unwrap().unwrap();
+        assert_eq!(image.data, b"architecture diagram");
+
+        let query = ImageQuery::Image(png("beach photo"));
+        let found = memory.search("s", query.clone(), 1).await.unwrap();
+        assert_eq!(
+            found[0].content,
+            "Image (image/png) sent with: Look at this"
+        );
+        assert!(memory.search("other", query, 1).await.unwrap().is_empty());
+    }
+}
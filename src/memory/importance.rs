@@ -0,0 +1,180 @@
+//! Importance scoring for memory records.
+//!
+//! Scores in `[0.0, 1.0]` drive which records survive context trimming and
+//! which are kept verbatim during compaction. [`HeuristicScorer`] is cheap and
+//! deterministic; [`LlmScorer`] asks a model to rate each record.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::error::{AgentError, Result};
+use crate::memory::MemoryRecord;
+use crate::models::LLM;
+use crate::types::{Message, Role};
+
+/// Importance assigned when no scorer is configured
+pub const DEFAULT_IMPORTANCE: f32 = 0.5;
+
+/// Assigns an importance score to a record before it is stored
+#[async_trait]
+pub trait ImportanceScorer: Send + Sync {
+    /// Returns the record's importance in `[0.0, 1.0]`
+    async fn score(&self, record: &MemoryRecord) -> Result<f32>;
+}
+
+/// Phrases signalling that the user wants something remembered
+const MEMORABLE_PHRASES: &[&str] = &[
+    "remember",
+    "don't forget",
+    "do not forget",
+    "important",
+    "always",
+    "never",
+    "i prefer",
+    "my name is",
+    "call me",
+    "deadline",
+];
+
+/// Rule-based scorer using role, explicit memory cues, and identifiers
+#[derive(Debug, Clone, Default)]
+pub struct HeuristicScorer;
+
+impl HeuristicScorer {
+    pub fn new() -> Self {
+        Self
+    }
+
+    /// Scores a record synchronously
+    pub fn score_record(&self, record: &MemoryRecord) -> f32 {
+        let mut score: f32 = match record.role.as_str() {
+            "user" => 0.5,
+            "assistant" => 0.4,
+            "tool" => 0.3,
+            _ => DEFAULT_IMPORTANCE,
+        };
+
+        let lower = record.content.to_lowercase();
+        if MEMORABLE_PHRASES.iter().any(|p| lower.contains(p)) {
+            score += 0.3;
+        }
+
+        // Order numbers, error codes, and similar identifiers are hard to recover
+        if record.content.split_whitespace().any(is_identifier) {
+            score += 0.1;
+        }
+
+        let words = record.content.split_whitespace().count();
+        if words < 4 {
+            score -= 0.2;
+        } else if words > 40 {
+            score += 0.05;
+        }
+
+        score.clamp(0.0, 1.0)
+    }
+}
+
+fn is_identifier(token: &str) -> bool {
+    let token = token.trim_matches(|c: char| !c.is_alphanumeric());
+    token.len() >= 4
+        && token.chars().any(|c| c.is_ascii_digit())
+        && token.chars().any(|c| c.is_alphabetic() || c == '-')
+}
+
+#[async_trait]
+impl ImportanceScorer for HeuristicScorer {
+    async fn score(&self, record: &MemoryRecord) -> Result<f32> {
+        Ok(self.score_record(record))
+    }
+}
+
+const SCORING_INSTRUCTIONS: &str = "Rate how important it is for an assistant to remember the \
+following conversation message in later turns, from 0.0 (irrelevant small talk) to 1.0 \
+(critical facts, preferences, or commitments). Reply with the number only.";
+
+/// Scorer that asks a model to rate each record
+pub struct LlmScorer {
+    model: Arc<dyn LLM>,
+}
+
+impl LlmScorer {
+    pub fn new(model: Arc<dyn LLM>) -> Self {
+        Self { model }
+    }
+}
+
+#[async_trait]
+impl ImportanceScorer for LlmScorer {
+    async fn score(&self, record: &MemoryRecord) -> Result<f32> {
+        let messages = vec![
+            Message {
+                role: Role::System,
+                content: SCORING_INSTRUCTIONS.to_string(),
+                metadata: None,
+            },
+            Message {
+                role: Role::User,
+                content: format!("{}: {}", record.role, record.content),
+                metadata: None,
+            },
+        ];
+        let response = self.model.generate(messages, None).await?;
+        parse_score(&response.content).ok_or_else(|| {
+            AgentError::ModelError(format!(
+                "Unparseable importance score: {}",
+                response.content
+            ))
+        })
+    }
+}
+
+/// Extracts the first number in `text` and clamps it to `[0.0, 1.0]`
+pub(crate) fn parse_score(text: &str) -> Option<f32> {
+    text.split(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .find_map(|token| token.trim_matches('.').parse::<f32>().ok())
+        .map(|score| score.clamp(0.0, 1.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn record(role: &str, content: &str) -> MemoryRecord {
+        MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: "test".to_string(),
+            role: role.to_string(),
+            content: content.to_string(),
+            importance: DEFAULT_IMPORTANCE,
+            timestamp: Utc::now(),
+            metadata: None,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn test_heuristic_ranks_memorable_content_higher() {
+        let scorer = HeuristicScorer::new();
+        let chatter = scorer.score_record(&record("user", "ok thanks"));
+        let fact = scorer.score_record(&record(
+            "user",
+            "Please remember my order ORD-4821 ships to Berlin",
+        ));
+        assert!(fact > chatter);
+        assert!((0.0..=1.0).contains(&fact));
+    }
+
+    #[test]
+    fn test_parse_score() {
+        assert_eq!(parse_score("0.8"), Some(0.8));
+        assert_eq!(parse_score("Score: 0.25."), Some(0.25));
+        assert_eq!(parse_score("7"), Some(1.0));
+        assert_eq!(parse_score("high"), None);
+    }
+}
@@ -0,0 +1,111 @@
+//! JSON Lines interchange for memory records.
+//!
+//! One serialized [`MemoryRecord`] per line, the same shape every backend
+//! stores. Pair [`write_jsonl`] with [`MemoryStore::export`] and
+//! [`read_jsonl`] with [`MemoryStore::import`] to move sessions between
+//! backends or keep them as backups.
+//!
+//! [`MemoryStore::export`]: crate::memory::MemoryStore::export
+//! [`MemoryStore::import`]: crate::memory::MemoryStore::import
+
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::error::{AgentError, Result};
+use crate::memory::MemoryRecord;
+
+/// Writes `records` to `writer` as JSON Lines, returning how many were written
+pub async fn write_jsonl<W>(
+    mut records: BoxStream<'_, Result<MemoryRecord>>,
+    mut writer: W,
+) -> Result<usize>
+where
+    W: AsyncWrite + Unpin,
+{
+    let mut written = 0;
+    while let Some(record) = records.try_next().await? {
+        let mut line = serde_json::to_vec(&record)?;
+        line.push(b'\n');
+        writer.write_all(&line).await?;
+        written += 1;
+    }
+    writer.flush().await?;
+    Ok(written)
+}
+
+/// Streams the records of a JSON Lines document, skipping blank lines
+pub fn read_jsonl<'a, R>(reader: R) -> BoxStream<'a, Result<MemoryRecord>>
+where
+    R: AsyncBufRead + Unpin + Send + 'a,
+{
+    futures::stream::try_unfold(
+        (reader.lines(), 0usize),
+        |(mut lines, mut number)| async move {
+            loop {
+                let Some(line) = lines.next_line().await? else {
+                    return Ok(None);
+                };
+                number += 1;
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                let record = serde_json::from_str(&line).map_err(|e| {
+                    AgentError::MemoryError(format!(
+                        "Invalid memory record on line {}: {}",
+                        number, e
+                    ))
+                })?;
+                return Ok(Some((record, (lines, number))));
+            }
+        },
+    )
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{InMemoryStore, MemoryStore};
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_jsonl_round_trip_between_stores() {
+        let source = InMemoryStore::new();
+        for i in 0..3 {
+            let record = MemoryRecord {
+                id: Uuid::new_v4(),
+                session_id: "s".to_string(),
+                role: "user".to_string(),
+                content: format!("message {}", i),
+                importance: 0.5,
+                timestamp: Utc::now(),
+                metadata: None,
+                embedding: Some(vec![0.1, 0.2]),
+                version: 0,
+                expires_at: None,
+            };
+            source.store(record).await.unwrap();
+        }
+
+        let mut buffer = Vec::new();
+        let written = write_jsonl(source.export("s"), &mut buffer).await.unwrap();
+        assert_eq!(written, 3);
+
+        let mut document = buffer.clone();
+        document.extend_from_slice(b"\n");
+        let target = InMemoryStore::new();
+        let imported = target.import(read_jsonl(&document[..])).await.unwrap();
+        assert_eq!(imported, 3);
+
+        let records = target.retrieve("s", 10).await.unwrap();
+        assert_eq!(records.len(), 3);
+        assert!(records.iter().all(|r| r.embedding.is_some()));
+
+        let broken = read_jsonl(&b"{\"not\": \"a record\"}\n"[..])
+            .try_collect::<Vec<_>>()
+            .await;
+        assert!(matches!(broken, Err(AgentError::MemoryError(_))));
+    }
+}
@@ -0,0 +1,162 @@
+//! Structured labels on memory records.
+//!
+//! Labels such as intent, sentiment or resolution status are attached to
+//! stored turns after the fact, by people or classifiers, for analytics and
+//! for building fine-tuning datasets. Each label is kept in the record's
+//! metadata under [`label_key`], JSON-encoded, so every store can filter on
+//! it with [`MemoryFilter::with_label`] without a schema change.
+
+use std::collections::HashMap;
+
+use serde_json::Value;
+use uuid::Uuid;
+
+use crate::error::{AgentError, Result};
+use crate::memory::{record_not_found, MemoryFilter, MemoryRecord, SessionMemory};
+
+/// Prefix of the metadata keys holding labels
+pub const LABEL_PREFIX: &str = "label:";
+
+/// Attempts at labeling a record that others keep updating concurrently
+const MAX_LABEL_ATTEMPTS: usize = 3;
+
+/// Returns the metadata key holding label `name`
+pub fn label_key(name: &str) -> String {
+    format!("{}{}", LABEL_PREFIX, name)
+}
+
+/// Returns the labels of `record`
+pub fn labels_of(record: &MemoryRecord) -> HashMap<String, Value> {
+    record
+        .metadata
+        .iter()
+        .flatten()
+        .filter_map(|(key, value)| {
+            let name = key.strip_prefix(LABEL_PREFIX)?;
+            let value =
+                serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.clone()));
+            Some((name.to_string(), value))
+        })
+        .collect()
+}
+
+impl MemoryFilter {
+    /// Requires label `name` to equal `value`
+    pub fn with_label(self, name: &str, value: impl Into<Value>) -> Self {
+        self.with_metadata(label_key(name), value.into().to_string())
+    }
+}
+
+impl SessionMemory {
+    /// Sets `labels` on record `record_id` of `session_id`, keeping its other
+    /// labels; a `null` value removes that label.
+    ///
+    /// Returns the updated record. Fails with `AgentError::MemoryError` if
+    /// the session has no such record.
+    pub async fn annotate(
+        &self,
+        session_id: &str,
+        record_id: Uuid,
+        labels: HashMap<String, Value>,
+    ) -> Result<MemoryRecord> {
+        let mut attempt = 1;
+        loop {
+            let mut record = self
+                .store
+                .get(session_id, record_id)
+                .await?
+                .ok_or_else(|| record_not_found(record_id))?;
+            let metadata = record.metadata.get_or_insert_with(HashMap::new);
+            for (name, value) in &labels {
+                match value {
+                    Value::Null => metadata.remove(&label_key(name)),
+                    value => metadata.insert(label_key(name), value.to_string()),
+                };
+            }
+            match self.update(record.clone()).await {
+                Ok(version) => {
+                    record.version = version;
+                    return Ok(record);
+                }
+                // Someone else updated the record; label the new version
+                Err(AgentError::VersionConflict(_)) if attempt < MAX_LABEL_ATTEMPTS => {
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InMemoryStore;
+    use serde_json::json;
+
+    fn record(session_id: &str, content: &str) -> MemoryRecord {
+        MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: session_id.to_string(),
+            role: "user".to_string(),
+            content: content.to_string(),
+            importance: 0.5,
+            timestamp: chrono::Utc::now(),
+            metadata: None,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_labels_are_merged_and_filterable() {
+        let memory = SessionMemory::new(Box::new(InMemoryStore::new()), 4);
+        let refund = record("s", "I want my money back");
+        let greeting = record("s", "Hi there");
+        memory.store(refund.clone()).await.unwrap();
+        memory.store(greeting.clone()).await.unwrap();
+
+        memory
+            .annotate(
+                "s",
+                refund.id,
+                HashMap::from([
+                    ("intent".to_string(), json!("refund")),
+                    ("sentiment".to_string(), json!(-0.6)),
+                ]),
+            )
+            .await
+            .unwrap();
+        let labeled = memory
+            .annotate(
+                "s",
+                refund.id,
+                HashMap::from([
+                    ("resolved".to_string(), json!(true)),
+                    ("sentiment".to_string(), Value::Null),
+                ]),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            labels_of(&labeled),
+            HashMap::from([
+                ("intent".to_string(), json!("refund")),
+                ("resolved".to_string(), json!(true)),
+            ])
+        );
+
+        let filter = MemoryFilter::new()
+            .with_label("intent", "refund")
+            .with_label("resolved", true);
+        let found = memory.retrieve_filtered("s", 10, &filter).await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].id, refund.id);
+
+        assert!(memory
+            .annotate("s", Uuid::new_v4(), HashMap::new())
+            .await
+            .is_err());
+    }
+}
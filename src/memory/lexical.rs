@@ -0,0 +1,189 @@
+//! Lexical (BM25) scoring and rank fusion for hybrid retrieval.
+//!
+//! Embeddings capture meaning but blur exact tokens, so a query for
+//! `ORD-4821` or `E_CONN_RESET` can rank unrelated records above the one
+//! containing it. BM25 over the raw terms catches those matches, and
+//! reciprocal rank fusion merges both rankings without calibrating their
+//! score scales against each other.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Term frequency saturation
+pub const BM25_K1: f32 = 1.2;
+/// Document length normalization
+pub const BM25_B: f32 = 0.75;
+/// Rank offset damping the influence of top positions in reciprocal rank fusion
+pub const DEFAULT_RRF_K: f32 = 60.0;
+
+/// Splits text into lowercase terms.
+///
+/// Hyphens and underscores stay inside terms so identifiers match whole;
+/// their parts are emitted as well so `4821` still finds `ORD-4821`.
+pub fn tokenize(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    for raw in text.split(|c: char| !(c.is_alphanumeric() || c == '-' || c == '_')) {
+        let term = raw.trim_matches(|c| c == '-' || c == '_').to_lowercase();
+        if term.is_empty() {
+            continue;
+        }
+        if term.contains(['-', '_']) {
+            terms.extend(
+                term.split(['-', '_'])
+                    .filter(|part| !part.is_empty())
+                    .map(str::to_string),
+            );
+        }
+        terms.push(term);
+    }
+    terms
+}
+
+/// Term counts of one indexed document
+#[derive(Debug, Clone, Default)]
+pub struct TermVector {
+    counts: HashMap<String, u32>,
+    len: usize,
+}
+
+impl TermVector {
+    /// Tokenizes and counts the terms of `text`
+    pub fn new(text: &str) -> Self {
+        let terms = tokenize(text);
+        let len = terms.len();
+        let mut counts = HashMap::new();
+        for term in terms {
+            *counts.entry(term).or_insert(0) += 1;
+        }
+        Self { counts, len }
+    }
+
+    /// Returns how often `term` occurs
+    pub fn count(&self, term: &str) -> u32 {
+        self.counts.get(term).copied().unwrap_or(0)
+    }
+
+    /// Returns the number of terms in the document
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns true if the document has no terms
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+/// Scores `docs` against `query` with Okapi BM25.
+///
+/// Document frequencies come from `docs` themselves, so scores reflect the
+/// collection being searched (for example a single session).
+pub fn bm25_scores(query: &str, docs: &[&TermVector]) -> Vec<f32> {
+    let mut terms = tokenize(query);
+    terms.sort_unstable();
+    terms.dedup();
+
+    if docs.is_empty() || terms.is_empty() {
+        return vec![0.0; docs.len()];
+    }
+
+    let n = docs.len() as f32;
+    let avg_len = (docs.iter().map(|d| d.len()).sum::<usize>() as f32 / n).max(1.0);
+    let idf: Vec<f32> = terms
+        .iter()
+        .map(|term| {
+            let df = docs.iter().filter(|d| d.count(term) > 0).count() as f32;
+            ((n - df + 0.5) / (df + 0.5) + 1.0).ln()
+        })
+        .collect();
+
+    docs.iter()
+        .map(|doc| {
+            let norm = BM25_K1 * (1.0 - BM25_B + BM25_B * doc.len() as f32 / avg_len);
+            terms
+                .iter()
+                .zip(&idf)
+                .map(|(term, idf)| {
+                    let tf = doc.count(term) as f32;
+                    idf * tf * (BM25_K1 + 1.0) / (tf + norm)
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// Fuses ranked lists (best first) into one ranking by reciprocal rank.
+///
+/// Each item scores `Σ 1 / (k + rank)` over the lists it appears in, ranks
+/// starting at 1; the result is sorted by descending fused score.
+pub fn reciprocal_rank_fusion<T>(rankings: &[Vec<T>], k: f32) -> Vec<(T, f32)>
+where
+    T: Clone + Eq + Hash,
+{
+    let mut fused: HashMap<T, f32> = HashMap::new();
+    let mut order = Vec::new();
+    for ranking in rankings {
+        for (rank, item) in ranking.iter().enumerate() {
+            let score = fused.entry(item.clone()).or_insert_with(|| {
+                order.push(item.clone());
+                0.0
+            });
+            *score += 1.0 / (k + rank as f32 + 1.0);
+        }
+    }
+
+    // Ties keep first-seen order so results are deterministic
+    let mut results: Vec<(T, f32)> = order
+        .into_iter()
+        .map(|item| {
+            let score = fused[&item];
+            (item, score)
+        })
+        .collect();
+    results.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize_keeps_identifiers() {
+        assert_eq!(
+            tokenize("Order ORD-4821 failed: E_CONN_RESET."),
+            vec![
+                "order",
+                "ord",
+                "4821",
+                "ord-4821",
+                "failed",
+                "e",
+                "conn",
+                "reset",
+                "e_conn_reset"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bm25_prefers_rare_exact_terms() {
+        let docs = [
+            TermVector::new("the order shipped"),
+            TermVector::new("order ORD-4821 is delayed"),
+            TermVector::new("the weather is nice"),
+        ];
+        let refs: Vec<&TermVector> = docs.iter().collect();
+        let scores = bm25_scores("ORD-4821", &refs);
+        assert!(scores[1] > 0.0);
+        assert_eq!(scores[0], 0.0);
+        assert_eq!(scores[2], 0.0);
+    }
+
+    #[test]
+    fn test_rrf_rewards_agreement() {
+        let fused = reciprocal_rank_fusion(&[vec!["a", "b", "c"], vec!["b", "c"]], DEFAULT_RRF_K);
+        let order: Vec<&str> = fused.iter().map(|(item, _)| *item).collect();
+        assert_eq!(order, vec!["b", "c", "a"]);
+    }
+}
@@ -1,9 +1,57 @@
 use chrono::{DateTime, Utc};
+use futures::stream::{BoxStream, StreamExt, TryStreamExt};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::error::Result;
+use crate::error::{AgentError, Result};
+use crate::memory::images::{is_image, IMAGE_MIME_KEY, IMAGE_REF_KEY, IMAGE_ROLE};
+use crate::memory::importance::DEFAULT_IMPORTANCE;
+use crate::telemetry;
+use crate::tenant::{self, stamped_tenant, TenantGuard, TENANT_METADATA_KEY};
+use crate::types::File;
+
+pub mod buffered;
+pub mod cached;
+pub mod compaction;
+pub mod degraded;
+pub mod embedding;
+pub mod hnsw;
+pub mod images;
+pub mod importance;
+pub mod interchange;
+pub mod labels;
+pub mod lexical;
+pub mod rerank;
+pub mod threads;
+pub mod tiers;
+pub mod wal;
+
+pub use buffered::{BufferConfig, BufferedStore};
+pub use cached::{CacheConfig, CacheStats, CachedStore};
+pub use compaction::Compactor;
+pub use degraded::{DeadLetter, DegradableStore, DegradationConfig, MemoryHealth};
+#[cfg(feature = "bedrock")]
+pub use embedding::BedrockEmbedder;
+#[cfg(feature = "memory")]
+pub use embedding::FastEmbedder;
+pub use embedding::{Embedder, EmbeddingConfig, PrefixedEmbedder, VertexEmbedder};
+pub use hnsw::HnswParams;
+pub use images::{image_scope, ImageEmbedder, ImageMemory, ImageQuery};
+pub use importance::{HeuristicScorer, ImportanceScorer, LlmScorer};
+pub use labels::{label_key, labels_of, LABEL_PREFIX};
+#[cfg(feature = "memory")]
+pub use rerank::CrossEncoderReranker;
+pub use rerank::{CohereReranker, LlmReranker, Reranker};
+pub use threads::{
+    parent_session, rollup_scope, thread_levels, thread_scope, SOURCE_ID_KEY, THREAD_SEPARATOR,
+};
+pub use tiers::{semantic_scope, MemoryTier, PromotionRules};
+pub use wal::WalStore;
+
+use hnsw::Hnsw;
+use lexical::{bm25_scores, reciprocal_rank_fusion, TermVector, DEFAULT_RRF_K};
 
 // Memory backend implementations
 #[cfg(feature = "postgres")]
@@ -15,18 +63,28 @@ pub mod qdrant;
 #[cfg(feature = "mongodb")]
 pub mod mongodb;
 
+#[cfg(feature = "surrealdb")]
+pub mod surrealdb;
+
 // Re-export backends
 #[cfg(feature = "postgres")]
-pub use postgres::PostgresStore;
+pub use postgres::{PostgresConfig, PostgresStore};
 
 #[cfg(feature = "qdrant")]
-pub use qdrant::QdrantStore;
+pub use qdrant::{QdrantConfig, QdrantStore};
 
 #[cfg(feature = "mongodb")]
 pub use mongodb::MongoStore;
 
-/// Memory record storing a piece of information
+#[cfg(feature = "surrealdb")]
+pub use surrealdb::SurrealStore;
+
+/// Memory record storing a piece of information.
+///
+/// Build one with [`MemoryRecord::new`]; fields may be added in minor
+/// releases.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[non_exhaustive]
 pub struct MemoryRecord {
     pub id: Uuid,
     pub session_id: String,
@@ -38,17 +96,215 @@ pub struct MemoryRecord {
     pub metadata: Option<HashMap<String, String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub embedding: Option<Vec<f32>>,
+    /// Revision counter used for optimistic concurrency on `update`
+    #[serde(default)]
+    pub version: u64,
+    /// Once passed, stores hide the record from reads and `purge_expired` deletes it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl MemoryRecord {
+    /// Creates a record of `content` said by `role` in `session_id`, with a
+    /// fresh id, the current time and default importance
+    pub fn new(
+        session_id: impl Into<String>,
+        role: impl Into<String>,
+        content: impl Into<String>,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            session_id: session_id.into(),
+            role: role.into(),
+            content: content.into(),
+            importance: DEFAULT_IMPORTANCE,
+            timestamp: Utc::now(),
+            metadata: None,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        }
+    }
+
+    /// Sets the record to expire `ttl` from now
+    pub fn with_ttl(mut self, ttl: chrono::Duration) -> Self {
+        self.expires_at = Some(Utc::now() + ttl);
+        self
+    }
+
+    /// Returns true if the record has expired as of `now`
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Constraints applied by a store to `retrieve` and `search` results.
+///
+/// All set conditions must hold. Backends push the filter down into their
+/// native query language rather than filtering client-side.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MemoryFilter {
+    /// Metadata entries that must be present with exactly these values
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub role: Option<String>,
+    /// Inclusive lower bound on `timestamp`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub since: Option<DateTime<Utc>>,
+    /// Exclusive upper bound on `timestamp`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub until: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_importance: Option<f32>,
+}
+
+impl MemoryFilter {
+    /// Creates a filter that matches every record
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requires a metadata entry with the given value
+    pub fn with_metadata(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.metadata.insert(key.into(), value.into());
+        self
+    }
+
+    /// Requires the given role
+    pub fn with_role(mut self, role: impl Into<String>) -> Self {
+        self.role = Some(role.into());
+        self
+    }
+
+    /// Requires records at or after `since`
+    pub fn with_since(mut self, since: DateTime<Utc>) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// Requires records strictly before `until`
+    pub fn with_until(mut self, until: DateTime<Utc>) -> Self {
+        self.until = Some(until);
+        self
+    }
+
+    /// Requires importance of at least `min_importance`
+    pub fn with_min_importance(mut self, min_importance: f32) -> Self {
+        self.min_importance = Some(min_importance);
+        self
+    }
+
+    /// Returns true if no condition is set
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Evaluates the filter against a record
+    pub fn matches(&self, record: &MemoryRecord) -> bool {
+        self.role.as_ref().is_none_or(|role| &record.role == role)
+            && self.since.is_none_or(|since| record.timestamp >= since)
+            && self.until.is_none_or(|until| record.timestamp < until)
+            && self
+                .min_importance
+                .is_none_or(|min| record.importance >= min)
+            && self.metadata.iter().all(|(key, value)| {
+                record
+                    .metadata
+                    .as_ref()
+                    .and_then(|m| m.get(key))
+                    .is_some_and(|v| v == value)
+            })
+    }
+}
+
+/// What [`SessionMemory::search`] looks for
+#[derive(Debug, Clone)]
+pub enum MemoryQuery {
+    /// Memories near a text embedding
+    Embedding(Vec<f32>),
+    /// Image memories matching a description or similar to an image
+    Image(ImageQuery),
+}
+
+impl From<Vec<f32>> for MemoryQuery {
+    fn from(embedding: Vec<f32>) -> Self {
+        Self::Embedding(embedding)
+    }
+}
+
+impl From<ImageQuery> for MemoryQuery {
+    fn from(query: ImageQuery) -> Self {
+        Self::Image(query)
+    }
+}
+
+/// One page of memories returned by [`MemoryStore::retrieve_page`]
+#[derive(Debug, Clone, Default)]
+pub struct MemoryPage {
+    /// Records in this page, newest first
+    pub records: Vec<MemoryRecord>,
+    /// Opaque token for the next (older) page, `None` once exhausted
+    pub next_cursor: Option<String>,
 }
 
 /// Memory store trait for different backends
 #[async_trait::async_trait]
 pub trait MemoryStore: Send + Sync {
-    /// Stores a memory record
+    /// Stores a memory record, replacing any record with the same id
     async fn store(&self, record: MemoryRecord) -> Result<()>;
 
-    /// Retrieves memories for a session
+    /// Retrieves the most recent memories for a session
     async fn retrieve(&self, session_id: &str, limit: usize) -> Result<Vec<MemoryRecord>>;
 
+    /// Retrieves the most recent memories for a session that match `filter`.
+    ///
+    /// The default implementation filters `export` client-side; backends
+    /// override it to push the filter down into their native query.
+    async fn retrieve_filtered(
+        &self,
+        session_id: &str,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        if filter.is_empty() {
+            return self.retrieve(session_id, limit).await;
+        }
+        self.export(session_id)
+            .try_filter(|record| futures::future::ready(filter.matches(record)))
+            .take(limit)
+            .try_collect()
+            .await
+    }
+
+    /// Retrieves one page of a session's memories, newest first.
+    ///
+    /// Pass `None` for the first page and the returned `next_cursor` for each
+    /// following page. Cursors are keyset positions, so records stored while
+    /// paging never cause duplicates or gaps in older pages.
+    ///
+    /// The default implementation retrieves the whole session for every
+    /// page; backends override it with a keyset query.
+    async fn retrieve_page(
+        &self,
+        session_id: &str,
+        cursor: Option<&str>,
+        page_size: usize,
+    ) -> Result<MemoryPage> {
+        let after = cursor.map(decode_cursor).transpose()?;
+        let now = Utc::now();
+        let mut records: Vec<MemoryRecord> = self
+            .retrieve(session_id, WHOLE_SESSION)
+            .await?
+            .into_iter()
+            .filter(|r| !r.is_expired_at(now))
+            .filter(|r| after.is_none_or(|position| (r.timestamp, r.id) < position))
+            .collect();
+        records.sort_by_key(|r| std::cmp::Reverse((r.timestamp, r.id)));
+        records.truncate(page_size + 1);
+        Ok(into_page(records, page_size))
+    }
+
     /// Searches for similar memories using embeddings
     async fn search(
         &self,
@@ -57,23 +313,418 @@ pub trait MemoryStore: Send + Sync {
         limit: usize,
     ) -> Result<Vec<MemoryRecord>>;
 
+    /// Searches for similar memories that match `filter`.
+    ///
+    /// The default implementation filters the hits of `search` client-side,
+    /// so it may return fewer than `limit`; backends override it to push the
+    /// filter down into their native query.
+    async fn search_filtered(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        let mut records = self.search(session_id, query_embedding, limit).await?;
+        records.retain(|record| filter.matches(record));
+        Ok(records)
+    }
+
+    /// Fetches record `id` of `session_id`, if it exists and hasn't expired.
+    ///
+    /// The default implementation scans `export`; backends override it with
+    /// a lookup by id.
+    async fn get(&self, session_id: &str, id: Uuid) -> Result<Option<MemoryRecord>> {
+        self.export(session_id)
+            .try_filter(|record| futures::future::ready(record.id == id))
+            .try_next()
+            .await
+    }
+
+    /// Updates an existing record with compare-and-swap semantics.
+    ///
+    /// The write only succeeds if the stored version equals `record.version`;
+    /// the stored version is then incremented and returned. A mismatch fails
+    /// with `AgentError::VersionConflict` so the caller can re-read and retry.
+    /// Records written before versioning count as version 0. A `store` over
+    /// an existing id bumps its version too, except in Qdrant, whose plain
+    /// upserts keep the version they are given.
+    ///
+    /// The default implementation fails, as a compare-and-swap can't be
+    /// built from the other methods.
+    async fn update(&self, record: MemoryRecord) -> Result<u64> {
+        Err(AgentError::MemoryError(format!(
+            "Store does not support updating record {}",
+            record.id
+        )))
+    }
+
+    /// Deletes every record whose `expires_at` has passed, returning how many were removed.
+    ///
+    /// The default implementation removes nothing.
+    async fn purge_expired(&self) -> Result<usize> {
+        Ok(0)
+    }
+
+    /// Streams every unexpired record of a session, newest first.
+    ///
+    /// The default implementation retrieves the whole session with one
+    /// `retrieve` call. Backends with a keyset `retrieve_page` override it
+    /// with [`export_pages`], so memory use stays bounded.
+    fn export<'a>(&'a self, session_id: &'a str) -> BoxStream<'a, Result<MemoryRecord>> {
+        let records = async move {
+            let now = Utc::now();
+            let mut records: Vec<MemoryRecord> = self
+                .retrieve(session_id, WHOLE_SESSION)
+                .await?
+                .into_iter()
+                .filter(|r| !r.is_expired_at(now))
+                .collect();
+            records.sort_by_key(|r| std::cmp::Reverse((r.timestamp, r.id)));
+            Ok::<_, AgentError>(futures::stream::iter(records.into_iter().map(Ok)))
+        };
+        futures::stream::once(records).try_flatten().boxed()
+    }
+
+    /// Stores several records at once.
+    ///
+    /// The default implementation stores them one by one; backends override
+    /// it with a single round trip where they can.
+    async fn store_batch(&self, records: Vec<MemoryRecord>) -> Result<()> {
+        for record in records {
+            self.store(record).await?;
+        }
+        Ok(())
+    }
+
+    /// Stores every record of `records`, returning how many were imported.
+    ///
+    /// Records keep their ids and sessions, so piping one store's `export`
+    /// into another's `import` migrates a session between backends.
+    async fn import(&self, mut records: BoxStream<'_, Result<MemoryRecord>>) -> Result<usize> {
+        let mut imported = 0;
+        let mut batch = Vec::with_capacity(EXPORT_PAGE_SIZE);
+        while let Some(record) = records.try_next().await? {
+            batch.push(record);
+            if batch.len() == EXPORT_PAGE_SIZE {
+                imported += batch.len();
+                self.store_batch(std::mem::take(&mut batch)).await?;
+            }
+        }
+        imported += batch.len();
+        if !batch.is_empty() {
+            self.store_batch(batch).await?;
+        }
+        Ok(imported)
+    }
+
     /// Flushes all pending writes
     async fn flush(&self) -> Result<()>;
 }
 
+/// Records fetched per page by [`export_pages`], and stored per batch by
+/// the default [`MemoryStore::import`]
+const EXPORT_PAGE_SIZE: usize = 256;
+
+/// Limit asking `retrieve` for a whole session; it fits an `i64`, so
+/// backends casting the limit for their query don't wrap it
+const WHOLE_SESSION: usize = i64::MAX as usize;
+
+/// Streams every record of a session through `store`'s `retrieve_page`,
+/// newest first, a page at a time.
+///
+/// Stores with a keyset `retrieve_page` return this from `export`.
+pub fn export_pages<'a, S: MemoryStore + ?Sized>(
+    store: &'a S,
+    session_id: &'a str,
+) -> BoxStream<'a, Result<MemoryRecord>> {
+    futures::stream::try_unfold(Some(None::<String>), move |cursor| async move {
+        let Some(cursor) = cursor else {
+            return Ok::<_, AgentError>(None);
+        };
+        let page = store
+            .retrieve_page(session_id, cursor.as_deref(), EXPORT_PAGE_SIZE)
+            .await?;
+        let next = page.next_cursor.map(Some);
+        Ok(Some((
+            futures::stream::iter(page.records.into_iter().map(Ok)),
+            next,
+        )))
+    })
+    .try_flatten()
+    .boxed()
+}
+
+/// Encodes the keyset position just after `record` as an opaque cursor
+pub(crate) fn encode_cursor(record: &MemoryRecord) -> String {
+    format!(
+        "{}|{}",
+        record
+            .timestamp
+            .to_rfc3339_opts(chrono::SecondsFormat::Nanos, true),
+        record.id
+    )
+}
+
+/// Decodes a cursor produced by [`encode_cursor`]
+pub(crate) fn decode_cursor(cursor: &str) -> Result<(DateTime<Utc>, Uuid)> {
+    let invalid = || AgentError::MemoryError(format!("Invalid page cursor: {}", cursor));
+    let (timestamp, id) = cursor.split_once('|').ok_or_else(invalid)?;
+    let timestamp = DateTime::parse_from_rfc3339(timestamp)
+        .map_err(|_| invalid())?
+        .with_timezone(&Utc);
+    let id = Uuid::parse_str(id).map_err(|_| invalid())?;
+    Ok((timestamp, id))
+}
+
+/// Builds a page from up to `page_size + 1` records fetched past the cursor
+pub(crate) fn into_page(mut records: Vec<MemoryRecord>, page_size: usize) -> MemoryPage {
+    let has_more = records.len() > page_size;
+    records.truncate(page_size);
+    let next_cursor = if has_more {
+        records.last().map(encode_cursor)
+    } else {
+        None
+    };
+    MemoryPage {
+        records,
+        next_cursor,
+    }
+}
+
+/// Builds the error returned when an optimistic update loses the race
+pub(crate) fn version_conflict(id: Uuid, expected: u64, actual: u64) -> AgentError {
+    AgentError::VersionConflict(format!(
+        "memory {} expected version {}, found {}",
+        id, expected, actual
+    ))
+}
+
+/// Builds the error returned when updating a record that does not exist
+pub(crate) fn record_not_found(id: Uuid) -> AgentError {
+    AgentError::MemoryError(format!("memory {} not found", id))
+}
+
+/// Per-session HNSW graphs over embedded records
+#[derive(Default)]
+struct VectorIndex {
+    params: HnswParams,
+    metric: SimilarityMetric,
+    // Every search is session-scoped, so each session gets its own graph
+    graphs: HashMap<String, Hnsw>,
+    /// Position in the record list of every indexed record
+    positions: HashMap<Uuid, usize>,
+}
+
+impl VectorIndex {
+    fn insert(&mut self, record: &MemoryRecord, position: usize) {
+        if let Some(embedding) = &record.embedding {
+            let (params, metric) = (self.params, self.metric);
+            self.graphs
+                .entry(record.session_id.clone())
+                .or_insert_with(|| Hnsw::new(params).with_metric(metric))
+                .insert(record.id, embedding);
+            self.positions.insert(record.id, position);
+        }
+    }
+
+    fn remove(&mut self, record: &MemoryRecord) {
+        if self.positions.remove(&record.id).is_none() {
+            return;
+        }
+        if let Some(graph) = self.graphs.get_mut(&record.session_id) {
+            graph.remove(record.id);
+            if graph.is_empty() {
+                self.graphs.remove(&record.session_id);
+            }
+        }
+    }
+}
+
 /// In-memory store implementation
+///
+/// Embeddings are indexed in an HNSW graph per session, so `search` stays
+/// fast as sessions grow to hundreds of thousands of records. Records are
+/// also indexed by term for BM25 keyword and hybrid search.
 pub struct InMemoryStore {
     records: parking_lot::RwLock<Vec<MemoryRecord>>,
+    // Always locked after `records`
+    terms: parking_lot::RwLock<HashMap<Uuid, TermVector>>,
+    // Always locked after `terms`
+    vectors: parking_lot::RwLock<VectorIndex>,
 }
 
 impl InMemoryStore {
     pub fn new() -> Self {
         Self {
             records: parking_lot::RwLock::new(Vec::new()),
+            terms: parking_lot::RwLock::new(HashMap::new()),
+            vectors: parking_lot::RwLock::new(VectorIndex::default()),
+        }
+    }
+
+    /// Tunes the approximate nearest-neighbour index used by `search`.
+    ///
+    /// Set this before storing records; graphs already built keep the
+    /// parameters they were created with.
+    pub fn with_index_params(self, params: HnswParams) -> Self {
+        self.vectors.write().params = params;
+        self
+    }
+
+    /// Ranks `search` and `hybrid_search` results by `metric` instead of
+    /// cosine similarity.
+    ///
+    /// Set this before storing records, like the index parameters.
+    pub fn with_metric(self, metric: SimilarityMetric) -> Self {
+        self.vectors.write().metric = metric;
+        self
+    }
+
+    /// Writes `record`, bumping the version of one it overwrites unless
+    /// `verbatim`, which mirrors records already versioned elsewhere
+    pub(crate) fn put(&self, record: MemoryRecord, verbatim: bool) {
+        self.put_unless(record, verbatim, |_| false);
+    }
+
+    /// Like [`put`](InMemoryStore::put), but leaves a record with the same
+    /// id in place if `keep` holds for it; returns whether `record` was
+    /// written
+    pub(crate) fn put_unless(
+        &self,
+        mut record: MemoryRecord,
+        verbatim: bool,
+        keep: impl FnOnce(&MemoryRecord) -> bool,
+    ) -> bool {
+        let mut records = self.records.write();
+        let position = records.iter().position(|r| r.id == record.id);
+        if position.is_some_and(|position| keep(&records[position])) {
+            return false;
+        }
+        self.terms
+            .write()
+            .insert(record.id, TermVector::new(&record.content));
+        let mut vectors = self.vectors.write();
+        match position {
+            Some(position) => {
+                if !verbatim {
+                    record.version = records[position].version.max(record.version) + 1;
+                }
+                vectors.remove(&records[position]);
+                vectors.insert(&record, position);
+                records[position] = record;
+            }
+            None => {
+                vectors.insert(&record, records.len());
+                records.push(record);
+            }
         }
+        true
+    }
+
+    /// Ranks a session's records against `query` by BM25 keyword relevance.
+    ///
+    /// Only records sharing at least one term with the query are returned.
+    pub async fn keyword_search(
+        &self,
+        session_id: &str,
+        query: &str,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        let records = self.records.read();
+        let ranked = self.keyword_ranking(&records, session_id, query, filter);
+        Ok(ranked
+            .into_iter()
+            .take(limit)
+            .map(|i| records[i].clone())
+            .collect())
+    }
+
+    /// Searches by keywords and embedding at once, fusing both rankings with
+    /// reciprocal rank fusion.
+    ///
+    /// Exact identifiers such as order numbers or error codes surface through
+    /// the keyword ranking even when their embeddings are not close.
+    pub async fn hybrid_search(
+        &self,
+        session_id: &str,
+        query: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        let records = self.records.read();
+        let similarity = self.vectors.read().metric.similarity();
+        let rankings = [
+            self.keyword_ranking(&records, session_id, query, filter),
+            vector_ranking(&records, session_id, &query_embedding, filter, similarity),
+        ];
+        Ok(reciprocal_rank_fusion(&rankings, DEFAULT_RRF_K)
+            .into_iter()
+            .take(limit)
+            .map(|(i, _)| records[i].clone())
+            .collect())
+    }
+
+    /// Indices of visible records with a positive BM25 score, best first
+    fn keyword_ranking(
+        &self,
+        records: &[MemoryRecord],
+        session_id: &str,
+        query: &str,
+        filter: &MemoryFilter,
+    ) -> Vec<usize> {
+        let now = Utc::now();
+        let terms = self.terms.read();
+        let empty = TermVector::default();
+
+        let candidates: Vec<usize> = records
+            .iter()
+            .enumerate()
+            .filter(|(_, r)| {
+                r.session_id == session_id && !r.is_expired_at(now) && filter.matches(r)
+            })
+            .map(|(i, _)| i)
+            .collect();
+        let docs: Vec<&TermVector> = candidates
+            .iter()
+            .map(|&i| terms.get(&records[i].id).unwrap_or(&empty))
+            .collect();
+
+        let mut scored: Vec<(usize, f32)> = candidates
+            .into_iter()
+            .zip(bm25_scores(query, &docs))
+            .filter(|(_, score)| *score > 0.0)
+            .collect();
+        scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+        scored.into_iter().map(|(i, _)| i).collect()
     }
 }
 
+/// Indices of visible records with embeddings, most similar first
+fn vector_ranking(
+    records: &[MemoryRecord],
+    session_id: &str,
+    query_embedding: &[f32],
+    filter: &MemoryFilter,
+    similarity: Similarity,
+) -> Vec<usize> {
+    let now = Utc::now();
+    let mut scored: Vec<(usize, f32)> = records
+        .iter()
+        .enumerate()
+        .filter(|(_, r)| r.session_id == session_id && !r.is_expired_at(now) && filter.matches(r))
+        .filter_map(|(i, r)| {
+            let embedding = r.embedding.as_ref()?;
+            Some((i, similarity(query_embedding, embedding)))
+        })
+        .collect();
+
+    scored.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap());
+    scored.into_iter().map(|(i, _)| i).collect()
+}
+
 impl Default for InMemoryStore {
     fn default() -> Self {
         Self::new()
@@ -83,16 +734,26 @@ impl Default for InMemoryStore {
 #[async_trait::async_trait]
 impl MemoryStore for InMemoryStore {
     async fn store(&self, record: MemoryRecord) -> Result<()> {
-        let mut records = self.records.write();
-        records.push(record);
+        self.put(record, false);
         Ok(())
     }
 
     async fn retrieve(&self, session_id: &str, limit: usize) -> Result<Vec<MemoryRecord>> {
+        self.retrieve_filtered(session_id, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn retrieve_filtered(
+        &self,
+        session_id: &str,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        let now = Utc::now();
         let records = self.records.read();
         let filtered: Vec<MemoryRecord> = records
             .iter()
-            .filter(|r| r.session_id == session_id)
+            .filter(|r| r.session_id == session_id && !r.is_expired_at(now) && filter.matches(r))
             .rev()
             .take(limit)
             .cloned()
@@ -100,25 +761,140 @@ impl MemoryStore for InMemoryStore {
         Ok(filtered)
     }
 
+    async fn retrieve_page(
+        &self,
+        session_id: &str,
+        cursor: Option<&str>,
+        page_size: usize,
+    ) -> Result<MemoryPage> {
+        let after = cursor.map(decode_cursor).transpose()?;
+        let now = Utc::now();
+        let records = self.records.read();
+        let mut matching: Vec<&MemoryRecord> = records
+            .iter()
+            .filter(|r| r.session_id == session_id && !r.is_expired_at(now))
+            .filter(|r| after.is_none_or(|position| (r.timestamp, r.id) < position))
+            .collect();
+        matching.sort_by_key(|r| std::cmp::Reverse((r.timestamp, r.id)));
+
+        let page = matching.into_iter().take(page_size + 1).cloned().collect();
+        Ok(into_page(page, page_size))
+    }
+
+    fn export<'a>(&'a self, session_id: &'a str) -> BoxStream<'a, Result<MemoryRecord>> {
+        export_pages(self, session_id)
+    }
+
+    async fn get(&self, session_id: &str, id: Uuid) -> Result<Option<MemoryRecord>> {
+        let now = Utc::now();
+        let records = self.records.read();
+        Ok(records
+            .iter()
+            .find(|r| r.id == id && r.session_id == session_id && !r.is_expired_at(now))
+            .cloned())
+    }
+
     async fn search(
         &self,
         session_id: &str,
         query_embedding: Vec<f32>,
         limit: usize,
     ) -> Result<Vec<MemoryRecord>> {
+        self.search_filtered(session_id, query_embedding, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn search_filtered(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        let now = Utc::now();
         let records = self.records.read();
-        let mut scored: Vec<(f32, MemoryRecord)> = records
+        let vectors = self.vectors.read();
+        let Some(graph) = vectors.graphs.get(session_id) else {
+            return Ok(Vec::new());
+        };
+
+        // The graph doesn't know about filters, so widen the search until
+        // enough hits pass them or the whole session has been considered
+        let mut depth = limit;
+        loop {
+            let hits = graph.search(&query_embedding, depth, depth);
+            let mut matches: Vec<(f32, &MemoryRecord)> = hits
+                .iter()
+                .filter_map(|(id, score)| vectors.positions.get(id).map(|&i| (*score, &records[i])))
+                .filter(|(_, r)| !r.is_expired_at(now) && filter.matches(r))
+                .collect();
+            if matches.len() >= limit || depth >= graph.len() {
+                // Equally similar records rank oldest first, as a full scan would
+                matches.sort_by(|a, b| {
+                    b.0.total_cmp(&a.0)
+                        .then(a.1.timestamp.cmp(&b.1.timestamp))
+                        .then(a.1.id.cmp(&b.1.id))
+                });
+                return Ok(matches
+                    .into_iter()
+                    .take(limit)
+                    .map(|(_, r)| r.clone())
+                    .collect());
+            }
+            depth = (depth * 4).min(graph.len());
+        }
+    }
+
+    async fn update(&self, mut record: MemoryRecord) -> Result<u64> {
+        let mut records = self.records.write();
+        let position = records
             .iter()
-            .filter(|r| r.session_id == session_id && r.embedding.is_some())
-            .map(|r| {
-                let embedding = r.embedding.as_ref().unwrap();
-                let similarity = cosine_similarity(&query_embedding, embedding);
-                (similarity, r.clone())
-            })
-            .collect();
+            .position(|r| r.id == record.id)
+            .ok_or_else(|| record_not_found(record.id))?;
+        let existing = &mut records[position];
+
+        if existing.version != record.version {
+            return Err(version_conflict(
+                record.id,
+                record.version,
+                existing.version,
+            ));
+        }
+
+        record.version += 1;
+        let version = record.version;
+        self.terms
+            .write()
+            .insert(record.id, TermVector::new(&record.content));
+        let mut vectors = self.vectors.write();
+        vectors.remove(existing);
+        vectors.insert(&record, position);
+        *existing = record;
+        Ok(version)
+    }
 
-        scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
-        Ok(scored.into_iter().take(limit).map(|(_, r)| r).collect())
+    async fn purge_expired(&self) -> Result<usize> {
+        let now = Utc::now();
+        let mut records = self.records.write();
+        let mut terms = self.terms.write();
+        let mut vectors = self.vectors.write();
+        let before = records.len();
+        records.retain(|r| {
+            let expired = r.is_expired_at(now);
+            if expired {
+                terms.remove(&r.id);
+                vectors.remove(r);
+            }
+            !expired
+        });
+
+        // Removal shifts the records after each gap
+        for (position, record) in records.iter().enumerate() {
+            if let Some(indexed) = vectors.positions.get_mut(&record.id) {
+                *indexed = position;
+            }
+        }
+        Ok(before - records.len())
     }
 
     async fn flush(&self) -> Result<()> {
@@ -127,7 +903,7 @@ impl MemoryStore for InMemoryStore {
 }
 
 /// Calculates cosine similarity between two vectors
-fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
     if a.len() != b.len() {
         return 0.0;
     }
@@ -143,126 +919,1242 @@ fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
     }
 }
 
-/// Maximal Marginal Relevance (MMR) for diverse retrieval
-///
-/// Balances relevance to query with diversity in results.
-/// Lambda controls the trade-off: 1.0 = pure relevance, 0.0 = pure diversity
-pub fn mmr_rerank(
-    query_embedding: &[f32],
-    candidates: Vec<MemoryRecord>,
-    k: usize,
-    lambda: f32,
-) -> Vec<MemoryRecord> {
-    if candidates.is_empty() {
-        return Vec::new();
-    }
+/// Similarity between two embeddings, higher meaning more alike
+pub type Similarity = fn(&[f32], &[f32]) -> f32;
 
-    let k = k.min(candidates.len());
-    let mut selected = Vec::with_capacity(k);
-    let mut remaining = candidates;
+/// Dot product similarity, for embeddings that are already normalized
+pub fn dot_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
+    }
+    a.iter().zip(b.iter()).map(|(x, y)| x * y).sum()
+}
 
-    // Select first item with highest similarity to query
-    if let Some((idx, _)) = remaining
-        .iter()
-        .enumerate()
-        .filter_map(|(i, r)| {
-            r.embedding
-                .as_ref()
-                .map(|emb| (i, cosine_similarity(query_embedding, emb)))
-        })
-        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
-    {
-        selected.push(remaining.swap_remove(idx));
+/// Euclidean similarity, `1 / (1 + distance)`, so identical embeddings score
+/// 1.0 and distant ones approach 0.0
+pub fn euclidean_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() {
+        return 0.0;
     }
+    let distance = a
+        .iter()
+        .zip(b.iter())
+        .map(|(x, y)| (x - y) * (x - y))
+        .sum::<f32>()
+        .sqrt();
+    1.0 / (1.0 + distance)
+}
 
-    // Iteratively select items that maximize MMR score
-    while selected.len() < k && !remaining.is_empty() {
-        let next_idx = remaining
+/// How a store compares embeddings when ranking search results.
+///
+/// Use the metric the embedding model was trained for: most sentence
+/// embedders expect cosine, while some retrieval models are trained for
+/// dot product and lose ranking quality when their lengths are ignored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SimilarityMetric {
+    #[default]
+    Cosine,
+    Dot,
+    Euclidean,
+}
+
+impl SimilarityMetric {
+    /// Returns the similarity function of this metric
+    pub fn similarity(self) -> Similarity {
+        match self {
+            SimilarityMetric::Cosine => cosine_similarity,
+            SimilarityMetric::Dot => dot_similarity,
+            SimilarityMetric::Euclidean => euclidean_similarity,
+        }
+    }
+}
+
+/// Settings for [`mmr_rerank_with`]
+#[derive(Debug, Clone, Copy)]
+pub struct MmrConfig {
+    lambda: f32,
+    similarity: Similarity,
+    half_life: Option<chrono::Duration>,
+    recency_weight: f32,
+    importance_weight: f32,
+}
+
+impl Default for MmrConfig {
+    fn default() -> Self {
+        Self {
+            lambda: 0.5,
+            similarity: cosine_similarity,
+            half_life: None,
+            recency_weight: 0.0,
+            importance_weight: 0.0,
+        }
+    }
+}
+
+impl MmrConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Trades relevance against diversity: 1.0 = pure relevance, 0.0 = pure
+    /// diversity
+    pub fn with_lambda(mut self, lambda: f32) -> Self {
+        self.lambda = lambda;
+        self
+    }
+
+    /// Compares embeddings with `similarity` instead of cosine similarity
+    pub fn with_similarity(mut self, similarity: Similarity) -> Self {
+        self.similarity = similarity;
+        self
+    }
+
+    /// Compares embeddings with the similarity function of `metric`
+    pub fn with_metric(self, metric: SimilarityMetric) -> Self {
+        self.with_similarity(metric.similarity())
+    }
+
+    /// Boosts recent records by `weight` times a decay that halves every
+    /// `half_life`
+    pub fn with_recency(mut self, half_life: chrono::Duration, weight: f32) -> Self {
+        self.half_life = Some(half_life);
+        self.recency_weight = weight;
+        self
+    }
+
+    /// Boosts records by `weight` times their importance
+    pub fn with_importance_weight(mut self, weight: f32) -> Self {
+        self.importance_weight = weight;
+        self
+    }
+
+    /// Relevance of `record` to the query, including freshness and importance
+    fn relevance(
+        &self,
+        query: &[f32],
+        embedding: &[f32],
+        record: &MemoryRecord,
+        now: DateTime<Utc>,
+    ) -> f32 {
+        let mut score = (self.similarity)(query, embedding);
+        if let Some(half_life) = self.half_life.filter(|h| *h > chrono::Duration::zero()) {
+            let age = (now - record.timestamp).max(chrono::Duration::zero());
+            let halvings = age.num_milliseconds() as f32 / half_life.num_milliseconds() as f32;
+            score += self.recency_weight * 0.5f32.powf(halvings);
+        }
+        score + self.importance_weight * record.importance
+    }
+}
+
+/// Maximal Marginal Relevance (MMR) for diverse retrieval
+///
+/// Balances relevance to query with diversity in results.
+/// Lambda controls the trade-off: 1.0 = pure relevance, 0.0 = pure diversity
+pub fn mmr_rerank(
+    query_embedding: &[f32],
+    candidates: Vec<MemoryRecord>,
+    k: usize,
+    lambda: f32,
+) -> Vec<MemoryRecord> {
+    mmr_rerank_with(
+        query_embedding,
+        candidates,
+        k,
+        &MmrConfig::new().with_lambda(lambda),
+    )
+}
+
+/// MMR with a configurable similarity function and recency and importance
+/// boosts, so retrieval can balance relevance, diversity, freshness and
+/// importance
+pub fn mmr_rerank_with(
+    query_embedding: &[f32],
+    candidates: Vec<MemoryRecord>,
+    k: usize,
+    config: &MmrConfig,
+) -> Vec<MemoryRecord> {
+    if candidates.is_empty() {
+        return Vec::new();
+    }
+
+    let now = Utc::now();
+    let k = k.min(candidates.len());
+    let mut selected: Vec<MemoryRecord> = Vec::with_capacity(k);
+    let mut remaining = candidates;
+
+    // Select first item with highest relevance to query
+    if let Some((idx, _)) = remaining
+        .iter()
+        .enumerate()
+        .filter_map(|(i, r)| {
+            r.embedding
+                .as_ref()
+                .map(|emb| (i, config.relevance(query_embedding, emb, r, now)))
+        })
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    {
+        selected.push(remaining.swap_remove(idx));
+    }
+
+    // Iteratively select items that maximize MMR score
+    while selected.len() < k && !remaining.is_empty() {
+        let next_idx = remaining
             .iter()
             .enumerate()
             .filter_map(|(i, r)| {
                 let emb = r.embedding.as_ref()?;
 
-                // Relevance: similarity to query
-                let relevance = cosine_similarity(query_embedding, emb);
+                // Relevance: similarity to query, plus freshness and importance
+                let relevance = config.relevance(query_embedding, emb, r, now);
 
                 // Diversity: max similarity to already selected items
                 let max_sim_selected = selected
                     .iter()
                     .filter_map(|s| s.embedding.as_ref())
-                    .map(|s_emb| cosine_similarity(emb, s_emb))
+                    .map(|s_emb| (config.similarity)(emb, s_emb))
                     .fold(f32::NEG_INFINITY, f32::max);
 
-                // MMR score: λ * relevance - (1-λ) * max_similarity_to_selected
-                let mmr_score = lambda * relevance - (1.0 - lambda) * max_sim_selected;
+                // MMR score: λ * relevance - (1-λ) * max_similarity_to_selected
+                let mmr_score =
+                    config.lambda * relevance - (1.0 - config.lambda) * max_sim_selected;
+
+                Some((i, mmr_score))
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(i, _)| i);
+
+        if let Some(idx) = next_idx {
+            selected.push(remaining.swap_remove(idx));
+        } else {
+            break;
+        }
+    }
+
+    selected
+}
+
+/// Metadata key naming the user a user-scoped record belongs to
+pub const USER_METADATA_KEY: &str = "user_id";
+/// Metadata key naming the session a user-scoped record was learned in
+pub const SOURCE_SESSION_KEY: &str = "source_session";
+/// Metadata key flagging an assistant turn that stopped before the model
+/// finished, e.g. because its stream was cancelled
+pub const TRUNCATED_KEY: &str = "truncated";
+
+/// Starts the store session ids [`SessionMemory`] keeps for itself, such
+/// as thread scopes; `SessionMemory::store` refuses session ids starting
+/// with it, so no conversation can write into them
+pub const RESERVED_SCOPE_PREFIX: char = '~';
+
+/// Returns the reserved store session id of SessionMemory's `kind` scope
+/// for `key`, e.g. `~thread:acme`
+pub fn reserved_scope(kind: &str, key: &str) -> String {
+    format!("{}{}:{}", RESERVED_SCOPE_PREFIX, kind, key)
+}
+
+/// Returns true if `session_id` is a reserved scope rather than a session
+pub fn is_reserved_scope(session_id: &str) -> bool {
+    session_id.starts_with(RESERVED_SCOPE_PREFIX)
+}
+
+/// Turns a thread scope entry back into the record it indexes
+fn thread_record(mut entry: MemoryRecord) -> MemoryRecord {
+    let Some(metadata) = entry.metadata.as_mut() else {
+        return entry;
+    };
+    let source = metadata.remove(SOURCE_ID_KEY);
+    if let Some(id) = source.and_then(|id| Uuid::parse_str(&id).ok()) {
+        entry.id = id;
+    }
+    if let Some(session_id) = metadata.get(SOURCE_SESSION_KEY) {
+        entry.session_id = session_id.clone();
+    }
+    entry
+}
+
+/// Returns the store session id holding `user_id`'s long-term memories.
+///
+/// User-scoped records live in this pseudo-session of the same backing
+/// store, so every backend supports them without schema changes.
+pub fn user_scope(user_id: &str) -> String {
+    format!("user:{}", user_id)
+}
+
+/// Whether `record` is visible to `tenant_id`; without a tenant, all are
+fn owned_by(tenant_id: Option<&str>, record: &MemoryRecord) -> bool {
+    tenant_id.is_none_or(|tenant_id| stamped_tenant(record) == Some(tenant_id))
+}
+
+/// Narrows `filter` to the records stamped by `tenant_id`, if any
+fn tenant_filter(tenant_id: Option<&str>, filter: &MemoryFilter) -> MemoryFilter {
+    match tenant_id {
+        Some(tenant_id) => filter.clone().with_metadata(TENANT_METADATA_KEY, tenant_id),
+        None => filter.clone(),
+    }
+}
+
+/// Session memory manages short-term and long-term memory for a session
+pub struct SessionMemory {
+    store: Box<dyn MemoryStore>,
+    // Short-term cache of recent messages
+    short_term: parking_lot::RwLock<HashMap<String, Vec<MemoryRecord>>>,
+    context_window: usize,
+    tenant_guard: Option<Arc<TenantGuard>>,
+    compactor: Option<Arc<Compactor>>,
+    // Sessions with a compaction in flight
+    compacting: parking_lot::Mutex<HashSet<String>>,
+    // Session id -> user id
+    users: parking_lot::RwLock<HashMap<String, String>>,
+    promote_importance: Option<f32>,
+    semantic: Option<PromotionRules>,
+    // Reranker and how many candidates to fetch for it
+    reranker: Option<(Arc<dyn Reranker>, usize)>,
+    // Copy records into the thread scope of every session id level
+    threads: bool,
+    images: Option<ImageMemory>,
+}
+
+impl SessionMemory {
+    /// Creates a new session memory with the given store
+    pub fn new(store: Box<dyn MemoryStore>, context_window: usize) -> Self {
+        Self {
+            store,
+            short_term: parking_lot::RwLock::new(HashMap::new()),
+            context_window,
+            tenant_guard: None,
+            compactor: None,
+            compacting: parking_lot::Mutex::new(HashSet::new()),
+            users: parking_lot::RwLock::new(HashMap::new()),
+            promote_importance: None,
+            semantic: None,
+            reranker: None,
+            threads: false,
+            images: None,
+        }
+    }
+
+    /// Enforces tenant isolation on every session read and write.
+    ///
+    /// Calls run on behalf of the current [`tenant::scope`]: writes are
+    /// stamped with the tenant, reads only return its records, and a session
+    /// owned by another tenant, or by any tenant for calls without one, is
+    /// refused. A session's owner is read back from the stamps on its
+    /// stored records, so it holds across restarts and replicas.
+    pub fn with_tenant_guard(mut self, guard: Arc<TenantGuard>) -> Self {
+        self.tenant_guard = Some(guard);
+        self
+    }
+
+    /// Summarizes overflowing short-term records instead of dropping them.
+    ///
+    /// When a session outgrows the context window, its oldest records are
+    /// condensed into one summary record by `compactor`. If summarization
+    /// fails the overflow is dropped as usual.
+    pub fn with_compactor(mut self, compactor: Compactor) -> Self {
+        self.compactor = Some(Arc::new(compactor));
+        self
+    }
+
+    /// Copies records with at least this importance from user-bound sessions
+    /// into the user's long-term memory.
+    ///
+    /// Preferences and profile facts scored as important in one conversation
+    /// then surface through [`SessionMemory::search_user`] in later ones.
+    pub fn with_user_promotion(mut self, min_importance: f32) -> Self {
+        self.promote_importance = Some(min_importance);
+        self
+    }
+
+    /// Maintains a semantic tier next to the verbatim (episodic) history.
+    ///
+    /// Records matching `rules` are copied, possibly distilled into a fact,
+    /// into the session's [`semantic_scope`] as they are stored, and so are
+    /// compaction summaries. Retrieve them with
+    /// [`SessionMemory::search_semantic`]; agent retrieval includes them
+    /// automatically.
+    pub fn with_semantic_tier(mut self, rules: PromotionRules) -> Self {
+        self.semantic = Some(rules);
+        self
+    }
+
+    /// Reranks search results with `reranker`.
+    ///
+    /// [`SessionMemory::search_with_query`] fetches `candidates` records from
+    /// the store and keeps the best of them by the reranker's judgement, so
+    /// `candidates` should comfortably exceed the usual search limit.
+    pub fn with_reranker(mut self, reranker: Arc<dyn Reranker>, candidates: usize) -> Self {
+        self.reranker = Some((reranker, candidates));
+        self
+    }
+
+    /// Treats session ids as `/`-separated paths such as
+    /// `"org/thread/turn"`.
+    ///
+    /// Every stored record is also indexed in the [`thread_scope`] of each
+    /// level of its session id, so [`SessionMemory::search_scope`] can look
+    /// at a whole thread or organization and
+    /// [`SessionMemory::summarize_thread`] can roll a thread up. Each level
+    /// adds a copy, so deep paths cost storage.
+    pub fn with_threads(mut self) -> Self {
+        self.threads = true;
+        self
+    }
+
+    /// Remembers images sent to the agent, found by
+    /// [`SessionMemory::search`] with a [`MemoryQuery::Image`]
+    pub fn with_image_memory(mut self, images: ImageMemory) -> Self {
+        self.images = Some(images);
+        self
+    }
+
+    /// Returns how many candidates to fetch for a search returning `limit`
+    pub fn candidate_limit(&self, limit: usize) -> usize {
+        match &self.reranker {
+            Some((_, candidates)) => limit.max(*candidates),
+            None => limit,
+        }
+    }
+
+    /// Reranks `candidates` against `query`, keeping the best `limit`.
+    ///
+    /// Without a reranker the candidates are returned unchanged.
+    pub async fn rerank(
+        &self,
+        query: &str,
+        candidates: Vec<MemoryRecord>,
+        limit: usize,
+    ) -> Result<Vec<MemoryRecord>> {
+        match &self.reranker {
+            Some((reranker, _)) => reranker.rerank(query, candidates, limit).await,
+            None => Ok(candidates),
+        }
+    }
+
+    /// Returns true if image memories are kept
+    pub fn has_image_memory(&self) -> bool {
+        self.images.is_some()
+    }
+
+    /// Stores `image` as a memory of `session_id`, described by `caption`,
+    /// such as the message it was sent with.
+    ///
+    /// Returns the record id, or `None` without image memory or if `image`
+    /// isn't an image. Image records skip the short-term cache; they are
+    /// recalled through [`SessionMemory::search`] with a
+    /// [`MemoryQuery::Image`].
+    pub async fn store_image(
+        &self,
+        session_id: &str,
+        image: &File,
+        caption: &str,
+    ) -> Result<Option<Uuid>> {
+        let Some(images) = self.images.as_ref().filter(|_| is_image(image)) else {
+            return Ok(None);
+        };
+        let tenant = self.enforce_tenant(session_id).await?;
+        let (blob, embedding) = futures::try_join!(
+            images.blobs.put(&image.data),
+            images.embedder.embed_image(image),
+        )?;
+        let id = Uuid::new_v4();
+        let mut record = MemoryRecord {
+            id,
+            session_id: image_scope(session_id),
+            role: IMAGE_ROLE.to_string(),
+            content: format!("Image ({}) sent with: {}", image.mime_type, caption),
+            importance: DEFAULT_IMPORTANCE,
+            timestamp: Utc::now(),
+            metadata: Some(HashMap::from([
+                (IMAGE_REF_KEY.to_string(), blob),
+                (IMAGE_MIME_KEY.to_string(), image.mime_type.clone()),
+                (SOURCE_SESSION_KEY.to_string(), session_id.to_string()),
+            ])),
+            embedding: Some(embedding),
+            version: 0,
+            expires_at: None,
+        };
+        if let (Some(tenant_id), Some(metadata)) = (tenant, record.metadata.as_mut()) {
+            metadata.insert(TENANT_METADATA_KEY.to_string(), tenant_id);
+        }
+        let scope = record.session_id.clone();
+        let store = images.store.as_deref().unwrap_or(self.store.as_ref());
+        telemetry::memory_op("store", &scope, store.store(record)).await?;
+        Ok(Some(id))
+    }
+
+    /// Searches the image memories of `session_id` by description or by a
+    /// similar image, keeping hits scoring at least the image memory's
+    /// minimum; empty without image memory
+    async fn search_images(
+        &self,
+        session_id: &str,
+        query: &ImageQuery,
+        limit: usize,
+        tenant_id: Option<&str>,
+    ) -> Result<Vec<MemoryRecord>> {
+        let Some(images) = &self.images else {
+            return Ok(Vec::new());
+        };
+        let embedding = images.embed_query(query).await?;
+        let scope = image_scope(session_id);
+        let store = images.store.as_deref().unwrap_or(self.store.as_ref());
+        let filter = tenant_filter(tenant_id, &MemoryFilter::default());
+        let search = store.search_filtered(&scope, embedding.clone(), limit, &filter);
+        let mut hits = telemetry::memory_op("search", &scope, search).await?;
+        hits.retain(|hit| {
+            hit.embedding
+                .as_deref()
+                .is_none_or(|e| cosine_similarity(e, &embedding) >= images.min_score)
+        });
+        hits.truncate(limit);
+        Ok(hits)
+    }
+
+    /// Loads the image an image memory record points at
+    pub async fn load_image(&self, record: &MemoryRecord) -> Result<Option<File>> {
+        let (Some(images), Some(metadata)) = (&self.images, &record.metadata) else {
+            return Ok(None);
+        };
+        let (Some(blob), Some(mime_type)) =
+            (metadata.get(IMAGE_REF_KEY), metadata.get(IMAGE_MIME_KEY))
+        else {
+            return Ok(None);
+        };
+        Ok(images.blobs.get(blob).await?.map(|data| File {
+            mime_type: mime_type.clone(),
+            data,
+        }))
+    }
+
+    /// Returns how many recent records the short-term window keeps
+    pub fn context_window(&self) -> usize {
+        self.context_window
+    }
+
+    /// Returns true if a semantic tier is maintained
+    pub fn has_semantic_tier(&self) -> bool {
+        self.semantic.is_some()
+    }
+
+    /// Retrieves the most recent semantic-tier records of `session_id`
+    pub async fn retrieve_semantic(
+        &self,
+        session_id: &str,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.store
+            .retrieve_filtered(&semantic_scope(session_id), limit, filter)
+            .await
+    }
+
+    /// Searches the semantic tier of `session_id`
+    pub async fn search_semantic(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.store
+            .search_filtered(&semantic_scope(session_id), query_embedding, limit, filter)
+            .await
+    }
+
+    /// Copies `record` into the semantic tier if the promotion rules allow.
+    ///
+    /// Failures are logged rather than returned; the episodic record is
+    /// already stored.
+    async fn promote_semantic(&self, record: &MemoryRecord) {
+        let Some(rules) = self
+            .semantic
+            .as_ref()
+            .filter(|rules| rules.promotes(record))
+        else {
+            return;
+        };
+        let promoted = match rules.promote(record).await {
+            Ok(Some(promoted)) => promoted,
+            Ok(None) => return,
+            Err(e) => {
+                tracing::warn!(session_id = %record.session_id, "Semantic promotion failed: {}", e);
+                return;
+            }
+        };
+        if let Err(e) = self.store.store(promoted).await {
+            tracing::warn!(session_id = %record.session_id, "Semantic promotion failed: {}", e);
+        }
+    }
+
+    /// Retrieves the most recent records of every session at or below
+    /// `path`
+    pub async fn retrieve_scope(
+        &self,
+        path: &str,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        let entries = self
+            .store
+            .retrieve_filtered(&thread_scope(path), limit, filter)
+            .await?;
+        Ok(entries.into_iter().map(thread_record).collect())
+    }
+
+    /// Searches the records of every session at or below `path`
+    pub async fn search_scope(
+        &self,
+        path: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        let scope = thread_scope(path);
+        let search = self
+            .store
+            .search_filtered(&scope, query_embedding, limit, filter);
+        let entries = telemetry::memory_op("search", &scope, search).await?;
+        Ok(entries.into_iter().map(thread_record).collect())
+    }
+
+    /// Summarizes the latest `limit` records at or below `path` with the
+    /// compactor and stores the summary in the path's [`rollup_scope`].
+    ///
+    /// Call it again as the thread grows; [`SessionMemory::thread_summary`]
+    /// returns the newest roll-up.
+    pub async fn summarize_thread(&self, path: &str, limit: usize) -> Result<MemoryRecord> {
+        let compactor = self.compactor.as_ref().ok_or_else(|| {
+            AgentError::ConfigError("No compactor configured for session memory".to_string())
+        })?;
+        let mut records = self
+            .retrieve_scope(path, limit, &MemoryFilter::default())
+            .await?;
+        records.sort_by_key(|r| r.timestamp);
+
+        let mut summary = compactor.summarize(&records).await?;
+        summary.session_id = rollup_scope(path);
+        self.store.store(summary.clone()).await?;
+        Ok(summary)
+    }
+
+    /// Returns the newest roll-up summary of `path`, if there is one
+    pub async fn thread_summary(&self, path: &str) -> Result<Option<MemoryRecord>> {
+        let summaries = self.store.retrieve(&rollup_scope(path), 1).await?;
+        Ok(summaries.into_iter().next())
+    }
+
+    /// Indexes `record` in the thread scope of each level of its session.
+    ///
+    /// Entry ids derive from the record's, so storing it again replaces
+    /// its entries. Replies still streaming, flagged with
+    /// [`TRUNCATED_KEY`], wait until they are complete. The record itself
+    /// is stored by then, so a failure is only logged.
+    async fn store_in_threads(&self, record: &MemoryRecord) {
+        let partial = record
+            .metadata
+            .as_ref()
+            .is_some_and(|metadata| metadata.get(TRUNCATED_KEY).is_some_and(|v| v == "true"));
+        if partial {
+            return;
+        }
+        let entries = thread_levels(&record.session_id)
+            .into_iter()
+            .map(|level| {
+                let mut entry = record.clone();
+                let metadata = entry.metadata.get_or_insert_with(HashMap::new);
+                metadata
+                    .entry(SOURCE_SESSION_KEY.to_string())
+                    .or_insert_with(|| record.session_id.clone());
+                metadata.insert(SOURCE_ID_KEY.to_string(), record.id.to_string());
+                entry.id = threads::thread_entry_id(record.id, &level);
+                entry.session_id = thread_scope(&level);
+                entry.version = 0;
+                entry
+            })
+            .collect();
+        if let Err(e) = self.store.store_batch(entries).await {
+            tracing::warn!(session_id = %record.session_id, "Thread indexing failed: {}", e);
+        }
+    }
+
+    /// Associates a session with the user taking part in it
+    pub fn bind_user(&self, session_id: impl Into<String>, user_id: impl Into<String>) {
+        self.users.write().insert(session_id.into(), user_id.into());
+    }
+
+    /// Returns the user bound to a session, if any
+    pub fn user_of(&self, session_id: &str) -> Option<String> {
+        self.users.read().get(session_id).cloned()
+    }
+
+    /// Returns the sessions bound to `user_id`, sorted
+    pub fn sessions_of(&self, user_id: &str) -> Vec<String> {
+        let mut sessions: Vec<String> = self
+            .users
+            .read()
+            .iter()
+            .filter(|(_, user)| *user == user_id)
+            .map(|(session, _)| session.clone())
+            .collect();
+        sessions.sort();
+        sessions
+    }
+
+    /// Returns every user bound to a session, sorted
+    pub fn users(&self) -> Vec<String> {
+        let mut users: Vec<String> = self.users.read().values().cloned().collect();
+        users.sort();
+        users.dedup();
+        users
+    }
+
+    /// Stores a copy of `record` in the long-term memory of `user_id`.
+    ///
+    /// The copy gets a fresh id, which is returned, and remembers its
+    /// originating session under [`SOURCE_SESSION_KEY`].
+    pub async fn store_for_user(&self, user_id: &str, mut record: MemoryRecord) -> Result<Uuid> {
+        let metadata = record.metadata.get_or_insert_with(HashMap::new);
+        metadata.insert(USER_METADATA_KEY.to_string(), user_id.to_string());
+        metadata
+            .entry(SOURCE_SESSION_KEY.to_string())
+            .or_insert_with(|| record.session_id.clone());
+
+        let id = Uuid::new_v4();
+        record.id = id;
+        record.session_id = user_scope(user_id);
+        record.version = 0;
+        self.store.store(record).await?;
+        Ok(id)
+    }
+
+    /// Retrieves the most recent memories of `user_id` across all sessions
+    pub async fn retrieve_user(
+        &self,
+        user_id: &str,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.store
+            .retrieve_filtered(&user_scope(user_id), limit, filter)
+            .await
+    }
+
+    /// Searches the long-term memory of `user_id` across all sessions
+    pub async fn search_user(
+        &self,
+        user_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.store
+            .search_filtered(&user_scope(user_id), query_embedding, limit, filter)
+            .await
+    }
+
+    /// Stores a memory record.
+    ///
+    /// Storing a record again under the same id replaces it, in the
+    /// short-term cache as in the store, and doesn't promote it again.
+    pub async fn store(&self, mut record: MemoryRecord) -> Result<()> {
+        let session_id = record.session_id.clone();
+        if is_reserved_scope(&session_id) {
+            return Err(AgentError::InvalidState(format!(
+                "Session id {} is reserved",
+                session_id
+            )));
+        }
+        self.admit(&mut record).await?;
+        telemetry::memory_op("store", &session_id, self.store_record(record)).await
+    }
+
+    async fn store_record(&self, record: MemoryRecord) -> Result<()> {
+        let session_id = record.session_id.clone();
+
+        let replaced = {
+            let mut short_term = self.short_term.write();
+            short_term
+                .get_mut(&session_id)
+                .and_then(|records| records.iter_mut().find(|r| r.id == record.id))
+                .map(|cached| *cached = record.clone())
+                .is_some()
+        };
+        if replaced {
+            self.store.store(record.clone()).await?;
+            if self.threads {
+                self.store_in_threads(&record).await;
+            }
+            return Ok(());
+        }
+
+        // Add to short-term cache
+        let batch = {
+            let mut short_term = self.short_term.write();
+            let session_records = short_term.entry(session_id.clone()).or_default();
+            session_records.push(record.clone());
+
+            // Oldest unpinned records, never including the one just stored
+            let overflow = session_records.len().saturating_sub(self.context_window);
+            let batch: Vec<MemoryRecord> = match &self.compactor {
+                Some(compactor) if overflow > 0 => session_records[..session_records.len() - 1]
+                    .iter()
+                    .filter(|r| !compactor.is_pinned(r))
+                    .take(compactor.batch_size().max(overflow + 1))
+                    .cloned()
+                    .collect(),
+                _ => Vec::new(),
+            };
+
+            if overflow == 0 {
+                None
+            } else if batch.len() >= 2 && self.compacting.lock().insert(session_id.clone()) {
+                Some(batch)
+            } else {
+                // Trim to context window
+                session_records.drain(0..overflow);
+                None
+            }
+        };
+
+        // Promote important records of user-bound sessions to user memory
+        let promoted = match self.promote_importance {
+            Some(min) if record.importance >= min => self
+                .user_of(&session_id)
+                .map(|user_id| (user_id, record.clone())),
+            _ => None,
+        };
+
+        let semantic = self
+            .semantic
+            .as_ref()
+            .filter(|rules| rules.promotes(&record))
+            .map(|_| record.clone());
+        let threaded = self.threads.then(|| record.clone());
+
+        // Store in long-term
+        self.store.store(record).await?;
+
+        if let Some(record) = threaded {
+            self.store_in_threads(&record).await;
+        }
+
+        if let Some((user_id, record)) = promoted {
+            self.store_for_user(&user_id, record).await?;
+        }
+        if let Some(record) = semantic {
+            self.promote_semantic(&record).await;
+        }
+
+        if let (Some(batch), Some(compactor)) = (batch, &self.compactor) {
+            self.compact(compactor, &session_id, batch).await;
+        }
+        Ok(())
+    }
+
+    /// Replaces `batch` in the short-term cache with its summary
+    async fn compact(&self, compactor: &Compactor, session_id: &str, batch: Vec<MemoryRecord>) {
+        let summary = match compactor.summarize(&batch).await {
+            Ok(mut summary) => match self.stamp(&mut summary) {
+                Ok(()) => self.store.store(summary.clone()).await.map(|_| summary),
+                Err(e) => Err(e),
+            },
+            Err(e) => Err(e),
+        };
+        if let Ok(summary) = &summary {
+            self.promote_semantic(summary).await;
+        }
+
+        {
+            let mut short_term = self.short_term.write();
+            if let Some(session_records) = short_term.get_mut(session_id) {
+                match summary {
+                    Ok(summary) => {
+                        let ids: HashSet<Uuid> = batch.iter().map(|r| r.id).collect();
+                        let position = session_records
+                            .iter()
+                            .position(|r| ids.contains(&r.id))
+                            .unwrap_or(0);
+                        session_records.retain(|r| !ids.contains(&r.id));
+                        session_records.insert(position, summary);
+                    }
+                    Err(e) => {
+                        tracing::warn!(session_id, "Memory compaction failed: {}", e);
+                    }
+                }
+
+                // Records stored meanwhile, or a failed summary, can still overflow
+                let overflow = session_records.len().saturating_sub(self.context_window);
+                session_records.drain(0..overflow);
+            }
+        }
+
+        self.compacting.lock().remove(session_id);
+    }
+
+    /// Updates a stored record if nobody else changed it since it was read.
+    ///
+    /// On success the short-term cache is refreshed with the new version.
+    pub async fn update(&self, mut record: MemoryRecord) -> Result<u64> {
+        self.admit(&mut record).await?;
+        let updated = self.store.update(record.clone());
+        let version = telemetry::memory_op("update", &record.session_id, updated).await?;
+        record.version = version;
+
+        let mut short_term = self.short_term.write();
+        if let Some(cached) = short_term
+            .get_mut(&record.session_id)
+            .and_then(|records| records.iter_mut().find(|r| r.id == record.id))
+        {
+            *cached = record;
+        }
+
+        Ok(version)
+    }
+
+    /// Retrieves recent, unexpired memories from short-term cache
+    pub async fn retrieve_recent(&self, session_id: &str) -> Result<Vec<MemoryRecord>> {
+        let tenant = self.enforce_tenant(session_id).await?;
+        let now = Utc::now();
+        let short_term = self.short_term.read();
+        Ok(short_term
+            .get(session_id)
+            .map(|records| {
+                records
+                    .iter()
+                    .filter(|r| !r.is_expired_at(now) && owned_by(tenant.as_deref(), r))
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    /// Pages through a session's long-term history, newest first
+    pub async fn retrieve_page(
+        &self,
+        session_id: &str,
+        cursor: Option<&str>,
+        page_size: usize,
+    ) -> Result<MemoryPage> {
+        let tenant = self.enforce_tenant(session_id).await?;
+        let mut page = self
+            .store
+            .retrieve_page(session_id, cursor, page_size)
+            .await?;
+        page.records.retain(|r| owned_by(tenant.as_deref(), r));
+        Ok(page)
+    }
+
+    /// Searches for relevant memories: text memories near an embedding, or
+    /// image memories matching an [`ImageQuery`]
+    pub async fn search(
+        &self,
+        session_id: &str,
+        query: impl Into<MemoryQuery>,
+        limit: usize,
+    ) -> Result<Vec<MemoryRecord>> {
+        let tenant = self.enforce_tenant(session_id).await?;
+        let query_embedding = match query.into() {
+            MemoryQuery::Embedding(embedding) => embedding,
+            MemoryQuery::Image(query) => {
+                return self
+                    .search_images(session_id, &query, limit, tenant.as_deref())
+                    .await
+            }
+        };
+        let filter = tenant_filter(tenant.as_deref(), &MemoryFilter::default());
+        let search = self
+            .store
+            .search_filtered(session_id, query_embedding, limit, &filter);
+        telemetry::memory_op("search", session_id, search).await
+    }
+
+    /// Searches for memories relevant to `query`, applying the reranker if
+    /// one is configured
+    pub async fn search_with_query(
+        &self,
+        session_id: &str,
+        query: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+    ) -> Result<Vec<MemoryRecord>> {
+        let candidates = self
+            .search(session_id, query_embedding, self.candidate_limit(limit))
+            .await?;
+        self.rerank(query, candidates, limit).await
+    }
+
+    /// Retrieves the most recent memories of a session that match `filter`,
+    /// such as those carrying a label
+    pub async fn retrieve_filtered(
+        &self,
+        session_id: &str,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        let tenant = self.enforce_tenant(session_id).await?;
+        let filter = tenant_filter(tenant.as_deref(), filter);
+        let retrieve = self.store.retrieve_filtered(session_id, limit, &filter);
+        telemetry::memory_op("retrieve", session_id, retrieve).await
+    }
+
+    /// Searches for relevant memories that also match `filter`
+    pub async fn search_filtered(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        let tenant = self.enforce_tenant(session_id).await?;
+        let filter = tenant_filter(tenant.as_deref(), filter);
+        let search = self
+            .store
+            .search_filtered(session_id, query_embedding, limit, &filter);
+        telemetry::memory_op("search", session_id, search).await
+    }
+
+    /// Stores a record on behalf of `tenant_id`, stamping it with the tenant
+    pub async fn store_as(&self, tenant_id: &str, record: MemoryRecord) -> Result<()> {
+        self.tenant_guard()?;
+        tenant::scope(Some(tenant_id.to_string()), self.store(record)).await
+    }
+
+    /// Updates a record on behalf of `tenant_id`, keeping its tenant stamp
+    pub async fn update_as(&self, tenant_id: &str, record: MemoryRecord) -> Result<u64> {
+        self.tenant_guard()?;
+        tenant::scope(Some(tenant_id.to_string()), self.update(record)).await
+    }
+
+    /// Retrieves recent memories of a session owned by `tenant_id`
+    pub async fn retrieve_recent_as(
+        &self,
+        tenant_id: &str,
+        session_id: &str,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.tenant_guard()?;
+        tenant::scope(
+            Some(tenant_id.to_string()),
+            self.retrieve_recent(session_id),
+        )
+        .await
+    }
 
-                Some((i, mmr_score))
-            })
-            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())
-            .map(|(i, _)| i);
+    /// Searches a session owned by `tenant_id`, only returning its own records
+    pub async fn search_as(
+        &self,
+        tenant_id: &str,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.tenant_guard()?;
+        let search = self.search_filtered(session_id, query_embedding, limit, filter);
+        tenant::scope(Some(tenant_id.to_string()), search).await
+    }
 
-        if let Some(idx) = next_idx {
-            selected.push(remaining.swap_remove(idx));
-        } else {
-            break;
+    fn tenant_guard(&self) -> Result<&TenantGuard> {
+        self.tenant_guard.as_deref().ok_or_else(|| {
+            AgentError::ConfigError("No tenant guard configured for session memory".to_string())
+        })
+    }
+
+    /// Checks the current tenant against the owner of `session_id`,
+    /// claiming the session if unowned, and returns the tenant
+    async fn enforce_tenant(&self, session_id: &str) -> Result<Option<String>> {
+        let Some(guard) = &self.tenant_guard else {
+            return Ok(None);
+        };
+        if guard.owner(session_id).is_none() {
+            // Not cached here; the newest record carries the owner's stamp
+            let newest = self.store.retrieve(session_id, 1).await?;
+            if let Some(owner) = newest.first().and_then(stamped_tenant) {
+                guard.remember(session_id, owner);
+            }
         }
+        let tenant = tenant::current();
+        guard.check(tenant.as_deref(), session_id)?;
+        Ok(tenant)
     }
 
-    selected
-}
+    /// Checks that the current tenant may write `record`, and stamps it
+    async fn admit(&self, record: &mut MemoryRecord) -> Result<()> {
+        self.enforce_tenant(&record.session_id).await?;
+        self.stamp(record)
+    }
 
-/// Session memory manages short-term and long-term memory for a session
-pub struct SessionMemory {
-    store: Box<dyn MemoryStore>,
-    // Short-term cache of recent messages
-    short_term: parking_lot::RwLock<HashMap<String, Vec<MemoryRecord>>>,
-    context_window: usize,
-}
+    /// Stamps `record` with the current tenant, if guarded and scoped
+    fn stamp(&self, record: &mut MemoryRecord) -> Result<()> {
+        match (&self.tenant_guard, tenant::current()) {
+            (Some(guard), Some(tenant_id)) => guard.stamp_record(&tenant_id, record),
+            _ => Ok(()),
+        }
+    }
 
-impl SessionMemory {
-    /// Creates a new session memory with the given store
-    pub fn new(store: Box<dyn MemoryStore>, context_window: usize) -> Self {
-        Self {
-            store,
-            short_term: parking_lot::RwLock::new(HashMap::new()),
-            context_window,
+    /// Streams a session's long-term history, newest first
+    pub fn export<'a>(&'a self, session_id: &'a str) -> BoxStream<'a, Result<MemoryRecord>> {
+        if self.tenant_guard.is_none() {
+            return self.store.export(session_id);
         }
+        let records = async move {
+            let tenant = self.enforce_tenant(session_id).await?;
+            let records = self
+                .store
+                .export(session_id)
+                .try_filter(move |r| futures::future::ready(owned_by(tenant.as_deref(), r)));
+            Ok::<_, AgentError>(records)
+        };
+        futures::stream::once(records).try_flatten().boxed()
     }
 
-    /// Stores a memory record
-    pub async fn store(&self, record: MemoryRecord) -> Result<()> {
-        let session_id = record.session_id.clone();
+    /// Imports records into long-term memory, returning how many were stored.
+    ///
+    /// The short-term cache is left alone; use [`SessionMemory::restore`] to
+    /// resume a conversation in place.
+    pub async fn import(&self, records: BoxStream<'_, Result<MemoryRecord>>) -> Result<usize> {
+        if self.tenant_guard.is_none() {
+            return self.store.import(records).await;
+        }
+        let admitted = records.and_then(|mut record| async move {
+            self.admit(&mut record).await?;
+            Ok(record)
+        });
+        self.store.import(admitted.boxed()).await
+    }
 
-        // Add to short-term cache
-        {
-            let mut short_term = self.short_term.write();
-            let session_records = short_term.entry(session_id).or_insert_with(Vec::new);
-            session_records.push(record.clone());
+    /// Restores checkpointed records into long-term memory and the short-term
+    /// cache, returning how many records were written.
+    ///
+    /// Records are deduplicated by id, and records the backing store already
+    /// holds are skipped (the cache takes the stored copy), so restoring the
+    /// same checkpoint again writes nothing. With `keep_last` set and a
+    /// compactor configured, all but the last `keep_last` unpinned records of
+    /// each session are condensed into one summary first, and only the
+    /// summary and the kept records are written; a summary stored by an
+    /// earlier restore is reused. If summarization fails the history is
+    /// restored as is.
+    pub async fn restore(
+        &self,
+        mut records: Vec<MemoryRecord>,
+        keep_last: Option<usize>,
+    ) -> Result<usize> {
+        for record in &mut records {
+            self.admit(record).await?;
+        }
+        // Keep the last copy of a record listed more than once
+        let mut seen = HashSet::new();
+        records.reverse();
+        records.retain(|r| seen.insert(r.id));
+        records.reverse();
+        records.sort_by_key(|r| r.timestamp);
 
-            // Trim to context window
-            if session_records.len() > self.context_window {
-                session_records.drain(0..session_records.len() - self.context_window);
+        let mut sessions: Vec<(String, Vec<MemoryRecord>)> = Vec::new();
+        for record in records {
+            match sessions.iter_mut().find(|(id, _)| *id == record.session_id) {
+                Some((_, session)) => session.push(record),
+                None => sessions.push((record.session_id.clone(), vec![record])),
             }
         }
 
-        // Store in long-term
-        self.store.store(record).await
-    }
+        let mut restored = 0;
+        for (session_id, mut session) in sessions {
+            let mut existing = self.stored_records(&session_id, &session).await?;
+            let mut summarized = HashSet::new();
+            if let (Some(keep_last), Some(compactor)) = (keep_last, &self.compactor) {
+                let older = session.len().saturating_sub(keep_last);
+                let batch: Vec<MemoryRecord> = session[..older]
+                    .iter()
+                    .filter(|r| !compactor.is_pinned(r))
+                    .cloned()
+                    .collect();
+                if batch.len() >= 2 {
+                    let condensed = match existing.get(&batch[batch.len() - 1].id) {
+                        Some(stored) if stored.role == compaction::SUMMARY_ROLE => {
+                            Ok(stored.clone())
+                        }
+                        _ => compactor.summarize(&batch).await,
+                    };
+                    match condensed {
+                        Ok(mut summary) => {
+                            self.stamp(&mut summary)?;
+                            // Reuse a replaced id so repeated restores upsert one summary
+                            summary.id = batch[batch.len() - 1].id;
+                            summarized = batch.iter().map(|r| r.id).collect();
+                            summarized.remove(&summary.id);
+                            session.retain(|r| !summarized.contains(&r.id));
+                            if let Some(slot) = session.iter_mut().find(|r| r.id == summary.id) {
+                                *slot = summary;
+                            }
+                        }
+                        Err(e) => {
+                            tracing::warn!(session_id, "Restore compaction failed: {}", e);
+                        }
+                    }
+                }
+            }
 
-    /// Retrieves recent memories from short-term cache
-    pub async fn retrieve_recent(&self, session_id: &str) -> Result<Vec<MemoryRecord>> {
-        let short_term = self.short_term.read();
-        Ok(short_term.get(session_id).cloned().unwrap_or_default())
+            let fresh: Vec<MemoryRecord> = session
+                .iter()
+                .filter(|r| !existing.contains_key(&r.id))
+                .cloned()
+                .collect();
+            restored += fresh.len();
+            if !fresh.is_empty() {
+                self.store.store_batch(fresh).await?;
+            }
+            // The stored copy may have moved on since the checkpoint
+            let session: Vec<MemoryRecord> = session
+                .into_iter()
+                .map(|r| existing.remove(&r.id).unwrap_or(r))
+                .collect();
+
+            let mut short_term = self.short_term.write();
+            let cached = short_term.entry(session_id).or_default();
+            cached.retain(|r| !summarized.contains(&r.id));
+            for record in session {
+                match cached.iter_mut().find(|r| r.id == record.id) {
+                    Some(slot) => *slot = record,
+                    None => cached.push(record),
+                }
+            }
+            cached.sort_by_key(|r| r.timestamp);
+            let overflow = cached.len().saturating_sub(self.context_window);
+            cached.drain(0..overflow);
+        }
+        Ok(restored)
     }
 
-    /// Searches for relevant memories
-    pub async fn search(
+    /// Records of `session` that the backing store already holds, by id
+    async fn stored_records(
         &self,
         session_id: &str,
-        query_embedding: Vec<f32>,
-        limit: usize,
-    ) -> Result<Vec<MemoryRecord>> {
-        self.store.search(session_id, query_embedding, limit).await
+        session: &[MemoryRecord],
+    ) -> Result<HashMap<Uuid, MemoryRecord>> {
+        let mut stored = HashMap::new();
+        for record in session {
+            if let Some(existing) = self.store.get(session_id, record.id).await? {
+                stored.insert(existing.id, existing);
+            }
+        }
+        Ok(stored)
+    }
+
+    /// Drops expired records from the short-term cache and the backing store.
+    ///
+    /// Reads already hide expired records; call this periodically to reclaim
+    /// space. Returns how many records the store deleted.
+    pub async fn purge_expired(&self) -> Result<usize> {
+        let now = Utc::now();
+        {
+            let mut short_term = self.short_term.write();
+            for records in short_term.values_mut() {
+                records.retain(|r| !r.is_expired_at(now));
+            }
+            short_term.retain(|_, records| !records.is_empty());
+        }
+
+        self.store.purge_expired().await
     }
 
     /// Flushes all pending writes
@@ -287,6 +2179,8 @@ mod tests {
             timestamp: Utc::now(),
             metadata: None,
             embedding: None,
+            version: 0,
+            expires_at: None,
         };
 
         store.store(record.clone()).await.unwrap();
@@ -295,6 +2189,119 @@ mod tests {
         assert_eq!(retrieved[0].content, "Hello");
     }
 
+    #[tokio::test]
+    async fn test_hybrid_search_finds_exact_identifiers() {
+        let store = InMemoryStore::new();
+        for (content, embedding) in [
+            ("My order ORD-4821 never arrived", vec![0.0, 1.0]),
+            ("Shipping usually takes three days", vec![1.0, 0.0]),
+            ("Thanks for the help", vec![0.9, 0.1]),
+        ] {
+            let record = MemoryRecord {
+                id: Uuid::new_v4(),
+                session_id: "test".to_string(),
+                role: "user".to_string(),
+                content: content.to_string(),
+                importance: 0.5,
+                timestamp: Utc::now(),
+                metadata: None,
+                embedding: Some(embedding),
+                version: 0,
+                expires_at: None,
+            };
+            store.store(record).await.unwrap();
+        }
+
+        let filter = MemoryFilter::default();
+        let semantic = store
+            .search_filtered("test", vec![1.0, 0.0], 1, &filter)
+            .await
+            .unwrap();
+        assert!(semantic[0].content.starts_with("Shipping"));
+
+        let keyword = store
+            .keyword_search("test", "ORD-4821", 10, &filter)
+            .await
+            .unwrap();
+        assert_eq!(keyword.len(), 1);
+
+        let hybrid = store
+            .hybrid_search("test", "ORD-4821", vec![1.0, 0.0], 3, &filter)
+            .await
+            .unwrap();
+        assert_eq!(hybrid.len(), 3);
+        assert!(hybrid[0].content.contains("ORD-4821"));
+    }
+
+    #[tokio::test]
+    async fn test_search_index_tracks_updates_and_filters() {
+        let store = InMemoryStore::new();
+        for i in 0..50 {
+            let record = MemoryRecord {
+                id: Uuid::new_v4(),
+                session_id: "test".to_string(),
+                role: if i == 0 { "assistant" } else { "user" }.to_string(),
+                content: format!("memory {}", i),
+                importance: 0.5,
+                timestamp: Utc::now(),
+                metadata: None,
+                embedding: Some(vec![1.0, i as f32 / 10.0]),
+                version: 0,
+                expires_at: None,
+            };
+            store.store(record).await.unwrap();
+        }
+
+        // The only assistant record is far down the ranking for this query
+        let filter = MemoryFilter::default().with_role("assistant");
+        let found = store
+            .search_filtered("test", vec![0.0, 1.0], 1, &filter)
+            .await
+            .unwrap();
+        assert_eq!(found[0].content, "memory 0");
+
+        let mut moved = found[0].clone();
+        moved.embedding = Some(vec![0.0, 1.0]);
+        store.update(moved.clone()).await.unwrap();
+        let found = store.search("test", vec![0.0, 1.0], 1).await.unwrap();
+        assert_eq!(found[0].id, moved.id);
+        assert!(store
+            .search("other", vec![0.0, 1.0], 1)
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_ranks_by_configured_metric() {
+        let top = |metric: SimilarityMetric| async move {
+            let store = InMemoryStore::new().with_metric(metric);
+            for (content, embedding) in [("aligned", vec![1.0, 0.0]), ("long", vec![3.0, 1.0])] {
+                let record = MemoryRecord {
+                    id: Uuid::new_v4(),
+                    session_id: "test".to_string(),
+                    role: "user".to_string(),
+                    content: content.to_string(),
+                    importance: 0.5,
+                    timestamp: Utc::now(),
+                    metadata: None,
+                    embedding: Some(embedding),
+                    version: 0,
+                    expires_at: None,
+                };
+                store.store(record).await.unwrap();
+            }
+            let found = store.search("test", vec![1.0, 0.0], 1).await.unwrap();
+            found[0].content.clone()
+        };
+
+        // Dot product rewards the longer vector that cosine normalizes away
+        assert_eq!(top(SimilarityMetric::Cosine).await, "aligned");
+        assert_eq!(top(SimilarityMetric::Dot).await, "long");
+        assert_eq!(top(SimilarityMetric::Euclidean).await, "aligned");
+        assert_eq!(euclidean_similarity(&[1.0, 2.0], &[1.0, 2.0]), 1.0);
+    }
+
     #[tokio::test]
     async fn test_session_memory() {
         let store = Box::new(InMemoryStore::new());
@@ -309,10 +2316,685 @@ mod tests {
             timestamp: Utc::now(),
             metadata: None,
             embedding: None,
+            version: 0,
+            expires_at: None,
         };
 
         memory.store(record).await.unwrap();
         let recent = memory.retrieve_recent("test").await.unwrap();
         assert_eq!(recent.len(), 1);
     }
+
+    #[tokio::test]
+    async fn test_update_rejects_stale_version() {
+        let memory = SessionMemory::new(Box::new(InMemoryStore::new()), 5);
+        let record = MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: "test".to_string(),
+            role: "user".to_string(),
+            content: "Original".to_string(),
+            importance: 0.5,
+            timestamp: Utc::now(),
+            metadata: None,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        };
+        memory.store(record.clone()).await.unwrap();
+
+        let mut edited = record.clone();
+        edited.content = "Edited".to_string();
+        assert_eq!(memory.update(edited).await.unwrap(), 1);
+
+        // A writer still holding version 0 must not clobber the edit
+        let mut stale = record;
+        stale.content = "Compacted".to_string();
+        let err = memory.update(stale).await.unwrap_err();
+        assert!(matches!(err, AgentError::VersionConflict(_)));
+
+        let recent = memory.retrieve_recent("test").await.unwrap();
+        assert_eq!(recent[0].content, "Edited");
+        assert_eq!(recent[0].version, 1);
+    }
+
+    #[tokio::test]
+    async fn test_store_over_existing_id_bumps_version() {
+        let store = InMemoryStore::new();
+        let mut record = MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: "test".to_string(),
+            role: "user".to_string(),
+            content: "Original".to_string(),
+            importance: 0.5,
+            timestamp: Utc::now(),
+            metadata: None,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        };
+        store.store(record.clone()).await.unwrap();
+        assert_eq!(store.update(record.clone()).await.unwrap(), 1);
+
+        // A plain overwrite with a stale version must not roll the version back
+        record.content = "Overwritten".to_string();
+        store.store(record.clone()).await.unwrap();
+        let err = store.update(record.clone()).await.unwrap_err();
+        assert!(matches!(err, AgentError::VersionConflict(_)));
+        record.version = 2;
+        assert_eq!(store.update(record.clone()).await.unwrap(), 3);
+
+        let fetched = store.get("test", record.id).await.unwrap().unwrap();
+        assert_eq!(fetched.version, 3);
+        assert!(store.get("other", record.id).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_retrieve_applies_filter() {
+        let store = InMemoryStore::new();
+        let now = Utc::now();
+        for (role, importance, topic) in [
+            ("user", 0.9, "billing"),
+            ("assistant", 0.9, "billing"),
+            ("user", 0.2, "billing"),
+            ("user", 0.9, "shipping"),
+        ] {
+            let mut metadata = HashMap::new();
+            metadata.insert("topic".to_string(), topic.to_string());
+            store
+                .store(MemoryRecord {
+                    id: Uuid::new_v4(),
+                    session_id: "test".to_string(),
+                    role: role.to_string(),
+                    content: format!("{} {}", role, topic),
+                    importance,
+                    timestamp: now,
+                    metadata: Some(metadata),
+                    embedding: None,
+                    version: 0,
+                    expires_at: None,
+                })
+                .await
+                .unwrap();
+        }
+
+        let filter = MemoryFilter::new()
+            .with_role("user")
+            .with_min_importance(0.5)
+            .with_metadata("topic", "billing");
+        let retrieved = store.retrieve_filtered("test", 10, &filter).await.unwrap();
+        assert_eq!(retrieved.len(), 1);
+        assert_eq!(retrieved[0].content, "user billing");
+
+        let future = MemoryFilter::new().with_since(now + chrono::Duration::seconds(1));
+        let retrieved = store.retrieve_filtered("test", 10, &future).await.unwrap();
+        assert!(retrieved.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_tenant_scoped_access() {
+        let guard = Arc::new(TenantGuard::new());
+        let memory = SessionMemory::new(Box::new(InMemoryStore::new()), 5).with_tenant_guard(guard);
+        let record = MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: "acme-chat".to_string(),
+            role: "user".to_string(),
+            content: "Quarterly numbers".to_string(),
+            importance: 0.5,
+            timestamp: Utc::now(),
+            metadata: None,
+            embedding: Some(vec![1.0, 0.0]),
+            version: 0,
+            expires_at: None,
+        };
+        memory.store_as("acme", record).await.unwrap();
+
+        let own = memory
+            .retrieve_recent_as("acme", "acme-chat")
+            .await
+            .unwrap();
+        assert_eq!(own.len(), 1);
+
+        let err = memory
+            .retrieve_recent_as("globex", "acme-chat")
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AgentError::TenantViolation(_)));
+
+        let filter = MemoryFilter::default();
+        let hits = memory
+            .search_as("acme", "acme-chat", vec![1.0, 0.0], 5, &filter)
+            .await
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+
+        // The plain methods enforce the scope's tenant too
+        let err = tenant::scope(
+            Some("globex".to_string()),
+            memory.retrieve_recent("acme-chat"),
+        )
+        .await
+        .unwrap_err();
+        assert!(matches!(err, AgentError::TenantViolation(_)));
+        assert!(memory.search("acme-chat", vec![1.0, 0.0], 5).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_tenant_owner_read_from_stamps() {
+        let record = |content: &str| MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: "acme-chat".to_string(),
+            role: "user".to_string(),
+            content: content.to_string(),
+            importance: 0.5,
+            timestamp: Utc::now(),
+            metadata: None,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        };
+        // Stamped before a restart, or by another replica
+        let store = InMemoryStore::new();
+        let mut stamped = record("Quarterly numbers");
+        stamped.metadata = Some(HashMap::from([(
+            TENANT_METADATA_KEY.to_string(),
+            "acme".to_string(),
+        )]));
+        store.store(stamped).await.unwrap();
+
+        let memory =
+            SessionMemory::new(Box::new(store), 5).with_tenant_guard(Arc::new(TenantGuard::new()));
+        let err = memory
+            .store_as("globex", record("mine now"))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, AgentError::TenantViolation(_)));
+        assert!(memory.store(record("no tenant")).await.is_err());
+
+        tenant::scope(Some("acme".to_string()), memory.store(record("more")))
+            .await
+            .unwrap();
+        let own = memory
+            .retrieve_recent_as("acme", "acme-chat")
+            .await
+            .unwrap();
+        assert_eq!(stamped_tenant(&own[0]), Some("acme"));
+    }
+
+    #[tokio::test]
+    async fn test_retrieve_page_walks_history() {
+        let store = InMemoryStore::new();
+        let start = Utc::now();
+        for i in 0..5 {
+            store
+                .store(MemoryRecord {
+                    id: Uuid::new_v4(),
+                    session_id: "test".to_string(),
+                    role: "user".to_string(),
+                    content: format!("message {}", i),
+                    importance: 0.5,
+                    timestamp: start + chrono::Duration::seconds(i),
+                    metadata: None,
+                    embedding: None,
+                    version: 0,
+                    expires_at: None,
+                })
+                .await
+                .unwrap();
+        }
+
+        let first = store.retrieve_page("test", None, 2).await.unwrap();
+        assert_eq!(first.records[0].content, "message 4");
+        assert_eq!(first.records[1].content, "message 3");
+
+        let cursor = first.next_cursor.unwrap();
+        let second = store.retrieve_page("test", Some(&cursor), 2).await.unwrap();
+        assert_eq!(second.records[0].content, "message 2");
+
+        let cursor = second.next_cursor.unwrap();
+        let last = store.retrieve_page("test", Some(&cursor), 2).await.unwrap();
+        assert_eq!(last.records.len(), 1);
+        assert_eq!(last.records[0].content, "message 0");
+        assert!(last.next_cursor.is_none());
+    }
+
+    /// Implements only the required methods, relying on the defaults
+    struct MinimalStore(InMemoryStore);
+
+    #[async_trait::async_trait]
+    impl MemoryStore for MinimalStore {
+        async fn store(&self, record: MemoryRecord) -> Result<()> {
+            self.0.store(record).await
+        }
+
+        async fn retrieve(&self, session_id: &str, limit: usize) -> Result<Vec<MemoryRecord>> {
+            self.0.retrieve(session_id, limit).await
+        }
+
+        async fn search(
+            &self,
+            session_id: &str,
+            query_embedding: Vec<f32>,
+            limit: usize,
+        ) -> Result<Vec<MemoryRecord>> {
+            self.0.search(session_id, query_embedding, limit).await
+        }
+
+        async fn flush(&self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_default_methods_build_on_retrieve() {
+        let store = MinimalStore(InMemoryStore::new());
+        let start = Utc::now();
+        for i in 0..3 {
+            store
+                .store(MemoryRecord {
+                    id: Uuid::new_v4(),
+                    session_id: "test".to_string(),
+                    role: if i == 1 { "assistant" } else { "user" }.to_string(),
+                    content: format!("message {}", i),
+                    importance: 0.5,
+                    timestamp: start + chrono::Duration::seconds(i),
+                    metadata: None,
+                    embedding: None,
+                    version: 0,
+                    expires_at: None,
+                })
+                .await
+                .unwrap();
+        }
+
+        let filter = MemoryFilter::new().with_role("user");
+        let users = store.retrieve_filtered("test", 10, &filter).await.unwrap();
+        let contents: Vec<&str> = users.iter().map(|r| r.content.as_str()).collect();
+        assert_eq!(contents, ["message 2", "message 0"]);
+
+        let first = store.retrieve_page("test", None, 2).await.unwrap();
+        assert_eq!(first.records[0].content, "message 2");
+        let cursor = first.next_cursor.unwrap();
+        let last = store.retrieve_page("test", Some(&cursor), 2).await.unwrap();
+        assert_eq!(last.records.len(), 1);
+        assert!(last.next_cursor.is_none());
+
+        assert!(store.update(users[0].clone()).await.is_err());
+        assert_eq!(store.purge_expired().await.unwrap(), 0);
+    }
+
+    struct SummaryLLM;
+
+    #[async_trait::async_trait]
+    impl crate::models::LLM for SummaryLLM {
+        async fn generate(
+            &self,
+            messages: Vec<crate::types::Message>,
+            _files: Option<Vec<crate::types::File>>,
+        ) -> Result<crate::types::GenerationResponse> {
+            let lines = messages.last().unwrap().content.lines().count();
+            Ok(crate::types::GenerationResponse {
+                content: format!("{} turns", lines),
+                metadata: None,
+            })
+        }
+
+        fn model_name(&self) -> &str {
+            "summary"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compaction_replaces_oldest_records() {
+        let memory = SessionMemory::new(Box::new(InMemoryStore::new()), 4)
+            .with_compactor(Compactor::new(Arc::new(SummaryLLM)).with_batch_size(3));
+
+        for i in 0..5 {
+            let record = MemoryRecord {
+                id: Uuid::new_v4(),
+                session_id: "test".to_string(),
+                role: "user".to_string(),
+                content: format!("message {}", i),
+                importance: 0.5,
+                timestamp: Utc::now(),
+                metadata: None,
+                embedding: None,
+                version: 0,
+                expires_at: None,
+            };
+            memory.store(record).await.unwrap();
+        }
+
+        let recent = memory.retrieve_recent("test").await.unwrap();
+        assert_eq!(recent.len(), 3);
+        assert_eq!(recent[0].role, compaction::SUMMARY_ROLE);
+        assert!(recent[0].content.ends_with("3 turns"));
+        assert_eq!(recent[1].content, "message 3");
+        assert_eq!(recent[2].content, "message 4");
+    }
+
+    #[tokio::test]
+    async fn test_compaction_keeps_pinned_records() {
+        let compactor = Compactor::new(Arc::new(SummaryLLM))
+            .with_batch_size(2)
+            .with_pin_importance(0.9);
+        let memory =
+            SessionMemory::new(Box::new(InMemoryStore::new()), 3).with_compactor(compactor);
+
+        for (content, importance) in [("pinned", 0.95), ("a", 0.5), ("b", 0.5), ("c", 0.5)] {
+            let record = MemoryRecord {
+                id: Uuid::new_v4(),
+                session_id: "test".to_string(),
+                role: "user".to_string(),
+                content: content.to_string(),
+                importance,
+                timestamp: Utc::now(),
+                metadata: None,
+                embedding: None,
+                version: 0,
+                expires_at: None,
+            };
+            memory.store(record).await.unwrap();
+        }
+
+        let recent = memory.retrieve_recent("test").await.unwrap();
+        let contents: Vec<&str> = recent.iter().map(|r| r.content.as_str()).collect();
+        assert_eq!(contents[0], "pinned");
+        assert_eq!(recent[1].role, compaction::SUMMARY_ROLE);
+        assert_eq!(contents[2], "c");
+    }
+
+    #[tokio::test]
+    async fn test_restore_is_idempotent_and_compacts() {
+        let records: Vec<MemoryRecord> = (0..6)
+            .map(|i| MemoryRecord {
+                id: Uuid::new_v4(),
+                session_id: "test".to_string(),
+                role: "user".to_string(),
+                content: format!("message {}", i),
+                importance: 0.5,
+                timestamp: Utc::now() + chrono::Duration::seconds(i),
+                metadata: None,
+                embedding: None,
+                version: 0,
+                expires_at: None,
+            })
+            .collect();
+
+        let memory = SessionMemory::new(Box::new(InMemoryStore::new()), 10);
+        let mut doubled = records.clone();
+        doubled.extend(records.clone());
+        assert_eq!(memory.restore(doubled, None).await.unwrap(), 6);
+        assert_eq!(memory.restore(records.clone(), None).await.unwrap(), 0);
+        assert_eq!(memory.retrieve_recent("test").await.unwrap().len(), 6);
+        let stored = memory.store.retrieve("test", 100).await.unwrap();
+        assert_eq!(stored.len(), 6);
+
+        let memory = SessionMemory::new(Box::new(InMemoryStore::new()), 10)
+            .with_compactor(Compactor::new(Arc::new(SummaryLLM)));
+        assert_eq!(memory.restore(records.clone(), Some(2)).await.unwrap(), 3);
+        // The stored summary is reused and nothing is written again
+        assert_eq!(memory.restore(records.clone(), Some(2)).await.unwrap(), 0);
+        let recent = memory.retrieve_recent("test").await.unwrap();
+        let contents: Vec<&str> = recent.iter().map(|r| r.content.as_str()).collect();
+        assert_eq!(recent[0].role, compaction::SUMMARY_ROLE);
+        assert!(contents[0].ends_with("4 turns"));
+        assert_eq!(contents[1..], ["message 4", "message 5"]);
+        let stored = memory.store.retrieve("test", 100).await.unwrap();
+        assert_eq!(stored.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_semantic_tier_promotion() {
+        let rules = PromotionRules::new().with_fact_extraction(Arc::new(SummaryLLM));
+        let memory =
+            SessionMemory::new(Box::new(InMemoryStore::new()), 10).with_semantic_tier(rules);
+
+        for (content, importance) in [("small talk", 0.3), ("my account is ACC-77", 0.9)] {
+            let record = MemoryRecord {
+                id: Uuid::new_v4(),
+                session_id: "test".to_string(),
+                role: "user".to_string(),
+                content: content.to_string(),
+                importance,
+                timestamp: Utc::now(),
+                metadata: None,
+                embedding: Some(vec![1.0, 0.0]),
+                version: 0,
+                expires_at: None,
+            };
+            memory.store(record).await.unwrap();
+        }
+
+        let filter = MemoryFilter::default();
+        let semantic = memory.retrieve_semantic("test", 10, &filter).await.unwrap();
+        assert_eq!(semantic.len(), 1);
+        assert_eq!(semantic[0].session_id, semantic_scope("test"));
+        assert_eq!(semantic[0].content, "1 turns");
+        assert_eq!(MemoryTier::of(&semantic[0]), MemoryTier::Semantic);
+        assert_eq!(memory.retrieve_recent("test").await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_thread_scopes_and_rollups() {
+        let memory = SessionMemory::new(Box::new(InMemoryStore::new()), 10)
+            .with_compactor(Compactor::new(Arc::new(SummaryLLM)))
+            .with_threads();
+
+        for (session_id, content) in [
+            ("acme/ticket-1/a", "printer jams"),
+            ("acme/ticket-1/b", "tried a reboot"),
+            ("acme/ticket-2/a", "invoice missing"),
+        ] {
+            let record = MemoryRecord {
+                id: Uuid::new_v4(),
+                session_id: session_id.to_string(),
+                role: "user".to_string(),
+                content: content.to_string(),
+                importance: 0.5,
+                timestamp: Utc::now(),
+                metadata: None,
+                embedding: Some(vec![1.0, 0.0]),
+                version: 0,
+                expires_at: None,
+            };
+            memory.store(record).await.unwrap();
+        }
+
+        let filter = MemoryFilter::default();
+        let thread = memory
+            .retrieve_scope("acme/ticket-1", 10, &filter)
+            .await
+            .unwrap();
+        assert_eq!(thread.len(), 2);
+        assert!(thread.iter().all(
+            |r| r.metadata.as_ref().unwrap()[SOURCE_SESSION_KEY].starts_with("acme/ticket-1/")
+        ));
+        let org = memory
+            .search_scope("acme", vec![1.0, 0.0], 10, &filter)
+            .await
+            .unwrap();
+        assert_eq!(org.len(), 3);
+        // The sessions themselves hold only their own records
+        let own = memory
+            .search("acme/ticket-1/a", vec![1.0, 0.0], 10)
+            .await
+            .unwrap();
+        assert_eq!(own.len(), 1);
+
+        assert!(memory
+            .thread_summary("acme/ticket-1")
+            .await
+            .unwrap()
+            .is_none());
+        let summary = memory.summarize_thread("acme/ticket-1", 10).await.unwrap();
+        assert_eq!(summary.session_id, rollup_scope("acme/ticket-1"));
+        assert!(summary.content.ends_with("2 turns"));
+        let latest = memory
+            .thread_summary("acme/ticket-1")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(latest.id, summary.id);
+    }
+
+    #[tokio::test]
+    async fn test_thread_scopes_reference_final_records() {
+        let memory = SessionMemory::new(Box::new(InMemoryStore::new()), 10).with_threads();
+        let mut record = MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: "acme/ticket-1".to_string(),
+            role: "assistant".to_string(),
+            content: "Try".to_string(),
+            importance: 0.5,
+            timestamp: Utc::now(),
+            metadata: Some(HashMap::from([(
+                TRUNCATED_KEY.to_string(),
+                "true".to_string(),
+            )])),
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        };
+        memory.store(record.clone()).await.unwrap();
+        let filter = MemoryFilter::default();
+        assert!(memory
+            .retrieve_scope("acme", 10, &filter)
+            .await
+            .unwrap()
+            .is_empty());
+
+        record.content = "Try a reboot".to_string();
+        record.metadata = None;
+        memory.store(record.clone()).await.unwrap();
+        memory.store(record.clone()).await.unwrap();
+        let thread = memory.retrieve_scope("acme", 10, &filter).await.unwrap();
+        assert_eq!(thread.len(), 1);
+        assert_eq!(thread[0].id, record.id);
+        assert_eq!(thread[0].session_id, "acme/ticket-1");
+        assert_eq!(thread[0].content, "Try a reboot");
+        assert!(!thread[0]
+            .metadata
+            .as_ref()
+            .unwrap()
+            .contains_key(SOURCE_ID_KEY));
+
+        record.id = Uuid::new_v4();
+        record.session_id = thread_scope("acme");
+        assert!(matches!(
+            memory.store(record).await,
+            Err(AgentError::InvalidState(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_user_memory_spans_sessions() {
+        let memory = SessionMemory::new(Box::new(InMemoryStore::new()), 5).with_user_promotion(0.7);
+        memory.bind_user("monday", "alice");
+
+        for (content, importance) in [("I prefer metric units", 0.9), ("ok", 0.2)] {
+            let record = MemoryRecord {
+                id: Uuid::new_v4(),
+                session_id: "monday".to_string(),
+                role: "user".to_string(),
+                content: content.to_string(),
+                importance,
+                timestamp: Utc::now(),
+                metadata: None,
+                embedding: Some(vec![1.0, 0.0]),
+                version: 0,
+                expires_at: None,
+            };
+            memory.store(record).await.unwrap();
+        }
+
+        let facts = memory
+            .search_user("alice", vec![1.0, 0.0], 10, &MemoryFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(facts.len(), 1);
+        assert_eq!(facts[0].content, "I prefer metric units");
+        let metadata = facts[0].metadata.as_ref().unwrap();
+        assert_eq!(metadata[USER_METADATA_KEY], "alice");
+        assert_eq!(metadata[SOURCE_SESSION_KEY], "monday");
+
+        // Another session of the same user sees the fact, other users don't
+        memory.bind_user("friday", "alice");
+        let user = memory.user_of("friday").unwrap();
+        let recalled = memory
+            .retrieve_user(&user, 10, &MemoryFilter::default())
+            .await
+            .unwrap();
+        assert_eq!(recalled.len(), 1);
+        assert!(memory
+            .retrieve_user("bob", 10, &MemoryFilter::default())
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_expired_records_hidden_and_purged() {
+        let memory = SessionMemory::new(Box::new(InMemoryStore::new()), 5);
+        for (content, ttl) in [("stale", -1), ("fresh", 60)] {
+            let record = MemoryRecord {
+                id: Uuid::new_v4(),
+                session_id: "test".to_string(),
+                role: "tool".to_string(),
+                content: content.to_string(),
+                importance: 0.5,
+                timestamp: Utc::now(),
+                metadata: None,
+                embedding: None,
+                version: 0,
+                expires_at: None,
+            }
+            .with_ttl(chrono::Duration::seconds(ttl));
+            memory.store(record).await.unwrap();
+        }
+
+        let recent = memory.retrieve_recent("test").await.unwrap();
+        assert_eq!(recent.len(), 1);
+        assert_eq!(recent[0].content, "fresh");
+
+        let page = memory.retrieve_page("test", None, 10).await.unwrap();
+        assert_eq!(page.records.len(), 1);
+
+        assert_eq!(memory.purge_expired().await.unwrap(), 1);
+        assert_eq!(memory.purge_expired().await.unwrap(), 0);
+    }
+
+    #[test]
+    fn test_mmr_balances_freshness_and_importance() {
+        let record =
+            |content: &str, age_days: i64, importance: f32, embedding: Vec<f32>| MemoryRecord {
+                id: Uuid::new_v4(),
+                session_id: "test".to_string(),
+                role: "user".to_string(),
+                content: content.to_string(),
+                importance,
+                timestamp: Utc::now() - chrono::Duration::days(age_days),
+                metadata: None,
+                embedding: Some(embedding),
+                version: 0,
+                expires_at: None,
+            };
+        let candidates = vec![
+            record("old", 30, 0.2, vec![1.0, 0.0]),
+            record("fresh", 0, 0.2, vec![0.9, 0.1]),
+            record("pinned", 30, 1.0, vec![0.8, 0.2]),
+        ];
+        let query = [1.0, 0.0];
+        let first = |config: &MmrConfig| mmr_rerank_with(&query, candidates.clone(), 1, config);
+
+        assert_eq!(
+            mmr_rerank(&query, candidates.clone(), 1, 0.5)[0].content,
+            "old"
+        );
+        let recency = MmrConfig::new().with_recency(chrono::Duration::days(7), 0.5);
+        assert_eq!(first(&recency)[0].content, "fresh");
+        let importance = MmrConfig::new().with_importance_weight(0.5);
+        assert_eq!(first(&importance)[0].content, "pinned");
+        let dot = MmrConfig::new().with_similarity(dot_similarity);
+        assert_eq!(first(&dot)[0].content, "old");
+    }
 }
@@ -1,13 +1,18 @@
 use async_trait::async_trait;
+use futures::stream::BoxStream;
 use mongodb::bson::{doc, Document};
 use mongodb::{Client, Collection};
 
 use crate::error::{AgentError, Result};
-use crate::memory::{MemoryRecord, MemoryStore};
+use crate::memory::{
+    decode_cursor, export_pages, into_page, record_not_found, version_conflict, MemoryFilter,
+    MemoryPage, MemoryRecord, MemoryStore, SimilarityMetric,
+};
 
 /// MongoDB memory store
 pub struct MongoStore {
     collection: Collection<Document>,
+    metric: SimilarityMetric,
 }
 
 impl MongoStore {
@@ -30,7 +35,31 @@ impl MongoStore {
             .await
             .map_err(|e| AgentError::MemoryError(format!("Failed to create index: {}", e)))?;
 
-        Ok(Self { collection })
+        // MongoDB's TTL monitor deletes expired documents in the background
+        collection
+            .create_index(
+                mongodb::IndexModel::builder()
+                    .keys(doc! { "expires_at": 1 })
+                    .options(
+                        mongodb::options::IndexOptions::builder()
+                            .expire_after(std::time::Duration::ZERO)
+                            .build(),
+                    )
+                    .build(),
+            )
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to create index: {}", e)))?;
+
+        Ok(Self {
+            collection,
+            metric: SimilarityMetric::default(),
+        })
+    }
+
+    /// Ranks `search` results by `metric` instead of cosine similarity
+    pub fn with_metric(mut self, metric: SimilarityMetric) -> Self {
+        self.metric = metric;
+        self
     }
 
     /// Create vector search index (MongoDB Atlas Search required)
@@ -43,51 +72,21 @@ impl MongoStore {
         );
         Ok(())
     }
-}
-
-#[async_trait]
-impl MemoryStore for MongoStore {
-    async fn store(&self, record: MemoryRecord) -> Result<()> {
-        let mut doc = doc! {
-            "_id": record.id.to_string(),
-            "session_id": &record.session_id,
-            "role": &record.role,
-            "content": &record.content,
-            "importance": record.importance,
-            "timestamp": mongodb::bson::DateTime::from_chrono(record.timestamp),
-        };
 
-        if let Some(metadata) = &record.metadata {
-            let metadata_doc = serde_json::to_value(metadata)
-                .map_err(|e| AgentError::SerializationError(e))
-                .and_then(|v| {
-                    mongodb::bson::to_bson(&v).map_err(|e| {
-                        AgentError::MemoryError(format!("Failed to convert metadata: {}", e))
-                    })
-                })?;
-            doc.insert("metadata", metadata_doc);
-        }
-
-        if let Some(embedding) = &record.embedding {
-            doc.insert("embedding", embedding);
-        }
-
-        self.collection
-            .replace_one(doc! { "_id": record.id.to_string() }, doc.clone())
-            .upsert(true)
-            .await
-            .map_err(|e| AgentError::MemoryError(format!("Failed to store memory: {}", e)))?;
-
-        Ok(())
-    }
-
-    async fn retrieve(&self, session_id: &str, limit: usize) -> Result<Vec<MemoryRecord>> {
-        let filter = doc! { "session_id": session_id };
+    /// Runs `filter` and returns up to `limit` matching records, newest first
+    async fn find_recent(&self, filter: Document, limit: usize) -> Result<Vec<MemoryRecord>> {
         let options = mongodb::options::FindOptions::builder()
             .sort(doc! { "timestamp": -1 })
             .limit(limit as i64)
             .build();
+        self.find_with(filter, options).await
+    }
 
+    async fn find_with(
+        &self,
+        filter: Document,
+        options: mongodb::options::FindOptions,
+    ) -> Result<Vec<MemoryRecord>> {
         let mut cursor = self
             .collection
             .find(filter)
@@ -101,30 +100,129 @@ impl MemoryStore for MongoStore {
             .await
             .map_err(|e| AgentError::MemoryError(format!("Failed to advance cursor: {}", e)))?
         {
-            let doc = cursor.current();
-            records.push(document_to_memory_record(doc)?);
+            let doc = cursor
+                .deserialize_current()
+                .map_err(|e| AgentError::MemoryError(format!("Failed to decode memory: {}", e)))?;
+            records.push(document_to_memory_record(&doc)?);
         }
 
         Ok(records)
     }
+}
+
+#[async_trait]
+impl MemoryStore for MongoStore {
+    async fn store(&self, record: MemoryRecord) -> Result<()> {
+        let mut doc = memory_record_to_document(&record)?;
+        doc.remove("version");
+        let incoming = record.version as i64;
+
+        // A pipeline update, so overwriting an existing document bumps its
+        // stored version instead of taking ours. `$literal` keeps content
+        // starting with `$` from being read as a field path.
+        let pipeline = vec![doc! {
+            "$replaceWith": {
+                "$mergeObjects": [
+                    { "$literal": doc },
+                    {
+                        "version": {
+                            "$cond": [
+                                { "$eq": [{ "$type": "$session_id" }, "missing"] },
+                                incoming,
+                                { "$add": [{ "$max": [{ "$ifNull": ["$version", 0_i64] }, incoming] }, 1_i64] },
+                            ]
+                        }
+                    },
+                ]
+            }
+        }];
+
+        self.collection
+            .update_one(doc! { "_id": record.id.to_string() }, pipeline)
+            .upsert(true)
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to store memory: {}", e)))?;
+
+        Ok(())
+    }
+
+    async fn retrieve(&self, session_id: &str, limit: usize) -> Result<Vec<MemoryRecord>> {
+        self.retrieve_filtered(session_id, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn retrieve_filtered(
+        &self,
+        session_id: &str,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.find_recent(memory_filter_document(session_id, filter), limit)
+            .await
+    }
+
+    async fn retrieve_page(
+        &self,
+        session_id: &str,
+        cursor: Option<&str>,
+        page_size: usize,
+    ) -> Result<MemoryPage> {
+        let mut query = doc! { "session_id": session_id, "expires_at": not_expired() };
+        if let Some(cursor) = cursor {
+            let (timestamp, id) = decode_cursor(cursor)?;
+            let timestamp = mongodb::bson::DateTime::from_millis(timestamp.timestamp_millis());
+            query.insert(
+                "$or",
+                vec![
+                    doc! { "timestamp": { "$lt": timestamp } },
+                    doc! { "timestamp": timestamp, "_id": { "$lt": id.to_string() } },
+                ],
+            );
+        }
+
+        let options = mongodb::options::FindOptions::builder()
+            .sort(doc! { "timestamp": -1, "_id": -1 })
+            .limit(page_size as i64 + 1)
+            .build();
+        let records = self.find_with(query, options).await?;
+
+        Ok(into_page(records, page_size))
+    }
+
+    fn export<'a>(&'a self, session_id: &'a str) -> BoxStream<'a, Result<MemoryRecord>> {
+        export_pages(self, session_id)
+    }
 
     async fn search(
         &self,
         session_id: &str,
         query_embedding: Vec<f32>,
         limit: usize,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.search_filtered(session_id, query_embedding, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn search_filtered(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
     ) -> Result<Vec<MemoryRecord>> {
         // For basic MongoDB, we'll do client-side vector search
         // For MongoDB Atlas, you'd use $vectorSearch aggregation
-        let all_records = self.retrieve(session_id, 1000).await?; // Get larger set
+        let mut query = memory_filter_document(session_id, filter);
+        query.insert("embedding", doc! { "$exists": true });
+        let candidates = self.find_recent(query, 1000).await?; // Get larger set
 
-        let mut scored: Vec<(f32, MemoryRecord)> = all_records
+        let similarity = self.metric.similarity();
+        let mut scored: Vec<(f32, MemoryRecord)> = candidates
             .into_iter()
             .filter(|r| r.embedding.is_some())
             .map(|r| {
                 let embedding = r.embedding.as_ref().unwrap();
-                let similarity = super::cosine_similarity(&query_embedding, embedding);
-                (similarity, r)
+                (similarity(&query_embedding, embedding), r)
             })
             .collect();
 
@@ -132,12 +230,147 @@ impl MemoryStore for MongoStore {
         Ok(scored.into_iter().take(limit).map(|(_, r)| r).collect())
     }
 
+    async fn get(&self, session_id: &str, id: uuid::Uuid) -> Result<Option<MemoryRecord>> {
+        let query = doc! {
+            "_id": id.to_string(),
+            "session_id": session_id,
+            "expires_at": not_expired(),
+        };
+        Ok(self.find_recent(query, 1).await?.pop())
+    }
+
+    async fn update(&self, record: MemoryRecord) -> Result<u64> {
+        let id = record.id.to_string();
+        let expected = record.version;
+
+        let mut next = record;
+        next.version = expected + 1;
+        let doc = memory_record_to_document(&next)?;
+
+        // Matching on the version turns the replace into a compare-and-swap.
+        // Documents written before versioning have no field and count as 0.
+        let version = if expected == 0 {
+            doc! { "$in": [0_i64, null] }
+        } else {
+            doc! { "$eq": expected as i64 }
+        };
+        let result = self
+            .collection
+            .replace_one(doc! { "_id": &id, "version": version }, doc)
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to update memory: {}", e)))?;
+
+        if result.matched_count == 1 {
+            return Ok(next.version);
+        }
+
+        let current = self
+            .collection
+            .find_one(doc! { "_id": &id })
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to read version: {}", e)))?;
+
+        match current {
+            Some(doc) => Err(version_conflict(
+                next.id,
+                expected,
+                doc.get_i64("version").unwrap_or(0) as u64,
+            )),
+            None => Err(record_not_found(next.id)),
+        }
+    }
+
+    async fn purge_expired(&self) -> Result<usize> {
+        // The TTL index also purges, but only once a minute
+        let result = self
+            .collection
+            .delete_many(doc! { "expires_at": { "$lte": mongodb::bson::DateTime::now() } })
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to purge memories: {}", e)))?;
+
+        Ok(result.deleted_count as usize)
+    }
+
     async fn flush(&self) -> Result<()> {
         // MongoDB commits automatically
         Ok(())
     }
 }
 
+/// Matches documents without an expiry or whose expiry is still ahead
+fn not_expired() -> Document {
+    doc! { "$not": { "$lte": mongodb::bson::DateTime::now() } }
+}
+
+/// Builds the query document for a session scope plus [`MemoryFilter`]
+fn memory_filter_document(session_id: &str, filter: &MemoryFilter) -> Document {
+    let mut query = doc! { "session_id": session_id, "expires_at": not_expired() };
+
+    for (key, value) in &filter.metadata {
+        query.insert(format!("metadata.{}", key), value);
+    }
+    if let Some(role) = &filter.role {
+        query.insert("role", role);
+    }
+    if filter.since.is_some() || filter.until.is_some() {
+        let mut range = Document::new();
+        if let Some(since) = filter.since {
+            range.insert(
+                "$gte",
+                mongodb::bson::DateTime::from_millis(since.timestamp_millis()),
+            );
+        }
+        if let Some(until) = filter.until {
+            range.insert(
+                "$lt",
+                mongodb::bson::DateTime::from_millis(until.timestamp_millis()),
+            );
+        }
+        query.insert("timestamp", range);
+    }
+    if let Some(min_importance) = filter.min_importance {
+        query.insert("importance", doc! { "$gte": min_importance });
+    }
+
+    query
+}
+
+fn memory_record_to_document(record: &MemoryRecord) -> Result<Document> {
+    let mut doc = doc! {
+        "_id": record.id.to_string(),
+        "session_id": &record.session_id,
+        "role": &record.role,
+        "content": &record.content,
+        "importance": record.importance,
+        "timestamp": mongodb::bson::DateTime::from_millis(record.timestamp.timestamp_millis()),
+        "version": record.version as i64,
+    };
+
+    if let Some(metadata) = &record.metadata {
+        let metadata_doc = serde_json::to_value(metadata)
+            .map_err(AgentError::SerializationError)
+            .and_then(|v| {
+                mongodb::bson::to_bson(&v).map_err(|e| {
+                    AgentError::MemoryError(format!("Failed to convert metadata: {}", e))
+                })
+            })?;
+        doc.insert("metadata", metadata_doc);
+    }
+
+    if let Some(embedding) = &record.embedding {
+        doc.insert("embedding", embedding);
+    }
+
+    if let Some(expires_at) = record.expires_at {
+        doc.insert(
+            "expires_at",
+            mongodb::bson::DateTime::from_millis(expires_at.timestamp_millis()),
+        );
+    }
+
+    Ok(doc)
+}
+
 fn document_to_memory_record(doc: &Document) -> Result<MemoryRecord> {
     let id = doc
         .get_str("_id")
@@ -165,7 +398,7 @@ fn document_to_memory_record(doc: &Document) -> Result<MemoryRecord> {
     let timestamp = doc
         .get_datetime("timestamp")
         .ok()
-        .map(|dt| dt.to_chrono())
+        .and_then(|dt| chrono::DateTime::from_timestamp_millis(dt.timestamp_millis()))
         .unwrap_or_else(chrono::Utc::now);
 
     let metadata = doc
@@ -179,6 +412,13 @@ fn document_to_memory_record(doc: &Document) -> Result<MemoryRecord> {
             .collect::<Option<Vec<f32>>>()
     });
 
+    let version = doc.get_i64("version").unwrap_or(0) as u64;
+
+    let expires_at = doc
+        .get_datetime("expires_at")
+        .ok()
+        .and_then(|dt| chrono::DateTime::from_timestamp_millis(dt.timestamp_millis()));
+
     Ok(MemoryRecord {
         id,
         session_id,
@@ -188,5 +428,7 @@ fn document_to_memory_record(doc: &Document) -> Result<MemoryRecord> {
         timestamp,
         metadata,
         embedding,
+        version,
+        expires_at,
     })
 }
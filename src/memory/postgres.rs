@@ -1,58 +1,436 @@
 use async_trait::async_trait;
-use sqlx::PgPool;
+use std::collections::HashSet;
+use std::time::Duration;
+
+use futures::stream::BoxStream;
+use sqlx::postgres::PgPoolOptions;
+use sqlx::{PgPool, Postgres, QueryBuilder};
 
 use crate::error::{AgentError, Result};
-use crate::memory::{MemoryRecord, MemoryStore};
+use crate::memory::{
+    decode_cursor, export_pages, into_page, record_not_found, version_conflict, MemoryFilter,
+    MemoryPage, MemoryRecord, MemoryStore, SimilarityMetric,
+};
+
+/// Column tuple shared by every SELECT on the memories table
+type MemoryRow = (
+    uuid::Uuid,
+    String,
+    String,
+    String,
+    f32,
+    chrono::DateTime<chrono::Utc>,
+    Option<serde_json::Value>,
+    Option<Vec<f32>>,
+    i64,
+    Option<chrono::DateTime<chrono::Utc>>,
+);
+
+/// Column list matching [`MemoryRow`]
+const SELECT_COLUMNS: &str =
+    "id, session_id, role, content, importance, timestamp, metadata, embedding, version, expires_at";
+
+/// Upsert clause shared by single and batched inserts. An overwrite bumps
+/// the stored version rather than taking the caller's, so it can never move
+/// backwards under a concurrent `update`. Expects the target aliased `existing`.
+const ON_CONFLICT_UPDATE: &str = " ON CONFLICT (id) DO UPDATE SET \
+    content = EXCLUDED.content, \
+    importance = EXCLUDED.importance, \
+    metadata = EXCLUDED.metadata, \
+    embedding = EXCLUDED.embedding, \
+    version = GREATEST(existing.version, EXCLUDED.version) + 1, \
+    expires_at = EXCLUDED.expires_at";
+
+/// Rows per multi-row INSERT in [`PostgresStore::store_batch`], keeping the
+/// 10 bound parameters per row under Postgres' 65535 parameter limit
+const BATCH_CHUNK_SIZE: usize = 1000;
+
+/// Predicate hiding expired records from reads
+const NOT_EXPIRED: &str = " AND (expires_at IS NULL OR expires_at > now())";
+
+/// Schema migrations applied in order by [`PostgresStore::run_migrations`].
+///
+/// Entries are append-only: never edit a released migration, add a new one.
+/// `{table}` and `{dimension}` are substituted from the store's [`PostgresConfig`].
+const MIGRATIONS: &[(i64, &str, &str)] = &[
+    (
+        1,
+        "create memories table",
+        r#"
+        CREATE TABLE IF NOT EXISTS {table} (
+            id UUID PRIMARY KEY,
+            session_id TEXT NOT NULL,
+            role TEXT NOT NULL,
+            content TEXT NOT NULL,
+            importance REAL NOT NULL,
+            timestamp TIMESTAMPTZ NOT NULL,
+            metadata JSONB,
+            embedding vector({dimension})
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_{table}_session ON {table}(session_id);
+        CREATE INDEX IF NOT EXISTS idx_{table}_timestamp ON {table}(timestamp DESC);
+        "#,
+    ),
+    (
+        2,
+        "add optimistic concurrency version",
+        r#"
+        ALTER TABLE {table} ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 0;
+        "#,
+    ),
+    (
+        3,
+        "add keyset pagination index",
+        r#"
+        CREATE INDEX IF NOT EXISTS idx_{table}_session_page
+            ON {table}(session_id, timestamp DESC, id DESC);
+        "#,
+    ),
+    (
+        4,
+        "add record expiration",
+        r#"
+        ALTER TABLE {table} ADD COLUMN IF NOT EXISTS expires_at TIMESTAMPTZ;
+
+        CREATE INDEX IF NOT EXISTS idx_{table}_expires_at
+            ON {table}(expires_at) WHERE expires_at IS NOT NULL;
+        "#,
+    ),
+];
+
+/// Advisory lock key serializing concurrent migration runs
+const MIGRATION_LOCK_KEY: i64 = 0x0072_7361_6765_6e74;
+
+/// Fails unless `table` is safe to splice into SQL as an identifier
+pub(crate) fn check_table_name(table: &str) -> Result<()> {
+    let mut chars = table.chars();
+    let valid = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_lowercase() || c == '_')
+        && chars.all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        && table.len() <= 48;
+    if !valid {
+        return Err(AgentError::ConfigError(format!(
+            "Invalid table name {:?}: use at most 48 lowercase letters, digits and underscores",
+            table
+        )));
+    }
+    Ok(())
+}
+
+/// Table layout of a [`PostgresStore`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PostgresConfig {
+    table: String,
+    dimension: usize,
+    metric: SimilarityMetric,
+    max_connections: u32,
+    min_connections: u32,
+    acquire_timeout: Duration,
+    idle_timeout: Option<Duration>,
+}
+
+impl Default for PostgresConfig {
+    /// A `memories` table with 384-dimensional embeddings, matching the
+    /// default `FastEmbedder`
+    fn default() -> Self {
+        Self {
+            table: "memories".to_string(),
+            dimension: 384,
+            metric: SimilarityMetric::Cosine,
+            max_connections: 10,
+            min_connections: 0,
+            acquire_timeout: Duration::from_secs(30),
+            idle_timeout: Some(Duration::from_secs(600)),
+        }
+    }
+}
+
+impl PostgresConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the table name; migrations are tracked in `<table>_schema_migrations`
+    pub fn with_table(mut self, table: impl Into<String>) -> Self {
+        self.table = table.into();
+        self
+    }
+
+    /// Sets the embedding dimension of newly created tables
+    pub fn with_dimension(mut self, dimension: usize) -> Self {
+        self.dimension = dimension;
+        self
+    }
+
+    /// Sets how `search` and the embedding index compare embeddings
+    pub fn with_metric(mut self, metric: SimilarityMetric) -> Self {
+        self.metric = metric;
+        self
+    }
+
+    /// Sets the maximum number of pooled connections
+    pub fn with_max_connections(mut self, max: u32) -> Self {
+        self.max_connections = max;
+        self
+    }
+
+    /// Sets how many connections the pool keeps open even when idle
+    pub fn with_min_connections(mut self, min: u32) -> Self {
+        self.min_connections = min;
+        self
+    }
+
+    /// Sets how long a query waits for a free connection before failing
+    pub fn with_acquire_timeout(mut self, timeout: Duration) -> Self {
+        self.acquire_timeout = timeout;
+        self
+    }
+
+    /// Sets how long an unused connection stays open; `None` keeps it forever
+    pub fn with_idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Returns the table name
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Returns the embedding dimension
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    /// Returns the similarity metric
+    pub fn metric(&self) -> SimilarityMetric {
+        self.metric
+    }
+
+    /// pgvector operator ordering rows by the metric, closest first
+    fn distance_operator(&self) -> &'static str {
+        match self.metric {
+            SimilarityMetric::Cosine => "<=>",
+            // Negative inner product, so ascending order is best first
+            SimilarityMetric::Dot => "<#>",
+            SimilarityMetric::Euclidean => "<->",
+        }
+    }
+
+    /// pgvector operator class an index needs to serve `distance_operator`
+    fn operator_class(&self) -> &'static str {
+        match self.metric {
+            SimilarityMetric::Cosine => "vector_cosine_ops",
+            SimilarityMetric::Dot => "vector_ip_ops",
+            SimilarityMetric::Euclidean => "vector_l2_ops",
+        }
+    }
+
+    /// Fails unless the table name is a plain SQL identifier, since it is
+    /// interpolated into queries
+    fn validate(&self) -> Result<()> {
+        check_table_name(&self.table)?;
+        if self.dimension == 0 {
+            return Err(AgentError::ConfigError(
+                "Embedding dimension must be positive".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn render(&self, sql: &str) -> String {
+        sql.replace("{table}", &self.table)
+            .replace("{dimension}", &self.dimension.to_string())
+    }
+
+    /// Fails if `embedding` doesn't have the configured dimension
+    fn check_embedding(&self, embedding: &[f32]) -> Result<()> {
+        if embedding.len() == self.dimension {
+            Ok(())
+        } else {
+            Err(AgentError::MemoryError(format!(
+                "Embedding has {} dimensions, table {} expects {}",
+                embedding.len(),
+                self.table,
+                self.dimension
+            )))
+        }
+    }
+}
 
 /// PostgreSQL memory store with pgvector support
 pub struct PostgresStore {
     pool: PgPool,
+    config: PostgresConfig,
 }
 
 impl PostgresStore {
-    /// Creates a new PostgreSQL store
+    /// Creates a new PostgreSQL store and brings its schema up to date
     pub async fn new(database_url: &str) -> Result<Self> {
-        let pool = PgPool::connect(database_url).await.map_err(|e| {
-            AgentError::MemoryError(format!("Failed to connect to PostgreSQL: {}", e))
+        Self::with_config(database_url, PostgresConfig::default()).await
+    }
+
+    /// Creates a PostgreSQL store with the given table layout.
+    ///
+    /// Migrations create the table if needed; an existing table must already
+    /// store embeddings of the configured dimension.
+    pub async fn with_config(database_url: &str, config: PostgresConfig) -> Result<Self> {
+        config.validate()?;
+        let pool = PgPoolOptions::new()
+            .max_connections(config.max_connections)
+            .min_connections(config.min_connections)
+            .acquire_timeout(config.acquire_timeout)
+            .idle_timeout(config.idle_timeout)
+            .connect(database_url)
+            .await
+            .map_err(|e| {
+                AgentError::MemoryError(format!("Failed to connect to PostgreSQL: {}", e))
+            })?;
+
+        let store = Self { pool, config };
+        store.run_migrations().await?;
+        store.check_dimension().await?;
+        Ok(store)
+    }
+
+    /// Returns the table layout of this store
+    pub fn config(&self) -> &PostgresConfig {
+        &self.config
+    }
+
+    /// Compares the embedding column's declared dimension with the config
+    async fn check_dimension(&self) -> Result<()> {
+        // pgvector stores the declared dimension as the column's type modifier
+        let (declared,): (i32,) = sqlx::query_as(
+            "SELECT atttypmod FROM pg_attribute WHERE attrelid = $1::regclass AND attname = 'embedding'",
+        )
+        .bind(&self.config.table)
+        .fetch_one(&self.pool)
+        .await
+        .map_err(|e| {
+            AgentError::MemoryError(format!("Failed to read embedding column: {}", e))
         })?;
 
-        // Create table if not exists
-        sqlx::query(
+        if declared > 0 && declared as usize != self.config.dimension {
+            return Err(AgentError::ConfigError(format!(
+                "Table {} stores {}-dimensional embeddings, configured for {}",
+                self.config.table, declared, self.config.dimension
+            )));
+        }
+        Ok(())
+    }
+
+    /// Applies any pending schema migrations and returns the resulting schema version.
+    ///
+    /// Tables created by earlier crate versions (before migrations were tracked)
+    /// are picked up safely because every step is idempotent. Concurrent callers
+    /// are serialized with an advisory lock, so it is safe to call on every start.
+    pub async fn run_migrations(&self) -> Result<i64> {
+        sqlx::query(&self.config.render(
             r#"
-            CREATE TABLE IF NOT EXISTS memories (
-                id UUID PRIMARY KEY,
-                session_id TEXT NOT NULL,
-                role TEXT NOT NULL,
-                content TEXT NOT NULL,
-                importance REAL NOT NULL,
-                timestamp TIMESTAMPTZ NOT NULL,
-                metadata JSONB,
-                embedding vector(384)
-            );
-            
-            CREATE INDEX IF NOT EXISTS idx_memories_session ON memories(session_id);
-            CREATE INDEX IF NOT EXISTS idx_memories_timestamp ON memories(timestamp DESC);
+            CREATE TABLE IF NOT EXISTS {table}_schema_migrations (
+                version BIGINT PRIMARY KEY,
+                description TEXT NOT NULL,
+                applied_at TIMESTAMPTZ NOT NULL DEFAULT now()
+            )
             "#,
+        ))
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            AgentError::MemoryError(format!("Failed to create migrations table: {}", e))
+        })?;
+
+        let mut current = self.schema_version().await?;
+        for &(version, description, sql) in MIGRATIONS {
+            if version <= current {
+                continue;
+            }
+
+            let mut tx = self.pool.begin().await.map_err(|e| {
+                AgentError::MemoryError(format!("Failed to start migration: {}", e))
+            })?;
+
+            sqlx::query("SELECT pg_advisory_xact_lock($1)")
+                .bind(MIGRATION_LOCK_KEY)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    AgentError::MemoryError(format!("Failed to lock migrations: {}", e))
+                })?;
+
+            // Another process may have applied it while we waited for the lock
+            let applied: Option<(i64,)> = sqlx::query_as(
+                &self
+                    .config
+                    .render("SELECT version FROM {table}_schema_migrations WHERE version = $1"),
+            )
+            .bind(version)
+            .fetch_optional(&mut *tx)
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to read migrations: {}", e)))?;
+
+            if applied.is_none() {
+                let sql = self.config.render(sql);
+                sqlx::raw_sql(&sql).execute(&mut *tx).await.map_err(|e| {
+                    AgentError::MemoryError(format!(
+                        "Migration {} ({}) failed: {}",
+                        version, description, e
+                    ))
+                })?;
+
+                sqlx::query(&self.config.render(
+                    "INSERT INTO {table}_schema_migrations (version, description) VALUES ($1, $2)",
+                ))
+                .bind(version)
+                .bind(description)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| {
+                    AgentError::MemoryError(format!("Failed to record migration: {}", e))
+                })?;
+            }
+
+            tx.commit().await.map_err(|e| {
+                AgentError::MemoryError(format!("Failed to commit migration: {}", e))
+            })?;
+
+            current = version;
+        }
+
+        Ok(current)
+    }
+
+    /// Returns the highest applied schema migration, or 0 for an unmigrated database
+    pub async fn schema_version(&self) -> Result<i64> {
+        let (version,): (Option<i64>,) = sqlx::query_as(
+            &self
+                .config
+                .render("SELECT MAX(version) FROM {table}_schema_migrations"),
         )
-        .execute(&pool)
+        .fetch_one(&self.pool)
         .await
-        .map_err(|e| AgentError::MemoryError(format!("Failed to create table: {}", e)))?;
+        .map_err(|e| AgentError::MemoryError(format!("Failed to read schema version: {}", e)))?;
 
-        Ok(Self { pool })
+        Ok(version.unwrap_or(0))
     }
 
-    /// Create embedding index for faster searches
+    /// Create embedding index for faster searches, using the operator class
+    /// of the configured metric
     pub async fn create_embedding_index(&self) -> Result<()> {
-        sqlx::query(
+        let sql = self.config.render(
             r#"
-            CREATE INDEX IF NOT EXISTS idx_memories_embedding 
-            ON memories USING ivfflat (embedding vector_cosine_ops)
+            CREATE INDEX IF NOT EXISTS idx_{table}_embedding
+            ON {table} USING ivfflat (embedding {ops})
             WITH (lists = 100);
             "#,
-        )
-        .execute(&self.pool)
-        .await
-        .map_err(|e| AgentError::MemoryError(format!("Failed to create index: {}", e)))?;
+        );
+        sqlx::query(&sql.replace("{ops}", self.config.operator_class()))
+            .execute(&self.pool)
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to create index: {}", e)))?;
 
         Ok(())
     }
@@ -61,24 +439,19 @@ impl PostgresStore {
 #[async_trait]
 impl MemoryStore for PostgresStore {
     async fn store(&self, record: MemoryRecord) -> Result<()> {
+        if let Some(embedding) = &record.embedding {
+            self.config.check_embedding(embedding)?;
+        }
         let embedding_vec: Option<Vec<f32>> = record.embedding;
         let metadata_json = record
             .metadata
             .as_ref()
-            .map(|m| serde_json::to_value(m).ok())
-            .flatten();
+            .and_then(|m| serde_json::to_value(m).ok());
 
-        sqlx::query(
-            r#"
-            INSERT INTO memories (id, session_id, role, content, importance, timestamp, metadata, embedding)
-            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
-            ON CONFLICT (id) DO UPDATE SET
-                content = EXCLUDED.content,
-                importance = EXCLUDED.importance,
-                metadata = EXCLUDED.metadata,
-                embedding = EXCLUDED.embedding
-            "#,
-        )
+        sqlx::query(&format!(
+            "INSERT INTO {} AS existing ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10){}",
+            self.config.table, SELECT_COLUMNS, ON_CONFLICT_UPDATE
+        ))
         .bind(record.id)
         .bind(&record.session_id)
         .bind(&record.role)
@@ -87,6 +460,8 @@ impl MemoryStore for PostgresStore {
         .bind(record.timestamp)
         .bind(metadata_json)
         .bind(embedding_vec)
+        .bind(record.version as i64)
+        .bind(record.expires_at)
         .execute(&self.pool)
         .await
         .map_err(|e| AgentError::MemoryError(format!("Failed to store memory: {}", e)))?;
@@ -94,49 +469,131 @@ impl MemoryStore for PostgresStore {
         Ok(())
     }
 
-    async fn retrieve(&self, session_id: &str, limit: usize) -> Result<Vec<MemoryRecord>> {
-        let records = sqlx::query_as::<
-            _,
-            (
-                uuid::Uuid,
-                String,
-                String,
-                String,
-                f32,
-                chrono::DateTime<chrono::Utc>,
-                Option<serde_json::Value>,
-                Option<Vec<f32>>,
-            ),
-        >(
-            r#"SELECT id, session_id, role, content, importance, timestamp, metadata, embedding
-               FROM memories
-               WHERE session_id = $1
-               ORDER BY timestamp DESC
-               LIMIT $2"#,
-        )
-        .bind(session_id)
-        .bind(limit as i64)
-        .fetch_all(&self.pool)
-        .await
-        .map_err(|e| AgentError::MemoryError(format!("Failed to retrieve memories: {}", e)))?;
+    async fn store_batch(&self, records: Vec<MemoryRecord>) -> Result<()> {
+        for embedding in records.iter().filter_map(|r| r.embedding.as_ref()) {
+            self.config.check_embedding(embedding)?;
+        }
 
-        Ok(records
+        // One statement can't upsert the same row twice; the last write wins
+        let mut seen = HashSet::new();
+        let mut records: Vec<MemoryRecord> = records
             .into_iter()
-            .map(
-                |(id, session_id, role, content, importance, timestamp, metadata, embedding)| {
-                    MemoryRecord {
-                        id,
-                        session_id,
-                        role,
-                        content,
-                        importance,
-                        timestamp,
-                        metadata: metadata.and_then(|v| serde_json::from_value(v).ok()),
-                        embedding,
-                    }
-                },
-            )
-            .collect())
+            .rev()
+            .filter(|r| seen.insert(r.id))
+            .collect();
+        records.reverse();
+
+        let mut tx = self
+            .pool
+            .begin()
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to start batch: {}", e)))?;
+
+        for chunk in records.chunks(BATCH_CHUNK_SIZE) {
+            let mut query = QueryBuilder::<Postgres>::new(format!(
+                "INSERT INTO {} AS existing ({}) ",
+                self.config.table, SELECT_COLUMNS
+            ));
+            query.push_values(chunk, |mut row, record| {
+                row.push_bind(record.id)
+                    .push_bind(&record.session_id)
+                    .push_bind(&record.role)
+                    .push_bind(&record.content)
+                    .push_bind(record.importance)
+                    .push_bind(record.timestamp)
+                    .push_bind(
+                        record
+                            .metadata
+                            .as_ref()
+                            .and_then(|m| serde_json::to_value(m).ok()),
+                    )
+                    .push_bind(&record.embedding)
+                    .push_bind(record.version as i64)
+                    .push_bind(record.expires_at);
+            });
+            query.push(ON_CONFLICT_UPDATE);
+
+            query
+                .build()
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| AgentError::MemoryError(format!("Failed to store memories: {}", e)))?;
+        }
+
+        tx.commit()
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to commit batch: {}", e)))?;
+
+        Ok(())
+    }
+
+    async fn retrieve(&self, session_id: &str, limit: usize) -> Result<Vec<MemoryRecord>> {
+        self.retrieve_filtered(session_id, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn retrieve_filtered(
+        &self,
+        session_id: &str,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        let mut query = QueryBuilder::<Postgres>::new(format!(
+            "SELECT {} FROM {} WHERE session_id = ",
+            SELECT_COLUMNS, self.config.table
+        ));
+        query.push_bind(session_id);
+        query.push(NOT_EXPIRED);
+        push_filter(&mut query, filter);
+        query.push(" ORDER BY timestamp DESC LIMIT ");
+        query.push_bind(limit as i64);
+
+        let records = query
+            .build_query_as::<MemoryRow>()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to retrieve memories: {}", e)))?;
+
+        Ok(records.into_iter().map(row_to_memory_record).collect())
+    }
+
+    async fn retrieve_page(
+        &self,
+        session_id: &str,
+        cursor: Option<&str>,
+        page_size: usize,
+    ) -> Result<MemoryPage> {
+        let mut query = QueryBuilder::<Postgres>::new(format!(
+            "SELECT {} FROM {} WHERE session_id = ",
+            SELECT_COLUMNS, self.config.table
+        ));
+        query.push_bind(session_id);
+        query.push(NOT_EXPIRED);
+        if let Some(cursor) = cursor {
+            let (timestamp, id) = decode_cursor(cursor)?;
+            query.push(" AND (timestamp, id) < (");
+            query.push_bind(timestamp);
+            query.push(", ");
+            query.push_bind(id);
+            query.push(")");
+        }
+        query.push(" ORDER BY timestamp DESC, id DESC LIMIT ");
+        query.push_bind(page_size as i64 + 1);
+
+        let records = query
+            .build_query_as::<MemoryRow>()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to retrieve memories: {}", e)))?;
+
+        Ok(into_page(
+            records.into_iter().map(row_to_memory_record).collect(),
+            page_size,
+        ))
+    }
+
+    fn export<'a>(&'a self, session_id: &'a str) -> BoxStream<'a, Result<MemoryRecord>> {
+        export_pages(self, session_id)
     }
 
     async fn search(
@@ -145,49 +602,126 @@ impl MemoryStore for PostgresStore {
         query_embedding: Vec<f32>,
         limit: usize,
     ) -> Result<Vec<MemoryRecord>> {
-        let records = sqlx::query_as::<
-            _,
-            (
-                uuid::Uuid,
-                String,
-                String,
-                String,
-                f32,
-                chrono::DateTime<chrono::Utc>,
-                Option<serde_json::Value>,
-                Option<Vec<f32>>,
-            ),
-        >(
-            r#"SELECT id, session_id, role, content, importance, timestamp, metadata, embedding
-               FROM memories
-               WHERE session_id = $1 AND embedding IS NOT NULL
-               ORDER BY embedding <=> $2
-               LIMIT $3"#,
+        self.search_filtered(session_id, query_embedding, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn search_filtered(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.config.check_embedding(&query_embedding)?;
+        let mut query = QueryBuilder::<Postgres>::new(format!(
+            "SELECT {} FROM {} WHERE embedding IS NOT NULL AND session_id = ",
+            SELECT_COLUMNS, self.config.table
+        ));
+        query.push_bind(session_id);
+        query.push(NOT_EXPIRED);
+        push_filter(&mut query, filter);
+        query.push(format!(
+            " ORDER BY embedding {} ",
+            self.config.distance_operator()
+        ));
+        query.push_bind(query_embedding);
+        query.push(" LIMIT ");
+        query.push_bind(limit as i64);
+
+        let records = query
+            .build_query_as::<MemoryRow>()
+            .fetch_all(&self.pool)
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to search memories: {}", e)))?;
+
+        Ok(records.into_iter().map(row_to_memory_record).collect())
+    }
+
+    async fn get(&self, session_id: &str, id: uuid::Uuid) -> Result<Option<MemoryRecord>> {
+        let mut query = QueryBuilder::<Postgres>::new(format!(
+            "SELECT {} FROM {} WHERE session_id = ",
+            SELECT_COLUMNS, self.config.table
+        ));
+        query.push_bind(session_id);
+        query.push(" AND id = ");
+        query.push_bind(id);
+        query.push(NOT_EXPIRED);
+
+        let record = query
+            .build_query_as::<MemoryRow>()
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to get memory: {}", e)))?;
+
+        Ok(record.map(row_to_memory_record))
+    }
+
+    async fn update(&self, record: MemoryRecord) -> Result<u64> {
+        if let Some(embedding) = &record.embedding {
+            self.config.check_embedding(embedding)?;
+        }
+        let metadata_json = record
+            .metadata
+            .as_ref()
+            .and_then(|m| serde_json::to_value(m).ok());
+
+        // The version predicate makes the write a single atomic compare-and-swap
+        let updated = sqlx::query_as::<_, (i64,)>(&self.config.render(
+            r#"
+            UPDATE {table} SET
+                content = $2,
+                importance = $3,
+                metadata = $4,
+                embedding = $5,
+                expires_at = $7,
+                version = version + 1
+            WHERE id = $1 AND version = $6
+            RETURNING version
+            "#,
+        ))
+        .bind(record.id)
+        .bind(&record.content)
+        .bind(record.importance)
+        .bind(metadata_json)
+        .bind(&record.embedding)
+        .bind(record.version as i64)
+        .bind(record.expires_at)
+        .fetch_optional(&self.pool)
+        .await
+        .map_err(|e| AgentError::MemoryError(format!("Failed to update memory: {}", e)))?;
+
+        if let Some((version,)) = updated {
+            return Ok(version as u64);
+        }
+
+        let current = sqlx::query_as::<_, (i64,)>(
+            &self
+                .config
+                .render("SELECT version FROM {table} WHERE id = $1"),
         )
-        .bind(session_id)
-        .bind(&query_embedding)
-        .bind(limit as i64)
-        .fetch_all(&self.pool)
+        .bind(record.id)
+        .fetch_optional(&self.pool)
         .await
-        .map_err(|e| AgentError::MemoryError(format!("Failed to search memories: {}", e)))?;
+        .map_err(|e| AgentError::MemoryError(format!("Failed to read version: {}", e)))?;
 
-        Ok(records
-            .into_iter()
-            .map(
-                |(id, session_id, role, content, importance, timestamp, metadata, embedding)| {
-                    MemoryRecord {
-                        id,
-                        session_id,
-                        role,
-                        content,
-                        importance,
-                        timestamp,
-                        metadata: metadata.and_then(|v| serde_json::from_value(v).ok()),
-                        embedding,
-                    }
-                },
-            )
-            .collect())
+        match current {
+            Some((actual,)) => Err(version_conflict(record.id, record.version, actual as u64)),
+            None => Err(record_not_found(record.id)),
+        }
+    }
+
+    async fn purge_expired(&self) -> Result<usize> {
+        let result = sqlx::query(
+            &self
+                .config
+                .render("DELETE FROM {table} WHERE expires_at <= now()"),
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AgentError::MemoryError(format!("Failed to purge memories: {}", e)))?;
+
+        Ok(result.rows_affected() as usize)
     }
 
     async fn flush(&self) -> Result<()> {
@@ -195,3 +729,82 @@ impl MemoryStore for PostgresStore {
         Ok(())
     }
 }
+
+/// Appends `AND ...` predicates for every condition set on `filter`
+fn push_filter(query: &mut QueryBuilder<'_, Postgres>, filter: &MemoryFilter) {
+    if !filter.metadata.is_empty() {
+        query.push(" AND metadata @> ");
+        query.push_bind(serde_json::json!(filter.metadata));
+    }
+    if let Some(role) = &filter.role {
+        query.push(" AND role = ");
+        query.push_bind(role.clone());
+    }
+    if let Some(since) = filter.since {
+        query.push(" AND timestamp >= ");
+        query.push_bind(since);
+    }
+    if let Some(until) = filter.until {
+        query.push(" AND timestamp < ");
+        query.push_bind(until);
+    }
+    if let Some(min_importance) = filter.min_importance {
+        query.push(" AND importance >= ");
+        query.push_bind(min_importance);
+    }
+}
+
+fn row_to_memory_record(row: MemoryRow) -> MemoryRecord {
+    let (
+        id,
+        session_id,
+        role,
+        content,
+        importance,
+        timestamp,
+        metadata,
+        embedding,
+        version,
+        expires_at,
+    ) = row;
+    MemoryRecord {
+        id,
+        session_id,
+        role,
+        content,
+        importance,
+        timestamp,
+        metadata: metadata.and_then(|v| serde_json::from_value(v).ok()),
+        embedding,
+        version: version as u64,
+        expires_at,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_renders_migrations_and_validates() {
+        let config = PostgresConfig::new()
+            .with_table("agent_memories")
+            .with_dimension(1536);
+        assert!(config.validate().is_ok());
+
+        let sql = config.render(MIGRATIONS[0].2);
+        assert!(sql.contains("CREATE TABLE IF NOT EXISTS agent_memories ("));
+        assert!(sql.contains("embedding vector(1536)"));
+        assert!(sql.contains("idx_agent_memories_session ON agent_memories(session_id)"));
+
+        assert!(PostgresConfig::new()
+            .with_table("memories; DROP TABLE users")
+            .validate()
+            .is_err());
+        assert!(config.check_embedding(&[0.0; 1536]).is_ok());
+        assert!(matches!(
+            config.check_embedding(&[0.0; 384]),
+            Err(AgentError::MemoryError(_))
+        ));
+    }
+}
@@ -1,97 +1,350 @@
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use qdrant_client::qdrant::vector_output::Vector;
+use qdrant_client::qdrant::vectors_config::Config;
 use qdrant_client::qdrant::{
-    Condition, CreateCollection, Filter, PointStruct, SearchPoints, UpsertPoints, VectorParams,
-    VectorsConfig,
+    Condition, CountPointsBuilder, CreateCollection, CreateFieldIndexCollectionBuilder,
+    DatetimeRange, DeletePointsBuilder, Direction, Distance, FieldType, Filter, GetPointsBuilder,
+    OrderBy, PointId, PointStruct, Range, ScrollPointsBuilder, SearchPoints,
+    SetPayloadPointsBuilder, UpdateMode, UpsertPoints, UpsertPointsBuilder, VectorParams,
+    VectorParamsMap, Vectors, VectorsConfig, VectorsOutput,
 };
 use qdrant_client::{Payload, Qdrant};
+use uuid::Uuid;
 
 use crate::error::{AgentError, Result};
-use crate::memory::{MemoryRecord, MemoryStore};
+use crate::memory::{
+    decode_cursor, export_pages, into_page, record_not_found, version_conflict, MemoryFilter,
+    MemoryPage, MemoryRecord, MemoryStore, SimilarityMetric,
+};
+
+/// Points copied per page by [`QdrantStore::reindex_to`]
+const REINDEX_BATCH_SIZE: u32 = 256;
+
+/// Vector layout of a Qdrant collection
+#[derive(Debug, Clone, PartialEq)]
+pub struct QdrantConfig {
+    dimension: u64,
+    distance: Distance,
+    vector_name: Option<String>,
+}
+
+impl Default for QdrantConfig {
+    /// 384-dimensional cosine vectors, matching the default `FastEmbedder`
+    fn default() -> Self {
+        Self {
+            dimension: 384,
+            distance: Distance::Cosine,
+            vector_name: None,
+        }
+    }
+}
+
+impl QdrantConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the embedding dimension
+    pub fn with_dimension(mut self, dimension: u64) -> Self {
+        self.dimension = dimension;
+        self
+    }
+
+    /// Sets the distance metric used for similarity search
+    pub fn with_distance(mut self, distance: Distance) -> Self {
+        self.distance = distance;
+        self
+    }
+
+    /// Sets the distance metric from a store-agnostic [`SimilarityMetric`]
+    pub fn with_metric(self, metric: SimilarityMetric) -> Self {
+        self.with_distance(match metric {
+            SimilarityMetric::Cosine => Distance::Cosine,
+            SimilarityMetric::Dot => Distance::Dot,
+            SimilarityMetric::Euclidean => Distance::Euclid,
+        })
+    }
+
+    /// Stores embeddings under a named vector instead of the default one
+    pub fn with_vector_name(mut self, name: impl Into<String>) -> Self {
+        self.vector_name = Some(name.into());
+        self
+    }
+
+    /// Returns the embedding dimension
+    pub fn dimension(&self) -> u64 {
+        self.dimension
+    }
+
+    fn params(&self) -> VectorParams {
+        VectorParams {
+            size: self.dimension,
+            distance: self.distance.into(),
+            ..Default::default()
+        }
+    }
+
+    fn vectors_config(&self) -> VectorsConfig {
+        let config = match &self.vector_name {
+            Some(name) => Config::ParamsMap(VectorParamsMap {
+                map: [(name.clone(), self.params())].into(),
+            }),
+            None => Config::Params(self.params()),
+        };
+        VectorsConfig {
+            config: Some(config),
+        }
+    }
+
+    /// Fails unless an existing collection stores vectors in this layout
+    fn check_collection(&self, collection_name: &str, existing: &VectorsConfig) -> Result<()> {
+        let params = match (&existing.config, &self.vector_name) {
+            (Some(Config::Params(params)), None) => Some(params),
+            (Some(Config::ParamsMap(map)), Some(name)) => map.map.get(name),
+            _ => None,
+        };
+        let Some(params) = params else {
+            return Err(AgentError::ConfigError(format!(
+                "Collection {} has no {} vector",
+                collection_name,
+                self.vector_name.as_deref().unwrap_or("default")
+            )));
+        };
+
+        if params.size != self.dimension || params.distance != i32::from(self.distance) {
+            return Err(AgentError::ConfigError(format!(
+                "Collection {} stores {}-dimensional {:?} vectors, configured for {}-dimensional {:?}",
+                collection_name,
+                params.size,
+                Distance::try_from(params.distance).unwrap_or(Distance::UnknownDistance),
+                self.dimension,
+                self.distance
+            )));
+        }
+        Ok(())
+    }
+
+    /// Fails if `embedding` doesn't have the configured dimension
+    fn check_embedding(&self, embedding: &[f32]) -> Result<()> {
+        if embedding.len() as u64 == self.dimension {
+            Ok(())
+        } else {
+            Err(AgentError::MemoryError(format!(
+                "Embedding has {} dimensions, collection expects {}",
+                embedding.len(),
+                self.dimension
+            )))
+        }
+    }
+
+    fn vectors(&self, embedding: Vec<f32>) -> Vectors {
+        match &self.vector_name {
+            Some(name) => std::collections::HashMap::from([(name.clone(), embedding)]).into(),
+            None => embedding.into(),
+        }
+    }
+
+    fn dense_vector(&self, vectors: Option<&VectorsOutput>) -> Option<Vec<f32>> {
+        let vector = match &self.vector_name {
+            Some(name) => vectors?.get_vector_by_name(name),
+            None => vectors?.get_vector(),
+        };
+        match vector? {
+            Vector::Dense(dense) => Some(dense.data),
+            _ => None,
+        }
+    }
+}
 
 /// Qdrant vector database memory store
 pub struct QdrantStore {
     client: Qdrant,
     collection_name: String,
+    config: QdrantConfig,
 }
 
 impl QdrantStore {
-    /// Creates a new Qdrant store
+    /// Creates a new Qdrant store with the default vector layout
     pub async fn new(url: &str, collection_name: impl Into<String>) -> Result<Self> {
+        Self::with_config(url, collection_name, QdrantConfig::default()).await
+    }
+
+    /// Creates a Qdrant store with the given vector layout.
+    ///
+    /// A missing collection is created with `config`; an existing one must
+    /// already use the same dimension, distance and vector name.
+    pub async fn with_config(
+        url: &str,
+        collection_name: impl Into<String>,
+        config: QdrantConfig,
+    ) -> Result<Self> {
         let client = Qdrant::from_url(url)
             .build()
             .map_err(|e| AgentError::MemoryError(format!("Failed to connect to Qdrant: {}", e)))?;
 
         let collection_name = collection_name.into();
 
-        // Create collection if it doesn't exist
-        let collections = client
-            .list_collections()
-            .await
-            .map_err(|e| AgentError::MemoryError(format!("Failed to list collections: {}", e)))?;
-
-        let exists = collections
-            .collections
-            .iter()
-            .any(|c| c.name == collection_name);
-
-        if !exists {
-            client
-                .create_collection(CreateCollection {
-                    collection_name: collection_name.clone(),
-                    vectors_config: Some(VectorsConfig {
-                        config: Some(qdrant_client::qdrant::vectors_config::Config::Params(
-                            VectorParams {
-                                size: 384, // Default embedding size, can be configured
-                                distance: qdrant_client::qdrant::Distance::Cosine.into(),
-                                ..Default::default()
-                            },
-                        )),
-                    }),
-                    ..Default::default()
-                })
-                .await
-                .map_err(|e| {
-                    AgentError::MemoryError(format!("Failed to create collection: {}", e))
-                })?;
-        }
+        ensure_collection(&client, &collection_name, config.vectors_config()).await?;
+        let existing = collection_vectors_config(&client, &collection_name)
+            .await?
+            .unwrap_or_else(|| config.vectors_config());
+        config.check_collection(&collection_name, &existing)?;
 
         Ok(Self {
             client,
             collection_name,
+            config,
         })
     }
 
-    /// Set embedding dimension
-    pub async fn with_dimension(self, _dim: u64) -> Result<Self> {
-        // Recreate collection with new dimension if needed
-        Ok(self)
+    /// Returns the vector layout of this store
+    pub fn config(&self) -> &QdrantConfig {
+        &self.config
     }
+
+    /// Copies every memory into `new_collection`, rewriting payloads in the
+    /// current layout, and returns the number of points copied.
+    ///
+    /// The target collection is created with the source's vector configuration
+    /// if it doesn't exist yet. The source collection is left untouched so
+    /// callers can switch over (e.g. via a collection alias) once it succeeds.
+    pub async fn reindex_to(&self, new_collection: impl Into<String>) -> Result<u64> {
+        let new_collection = new_collection.into();
+        if new_collection == self.collection_name {
+            return Err(AgentError::MemoryError(
+                "Cannot reindex a collection into itself".to_string(),
+            ));
+        }
+
+        let vectors_config = collection_vectors_config(&self.client, &self.collection_name)
+            .await?
+            .unwrap_or_else(|| self.config.vectors_config());
+
+        ensure_collection(&self.client, &new_collection, vectors_config).await?;
+
+        let mut copied = 0u64;
+        let mut offset: Option<PointId> = None;
+        loop {
+            let mut request = ScrollPointsBuilder::new(&self.collection_name)
+                .limit(REINDEX_BATCH_SIZE)
+                .with_payload(true)
+                .with_vectors(true);
+            if let Some(offset) = offset.take() {
+                request = request.offset(offset);
+            }
+
+            let page =
+                self.client.scroll(request).await.map_err(|e| {
+                    AgentError::MemoryError(format!("Failed to scroll points: {}", e))
+                })?;
+
+            let mut points = Vec::with_capacity(page.result.len());
+            for point in page.result {
+                let embedding = match self.config.dense_vector(point.vectors.as_ref()) {
+                    Some(embedding) => embedding,
+                    None => {
+                        tracing::warn!(id = ?point.id, "Skipping point without a dense vector");
+                        continue;
+                    }
+                };
+
+                match payload_to_memory_record(point.payload) {
+                    Ok(record) => points.push(PointStruct::new(
+                        record.id.to_string(),
+                        self.config.vectors(embedding),
+                        memory_record_payload(&record)?,
+                    )),
+                    Err(e) => tracing::warn!(id = ?point.id, "Skipping unreadable point: {}", e),
+                }
+            }
+
+            if !points.is_empty() {
+                copied += points.len() as u64;
+                self.client
+                    .upsert_points(UpsertPointsBuilder::new(&new_collection, points).wait(true))
+                    .await
+                    .map_err(|e| {
+                        AgentError::MemoryError(format!("Failed to upsert points: {}", e))
+                    })?;
+            }
+
+            match page.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+
+        Ok(copied)
+    }
+
+    /// Reads every unexpired record of `session_id` timestamped from `start`
+    /// until `end`, in no particular order
+    async fn scan_between(
+        &self,
+        session_id: &str,
+        start: DateTime<Utc>,
+        end: DateTime<Utc>,
+    ) -> Result<Vec<MemoryRecord>> {
+        let filter = memory_filter(
+            session_id,
+            &MemoryFilter::new().with_since(start).with_until(end),
+        );
+        let mut records = Vec::new();
+        let mut offset: Option<PointId> = None;
+        loop {
+            let mut request = ScrollPointsBuilder::new(&self.collection_name)
+                .filter(filter.clone())
+                .limit(REINDEX_BATCH_SIZE)
+                .with_payload(true);
+            if let Some(offset) = offset.take() {
+                request = request.offset(offset);
+            }
+            let page =
+                self.client.scroll(request).await.map_err(|e| {
+                    AgentError::MemoryError(format!("Failed to scroll points: {}", e))
+                })?;
+            for point in page.result {
+                records.push(payload_to_memory_record(point.payload)?);
+            }
+            match page.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => return Ok(records),
+            }
+        }
+    }
+}
+
+/// Returns the microsecond `timestamp` falls in, as a half-open range
+fn microsecond_of(timestamp: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let start = timestamp
+        - chrono::Duration::nanoseconds((timestamp.timestamp_subsec_nanos() % 1000) as i64);
+    (start, start + chrono::Duration::microseconds(1))
+}
+
+/// Orders `records` newest first by (timestamp, id) and returns the page
+/// following the `after` position
+fn keyset_page(
+    mut records: Vec<MemoryRecord>,
+    after: Option<(DateTime<Utc>, Uuid)>,
+    page_size: usize,
+) -> MemoryPage {
+    records.retain(|record| after.is_none_or(|position| (record.timestamp, record.id) < position));
+    records.sort_by_key(|record| std::cmp::Reverse((record.timestamp, record.id)));
+    records.truncate(page_size + 1);
+    into_page(records, page_size)
 }
 
 #[async_trait]
 impl MemoryStore for QdrantStore {
     async fn store(&self, record: MemoryRecord) -> Result<()> {
         if let Some(embedding) = &record.embedding {
-            let mut payload = serde_json::json!({
-                "id": record.id.to_string(),
-                "session_id": record.session_id,
-                "role": record.role,
-                "content": record.content,
-                "importance": record.importance,
-                "timestamp": record.timestamp.to_rfc3339(),
-            });
-
-            if let Some(metadata) = &record.metadata {
-                payload["metadata"] = serde_json::to_value(metadata)
-                    .map_err(|e| AgentError::SerializationError(e))?;
-            }
-
+            self.config.check_embedding(embedding)?;
             let point = PointStruct::new(
                 record.id.to_string(),
-                embedding.clone(),
-                Payload::try_from(payload).map_err(|e| {
-                    AgentError::MemoryError(format!("Failed to convert payload: {:?}", e))
-                })?,
+                self.config.vectors(embedding.clone()),
+                memory_record_payload(&record)?,
             );
 
             self.client
@@ -109,33 +362,72 @@ impl MemoryStore for QdrantStore {
     }
 
     async fn retrieve(&self, session_id: &str, limit: usize) -> Result<Vec<MemoryRecord>> {
-        // Qdrant doesn't support direct filtering without vector search
-        // We'll use a dummy search with high limit
-        let dummy_vector = vec![0.0; 384]; // Adjust dimension as needed
+        self.retrieve_filtered(session_id, limit, &MemoryFilter::default())
+            .await
+    }
 
-        let search_result = self
+    async fn retrieve_filtered(
+        &self,
+        session_id: &str,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        // Scroll in timestamp order; the datetime index from
+        // `ensure_collection` makes this an indexed read, not a vector search.
+        let page = self
             .client
-            .search_points(SearchPoints {
-                collection_name: self.collection_name.clone(),
-                vector: dummy_vector,
-                limit: limit as u64,
-                with_payload: Some(true.into()),
-                filter: Some(
-                    Filter::must([Condition::matches("session_id", session_id.to_string())]).into(),
-                ),
-                ..Default::default()
-            })
+            .scroll(
+                ScrollPointsBuilder::new(&self.collection_name)
+                    .filter(memory_filter(session_id, filter))
+                    .order_by(OrderBy {
+                        key: "timestamp".to_string(),
+                        direction: Some(Direction::Desc.into()),
+                        start_from: None,
+                    })
+                    .limit(limit as u32)
+                    .with_payload(true),
+            )
             .await
-            .map_err(|e| AgentError::MemoryError(format!("Failed to search: {}", e)))?;
+            .map_err(|e| AgentError::MemoryError(format!("Failed to scroll points: {}", e)))?;
+
+        page.result
+            .into_iter()
+            .map(|point| payload_to_memory_record(point.payload))
+            .collect()
+    }
 
+    async fn retrieve_page(
+        &self,
+        session_id: &str,
+        cursor: Option<&str>,
+        page_size: usize,
+    ) -> Result<MemoryPage> {
+        // Ordered scrolls sort by timestamp alone and can't resume from a
+        // point id, so the (timestamp, id) keyset is rebuilt here: records
+        // sharing the cursor's or the page end's microsecond are read whole
+        // and ordered by id, the rest come from an ordered scroll before them.
+        let after = cursor.map(decode_cursor).transpose()?;
+        let limit = page_size + 1;
         let mut records = Vec::new();
-        for point in search_result.result {
-            if let Some(payload) = point.payload {
-                records.push(payload_to_memory_record(payload)?);
-            }
+        let mut older = MemoryFilter::new();
+        if let Some((timestamp, _)) = after {
+            let (start, end) = microsecond_of(timestamp);
+            records.extend(self.scan_between(session_id, start, end).await?);
+            older.until = Some(start);
         }
+        let mut scrolled = self.retrieve_filtered(session_id, limit, &older).await?;
+        if scrolled.len() == limit {
+            // The page may end partway through a microsecond
+            let (start, end) = microsecond_of(scrolled[limit - 1].timestamp);
+            scrolled.retain(|record| record.timestamp < start);
+            records.extend(self.scan_between(session_id, start, end).await?);
+        }
+        records.extend(scrolled);
+        Ok(keyset_page(records, after, page_size))
+    }
 
-        Ok(records)
+    fn export<'a>(&'a self, session_id: &'a str) -> BoxStream<'a, Result<MemoryRecord>> {
+        export_pages(self, session_id)
     }
 
     async fn search(
@@ -144,16 +436,27 @@ impl MemoryStore for QdrantStore {
         query_embedding: Vec<f32>,
         limit: usize,
     ) -> Result<Vec<MemoryRecord>> {
+        self.search_filtered(session_id, query_embedding, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn search_filtered(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.config.check_embedding(&query_embedding)?;
         let search_result = self
             .client
             .search_points(SearchPoints {
                 collection_name: self.collection_name.clone(),
                 vector: query_embedding,
+                vector_name: self.config.vector_name.clone(),
                 limit: limit as u64,
                 with_payload: Some(true.into()),
-                filter: Some(
-                    Filter::must([Condition::matches("session_id", session_id.to_string())]).into(),
-                ),
+                filter: Some(memory_filter(session_id, filter)),
                 ..Default::default()
             })
             .await
@@ -161,20 +464,288 @@ impl MemoryStore for QdrantStore {
 
         let mut records = Vec::new();
         for point in search_result.result {
-            if let Some(payload) = point.payload {
-                records.push(payload_to_memory_record(payload)?);
-            }
+            records.push(payload_to_memory_record(point.payload)?);
         }
 
         Ok(records)
     }
 
+    async fn get(&self, session_id: &str, id: Uuid) -> Result<Option<MemoryRecord>> {
+        let points = self
+            .client
+            .get_points(
+                GetPointsBuilder::new(&self.collection_name, vec![id.to_string().into()])
+                    .with_payload(true),
+            )
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to get point: {}", e)))?;
+
+        let Some(point) = points.result.into_iter().next() else {
+            return Ok(None);
+        };
+        let record = payload_to_memory_record(point.payload)?;
+        let visible = record.session_id == session_id && !record.is_expired_at(Utc::now());
+        Ok(visible.then_some(record))
+    }
+
+    async fn update(&self, record: MemoryRecord) -> Result<u64> {
+        let id = record.id;
+        let expected = record.version;
+
+        let mut next = record;
+        next.version = expected + 1;
+        let mut payload = memory_record_payload(&next)?;
+        // Qdrant doesn't report whether a filtered write matched, so tag the
+        // write and check for the tag on read-back; another writer landing
+        // the same version number doesn't count as ours.
+        let revision = uuid::Uuid::new_v4().to_string();
+        payload.insert("revision", revision.clone());
+
+        // Qdrant has no transactions; the version condition is evaluated
+        // server-side so a stale writer leaves the point untouched. Points
+        // written before versioning have no version and count as 0.
+        let mut version = Condition::matches("version", expected as i64);
+        if expected == 0 {
+            version = Filter::should([version, Condition::is_empty("version")]).into();
+        }
+        let guard = Filter::must([Condition::has_id([id.to_string()]), version]);
+
+        match &next.embedding {
+            Some(embedding) => {
+                self.config.check_embedding(embedding)?;
+                let point = PointStruct::new(
+                    id.to_string(),
+                    self.config.vectors(embedding.clone()),
+                    payload,
+                );
+                self.client
+                    .upsert_points(
+                        UpsertPointsBuilder::new(&self.collection_name, vec![point])
+                            .update_filter(guard)
+                            .update_mode(UpdateMode::UpdateOnly)
+                            .wait(true),
+                    )
+                    .await
+                    .map_err(|e| {
+                        AgentError::MemoryError(format!("Failed to update point: {}", e))
+                    })?;
+            }
+            None => {
+                self.client
+                    .set_payload(
+                        SetPayloadPointsBuilder::new(&self.collection_name, payload)
+                            .points_selector(guard)
+                            .wait(true),
+                    )
+                    .await
+                    .map_err(|e| {
+                        AgentError::MemoryError(format!("Failed to update payload: {}", e))
+                    })?;
+            }
+        }
+
+        // Read back to learn whether our conditional write was applied
+        let current = self
+            .client
+            .get_points(
+                GetPointsBuilder::new(&self.collection_name, vec![id.to_string().into()])
+                    .with_payload(true),
+            )
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to read point: {}", e)))?;
+
+        let point = current
+            .result
+            .into_iter()
+            .next()
+            .ok_or_else(|| record_not_found(id))?;
+        let actual = point
+            .payload
+            .get("version")
+            .and_then(|v| v.as_integer())
+            .unwrap_or(0) as u64;
+        let landed = point
+            .payload
+            .get("revision")
+            .and_then(|v| v.as_str())
+            .is_some_and(|tag| *tag == revision);
+
+        if landed {
+            Ok(actual)
+        } else {
+            Err(version_conflict(id, expected, actual))
+        }
+    }
+
+    async fn purge_expired(&self) -> Result<usize> {
+        let expired = Filter::must([expired_condition()]);
+
+        // Deletes don't report how many points matched, so count first
+        let count = self
+            .client
+            .count(
+                CountPointsBuilder::new(&self.collection_name)
+                    .filter(expired.clone())
+                    .exact(true),
+            )
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to count points: {}", e)))?
+            .result
+            .map_or(0, |result| result.count);
+
+        if count > 0 {
+            self.client
+                .delete_points(
+                    DeletePointsBuilder::new(&self.collection_name)
+                        .points(expired)
+                        .wait(true),
+                )
+                .await
+                .map_err(|e| AgentError::MemoryError(format!("Failed to delete points: {}", e)))?;
+        }
+
+        Ok(count as usize)
+    }
+
     async fn flush(&self) -> Result<()> {
         // Qdrant writes are immediate
         Ok(())
     }
 }
 
+/// Reads the vector configuration of an existing collection
+async fn collection_vectors_config(
+    client: &Qdrant,
+    collection_name: &str,
+) -> Result<Option<VectorsConfig>> {
+    let info = client
+        .collection_info(collection_name)
+        .await
+        .map_err(|e| AgentError::MemoryError(format!("Failed to read collection: {}", e)))?;
+    Ok(info
+        .result
+        .and_then(|info| info.config)
+        .and_then(|config| config.params)
+        .and_then(|params| params.vectors_config))
+}
+
+/// Creates `collection_name` with the given vector configuration unless it
+/// already exists, and makes sure its payload indexes are in place
+async fn ensure_collection(
+    client: &Qdrant,
+    collection_name: &str,
+    vectors_config: VectorsConfig,
+) -> Result<()> {
+    let exists = client
+        .collection_exists(collection_name)
+        .await
+        .map_err(|e| AgentError::MemoryError(format!("Failed to check collection: {}", e)))?;
+
+    if !exists {
+        client
+            .create_collection(CreateCollection {
+                collection_name: collection_name.to_string(),
+                vectors_config: Some(vectors_config),
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to create collection: {}", e)))?;
+    }
+
+    // Ordered scrolls in `retrieve_page` require a datetime index, and the
+    // expiry index keeps TTL filtering cheap; re-creating an index is a no-op.
+    for field in ["timestamp", "expires_at"] {
+        client
+            .create_field_index(CreateFieldIndexCollectionBuilder::new(
+                collection_name,
+                field,
+                FieldType::Datetime,
+            ))
+            .await
+            .map_err(|e| AgentError::MemoryError(format!("Failed to create index: {}", e)))?;
+    }
+
+    Ok(())
+}
+
+/// Matches points whose `expires_at` has passed; points without one never match
+fn expired_condition() -> Condition {
+    let now = chrono::Utc::now();
+    let mut range = DatetimeRange::default();
+    let lte = range.lte.insert(Default::default());
+    lte.seconds = now.timestamp();
+    lte.nanos = now.timestamp_subsec_nanos() as i32;
+    Condition::datetime_range("expires_at", range)
+}
+
+/// Translates a session scope plus [`MemoryFilter`] into a Qdrant payload filter
+fn memory_filter(session_id: &str, filter: &MemoryFilter) -> Filter {
+    let mut conditions = vec![Condition::matches("session_id", session_id.to_string())];
+
+    for (key, value) in &filter.metadata {
+        conditions.push(Condition::matches(
+            format!("metadata.{}", key),
+            value.clone(),
+        ));
+    }
+    if let Some(role) = &filter.role {
+        conditions.push(Condition::matches("role", role.clone()));
+    }
+    if filter.since.is_some() || filter.until.is_some() {
+        let mut range = DatetimeRange::default();
+        if let Some(since) = filter.since {
+            let gte = range.gte.insert(Default::default());
+            gte.seconds = since.timestamp();
+            gte.nanos = since.timestamp_subsec_nanos() as i32;
+        }
+        if let Some(until) = filter.until {
+            let lt = range.lt.insert(Default::default());
+            lt.seconds = until.timestamp();
+            lt.nanos = until.timestamp_subsec_nanos() as i32;
+        }
+        conditions.push(Condition::datetime_range("timestamp", range));
+    }
+    if let Some(min_importance) = filter.min_importance {
+        conditions.push(Condition::range(
+            "importance",
+            Range {
+                gte: Some(min_importance as f64),
+                ..Default::default()
+            },
+        ));
+    }
+
+    Filter {
+        must: conditions,
+        must_not: vec![expired_condition()],
+        ..Default::default()
+    }
+}
+
+fn memory_record_payload(record: &MemoryRecord) -> Result<Payload> {
+    let mut payload = serde_json::json!({
+        "id": record.id.to_string(),
+        "session_id": record.session_id,
+        "role": record.role,
+        "content": record.content,
+        "importance": record.importance,
+        "timestamp": record.timestamp.to_rfc3339(),
+        "version": record.version,
+    });
+
+    if let Some(metadata) = &record.metadata {
+        payload["metadata"] =
+            serde_json::to_value(metadata).map_err(AgentError::SerializationError)?;
+    }
+
+    if let Some(expires_at) = record.expires_at {
+        payload["expires_at"] = serde_json::json!(expires_at.to_rfc3339());
+    }
+
+    Payload::try_from(payload)
+        .map_err(|e| AgentError::MemoryError(format!("Failed to convert payload: {:?}", e)))
+}
+
 fn payload_to_memory_record(
     payload: std::collections::HashMap<String, qdrant_client::qdrant::Value>,
 ) -> Result<MemoryRecord> {
@@ -220,6 +791,17 @@ fn payload_to_memory_record(
             .and_then(|jv| serde_json::from_value(jv).ok())
     });
 
+    let version = payload
+        .get("version")
+        .and_then(|v| v.as_integer())
+        .unwrap_or(0) as u64;
+
+    let expires_at = payload
+        .get("expires_at")
+        .and_then(|v| v.as_str())
+        .and_then(|s| chrono::DateTime::parse_from_rfc3339(s).ok())
+        .map(|dt| dt.with_timezone(&chrono::Utc));
+
     Ok(MemoryRecord {
         id,
         session_id,
@@ -229,5 +811,72 @@ fn payload_to_memory_record(
         timestamp,
         metadata,
         embedding: None, // Qdrant stores embeddings separately
+        version,
+        expires_at,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_config_validates_collection_and_embeddings() {
+        let config = QdrantConfig::new()
+            .with_dimension(768)
+            .with_distance(Distance::Dot)
+            .with_vector_name("text");
+
+        assert!(config
+            .check_collection("memories", &config.vectors_config())
+            .is_ok());
+        let unnamed = QdrantConfig::new().with_dimension(768).vectors_config();
+        assert!(matches!(
+            config.check_collection("memories", &unnamed),
+            Err(AgentError::ConfigError(_))
+        ));
+        let cosine = QdrantConfig::new()
+            .with_dimension(768)
+            .with_vector_name("text")
+            .vectors_config();
+        assert!(config.check_collection("memories", &cosine).is_err());
+
+        assert!(config.check_embedding(&[0.0; 768]).is_ok());
+        assert!(matches!(
+            config.check_embedding(&[0.0; 384]),
+            Err(AgentError::MemoryError(_))
+        ));
+    }
+
+    #[test]
+    fn test_pages_break_timestamp_ties_by_id() {
+        let at = DateTime::parse_from_rfc3339("2024-05-01T12:00:00.000001500Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let (start, end) = microsecond_of(at);
+        assert_eq!(start.timestamp_subsec_nanos(), 1000);
+        assert_eq!(end.timestamp_subsec_nanos(), 2000);
+
+        let record = |id: u128| MemoryRecord {
+            id: Uuid::from_u128(id),
+            session_id: "s".to_string(),
+            role: "user".to_string(),
+            content: id.to_string(),
+            importance: 0.5,
+            timestamp: at,
+            metadata: None,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        };
+        let records = || (1..=5).map(record).collect::<Vec<_>>();
+        let first = keyset_page(records(), None, 2);
+        let ids: Vec<String> = first.records.iter().map(|r| r.content.clone()).collect();
+        assert_eq!(ids, ["5", "4"]);
+
+        let after = decode_cursor(first.next_cursor.as_deref().unwrap()).unwrap();
+        let second = keyset_page(records(), Some(after), 2);
+        let ids: Vec<String> = second.records.iter().map(|r| r.content.clone()).collect();
+        assert_eq!(ids, ["3", "2"]);
+    }
+}
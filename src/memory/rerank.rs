@@ -0,0 +1,319 @@
+//! Second-stage reranking of retrieved memories.
+//!
+//! Vector search is fast but coarse. A [`Reranker`] looks at the query and
+//! each candidate together and reorders the candidate set, so
+//! [`SessionMemory`](crate::memory::SessionMemory) can over-fetch from the
+//! store and keep only the best matches. [`CohereReranker`] calls the Cohere
+//! rerank API, [`LlmReranker`] asks a chat model, and with the `memory`
+//! feature `CrossEncoderReranker` runs a local cross-encoder.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::error::{AgentError, Result};
+use crate::memory::importance::parse_score;
+use crate::memory::MemoryRecord;
+use crate::models::LLM;
+use crate::types::{Message, Role};
+
+/// Reorders retrieved memories by relevance to a query
+#[async_trait]
+pub trait Reranker: Send + Sync {
+    /// Returns one relevance score per candidate, higher is more relevant
+    async fn score(&self, query: &str, candidates: &[MemoryRecord]) -> Result<Vec<f32>>;
+
+    /// Returns the `limit` most relevant candidates, best first
+    async fn rerank(
+        &self,
+        query: &str,
+        candidates: Vec<MemoryRecord>,
+        limit: usize,
+    ) -> Result<Vec<MemoryRecord>> {
+        if candidates.is_empty() {
+            return Ok(candidates);
+        }
+        let scores = self.score(query, &candidates).await?;
+        if scores.len() != candidates.len() {
+            return Err(AgentError::ModelError(format!(
+                "Reranker returned {} scores for {} candidates",
+                scores.len(),
+                candidates.len()
+            )));
+        }
+
+        let mut scored: Vec<(f32, MemoryRecord)> = scores.into_iter().zip(candidates).collect();
+        scored.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        Ok(scored
+            .into_iter()
+            .take(limit)
+            .map(|(_, record)| record)
+            .collect())
+    }
+}
+
+const DEFAULT_COHERE_ENDPOINT: &str = "https://api.cohere.com/v1/rerank";
+const DEFAULT_COHERE_MODEL: &str = "rerank-english-v3.0";
+
+#[derive(Deserialize)]
+struct CohereResponse {
+    results: Vec<CohereResult>,
+}
+
+#[derive(Deserialize)]
+struct CohereResult {
+    index: usize,
+    relevance_score: f32,
+}
+
+/// Reranker backed by the Cohere rerank API
+pub struct CohereReranker {
+    api_key: String,
+    model: String,
+    endpoint: String,
+    client: reqwest::Client,
+}
+
+impl CohereReranker {
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Self {
+            api_key: api_key.into(),
+            model: DEFAULT_COHERE_MODEL.to_string(),
+            endpoint: DEFAULT_COHERE_ENDPOINT.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Uses another rerank model, e.g. `rerank-multilingual-v3.0`
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = model.into();
+        self
+    }
+
+    /// Sends requests to a different endpoint, such as a proxy
+    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+}
+
+#[async_trait]
+impl Reranker for CohereReranker {
+    async fn score(&self, query: &str, candidates: &[MemoryRecord]) -> Result<Vec<f32>> {
+        let documents: Vec<&str> = candidates.iter().map(|r| r.content.as_str()).collect();
+        let body = serde_json::json!({
+            "model": self.model,
+            "query": query,
+            "documents": documents,
+        });
+
+        let response = self
+            .client
+            .post(&self.endpoint)
+            .bearer_auth(&self.api_key)
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| AgentError::ModelError(format!("Cohere rerank error: {}", e)))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AgentError::ModelError(format!(
+                "Cohere rerank error {}: {}",
+                status, text
+            )));
+        }
+
+        let parsed: CohereResponse = response
+            .json()
+            .await
+            .map_err(|e| AgentError::ModelError(format!("Failed to parse response: {}", e)))?;
+
+        // Results come back sorted by score; put them back in candidate order
+        let mut scores = vec![f32::NEG_INFINITY; candidates.len()];
+        for result in parsed.results {
+            if let Some(score) = scores.get_mut(result.index) {
+                *score = result.relevance_score;
+            }
+        }
+        Ok(scores)
+    }
+}
+
+const RELEVANCE_INSTRUCTIONS: &str = "Rate how relevant the following memory is to the query, \
+from 0.0 (unrelated) to 1.0 (directly answers or is needed to answer it). Reply with the \
+number only.";
+
+/// Reranker that asks a model to rate each candidate.
+///
+/// Candidates are rated concurrently, one request each; a reply without a
+/// number counts as irrelevant.
+pub struct LlmReranker {
+    model: Arc<dyn LLM>,
+}
+
+impl LlmReranker {
+    pub fn new(model: Arc<dyn LLM>) -> Self {
+        Self { model }
+    }
+
+    async fn rate(&self, query: &str, record: &MemoryRecord) -> Result<f32> {
+        let messages = vec![
+            Message {
+                role: Role::System,
+                content: RELEVANCE_INSTRUCTIONS.to_string(),
+                metadata: None,
+            },
+            Message {
+                role: Role::User,
+                content: format!(
+                    "Query: {}\n\nMemory ({}): {}",
+                    query, record.role, record.content
+                ),
+                metadata: None,
+            },
+        ];
+        let response = self.model.generate(messages, None).await?;
+        Ok(parse_score(&response.content).unwrap_or(0.0))
+    }
+}
+
+#[async_trait]
+impl Reranker for LlmReranker {
+    async fn score(&self, query: &str, candidates: &[MemoryRecord]) -> Result<Vec<f32>> {
+        futures::future::try_join_all(candidates.iter().map(|record| self.rate(query, record)))
+            .await
+    }
+}
+
+#[cfg(feature = "memory")]
+pub use self::cross_encoder::CrossEncoderReranker;
+
+#[cfg(feature = "memory")]
+mod cross_encoder {
+    use std::sync::Arc;
+
+    use async_trait::async_trait;
+    use fastembed::{RerankInitOptions, RerankerModel, TextRerank};
+
+    use super::Reranker;
+    use crate::error::{AgentError, Result};
+    use crate::memory::MemoryRecord;
+
+    /// Local ONNX cross-encoder via fastembed.
+    ///
+    /// Defaults to BAAI/bge-reranker-base.
+    pub struct CrossEncoderReranker {
+        model: Arc<TextRerank>,
+    }
+
+    impl CrossEncoderReranker {
+        /// Loads the default model, downloading it on first use
+        pub fn new() -> Result<Self> {
+            Self::with_model(RerankerModel::BGERerankerBase)
+        }
+
+        /// Loads the given fastembed reranker model
+        pub fn with_model(model: RerankerModel) -> Result<Self> {
+            let model = TextRerank::try_new(RerankInitOptions::new(model))
+                .map_err(|e| AgentError::ConfigError(format!("Reranker model: {}", e)))?;
+            Ok(Self {
+                model: Arc::new(model),
+            })
+        }
+    }
+
+    #[async_trait]
+    impl Reranker for CrossEncoderReranker {
+        async fn score(&self, query: &str, candidates: &[MemoryRecord]) -> Result<Vec<f32>> {
+            // Inference is CPU-bound; keep it off the async workers
+            let model = Arc::clone(&self.model);
+            let query = query.to_string();
+            let documents: Vec<String> = candidates.iter().map(|r| r.content.clone()).collect();
+            let results =
+                tokio::task::spawn_blocking(move || model.rerank(query, documents, false, None))
+                    .await
+                    .map_err(|e| AgentError::Other(e.to_string()))?
+                    .map_err(|e| AgentError::ModelError(format!("Reranking failed: {}", e)))?;
+
+            let mut scores = vec![f32::NEG_INFINITY; candidates.len()];
+            for result in results {
+                if let Some(score) = scores.get_mut(result.index) {
+                    *score = result.score;
+                }
+            }
+            Ok(scores)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{File, GenerationResponse};
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    /// Rates memories mentioning the query's last word as relevant
+    struct KeywordLLM;
+
+    #[async_trait]
+    impl LLM for KeywordLLM {
+        async fn generate(
+            &self,
+            messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<GenerationResponse> {
+            let prompt = &messages[1].content;
+            let (query, memory) = prompt.split_once("\n\n").unwrap();
+            let keyword = query.split_whitespace().last().unwrap();
+            let score = if memory.contains(keyword) {
+                "0.9"
+            } else {
+                "0.1"
+            };
+            Ok(GenerationResponse {
+                content: score.to_string(),
+                metadata: None,
+            })
+        }
+
+        fn model_name(&self) -> &str {
+            "keyword"
+        }
+    }
+
+    fn record(content: &str) -> MemoryRecord {
+        MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: "test".to_string(),
+            role: "user".to_string(),
+            content: content.to_string(),
+            importance: 0.5,
+            timestamp: Utc::now(),
+            metadata: None,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_llm_reranker_orders_by_relevance() {
+        let reranker = LlmReranker::new(Arc::new(KeywordLLM));
+        let candidates = vec![
+            record("The weather is nice"),
+            record("My refund is pending"),
+            record("Lunch was great"),
+        ];
+
+        let ranked = reranker
+            .rerank("status of my refund", candidates, 2)
+            .await
+            .unwrap();
+        assert_eq!(ranked.len(), 2);
+        assert_eq!(ranked[0].content, "My refund is pending");
+    }
+}
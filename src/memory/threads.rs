@@ -0,0 +1,95 @@
+//! Hierarchical sessions and conversation threads.
+//!
+//! A session id such as `"acme/ticket-42/turn-3"` names a path of levels:
+//! the organization `acme`, its thread `acme/ticket-42` and the session
+//! itself, the way support desks and forums nest conversations. With
+//! `SessionMemory::with_threads`, every stored record is also indexed in
+//! the [`thread_scope`] of each level, so retrieval can be scoped to any of
+//! them, and a thread's records can be rolled up into a summary kept in its
+//! [`rollup_scope`]. Index entries reference their record by
+//! [`SOURCE_ID_KEY`], and scoped reads return the records under their own
+//! ids and sessions.
+
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use super::reserved_scope;
+
+/// Separator between the levels of a hierarchical session id
+pub const THREAD_SEPARATOR: char = '/';
+/// Metadata key of a thread scope entry naming the record it indexes
+pub const SOURCE_ID_KEY: &str = "source_id";
+
+/// Returns the store session id holding the records of every session at or
+/// below `path`
+pub fn thread_scope(path: &str) -> String {
+    reserved_scope("thread", path)
+}
+
+/// Returns the store session id holding the roll-up summaries of `path`
+pub fn rollup_scope(path: &str) -> String {
+    reserved_scope("rollup", path)
+}
+
+/// Returns the id of the entry indexing record `id` in the thread scope of
+/// `level`, the same every time the record is stored
+pub(crate) fn thread_entry_id(id: Uuid, level: &str) -> Uuid {
+    let digest = Sha256::new()
+        .chain_update(id.as_bytes())
+        .chain_update(level.as_bytes())
+        .finalize();
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(&digest[..16]);
+    uuid::Builder::from_random_bytes(bytes).into_uuid()
+}
+
+/// Returns every level of `session_id`, outermost first.
+///
+/// `"a/b/c"` yields `"a"`, `"a/b"` and `"a/b/c"`; empty segments are
+/// skipped, so `"a//b/"` yields `"a"` and `"a/b"`.
+pub fn thread_levels(session_id: &str) -> Vec<String> {
+    let mut levels = Vec::new();
+    let mut path = String::new();
+    for segment in session_id
+        .split(THREAD_SEPARATOR)
+        .filter(|segment| !segment.is_empty())
+    {
+        if !path.is_empty() {
+            path.push(THREAD_SEPARATOR);
+        }
+        path.push_str(segment);
+        levels.push(path.clone());
+    }
+    levels
+}
+
+/// Returns the level enclosing `session_id`, if it is nested
+pub fn parent_session(session_id: &str) -> Option<&str> {
+    let trimmed = session_id.trim_end_matches(THREAD_SEPARATOR);
+    trimmed
+        .rsplit_once(THREAD_SEPARATOR)
+        .map(|(parent, _)| parent.trim_end_matches(THREAD_SEPARATOR))
+        .filter(|parent| !parent.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels_and_parents() {
+        assert_eq!(
+            thread_levels("acme/t-1/turn"),
+            ["acme", "acme/t-1", "acme/t-1/turn"]
+        );
+        assert_eq!(thread_levels("a//b/"), ["a", "a/b"]);
+        assert_eq!(thread_levels("flat"), ["flat"]);
+        assert_eq!(parent_session("acme/t-1/turn"), Some("acme/t-1"));
+        assert_eq!(parent_session("acme/"), None);
+        assert_eq!(parent_session("flat"), None);
+
+        let id = Uuid::new_v4();
+        assert_eq!(thread_entry_id(id, "a"), thread_entry_id(id, "a"));
+        assert_ne!(thread_entry_id(id, "a"), thread_entry_id(id, "a/b"));
+    }
+}
@@ -0,0 +1,189 @@
+//! Episodic and semantic memory tiers.
+//!
+//! The episodic tier is what `SessionMemory` has always kept: every turn,
+//! verbatim. The semantic tier holds what is worth knowing about a session
+//! independent of when it was said: important turns distilled into facts and
+//! compaction summaries, each with an embedding for retrieval.
+//! [`PromotionRules`] decide what moves up; promoted copies live in the
+//! [`semantic_scope`] pseudo-session of the same backing store.
+
+use std::sync::Arc;
+
+use uuid::Uuid;
+
+use crate::error::Result;
+use crate::memory::compaction::SUMMARY_ROLE;
+use crate::memory::{Embedder, MemoryRecord, SOURCE_SESSION_KEY};
+use crate::models::LLM;
+use crate::types::{Message, Role};
+
+/// Metadata key naming the tier a record belongs to
+pub const TIER_METADATA_KEY: &str = "tier";
+
+/// Reply the fact extractor gives when a record holds nothing worth keeping
+const NO_FACT: &str = "NONE";
+
+const EXTRACTION_INSTRUCTIONS: &str = "Rewrite the following conversation turn as one short, \
+self-contained statement of fact worth remembering later, such as a preference, decision, \
+identifier or commitment. Reply with the statement only, or with NONE if the turn contains \
+nothing worth remembering.";
+
+/// Which tier a memory record belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemoryTier {
+    /// Verbatim conversation turns
+    Episodic,
+    /// Facts and summaries distilled from the conversation
+    Semantic,
+}
+
+impl MemoryTier {
+    /// Returns the tier name stored under [`TIER_METADATA_KEY`]
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MemoryTier::Episodic => "episodic",
+            MemoryTier::Semantic => "semantic",
+        }
+    }
+
+    /// Returns the tier of `record`; untagged records are episodic
+    pub fn of(record: &MemoryRecord) -> Self {
+        let tagged = record
+            .metadata
+            .as_ref()
+            .and_then(|m| m.get(TIER_METADATA_KEY))
+            .is_some_and(|tier| tier == MemoryTier::Semantic.as_str());
+        if tagged {
+            MemoryTier::Semantic
+        } else {
+            MemoryTier::Episodic
+        }
+    }
+}
+
+/// Returns the store session id holding the semantic tier of `session_id`
+pub fn semantic_scope(session_id: &str) -> String {
+    format!("semantic:{}", session_id)
+}
+
+/// Decides which episodic records are promoted to the semantic tier, and how
+#[derive(Clone)]
+pub struct PromotionRules {
+    min_importance: Option<f32>,
+    summaries: bool,
+    extractor: Option<Arc<dyn LLM>>,
+    embedder: Option<Arc<dyn Embedder>>,
+}
+
+impl Default for PromotionRules {
+    fn default() -> Self {
+        Self {
+            min_importance: Some(0.8),
+            summaries: true,
+            extractor: None,
+            embedder: None,
+        }
+    }
+}
+
+impl PromotionRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Promotes turns with at least this importance; `None` promotes none
+    pub fn with_min_importance(mut self, min_importance: Option<f32>) -> Self {
+        self.min_importance = min_importance;
+        self
+    }
+
+    /// Promotes the summaries written by compaction
+    pub fn with_summaries(mut self, enabled: bool) -> Self {
+        self.summaries = enabled;
+        self
+    }
+
+    /// Distills promoted turns into standalone facts with `model` instead of
+    /// copying them verbatim
+    pub fn with_fact_extraction(mut self, model: Arc<dyn LLM>) -> Self {
+        self.extractor = Some(model);
+        self
+    }
+
+    /// Embeds promoted records that don't carry an embedding yet
+    pub fn with_embedder(mut self, embedder: Arc<dyn Embedder>) -> Self {
+        self.embedder = Some(embedder);
+        self
+    }
+
+    /// Returns true if `record` qualifies for the semantic tier
+    pub fn promotes(&self, record: &MemoryRecord) -> bool {
+        if MemoryTier::of(record) == MemoryTier::Semantic {
+            return false;
+        }
+        if record.role == SUMMARY_ROLE {
+            return self.summaries;
+        }
+        self.min_importance
+            .is_some_and(|min| record.importance >= min)
+    }
+
+    /// Builds the semantic copy of `record`, or `None` if extraction finds
+    /// nothing worth keeping
+    pub(crate) async fn promote(&self, record: &MemoryRecord) -> Result<Option<MemoryRecord>> {
+        let mut promoted = record.clone();
+        if record.role != SUMMARY_ROLE {
+            if let Some(model) = &self.extractor {
+                match self.extract(model.as_ref(), record).await? {
+                    Some(fact) => {
+                        promoted.content = fact;
+                        // The old embedding described the turn, not the fact
+                        promoted.embedding = None;
+                    }
+                    None => return Ok(None),
+                }
+            }
+        }
+        if promoted.embedding.is_none() {
+            if let Some(embedder) = &self.embedder {
+                promoted.embedding = Some(embedder.embed(&promoted.content).await?);
+            }
+        }
+
+        let metadata = promoted.metadata.get_or_insert_with(Default::default);
+        metadata.insert(
+            TIER_METADATA_KEY.to_string(),
+            MemoryTier::Semantic.as_str().to_string(),
+        );
+        metadata
+            .entry(SOURCE_SESSION_KEY.to_string())
+            .or_insert_with(|| record.session_id.clone());
+
+        promoted.id = Uuid::new_v4();
+        promoted.session_id = semantic_scope(&record.session_id);
+        promoted.version = 0;
+        Ok(Some(promoted))
+    }
+
+    async fn extract(&self, model: &dyn LLM, record: &MemoryRecord) -> Result<Option<String>> {
+        let messages = vec![
+            Message {
+                role: Role::System,
+                content: EXTRACTION_INSTRUCTIONS.to_string(),
+                metadata: None,
+            },
+            Message {
+                role: Role::User,
+                content: format!("{}: {}", record.role, record.content),
+                metadata: None,
+            },
+        ];
+        let response = model.generate(messages, None).await?;
+        let fact = response.content.trim();
+        if fact.is_empty() || fact.eq_ignore_ascii_case(NO_FACT) {
+            Ok(None)
+        } else {
+            Ok(Some(fact.to_string()))
+        }
+    }
+}
@@ -0,0 +1,398 @@
+//! Write-ahead log for memory durability.
+//!
+//! [`WalStore`] appends every write to a local file, and syncs it, before
+//! sending it to the wrapped store. Writes the store acknowledges are marked
+//! done; the rest stay in the log and are retried on later writes, on
+//! `flush`, and on the next [`WalStore::open`], so a turn survives both an
+//! unreachable vector DB and a process restart. The log is truncated
+//! whenever nothing is outstanding, so it only grows during an outage.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use futures::stream::BoxStream;
+use serde::{Deserialize, Serialize};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::error::Result;
+use crate::memory::{export_pages, MemoryFilter, MemoryPage, MemoryRecord, MemoryStore};
+
+/// One line of the log. Writes are numbered, so acknowledging one write
+/// leaves a later write of the same record pending.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Entry {
+    Store { seq: u64, record: MemoryRecord },
+    Ack { seq: u64 },
+}
+
+/// A logged write and its sequence number
+#[derive(Clone)]
+struct Logged {
+    seq: u64,
+    record: MemoryRecord,
+}
+
+/// A [`MemoryStore`] wrapper that logs writes locally before storing them.
+///
+/// `store` succeeds once the record is in the log, even if the wrapped store
+/// is down; until it comes back, reads don't see the logged records.
+/// Updates are compare-and-swap against the current version and go straight
+/// to the wrapped store.
+pub struct WalStore {
+    inner: Arc<dyn MemoryStore>,
+    path: PathBuf,
+    // Held across appends, so the log and `pending` change together
+    log: tokio::sync::Mutex<tokio::fs::File>,
+    // Logged writes the wrapped store hasn't acknowledged, oldest first
+    pending: parking_lot::Mutex<Vec<Logged>>,
+    // Sequence number of the next write
+    next_seq: AtomicU64,
+}
+
+impl WalStore {
+    /// Opens (or creates) the log at `path` and replays what an earlier
+    /// process left unacknowledged into `inner`.
+    ///
+    /// If `inner` is still unreachable, the records stay pending and opening
+    /// succeeds anyway.
+    pub async fn open(inner: Arc<dyn MemoryStore>, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let pending = match tokio::fs::read_to_string(&path).await {
+            Ok(log) => unacknowledged(&log),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        // Rewrite the log with only what is pending, dropping acknowledged
+        // entries and any torn tail later appends would otherwise follow.
+        // The new log is synced before it replaces the old one, and the
+        // rename is synced with the directory.
+        let rewritten = path.with_extension("wal-rewrite");
+        let mut file = tokio::fs::File::create(&rewritten).await?;
+        file.write_all(&encode(&pending)?).await?;
+        file.sync_all().await?;
+        drop(file);
+        tokio::fs::rename(&rewritten, &path).await?;
+        sync_parent(&path).await?;
+        let next_seq = pending.last().map_or(0, |logged| logged.seq + 1);
+
+        let file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await?;
+
+        let store = Self {
+            inner,
+            path,
+            log: tokio::sync::Mutex::new(file),
+            pending: parking_lot::Mutex::new(pending),
+            next_seq: AtomicU64::new(next_seq),
+        };
+        match store.replay().await {
+            Ok(0) => {}
+            Ok(replayed) => tracing::info!(replayed, "Replayed memory write-ahead log"),
+            Err(e) => tracing::warn!(
+                pending = store.pending(),
+                "Memory write-ahead log replay failed: {}",
+                e
+            ),
+        }
+        Ok(store)
+    }
+
+    /// Returns the path of the log file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns how many logged writes the wrapped store hasn't acknowledged
+    pub fn pending(&self) -> usize {
+        self.pending.lock().len()
+    }
+
+    /// Sends every pending record to the wrapped store, returning how many
+    /// were written
+    pub async fn replay(&self) -> Result<usize> {
+        let logged = self.pending.lock().clone();
+        if logged.is_empty() {
+            return Ok(0);
+        }
+        let seqs: Vec<u64> = logged.iter().map(|l| l.seq).collect();
+        let records = logged.into_iter().map(|l| l.record).collect();
+        self.inner.store_batch(records).await?;
+        self.acknowledge(&seqs).await?;
+        Ok(seqs.len())
+    }
+
+    /// Appends `records` to the log and marks them pending
+    async fn append(&self, records: &[MemoryRecord]) -> Result<()> {
+        let mut log = self.log.lock().await;
+        let logged: Vec<Logged> = records
+            .iter()
+            .map(|record| Logged {
+                seq: self.next_seq.fetch_add(1, Ordering::Relaxed),
+                record: record.clone(),
+            })
+            .collect();
+        log.write_all(&encode(&logged)?).await?;
+        log.sync_data().await?;
+        self.pending.lock().extend(logged);
+        Ok(())
+    }
+
+    /// Marks the writes `seqs` as stored, truncating the log once nothing
+    /// is pending
+    async fn acknowledge(&self, seqs: &[u64]) -> Result<()> {
+        let mut log = self.log.lock().await;
+        let drained = {
+            let acked: HashSet<&u64> = seqs.iter().collect();
+            let mut pending = self.pending.lock();
+            pending.retain(|l| !acked.contains(&l.seq));
+            pending.is_empty()
+        };
+
+        if drained {
+            log.set_len(0).await?;
+        } else {
+            let mut lines = Vec::new();
+            for &seq in seqs {
+                serde_json::to_writer(&mut lines, &Entry::Ack { seq })?;
+                lines.push(b'\n');
+            }
+            log.write_all(&lines).await?;
+        }
+        log.sync_data().await?;
+        Ok(())
+    }
+}
+
+/// Encodes `logged` writes as log lines
+fn encode(logged: &[Logged]) -> Result<Vec<u8>> {
+    let mut lines = Vec::new();
+    for logged in logged {
+        let entry = Entry::Store {
+            seq: logged.seq,
+            record: logged.record.clone(),
+        };
+        serde_json::to_writer(&mut lines, &entry)?;
+        lines.push(b'\n');
+    }
+    Ok(lines)
+}
+
+/// Syncs the directory holding `path`, so a rename into it is durable
+async fn sync_parent(path: &Path) -> Result<()> {
+    // Directories can't be opened as files on Windows, which orders renames
+    #[cfg(unix)]
+    {
+        let parent = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        tokio::fs::File::open(parent).await?.sync_all().await?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Returns the writes of `log` without an acknowledgement, oldest first
+fn unacknowledged(log: &str) -> Vec<Logged> {
+    let mut logged: Vec<Logged> = Vec::new();
+    for line in log.lines().filter(|line| !line.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(Entry::Store { seq, record }) => logged.push(Logged { seq, record }),
+            Ok(Entry::Ack { seq }) => logged.retain(|l| l.seq != seq),
+            // Only the line being written during a crash can be torn
+            Err(e) => tracing::warn!("Skipping unreadable write-ahead log entry: {}", e),
+        }
+    }
+    logged
+}
+
+#[async_trait::async_trait]
+impl MemoryStore for WalStore {
+    async fn store(&self, record: MemoryRecord) -> Result<()> {
+        self.store_batch(vec![record]).await
+    }
+
+    async fn store_batch(&self, records: Vec<MemoryRecord>) -> Result<()> {
+        self.append(&records).await?;
+        // Sends earlier pending records too, keeping their order
+        if let Err(e) = self.replay().await {
+            tracing::warn!(
+                pending = self.pending(),
+                "Memory write kept in write-ahead log: {}",
+                e
+            );
+        }
+        Ok(())
+    }
+
+    async fn retrieve(&self, session_id: &str, limit: usize) -> Result<Vec<MemoryRecord>> {
+        self.retrieve_filtered(session_id, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn retrieve_filtered(
+        &self,
+        session_id: &str,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.inner
+            .retrieve_filtered(session_id, limit, filter)
+            .await
+    }
+
+    async fn retrieve_page(
+        &self,
+        session_id: &str,
+        cursor: Option<&str>,
+        page_size: usize,
+    ) -> Result<MemoryPage> {
+        self.inner
+            .retrieve_page(session_id, cursor, page_size)
+            .await
+    }
+
+    fn export<'a>(&'a self, session_id: &'a str) -> BoxStream<'a, Result<MemoryRecord>> {
+        export_pages(self, session_id)
+    }
+
+    async fn search(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.search_filtered(session_id, query_embedding, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn search_filtered(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.inner
+            .search_filtered(session_id, query_embedding, limit, filter)
+            .await
+    }
+
+    async fn get(&self, session_id: &str, id: Uuid) -> Result<Option<MemoryRecord>> {
+        self.inner.get(session_id, id).await
+    }
+
+    async fn update(&self, record: MemoryRecord) -> Result<u64> {
+        self.inner.update(record).await
+    }
+
+    async fn purge_expired(&self) -> Result<usize> {
+        self.inner.purge_expired().await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.replay().await?;
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InMemoryStore;
+    use chrono::Utc;
+
+    fn record(content: &str) -> MemoryRecord {
+        MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: "s".to_string(),
+            role: "user".to_string(),
+            content: content.to_string(),
+            importance: 0.5,
+            timestamp: Utc::now(),
+            metadata: None,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_open_replays_unacknowledged_writes() {
+        let path = std::env::temp_dir().join(format!("memory-{}.wal", Uuid::new_v4()));
+        let (rewritten, unsent) = (record("rewritten"), record("unsent"));
+        let mut log = String::new();
+        for entry in [
+            Entry::Store {
+                seq: 0,
+                record: rewritten.clone(),
+            },
+            Entry::Store {
+                seq: 1,
+                record: unsent,
+            },
+            Entry::Ack { seq: 0 },
+            Entry::Store {
+                seq: 2,
+                record: rewritten,
+            },
+        ] {
+            log.push_str(&serde_json::to_string(&entry).unwrap());
+            log.push('\n');
+        }
+        // Torn by a crash mid-append
+        log.push_str(r#"{"op":"store","rec"#);
+        tokio::fs::write(&path, log).await.unwrap();
+
+        let inner = Arc::new(InMemoryStore::new());
+        let wal = WalStore::open(inner.clone(), &path).await.unwrap();
+        assert_eq!(wal.pending(), 0);
+        let mut contents: Vec<String> = inner
+            .retrieve("s", 10)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| r.content)
+            .collect();
+        contents.sort();
+        assert_eq!(contents, ["rewritten", "unsent"]);
+
+        wal.store(record("new")).await.unwrap();
+        assert_eq!(wal.retrieve("s", 10).await.unwrap().len(), 3);
+        assert!(tokio::fs::read(&path).await.unwrap().is_empty());
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn test_ack_keeps_later_write_of_the_same_record() {
+        let path = std::env::temp_dir().join(format!("memory-{}.wal", Uuid::new_v4()));
+        let wal = WalStore::open(Arc::new(InMemoryStore::new()), &path)
+            .await
+            .unwrap();
+        let first = record("v1");
+        let mut second = first.clone();
+        second.content = "v2".to_string();
+
+        wal.append(&[first]).await.unwrap();
+        wal.append(&[second]).await.unwrap();
+        wal.acknowledge(&[0]).await.unwrap();
+        assert_eq!(wal.pending(), 1);
+
+        // The log agrees after a restart
+        let log = tokio::fs::read_to_string(&path).await.unwrap();
+        let pending = unacknowledged(&log);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].record.content, "v2");
+
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+}
@@ -1,9 +1,15 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
 use crate::error::{AgentError, Result};
-use crate::models::LLM;
+use crate::models::{
+    known_limits, normalize_roles, ratelimit, AdaptiveThrottle, Capabilities, GenerationSettings,
+    Passthrough, RoleRules, LLM,
+};
+use crate::secrets::SecretsProvider;
 use crate::types::{File, GenerationResponse, Message, Role};
 
 /// Anthropic Claude LLM provider
@@ -12,6 +18,8 @@ pub struct AnthropicLLM {
     api_key: String,
     model: String,
     max_tokens: u32,
+    passthrough: Passthrough,
+    throttle: Option<Arc<AdaptiveThrottle>>,
 }
 
 #[derive(Debug, Serialize)]
@@ -21,6 +29,8 @@ struct AnthropicRequest {
     max_tokens: u32,
     #[serde(skip_serializing_if = "Option::is_none")]
     system: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -70,9 +80,20 @@ impl AnthropicLLM {
             api_key,
             model: model.into(),
             max_tokens: 4096,
+            passthrough: Passthrough::default(),
+            throttle: None,
         })
     }
 
+    /// Creates with the API key stored as `ANTHROPIC_API_KEY` in `secrets`
+    pub async fn from_secrets(
+        secrets: &dyn SecretsProvider,
+        model: impl Into<String>,
+    ) -> Result<Self> {
+        let api_key = secrets.require("ANTHROPIC_API_KEY").await?;
+        Ok(Self::with_api_key(api_key, model))
+    }
+
     /// Creates with explicit API key
     pub fn with_api_key(api_key: impl Into<String>, model: impl Into<String>) -> Self {
         Self {
@@ -80,6 +101,7 @@ impl AnthropicLLM {
             api_key: api_key.into(),
             model: model.into(),
             max_tokens: 4096,
+            passthrough: Passthrough::default(),
         }
     }
 
@@ -89,6 +111,27 @@ impl AnthropicLLM {
         self
     }
 
+    /// Merges raw fields into every request body, for API parameters without
+    /// a typed option yet. Nested objects merge and `null` removes a field.
+    pub fn with_extra_body(mut self, extra: serde_json::Value) -> Self {
+        self.passthrough = self.passthrough.with_body(extra);
+        self
+    }
+
+    /// Sends an extra HTTP header with every request
+    pub fn with_extra_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.passthrough = self.passthrough.with_header(name, value);
+        self
+    }
+
+    /// Paces requests by the rate limits Anthropic reports, slowing down as
+    /// the remaining quota runs low; share `throttle` between models on
+    /// the same API key
+    pub fn with_throttle(mut self, throttle: Arc<AdaptiveThrottle>) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
     fn convert_role(role: &Role) -> String {
         match role {
             Role::User => "user".to_string(),
@@ -106,6 +149,18 @@ impl LLM for AnthropicLLM {
         messages: Vec<Message>,
         files: Option<Vec<File>>,
     ) -> Result<GenerationResponse> {
+        self.generate_with(messages, files, &GenerationSettings::default())
+            .await
+    }
+
+    async fn generate_with(
+        &self,
+        messages: Vec<Message>,
+        files: Option<Vec<File>>,
+        settings: &GenerationSettings,
+    ) -> Result<GenerationResponse> {
+        let messages = normalize_roles(messages, RoleRules::ALTERNATING);
+
         // Extract system message if present
         let system_prompt = messages
             .iter()
@@ -153,18 +208,33 @@ impl LLM for AnthropicLLM {
             messages: anthropic_messages,
             max_tokens: self.max_tokens,
             system: system_prompt,
+            temperature: settings.temperature,
         };
+        let mut body = serde_json::to_value(&request)?;
+        self.passthrough.apply_body(&mut body);
 
-        let response = self
+        let request = self
             .client
             .post("https://api.anthropic.com/v1/messages")
             .header("x-api-key", &self.api_key)
             .header("anthropic-version", "2023-06-01")
             .header("content-type", "application/json")
-            .json(&request)
+            .json(&body);
+        if let Some(throttle) = &self.throttle {
+            throttle.wait().await;
+        }
+        let response = self
+            .passthrough
+            .apply_headers(request)
             .send()
             .await
             .map_err(|e| AgentError::ModelError(format!("Anthropic request failed: {}", e)))?;
+        ratelimit::observe_response(
+            "anthropic",
+            &self.model,
+            response.headers(),
+            self.throttle.as_deref(),
+        );
 
         if !response.status().is_success() {
             let status = response.status();
@@ -199,6 +269,14 @@ impl LLM for AnthropicLLM {
     fn model_name(&self) -> &str {
         &self.model
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            vision: true,
+            max_context_tokens: known_limits(&self.model).map(|l| l.context_tokens),
+            ..Capabilities::default()
+        }
+    }
 }
 
 #[cfg(test)]
@@ -1,10 +1,17 @@
+use std::sync::Arc;
+
 use async_trait::async_trait;
 use base64::Engine;
+use chrono::{DateTime, Utc};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 
 use crate::error::{AgentError, Result};
-use crate::models::LLM;
+use crate::models::uploads::{FileUploader, UploadManager, UploadedFile};
+use crate::models::{
+    known_limits, normalize_roles, Capabilities, GenerationSettings, Passthrough, RoleRules, LLM,
+};
+use crate::secrets::SecretsProvider;
 use crate::types::{File, GenerationResponse, Message, Role};
 
 /// Gemini LLM provider
@@ -12,6 +19,8 @@ pub struct GeminiLLM {
     client: Client,
     api_key: String,
     model: String,
+    passthrough: Passthrough,
+    uploads: Option<Arc<UploadManager>>,
 }
 
 #[derive(Debug, Serialize)]
@@ -19,6 +28,16 @@ struct GeminiRequest {
     contents: Vec<GeminiContent>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tools: Option<Vec<serde_json::Value>>,
+    #[serde(rename = "generationConfig", skip_serializing_if = "Option::is_none")]
+    generation_config: Option<GeminiGenerationConfig>,
+}
+
+#[derive(Debug, Serialize)]
+struct GeminiGenerationConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(rename = "responseMimeType", skip_serializing_if = "Option::is_none")]
+    response_mime_type: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
@@ -32,6 +51,13 @@ struct GeminiContent {
 enum GeminiPart {
     Text { text: String },
     InlineData { inline_data: GeminiBlob },
+    FileData { file_data: GeminiFileData },
+}
+
+#[derive(Debug, Serialize)]
+struct GeminiFileData {
+    mime_type: String,
+    file_uri: String,
 }
 
 #[derive(Debug, Serialize)]
@@ -60,6 +86,86 @@ struct GeminiPartResponse {
     text: Option<String>,
 }
 
+#[derive(Debug, Deserialize)]
+struct GeminiUploadResponse {
+    file: GeminiFileInfo,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiFileInfo {
+    uri: String,
+    expiration_time: Option<DateTime<Utc>>,
+}
+
+/// Uploads files to the Gemini Files API, which keeps them for 48 hours
+pub struct GeminiFiles {
+    client: Client,
+    api_key: String,
+}
+
+impl GeminiFiles {
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Self {
+            client: Client::new(),
+            api_key: api_key.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl FileUploader for GeminiFiles {
+    async fn upload(&self, file: &crate::types::File) -> Result<UploadedFile> {
+        let error =
+            |e: reqwest::Error| AgentError::ModelError(format!("Gemini upload error: {}", e));
+
+        // Resumable protocol: announce the file, then send it in one piece
+        let start = self
+            .client
+            .post(format!(
+                "https://generativelanguage.googleapis.com/upload/v1beta/files?key={}",
+                self.api_key
+            ))
+            .header("X-Goog-Upload-Protocol", "resumable")
+            .header("X-Goog-Upload-Command", "start")
+            .header(
+                "X-Goog-Upload-Header-Content-Length",
+                file.data.len().to_string(),
+            )
+            .header("X-Goog-Upload-Header-Content-Type", &file.mime_type)
+            .json(&serde_json::json!({ "file": {} }))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(error)?;
+        let url = start
+            .headers()
+            .get("x-goog-upload-url")
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| AgentError::ModelError("Gemini upload returned no URL".to_string()))?
+            .to_string();
+
+        let uploaded: GeminiUploadResponse = self
+            .client
+            .post(url)
+            .header("X-Goog-Upload-Offset", "0")
+            .header("X-Goog-Upload-Command", "upload, finalize")
+            .body(file.data.clone())
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(error)?
+            .json()
+            .await
+            .map_err(error)?;
+        Ok(UploadedFile {
+            id: uploaded.file.uri,
+            mime_type: file.mime_type.clone(),
+            expires_at: uploaded.file.expiration_time,
+        })
+    }
+}
+
 impl GeminiLLM {
     /// Creates a new Gemini LLM with API key from environment
     pub fn new(model: impl Into<String>) -> Result<Self> {
@@ -75,18 +181,65 @@ impl GeminiLLM {
             client: Client::new(),
             api_key,
             model: model.into(),
+            passthrough: Passthrough::default(),
+            uploads: None,
         })
     }
 
+    /// Creates with the API key stored as `GOOGLE_API_KEY` or
+    /// `GEMINI_API_KEY` in `secrets`
+    pub async fn from_secrets(
+        secrets: &dyn SecretsProvider,
+        model: impl Into<String>,
+    ) -> Result<Self> {
+        let api_key = match secrets.get("GOOGLE_API_KEY").await? {
+            Some(api_key) => api_key,
+            None => secrets.require("GEMINI_API_KEY").await?,
+        };
+        Ok(Self::with_api_key(api_key, model))
+    }
+
     /// Creates with explicit API key
     pub fn with_api_key(api_key: impl Into<String>, model: impl Into<String>) -> Self {
         Self {
             client: Client::new(),
             api_key: api_key.into(),
             model: model.into(),
+            passthrough: Passthrough::default(),
+            uploads: None,
         }
     }
 
+    /// Merges raw fields into every request body, for API parameters without
+    /// a typed option yet. Nested objects merge and `null` removes a field.
+    pub fn with_extra_body(mut self, extra: serde_json::Value) -> Self {
+        self.passthrough = self.passthrough.with_body(extra);
+        self
+    }
+
+    /// Sends an extra HTTP header with every request
+    pub fn with_extra_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.passthrough = self.passthrough.with_header(name, value);
+        self
+    }
+
+    /// Uploads attachments larger than `threshold` bytes to the Files API
+    /// once and references them by URI in later calls
+    pub fn with_file_uploads(mut self, threshold: usize) -> Self {
+        let files = GeminiFiles::new(self.api_key.clone());
+        self.uploads = Some(Arc::new(
+            UploadManager::new(Arc::new(files)).with_threshold(threshold),
+        ));
+        self
+    }
+
+    /// Shares `uploads` with other models, so a file uploaded for one is
+    /// reused by all
+    pub fn with_upload_manager(mut self, uploads: Arc<UploadManager>) -> Self {
+        self.uploads = Some(uploads);
+        self
+    }
+
     fn convert_role(role: &Role) -> String {
         match role {
             Role::User => "user".to_string(),
@@ -104,6 +257,17 @@ impl LLM for GeminiLLM {
         messages: Vec<Message>,
         files: Option<Vec<File>>,
     ) -> Result<GenerationResponse> {
+        self.generate_with(messages, files, &GenerationSettings::default())
+            .await
+    }
+
+    async fn generate_with(
+        &self,
+        messages: Vec<Message>,
+        files: Option<Vec<File>>,
+        settings: &GenerationSettings,
+    ) -> Result<GenerationResponse> {
+        let messages = normalize_roles(messages, RoleRules::ALTERNATING_NO_SYSTEM);
         let mut contents: Vec<GeminiContent> = messages
             .iter()
             .map(|m| GeminiContent {
@@ -114,14 +278,27 @@ impl LLM for GeminiLLM {
             })
             .collect();
 
-        // Add file attachments if present to the last message
+        // Add file attachments if present to the last message, by reference
+        // once uploaded
         if let Some(files) = files {
             if let Some(last_content) = contents.last_mut() {
                 for file in files {
-                    last_content.parts.push(GeminiPart::InlineData {
-                        inline_data: GeminiBlob {
-                            mime_type: file.mime_type,
-                            data: base64::engine::general_purpose::STANDARD.encode(&file.data),
+                    let uploaded = match &self.uploads {
+                        Some(uploads) => uploads.upload(&file).await?,
+                        None => None,
+                    };
+                    last_content.parts.push(match uploaded {
+                        Some(uploaded) => GeminiPart::FileData {
+                            file_data: GeminiFileData {
+                                mime_type: uploaded.mime_type,
+                                file_uri: uploaded.id,
+                            },
+                        },
+                        None => GeminiPart::InlineData {
+                            inline_data: GeminiBlob {
+                                mime_type: file.mime_type,
+                                data: base64::engine::general_purpose::STANDARD.encode(&file.data),
+                            },
                         },
                     });
                 }
@@ -131,6 +308,12 @@ impl LLM for GeminiLLM {
         let request = GeminiRequest {
             contents,
             tools: None,
+            generation_config: (*settings != GenerationSettings::default()).then(|| {
+                GeminiGenerationConfig {
+                    temperature: settings.temperature,
+                    response_mime_type: settings.json_mode.then(|| "application/json".to_string()),
+                }
+            }),
         };
 
         let url = format!(
@@ -138,10 +321,12 @@ impl LLM for GeminiLLM {
             self.model, self.api_key
         );
 
+        let mut body = serde_json::to_value(&request)?;
+        self.passthrough.apply_body(&mut body);
+
         let response = self
-            .client
-            .post(&url)
-            .json(&request)
+            .passthrough
+            .apply_headers(self.client.post(&url).json(&body))
             .send()
             .await
             .map_err(|e| AgentError::ModelError(format!("Gemini API error: {}", e)))?;
@@ -179,6 +364,15 @@ impl LLM for GeminiLLM {
     fn model_name(&self) -> &str {
         &self.model
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            vision: true,
+            json_mode: true,
+            max_context_tokens: known_limits(&self.model).map(|l| l.context_tokens),
+            ..Capabilities::default()
+        }
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,146 @@
+//! Context and output limits of known models.
+//!
+//! Token budgeting needs to know how much a model can read and write. The
+//! built-in table covers common model ids by prefix, so dated or tagged
+//! variants (`claude-3-5-sonnet-20241022`, `llama3.1:8b`) resolve to their
+//! family; [`ModelLimitRegistry`] layers user overrides on top.
+
+use std::collections::HashMap;
+
+/// Token limits of one model
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModelLimits {
+    /// Total context window, prompt and completion combined
+    pub context_tokens: usize,
+    /// Maximum tokens the model generates in one response
+    pub output_tokens: usize,
+}
+
+impl ModelLimits {
+    pub const fn new(context_tokens: usize, output_tokens: usize) -> Self {
+        Self {
+            context_tokens,
+            output_tokens,
+        }
+    }
+
+    /// Tokens left for the prompt once a full response is reserved
+    pub fn input_budget(&self) -> usize {
+        self.context_tokens.saturating_sub(self.output_tokens)
+    }
+}
+
+/// Built-in limits keyed by model id prefix; the longest matching prefix wins
+const KNOWN_MODELS: &[(&str, ModelLimits)] = &[
+    // Anthropic
+    ("claude-3-haiku", ModelLimits::new(200_000, 4_096)),
+    ("claude-3-sonnet", ModelLimits::new(200_000, 4_096)),
+    ("claude-3-opus", ModelLimits::new(200_000, 4_096)),
+    ("claude-3-5-haiku", ModelLimits::new(200_000, 8_192)),
+    ("claude-3-5-sonnet", ModelLimits::new(200_000, 8_192)),
+    ("claude-3-7-sonnet", ModelLimits::new(200_000, 64_000)),
+    ("claude-sonnet-4", ModelLimits::new(200_000, 64_000)),
+    ("claude-opus-4", ModelLimits::new(200_000, 32_000)),
+    // OpenAI
+    ("gpt-3.5-turbo", ModelLimits::new(16_385, 4_096)),
+    ("gpt-4", ModelLimits::new(8_192, 4_096)),
+    ("gpt-4-turbo", ModelLimits::new(128_000, 4_096)),
+    ("gpt-4o", ModelLimits::new(128_000, 16_384)),
+    ("gpt-4.1", ModelLimits::new(1_047_576, 32_768)),
+    ("o1", ModelLimits::new(200_000, 100_000)),
+    ("o3", ModelLimits::new(200_000, 100_000)),
+    ("o4-mini", ModelLimits::new(200_000, 100_000)),
+    // Google
+    ("gemini-1.5-flash", ModelLimits::new(1_048_576, 8_192)),
+    ("gemini-1.5-pro", ModelLimits::new(2_097_152, 8_192)),
+    ("gemini-2.0-flash", ModelLimits::new(1_048_576, 8_192)),
+    ("gemini-2.5", ModelLimits::new(1_048_576, 65_536)),
+    // Common Ollama models
+    ("llama2", ModelLimits::new(4_096, 2_048)),
+    ("llama3", ModelLimits::new(8_192, 2_048)),
+    ("llama3.1", ModelLimits::new(131_072, 4_096)),
+    ("llama3.2", ModelLimits::new(131_072, 4_096)),
+    ("mistral", ModelLimits::new(32_768, 4_096)),
+    ("qwen2.5", ModelLimits::new(32_768, 8_192)),
+];
+
+fn longest_prefix<'a>(
+    entries: impl Iterator<Item = (&'a str, ModelLimits)>,
+    model: &str,
+) -> Option<(usize, ModelLimits)> {
+    entries
+        .filter(|(prefix, _)| model.starts_with(prefix))
+        .map(|(prefix, limits)| (prefix.len(), limits))
+        .max_by_key(|(len, _)| *len)
+}
+
+/// Returns the built-in limits for `model`, if it is known
+pub fn known_limits(model: &str) -> Option<ModelLimits> {
+    longest_prefix(KNOWN_MODELS.iter().copied(), model).map(|(_, limits)| limits)
+}
+
+/// Built-in model limits plus user overrides
+#[derive(Debug, Clone, Default)]
+pub struct ModelLimitRegistry {
+    overrides: HashMap<String, ModelLimits>,
+}
+
+impl ModelLimitRegistry {
+    /// Creates a registry with only the built-in table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the limits for model ids starting with `prefix`.
+    ///
+    /// Overrides take precedence over built-in entries, even shorter ones.
+    pub fn with_limits(mut self, prefix: impl Into<String>, limits: ModelLimits) -> Self {
+        self.overrides.insert(prefix.into(), limits);
+        self
+    }
+
+    /// Returns the limits for `model`, if known
+    pub fn lookup(&self, model: &str) -> Option<ModelLimits> {
+        let overrides = self.overrides.iter().map(|(p, l)| (p.as_str(), *l));
+        longest_prefix(overrides, model)
+            .map(|(_, limits)| limits)
+            .or_else(|| known_limits(model))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_longest_prefix_wins() {
+        assert_eq!(known_limits("gpt-4o-mini").unwrap().context_tokens, 128_000);
+        assert_eq!(known_limits("gpt-4-0613").unwrap().context_tokens, 8_192);
+        assert_eq!(
+            known_limits("claude-3-5-sonnet-20241022").unwrap(),
+            ModelLimits::new(200_000, 8_192)
+        );
+        assert_eq!(known_limits("llama3.1:8b").unwrap().context_tokens, 131_072);
+        assert!(known_limits("my-finetune").is_none());
+    }
+
+    #[test]
+    fn test_overrides_take_precedence() {
+        let registry = ModelLimitRegistry::new()
+            .with_limits("gpt-4o", ModelLimits::new(32_000, 4_000))
+            .with_limits("my-finetune", ModelLimits::new(4_096, 1_024));
+
+        assert_eq!(
+            registry.lookup("gpt-4o-mini").unwrap().input_budget(),
+            28_000
+        );
+        assert_eq!(
+            registry.lookup("my-finetune-v2").unwrap().context_tokens,
+            4_096
+        );
+        assert_eq!(
+            registry.lookup("gpt-4.1").unwrap().context_tokens,
+            1_047_576
+        );
+    }
+}
@@ -1,8 +1,63 @@
 use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
 
-use crate::error::Result;
+use crate::error::{AgentError, Result};
 use crate::types::{File, GenerationResponse, Message};
 
+/// Features a model implementation supports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities {
+    /// Accepts image attachments
+    pub vision: bool,
+    /// Accepts tool definitions and returns structured tool calls
+    pub tool_calling: bool,
+    /// Streams tokens as they are generated
+    pub streaming: bool,
+    /// Can be constrained to emit valid JSON
+    pub json_mode: bool,
+    /// Context window in tokens, if known
+    pub max_context_tokens: Option<usize>,
+}
+
+impl Capabilities {
+    /// Claims every feature with an unknown context window
+    pub const UNRESTRICTED: Self = Self {
+        vision: true,
+        tool_calling: true,
+        streaming: true,
+        json_mode: true,
+        max_context_tokens: None,
+    };
+
+    /// Fails with a clear error unless file attachments are supported
+    pub fn require_vision(&self, model: &str) -> Result<()> {
+        if self.vision {
+            Ok(())
+        } else {
+            Err(AgentError::ConfigError(format!(
+                "Model {} does not accept file attachments",
+                model
+            )))
+        }
+    }
+
+    /// Caps a context budget at the model's context window
+    pub fn clamp_context(&self, context_limit: usize) -> usize {
+        self.max_context_tokens
+            .map_or(context_limit, |max| context_limit.min(max))
+    }
+}
+
+/// Sampling settings for a single call to [`LLM::generate_with`]
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct GenerationSettings {
+    /// Sampling temperature; the provider default if unset
+    pub temperature: Option<f32>,
+    /// Constrains the reply to valid JSON, for models whose capabilities
+    /// claim `json_mode`; others ignore it
+    pub json_mode: bool,
+}
+
 /// LLM model interface
 #[async_trait]
 pub trait LLM: Send + Sync {
@@ -13,10 +68,67 @@ pub trait LLM: Send + Sync {
         files: Option<Vec<File>>,
     ) -> Result<GenerationResponse>;
 
+    /// Generates a response with per-call sampling `settings`.
+    ///
+    /// The default implementation ignores the settings and calls
+    /// [`LLM::generate`]; providers that can apply them override it.
+    async fn generate_with(
+        &self,
+        messages: Vec<Message>,
+        files: Option<Vec<File>>,
+        settings: &GenerationSettings,
+    ) -> Result<GenerationResponse> {
+        let _ = settings;
+        self.generate(messages, files).await
+    }
+
+    /// Streams the response as text chunks.
+    ///
+    /// The default implementation yields the whole [`LLM::generate`]
+    /// response as a single chunk.
+    async fn generate_stream(
+        &self,
+        messages: Vec<Message>,
+        files: Option<Vec<File>>,
+    ) -> Result<BoxStream<'static, Result<String>>> {
+        let response = self.generate(messages, files).await?;
+        Ok(stream::once(async move { Ok(response.content) }).boxed())
+    }
+
     /// Returns the model name
     fn model_name(&self) -> &str;
+
+    /// Reports which features this model supports.
+    ///
+    /// Models that don't override this are assumed to support everything,
+    /// so the agent never rejects their requests up front.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::UNRESTRICTED
+    }
 }
 
+pub mod limits;
+pub mod passthrough;
+pub mod pricing;
+pub mod ratelimit;
+pub mod roles;
+pub mod routing;
+pub mod tokenizer;
+pub mod uploads;
+
+pub use limits::{known_limits, ModelLimitRegistry, ModelLimits};
+pub use passthrough::Passthrough;
+pub use pricing::{known_pricing, ModelPricing, PricingRegistry};
+pub use ratelimit::{AdaptiveThrottle, Quota, RateLimitInfo};
+pub use roles::{normalize_roles, RoleRules};
+pub use routing::{ModelRoute, ModelRoutingPolicy, MODEL_ROUTE_KEY};
+#[cfg(feature = "hf-tokenizers")]
+pub use tokenizer::HfTokenizer;
+#[cfg(feature = "tiktoken")]
+pub use tokenizer::TiktokenTokenizer;
+pub use tokenizer::{HeuristicTokenizer, Tokenizer, TokenizerRegistry};
+pub use uploads::{FileUploader, UploadManager, UploadedFile, DEFAULT_UPLOAD_THRESHOLD};
+
 // LLM provider implementations
 #[cfg(feature = "gemini")]
 pub mod gemini;
@@ -32,7 +144,7 @@ pub mod openai;
 
 // Re-export providers
 #[cfg(feature = "gemini")]
-pub use gemini::GeminiLLM;
+pub use gemini::{GeminiFiles, GeminiLLM};
 
 #[cfg(feature = "ollama")]
 pub use ollama::OllamaLLM;
@@ -41,4 +153,4 @@ pub use ollama::OllamaLLM;
 pub use anthropic::AnthropicLLM;
 
 #[cfg(feature = "openai")]
-pub use openai::OpenAILLM;
+pub use openai::{OpenAIFiles, OpenAILLM};
@@ -4,30 +4,98 @@ use ollama_rs::generation::chat::ChatMessage;
 use ollama_rs::Ollama;
 
 use crate::error::{AgentError, Result};
-use crate::models::LLM;
+use crate::models::{known_limits, Capabilities, GenerationSettings, Passthrough, LLM};
 use crate::types::{File, GenerationResponse, Message, Role};
 
 /// Ollama LLM provider using ollama-rs SDK
 pub struct OllamaLLM {
     client: Ollama,
+    // Shared by raw requests so they reuse pooled connections
+    http: reqwest::Client,
+    host: String,
+    port: u16,
     model: String,
+    passthrough: Passthrough,
 }
 
 impl OllamaLLM {
     /// Creates a new Ollama LLM with default localhost connection
     pub fn new(model: impl Into<String>) -> Self {
-        Self {
-            client: Ollama::default(),
-            model: model.into(),
-        }
+        Self::with_host("http://127.0.0.1", 11434, model)
     }
 
     /// Creates with custom host and port
     pub fn with_host(host: impl Into<String>, port: u16, model: impl Into<String>) -> Self {
+        let host = host.into();
         Self {
-            client: Ollama::new(host.into(), port),
+            client: Ollama::new(host.clone(), port),
+            http: reqwest::Client::new(),
+            host,
+            port,
             model: model.into(),
+            passthrough: Passthrough::default(),
+        }
+    }
+
+    /// Merges raw fields into every request body, for API parameters without
+    /// a typed option yet. Nested objects merge and `null` removes a field.
+    pub fn with_extra_body(mut self, extra: serde_json::Value) -> Self {
+        self.passthrough = self.passthrough.with_body(extra);
+        self
+    }
+
+    /// Sends an extra HTTP header with every request
+    pub fn with_extra_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.passthrough = self.passthrough.with_header(name, value);
+        self
+    }
+
+    /// Calls `/api/chat` directly so passthrough fields, headers and
+    /// per-call settings apply
+    async fn chat_raw(
+        &self,
+        messages: &[ChatMessage],
+        settings: &GenerationSettings,
+    ) -> Result<String> {
+        let mut body = serde_json::json!({
+            "model": self.model,
+            "messages": messages,
+            "stream": false,
+        });
+        if let Some(temperature) = settings.temperature {
+            body["options"] = serde_json::json!({ "temperature": temperature });
+        }
+        if settings.json_mode {
+            body["format"] = serde_json::json!("json");
+        }
+        self.passthrough.apply_body(&mut body);
+
+        let url = format!("{}:{}/api/chat", self.host.trim_end_matches('/'), self.port);
+        let request = self.http.post(url).json(&body);
+        let response = self
+            .passthrough
+            .apply_headers(request)
+            .send()
+            .await
+            .map_err(|e| AgentError::ModelError(format!("Ollama error: {}", e)))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AgentError::ModelError(format!(
+                "Ollama error {}: {}",
+                status, text
+            )));
         }
+
+        let value: serde_json::Value = response
+            .json()
+            .await
+            .map_err(|e| AgentError::ModelError(format!("Failed to parse response: {}", e)))?;
+        Ok(value["message"]["content"]
+            .as_str()
+            .unwrap_or_default()
+            .to_string())
     }
 
     fn convert_role(role: &Role) -> String {
@@ -54,6 +122,16 @@ impl LLM for OllamaLLM {
         &self,
         messages: Vec<Message>,
         files: Option<Vec<File>>,
+    ) -> Result<GenerationResponse> {
+        self.generate_with(messages, files, &GenerationSettings::default())
+            .await
+    }
+
+    async fn generate_with(
+        &self,
+        messages: Vec<Message>,
+        files: Option<Vec<File>>,
+        settings: &GenerationSettings,
     ) -> Result<GenerationResponse> {
         let mut chat_messages: Vec<ChatMessage> =
             messages.iter().map(|m| self.convert_message(m)).collect();
@@ -73,6 +151,13 @@ impl LLM for OllamaLLM {
             }
         }
 
+        if !self.passthrough.is_empty() || *settings != GenerationSettings::default() {
+            return Ok(GenerationResponse {
+                content: self.chat_raw(&chat_messages, settings).await?,
+                metadata: None,
+            });
+        }
+
         let request = ChatMessageRequest::new(self.model.clone(), chat_messages);
 
         let response = self
@@ -90,6 +175,16 @@ impl LLM for OllamaLLM {
     fn model_name(&self) -> &str {
         &self.model
     }
+
+    fn capabilities(&self) -> Capabilities {
+        // Vision depends on the pulled model, so let the server decide
+        Capabilities {
+            vision: true,
+            json_mode: true,
+            max_context_tokens: known_limits(&self.model).map(|l| l.context_tokens),
+            ..Capabilities::default()
+        }
+    }
 }
 
 #[cfg(test)]
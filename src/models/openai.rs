@@ -1,21 +1,100 @@
 use async_openai::{
+    config::{Config, OpenAIConfig},
     types::{
         ChatCompletionRequestAssistantMessageArgs, ChatCompletionRequestSystemMessageArgs,
         ChatCompletionRequestUserMessageArgs, ChatCompletionRequestUserMessageContent,
-        CreateChatCompletionRequestArgs, ImageUrl,
+        ChatCompletionResponseFormat, ChatCompletionResponseFormatType,
+        CreateChatCompletionRequest, CreateChatCompletionRequestArgs, CreateChatCompletionResponse,
+        ImageUrl,
     },
     Client,
 };
+use std::sync::Arc;
+
 use async_trait::async_trait;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Value};
 
 use crate::error::{AgentError, Result};
-use crate::models::LLM;
+use crate::models::uploads::{FileUploader, UploadManager, UploadedFile};
+use crate::models::{
+    known_limits, ratelimit, AdaptiveThrottle, Capabilities, GenerationSettings, Passthrough, LLM,
+};
+use crate::secrets::SecretsProvider;
 use crate::types::{File, GenerationResponse, Message, Role};
 
 /// OpenAI LLM provider
 pub struct OpenAILLM {
-    client: Client<async_openai::config::OpenAIConfig>,
+    client: Client<OpenAIConfig>,
+    // Shared by raw requests so they reuse pooled connections
+    http: reqwest::Client,
+    config: OpenAIConfig,
     model: String,
+    passthrough: Passthrough,
+    uploads: Option<Arc<UploadManager>>,
+    throttle: Option<Arc<AdaptiveThrottle>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OpenAIFileObject {
+    id: String,
+    expires_at: Option<i64>,
+}
+
+/// Uploads files to the OpenAI Files API for use in chat completions
+pub struct OpenAIFiles {
+    http: reqwest::Client,
+    config: OpenAIConfig,
+}
+
+impl OpenAIFiles {
+    pub fn new(api_key: impl Into<String>) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            config: OpenAIConfig::new().with_api_key(api_key),
+        }
+    }
+}
+
+#[async_trait]
+impl FileUploader for OpenAIFiles {
+    async fn upload(&self, file: &File) -> Result<UploadedFile> {
+        let error =
+            |e: reqwest::Error| AgentError::ModelError(format!("OpenAI upload error: {}", e));
+        let part = reqwest::multipart::Part::bytes(file.data.clone())
+            .file_name(file_name(&file.mime_type))
+            .mime_str(&file.mime_type)
+            .map_err(error)?;
+        let form = reqwest::multipart::Form::new()
+            .text("purpose", "user_data")
+            .part("file", part);
+        let uploaded: OpenAIFileObject = self
+            .http
+            .post(self.config.url("/files"))
+            .headers(self.config.headers())
+            .multipart(form)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(error)?
+            .json()
+            .await
+            .map_err(error)?;
+        Ok(UploadedFile {
+            id: uploaded.id,
+            mime_type: file.mime_type.clone(),
+            expires_at: uploaded
+                .expires_at
+                .and_then(|t| chrono::DateTime::from_timestamp(t, 0)),
+        })
+    }
+}
+
+/// Returns a file name whose extension tells OpenAI the file type
+fn file_name(mime_type: &str) -> String {
+    let extension = mime_type.rsplit('/').next().unwrap_or("bin");
+    format!("attachment.{}", extension)
 }
 
 impl OpenAILLM {
@@ -25,19 +104,159 @@ impl OpenAILLM {
             AgentError::ConfigError("OPENAI_API_KEY environment variable not set".to_string())
         })?;
 
-        Ok(Self {
-            client: Client::new(),
-            model: model.into(),
-        })
+        Ok(Self::with_config(OpenAIConfig::default(), model))
+    }
+
+    /// Creates with the API key stored as `OPENAI_API_KEY` in `secrets`
+    pub async fn from_secrets(
+        secrets: &dyn SecretsProvider,
+        model: impl Into<String>,
+    ) -> Result<Self> {
+        let api_key = secrets.require("OPENAI_API_KEY").await?;
+        Ok(Self::with_api_key(api_key, model))
     }
 
     /// Creates with explicit API key
     pub fn with_api_key(api_key: impl Into<String>, model: impl Into<String>) -> Self {
-        let config = async_openai::config::OpenAIConfig::new().with_api_key(api_key);
+        let config = OpenAIConfig::new().with_api_key(api_key);
+        Self::with_config(config, model)
+    }
+
+    fn with_config(config: OpenAIConfig, model: impl Into<String>) -> Self {
         Self {
-            client: Client::with_config(config),
+            client: Client::with_config(config.clone()),
+            http: reqwest::Client::new(),
+            config,
             model: model.into(),
+            passthrough: Passthrough::default(),
+            uploads: None,
+            throttle: None,
+        }
+    }
+
+    /// Merges raw fields into every request body, for API parameters without
+    /// a typed option yet. Nested objects merge and `null` removes a field.
+    pub fn with_extra_body(mut self, extra: serde_json::Value) -> Self {
+        self.passthrough = self.passthrough.with_body(extra);
+        self
+    }
+
+    /// Sends an extra HTTP header with every request
+    pub fn with_extra_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.passthrough = self.passthrough.with_header(name, value);
+        self
+    }
+
+    /// Uploads non-image attachments larger than `threshold` bytes, such as
+    /// PDFs, to the Files API once and references them by id in later
+    /// calls; Chat Completions only takes images inline
+    pub fn with_file_uploads(mut self, threshold: usize) -> Self {
+        let files = OpenAIFiles {
+            http: self.http.clone(),
+            config: self.config.clone(),
+        };
+        self.uploads = Some(Arc::new(
+            UploadManager::new(Arc::new(files)).with_threshold(threshold),
+        ));
+        self
+    }
+
+    /// Shares `uploads` with other models, so a file uploaded for one is
+    /// reused by all
+    pub fn with_upload_manager(mut self, uploads: Arc<UploadManager>) -> Self {
+        self.uploads = Some(uploads);
+        self
+    }
+
+    /// Paces requests by the rate limits OpenAI reports, slowing down as the
+    /// remaining quota runs low; share `throttle` between models on the
+    /// same API key
+    pub fn with_throttle(mut self, throttle: Arc<AdaptiveThrottle>) -> Self {
+        self.throttle = Some(throttle);
+        self
+    }
+
+    /// Returns `file` as a `file` content part, by id once uploaded
+    async fn file_part(&self, file: &File) -> Result<Value> {
+        let uploaded = match &self.uploads {
+            Some(uploads) => uploads.upload(file).await?,
+            None => None,
+        };
+        Ok(match uploaded {
+            Some(uploaded) => json!({"type": "file", "file": {"file_id": uploaded.id}}),
+            None => json!({
+                "type": "file",
+                "file": {
+                    "filename": file_name(&file.mime_type),
+                    "file_data": format!(
+                        "data:{};base64,{}",
+                        file.mime_type,
+                        base64::engine::general_purpose::STANDARD.encode(&file.data)
+                    ),
+                }
+            }),
+        })
+    }
+
+    /// Sends the request as raw JSON so passthrough fields and headers
+    /// apply, `file_parts` join the last user message and rate limit
+    /// headers can be read
+    async fn create_raw(
+        &self,
+        request: &CreateChatCompletionRequest,
+        file_parts: Vec<Value>,
+    ) -> Result<CreateChatCompletionResponse> {
+        let mut body = serde_json::to_value(request)?;
+        if let Some(message) = body["messages"]
+            .as_array_mut()
+            .and_then(|messages| messages.last_mut())
+            .filter(|message| message["role"] == "user" && !file_parts.is_empty())
+        {
+            let mut parts = match message["content"].take() {
+                Value::String(text) => vec![json!({"type": "text", "text": text})],
+                Value::Array(parts) => parts,
+                _ => Vec::new(),
+            };
+            parts.extend(file_parts);
+            message["content"] = Value::Array(parts);
         }
+        self.passthrough.apply_body(&mut body);
+
+        let request = self
+            .http
+            .post(self.config.url("/chat/completions"))
+            .query(&self.config.query())
+            .headers(self.config.headers())
+            .json(&body);
+        if let Some(throttle) = &self.throttle {
+            throttle.wait().await;
+        }
+        let response = self
+            .passthrough
+            .apply_headers(request)
+            .send()
+            .await
+            .map_err(|e| AgentError::ModelError(format!("OpenAI API error: {}", e)))?;
+        ratelimit::observe_response(
+            "openai",
+            &self.model,
+            response.headers(),
+            self.throttle.as_deref(),
+        );
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let text = response.text().await.unwrap_or_default();
+            return Err(AgentError::ModelError(format!(
+                "OpenAI API error {}: {}",
+                status, text
+            )));
+        }
+
+        response
+            .json()
+            .await
+            .map_err(|e| AgentError::ModelError(format!("Failed to parse response: {}", e)))
     }
 }
 
@@ -47,6 +266,16 @@ impl LLM for OpenAILLM {
         &self,
         messages: Vec<Message>,
         files: Option<Vec<File>>,
+    ) -> Result<GenerationResponse> {
+        self.generate_with(messages, files, &GenerationSettings::default())
+            .await
+    }
+
+    async fn generate_with(
+        &self,
+        messages: Vec<Message>,
+        files: Option<Vec<File>>,
+        settings: &GenerationSettings,
     ) -> Result<GenerationResponse> {
         let mut chat_messages = Vec::new();
 
@@ -113,8 +342,18 @@ impl LLM for OpenAILLM {
             }
         }
 
+        // Other files go in `file` parts, which the typed request can't hold
+        let (images, documents): (Vec<File>, Vec<File>) = files
+            .unwrap_or_default()
+            .into_iter()
+            .partition(|file| file.mime_type.starts_with("image/"));
+        let mut file_parts = Vec::with_capacity(documents.len());
+        for document in &documents {
+            file_parts.push(self.file_part(document).await?);
+        }
+
         // Handle files (images) by appending to the last user message
-        if let Some(files) = files {
+        if let Some(files) = (!images.is_empty()).then_some(images) {
             if let Some(last_msg) = chat_messages.last_mut() {
                 if let async_openai::types::ChatCompletionRequestMessage::User(user_msg) = last_msg
                 {
@@ -178,18 +417,30 @@ impl LLM for OpenAILLM {
             }
         }
 
-        let request = CreateChatCompletionRequestArgs::default()
-            .model(&self.model)
-            .messages(chat_messages)
+        let mut request = CreateChatCompletionRequestArgs::default();
+        request.model(&self.model).messages(chat_messages);
+        if let Some(temperature) = settings.temperature {
+            request.temperature(temperature);
+        }
+        if settings.json_mode {
+            request.response_format(ChatCompletionResponseFormat {
+                r#type: ChatCompletionResponseFormatType::JsonObject,
+            });
+        }
+        let request = request
             .build()
             .map_err(|e| AgentError::ModelError(format!("Failed to build request: {}", e)))?;
 
-        let response = self
-            .client
-            .chat()
-            .create(request)
-            .await
-            .map_err(|e| AgentError::ModelError(format!("OpenAI API error: {}", e)))?;
+        let response =
+            if self.passthrough.is_empty() && file_parts.is_empty() && self.throttle.is_none() {
+                self.client
+                    .chat()
+                    .create(request)
+                    .await
+                    .map_err(|e| AgentError::ModelError(format!("OpenAI API error: {}", e)))?
+            } else {
+                self.create_raw(&request, file_parts).await?
+            };
 
         let content = response
             .choices
@@ -206,6 +457,15 @@ impl LLM for OpenAILLM {
     fn model_name(&self) -> &str {
         &self.model
     }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            vision: true,
+            json_mode: true,
+            max_context_tokens: known_limits(&self.model).map(|l| l.context_tokens),
+            ..Capabilities::default()
+        }
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,122 @@
+//! Raw request overrides for LLM providers.
+//!
+//! Providers gain parameters faster than this crate can model them. Extra
+//! body fields and headers set here are merged into each provider request as
+//! is, so new features can be used before they get a typed option.
+
+use std::collections::HashMap;
+
+use serde_json::{Map, Value};
+
+/// Extra JSON body fields and HTTP headers sent with every provider request
+#[derive(Debug, Clone, Default)]
+pub struct Passthrough {
+    body: Map<String, Value>,
+    headers: HashMap<String, String>,
+}
+
+impl Passthrough {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Merges the fields of `extra` into the request body overrides.
+    ///
+    /// `extra` must be a JSON object; other values are ignored with a warning.
+    pub fn with_body(mut self, extra: Value) -> Self {
+        match extra {
+            Value::Object(fields) => overlay(&mut self.body, fields),
+            other => tracing::warn!("Ignoring non-object extra body: {}", other),
+        }
+        self
+    }
+
+    /// Adds a header sent with every request, replacing any previous value
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    /// Returns the extra headers
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+
+    /// Returns true if nothing would be overridden
+    pub fn is_empty(&self) -> bool {
+        self.body.is_empty() && self.headers.is_empty()
+    }
+
+    /// Deep-merges the body overrides into a serialized request.
+    ///
+    /// Nested objects are merged key by key, any other value replaces the
+    /// original, and `null` removes the key altogether.
+    pub fn apply_body(&self, body: &mut Value) {
+        if let Value::Object(fields) = body {
+            apply(fields, &self.body);
+        }
+    }
+
+    /// Adds the extra headers to an outgoing request
+    pub fn apply_headers(&self, mut request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        request
+    }
+}
+
+/// Combines override sets, keeping `null` markers for `apply`
+fn overlay(target: &mut Map<String, Value>, extra: Map<String, Value>) {
+    for (key, value) in extra {
+        match (target.get_mut(&key), value) {
+            (Some(Value::Object(existing)), Value::Object(nested)) => overlay(existing, nested),
+            (_, value) => {
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+fn apply(target: &mut Map<String, Value>, overrides: &Map<String, Value>) {
+    for (key, value) in overrides {
+        match (target.get_mut(key), value) {
+            (_, Value::Null) => {
+                target.remove(key);
+            }
+            (Some(Value::Object(existing)), Value::Object(nested)) => apply(existing, nested),
+            (_, value) => {
+                target.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_apply_body_deep_merges() {
+        let passthrough = Passthrough::new()
+            .with_body(json!({"generationConfig": {"seed": 7}, "stream": null}))
+            .with_body(json!({"service_tier": "flex"}));
+
+        let mut body = json!({
+            "model": "m",
+            "stream": false,
+            "generationConfig": {"temperature": 0.2}
+        });
+        passthrough.apply_body(&mut body);
+
+        assert_eq!(
+            body,
+            json!({
+                "model": "m",
+                "service_tier": "flex",
+                "generationConfig": {"temperature": 0.2, "seed": 7}
+            })
+        );
+    }
+}
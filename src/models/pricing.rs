@@ -0,0 +1,160 @@
+//! Token prices of known models.
+//!
+//! Cost tracking needs to know what a model charges per token. Like
+//! [`ModelLimitRegistry`](super::ModelLimitRegistry), the built-in table
+//! covers common hosted model ids by prefix and [`PricingRegistry`] layers
+//! user overrides on top. Prices are list prices in US dollars and change
+//! over time; override them with your negotiated rates. Local models, such
+//! as those served by Ollama, are not listed and cost nothing.
+
+use std::collections::HashMap;
+
+/// Price of one model, in US dollars per million tokens
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ModelPricing {
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+impl ModelPricing {
+    pub const fn new(input_per_million: f64, output_per_million: f64) -> Self {
+        Self {
+            input_per_million,
+            output_per_million,
+        }
+    }
+
+    /// Returns the cost of a call that read `input_tokens` and wrote
+    /// `output_tokens`
+    pub fn cost(&self, input_tokens: usize, output_tokens: usize) -> f64 {
+        (input_tokens as f64 * self.input_per_million
+            + output_tokens as f64 * self.output_per_million)
+            / 1_000_000.0
+    }
+}
+
+/// Built-in prices keyed by model id prefix; the longest matching prefix wins.
+///
+/// A prefix only matches at a `-` boundary of the id, so `o1` prices
+/// `o1-2024-12-17` but not `o1-mini`, which needs its own entry.
+const KNOWN_PRICES: &[(&str, ModelPricing)] = &[
+    // Anthropic
+    ("claude-3-haiku", ModelPricing::new(0.25, 1.25)),
+    ("claude-3-sonnet", ModelPricing::new(3.0, 15.0)),
+    ("claude-3-opus", ModelPricing::new(15.0, 75.0)),
+    ("claude-3-5-haiku", ModelPricing::new(0.8, 4.0)),
+    ("claude-3-5-sonnet", ModelPricing::new(3.0, 15.0)),
+    ("claude-3-7-sonnet", ModelPricing::new(3.0, 15.0)),
+    ("claude-sonnet-4", ModelPricing::new(3.0, 15.0)),
+    ("claude-opus-4", ModelPricing::new(15.0, 75.0)),
+    // OpenAI
+    ("gpt-3.5-turbo", ModelPricing::new(0.5, 1.5)),
+    ("gpt-4", ModelPricing::new(30.0, 60.0)),
+    ("gpt-4-turbo", ModelPricing::new(10.0, 30.0)),
+    ("gpt-4o", ModelPricing::new(2.5, 10.0)),
+    ("gpt-4o-mini", ModelPricing::new(0.15, 0.6)),
+    ("gpt-4.1", ModelPricing::new(2.0, 8.0)),
+    ("gpt-4.1-mini", ModelPricing::new(0.4, 1.6)),
+    ("gpt-4.1-nano", ModelPricing::new(0.1, 0.4)),
+    ("o1", ModelPricing::new(15.0, 60.0)),
+    ("o1-mini", ModelPricing::new(1.1, 4.4)),
+    ("o1-pro", ModelPricing::new(150.0, 600.0)),
+    ("o3", ModelPricing::new(2.0, 8.0)),
+    ("o3-mini", ModelPricing::new(1.1, 4.4)),
+    ("o3-pro", ModelPricing::new(20.0, 80.0)),
+    ("o4-mini", ModelPricing::new(1.1, 4.4)),
+    // Google
+    ("gemini-1.5-flash", ModelPricing::new(0.075, 0.3)),
+    ("gemini-1.5-pro", ModelPricing::new(1.25, 5.0)),
+    ("gemini-2.0-flash", ModelPricing::new(0.1, 0.4)),
+    ("gemini-2.5-flash", ModelPricing::new(0.3, 2.5)),
+    ("gemini-2.5-flash-lite", ModelPricing::new(0.1, 0.4)),
+    ("gemini-2.5-pro", ModelPricing::new(1.25, 10.0)),
+];
+
+fn longest_prefix<'a>(
+    entries: impl Iterator<Item = (&'a str, ModelPricing)>,
+    model: &str,
+) -> Option<(usize, ModelPricing)> {
+    entries
+        .filter(|(prefix, _)| {
+            model
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('-'))
+        })
+        .map(|(prefix, pricing)| (prefix.len(), pricing))
+        .max_by_key(|(len, _)| *len)
+}
+
+/// Returns the built-in price of `model`, if it is known
+pub fn known_pricing(model: &str) -> Option<ModelPricing> {
+    longest_prefix(KNOWN_PRICES.iter().copied(), model).map(|(_, pricing)| pricing)
+}
+
+/// Built-in model prices plus user overrides
+#[derive(Debug, Clone, Default)]
+pub struct PricingRegistry {
+    overrides: HashMap<String, ModelPricing>,
+}
+
+impl PricingRegistry {
+    /// Creates a registry with only the built-in table
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the price of model ids starting with `prefix`, matched at a
+    /// `-` boundary like the built-in table.
+    ///
+    /// Overrides take precedence over built-in entries, even shorter ones.
+    pub fn with_pricing(mut self, prefix: impl Into<String>, pricing: ModelPricing) -> Self {
+        self.overrides.insert(prefix.into(), pricing);
+        self
+    }
+
+    /// Returns the price of `model`, if known
+    pub fn lookup(&self, model: &str) -> Option<ModelPricing> {
+        let overrides = self.overrides.iter().map(|(p, l)| (p.as_str(), *l));
+        longest_prefix(overrides, model)
+            .map(|(_, pricing)| pricing)
+            .or_else(|| known_pricing(model))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prices_resolve_by_prefix() {
+        assert_eq!(
+            known_pricing("gpt-4o-mini-2024-07-18")
+                .unwrap()
+                .input_per_million,
+            0.15
+        );
+        assert_eq!(
+            known_pricing("gpt-4o-2024-08-06")
+                .unwrap()
+                .input_per_million,
+            2.5
+        );
+        assert!(known_pricing("llama3.1:8b").is_none());
+        // Variants don't fall back to a shorter family's price
+        assert_eq!(
+            known_pricing("o3-mini-2025-01-31")
+                .unwrap()
+                .input_per_million,
+            1.1
+        );
+        assert_eq!(
+            known_pricing("o1-2024-12-17").unwrap().input_per_million,
+            15.0
+        );
+        assert!(known_pricing("o3x").is_none());
+
+        let registry = PricingRegistry::new().with_pricing("gpt-4o", ModelPricing::new(1.0, 2.0));
+        let pricing = registry.lookup("gpt-4o-mini").unwrap();
+        assert_eq!(pricing.cost(1_000_000, 500_000), 2.0);
+    }
+}
@@ -0,0 +1,365 @@
+//! Provider rate limits and adaptive throttling.
+//!
+//! OpenAI and Anthropic report the remaining quota with every response, in
+//! `x-ratelimit-*` and `anthropic-ratelimit-*` headers respectively, and
+//! send `retry-after` with a 429. [`RateLimitInfo::from_headers`] parses
+//! them into a typed snapshot, which providers report to telemetry. An
+//! [`AdaptiveThrottle`] shared by the provider spaces requests out as the
+//! remaining quota approaches zero, so a busy agent spreads its last
+//! requests over the reset window instead of running into 429s.
+
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use reqwest::header::HeaderMap;
+
+/// Remaining share of a quota below which requests are slowed by default
+pub const DEFAULT_THROTTLE_THRESHOLD: f64 = 0.2;
+/// Longest a request is held back by default
+pub const DEFAULT_MAX_THROTTLE_DELAY: Duration = Duration::from_secs(60);
+
+/// One quota of a provider, e.g. requests or tokens per minute
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quota {
+    pub limit: u64,
+    pub remaining: u64,
+    /// Time until the quota is fully replenished, if reported
+    pub reset_after: Option<Duration>,
+}
+
+impl Quota {
+    /// Returns the share of the quota left, between 0 and 1
+    pub fn remaining_fraction(&self) -> f64 {
+        if self.limit == 0 {
+            return 1.0;
+        }
+        (self.remaining as f64 / self.limit as f64).min(1.0)
+    }
+}
+
+/// Rate limit state reported with a provider response
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimitInfo {
+    pub provider: String,
+    pub requests: Option<Quota>,
+    pub tokens: Option<Quota>,
+    /// Anthropic's separate input and output token quotas
+    pub input_tokens: Option<Quota>,
+    pub output_tokens: Option<Quota>,
+    /// Wait the provider asked for before retrying
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimitInfo {
+    /// Parses the rate limit headers of a `provider` response, or `None` if
+    /// it sent none
+    pub fn from_headers(provider: &str, headers: &HeaderMap) -> Option<Self> {
+        let info = Self {
+            provider: provider.to_string(),
+            requests: quota(headers, "requests"),
+            tokens: quota(headers, "tokens"),
+            input_tokens: quota(headers, "input-tokens"),
+            output_tokens: quota(headers, "output-tokens"),
+            retry_after: header(headers, "retry-after")
+                .and_then(|value| value.parse().ok())
+                .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok()),
+        };
+        (info.quotas().next().is_some() || info.retry_after.is_some()).then_some(info)
+    }
+
+    /// Returns every quota reported
+    pub fn quotas(&self) -> impl Iterator<Item = &Quota> {
+        self.requests
+            .iter()
+            .chain(&self.tokens)
+            .chain(&self.input_tokens)
+            .chain(&self.output_tokens)
+    }
+
+    /// Returns the quota with the smallest share left
+    pub fn scarcest(&self) -> Option<&Quota> {
+        self.quotas()
+            .min_by(|a, b| a.remaining_fraction().total_cmp(&b.remaining_fraction()))
+    }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name)?.to_str().ok().map(str::trim)
+}
+
+/// Reads quota `kind` in either OpenAI's or Anthropic's naming
+fn quota(headers: &HeaderMap, kind: &str) -> Option<Quota> {
+    let field = |field: &str| {
+        header(headers, &format!("x-ratelimit-{}-{}", field, kind))
+            .or_else(|| header(headers, &format!("anthropic-ratelimit-{}-{}", kind, field)))
+    };
+    Some(Quota {
+        limit: field("limit")?.parse().ok()?,
+        remaining: field("remaining")?.parse().ok()?,
+        reset_after: field("reset").and_then(parse_reset),
+    })
+}
+
+/// Parses a reset time, either a duration such as `6m0s` or `20ms`
+/// (OpenAI) or an RFC 3339 timestamp (Anthropic)
+fn parse_reset(value: &str) -> Option<Duration> {
+    if let Ok(at) = DateTime::parse_from_rfc3339(value) {
+        return Some(
+            (at.with_timezone(&Utc) - Utc::now())
+                .to_std()
+                .unwrap_or_default(),
+        );
+    }
+    let mut total = Duration::ZERO;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let amount: f64 = rest[..digits].parse().ok()?;
+        rest = &rest[digits..];
+        let unit = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let seconds = match &rest[..unit] {
+            "h" => 3600.0,
+            "m" => 60.0,
+            "s" => 1.0,
+            "ms" => 0.001,
+            _ => return None,
+        };
+        total += Duration::try_from_secs_f64(amount * seconds).ok()?;
+        rest = &rest[unit..];
+    }
+    Some(total)
+}
+
+#[derive(Default)]
+struct ThrottleState {
+    latest: Option<RateLimitInfo>,
+    /// Spacing between requests
+    interval: Duration,
+    /// When the last request was let through, or will be
+    last_slot: Option<Instant>,
+    /// No request is sent before this, after a `retry-after`
+    resume_at: Option<Instant>,
+}
+
+impl ThrottleState {
+    /// Returns the earliest time the next request may go out
+    fn next_slot(&self, now: Instant) -> Instant {
+        let paced = self.last_slot.map_or(now, |last| last + self.interval);
+        now.max(paced).max(self.resume_at.unwrap_or(now))
+    }
+}
+
+/// Spaces requests out as a provider's remaining quota runs low.
+///
+/// Above the threshold share of every quota, requests go out at once.
+/// Below it, requests are let through one at a time, a growing part of the
+/// quota's reset time apart, up to all of it when nothing is left; a quota
+/// reported without a reset time doesn't pace requests. A `retry-after`
+/// holds every request back until it has passed.
+pub struct AdaptiveThrottle {
+    threshold: f64,
+    max_delay: Duration,
+    state: parking_lot::Mutex<ThrottleState>,
+}
+
+impl Default for AdaptiveThrottle {
+    fn default() -> Self {
+        Self {
+            threshold: DEFAULT_THROTTLE_THRESHOLD,
+            max_delay: DEFAULT_MAX_THROTTLE_DELAY,
+            state: parking_lot::Mutex::new(ThrottleState::default()),
+        }
+    }
+}
+
+impl AdaptiveThrottle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts slowing down below `fraction` of any quota
+    pub fn with_threshold(mut self, fraction: f64) -> Self {
+        self.threshold = fraction.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Caps the time a request is held back
+    pub fn with_max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Adjusts the pace to the rate limits reported with a response
+    pub fn observe(&self, info: RateLimitInfo) {
+        let interval = info
+            .quotas()
+            .filter(|quota| quota.remaining_fraction() < self.threshold)
+            .filter_map(|quota| {
+                let pressure = 1.0 - quota.remaining_fraction() / self.threshold;
+                Some(quota.reset_after?.mul_f64(pressure))
+            })
+            .max()
+            .unwrap_or_default()
+            .min(self.max_delay);
+        let mut state = self.state.lock();
+        state.interval = interval;
+        if interval.is_zero() {
+            state.last_slot = None;
+        }
+        if let Some(retry_after) = info.retry_after {
+            state.resume_at = Some(Instant::now() + retry_after.min(self.max_delay));
+        }
+        state.latest = Some(info);
+    }
+
+    /// Returns the last rate limits observed
+    pub fn latest(&self) -> Option<RateLimitInfo> {
+        self.state.lock().latest.clone()
+    }
+
+    /// Returns how long the next request would wait
+    pub fn delay(&self) -> Duration {
+        let now = Instant::now();
+        self.state.lock().next_slot(now).duration_since(now)
+    }
+
+    /// Books the next slot, returning how long to wait for it
+    fn reserve(&self) -> Duration {
+        let now = Instant::now();
+        let mut state = self.state.lock();
+        let slot = state.next_slot(now);
+        if !state.interval.is_zero() {
+            state.last_slot = Some(slot);
+        }
+        slot.duration_since(now)
+    }
+
+    /// Waits until the next request may be sent; concurrent callers are
+    /// let through one slot apart
+    pub async fn wait(&self) {
+        let delay = self.reserve();
+        if !delay.is_zero() {
+            tracing::debug!("Throttling provider request for {:?}", delay);
+            tokio::time::sleep(delay).await;
+        }
+    }
+}
+
+/// Reports the rate limits of a `provider` response for `model` to
+/// telemetry and to `throttle`
+#[cfg(any(feature = "openai", feature = "anthropic"))]
+pub(crate) fn observe_response(
+    provider: &str,
+    model: &str,
+    headers: &HeaderMap,
+    throttle: Option<&AdaptiveThrottle>,
+) {
+    if let Some(info) = RateLimitInfo::from_headers(provider, headers) {
+        crate::telemetry::record_rate_limit(model, &info);
+        if let Some(throttle) = throttle {
+            throttle.observe(info);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_headers_parse_and_throttle_slows_near_zero() {
+        let openai = RateLimitInfo::from_headers(
+            "openai",
+            &headers(&[
+                ("x-ratelimit-limit-requests", "100"),
+                ("x-ratelimit-remaining-requests", "90"),
+                ("x-ratelimit-reset-requests", "1m30s"),
+                ("x-ratelimit-limit-tokens", "10000"),
+                ("x-ratelimit-remaining-tokens", "1000"),
+                ("x-ratelimit-reset-tokens", "20s"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            openai.requests.unwrap().reset_after,
+            Some(Duration::from_secs(90))
+        );
+        assert_eq!(openai.scarcest().unwrap().remaining, 1000);
+
+        let reset = (Utc::now() + chrono::Duration::seconds(30)).to_rfc3339();
+        let anthropic = RateLimitInfo::from_headers(
+            "anthropic",
+            &headers(&[
+                ("anthropic-ratelimit-requests-limit", "50"),
+                ("anthropic-ratelimit-requests-remaining", "49"),
+                ("anthropic-ratelimit-requests-reset", &reset),
+                ("retry-after", "2"),
+            ]),
+        )
+        .unwrap();
+        assert!(anthropic.requests.unwrap().reset_after.unwrap() > Duration::from_secs(28));
+        assert_eq!(anthropic.retry_after, Some(Duration::from_secs(2)));
+        assert!(RateLimitInfo::from_headers("ollama", &HeaderMap::new()).is_none());
+
+        // 10% of tokens left against a 20% threshold: requests go out half
+        // the reset time apart
+        let throttle = AdaptiveThrottle::new();
+        throttle.observe(openai.clone());
+        assert_eq!(throttle.reserve(), Duration::ZERO);
+        let second = throttle.reserve();
+        assert!(second > Duration::from_secs(9) && second <= Duration::from_secs(10));
+        assert!(throttle.reserve() > Duration::from_secs(19));
+        let mut plenty = openai.clone();
+        plenty.tokens = None;
+        throttle.observe(plenty);
+        assert_eq!(throttle.delay(), Duration::ZERO);
+        throttle.observe(anthropic);
+        assert!(throttle.delay() > Duration::from_secs(1));
+        assert_eq!(throttle.latest().unwrap().provider, "anthropic");
+
+        // A quota without a reset time doesn't pace
+        let mut unknown = openai;
+        unknown.tokens.as_mut().unwrap().reset_after = None;
+        let throttle = AdaptiveThrottle::new();
+        throttle.observe(unknown);
+        throttle.reserve();
+        assert_eq!(throttle.delay(), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_anthropic_input_and_output_token_quotas() {
+        let reset = (Utc::now() + chrono::Duration::seconds(60)).to_rfc3339();
+        let info = RateLimitInfo::from_headers(
+            "anthropic",
+            &headers(&[
+                ("anthropic-ratelimit-input-tokens-limit", "40000"),
+                ("anthropic-ratelimit-input-tokens-remaining", "39000"),
+                ("anthropic-ratelimit-input-tokens-reset", &reset),
+                ("anthropic-ratelimit-output-tokens-limit", "8000"),
+                ("anthropic-ratelimit-output-tokens-remaining", "0"),
+                ("anthropic-ratelimit-output-tokens-reset", &reset),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(info.input_tokens.unwrap().remaining, 39000);
+        assert_eq!(info.scarcest().unwrap().limit, 8000);
+
+        let throttle = AdaptiveThrottle::new();
+        throttle.observe(info);
+        throttle.reserve();
+        assert!(throttle.delay() > Duration::from_secs(55));
+    }
+}
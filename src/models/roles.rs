@@ -0,0 +1,164 @@
+//! Role normalization for providers with strict turn ordering.
+//!
+//! Replayed memory rarely forms a clean conversation: windows start
+//! mid-exchange, tool results sit between turns, and retrieval adds extra
+//! system messages. Providers such as Anthropic and Gemini reject anything
+//! but alternating user/assistant turns, so [`normalize_roles`] repairs the
+//! history right before it is sent.
+
+use crate::types::{Message, Role};
+
+/// User turn inserted when a history would otherwise open with the assistant
+pub const CONTINUATION_PLACEHOLDER: &str = "(continuing our conversation)";
+
+/// Turn-ordering constraints of a provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RoleRules {
+    /// Turns must alternate between user and assistant, starting with the user
+    pub alternate: bool,
+    /// The provider has no system role, so system messages become user turns
+    pub system_as_user: bool,
+}
+
+impl RoleRules {
+    /// Any role order is accepted; only empty messages are dropped
+    pub const LENIENT: Self = Self {
+        alternate: false,
+        system_as_user: false,
+    };
+
+    /// Alternating turns with a single leading system prompt (Anthropic)
+    pub const ALTERNATING: Self = Self {
+        alternate: true,
+        system_as_user: false,
+    };
+
+    /// Alternating turns without a system role (Gemini)
+    pub const ALTERNATING_NO_SYSTEM: Self = Self {
+        alternate: true,
+        system_as_user: true,
+    };
+}
+
+/// Rewrites `messages` so they satisfy `rules`.
+///
+/// Empty messages are always dropped. Under alternation, system messages are
+/// merged into one leading prompt (or turned into user turns), tool results
+/// become user turns, consecutive turns of the same role are joined, and a
+/// placeholder user turn is inserted if the assistant would speak first.
+pub fn normalize_roles(messages: Vec<Message>, rules: RoleRules) -> Vec<Message> {
+    let messages = messages
+        .into_iter()
+        .filter(|m| !m.content.trim().is_empty());
+    if !rules.alternate {
+        return messages.collect();
+    }
+
+    let mut system: Option<Message> = None;
+    let mut turns: Vec<Message> = Vec::new();
+    for mut message in messages {
+        match message.role {
+            Role::System if !rules.system_as_user => {
+                match system.as_mut() {
+                    Some(prompt) => join(prompt, message),
+                    None => system = Some(message),
+                }
+                continue;
+            }
+            Role::System | Role::Tool => message.role = Role::User,
+            Role::User | Role::Assistant => {}
+        }
+
+        match turns.last_mut() {
+            Some(last) if last.role == message.role => join(last, message),
+            _ => turns.push(message),
+        }
+    }
+
+    if turns.first().is_some_and(|m| m.role == Role::Assistant) {
+        turns.insert(
+            0,
+            Message {
+                role: Role::User,
+                content: CONTINUATION_PLACEHOLDER.to_string(),
+                metadata: None,
+            },
+        );
+    }
+
+    system.into_iter().chain(turns).collect()
+}
+
+/// Appends `next` to `target`, keeping the metadata of `target`
+fn join(target: &mut Message, next: Message) {
+    target.content.push_str("\n\n");
+    target.content.push_str(&next.content);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn msg(role: Role, content: &str) -> Message {
+        Message {
+            role,
+            content: content.to_string(),
+            metadata: None,
+        }
+    }
+
+    fn roles(messages: &[Message]) -> Vec<Role> {
+        messages.iter().map(|m| m.role.clone()).collect()
+    }
+
+    #[test]
+    fn test_alternating_repairs_replayed_history() {
+        let messages = vec![
+            msg(Role::System, "You are helpful."),
+            msg(Role::Assistant, "Earlier answer"),
+            msg(Role::User, "Question"),
+            msg(Role::Tool, "tool output"),
+            msg(Role::User, "   "),
+            msg(Role::System, "Relevant memories"),
+            msg(Role::Assistant, "Answer"),
+            msg(Role::Assistant, "Follow-up"),
+        ];
+
+        let repaired = normalize_roles(messages, RoleRules::ALTERNATING);
+
+        assert_eq!(
+            roles(&repaired),
+            vec![
+                Role::System,
+                Role::User,
+                Role::Assistant,
+                Role::User,
+                Role::Assistant
+            ]
+        );
+        assert_eq!(repaired[0].content, "You are helpful.\n\nRelevant memories");
+        assert_eq!(repaired[1].content, CONTINUATION_PLACEHOLDER);
+        assert_eq!(repaired[3].content, "Question\n\ntool output");
+        assert_eq!(repaired[4].content, "Answer\n\nFollow-up");
+    }
+
+    #[test]
+    fn test_system_as_user_and_lenient() {
+        let messages = vec![
+            msg(Role::System, "Be brief."),
+            msg(Role::User, "Hi"),
+            msg(Role::Assistant, ""),
+            msg(Role::Assistant, "Hello"),
+        ];
+
+        let repaired = normalize_roles(messages.clone(), RoleRules::ALTERNATING_NO_SYSTEM);
+        assert_eq!(roles(&repaired), vec![Role::User, Role::Assistant]);
+        assert_eq!(repaired[0].content, "Be brief.\n\nHi");
+
+        let lenient = normalize_roles(messages, RoleRules::LENIENT);
+        assert_eq!(
+            roles(&lenient),
+            vec![Role::System, Role::User, Role::Assistant]
+        );
+    }
+}
@@ -0,0 +1,191 @@
+//! Routing between fast and strong models.
+//!
+//! Most turns don't need the strongest model. A [`ModelRoutingPolicy`]
+//! classifies each input with [`classify_query`] and sends complex queries
+//! to a strong model and everything else to a fast, cheap one. Each
+//! [`ModelRoute`] has its own fallbacks, tried in order when its model fails.
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::stream::BoxStream;
+
+use crate::error::{AgentError, Result};
+use crate::models::{Capabilities, GenerationSettings, LLM};
+use crate::query::{classify_query, QueryType};
+use crate::types::{File, GenerationResponse, Message};
+
+/// Response metadata key naming the route that served a turn
+pub const MODEL_ROUTE_KEY: &str = "model_route";
+
+/// A model plus the fallbacks tried, in order, when it fails.
+///
+/// Behaves as a single [`LLM`] reporting the primary model's name and
+/// capabilities, so context budgets are sized for the primary.
+pub struct ModelRoute {
+    model: Arc<dyn LLM>,
+    fallbacks: Vec<Arc<dyn LLM>>,
+}
+
+impl ModelRoute {
+    pub fn new(model: Arc<dyn LLM>) -> Self {
+        Self {
+            model,
+            fallbacks: Vec::new(),
+        }
+    }
+
+    /// Tries `model` if everything before it failed
+    pub fn with_fallback(mut self, model: Arc<dyn LLM>) -> Self {
+        self.fallbacks.push(model);
+        self
+    }
+}
+
+#[async_trait]
+impl LLM for ModelRoute {
+    async fn generate(
+        &self,
+        messages: Vec<Message>,
+        files: Option<Vec<File>>,
+    ) -> Result<GenerationResponse> {
+        self.generate_with(messages, files, &GenerationSettings::default())
+            .await
+    }
+
+    async fn generate_with(
+        &self,
+        messages: Vec<Message>,
+        files: Option<Vec<File>>,
+        settings: &GenerationSettings,
+    ) -> Result<GenerationResponse> {
+        let mut last_error = None;
+        for model in std::iter::once(&self.model).chain(&self.fallbacks) {
+            match model
+                .generate_with(messages.clone(), files.clone(), settings)
+                .await
+            {
+                Ok(response) => return Ok(response),
+                Err(e) => {
+                    tracing::warn!(model = model.model_name(), "Model failed: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error
+            .unwrap_or_else(|| AgentError::ModelError("No model to route to".to_string())))
+    }
+
+    async fn generate_stream(
+        &self,
+        messages: Vec<Message>,
+        files: Option<Vec<File>>,
+    ) -> Result<BoxStream<'static, Result<String>>> {
+        // Falls back only while opening the stream, not once chunks flow
+        let mut last_error = None;
+        for model in std::iter::once(&self.model).chain(&self.fallbacks) {
+            match model.generate_stream(messages.clone(), files.clone()).await {
+                Ok(chunks) => return Ok(chunks),
+                Err(e) => {
+                    tracing::warn!(model = model.model_name(), "Model failed: {}", e);
+                    last_error = Some(e);
+                }
+            }
+        }
+        Err(last_error
+            .unwrap_or_else(|| AgentError::ModelError("No model to route to".to_string())))
+    }
+
+    fn model_name(&self) -> &str {
+        self.model.model_name()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.model.capabilities()
+    }
+}
+
+/// Sends complex queries to a strong model and the rest to a fast one
+pub struct ModelRoutingPolicy {
+    fast: Arc<ModelRoute>,
+    strong: Arc<ModelRoute>,
+}
+
+impl ModelRoutingPolicy {
+    pub fn new(fast: ModelRoute, strong: ModelRoute) -> Self {
+        Self {
+            fast: Arc::new(fast),
+            strong: Arc::new(strong),
+        }
+    }
+
+    /// Picks the route for `input`, returning its name and model
+    pub fn route(&self, input: &str) -> (&'static str, Arc<dyn LLM>) {
+        match classify_query(input) {
+            QueryType::Complex => ("strong", Arc::clone(&self.strong) as Arc<dyn LLM>),
+            QueryType::Math | QueryType::ShortFactoid | QueryType::Unknown => {
+                ("fast", Arc::clone(&self.fast) as Arc<dyn LLM>)
+            }
+        }
+    }
+}
+
+impl std::fmt::Debug for ModelRoutingPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ModelRoutingPolicy")
+            .field("fast", &self.fast.model_name())
+            .field("strong", &self.strong.model_name())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Replies with its name, or fails
+    struct NamedLLM(&'static str, bool);
+
+    #[async_trait]
+    impl LLM for NamedLLM {
+        async fn generate(
+            &self,
+            _messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<GenerationResponse> {
+            if self.1 {
+                return Err(AgentError::ModelError(format!("{} is down", self.0)));
+            }
+            Ok(GenerationResponse {
+                content: self.0.to_string(),
+                metadata: None,
+            })
+        }
+
+        fn model_name(&self) -> &str {
+            self.0
+        }
+    }
+
+    #[tokio::test]
+    async fn test_routes_by_query_type_with_fallback() {
+        let policy = ModelRoutingPolicy::new(
+            ModelRoute::new(Arc::new(NamedLLM("mini", true)))
+                .with_fallback(Arc::new(NamedLLM("mini-backup", false))),
+            ModelRoute::new(Arc::new(NamedLLM("large", false))),
+        );
+
+        let (route, model) = policy.route("Explain how the borrow checker works");
+        assert_eq!(route, "strong");
+        assert_eq!(
+            model.generate(Vec::new(), None).await.unwrap().content,
+            "large"
+        );
+
+        let (route, model) = policy.route("What is Rust?");
+        assert_eq!(route, "fast");
+        assert_eq!(model.model_name(), "mini");
+        let response = model.generate(Vec::new(), None).await.unwrap();
+        assert_eq!(response.content, "mini-backup");
+    }
+}
@@ -0,0 +1,215 @@
+//! Token counting for context budgets.
+//!
+//! Budgets are only as good as the token counts behind them. A [`Tokenizer`]
+//! counts tokens the way a model does: `TiktokenTokenizer` (feature
+//! `tiktoken`) for OpenAI encodings, `HfTokenizer` (feature `hf-tokenizers`)
+//! for any Hugging Face `tokenizer.json`, and [`HeuristicTokenizer`] as the
+//! fallback. [`TokenizerRegistry`] picks one per model id, like
+//! [`ModelLimitRegistry`](super::ModelLimitRegistry) does for limits.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Counts the tokens a model sees in a text
+pub trait Tokenizer: Send + Sync {
+    fn count_tokens(&self, text: &str) -> usize;
+}
+
+/// Estimates 4 bytes per token, close for English prose on most models
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HeuristicTokenizer;
+
+impl Tokenizer for HeuristicTokenizer {
+    fn count_tokens(&self, text: &str) -> usize {
+        text.len() / 4
+    }
+}
+
+#[cfg(feature = "tiktoken")]
+pub use self::tiktoken_impl::TiktokenTokenizer;
+
+#[cfg(feature = "tiktoken")]
+mod tiktoken_impl {
+    use std::sync::{Arc, OnceLock};
+
+    use tiktoken_rs::CoreBPE;
+
+    use super::Tokenizer;
+    use crate::error::{AgentError, Result};
+
+    /// OpenAI BPE encodings via tiktoken-rs
+    #[derive(Clone)]
+    pub struct TiktokenTokenizer {
+        bpe: Arc<CoreBPE>,
+    }
+
+    impl TiktokenTokenizer {
+        /// Uses the encoding of an OpenAI model, e.g. `gpt-4o` or `gpt-3.5-turbo`
+        pub fn for_model(model: &str) -> Result<Self> {
+            tiktoken_rs::get_bpe_from_model(model)
+                .map(Self::from_bpe)
+                .map_err(|e| AgentError::ConfigError(format!("Tokenizer for {}: {}", model, e)))
+        }
+
+        /// The `cl100k_base` encoding of GPT-4 and GPT-3.5
+        pub fn cl100k_base() -> Result<Self> {
+            tiktoken_rs::cl100k_base()
+                .map(Self::from_bpe)
+                .map_err(|e| AgentError::ConfigError(format!("Tokenizer: {}", e)))
+        }
+
+        /// The `o200k_base` encoding of GPT-4o and the o-series
+        pub fn o200k_base() -> Result<Self> {
+            tiktoken_rs::o200k_base()
+                .map(Self::from_bpe)
+                .map_err(|e| AgentError::ConfigError(format!("Tokenizer: {}", e)))
+        }
+
+        fn from_bpe(bpe: CoreBPE) -> Self {
+            Self { bpe: Arc::new(bpe) }
+        }
+    }
+
+    impl Tokenizer for TiktokenTokenizer {
+        fn count_tokens(&self, text: &str) -> usize {
+            self.bpe.encode_with_special_tokens(text).len()
+        }
+    }
+
+    /// Built-in tokenizer for OpenAI model ids, loaded once per encoding
+    pub(super) fn builtin(model: &str) -> Option<Arc<dyn Tokenizer>> {
+        static CL100K: OnceLock<Option<TiktokenTokenizer>> = OnceLock::new();
+        static O200K: OnceLock<Option<TiktokenTokenizer>> = OnceLock::new();
+
+        let o200k = ["gpt-4o", "gpt-4.1", "gpt-5", "o1", "o3", "o4"];
+        let cl100k = ["gpt-4", "gpt-3.5", "text-embedding-3", "text-embedding-ada"];
+        let tokenizer = if o200k.iter().any(|prefix| model.starts_with(prefix)) {
+            O200K.get_or_init(|| TiktokenTokenizer::o200k_base().ok())
+        } else if cl100k.iter().any(|prefix| model.starts_with(prefix)) {
+            CL100K.get_or_init(|| TiktokenTokenizer::cl100k_base().ok())
+        } else {
+            return None;
+        };
+        tokenizer.clone().map(|t| Arc::new(t) as Arc<dyn Tokenizer>)
+    }
+}
+
+#[cfg(feature = "hf-tokenizers")]
+pub use self::hf_impl::HfTokenizer;
+
+#[cfg(feature = "hf-tokenizers")]
+mod hf_impl {
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use super::{HeuristicTokenizer, Tokenizer};
+    use crate::error::{AgentError, Result};
+
+    /// Any Hugging Face tokenizer, e.g. for Llama, Mistral or Qwen models
+    #[derive(Clone)]
+    pub struct HfTokenizer {
+        inner: Arc<tokenizers::Tokenizer>,
+    }
+
+    impl HfTokenizer {
+        /// Loads a `tokenizer.json` file
+        pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+            tokenizers::Tokenizer::from_file(path)
+                .map(|inner| Self {
+                    inner: Arc::new(inner),
+                })
+                .map_err(|e| AgentError::ConfigError(format!("Tokenizer: {}", e)))
+        }
+
+        /// Loads a `tokenizer.json` already in memory
+        pub fn from_bytes(data: impl AsRef<[u8]>) -> Result<Self> {
+            tokenizers::Tokenizer::from_bytes(data)
+                .map(|inner| Self {
+                    inner: Arc::new(inner),
+                })
+                .map_err(|e| AgentError::ConfigError(format!("Tokenizer: {}", e)))
+        }
+    }
+
+    impl Tokenizer for HfTokenizer {
+        fn count_tokens(&self, text: &str) -> usize {
+            match self.inner.encode(text, false) {
+                Ok(encoding) => encoding.len(),
+                // Rare malformed input shouldn't break budgeting
+                Err(_) => HeuristicTokenizer.count_tokens(text),
+            }
+        }
+    }
+}
+
+/// Tokenizers selected per model id, with user overrides.
+///
+/// Overrides match by the longest prefix. Without one, OpenAI models use
+/// their tiktoken encoding when the `tiktoken` feature is on, and everything
+/// else falls back to [`HeuristicTokenizer`].
+#[derive(Clone, Default)]
+pub struct TokenizerRegistry {
+    overrides: HashMap<String, Arc<dyn Tokenizer>>,
+}
+
+impl TokenizerRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Uses `tokenizer` for model ids starting with `prefix`
+    pub fn with_tokenizer(
+        mut self,
+        prefix: impl Into<String>,
+        tokenizer: Arc<dyn Tokenizer>,
+    ) -> Self {
+        self.overrides.insert(prefix.into(), tokenizer);
+        self
+    }
+
+    /// Returns the tokenizer for `model`
+    pub fn lookup(&self, model: &str) -> Arc<dyn Tokenizer> {
+        let matched = self
+            .overrides
+            .iter()
+            .filter(|(prefix, _)| model.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, tokenizer)| Arc::clone(tokenizer));
+        if let Some(tokenizer) = matched {
+            return tokenizer;
+        }
+
+        #[cfg(feature = "tiktoken")]
+        if let Some(tokenizer) = tiktoken_impl::builtin(model) {
+            return tokenizer;
+        }
+
+        Arc::new(HeuristicTokenizer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Counts whitespace-separated words
+    struct WordTokenizer;
+
+    impl Tokenizer for WordTokenizer {
+        fn count_tokens(&self, text: &str) -> usize {
+            text.split_whitespace().count()
+        }
+    }
+
+    #[test]
+    fn test_registry_prefers_longest_override() {
+        let registry = TokenizerRegistry::new()
+            .with_tokenizer("llama", Arc::new(HeuristicTokenizer))
+            .with_tokenizer("llama3", Arc::new(WordTokenizer));
+
+        let text = "one two three four five six seven eight";
+        assert_eq!(registry.lookup("llama3.1:8b").count_tokens(text), 8);
+        assert_eq!(registry.lookup("llama2").count_tokens(text), 9);
+        assert_eq!(registry.lookup("my-finetune").count_tokens(text), 9);
+    }
+}
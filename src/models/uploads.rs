@@ -0,0 +1,161 @@
+//! Provider-side storage for large attachments.
+//!
+//! Sending an attachment inline re-encodes it as base64 on every call, and
+//! an agent sends the same attachments turn after turn. Providers with a
+//! file API can store the file once and take a reference instead. An
+//! [`UploadManager`] uploads each attachment above a size threshold through
+//! a provider's [`FileUploader`], keeps the returned reference keyed by the
+//! content hash, and reuses it until shortly before the provider expires it.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+
+use crate::blob::content_hash;
+use crate::error::Result;
+use crate::types::File;
+
+/// Attachments larger than this many bytes are uploaded by default
+pub const DEFAULT_UPLOAD_THRESHOLD: usize = 256 * 1024;
+
+/// Uploads expiring within this many seconds are uploaded again rather
+/// than referenced by a request that may outlive them
+const EXPIRY_MARGIN_SECS: i64 = 600;
+
+/// A file stored with a provider
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UploadedFile {
+    /// File id or URI to reference in requests
+    pub id: String,
+    pub mime_type: String,
+    /// When the provider deletes the file, if it does
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl UploadedFile {
+    fn is_fresh(&self, now: DateTime<Utc>) -> bool {
+        self.expires_at.is_none_or(|expires_at| {
+            expires_at - chrono::Duration::seconds(EXPIRY_MARGIN_SECS) > now
+        })
+    }
+}
+
+/// Stores files with a provider's file API
+#[async_trait]
+pub trait FileUploader: Send + Sync {
+    async fn upload(&self, file: &File) -> Result<UploadedFile>;
+}
+
+/// Uploads large attachments once and reuses their references
+pub struct UploadManager {
+    uploader: Arc<dyn FileUploader>,
+    threshold: usize,
+    // Content hash -> upload; the async lock makes concurrent turns sending
+    // the same file wait for one upload
+    uploads: parking_lot::Mutex<HashMap<String, Arc<tokio::sync::Mutex<Option<UploadedFile>>>>>,
+}
+
+impl UploadManager {
+    /// Uploads attachments above [`DEFAULT_UPLOAD_THRESHOLD`] bytes through
+    /// `uploader`
+    pub fn new(uploader: Arc<dyn FileUploader>) -> Self {
+        Self {
+            uploader,
+            threshold: DEFAULT_UPLOAD_THRESHOLD,
+            uploads: parking_lot::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the size in bytes above which attachments are uploaded
+    pub fn with_threshold(mut self, bytes: usize) -> Self {
+        self.threshold = bytes;
+        self
+    }
+
+    /// Returns the stored copy of `file`, uploading it unless a fresh one
+    /// exists, or `None` if `file` is small enough to send inline
+    pub async fn upload(&self, file: &File) -> Result<Option<UploadedFile>> {
+        if file.data.len() <= self.threshold {
+            return Ok(None);
+        }
+        let hash = content_hash(&file.data);
+        let slot = Arc::clone(self.uploads.lock().entry(hash).or_default());
+        let mut slot = slot.lock().await;
+        if let Some(uploaded) = slot.as_ref().filter(|u| u.is_fresh(Utc::now())) {
+            return Ok(Some(uploaded.clone()));
+        }
+        let uploaded = self.uploader.upload(file).await?;
+        *slot = Some(uploaded.clone());
+        Ok(Some(uploaded))
+    }
+
+    /// Returns how many distinct files have been uploaded
+    pub fn len(&self) -> usize {
+        self.uploads.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Counts uploads; files it stores expire at once when asked to
+    struct CountingUploader {
+        uploads: AtomicUsize,
+        expire: bool,
+    }
+
+    #[async_trait]
+    impl FileUploader for CountingUploader {
+        async fn upload(&self, file: &File) -> Result<UploadedFile> {
+            let n = self.uploads.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            Ok(UploadedFile {
+                id: format!("files/{}", n),
+                mime_type: file.mime_type.clone(),
+                expires_at: self.expire.then(Utc::now),
+            })
+        }
+    }
+
+    fn pdf(data: &[u8]) -> File {
+        File {
+            mime_type: "application/pdf".to_string(),
+            data: data.to_vec(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_large_files_are_uploaded_once() {
+        let uploader = Arc::new(CountingUploader {
+            uploads: AtomicUsize::new(0),
+            expire: false,
+        });
+        let manager = UploadManager::new(uploader.clone()).with_threshold(4);
+
+        assert!(manager.upload(&pdf(b"tiny")).await.unwrap().is_none());
+        let report = pdf(b"quarterly report");
+        let (first, second) = tokio::join!(manager.upload(&report), manager.upload(&report));
+        assert_eq!(first.unwrap(), second.unwrap());
+        assert_eq!(uploader.uploads.load(Ordering::SeqCst), 1);
+        manager.upload(&pdf(b"another report")).await.unwrap();
+        assert_eq!(manager.len(), 2);
+
+        // Expiring uploads are replaced
+        let uploader = Arc::new(CountingUploader {
+            uploads: AtomicUsize::new(0),
+            expire: true,
+        });
+        let manager = UploadManager::new(uploader.clone()).with_threshold(4);
+        let first = manager.upload(&report).await.unwrap().unwrap();
+        let second = manager.upload(&report).await.unwrap().unwrap();
+        assert_ne!(first.id, second.id);
+    }
+}
@@ -0,0 +1,299 @@
+//! Structured execution plans.
+//!
+//! An [`ExecutionPlan`] lists the steps needed to reach a goal: each
+//! [`PlanStep`] binds a tool or sub-agent, carries an arguments template,
+//! and names the steps it depends on. Plans serialize to JSON, so they can
+//! be shown on an approval screen before anything runs, and
+//! [`Agent::check_plan`](crate::Agent::check_plan) verifies that every step
+//! binds to something the agent actually has.
+//! [`Agent::run_plan`](crate::Agent::run_plan) then runs it, and
+//! [`flow`](crate::flow) builds common multi-agent plans fluently.
+
+use std::collections::{HashMap, HashSet};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::{AgentError, Result};
+
+/// Placeholder id standing for the input a plan is run with, as in
+/// `{{input}}`
+pub const PLAN_INPUT: &str = "input";
+
+/// What a plan step invokes
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum StepTarget {
+    Tool { name: String },
+    SubAgent { name: String },
+}
+
+/// One step of an [`ExecutionPlan`]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PlanStep {
+    /// Unique within the plan; other steps refer to it by this id
+    pub id: String,
+    pub target: StepTarget,
+    /// Arguments template; `{{step_id}}` in a string stands for that step's
+    /// output and `{{input}}` for the plan's input
+    #[serde(default)]
+    pub arguments: HashMap<String, Value>,
+    /// Steps whose output this one needs
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+impl PlanStep {
+    /// A step invoking the tool `name`
+    pub fn tool(id: impl Into<String>, name: impl Into<String>) -> Self {
+        Self::new(id, StepTarget::Tool { name: name.into() })
+    }
+
+    /// A step delegating to the sub-agent `name`
+    pub fn subagent(id: impl Into<String>, name: impl Into<String>) -> Self {
+        Self::new(id, StepTarget::SubAgent { name: name.into() })
+    }
+
+    fn new(id: impl Into<String>, target: StepTarget) -> Self {
+        Self {
+            id: id.into(),
+            target,
+            arguments: HashMap::new(),
+            depends_on: Vec::new(),
+            description: None,
+        }
+    }
+
+    pub fn with_argument(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.arguments.insert(key.into(), value.into());
+        self
+    }
+
+    /// Runs this step only after `step_id`
+    pub fn after(mut self, step_id: impl Into<String>) -> Self {
+        self.depends_on.push(step_id.into());
+        self
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Fills `{{step_id}}` placeholders in the arguments with step outputs.
+    ///
+    /// Placeholders of steps without an output are left as they are, and
+    /// placeholders inside an output are not filled in turn.
+    pub fn render_arguments(&self, outputs: &HashMap<String, String>) -> HashMap<String, Value> {
+        self.arguments
+            .iter()
+            .map(|(key, value)| (key.clone(), render_value(value, outputs)))
+            .collect()
+    }
+}
+
+fn render_value(value: &Value, outputs: &HashMap<String, String>) -> Value {
+    match value {
+        Value::String(text) => Value::String(render_text(text, outputs)),
+        Value::Array(items) => {
+            Value::Array(items.iter().map(|v| render_value(v, outputs)).collect())
+        }
+        Value::Object(fields) => Value::Object(
+            fields
+                .iter()
+                .map(|(k, v)| (k.clone(), render_value(v, outputs)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Fills the placeholders of `text` in one pass over it
+fn render_text(text: &str, outputs: &HashMap<String, String>) -> String {
+    let mut rendered = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        match outputs.get(&after[..end]) {
+            Some(output) => rendered.push_str(output),
+            None => rendered.push_str(&rest[start..start + end + 4]),
+        }
+        rest = &after[end + 2..];
+    }
+    rendered.push_str(rest);
+    rendered
+}
+
+/// Joins several outputs into one text, each under its label
+pub(crate) fn join_labeled<'a>(outputs: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    outputs
+        .into_iter()
+        .map(|(label, output)| format!("{}:\n{}", label, output))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// Steps to reach a goal, with the dependencies between them
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExecutionPlan {
+    pub goal: String,
+    pub steps: Vec<PlanStep>,
+}
+
+impl ExecutionPlan {
+    pub fn new(goal: impl Into<String>) -> Self {
+        Self {
+            goal: goal.into(),
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn with_step(mut self, step: PlanStep) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    /// Returns the step with the given id
+    pub fn step(&self, id: &str) -> Option<&PlanStep> {
+        self.steps.iter().find(|step| step.id == id)
+    }
+
+    /// Checks that step ids are unique and not [`PLAN_INPUT`], dependencies
+    /// exist, and there are no cycles
+    pub fn validate(&self) -> Result<()> {
+        self.execution_order().map(|_| ())
+    }
+
+    /// Returns the steps in an order that respects their dependencies.
+    ///
+    /// Independent steps keep their order in the plan. Fails like
+    /// [`ExecutionPlan::validate`] on a malformed plan.
+    pub fn execution_order(&self) -> Result<Vec<&PlanStep>> {
+        Ok(self.stages()?.into_iter().flatten().collect())
+    }
+
+    /// Groups the steps into stages that can run one after another, each
+    /// stage's steps depending only on earlier stages.
+    ///
+    /// Fails like [`ExecutionPlan::validate`] on a malformed plan.
+    pub fn stages(&self) -> Result<Vec<Vec<&PlanStep>>> {
+        let mut ids = HashSet::new();
+        for step in &self.steps {
+            if step.id == PLAN_INPUT {
+                return Err(AgentError::InvalidState(format!(
+                    "Plan step id {} is reserved for the plan's input",
+                    PLAN_INPUT
+                )));
+            }
+            if !ids.insert(step.id.as_str()) {
+                return Err(AgentError::InvalidState(format!(
+                    "Duplicate plan step id: {}",
+                    step.id
+                )));
+            }
+        }
+        for step in &self.steps {
+            if let Some(missing) = step.depends_on.iter().find(|d| !ids.contains(d.as_str())) {
+                return Err(AgentError::InvalidState(format!(
+                    "Plan step {} depends on unknown step {}",
+                    step.id, missing
+                )));
+            }
+        }
+
+        let mut done: HashSet<&str> = HashSet::new();
+        let mut stages = Vec::new();
+        while done.len() < self.steps.len() {
+            let ready: Vec<&PlanStep> = self
+                .steps
+                .iter()
+                .filter(|step| !done.contains(step.id.as_str()))
+                .filter(|step| step.depends_on.iter().all(|d| done.contains(d.as_str())))
+                .collect();
+            if ready.is_empty() {
+                return Err(AgentError::InvalidState(
+                    "Plan steps depend on each other in a cycle".to_string(),
+                ));
+            }
+            done.extend(ready.iter().map(|step| step.id.as_str()));
+            stages.push(ready);
+        }
+        Ok(stages)
+    }
+
+    /// Returns the steps no other step depends on, whose outputs are the
+    /// plan's result
+    pub fn final_steps(&self) -> Vec<&PlanStep> {
+        self.steps
+            .iter()
+            .filter(|step| {
+                !self
+                    .steps
+                    .iter()
+                    .any(|other| other.depends_on.contains(&step.id))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plan_orders_steps_and_renders_arguments() {
+        let plan = ExecutionPlan::new("Summarize the weather")
+            .with_step(
+                PlanStep::subagent("write", "writer")
+                    .with_argument("input", "Summarize: {{fetch}}")
+                    .after("fetch"),
+            )
+            .with_step(PlanStep::tool("fetch", "weather").with_argument("city", "Oslo"));
+
+        let order: Vec<&str> = plan
+            .execution_order()
+            .unwrap()
+            .iter()
+            .map(|step| step.id.as_str())
+            .collect();
+        assert_eq!(order, ["fetch", "write"]);
+
+        let outputs = HashMap::from([("fetch".to_string(), "rain".to_string())]);
+        let arguments = plan.step("write").unwrap().render_arguments(&outputs);
+        assert_eq!(arguments["input"], "Summarize: rain");
+
+        // Outputs are inserted as they are, even when they look like
+        // placeholders themselves
+        let outputs = HashMap::from([
+            ("fetch".to_string(), "{{input}}".to_string()),
+            ("input".to_string(), "secret".to_string()),
+        ]);
+        let arguments = plan.step("write").unwrap().render_arguments(&outputs);
+        assert_eq!(arguments["input"], "Summarize: {{input}}");
+        let text = render_text("{{missing}} and {{fetch}} {{", &outputs);
+        assert_eq!(text, "{{missing}} and {{input}} {{");
+
+        let json = serde_json::to_value(&plan).unwrap();
+        assert_eq!(json["steps"][1]["target"]["kind"], "tool");
+        let parsed: ExecutionPlan = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, plan);
+
+        let shadowing = plan
+            .clone()
+            .with_step(PlanStep::tool(PLAN_INPUT, "weather"));
+        assert!(shadowing.validate().is_err());
+
+        let cyclic = plan.with_step(PlanStep::tool("loop", "weather").after("loop"));
+        assert!(matches!(
+            cyclic.validate(),
+            Err(AgentError::InvalidState(_))
+        ));
+    }
+}
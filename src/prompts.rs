@@ -0,0 +1,346 @@
+//! Named, versioned prompt templates with weighted A/B variants.
+//!
+//! Every registration of a prompt name produces a new [`PromptVersion`]. Each
+//! variant serves its latest version, and sessions are assigned to variants
+//! deterministically by weight so a conversation keeps seeing the same prompt.
+//! The chosen version is exposed as metadata so responses can be attributed
+//! to the prompt that produced them.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::error::{AgentError, Result};
+use crate::memory::{MemoryRecord, MemoryStore};
+
+/// Variant used by [`PromptRegistry::register`]
+pub const DEFAULT_VARIANT: &str = "control";
+
+/// Session id under which prompt versions are persisted in a `MemoryStore`
+pub const PROMPT_REGISTRY_SESSION: &str = "__prompt_registry";
+
+/// Metadata key holding the prompt name that produced a response
+pub const PROMPT_NAME_KEY: &str = "prompt_name";
+/// Metadata key holding the prompt version that produced a response
+pub const PROMPT_VERSION_KEY: &str = "prompt_version";
+/// Metadata key holding the prompt variant that produced a response
+pub const PROMPT_VARIANT_KEY: &str = "prompt_variant";
+
+const PROMPT_WEIGHT_KEY: &str = "prompt_weight";
+const LOAD_PAGE_SIZE: usize = 200;
+
+/// A single immutable revision of a prompt template
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PromptVersion {
+    pub name: String,
+    pub version: u32,
+    pub variant: String,
+    pub template: String,
+    /// Relative share of sessions routed to this variant; 0 disables it
+    pub weight: u32,
+    pub created_at: DateTime<Utc>,
+}
+
+impl PromptVersion {
+    /// Renders the template, replacing `{{key}}` placeholders with `vars`
+    pub fn render(&self, vars: &HashMap<String, String>) -> String {
+        vars.iter()
+            .fold(self.template.clone(), |acc, (key, value)| {
+                acc.replace(&format!("{{{{{}}}}}", key), value)
+            })
+    }
+
+    /// Attribution metadata recorded alongside responses
+    pub fn metadata(&self) -> HashMap<String, String> {
+        HashMap::from([
+            (PROMPT_NAME_KEY.to_string(), self.name.clone()),
+            (PROMPT_VERSION_KEY.to_string(), self.version.to_string()),
+            (PROMPT_VARIANT_KEY.to_string(), self.variant.clone()),
+        ])
+    }
+}
+
+/// Registry of prompt templates, optionally persisted to a `MemoryStore`
+#[derive(Default)]
+pub struct PromptRegistry {
+    prompts: parking_lot::RwLock<HashMap<String, Vec<PromptVersion>>>,
+    store: Option<Arc<dyn MemoryStore>>,
+}
+
+impl PromptRegistry {
+    /// Creates an empty in-process registry
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Persists every registered version to `store`
+    pub fn with_store(mut self, store: Arc<dyn MemoryStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Registers a new version of the default variant
+    pub async fn register(
+        &self,
+        name: impl Into<String>,
+        template: impl Into<String>,
+    ) -> Result<PromptVersion> {
+        self.register_variant(name, DEFAULT_VARIANT, template, 1)
+            .await
+    }
+
+    /// Registers a new version of `variant` with the given routing weight
+    pub async fn register_variant(
+        &self,
+        name: impl Into<String>,
+        variant: impl Into<String>,
+        template: impl Into<String>,
+        weight: u32,
+    ) -> Result<PromptVersion> {
+        let name = name.into();
+        let prompt = {
+            let mut prompts = self.prompts.write();
+            let versions = prompts.entry(name.clone()).or_default();
+            let prompt = PromptVersion {
+                name,
+                version: versions.last().map_or(1, |v| v.version + 1),
+                variant: variant.into(),
+                template: template.into(),
+                weight,
+                created_at: Utc::now(),
+            };
+            versions.push(prompt.clone());
+            prompt
+        };
+
+        if let Some(store) = &self.store {
+            store.store(prompt_to_record(&prompt)).await?;
+        }
+
+        Ok(prompt)
+    }
+
+    /// Returns the latest version of the default variant
+    pub fn get(&self, name: &str) -> Option<PromptVersion> {
+        self.latest(name, DEFAULT_VARIANT)
+    }
+
+    /// Returns the latest version of a specific variant
+    pub fn latest(&self, name: &str, variant: &str) -> Option<PromptVersion> {
+        let prompts = self.prompts.read();
+        prompts
+            .get(name)?
+            .iter()
+            .rev()
+            .find(|v| v.variant == variant)
+            .cloned()
+    }
+
+    /// Returns an exact version of a prompt
+    pub fn get_version(&self, name: &str, version: u32) -> Option<PromptVersion> {
+        let prompts = self.prompts.read();
+        prompts
+            .get(name)?
+            .iter()
+            .find(|v| v.version == version)
+            .cloned()
+    }
+
+    /// Returns every version of a prompt, oldest first
+    pub fn history(&self, name: &str) -> Vec<PromptVersion> {
+        let prompts = self.prompts.read();
+        prompts.get(name).cloned().unwrap_or_default()
+    }
+
+    /// Picks the variant serving `session_id`.
+    ///
+    /// The choice is weighted across the latest version of each variant and
+    /// stable for a given session, so A/B assignments survive restarts.
+    pub fn select(&self, name: &str, session_id: &str) -> Option<PromptVersion> {
+        let prompts = self.prompts.read();
+        let versions = prompts.get(name)?;
+
+        let mut candidates: Vec<&PromptVersion> = Vec::new();
+        for version in versions.iter().rev() {
+            if !candidates.iter().any(|c| c.variant == version.variant) {
+                candidates.push(version);
+            }
+        }
+        candidates.retain(|c| c.weight > 0);
+        candidates.sort_by(|a, b| a.variant.cmp(&b.variant));
+
+        weighted_pick(
+            &candidates,
+            |c| c.weight,
+            &format!("{}:{}", name, session_id),
+        )
+        .map(|c| (*c).clone())
+    }
+
+    /// Reloads all persisted versions from the backing store, returning how many were read
+    pub async fn load(&self) -> Result<usize> {
+        let store = self.store.as_ref().ok_or_else(|| {
+            AgentError::ConfigError("Prompt registry has no backing store".to_string())
+        })?;
+
+        let mut loaded: HashMap<String, Vec<PromptVersion>> = HashMap::new();
+        let mut count = 0;
+        let mut cursor: Option<String> = None;
+        loop {
+            let page = store
+                .retrieve_page(PROMPT_REGISTRY_SESSION, cursor.as_deref(), LOAD_PAGE_SIZE)
+                .await?;
+            for record in &page.records {
+                let prompt = record_to_prompt(record)?;
+                loaded.entry(prompt.name.clone()).or_default().push(prompt);
+                count += 1;
+            }
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        for versions in loaded.values_mut() {
+            versions.sort_by_key(|v| v.version);
+        }
+        *self.prompts.write() = loaded;
+        Ok(count)
+    }
+}
+
+fn prompt_to_record(prompt: &PromptVersion) -> MemoryRecord {
+    let mut metadata = prompt.metadata();
+    metadata.insert(PROMPT_WEIGHT_KEY.to_string(), prompt.weight.to_string());
+
+    MemoryRecord {
+        id: Uuid::new_v4(),
+        session_id: PROMPT_REGISTRY_SESSION.to_string(),
+        role: "prompt".to_string(),
+        content: prompt.template.clone(),
+        importance: 0.0,
+        timestamp: prompt.created_at,
+        metadata: Some(metadata),
+        embedding: None,
+        version: 0,
+        expires_at: None,
+    }
+}
+
+fn record_to_prompt(record: &MemoryRecord) -> Result<PromptVersion> {
+    let invalid = || AgentError::MemoryError(format!("Invalid prompt record {}", record.id));
+    let metadata = record.metadata.as_ref().ok_or_else(invalid)?;
+    let field = |key: &str| metadata.get(key).cloned().ok_or_else(invalid);
+
+    Ok(PromptVersion {
+        name: field(PROMPT_NAME_KEY)?,
+        version: field(PROMPT_VERSION_KEY)?.parse().map_err(|_| invalid())?,
+        variant: field(PROMPT_VARIANT_KEY)?,
+        template: record.content.clone(),
+        weight: field(PROMPT_WEIGHT_KEY)?.parse().map_err(|_| invalid())?,
+        created_at: record.timestamp,
+    })
+}
+
+/// Picks one of `items` by weight, deterministically for a given `key`
+pub(crate) fn weighted_pick<'a, T>(
+    items: &'a [T],
+    weight: impl Fn(&T) -> u32,
+    key: &str,
+) -> Option<&'a T> {
+    let total: u64 = items.iter().map(|item| weight(item) as u64).sum();
+    if total == 0 {
+        return None;
+    }
+
+    let mut point = stable_hash(key) % total;
+    for item in items {
+        let share = weight(item) as u64;
+        if point < share {
+            return Some(item);
+        }
+        point -= share;
+    }
+    None
+}
+
+/// FNV-1a, stable across processes unlike `DefaultHasher`
+fn stable_hash(input: &str) -> u64 {
+    input.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::InMemoryStore;
+
+    #[tokio::test]
+    async fn test_versions_increment_and_render() {
+        let registry = PromptRegistry::new();
+        registry
+            .register("support", "v1 for {{product}}")
+            .await
+            .unwrap();
+        let latest = registry
+            .register("support", "v2 for {{product}}")
+            .await
+            .unwrap();
+
+        assert_eq!(latest.version, 2);
+        assert_eq!(registry.get("support").unwrap().version, 2);
+        assert_eq!(registry.history("support").len(), 2);
+
+        let vars = HashMap::from([("product".to_string(), "rs-agent".to_string())]);
+        assert_eq!(latest.render(&vars), "v2 for rs-agent");
+    }
+
+    #[tokio::test]
+    async fn test_select_is_sticky_and_respects_weights() {
+        let registry = PromptRegistry::new();
+        registry
+            .register_variant("support", "control", "A", 1)
+            .await
+            .unwrap();
+        registry
+            .register_variant("support", "concise", "B", 1)
+            .await
+            .unwrap();
+
+        let first = registry.select("support", "session-1").unwrap();
+        assert_eq!(registry.select("support", "session-1").unwrap(), first);
+
+        let variants: std::collections::HashSet<String> = (0..50)
+            .filter_map(|i| registry.select("support", &format!("session-{}", i)))
+            .map(|v| v.variant)
+            .collect();
+        assert_eq!(variants.len(), 2);
+
+        registry
+            .register_variant("support", "concise", "B2", 0)
+            .await
+            .unwrap();
+        for i in 0..20 {
+            let chosen = registry
+                .select("support", &format!("session-{}", i))
+                .unwrap();
+            assert_eq!(chosen.variant, "control");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_load_from_store() {
+        let store: Arc<dyn MemoryStore> = Arc::new(InMemoryStore::new());
+        let registry = PromptRegistry::new().with_store(store.clone());
+        registry.register("support", "v1").await.unwrap();
+        registry.register("support", "v2").await.unwrap();
+
+        let reloaded = PromptRegistry::new().with_store(store);
+        assert_eq!(reloaded.load().await.unwrap(), 2);
+        assert_eq!(reloaded.get("support").unwrap().template, "v2");
+    }
+}
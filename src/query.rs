@@ -3,6 +3,8 @@
 //! This module provides query type detection to optimize context retrieval,
 //! matching the structure from go-agent's query.go.
 
+use crate::memory::MmrConfig;
+
 /// Types of queries that determine retrieval strategy
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum QueryType {
@@ -20,6 +22,11 @@ pub enum QueryType {
 pub fn classify_query(query: &str) -> QueryType {
     let lower = query.trim().to_lowercase();
     
+    // Explicit explanation requests win over incidental operators ("async/await")
+    if has_complex_keyword(&lower) {
+        return QueryType::Complex;
+    }
+    
     // Math queries - numerical operations, calculations
     if is_math_query(&lower) {
         return QueryType::Math;
@@ -83,29 +90,105 @@ fn is_short_factoid(query: &str) -> bool {
         && word_count < 15
 }
 
+// Complexity indicators
+const COMPLEX_KEYWORDS: [&str; 10] = [
+    "explain",
+    "describe",
+    "analyze",
+    "compare",
+    "discuss",
+    "evaluate",
+    "how does",
+    "why does",
+    "tell me about",
+    "walk me through",
+];
+
+fn has_complex_keyword(query: &str) -> bool {
+    COMPLEX_KEYWORDS.iter().any(|&kw| query.contains(kw))
+}
+
 fn is_complex_query(query: &str) -> bool {
-    // Complexity indicators
-    let complex_keywords = [
-        "explain",
-        "describe",
-        "analyze",
-        "compare",
-        "discuss",
-        "evaluate",
-        "how does",
-        "why does",
-        "tell me about",
-        "walk me through",
-    ];
-    
     // Longer queries are typically more complex
     let word_count = query.split_whitespace().count();
     
-    complex_keywords.iter().any(|&kw| query.contains(kw))
+    has_complex_keyword(query)
         || word_count > 20
         || query.contains('?') && word_count > 10
 }
 
+/// How much retrieval a turn gets
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetrievalPlan {
+    /// No retrieval; the query doesn't need earlier context
+    Skip,
+    /// The `k` nearest memories
+    TopK(usize),
+    /// Over-fetch `candidates` and keep `limit` diverse ones with MMR
+    Diverse { candidates: usize, limit: usize },
+}
+
+/// Maps each [`QueryType`] to a [`RetrievalPlan`].
+///
+/// Math and unknown queries skip retrieval, short factoids take a small
+/// top-k, and complex queries get a full semantic search reranked with MMR.
+#[derive(Debug, Clone, Copy)]
+pub struct RetrievalPolicy {
+    factoid_limit: usize,
+    complex_candidates: usize,
+    mmr: MmrConfig,
+}
+
+impl Default for RetrievalPolicy {
+    fn default() -> Self {
+        Self {
+            factoid_limit: 2,
+            complex_candidates: 20,
+            mmr: MmrConfig::default(),
+        }
+    }
+}
+
+impl RetrievalPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Memories a short factoid pulls, at most the agent's retrieval limit
+    pub fn with_factoid_limit(mut self, limit: usize) -> Self {
+        self.factoid_limit = limit;
+        self
+    }
+
+    /// Candidates a complex query fetches before MMR picks the final set
+    pub fn with_complex_candidates(mut self, candidates: usize) -> Self {
+        self.complex_candidates = candidates;
+        self
+    }
+
+    /// MMR settings for complex queries
+    pub fn with_mmr(mut self, mmr: MmrConfig) -> Self {
+        self.mmr = mmr;
+        self
+    }
+
+    pub fn mmr(&self) -> &MmrConfig {
+        &self.mmr
+    }
+
+    /// Plans retrieval for `query`, given the agent's retrieval `limit`
+    pub fn plan(&self, query: &str, limit: usize) -> RetrievalPlan {
+        match classify_query(query) {
+            QueryType::Math | QueryType::Unknown => RetrievalPlan::Skip,
+            QueryType::ShortFactoid => RetrievalPlan::TopK(self.factoid_limit.min(limit)),
+            QueryType::Complex => RetrievalPlan::Diverse {
+                candidates: self.complex_candidates.max(limit),
+                limit,
+            },
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -158,4 +241,17 @@ mod tests {
             QueryType::Complex
         );
     }
+
+    #[test]
+    fn test_retrieval_policy_plans_by_query_type() {
+        let policy = RetrievalPolicy::new().with_factoid_limit(2);
+        assert_eq!(policy.plan("What is 5 + 3?", 5), RetrievalPlan::Skip);
+        assert_eq!(policy.plan("Hello", 5), RetrievalPlan::Skip);
+        assert_eq!(policy.plan("What is Rust?", 5), RetrievalPlan::TopK(2));
+        assert_eq!(policy.plan("What is Rust?", 1), RetrievalPlan::TopK(1));
+        assert_eq!(
+            policy.plan("Explain how the borrow checker works", 5),
+            RetrievalPlan::Diverse { candidates: 20, limit: 5 }
+        );
+    }
 }
@@ -0,0 +1,176 @@
+//! Hot reload of agent options from a file.
+//!
+//! [`Agent::watch_options`] starts an [`OptionsWatcher`] that polls a JSON
+//! file of [`AgentOptions`] and replaces the agent's options with every
+//! change, so the system prompt, context limit and tool output guard can be
+//! tuned, or turned off, without recreating the agent.
+
+use std::path::{Path, PathBuf};
+use std::sync::Weak;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+
+use crate::agent::Agent;
+use crate::error::{AgentError, Result};
+use crate::types::AgentOptions;
+
+/// Reads [`AgentOptions`] from a JSON file
+pub async fn load_options(path: &Path) -> Result<AgentOptions> {
+    let data = tokio::fs::read(path).await?;
+    serde_json::from_slice(&data).map_err(AgentError::SerializationError)
+}
+
+/// Background task applying changes of an options file to an agent.
+///
+/// Stops when dropped.
+pub struct OptionsWatcher {
+    handle: JoinHandle<()>,
+}
+
+impl OptionsWatcher {
+    pub(crate) fn spawn(agent: Weak<Agent>, path: PathBuf, interval: Duration) -> Self {
+        let handle = tokio::spawn(async move {
+            let mut applied: Option<Vec<u8>> = None;
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let Some(agent) = agent.upgrade() else {
+                    return;
+                };
+
+                let data = match tokio::fs::read(&path).await {
+                    Ok(data) => data,
+                    Err(e) => {
+                        tracing::warn!(path = %path.display(), "Cannot read options: {}", e);
+                        continue;
+                    }
+                };
+                if applied.as_ref() == Some(&data) {
+                    continue;
+                }
+
+                match serde_json::from_slice::<AgentOptions>(&data) {
+                    Ok(options) => {
+                        agent.reload_options(options);
+                        tracing::info!(path = %path.display(), "Reloaded agent options");
+                    }
+                    Err(e) => {
+                        tracing::warn!(
+                            path = %path.display(),
+                            "Invalid options, keeping current: {}",
+                            e
+                        );
+                    }
+                }
+                // Don't re-parse or re-log a bad file until it changes again
+                applied = Some(data);
+            }
+        });
+        Self { handle }
+    }
+}
+
+impl Drop for OptionsWatcher {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::memory::{InMemoryStore, SessionMemory};
+    use crate::models::LLM;
+    use crate::types::{File, GenerationResponse, Message};
+    use async_trait::async_trait;
+
+    /// Replies with the system prompt it was given
+    struct SystemEchoLLM;
+
+    #[async_trait]
+    impl LLM for SystemEchoLLM {
+        async fn generate(
+            &self,
+            messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<GenerationResponse> {
+            Ok(GenerationResponse {
+                content: messages[0].content.clone(),
+                metadata: None,
+            })
+        }
+
+        fn model_name(&self) -> &str {
+            "system-echo"
+        }
+    }
+
+    /// Returns the system prompt `agent` used for a turn
+    async fn system_prompt(agent: &Agent) -> String {
+        agent
+            .generate_with_options("s", "hi", Default::default())
+            .await
+            .unwrap()
+            .content
+    }
+
+    #[tokio::test]
+    async fn test_watcher_swaps_system_prompt() {
+        let path = std::env::temp_dir().join(format!("options-{}.json", uuid::Uuid::new_v4()));
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 8));
+        let agent = Arc::new(
+            Agent::new(Arc::new(SystemEchoLLM), memory, AgentOptions::default())
+                .with_system_prompt("v1"),
+        );
+        assert_eq!(system_prompt(&agent).await, "v1");
+
+        tokio::fs::write(&path, r#"{"system_prompt": "v2"}"#)
+            .await
+            .unwrap();
+        let watcher = agent.watch_options(&path, Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(system_prompt(&agent).await, "v2");
+
+        // A broken file leaves the current options in place
+        tokio::fs::write(&path, "{not json").await.unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(system_prompt(&agent).await, "v2");
+
+        drop(watcher);
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+
+    #[tokio::test]
+    async fn test_watcher_turns_off_settings_removed_from_the_file() {
+        let path = std::env::temp_dir().join(format!("options-{}.json", uuid::Uuid::new_v4()));
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 8));
+        let agent = Arc::new(Agent::new(
+            Arc::new(SystemEchoLLM),
+            memory,
+            AgentOptions::default(),
+        ));
+
+        tokio::fs::write(
+            &path,
+            r#"{"system_prompt": "v2", "tool_guard": {"wrap": true}}"#,
+        )
+        .await
+        .unwrap();
+        let watcher = agent.watch_options(&path, Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(agent.options().tool_guard.is_some());
+
+        tokio::fs::write(&path, r#"{"system_prompt": "v2"}"#)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(agent.options().tool_guard.is_none());
+        assert_eq!(system_prompt(&agent).await, "v2");
+
+        drop(watcher);
+        let _ = tokio::fs::remove_file(&path).await;
+    }
+}
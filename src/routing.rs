@@ -0,0 +1,248 @@
+//! Routing decisions of the agent.
+//!
+//! Every request an [`Agent`](crate::Agent) handles is answered by one
+//! [`RoutePath`]: the CodeMode orchestrator, a tool call, plain generation,
+//! or a sub-agent. The agent emits a structured `tracing` event per request
+//! with the path and the time spent in each stage, and aggregates the events
+//! into a [`RoutingReport`] per session, returned by
+//! `Agent::routing_report`.
+
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Events kept per session for [`RoutingReport::recent`]
+const RECENT_EVENTS: usize = 32;
+
+/// Path that handled a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RoutePath {
+    /// The CodeMode orchestrator answered without a model call
+    Codemode,
+    /// A tool was invoked through `Agent::invoke_tool`
+    Tool,
+    /// The model generated the reply
+    Generation,
+    /// The request was delegated to a sub-agent
+    SubAgent,
+}
+
+impl RoutePath {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RoutePath::Codemode => "codemode",
+            RoutePath::Tool => "tool",
+            RoutePath::Generation => "generation",
+            RoutePath::SubAgent => "subagent",
+        }
+    }
+}
+
+/// One routed request
+#[derive(Debug, Clone, Serialize)]
+pub struct RoutingEvent {
+    pub path: RoutePath,
+    /// Tool or sub-agent name, for those paths
+    pub target: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub total: Duration,
+    /// Time per stage, e.g. `retrieval` or `model`, in the order run.
+    /// Concurrent stages overlap, so they can add up to more than `total`.
+    pub stages: Vec<(&'static str, Duration)>,
+    pub success: bool,
+}
+
+/// Totals for one path in a [`RoutingReport`]
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PathStats {
+    pub requests: u64,
+    pub failures: u64,
+    pub total: Duration,
+    pub max: Duration,
+    /// Summed time per stage
+    pub stages: BTreeMap<&'static str, Duration>,
+}
+
+impl PathStats {
+    /// Average time per request
+    pub fn mean(&self) -> Duration {
+        match self.requests {
+            0 => Duration::ZERO,
+            requests => self.total / u32::try_from(requests).unwrap_or(u32::MAX),
+        }
+    }
+
+    fn add(&mut self, event: &RoutingEvent) {
+        self.requests += 1;
+        if !event.success {
+            self.failures += 1;
+        }
+        self.total += event.total;
+        self.max = self.max.max(event.total);
+        for (stage, elapsed) in &event.stages {
+            *self.stages.entry(stage).or_default() += *elapsed;
+        }
+    }
+}
+
+/// Routing decisions of one session
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct RoutingReport {
+    pub paths: BTreeMap<RoutePath, PathStats>,
+    /// Latest events, oldest first
+    pub recent: Vec<RoutingEvent>,
+}
+
+impl RoutingReport {
+    /// Returns the stats of `path`, if it handled any request
+    pub fn path(&self, path: RoutePath) -> Option<&PathStats> {
+        self.paths.get(&path)
+    }
+
+    /// Returns how many requests the session made
+    pub fn requests(&self) -> u64 {
+        self.paths.values().map(|stats| stats.requests).sum()
+    }
+}
+
+#[derive(Default)]
+struct SessionRoutes {
+    paths: BTreeMap<RoutePath, PathStats>,
+    recent: VecDeque<RoutingEvent>,
+}
+
+/// Collects routing events per session
+#[derive(Default)]
+pub(crate) struct RoutingLog {
+    sessions: parking_lot::Mutex<HashMap<String, SessionRoutes>>,
+}
+
+impl RoutingLog {
+    /// Logs `event` for `session_id` and adds it to the session's report
+    pub(crate) fn record(&self, session_id: &str, event: RoutingEvent) {
+        emit(Some(session_id), &event);
+        let mut sessions = self.sessions.lock();
+        let session = sessions.entry(session_id.to_string()).or_default();
+        session.paths.entry(event.path).or_default().add(&event);
+        if session.recent.len() == RECENT_EVENTS {
+            session.recent.pop_front();
+        }
+        session.recent.push_back(event);
+    }
+
+    pub(crate) fn report(&self, session_id: &str) -> RoutingReport {
+        self.sessions
+            .lock()
+            .get(session_id)
+            .map(|session| RoutingReport {
+                paths: session.paths.clone(),
+                recent: session.recent.iter().cloned().collect(),
+            })
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn clear(&self, session_id: &str) {
+        self.sessions.lock().remove(session_id);
+    }
+}
+
+/// Emits the structured `tracing` event and metrics for a routed request
+pub(crate) fn emit(session_id: Option<&str>, event: &RoutingEvent) {
+    crate::telemetry::record_route(event);
+    tracing::info!(
+        session_id,
+        path = event.path.as_str(),
+        name = event.target.as_deref(),
+        total_ms = event.total.as_millis() as u64,
+        stages = ?event.stages,
+        success = event.success,
+        "Routed request"
+    );
+}
+
+/// Times the stages of one request
+pub(crate) struct RouteTimer {
+    started: Instant,
+    started_at: DateTime<Utc>,
+    stages: Vec<(&'static str, Duration)>,
+}
+
+impl RouteTimer {
+    pub(crate) fn start() -> Self {
+        Self {
+            started: Instant::now(),
+            started_at: Utc::now(),
+            stages: Vec::new(),
+        }
+    }
+
+    /// Records that `stage` took `elapsed`
+    pub(crate) fn stage(&mut self, stage: &'static str, elapsed: Duration) {
+        self.stages.push((stage, elapsed));
+    }
+
+    /// Records `stage` as running from `since` until now
+    pub(crate) fn stage_since(&mut self, stage: &'static str, since: Instant) {
+        self.stage(stage, since.elapsed());
+    }
+
+    pub(crate) fn finish(
+        self,
+        path: RoutePath,
+        target: Option<String>,
+        success: bool,
+    ) -> RoutingEvent {
+        RoutingEvent {
+            path,
+            target,
+            started_at: self.started_at,
+            total: self.started.elapsed(),
+            stages: self.stages,
+            success,
+        }
+    }
+}
+
+/// Awaits `future`, returning its output and how long it took
+pub(crate) async fn timed<T>(future: impl std::future::Future<Output = T>) -> (T, Duration) {
+    let started = Instant::now();
+    let output = future.await;
+    (output, started.elapsed())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_aggregates_per_path() {
+        let log = RoutingLog::default();
+        for (path, millis, success) in [
+            (RoutePath::Generation, 30, true),
+            (RoutePath::Generation, 10, false),
+            (RoutePath::Tool, 5, true),
+        ] {
+            let mut timer = RouteTimer::start();
+            timer.stage("model", Duration::from_millis(millis));
+            let mut event = timer.finish(path, None, success);
+            event.total = Duration::from_millis(millis);
+            log.record("s", event);
+        }
+
+        let report = log.report("s");
+        assert_eq!(report.requests(), 3);
+        let generation = report.path(RoutePath::Generation).unwrap();
+        assert_eq!(generation.failures, 1);
+        assert_eq!(generation.mean(), Duration::from_millis(20));
+        assert_eq!(generation.max, Duration::from_millis(30));
+        assert_eq!(generation.stages["model"], Duration::from_millis(40));
+        assert_eq!(report.recent.len(), 3);
+        assert!(log.report("other").paths.is_empty());
+
+        log.clear("s");
+        assert_eq!(log.report("s").requests(), 0);
+    }
+}
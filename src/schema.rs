@@ -0,0 +1,235 @@
+//! JSON schema validation.
+//!
+//! Tool arguments, structured replies and merged sub-agent outputs are all
+//! checked against JSON schemas. This validator covers the keywords those
+//! schemas use in practice, including the `$ref`s and `definitions` that
+//! `schemars` generates, and reports every failing field rather than just
+//! the first.
+
+use std::fmt;
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::error::{AgentError, Result};
+
+/// One way a value fails its schema
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SchemaError {
+    /// Where the failing value is, e.g. `$.items[2].name`
+    pub path: String,
+    pub message: String,
+}
+
+impl fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.message)
+    }
+}
+
+/// Returns every way `value` fails `schema`; empty if it matches.
+///
+/// Supports `type`, `enum`, `minimum`, `maximum`, `properties`, `required`,
+/// `additionalProperties: false`, `items`, `anyOf`, `oneOf`, `allOf`, and
+/// `$ref`s into the schema itself. Other keywords are ignored.
+pub fn schema_errors(value: &Value, schema: &Value) -> Vec<SchemaError> {
+    let mut errors = Vec::new();
+    check(value, schema, schema, "$", &mut errors);
+    errors
+}
+
+/// Checks `value` against `schema`, failing with
+/// [`AgentError::SchemaViolation`] that lists every failing field
+pub fn validate_schema(value: &Value, schema: &Value) -> Result<()> {
+    let errors = schema_errors(value, schema);
+    if errors.is_empty() {
+        return Ok(());
+    }
+    let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+    Err(AgentError::SchemaViolation(errors.join("; ")))
+}
+
+fn check(value: &Value, schema: &Value, root: &Value, path: &str, errors: &mut Vec<SchemaError>) {
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        match reference
+            .strip_prefix('#')
+            .and_then(|pointer| root.pointer(pointer))
+        {
+            Some(target) => check(value, target, root, path, errors),
+            None => fail(
+                errors,
+                path,
+                format!("unresolved schema reference {}", reference),
+            ),
+        }
+        return;
+    }
+    for keyword in ["anyOf", "oneOf"] {
+        if let Some(Value::Array(options)) = schema.get(keyword) {
+            if !options
+                .iter()
+                .any(|option| schema_errors_at(value, option, root, path).is_empty())
+            {
+                fail(
+                    errors,
+                    path,
+                    "matches none of the allowed shapes".to_string(),
+                );
+                return;
+            }
+        }
+    }
+
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|name| has_type(value, name)) {
+            fail(
+                errors,
+                path,
+                format!("expected {}, got {}", types.join(" or "), type_name(value)),
+            );
+            return;
+        }
+    }
+    if let Some(Value::Array(allowed)) = schema.get("enum") {
+        if !allowed.contains(value) {
+            fail(
+                errors,
+                path,
+                format!("{} is not one of the allowed values", value),
+            );
+        }
+    }
+    if let Some(number) = value.as_f64() {
+        if let Some(minimum) = schema.get("minimum").and_then(Value::as_f64) {
+            if number < minimum {
+                fail(errors, path, format!("{} is less than {}", number, minimum));
+            }
+        }
+        if let Some(maximum) = schema.get("maximum").and_then(Value::as_f64) {
+            if number > maximum {
+                fail(
+                    errors,
+                    path,
+                    format!("{} is greater than {}", number, maximum),
+                );
+            }
+        }
+    }
+
+    if let Value::Object(fields) = value {
+        for required in schema
+            .get("required")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+        {
+            if !fields.contains_key(required) {
+                fail(
+                    errors,
+                    path,
+                    format!("missing required field `{}`", required),
+                );
+            }
+        }
+        let properties = schema.get("properties").and_then(Value::as_object);
+        let closed = schema.get("additionalProperties") == Some(&Value::Bool(false));
+        for (key, field) in fields {
+            match properties.and_then(|properties| properties.get(key)) {
+                Some(field_schema) => {
+                    check(field, field_schema, root, &format!("{path}.{key}"), errors)
+                }
+                None if closed => fail(errors, path, format!("unexpected field `{}`", key)),
+                None => {}
+            }
+        }
+    }
+    if let (Value::Array(items), Some(item_schema)) = (value, schema.get("items")) {
+        for (index, item) in items.iter().enumerate() {
+            check(item, item_schema, root, &format!("{path}[{index}]"), errors);
+        }
+    }
+    for all in schema
+        .get("allOf")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+    {
+        check(value, all, root, path, errors);
+    }
+}
+
+fn fail(errors: &mut Vec<SchemaError>, path: &str, message: String) {
+    errors.push(SchemaError {
+        path: path.to_string(),
+        message,
+    });
+}
+
+fn schema_errors_at(value: &Value, schema: &Value, root: &Value, path: &str) -> Vec<SchemaError> {
+    let mut errors = Vec::new();
+    check(value, schema, root, path, &mut errors);
+    errors
+}
+
+fn has_type(value: &Value, name: &str) -> bool {
+    match name {
+        "object" => value.is_object(),
+        "array" => value.is_array(),
+        "string" => value.is_string(),
+        "number" => value.is_number(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "boolean" => value.is_boolean(),
+        "null" => value.is_null(),
+        _ => true,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_reports_every_failing_field() {
+        let schema = json!({
+            "type": "object",
+            "required": ["city", "days"],
+            "properties": {
+                "city": {"type": "string"},
+                "days": {"type": "integer", "minimum": 1},
+                "units": {"$ref": "#/definitions/Units"}
+            },
+            "definitions": {
+                "Units": {"type": "string", "enum": ["metric", "imperial"]}
+            }
+        });
+        assert!(schema_errors(&json!({"city": "Oslo", "days": 3}), &schema).is_empty());
+
+        let errors = schema_errors(&json!({"days": 0, "units": "kelvin"}), &schema);
+        let paths: Vec<&str> = errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, ["$", "$.days", "$.units"]);
+        assert!(errors[0].message.contains("`city`"));
+
+        assert!(matches!(
+            validate_schema(&json!("Oslo"), &schema),
+            Err(AgentError::SchemaViolation(_))
+        ));
+    }
+}
@@ -0,0 +1,317 @@
+//! Credential lookup.
+//!
+//! Model providers, UTCP auth and tools all need credentials. A
+//! [`SecretsProvider`] resolves them by name from one place, so where they
+//! live (environment, mounted files, Vault, AWS Secrets Manager) is a
+//! deployment choice and tests can use [`StaticSecrets`]. Model
+//! constructors take one through `from_secrets`, [`resolve_utcp_auth`]
+//! fills in UTCP auth configs, and
+//! [`SecretArgumentsLayer`](crate::tools::layer::SecretArgumentsLayer) passes
+//! secrets to tools as hidden arguments.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use rs_utcp::auth::AuthConfig;
+
+use crate::error::{AgentError, Result};
+
+/// Prefix marking a UTCP auth value as the name of a secret, e.g.
+/// `secret://WEATHER_API_KEY`
+pub const SECRET_REF_PREFIX: &str = "secret://";
+
+/// Resolves credentials by name
+#[async_trait]
+pub trait SecretsProvider: Send + Sync {
+    /// Returns the secret stored under `name`, if any
+    async fn get(&self, name: &str) -> Result<Option<String>>;
+
+    /// Returns the secret stored under `name`, failing with
+    /// [`AgentError::ConfigError`] if there is none
+    async fn require(&self, name: &str) -> Result<String> {
+        self.get(name)
+            .await?
+            .ok_or_else(|| AgentError::ConfigError(format!("Secret {} is not set", name)))
+    }
+}
+
+/// Reads secrets from environment variables, optionally under a prefix
+#[derive(Debug, Clone, Default)]
+pub struct EnvSecrets {
+    prefix: String,
+}
+
+impl EnvSecrets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads secret `name` from the variable `{prefix}{name}`
+    pub fn with_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.prefix = prefix.into();
+        self
+    }
+}
+
+#[async_trait]
+impl SecretsProvider for EnvSecrets {
+    async fn get(&self, name: &str) -> Result<Option<String>> {
+        Ok(std::env::var(format!("{}{}", self.prefix, name)).ok())
+    }
+}
+
+/// Reads each secret from a file named after it, as Docker and Kubernetes
+/// mount them; trailing newlines are trimmed
+#[derive(Debug, Clone)]
+pub struct FileSecrets {
+    dir: PathBuf,
+}
+
+impl FileSecrets {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl SecretsProvider for FileSecrets {
+    async fn get(&self, name: &str) -> Result<Option<String>> {
+        // Names are file names, never paths out of the directory
+        if name.is_empty() || name.contains(['/', '\\']) || name.starts_with('.') {
+            return Ok(None);
+        }
+        match tokio::fs::read_to_string(self.dir.join(name)).await {
+            Ok(secret) => Ok(Some(secret.trim_end_matches(['\r', '\n']).to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Fixed secrets, for tests and local setups
+#[derive(Clone, Default)]
+pub struct StaticSecrets {
+    secrets: HashMap<String, String>,
+}
+
+impl StaticSecrets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_secret(mut self, name: impl Into<String>, secret: impl Into<String>) -> Self {
+        self.secrets.insert(name.into(), secret.into());
+        self
+    }
+}
+
+impl fmt::Debug for StaticSecrets {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StaticSecrets")
+            .field("names", &self.secrets.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[async_trait]
+impl SecretsProvider for StaticSecrets {
+    async fn get(&self, name: &str) -> Result<Option<String>> {
+        Ok(self.secrets.get(name).cloned())
+    }
+}
+
+/// Asks several providers in turn and returns the first secret found, e.g.
+/// files mounted in production with the environment as a fallback
+#[derive(Clone, Default)]
+pub struct SecretsChain {
+    providers: Vec<Arc<dyn SecretsProvider>>,
+}
+
+impl SecretsChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Asks `provider` after the ones added before it
+    pub fn with_provider(mut self, provider: Arc<dyn SecretsProvider>) -> Self {
+        self.providers.push(provider);
+        self
+    }
+}
+
+#[async_trait]
+impl SecretsProvider for SecretsChain {
+    async fn get(&self, name: &str) -> Result<Option<String>> {
+        for provider in &self.providers {
+            if let Some(secret) = provider.get(name).await? {
+                return Ok(Some(secret));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Replaces values of `auth` written as `secret://NAME` with secret `NAME`,
+/// so UTCP provider configs can be checked in without credentials
+pub async fn resolve_utcp_auth(secrets: &dyn SecretsProvider, auth: &mut AuthConfig) -> Result<()> {
+    let values = match auth {
+        AuthConfig::ApiKey(auth) => vec![&mut auth.api_key],
+        AuthConfig::Basic(auth) => vec![&mut auth.username, &mut auth.password],
+        AuthConfig::OAuth2(auth) => vec![&mut auth.client_id, &mut auth.client_secret],
+    };
+    for value in values {
+        if let Some(name) = value.strip_prefix(SECRET_REF_PREFIX) {
+            *value = secrets.require(name).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "vault")]
+pub use vault::VaultSecrets;
+
+#[cfg(feature = "vault")]
+mod vault {
+    use super::*;
+
+    /// Reads secrets from one path of a HashiCorp Vault KV v2 engine; each
+    /// secret is a key of that path's data
+    pub struct VaultSecrets {
+        client: reqwest::Client,
+        url: String,
+        token: String,
+    }
+
+    impl VaultSecrets {
+        /// Reads `{address}/v1/{mount}/data/{path}` with `token`
+        pub fn new(
+            address: impl AsRef<str>,
+            token: impl Into<String>,
+            mount: &str,
+            path: &str,
+        ) -> Self {
+            Self {
+                client: reqwest::Client::new(),
+                url: format!(
+                    "{}/v1/{}/data/{}",
+                    address.as_ref().trim_end_matches('/'),
+                    mount,
+                    path
+                ),
+                token: token.into(),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl SecretsProvider for VaultSecrets {
+        async fn get(&self, name: &str) -> Result<Option<String>> {
+            let response = self
+                .client
+                .get(&self.url)
+                .header("X-Vault-Token", &self.token)
+                .send()
+                .await
+                .map_err(|e| AgentError::ConfigError(format!("Vault request failed: {}", e)))?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+            let body: serde_json::Value = response
+                .error_for_status()
+                .map_err(|e| AgentError::ConfigError(format!("Vault request failed: {}", e)))?
+                .json()
+                .await
+                .map_err(|e| AgentError::ConfigError(format!("Invalid Vault response: {}", e)))?;
+            Ok(body["data"]["data"][name].as_str().map(str::to_string))
+        }
+    }
+}
+
+#[cfg(feature = "aws-secrets")]
+pub use aws::AwsSecretsManager;
+
+#[cfg(feature = "aws-secrets")]
+mod aws {
+    use super::*;
+
+    /// Reads each secret from the AWS Secrets Manager secret of the same
+    /// name; KMS decryption happens on the AWS side
+    pub struct AwsSecretsManager {
+        client: aws_sdk_secretsmanager::Client,
+    }
+
+    impl AwsSecretsManager {
+        pub fn new(client: aws_sdk_secretsmanager::Client) -> Self {
+            Self { client }
+        }
+
+        /// Connects with the default AWS credential and region chain
+        pub async fn from_env() -> Self {
+            let config = aws_config::load_defaults(aws_config::BehaviorVersion::latest()).await;
+            Self::new(aws_sdk_secretsmanager::Client::new(&config))
+        }
+    }
+
+    #[async_trait]
+    impl SecretsProvider for AwsSecretsManager {
+        async fn get(&self, name: &str) -> Result<Option<String>> {
+            match self.client.get_secret_value().secret_id(name).send().await {
+                Ok(output) => Ok(output.secret_string().map(str::to_string)),
+                Err(e) => {
+                    let e = e.into_service_error();
+                    if e.is_resource_not_found_exception() {
+                        Ok(None)
+                    } else {
+                        Err(AgentError::ConfigError(format!(
+                            "Reading secret {} failed: {}",
+                            name, e
+                        )))
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rs_utcp::auth::ApiKeyAuth;
+
+    #[tokio::test]
+    async fn test_chain_resolves_from_first_provider_with_the_secret() {
+        let dir = std::env::temp_dir().join(format!("secrets-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("WEATHER_API_KEY"), "from-file\n").unwrap();
+
+        let secrets = SecretsChain::new()
+            .with_provider(Arc::new(FileSecrets::new(&dir)))
+            .with_provider(Arc::new(
+                StaticSecrets::new()
+                    .with_secret("WEATHER_API_KEY", "from-static")
+                    .with_secret("DB_PASSWORD", "hunter2"),
+            ));
+        assert_eq!(
+            secrets.require("WEATHER_API_KEY").await.unwrap(),
+            "from-file"
+        );
+        assert_eq!(secrets.require("DB_PASSWORD").await.unwrap(), "hunter2");
+        assert!(secrets.get("../WEATHER_API_KEY").await.unwrap().is_none());
+        assert!(matches!(
+            secrets.require("MISSING").await,
+            Err(AgentError::ConfigError(_))
+        ));
+
+        let mut auth = AuthConfig::ApiKey(ApiKeyAuth::new("secret://WEATHER_API_KEY".to_string()));
+        resolve_utcp_auth(&secrets, &mut auth).await.unwrap();
+        match auth {
+            AuthConfig::ApiKey(auth) => assert_eq!(auth.api_key, "from-file"),
+            other => panic!("unexpected auth {:?}", other),
+        }
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
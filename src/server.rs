@@ -0,0 +1,236 @@
+//! Streaming replies to HTTP clients.
+//!
+//! [`spawn_reply_stream`] runs `Agent::generate_stream` on its own task and
+//! hands the chunks over a bounded channel, so the stream is `'static` and
+//! a slow client slows the model down instead of buffering without bound.
+//! When the client goes away the task drops the model stream, which saves
+//! the partial reply as interrupted, like any cancelled stream. Replies
+//! run on behalf of the tenant of the calling [`tenant::scope`], so a
+//! handler can wrap the call in the tenant it authenticated.
+//!
+//! With the `axum` feature, [`sse`] turns a reply into a Server-Sent Events
+//! response and [`serve_websocket`] runs a chat session over a WebSocket.
+
+use std::sync::Arc;
+
+use futures::stream::{self, BoxStream, StreamExt};
+use tokio::sync::mpsc;
+
+use crate::agent::Agent;
+use crate::error::Result;
+use crate::tenant;
+
+/// Chunks buffered between the model and a slow client by default
+pub const DEFAULT_STREAM_BUFFER: usize = 16;
+
+/// Streams the reply to `input` from a spawned task.
+///
+/// At most `buffer` chunks wait for the client; the model stream is only
+/// polled as the client catches up. Dropping the returned stream cancels
+/// the reply, even while the model is still thinking.
+pub fn spawn_reply_stream(
+    agent: Arc<Agent>,
+    session_id: impl Into<String>,
+    input: impl Into<String>,
+    buffer: usize,
+) -> BoxStream<'static, Result<String>> {
+    let (session_id, input) = (session_id.into(), input.into());
+    let (tx, rx) = mpsc::channel(buffer.max(1));
+    tokio::spawn(tenant::scope(tenant::current(), async move {
+        let started = tokio::select! {
+            _ = tx.closed() => return,
+            started = agent.generate_stream(session_id, input) => started,
+        };
+        let mut chunks = match started {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                let _ = tx.send(Err(e)).await;
+                return;
+            }
+        };
+        loop {
+            let chunk = tokio::select! {
+                _ = tx.closed() => break,
+                chunk = chunks.next() => chunk,
+            };
+            let Some(chunk) = chunk else { break };
+            let failed = chunk.is_err();
+            // Waits while the buffer is full
+            if tx.send(chunk).await.is_err() || failed {
+                break;
+            }
+        }
+    }));
+
+    stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|chunk| (chunk, rx))
+    })
+    .boxed()
+}
+
+#[cfg(feature = "axum")]
+pub use self::axum_bridge::{serve_websocket, sse};
+
+#[cfg(feature = "axum")]
+mod axum_bridge {
+    use std::convert::Infallible;
+    use std::sync::Arc;
+
+    use axum::extract::ws::{Message as WsMessage, WebSocket};
+    use axum::response::sse::{Event, KeepAlive, Sse};
+    use futures::stream::{self, Stream, StreamExt};
+    use serde_json::json;
+
+    use super::{spawn_reply_stream, DEFAULT_STREAM_BUFFER};
+    use crate::agent::Agent;
+
+    /// Streams the reply to `input` as Server-Sent Events.
+    ///
+    /// Each chunk is a `chunk` event; the reply ends with a `done` event, or
+    /// an `error` event carrying the message. Return it from an axum
+    /// handler; if the client disconnects, the reply is cancelled.
+    pub fn sse(
+        agent: Arc<Agent>,
+        session_id: impl Into<String>,
+        input: impl Into<String>,
+    ) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+        let chunks = spawn_reply_stream(agent, session_id, input, DEFAULT_STREAM_BUFFER);
+        let events = chunks
+            .map(|chunk| match chunk {
+                Ok(chunk) => Event::default().event("chunk").data(chunk),
+                Err(e) => Event::default().event("error").data(e.to_string()),
+            })
+            .chain(stream::once(async { Event::default().event("done") }))
+            .map(Ok);
+        Sse::new(events).keep_alive(KeepAlive::default())
+    }
+
+    /// Runs a chat session over `socket` until the client closes it.
+    ///
+    /// Every text message is a user input. The reply streams back as JSON
+    /// frames: `{"type":"chunk","content":...}` per chunk, then
+    /// `{"type":"done"}` or `{"type":"error","message":...}`. A message
+    /// that arrives mid-reply cancels that reply and starts the next; a
+    /// closed or dropped connection cancels it too.
+    pub async fn serve_websocket(agent: Arc<Agent>, mut socket: WebSocket, session_id: String) {
+        let mut next_input: Option<String> = None;
+        loop {
+            let input = match next_input.take() {
+                Some(input) => input,
+                None => match socket.recv().await {
+                    Some(Ok(WsMessage::Text(input))) => input,
+                    Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => return,
+                    Some(Ok(_)) => continue,
+                },
+            };
+
+            let mut chunks = match agent.generate_stream(session_id.as_str(), input).await {
+                Ok(chunks) => chunks,
+                Err(e) => {
+                    let frame = json!({"type": "error", "message": e.to_string()});
+                    if send(&mut socket, frame).await {
+                        continue;
+                    }
+                    return;
+                }
+            };
+            loop {
+                let frame = tokio::select! {
+                    incoming = socket.recv() => match incoming {
+                        Some(Ok(WsMessage::Text(input))) => {
+                            next_input = Some(input);
+                            break;
+                        }
+                        Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => return,
+                        Some(Ok(_)) => continue,
+                    },
+                    chunk = chunks.next() => match chunk {
+                        Some(Ok(content)) => json!({"type": "chunk", "content": content}),
+                        Some(Err(e)) => json!({"type": "error", "message": e.to_string()}),
+                        None => json!({"type": "done"}),
+                    },
+                };
+                let finished = frame["type"] != "chunk";
+                if !send(&mut socket, frame).await {
+                    return;
+                }
+                if finished {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Sends `frame`, returning false once the client is gone
+    async fn send(socket: &mut WebSocket, frame: serde_json::Value) -> bool {
+        socket
+            .send(WsMessage::Text(frame.to_string()))
+            .await
+            .is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{InMemoryStore, SessionMemory};
+    use crate::models::LLM;
+    use crate::types::{AgentOptions, File, GenerationResponse, Message};
+    use async_trait::async_trait;
+
+    /// Streams the words of a fixed reply, one chunk each
+    struct WordsLLM;
+
+    #[async_trait]
+    impl LLM for WordsLLM {
+        async fn generate(
+            &self,
+            _messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<GenerationResponse> {
+            Ok(GenerationResponse {
+                content: "one two three".to_string(),
+                metadata: None,
+            })
+        }
+
+        async fn generate_stream(
+            &self,
+            _messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<BoxStream<'static, Result<String>>> {
+            let words = ["one ", "two ", "three"].map(|w| Ok(w.to_string()));
+            Ok(stream::iter(words).boxed())
+        }
+
+        fn model_name(&self) -> &str {
+            "words"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spawned_stream_delivers_and_cancels() {
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 8));
+        let agent = Arc::new(Agent::new(
+            Arc::new(WordsLLM),
+            memory.clone(),
+            AgentOptions::default(),
+        ));
+
+        let chunks: Vec<String> = spawn_reply_stream(agent.clone(), "s", "hi", 1)
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        assert_eq!(chunks.concat(), "one two three");
+
+        // Dropping the stream mid-reply leaves an interrupted reply in memory
+        let mut chunks = spawn_reply_stream(agent, "t", "hi", 1);
+        assert_eq!(chunks.next().await.unwrap().unwrap(), "one ");
+        drop(chunks);
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        let records = memory.retrieve_recent("t").await.unwrap();
+        assert!(records
+            .iter()
+            .any(|r| r.role == "assistant" && r.content.starts_with("one")));
+    }
+}
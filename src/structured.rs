@@ -0,0 +1,225 @@
+//! Structured output for `Agent::generate_typed`.
+//!
+//! The JSON schema of the requested type is added to the system prompt,
+//! and models that can be constrained to JSON are asked to. Replies are
+//! rarely clean, so [`extract_json`] finds the JSON in fences or
+//! surrounding prose and repairs common slips such as trailing commas or
+//! unclosed brackets before [`parse_structured`] checks it against the
+//! schema and deserializes it. A reply that still fails is sent back to the
+//! model with the error, up to the agent's retry limit.
+
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::error::{AgentError, Result};
+use crate::models::{GenerationSettings, LLM};
+use crate::schema::validate_schema;
+use crate::types::{Message, Role};
+
+/// Times a typed generation is retried after an invalid reply unless
+/// configured otherwise
+pub const DEFAULT_STRUCTURED_RETRIES: usize = 2;
+
+/// Returns the JSON schema of `T`
+pub fn schema_for<T: JsonSchema>() -> Result<Value> {
+    Ok(serde_json::to_value(schemars::schema_for!(T))?)
+}
+
+/// Returns the prompt instruction asking for a reply matching `schema`
+pub fn schema_instruction(schema: &Value) -> String {
+    format!(
+        "Reply with only a JSON value that matches this JSON schema, with no other text:\n{}",
+        serde_json::to_string_pretty(schema).unwrap_or_else(|_| schema.to_string())
+    )
+}
+
+/// Returns the follow-up asking the model to fix a reply that failed with
+/// `error`
+pub fn correction(error: &AgentError) -> String {
+    format!(
+        "Your previous reply could not be used: {}. Reply again with only the corrected JSON.",
+        error
+    )
+}
+
+/// Finds the JSON value in a model reply.
+///
+/// Tries the whole reply, then the first ```` ``` ```` fenced block, then
+/// the span from the first `{` or `[` to the last `}` or `]`; each candidate
+/// is parsed as is and, failing that, after [`repair_json`].
+pub fn extract_json(reply: &str) -> Option<Value> {
+    let trimmed = reply.trim();
+    let fenced = trimmed.split_once("```").map(|(_, rest)| {
+        let body = rest.split_once('\n').map_or(rest, |(_, body)| body);
+        body.split_once("```").map_or(body, |(body, _)| body)
+    });
+    let spanned = trimmed.find(['{', '[']).map(|start| {
+        let end = trimmed.rfind(['}', ']']).filter(|end| *end >= start);
+        end.map_or(&trimmed[start..], |end| &trimmed[start..=end])
+    });
+
+    [Some(trimmed), fenced, spanned]
+        .into_iter()
+        .flatten()
+        .find_map(|candidate| {
+            serde_json::from_str(candidate)
+                .or_else(|_| serde_json::from_str(&repair_json(candidate)))
+                .ok()
+        })
+}
+
+/// Fixes slips models make in JSON: trailing commas, and strings, objects
+/// and arrays left open when the reply was cut off
+pub fn repair_json(text: &str) -> String {
+    let mut repaired = String::with_capacity(text.len());
+    let mut open: Vec<char> = Vec::new();
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for c in text.chars() {
+        if in_string {
+            repaired.push(c);
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+        match c {
+            '"' => in_string = true,
+            '{' => open.push('}'),
+            '[' => open.push(']'),
+            '}' | ']' => {
+                // Drop a comma right before the closing bracket
+                let kept = repaired.trim_end().len();
+                if repaired[..kept].ends_with(',') {
+                    repaired.truncate(kept - 1);
+                }
+                open.pop();
+            }
+            _ => {}
+        }
+        repaired.push(c);
+    }
+
+    if in_string {
+        repaired.push('"');
+    }
+    let kept = repaired.trim_end().len();
+    if repaired[..kept].ends_with(',') {
+        repaired.truncate(kept - 1);
+    }
+    while let Some(close) = open.pop() {
+        repaired.push(close);
+    }
+    repaired
+}
+
+/// Extracts the JSON in `reply`, checks it against `schema` and
+/// deserializes it into `T`
+pub fn parse_structured<T: DeserializeOwned>(reply: &str, schema: &Value) -> Result<T> {
+    let value = extract_json(reply)
+        .ok_or_else(|| AgentError::SchemaViolation("reply contains no JSON".to_string()))?;
+    validate_schema(&value, schema)?;
+    Ok(serde_json::from_value(value)?)
+}
+
+/// Asks `model` for a `T` outside of any agent session.
+///
+/// The schema of `T` is appended to `system_prompt`, and a reply that
+/// doesn't parse or match it is sent back with the error up to `retries`
+/// times, as `Agent::generate_typed` does.
+pub async fn generate_structured<T: DeserializeOwned + JsonSchema>(
+    model: &dyn LLM,
+    system_prompt: &str,
+    input: &str,
+    retries: usize,
+) -> Result<T> {
+    let schema = schema_for::<T>()?;
+    let mut messages = vec![
+        Message {
+            role: Role::System,
+            content: format!("{}\n\n{}", system_prompt, schema_instruction(&schema)),
+            metadata: None,
+        },
+        Message {
+            role: Role::User,
+            content: input.to_string(),
+            metadata: None,
+        },
+    ];
+    let settings = GenerationSettings {
+        temperature: None,
+        json_mode: model.capabilities().json_mode,
+    };
+
+    let mut retries = retries;
+    loop {
+        let reply = model
+            .generate_with(messages.clone(), None, &settings)
+            .await?;
+        match parse_structured(&reply.content, &schema) {
+            Ok(value) => return Ok(value),
+            Err(e) if retries > 0 => {
+                retries -= 1;
+                messages.push(Message {
+                    role: Role::Assistant,
+                    content: reply.content,
+                    metadata: None,
+                });
+                messages.push(Message {
+                    role: Role::User,
+                    content: correction(&e),
+                    metadata: None,
+                });
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, JsonSchema, PartialEq)]
+    struct Ticket {
+        title: String,
+        priority: u8,
+    }
+
+    #[test]
+    fn test_extracts_and_repairs_json() {
+        let schema = schema_for::<Ticket>().unwrap();
+        let expected = Ticket {
+            title: "Login fails".to_string(),
+            priority: 2,
+        };
+
+        let fenced = "Here you go:\n```json\n{\"title\": \"Login fails\", \"priority\": 2,}\n```";
+        assert_eq!(
+            parse_structured::<Ticket>(fenced, &schema).unwrap(),
+            expected
+        );
+        let prose = "Sure! {\"title\": \"Login fails\", \"priority\": 2} Let me know.";
+        assert_eq!(
+            parse_structured::<Ticket>(prose, &schema).unwrap(),
+            expected
+        );
+        let cut_off = "{\"title\": \"Login fails\", \"priority\": 2";
+        assert_eq!(
+            parse_structured::<Ticket>(cut_off, &schema).unwrap(),
+            expected
+        );
+
+        assert!(matches!(
+            parse_structured::<Ticket>("{\"title\": \"Login fails\"}", &schema),
+            Err(AgentError::SchemaViolation(_))
+        ));
+        assert!(parse_structured::<Ticket>("I can't help with that.", &schema).is_err());
+    }
+}
@@ -0,0 +1,494 @@
+//! Traces and metrics of the agent.
+//!
+//! The agent always emits `tracing` spans, tagged with `session_id`:
+//! `agent.generate` per turn, `llm.generate` per provider call with the
+//! `model` and its `input_tokens` and `output_tokens`, `agent.tool` per tool
+//! invocation, and `memory` per memory operation. Any subscriber can collect
+//! them.
+//!
+//! With the `otel` feature, [`init_otlp`] installs a subscriber that exports
+//! the spans over OTLP and a meter provider for these instruments:
+//!
+//! - `agent.requests` and `agent.request.duration`, by routing `path`, plus
+//!   `agent.stage.duration` by `path` and `stage`
+//! - `llm.requests`, `llm.request.duration` and `llm.tokens`, by `model`
+//! - `memory.operations` and `memory.operation.duration`, by `operation`
+//! - `llm.rate_limit.remaining` and `llm.rate_limit.limit`, by `model` and
+//!   `quota` (`requests` or `tokens`), from providers reporting rate limits
+//!
+//! Token counts come from the agent's `TokenizerRegistry`, so they are as
+//! exact as the tokenizer configured for the model.
+
+use std::future::Future;
+use std::sync::Arc;
+#[cfg(not(feature = "otel"))]
+use std::time::Duration;
+use std::time::Instant;
+
+use tracing::Instrument;
+
+use crate::error::Result;
+#[cfg(any(feature = "openai", feature = "anthropic"))]
+use crate::models::RateLimitInfo;
+use crate::models::Tokenizer;
+#[cfg(not(feature = "otel"))]
+use crate::routing::RoutingEvent;
+use crate::types::Message;
+
+/// Tokens a provider call consumed
+#[derive(Debug, PartialEq)]
+pub(crate) struct ModelUsage {
+    pub(crate) model: String,
+    pub(crate) input_tokens: usize,
+    pub(crate) output_tokens: usize,
+}
+
+/// One provider call, from prompt to reply
+pub(crate) struct ModelCall {
+    span: tracing::Span,
+    model: String,
+    tokenizer: Arc<dyn Tokenizer>,
+    input_tokens: usize,
+    /// Tokens of the chunks streamed so far
+    streamed_tokens: usize,
+    started: Instant,
+}
+
+impl ModelCall {
+    /// Opens the `llm.generate` span for sending `messages` to `model`
+    pub(crate) fn start(
+        session_id: &str,
+        model: &str,
+        tokenizer: Arc<dyn Tokenizer>,
+        messages: &[Message],
+    ) -> Self {
+        let input_tokens = messages
+            .iter()
+            .map(|m| tokenizer.count_tokens(&m.content))
+            .sum();
+        let span = tracing::info_span!(
+            "llm.generate",
+            session_id,
+            model,
+            input_tokens,
+            output_tokens = tracing::field::Empty,
+        );
+        Self {
+            span,
+            model: model.to_string(),
+            tokenizer,
+            input_tokens,
+            streamed_tokens: 0,
+            started: Instant::now(),
+        }
+    }
+
+    /// Counts a chunk of a streamed reply
+    pub(crate) fn stream(&mut self, chunk: &str) {
+        self.streamed_tokens += self.tokenizer.count_tokens(chunk);
+    }
+
+    /// Returns the call's span, for instrumenting the request
+    pub(crate) fn span(&self) -> tracing::Span {
+        self.span.clone()
+    }
+
+    /// Records the reply, or `None` if the call failed, returning the tokens
+    /// of a successful call
+    pub(crate) fn finish(self, reply: Option<&str>) -> Option<ModelUsage> {
+        let output_tokens = reply.map(|reply| self.tokenizer.count_tokens(reply));
+        if let Some(output_tokens) = output_tokens {
+            self.span.record("output_tokens", output_tokens);
+        }
+        record_model_call(
+            &self.model,
+            self.started.elapsed(),
+            self.input_tokens,
+            output_tokens,
+        );
+        output_tokens.map(|output_tokens| ModelUsage {
+            model: self.model,
+            input_tokens: self.input_tokens,
+            output_tokens,
+        })
+    }
+
+    /// Records a stream that failed or was dropped before it ended,
+    /// returning the tokens it consumed: the prompt and the chunks counted
+    /// so far, which the provider bills all the same
+    pub(crate) fn interrupt(self) -> ModelUsage {
+        self.span.record("output_tokens", self.streamed_tokens);
+        record_model_call(&self.model, self.started.elapsed(), self.input_tokens, None);
+        ModelUsage {
+            model: self.model,
+            input_tokens: self.input_tokens,
+            output_tokens: self.streamed_tokens,
+        }
+    }
+}
+
+/// Runs the memory operation `future` in a `memory` span and records it
+pub(crate) async fn memory_op<T>(
+    operation: &'static str,
+    session_id: &str,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    let started = Instant::now();
+    let result = future
+        .instrument(tracing::info_span!("memory", operation, session_id))
+        .await;
+    record_memory_op(operation, started.elapsed(), result.is_ok());
+    result
+}
+
+/// Logs the rate limits a provider reported for `model` and records them
+#[cfg(any(feature = "openai", feature = "anthropic"))]
+pub(crate) fn record_rate_limit(model: &str, info: &RateLimitInfo) {
+    tracing::debug!(
+        provider = %info.provider,
+        model,
+        remaining_requests = ?info.requests.map(|quota| quota.remaining),
+        remaining_tokens = ?info.tokens.map(|quota| quota.remaining),
+        retry_after = ?info.retry_after,
+        "Rate limits reported"
+    );
+    record_quotas(model, info);
+}
+
+#[cfg(not(feature = "otel"))]
+pub(crate) fn record_route(_event: &RoutingEvent) {}
+
+#[cfg(not(feature = "otel"))]
+fn record_model_call(_model: &str, _elapsed: Duration, _input: usize, _output: Option<usize>) {}
+
+#[cfg(not(feature = "otel"))]
+fn record_memory_op(_operation: &'static str, _elapsed: Duration, _success: bool) {}
+
+#[cfg(all(not(feature = "otel"), any(feature = "openai", feature = "anthropic")))]
+fn record_quotas(_model: &str, _info: &RateLimitInfo) {}
+
+#[cfg(feature = "otel")]
+pub use self::otel::{init_otlp, OtelConfig, OtelGuard};
+
+#[cfg(all(feature = "otel", any(feature = "openai", feature = "anthropic")))]
+use self::otel::record_quotas;
+#[cfg(feature = "otel")]
+pub(crate) use self::otel::record_route;
+#[cfg(feature = "otel")]
+use self::otel::{record_memory_op, record_model_call};
+
+#[cfg(feature = "otel")]
+mod otel {
+    use std::sync::OnceLock;
+    use std::time::Duration;
+
+    #[cfg(any(feature = "openai", feature = "anthropic"))]
+    use opentelemetry::metrics::Gauge;
+    use opentelemetry::metrics::{Counter, Histogram};
+    use opentelemetry::trace::TracerProvider as _;
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider};
+    use opentelemetry_sdk::trace::TracerProvider;
+    use opentelemetry_sdk::{runtime, Resource};
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::util::SubscriberInitExt;
+
+    use crate::error::{AgentError, Result};
+    #[cfg(any(feature = "openai", feature = "anthropic"))]
+    use crate::models::RateLimitInfo;
+    use crate::routing::RoutingEvent;
+
+    /// Where and how to export over OTLP
+    #[derive(Debug, Clone)]
+    pub struct OtelConfig {
+        endpoint: String,
+        service_name: String,
+        metrics_interval: Duration,
+    }
+
+    impl Default for OtelConfig {
+        fn default() -> Self {
+            Self {
+                endpoint: "http://localhost:4317".to_string(),
+                service_name: "rs-agent".to_string(),
+                metrics_interval: Duration::from_secs(60),
+            }
+        }
+    }
+
+    impl OtelConfig {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Exports to the OTLP gRPC collector at `endpoint`
+        pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+            self.endpoint = endpoint.into();
+            self
+        }
+
+        /// Reports spans and metrics under the `service.name` `name`
+        pub fn with_service_name(mut self, name: impl Into<String>) -> Self {
+            self.service_name = name.into();
+            self
+        }
+
+        /// Exports metrics every `interval`
+        pub fn with_metrics_interval(mut self, interval: Duration) -> Self {
+            self.metrics_interval = interval;
+            self
+        }
+
+        pub fn endpoint(&self) -> &str {
+            &self.endpoint
+        }
+
+        pub fn service_name(&self) -> &str {
+            &self.service_name
+        }
+
+        pub fn metrics_interval(&self) -> Duration {
+            self.metrics_interval
+        }
+    }
+
+    /// Keeps the OTLP exporters running; call [`OtelGuard::shutdown`] before
+    /// exiting to flush what is still buffered
+    pub struct OtelGuard {
+        tracer_provider: TracerProvider,
+        meter_provider: SdkMeterProvider,
+    }
+
+    impl OtelGuard {
+        /// Flushes and stops both exporters
+        pub fn shutdown(self) -> Result<()> {
+            let traced = self.tracer_provider.shutdown();
+            let metered = self.meter_provider.shutdown();
+            traced.map_err(|e| AgentError::Other(format!("OTLP trace shutdown: {}", e)))?;
+            metered.map_err(|e| AgentError::Other(format!("OTLP metrics shutdown: {}", e)))
+        }
+    }
+
+    /// Installs the global `tracing` subscriber and meter provider, exporting
+    /// over OTLP.
+    ///
+    /// Besides the OTLP layer the subscriber logs to stdout, filtered by
+    /// `RUST_LOG` (default `info`). Call it once, from within a Tokio
+    /// runtime, before building agents; it fails if a subscriber is already
+    /// installed.
+    pub fn init_otlp(config: OtelConfig) -> Result<OtelGuard> {
+        let resource = Resource::new([KeyValue::new("service.name", config.service_name)]);
+
+        let spans = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(config.endpoint.clone())
+            .build()
+            .map_err(|e| AgentError::ConfigError(format!("OTLP span exporter: {}", e)))?;
+        let tracer_provider = TracerProvider::builder()
+            .with_batch_exporter(spans, runtime::Tokio)
+            .with_resource(resource.clone())
+            .build();
+
+        let metrics = opentelemetry_otlp::MetricExporter::builder()
+            .with_tonic()
+            .with_endpoint(config.endpoint)
+            .build()
+            .map_err(|e| AgentError::ConfigError(format!("OTLP metric exporter: {}", e)))?;
+        let reader = PeriodicReader::builder(metrics, runtime::Tokio)
+            .with_interval(config.metrics_interval)
+            .build();
+        let meter_provider = SdkMeterProvider::builder()
+            .with_reader(reader)
+            .with_resource(resource)
+            .build();
+        opentelemetry::global::set_meter_provider(meter_provider.clone());
+
+        let filter = tracing_subscriber::EnvFilter::try_from_default_env()
+            .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info"));
+        tracing_subscriber::registry()
+            .with(filter)
+            .with(tracing_subscriber::fmt::layer())
+            .with(tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer("rs-agent")))
+            .try_init()
+            .map_err(|e| AgentError::ConfigError(format!("Tracing subscriber: {}", e)))?;
+
+        Ok(OtelGuard {
+            tracer_provider,
+            meter_provider,
+        })
+    }
+
+    struct Instruments {
+        requests: Counter<u64>,
+        request_duration: Histogram<f64>,
+        stage_duration: Histogram<f64>,
+        model_requests: Counter<u64>,
+        model_duration: Histogram<f64>,
+        tokens: Counter<u64>,
+        memory_operations: Counter<u64>,
+        memory_duration: Histogram<f64>,
+        #[cfg(any(feature = "openai", feature = "anthropic"))]
+        quota_remaining: Gauge<u64>,
+        #[cfg(any(feature = "openai", feature = "anthropic"))]
+        quota_limit: Gauge<u64>,
+    }
+
+    /// Instruments of the global meter, created on first use
+    fn instruments() -> &'static Instruments {
+        static INSTRUMENTS: OnceLock<Instruments> = OnceLock::new();
+        INSTRUMENTS.get_or_init(|| {
+            let meter = opentelemetry::global::meter("rs-agent");
+            Instruments {
+                requests: meter
+                    .u64_counter("agent.requests")
+                    .with_description("Requests handled, by routing path")
+                    .build(),
+                request_duration: meter
+                    .f64_histogram("agent.request.duration")
+                    .with_unit("s")
+                    .build(),
+                stage_duration: meter
+                    .f64_histogram("agent.stage.duration")
+                    .with_unit("s")
+                    .build(),
+                model_requests: meter
+                    .u64_counter("llm.requests")
+                    .with_description("Provider calls, by model")
+                    .build(),
+                model_duration: meter
+                    .f64_histogram("llm.request.duration")
+                    .with_unit("s")
+                    .build(),
+                tokens: meter
+                    .u64_counter("llm.tokens")
+                    .with_description("Tokens sent and received, by model and direction")
+                    .build(),
+                memory_operations: meter
+                    .u64_counter("memory.operations")
+                    .with_description("Memory operations, by operation")
+                    .build(),
+                memory_duration: meter
+                    .f64_histogram("memory.operation.duration")
+                    .with_unit("s")
+                    .build(),
+                #[cfg(any(feature = "openai", feature = "anthropic"))]
+                quota_remaining: meter
+                    .u64_gauge("llm.rate_limit.remaining")
+                    .with_description("Provider quota left, by model and quota")
+                    .build(),
+                #[cfg(any(feature = "openai", feature = "anthropic"))]
+                quota_limit: meter.u64_gauge("llm.rate_limit.limit").build(),
+            }
+        })
+    }
+
+    pub(crate) fn record_route(event: &RoutingEvent) {
+        let instruments = instruments();
+        let path = KeyValue::new("path", event.path.as_str());
+        instruments
+            .requests
+            .add(1, &[path.clone(), KeyValue::new("success", event.success)]);
+        instruments
+            .request_duration
+            .record(event.total.as_secs_f64(), std::slice::from_ref(&path));
+        for (stage, elapsed) in &event.stages {
+            instruments.stage_duration.record(
+                elapsed.as_secs_f64(),
+                &[path.clone(), KeyValue::new("stage", *stage)],
+            );
+        }
+    }
+
+    pub(super) fn record_model_call(
+        model: &str,
+        elapsed: Duration,
+        input_tokens: usize,
+        output_tokens: Option<usize>,
+    ) {
+        let instruments = instruments();
+        let model = KeyValue::new("model", model.to_string());
+        instruments.model_requests.add(
+            1,
+            &[
+                model.clone(),
+                KeyValue::new("success", output_tokens.is_some()),
+            ],
+        );
+        instruments
+            .model_duration
+            .record(elapsed.as_secs_f64(), std::slice::from_ref(&model));
+        instruments.tokens.add(
+            input_tokens as u64,
+            &[model.clone(), KeyValue::new("direction", "input")],
+        );
+        if let Some(output_tokens) = output_tokens {
+            instruments.tokens.add(
+                output_tokens as u64,
+                &[model, KeyValue::new("direction", "output")],
+            );
+        }
+    }
+
+    pub(super) fn record_memory_op(operation: &'static str, elapsed: Duration, success: bool) {
+        let instruments = instruments();
+        let operation = KeyValue::new("operation", operation);
+        instruments
+            .memory_operations
+            .add(1, &[operation.clone(), KeyValue::new("success", success)]);
+        instruments
+            .memory_duration
+            .record(elapsed.as_secs_f64(), &[operation]);
+    }
+
+    #[cfg(any(feature = "openai", feature = "anthropic"))]
+    pub(super) fn record_quotas(model: &str, info: &RateLimitInfo) {
+        let instruments = instruments();
+        let quotas = [
+            ("requests", info.requests),
+            ("tokens", info.tokens),
+            ("input_tokens", info.input_tokens),
+            ("output_tokens", info.output_tokens),
+        ];
+        for (kind, quota) in quotas {
+            if let Some(quota) = quota {
+                let attributes = [
+                    KeyValue::new("model", model.to_string()),
+                    KeyValue::new("quota", kind),
+                ];
+                instruments
+                    .quota_remaining
+                    .record(quota.remaining, &attributes);
+                instruments.quota_limit.record(quota.limit, &attributes);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AgentError;
+    use crate::models::HeuristicTokenizer;
+    use crate::types::Role;
+
+    #[tokio::test]
+    async fn test_model_call_counts_tokens_and_memory_op_passes_results() {
+        let messages = vec![Message {
+            role: Role::User,
+            content: "twelve bytes".to_string(),
+            metadata: None,
+        }];
+        let call = ModelCall::start("s", "test-model", Arc::new(HeuristicTokenizer), &messages);
+        assert_eq!(call.input_tokens, 3);
+        let usage = call.finish(Some("done")).unwrap();
+        assert_eq!((usage.input_tokens, usage.output_tokens), (3, 1));
+
+        assert_eq!(memory_op("store", "s", async { Ok(7) }).await.unwrap(), 7);
+        let failed: Result<()> = memory_op("store", "s", async {
+            Err(AgentError::MemoryError("down".into()))
+        })
+        .await;
+        assert!(failed.is_err());
+    }
+}
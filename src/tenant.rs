@@ -0,0 +1,283 @@
+//! Multi-tenant isolation for shared agent deployments.
+//!
+//! A [`TenantGuard`] records which tenant owns each session. The first tenant
+//! to touch a session claims it; any later access from a different tenant,
+//! or without a tenant, is rejected with [`AgentError::TenantViolation`].
+//! Memory records and tool requests passing through the guard are stamped
+//! with the caller's tenant id, and `SessionMemory` reads a session's owner
+//! back from those stamps, so ownership survives restarts and holds across
+//! replicas sharing a store.
+//!
+//! Calls run on behalf of the tenant of the enclosing [`scope`];
+//! `GenerateOptions::tenant_id` sets it for a turn.
+
+use std::collections::HashMap;
+use std::future::Future;
+
+use futures::stream::{BoxStream, StreamExt};
+
+use crate::error::{AgentError, Result};
+use crate::memory::MemoryRecord;
+use crate::types::ToolRequest;
+
+/// Metadata key under which the owning tenant is stamped on memory records
+pub const TENANT_METADATA_KEY: &str = "tenant_id";
+
+/// Session owners a guard caches by default
+pub const DEFAULT_OWNER_CACHE: usize = 10_000;
+
+tokio::task_local! {
+    static CURRENT: String;
+}
+
+/// Runs `future` on behalf of `tenant_id`; with `None` it keeps the
+/// enclosing scope's tenant, if any
+pub async fn scope<F: Future>(tenant_id: Option<String>, future: F) -> F::Output {
+    match tenant_id {
+        Some(tenant_id) => CURRENT.scope(tenant_id, future).await,
+        None => future.await,
+    }
+}
+
+/// Returns the tenant the current call runs on behalf of, if any
+pub fn current() -> Option<String> {
+    CURRENT.try_with(Clone::clone).ok()
+}
+
+/// Polls `stream` on behalf of `tenant_id`, for streams outliving the
+/// scope they were started in
+pub(crate) fn scope_stream<'a, T: 'a>(
+    tenant_id: Option<String>,
+    mut stream: BoxStream<'a, T>,
+) -> BoxStream<'a, T> {
+    let Some(tenant_id) = tenant_id else {
+        return stream;
+    };
+    futures::stream::poll_fn(move |cx| {
+        CURRENT.sync_scope(tenant_id.clone(), || stream.poll_next_unpin(cx))
+    })
+    .boxed()
+}
+
+/// Returns the tenant stamped on `record`, if any
+pub fn stamped_tenant(record: &MemoryRecord) -> Option<&str> {
+    record
+        .metadata
+        .as_ref()
+        .and_then(|m| m.get(TENANT_METADATA_KEY))
+        .map(String::as_str)
+}
+
+/// Session ownership registry shared by `SessionMemory` and `ToolCatalog`.
+///
+/// Owners are cached in process, up to a bound; the stamps on stored
+/// records stay the source of truth.
+pub struct TenantGuard {
+    owners: parking_lot::RwLock<HashMap<String, String>>,
+    capacity: usize,
+}
+
+impl Default for TenantGuard {
+    fn default() -> Self {
+        Self::with_capacity(DEFAULT_OWNER_CACHE)
+    }
+}
+
+impl TenantGuard {
+    /// Creates a guard with no claimed sessions
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a guard caching the owners of at most `capacity` sessions
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            owners: parking_lot::RwLock::new(HashMap::new()),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Allows `tenant_id` to access `session_id`, claiming it if unowned
+    pub fn authorize(&self, tenant_id: &str, session_id: &str) -> Result<()> {
+        if let Some(owner) = self.owners.read().get(session_id) {
+            return check_owner(owner, tenant_id, session_id);
+        }
+
+        let mut owners = self.owners.write();
+        if !owners.contains_key(session_id) {
+            evict_one(&mut owners, self.capacity);
+        }
+        let owner = owners
+            .entry(session_id.to_string())
+            .or_insert_with(|| tenant_id.to_string());
+        check_owner(owner, tenant_id, session_id)
+    }
+
+    /// Allows a call on behalf of `tenant_id`, or of no tenant, to access
+    /// `session_id`; sessions owned by a tenant refuse calls without one
+    pub fn check(&self, tenant_id: Option<&str>, session_id: &str) -> Result<()> {
+        match tenant_id {
+            Some(tenant_id) => self.authorize(tenant_id, session_id),
+            None if self.owner(session_id).is_some() => Err(AgentError::TenantViolation(format!(
+                "session {} is owned by a tenant",
+                session_id
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// Records `tenant_id` as the owner of `session_id`, as read from the
+    /// session's stored records
+    pub fn remember(&self, session_id: &str, tenant_id: &str) {
+        let mut owners = self.owners.write();
+        if !owners.contains_key(session_id) {
+            evict_one(&mut owners, self.capacity);
+        }
+        owners.insert(session_id.to_string(), tenant_id.to_string());
+    }
+
+    /// Returns the tenant that owns `session_id`, if claimed
+    pub fn owner(&self, session_id: &str) -> Option<String> {
+        self.owners.read().get(session_id).cloned()
+    }
+
+    /// Releases a session so another tenant may claim it
+    pub fn release(&self, session_id: &str) -> Option<String> {
+        self.owners.write().remove(session_id)
+    }
+
+    /// Authorizes the record's session and stamps it with `tenant_id`
+    pub fn stamp_record(&self, tenant_id: &str, record: &mut MemoryRecord) -> Result<()> {
+        self.authorize(tenant_id, &record.session_id)?;
+
+        let metadata = record.metadata.get_or_insert_with(HashMap::new);
+        if let Some(existing) = metadata.get(TENANT_METADATA_KEY) {
+            if existing != tenant_id {
+                return Err(AgentError::TenantViolation(format!(
+                    "record {} belongs to another tenant",
+                    record.id
+                )));
+            }
+        }
+        metadata.insert(TENANT_METADATA_KEY.to_string(), tenant_id.to_string());
+        Ok(())
+    }
+
+    /// Verifies that a record read back from a store carries `tenant_id`'s stamp
+    pub fn check_record(&self, tenant_id: &str, record: &MemoryRecord) -> Result<()> {
+        if stamped_tenant(record) == Some(tenant_id) {
+            Ok(())
+        } else {
+            Err(AgentError::TenantViolation(format!(
+                "record {} is not owned by tenant {}",
+                record.id, tenant_id
+            )))
+        }
+    }
+
+    /// Authorizes the request's session and stamps it with `tenant_id`
+    pub fn stamp_request(&self, tenant_id: &str, req: &mut ToolRequest) -> Result<()> {
+        self.authorize(tenant_id, &req.session_id)?;
+
+        match &req.tenant_id {
+            Some(existing) if existing != tenant_id => Err(AgentError::TenantViolation(format!(
+                "tool request for session {} was issued by another tenant",
+                req.session_id
+            ))),
+            _ => {
+                req.tenant_id = Some(tenant_id.to_string());
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Makes room for one more owner; any will do, since evicted owners are
+/// read back from the store
+fn evict_one(owners: &mut HashMap<String, String>, capacity: usize) {
+    if owners.len() >= capacity {
+        if let Some(session_id) = owners.keys().next().cloned() {
+            owners.remove(&session_id);
+        }
+    }
+}
+
+fn check_owner(owner: &str, tenant_id: &str, session_id: &str) -> Result<()> {
+    if owner == tenant_id {
+        Ok(())
+    } else {
+        Err(AgentError::TenantViolation(format!(
+            "session {} is owned by another tenant",
+            session_id
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    fn record(session_id: &str) -> MemoryRecord {
+        MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: session_id.to_string(),
+            role: "user".to_string(),
+            content: "hello".to_string(),
+            importance: 0.5,
+            timestamp: Utc::now(),
+            metadata: None,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        }
+    }
+
+    #[test]
+    fn test_first_tenant_claims_session() {
+        let guard = TenantGuard::new();
+        guard.authorize("acme", "s1").unwrap();
+        guard.authorize("acme", "s1").unwrap();
+
+        let err = guard.authorize("globex", "s1").unwrap_err();
+        assert!(matches!(err, AgentError::TenantViolation(_)));
+        assert_eq!(guard.owner("s1").as_deref(), Some("acme"));
+
+        guard.release("s1");
+        guard.authorize("globex", "s1").unwrap();
+        assert!(guard.check(None, "s1").is_err());
+        guard.check(None, "s2").unwrap();
+    }
+
+    #[test]
+    fn test_owner_cache_is_bounded() {
+        let guard = TenantGuard::with_capacity(2);
+        guard.authorize("acme", "s1").unwrap();
+        guard.authorize("acme", "s2").unwrap();
+        guard.remember("s3", "globex");
+
+        assert_eq!(guard.owners.read().len(), 2);
+        assert_eq!(guard.owner("s3").as_deref(), Some("globex"));
+    }
+
+    #[tokio::test]
+    async fn test_scope_sets_current_tenant() {
+        assert_eq!(current(), None);
+        let inner = scope(Some("acme".to_string()), async {
+            scope(None, async { current() }).await
+        });
+        assert_eq!(inner.await.as_deref(), Some("acme"));
+    }
+
+    #[test]
+    fn test_stamped_record_round_trip() {
+        let guard = TenantGuard::new();
+        let mut rec = record("s1");
+        guard.stamp_record("acme", &mut rec).unwrap();
+
+        guard.check_record("acme", &rec).unwrap();
+        assert!(guard.check_record("globex", &rec).is_err());
+        assert!(guard.check_record("acme", &record("s1")).is_err());
+    }
+}
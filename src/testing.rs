@@ -0,0 +1,531 @@
+//! Fault injection for resilience testing.
+//!
+//! [`ChaosLLM`] and [`ChaosStore`] wrap a model or memory store and inject
+//! latency, errors and malformed responses at configurable rates, so retry,
+//! fallback ([`ModelRoute`](crate::ModelRoute)) and degraded-mode
+//! ([`DegradableStore`](crate::DegradableStore)) setups can be checked
+//! under failure. Faults are drawn from a seeded generator, so a test run
+//! is reproducible; [`ChaosLLM::set_config`] and [`ChaosStore::set_config`]
+//! switch faults on and off mid-test.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::stream::{self, BoxStream, StreamExt};
+
+use crate::error::{AgentError, Result};
+use crate::memory::{export_pages, MemoryFilter, MemoryPage, MemoryRecord, MemoryStore};
+use crate::models::{Capabilities, GenerationSettings, LLM};
+use crate::types::{File, GenerationResponse, Message};
+
+/// Which faults a chaos wrapper injects, and how often
+#[derive(Debug, Clone)]
+pub struct ChaosConfig {
+    latency: Duration,
+    jitter: Duration,
+    error_rate: f64,
+    malformed_rate: f64,
+    seed: u64,
+}
+
+impl Default for ChaosConfig {
+    fn default() -> Self {
+        Self {
+            latency: Duration::ZERO,
+            jitter: Duration::ZERO,
+            error_rate: 0.0,
+            malformed_rate: 0.0,
+            seed: 0x5eed,
+        }
+    }
+}
+
+impl ChaosConfig {
+    /// Injects nothing until configured
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delays every call by `latency`
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Adds a random extra delay of up to `jitter` to every call
+    pub fn with_jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Fails this share of calls, from 0.0 to 1.0
+    pub fn with_error_rate(mut self, rate: f64) -> Self {
+        self.error_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Corrupts this share of responses, from 0.0 to 1.0
+    pub fn with_malformed_rate(mut self, rate: f64) -> Self {
+        self.malformed_rate = rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Seeds the fault generator; the same seed injects the same faults
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Returns the fixed delay
+    pub fn latency(&self) -> Duration {
+        self.latency
+    }
+
+    /// Returns the maximum random extra delay
+    pub fn jitter(&self) -> Duration {
+        self.jitter
+    }
+
+    /// Returns the share of calls that fail
+    pub fn error_rate(&self) -> f64 {
+        self.error_rate
+    }
+
+    /// Returns the share of responses that are corrupted
+    pub fn malformed_rate(&self) -> f64 {
+        self.malformed_rate
+    }
+}
+
+/// Faults injected so far by a chaos wrapper
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChaosStats {
+    /// Calls that went through the wrapper
+    pub calls: u64,
+    /// Calls failed with an injected error
+    pub errors: u64,
+    /// Responses that were corrupted
+    pub malformed: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Fault {
+    None,
+    Error,
+    Malformed,
+}
+
+/// Fault generator shared by the wrappers
+struct Chaos {
+    config: parking_lot::RwLock<ChaosConfig>,
+    state: AtomicU64,
+    calls: AtomicU64,
+    errors: AtomicU64,
+    malformed: AtomicU64,
+}
+
+impl Chaos {
+    fn new(config: ChaosConfig) -> Self {
+        Self {
+            state: AtomicU64::new(config.seed),
+            config: parking_lot::RwLock::new(config),
+            calls: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            malformed: AtomicU64::new(0),
+        }
+    }
+
+    fn set_config(&self, config: ChaosConfig) {
+        self.state.store(config.seed, Ordering::Relaxed);
+        *self.config.write() = config;
+    }
+
+    fn stats(&self) -> ChaosStats {
+        ChaosStats {
+            calls: self.calls.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            malformed: self.malformed.load(Ordering::Relaxed),
+        }
+    }
+
+    /// SplitMix64, uniform in `[0, 1)`
+    fn roll(&self) -> f64 {
+        const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut z = self
+            .state
+            .fetch_add(GAMMA, Ordering::Relaxed)
+            .wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Sleeps for the configured latency and picks this call's fault
+    async fn inject(&self) -> Fault {
+        let config = self.config.read().clone();
+        self.calls.fetch_add(1, Ordering::Relaxed);
+
+        let delay = config.latency + config.jitter.mul_f64(self.roll());
+        if !delay.is_zero() {
+            tokio::time::sleep(delay).await;
+        }
+
+        let roll = self.roll();
+        if roll < config.error_rate {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+            Fault::Error
+        } else if roll < config.error_rate + config.malformed_rate {
+            self.malformed.fetch_add(1, Ordering::Relaxed);
+            Fault::Malformed
+        } else {
+            Fault::None
+        }
+    }
+}
+
+/// Returns the first half of `text`, cut at a char boundary
+fn truncated(text: &str) -> &str {
+    let mut end = text.len() / 2;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    &text[..end]
+}
+
+/// An [`LLM`] wrapper that injects latency, errors and malformed responses.
+///
+/// Errors are [`AgentError::ModelError`]s. A malformed response is cut in
+/// half and ends in unterminated JSON, with its metadata dropped; a
+/// malformed stream fails after its first chunk.
+pub struct ChaosLLM {
+    inner: Arc<dyn LLM>,
+    chaos: Chaos,
+}
+
+impl ChaosLLM {
+    /// Wraps `inner` with the given faults
+    pub fn new(inner: Arc<dyn LLM>, config: ChaosConfig) -> Self {
+        Self {
+            inner,
+            chaos: Chaos::new(config),
+        }
+    }
+
+    /// Replaces the injected faults, restarting the generator at the new seed
+    pub fn set_config(&self, config: ChaosConfig) {
+        self.chaos.set_config(config);
+    }
+
+    /// Returns the faults injected so far
+    pub fn stats(&self) -> ChaosStats {
+        self.chaos.stats()
+    }
+
+    fn injected_error(&self) -> AgentError {
+        AgentError::ModelError(format!("Injected failure in {}", self.inner.model_name()))
+    }
+}
+
+#[async_trait]
+impl LLM for ChaosLLM {
+    async fn generate(
+        &self,
+        messages: Vec<Message>,
+        files: Option<Vec<File>>,
+    ) -> Result<GenerationResponse> {
+        self.generate_with(messages, files, &GenerationSettings::default())
+            .await
+    }
+
+    async fn generate_with(
+        &self,
+        messages: Vec<Message>,
+        files: Option<Vec<File>>,
+        settings: &GenerationSettings,
+    ) -> Result<GenerationResponse> {
+        let fault = self.chaos.inject().await;
+        if fault == Fault::Error {
+            return Err(self.injected_error());
+        }
+        let response = self.inner.generate_with(messages, files, settings).await?;
+        if fault == Fault::Malformed {
+            return Ok(GenerationResponse {
+                content: format!("{}{{\"", truncated(&response.content)),
+                metadata: None,
+            });
+        }
+        Ok(response)
+    }
+
+    async fn generate_stream(
+        &self,
+        messages: Vec<Message>,
+        files: Option<Vec<File>>,
+    ) -> Result<BoxStream<'static, Result<String>>> {
+        let fault = self.chaos.inject().await;
+        if fault == Fault::Error {
+            return Err(self.injected_error());
+        }
+        let chunks = self.inner.generate_stream(messages, files).await?;
+        if fault == Fault::Malformed {
+            let error = AgentError::ModelError("Injected stream interruption".to_string());
+            return Ok(chunks
+                .take(1)
+                .chain(stream::once(async move { Err(error) }))
+                .boxed());
+        }
+        Ok(chunks)
+    }
+
+    fn model_name(&self) -> &str {
+        self.inner.model_name()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+}
+
+/// A [`MemoryStore`] wrapper that injects latency, errors and malformed
+/// records.
+///
+/// Every call can be delayed or fail with an [`AgentError::MemoryError`].
+/// Only reads are malformed: their records come back with the content cut
+/// in half and the embedding truncated to the wrong dimension.
+pub struct ChaosStore {
+    inner: Arc<dyn MemoryStore>,
+    chaos: Chaos,
+}
+
+impl ChaosStore {
+    /// Wraps `inner` with the given faults
+    pub fn new(inner: Arc<dyn MemoryStore>, config: ChaosConfig) -> Self {
+        Self {
+            inner,
+            chaos: Chaos::new(config),
+        }
+    }
+
+    /// Replaces the injected faults, restarting the generator at the new seed
+    pub fn set_config(&self, config: ChaosConfig) {
+        self.chaos.set_config(config);
+    }
+
+    /// Returns the faults injected so far
+    pub fn stats(&self) -> ChaosStats {
+        self.chaos.stats()
+    }
+
+    /// Fails the call if an error is injected, returning whether to corrupt
+    /// what it reads
+    async fn inject(&self) -> Result<bool> {
+        match self.chaos.inject().await {
+            Fault::Error => Err(AgentError::MemoryError(
+                "Injected memory store failure".to_string(),
+            )),
+            Fault::Malformed => Ok(true),
+            Fault::None => Ok(false),
+        }
+    }
+}
+
+fn corrupt(mut records: Vec<MemoryRecord>) -> Vec<MemoryRecord> {
+    for record in &mut records {
+        record.content = truncated(&record.content).to_string();
+        if let Some(embedding) = &mut record.embedding {
+            embedding.truncate(embedding.len() / 2);
+        }
+    }
+    records
+}
+
+#[async_trait]
+impl MemoryStore for ChaosStore {
+    async fn store(&self, record: MemoryRecord) -> Result<()> {
+        self.inject().await?;
+        self.inner.store(record).await
+    }
+
+    async fn store_batch(&self, records: Vec<MemoryRecord>) -> Result<()> {
+        self.inject().await?;
+        self.inner.store_batch(records).await
+    }
+
+    async fn retrieve(&self, session_id: &str, limit: usize) -> Result<Vec<MemoryRecord>> {
+        self.retrieve_filtered(session_id, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn retrieve_filtered(
+        &self,
+        session_id: &str,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        let malformed = self.inject().await?;
+        let records = self
+            .inner
+            .retrieve_filtered(session_id, limit, filter)
+            .await?;
+        Ok(if malformed { corrupt(records) } else { records })
+    }
+
+    async fn retrieve_page(
+        &self,
+        session_id: &str,
+        cursor: Option<&str>,
+        page_size: usize,
+    ) -> Result<MemoryPage> {
+        let malformed = self.inject().await?;
+        let mut page = self
+            .inner
+            .retrieve_page(session_id, cursor, page_size)
+            .await?;
+        if malformed {
+            page.records = corrupt(page.records);
+        }
+        Ok(page)
+    }
+
+    fn export<'a>(&'a self, session_id: &'a str) -> BoxStream<'a, Result<MemoryRecord>> {
+        export_pages(self, session_id)
+    }
+
+    async fn search(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+    ) -> Result<Vec<MemoryRecord>> {
+        self.search_filtered(session_id, query_embedding, limit, &MemoryFilter::default())
+            .await
+    }
+
+    async fn search_filtered(
+        &self,
+        session_id: &str,
+        query_embedding: Vec<f32>,
+        limit: usize,
+        filter: &MemoryFilter,
+    ) -> Result<Vec<MemoryRecord>> {
+        let malformed = self.inject().await?;
+        let records = self
+            .inner
+            .search_filtered(session_id, query_embedding, limit, filter)
+            .await?;
+        Ok(if malformed { corrupt(records) } else { records })
+    }
+
+    async fn update(&self, record: MemoryRecord) -> Result<u64> {
+        self.inject().await?;
+        self.inner.update(record).await
+    }
+
+    async fn purge_expired(&self) -> Result<usize> {
+        self.inject().await?;
+        self.inner.purge_expired().await
+    }
+
+    async fn flush(&self) -> Result<()> {
+        self.inject().await?;
+        self.inner.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::{DegradableStore, DegradationConfig, InMemoryStore};
+    use crate::types::Role;
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    struct FixedLLM;
+
+    #[async_trait]
+    impl LLM for FixedLLM {
+        async fn generate(
+            &self,
+            _messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<GenerationResponse> {
+            Ok(GenerationResponse {
+                content: r#"{"answer": 42}"#.to_string(),
+                metadata: None,
+            })
+        }
+
+        fn model_name(&self) -> &str {
+            "fixed"
+        }
+    }
+
+    fn message() -> Vec<Message> {
+        vec![Message {
+            role: Role::User,
+            content: "question".to_string(),
+            metadata: None,
+        }]
+    }
+
+    #[tokio::test]
+    async fn test_chaos_llm_injects_errors_and_malformed_responses() {
+        let llm = ChaosLLM::new(Arc::new(FixedLLM), ChaosConfig::new().with_error_rate(1.0));
+        assert!(matches!(
+            llm.generate(message(), None).await,
+            Err(AgentError::ModelError(_))
+        ));
+
+        llm.set_config(ChaosConfig::new().with_malformed_rate(1.0));
+        let response = llm.generate(message(), None).await.unwrap();
+        assert!(serde_json::from_str::<serde_json::Value>(&response.content).is_err());
+
+        llm.set_config(ChaosConfig::new());
+        assert_eq!(
+            llm.generate(message(), None).await.unwrap().content,
+            r#"{"answer": 42}"#
+        );
+        assert_eq!(
+            llm.stats(),
+            ChaosStats {
+                calls: 3,
+                errors: 1,
+                malformed: 1,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chaos_store_drives_degraded_mode() {
+        let chaos = Arc::new(ChaosStore::new(
+            Arc::new(InMemoryStore::new()),
+            ChaosConfig::new().with_error_rate(1.0),
+        ));
+        let store = DegradableStore::with_config(
+            chaos.clone(),
+            DegradationConfig::new().with_retry_interval(Duration::ZERO),
+        );
+
+        let record = MemoryRecord {
+            id: Uuid::new_v4(),
+            session_id: "s".to_string(),
+            role: "user".to_string(),
+            content: "hello".to_string(),
+            importance: 0.5,
+            timestamp: Utc::now(),
+            metadata: None,
+            embedding: None,
+            version: 0,
+            expires_at: None,
+        };
+        store.store(record).await.unwrap();
+        assert!(store.is_degraded());
+
+        chaos.set_config(ChaosConfig::new());
+        assert_eq!(store.retrieve("s", 10).await.unwrap().len(), 1);
+        assert!(!store.is_degraded());
+    }
+}
@@ -0,0 +1,130 @@
+//! Several tool calls run as one batch.
+//!
+//! When a turn fans out to several tools and only some of them fail, the
+//! turn shouldn't collapse into one opaque error. A [`ToolBatch`] keeps the
+//! result of every call, [`ToolBatch::statuses`] reports which succeeded,
+//! and [`ToolBatch::metadata`] puts that report in the response metadata
+//! under [`TOOL_CALLS_KEY`]. The agent also stores each failure in the
+//! session, so the model sees which calls failed and why.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::error::Result;
+
+/// Response metadata key holding the JSON array of [`ToolCallStatus`]es
+pub const TOOL_CALLS_KEY: &str = "tool_calls";
+/// Metadata key set to `"error"` on the stored record of a failed call
+pub const TOOL_STATUS_KEY: &str = "tool_status";
+
+/// One tool to call and its arguments
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub name: String,
+    #[serde(default)]
+    pub arguments: HashMap<String, Value>,
+}
+
+impl ToolCall {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            arguments: HashMap::new(),
+        }
+    }
+
+    /// Sets one argument
+    pub fn with_argument(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.arguments.insert(key.into(), value.into());
+        self
+    }
+}
+
+/// How one call of a batch went
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolCallStatus {
+    pub name: String,
+    pub ok: bool,
+    /// Why the call failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Results of a batch of tool calls, in call order
+pub struct ToolBatch {
+    results: Vec<(ToolCall, Result<String>)>,
+}
+
+impl ToolBatch {
+    pub fn new(results: Vec<(ToolCall, Result<String>)>) -> Self {
+        Self { results }
+    }
+
+    /// Returns each call with its output or error
+    pub fn results(&self) -> &[(ToolCall, Result<String>)] {
+        &self.results
+    }
+
+    /// Takes the calls and their outputs or errors
+    pub fn into_results(self) -> Vec<(ToolCall, Result<String>)> {
+        self.results
+    }
+
+    /// Returns whether every call succeeded
+    pub fn all_succeeded(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+
+    /// Returns how each call went
+    pub fn statuses(&self) -> Vec<ToolCallStatus> {
+        self.results
+            .iter()
+            .map(|(call, result)| ToolCallStatus {
+                name: call.name.clone(),
+                ok: result.is_ok(),
+                error: result.as_ref().err().map(ToString::to_string),
+            })
+            .collect()
+    }
+
+    /// Returns response metadata holding the statuses under
+    /// [`TOOL_CALLS_KEY`]
+    pub fn metadata(&self) -> HashMap<String, String> {
+        let statuses = serde_json::to_string(&self.statuses()).unwrap_or_default();
+        HashMap::from([(TOOL_CALLS_KEY.to_string(), statuses)])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AgentError;
+
+    #[test]
+    fn test_statuses_report_each_call() {
+        let batch = ToolBatch::new(vec![
+            (
+                ToolCall::new("weather").with_argument("city", "Oslo"),
+                Ok("sunny".to_string()),
+            ),
+            (
+                ToolCall::new("stocks"),
+                Err(AgentError::ToolError("rate limited".to_string())),
+            ),
+        ]);
+        assert!(!batch.all_succeeded());
+
+        let statuses: Vec<ToolCallStatus> =
+            serde_json::from_str(&batch.metadata()[TOOL_CALLS_KEY]).unwrap();
+        assert_eq!(statuses, batch.statuses());
+        assert!(statuses[0].ok && statuses[0].error.is_none());
+        assert_eq!(statuses[1].name, "stocks");
+        assert!(statuses[1]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("rate limited"));
+    }
+}
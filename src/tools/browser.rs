@@ -0,0 +1,563 @@
+//! Built-in headless browser tool.
+//!
+//! Many pages render their content with JavaScript, so a plain HTTP fetch
+//! sees an empty shell. [`BrowserTool`] drives headless Chrome over the
+//! DevTools protocol instead: `navigate` loads a page and waits for it,
+//! `extract_text` returns the rendered text of the page or of an element,
+//! and `screenshot` captures it as PNG. Each session keeps its own page
+//! between calls. Screenshots are too large for a tool reply; they go to a
+//! [`BlobStore`] and the reply carries the reference, for the host to
+//! attach to the next turn of a vision model.
+//!
+//! The model picks the URLs, so a [`HostPolicy`] decides which hosts the
+//! browser may load. By default it refuses loopback, private and
+//! link-local addresses, cloud metadata endpoints included.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use chromiumoxide::browser::{Browser, BrowserConfig};
+use chromiumoxide::page::ScreenshotParams;
+use chromiumoxide::Page;
+use futures::StreamExt;
+use serde_json::{json, Value};
+use tokio::sync::OnceCell;
+use tokio::task::JoinHandle;
+
+use crate::blob::{BlobStore, BLOB_REF_KEY, BLOB_SIZE_KEY};
+use crate::error::{AgentError, Result};
+use crate::tools::Tool;
+use crate::types::{ToolRequest, ToolResponse, ToolSpec};
+
+/// Name under which [`BrowserTool`] is registered
+pub const BROWSER_TOOL: &str = "browser";
+/// Metadata key holding the URL of the page an action ran on
+pub const PAGE_URL_KEY: &str = "page_url";
+
+/// Time an action may take by default, page load included
+pub const DEFAULT_BROWSER_TIMEOUT: Duration = Duration::from_secs(30);
+/// Bytes of page text returned by default
+pub const DEFAULT_MAX_TEXT_BYTES: usize = 32 * 1024;
+/// Time a session's page stays open unused by default
+pub const DEFAULT_PAGE_IDLE_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Which hosts [`BrowserTool`] may load pages from.
+///
+/// Hosts on the deny list are refused, and with an allow list only the
+/// hosts on it are loaded; either list covers subdomains too. Unless
+/// [`allow_private_networks`](HostPolicy::allow_private_networks) is set,
+/// hosts resolving to loopback, private, link-local or otherwise
+/// non-public addresses are refused as well.
+#[derive(Debug, Clone, Default)]
+pub struct HostPolicy {
+    allowed: Vec<String>,
+    denied: Vec<String>,
+    private_networks: bool,
+}
+
+impl HostPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Loads only hosts on the allow list, `host` and its subdomains among
+    /// them
+    pub fn with_allowed(mut self, host: impl Into<String>) -> Self {
+        self.allowed.push(host.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Refuses `host` and its subdomains
+    pub fn with_denied(mut self, host: impl Into<String>) -> Self {
+        self.denied.push(host.into().to_ascii_lowercase());
+        self
+    }
+
+    /// Loads hosts on internal networks too, for browsing an intranet
+    pub fn allow_private_networks(mut self) -> Self {
+        self.private_networks = true;
+        self
+    }
+
+    /// Checks that `url` is an `http` or `https` URL of a host the policy
+    /// admits, resolving its name to check the addresses
+    pub async fn check(&self, url: &str) -> Result<()> {
+        let refuse = |reason: &str| {
+            Err(AgentError::ToolError(format!(
+                "Refusing to open {}: {}",
+                url, reason
+            )))
+        };
+        let parsed = match reqwest::Url::parse(url) {
+            Ok(parsed) => parsed,
+            Err(_) => return refuse("not a valid URL"),
+        };
+        if !matches!(parsed.scheme(), "http" | "https") {
+            return refuse("only http and https URLs can be opened");
+        }
+        let Some(host) = parsed.host_str() else {
+            return refuse("no host");
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        let host = host.to_ascii_lowercase();
+        if self.denied.iter().any(|denied| covers(denied, &host)) {
+            return refuse("host is denied");
+        }
+        if !self.allowed.is_empty() && !self.allowed.iter().any(|allowed| covers(allowed, &host)) {
+            return refuse("host is not allowed");
+        }
+        if self.private_networks {
+            return Ok(());
+        }
+        let addresses: Vec<IpAddr> = match host.parse::<IpAddr>() {
+            Ok(address) => vec![address],
+            Err(_) => {
+                let port = parsed.port_or_known_default().unwrap_or(80);
+                match tokio::net::lookup_host((host.as_str(), port)).await {
+                    Ok(resolved) => resolved.map(|address| address.ip()).collect(),
+                    Err(_) => return refuse("host doesn't resolve"),
+                }
+            }
+        };
+        if addresses.iter().any(|address| !is_public(address)) {
+            return refuse("host is on a private network");
+        }
+        Ok(())
+    }
+}
+
+/// Returns true if `pattern` is `host` or one of its parent domains
+fn covers(pattern: &str, host: &str) -> bool {
+    host == pattern
+        || host
+            .strip_suffix(pattern)
+            .is_some_and(|rest| rest.ends_with('.'))
+}
+
+/// Returns true for addresses reachable on the public internet
+fn is_public(address: &IpAddr) -> bool {
+    match address {
+        IpAddr::V4(v4) => {
+            let [a, b, ..] = v4.octets();
+            !(v4.is_loopback()
+                || v4.is_private()
+                || v4.is_link_local()
+                || v4.is_unspecified()
+                || v4.is_broadcast()
+                || v4.is_documentation()
+                // Shared address space (carrier-grade NAT)
+                || (a == 100 && (64..128).contains(&b))
+                || a == 0
+                || a >= 240)
+        }
+        IpAddr::V6(v6) => {
+            if let Some(v4) = v6.to_ipv4_mapped() {
+                return is_public(&IpAddr::V4(v4));
+            }
+            let first = v6.segments()[0];
+            !(v6.is_loopback()
+                || v6.is_unspecified()
+                || v6.is_multicast()
+                // Unique local fc00::/7 and link-local fe80::/10
+                || (first & 0xfe00) == 0xfc00
+                || (first & 0xffc0) == 0xfe80)
+        }
+    }
+}
+
+struct RunningBrowser {
+    browser: Browser,
+    // Drives the DevTools connection; the browser stalls without it
+    handler: JoinHandle<()>,
+}
+
+/// Built-in tool that browses JavaScript-rendered pages with headless
+/// Chrome.
+///
+/// Chrome is launched on first use. Only `http` and `https` URLs of hosts
+/// the [`HostPolicy`] admits are opened, and a page that ends up
+/// elsewhere, through a redirect or a script, is left before anything is
+/// read from it. Redirect hops in between are still fetched, so pair the
+/// policy with network egress rules where that matters. Pages unused for
+/// the idle timeout are closed.
+pub struct BrowserTool {
+    blobs: Arc<dyn BlobStore>,
+    chrome_path: Option<PathBuf>,
+    timeout: Duration,
+    max_text: usize,
+    hosts: HostPolicy,
+    idle_timeout: Duration,
+    browser: OnceCell<RunningBrowser>,
+    // Page of each session and when it was last used
+    pages: parking_lot::Mutex<HashMap<String, (Page, Instant)>>,
+}
+
+impl BrowserTool {
+    /// Keeps screenshots in `blobs`
+    pub fn new(blobs: Arc<dyn BlobStore>) -> Self {
+        Self {
+            blobs,
+            chrome_path: None,
+            timeout: DEFAULT_BROWSER_TIMEOUT,
+            max_text: DEFAULT_MAX_TEXT_BYTES,
+            hosts: HostPolicy::default(),
+            idle_timeout: DEFAULT_PAGE_IDLE_TIMEOUT,
+            browser: OnceCell::new(),
+            pages: parking_lot::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Runs the Chrome or Chromium binary at `path` instead of looking one up
+    pub fn with_chrome_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.chrome_path = Some(path.into());
+        self
+    }
+
+    /// Fails actions taking longer than `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Truncates extracted text beyond `bytes`
+    pub fn with_max_text(mut self, bytes: usize) -> Self {
+        self.max_text = bytes;
+        self
+    }
+
+    /// Loads pages only from the hosts `policy` admits
+    pub fn with_host_policy(mut self, policy: HostPolicy) -> Self {
+        self.hosts = policy;
+        self
+    }
+
+    /// Closes session pages unused for `timeout`
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Closes the page of `session_id`, if it has one
+    pub async fn close_session(&self, session_id: &str) -> Result<()> {
+        let page = self.pages.lock().remove(session_id);
+        if let Some((page, _)) = page {
+            page.close().await.map_err(browser_error)?;
+        }
+        Ok(())
+    }
+
+    /// Closes the pages unused for the idle timeout
+    async fn evict_idle(&self) {
+        let idle: Vec<Page> = {
+            let mut pages = self.pages.lock();
+            let expired: Vec<String> = pages
+                .iter()
+                .filter(|(_, (_, used))| used.elapsed() >= self.idle_timeout)
+                .map(|(session_id, _)| session_id.clone())
+                .collect();
+            expired
+                .into_iter()
+                .filter_map(|session_id| pages.remove(&session_id))
+                .map(|(page, _)| page)
+                .collect()
+        };
+        for page in idle {
+            if let Err(e) = page.close().await {
+                tracing::warn!("Closing idle browser page failed: {}", e);
+            }
+        }
+    }
+
+    /// Returns the open page of `session_id`, marking it used
+    fn open_page(&self, session_id: &str) -> Option<Page> {
+        let mut pages = self.pages.lock();
+        let (page, used) = pages.get_mut(session_id)?;
+        *used = Instant::now();
+        Some(page.clone())
+    }
+
+    /// Checks that `page` is still on a host the policy admits, leaving it
+    /// for a blank page if not
+    async fn check_page(&self, page: &Page) -> Result<()> {
+        let Some(url) = page.url().await.map_err(browser_error)? else {
+            return Ok(());
+        };
+        if url == "about:blank" {
+            return Ok(());
+        }
+        if let Err(e) = self.hosts.check(&url).await {
+            if let Err(e) = page.goto("about:blank").await {
+                tracing::warn!("Leaving refused browser page failed: {}", e);
+            }
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    async fn browser(&self) -> Result<&Browser> {
+        let running = self
+            .browser
+            .get_or_try_init(|| async {
+                let mut config = BrowserConfig::builder();
+                if let Some(path) = &self.chrome_path {
+                    config = config.chrome_executable(path);
+                }
+                let config = config.build().map_err(AgentError::ToolError)?;
+                let (browser, mut events) = Browser::launch(config).await.map_err(browser_error)?;
+                let handler = tokio::spawn(async move {
+                    while let Some(event) = events.next().await {
+                        if event.is_err() {
+                            break;
+                        }
+                    }
+                });
+                Ok::<_, AgentError>(RunningBrowser { browser, handler })
+            })
+            .await?;
+        Ok(&running.browser)
+    }
+
+    /// Loads `url` in the page of `session_id`, opening one if needed
+    async fn navigate(&self, session_id: &str, url: &str) -> Result<Page> {
+        self.hosts.check(url).await?;
+        let existing = self.open_page(session_id);
+        let page = match existing {
+            Some(page) => {
+                page.goto(url).await.map_err(browser_error)?;
+                page
+            }
+            None => {
+                let page = self
+                    .browser()
+                    .await?
+                    .new_page(url)
+                    .await
+                    .map_err(browser_error)?;
+                self.pages
+                    .lock()
+                    .insert(session_id.to_string(), (page.clone(), Instant::now()));
+                page
+            }
+        };
+        page.wait_for_navigation().await.map_err(browser_error)?;
+        // Redirects may have ended up somewhere the policy refuses
+        self.check_page(&page).await?;
+        Ok(page)
+    }
+
+    /// Returns the page of `session_id`, first loading `url` if given
+    async fn page(&self, session_id: &str, url: Option<&str>) -> Result<Page> {
+        if let Some(url) = url {
+            return self.navigate(session_id, url).await;
+        }
+        let page = self.open_page(session_id).ok_or_else(|| {
+            AgentError::ToolError("No page open; navigate to a URL first".to_string())
+        })?;
+        // Scripts may have navigated the page since it was loaded
+        self.check_page(&page).await?;
+        Ok(page)
+    }
+
+    async fn run(&self, req: &ToolRequest) -> Result<ToolResponse> {
+        self.evict_idle().await;
+        let arg = |key: &str| req.arguments.get(key).and_then(Value::as_str);
+        let action =
+            arg("action").ok_or_else(|| AgentError::ToolError("missing 'action'".to_string()))?;
+        let url = arg("url");
+        let (page, content, mut metadata) = match action {
+            "navigate" => {
+                let url = url.ok_or_else(|| AgentError::ToolError("missing 'url'".to_string()))?;
+                let page = self.navigate(&req.session_id, url).await?;
+                let title = page.get_title().await.map_err(browser_error)?;
+                let content = format!("Loaded page: {}", title.unwrap_or_default());
+                (page, content, HashMap::new())
+            }
+            "extract_text" => {
+                let page = self.page(&req.session_id, url).await?;
+                let selector = arg("selector").unwrap_or("body");
+                let text = page
+                    .find_element(selector)
+                    .await
+                    .map_err(browser_error)?
+                    .inner_text()
+                    .await
+                    .map_err(browser_error)?
+                    .unwrap_or_default();
+                (page, truncate(text, self.max_text), HashMap::new())
+            }
+            "screenshot" => {
+                let page = self.page(&req.session_id, url).await?;
+                let full_page = req
+                    .arguments
+                    .get("full_page")
+                    .and_then(Value::as_bool)
+                    .unwrap_or(false);
+                let png = page
+                    .screenshot(ScreenshotParams::builder().full_page(full_page).build())
+                    .await
+                    .map_err(browser_error)?;
+                let hash = self.blobs.put(&png).await?;
+                let content = format!(
+                    "Screenshot saved as image/png blob \"{}\" ({} bytes)",
+                    hash,
+                    png.len()
+                );
+                let metadata = HashMap::from([
+                    (BLOB_REF_KEY.to_string(), hash),
+                    (BLOB_SIZE_KEY.to_string(), png.len().to_string()),
+                ]);
+                (page, content, metadata)
+            }
+            other => {
+                return Err(AgentError::ToolError(format!(
+                    "Unknown browser action: {}",
+                    other
+                )))
+            }
+        };
+        if let Some(url) = page.url().await.map_err(browser_error)? {
+            metadata.insert(PAGE_URL_KEY.to_string(), url);
+        }
+        Ok(ToolResponse {
+            content,
+            metadata: Some(metadata),
+        })
+    }
+}
+
+impl Drop for BrowserTool {
+    fn drop(&mut self) {
+        if let Some(running) = self.browser.get() {
+            running.handler.abort();
+        }
+    }
+}
+
+fn browser_error(e: impl std::fmt::Display) -> AgentError {
+    AgentError::ToolError(format!("Browser error: {}", e))
+}
+
+/// Cuts `text` to at most `max` bytes at a char boundary, noting the cut
+fn truncate(mut text: String, max: usize) -> String {
+    if text.len() <= max {
+        return text;
+    }
+    let mut end = max;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    let cut = text.len() - end;
+    text.truncate(end);
+    text.push_str(&format!("\n[truncated {} bytes]", cut));
+    text
+}
+
+#[async_trait]
+impl Tool for BrowserTool {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: BROWSER_TOOL.to_string(),
+            description: "Browses web pages in a headless browser, running their JavaScript"
+                .to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "action": {
+                        "type": "string",
+                        "enum": ["navigate", "extract_text", "screenshot"],
+                        "description": "navigate loads a URL; extract_text and screenshot act on the loaded page"
+                    },
+                    "url": {
+                        "type": "string",
+                        "description": "http(s) URL; loaded first if given"
+                    },
+                    "selector": {
+                        "type": "string",
+                        "description": "CSS selector of the element to extract text from; the whole page by default"
+                    },
+                    "full_page": {
+                        "type": "boolean",
+                        "description": "Capture the whole page rather than the viewport"
+                    }
+                },
+                "required": ["action"]
+            }),
+            examples: None,
+            output_schema: None,
+        }
+    }
+
+    async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse> {
+        tokio::time::timeout(self.timeout, self.run(&req))
+            .await
+            .map_err(|_| {
+                AgentError::ToolTimeout(format!(
+                    "{} exceeded {}ms",
+                    BROWSER_TOOL,
+                    self.timeout.as_millis()
+                ))
+            })?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blob::InMemoryBlobStore;
+
+    fn request(arguments: Value) -> ToolRequest {
+        ToolRequest {
+            session_id: "s".to_string(),
+            arguments: serde_json::from_value(arguments).unwrap(),
+            tenant_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_requests_are_checked_before_launching() {
+        let tool = BrowserTool::new(Arc::new(InMemoryBlobStore::new()));
+        for arguments in [
+            json!({"action": "navigate", "url": "file:///etc/passwd"}),
+            json!({"action": "navigate"}),
+            json!({"action": "extract_text"}),
+            json!({"action": "click"}),
+        ] {
+            assert!(matches!(
+                tool.invoke(request(arguments)).await,
+                Err(AgentError::ToolError(_))
+            ));
+        }
+        assert!(tool.browser.get().is_none());
+        assert_eq!(truncate("héllo".to_string(), 2), "h\n[truncated 5 bytes]");
+    }
+
+    #[tokio::test]
+    async fn test_host_policy_refuses_internal_and_unlisted_hosts() {
+        let policy = HostPolicy::new();
+        for url in [
+            "http://127.0.0.1/",
+            "http://169.254.169.254/latest/meta-data/",
+            "http://10.0.0.8:8080/admin",
+            "http://[::1]/",
+            "http://[::ffff:192.168.1.1]/",
+            "http://localhost/",
+            "file:///etc/passwd",
+        ] {
+            assert!(policy.check(url).await.is_err(), "{}", url);
+        }
+        assert!(policy.check("http://93.184.216.34/").await.is_ok());
+
+        let policy = HostPolicy::new()
+            .with_allowed("example.com")
+            .with_denied("admin.example.com")
+            .allow_private_networks();
+        assert!(policy.check("https://docs.example.com/").await.is_ok());
+        assert!(policy.check("https://admin.example.com/").await.is_err());
+        assert!(policy.check("https://badexample.com/").await.is_err());
+        assert!(policy.check("http://10.0.0.8/").await.is_err());
+    }
+}
@@ -0,0 +1,502 @@
+//! Built-in filesystem tools confined to a sandbox directory.
+//!
+//! [`FileReadTool`], [`FileWriteTool`] and [`ListDirTool`] give coding
+//! assistants a working directory without the rest of the disk. Paths are
+//! relative to the [`FsSandbox`] root. A path that climbs out with `..`, or
+//! reaches outside through a symlink, is rejected before anything is read
+//! or written, and reads, writes and listings are capped in size.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Component, Path, PathBuf};
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+
+use crate::error::{AgentError, Result};
+use crate::tools::Tool;
+use crate::types::{ToolRequest, ToolResponse, ToolSpec};
+
+/// Name under which [`FileReadTool`] is registered
+pub const FILE_READ_TOOL: &str = "fs.read";
+/// Name under which [`FileWriteTool`] is registered
+pub const FILE_WRITE_TOOL: &str = "fs.write";
+/// Name under which [`ListDirTool`] is registered
+pub const LIST_DIR_TOOL: &str = "fs.list";
+
+/// Metadata key holding the full size in bytes of a file read
+pub const FILE_SIZE_KEY: &str = "file_size";
+
+/// Bytes returned by one read, and accepted by one write, by default
+pub const DEFAULT_MAX_FILE_BYTES: usize = 1024 * 1024;
+/// Entries one listing returns by default
+pub const DEFAULT_MAX_DIR_ENTRIES: usize = 1_000;
+
+/// Symlinks one path may pass through, as in `SYMLOOP_MAX`
+const MAX_SYMLINKS: usize = 40;
+
+/// The directory filesystem tools work in, and their size limits
+#[derive(Debug, Clone)]
+pub struct FsSandbox {
+    root: PathBuf,
+    max_read_bytes: usize,
+    max_write_bytes: usize,
+    max_entries: usize,
+}
+
+impl FsSandbox {
+    /// Confines the tools to `root`, which must exist
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            max_read_bytes: DEFAULT_MAX_FILE_BYTES,
+            max_write_bytes: DEFAULT_MAX_FILE_BYTES,
+            max_entries: DEFAULT_MAX_DIR_ENTRIES,
+        }
+    }
+
+    /// Caps the bytes one read returns; larger files are read in pieces
+    /// with `offset` and `length`
+    pub fn with_max_read_bytes(mut self, bytes: usize) -> Self {
+        self.max_read_bytes = bytes;
+        self
+    }
+
+    /// Rejects writes of more than `bytes`
+    pub fn with_max_write_bytes(mut self, bytes: usize) -> Self {
+        self.max_write_bytes = bytes;
+        self
+    }
+
+    /// Caps the entries one listing returns
+    pub fn with_max_entries(mut self, entries: usize) -> Self {
+        self.max_entries = entries;
+        self
+    }
+
+    /// Returns the sandbox root
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Resolves `path` inside the sandbox, failing with
+    /// [`AgentError::ToolError`] if it leads outside.
+    ///
+    /// The path is walked one component at a time the way the OS would,
+    /// following every symlink, dangling ones included, so a link pointing
+    /// out of the sandbox is caught; the path may not exist yet, for
+    /// writes. The result has no symlinks left in it.
+    pub async fn resolve(&self, path: &str) -> Result<PathBuf> {
+        let outside = || AgentError::ToolError(format!("{} is outside the sandbox", path));
+        if Path::new(path).is_absolute() {
+            return Err(outside());
+        }
+        let root = tokio::fs::canonicalize(&self.root).await?;
+
+        let mut resolved = root.clone();
+        // Steps left to walk, the next one last
+        let mut pending = steps(Path::new(path));
+        let mut links = 0;
+        while let Some(step) = pending.pop() {
+            match step {
+                Step::Root(start) => resolved = start,
+                Step::Parent => {
+                    resolved.pop();
+                }
+                Step::Name(name) => {
+                    let next = resolved.join(name);
+                    match tokio::fs::symlink_metadata(&next).await {
+                        Ok(meta) if meta.file_type().is_symlink() => {
+                            links += 1;
+                            if links > MAX_SYMLINKS {
+                                return Err(AgentError::ToolError(format!(
+                                    "{} passes through too many symlinks",
+                                    path
+                                )));
+                            }
+                            // Walk the target from the link's directory
+                            let target = tokio::fs::read_link(&next).await?;
+                            pending.extend(steps(&target));
+                        }
+                        Ok(_) => resolved = next,
+                        Err(e) if e.kind() == std::io::ErrorKind::NotFound => resolved = next,
+                        Err(e) => return Err(e.into()),
+                    }
+                }
+            }
+        }
+        if !resolved.starts_with(&root) {
+            return Err(outside());
+        }
+        Ok(resolved)
+    }
+
+    /// Fails if `path`, just opened, turned out to be outside the sandbox
+    /// because a directory on the way was swapped for a symlink
+    async fn check_opened(&self, path: &Path) -> Result<()> {
+        let root = tokio::fs::canonicalize(&self.root).await?;
+        if !tokio::fs::canonicalize(path).await?.starts_with(&root) {
+            return Err(AgentError::ToolError(format!(
+                "{} is outside the sandbox",
+                path.display()
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// One step of walking a path in [`FsSandbox::resolve`]
+enum Step {
+    /// Start over from an absolute path
+    Root(PathBuf),
+    Parent,
+    Name(OsString),
+}
+
+/// The steps of walking `path`, in reverse so they pop in order
+fn steps(path: &Path) -> Vec<Step> {
+    let mut steps = Vec::new();
+    let mut start = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => start.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => steps.push(Step::Parent),
+            Component::Normal(name) => steps.push(Step::Name(name.to_os_string())),
+        }
+    }
+    if !start.as_os_str().is_empty() {
+        steps.insert(0, Step::Root(start));
+    }
+    steps.reverse();
+    steps
+}
+
+fn string_arg<'a>(req: &'a ToolRequest, key: &str) -> Result<&'a str> {
+    req.arguments
+        .get(key)
+        .and_then(Value::as_str)
+        .ok_or_else(|| AgentError::ToolError(format!("missing '{}'", key)))
+}
+
+fn usize_arg(req: &ToolRequest, key: &str) -> Option<usize> {
+    req.arguments
+        .get(key)
+        .and_then(Value::as_u64)
+        .map(|v| v as usize)
+}
+
+/// Built-in tool that reads a file in the sandbox
+pub struct FileReadTool {
+    sandbox: FsSandbox,
+}
+
+impl FileReadTool {
+    pub fn new(sandbox: FsSandbox) -> Self {
+        Self { sandbox }
+    }
+}
+
+#[async_trait]
+impl Tool for FileReadTool {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: FILE_READ_TOOL.to_string(),
+            description: format!(
+                "Reads a text file, at most {} bytes at a time",
+                self.sandbox.max_read_bytes
+            ),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Path relative to the working directory"
+                    },
+                    "offset": {
+                        "type": "integer",
+                        "description": "Byte offset to start reading from"
+                    },
+                    "length": {
+                        "type": "integer",
+                        "description": "Maximum number of bytes to return"
+                    }
+                },
+                "required": ["path"]
+            }),
+            examples: None,
+            output_schema: None,
+        }
+    }
+
+    async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse> {
+        let path = self.sandbox.resolve(string_arg(&req, "path")?).await?;
+        let mut options = tokio::fs::OpenOptions::new();
+        options.read(true);
+        #[cfg(unix)]
+        options.custom_flags(libc::O_NOFOLLOW);
+        let mut file = options.open(&path).await?;
+        self.sandbox.check_opened(&path).await?;
+        let size = file.metadata().await?.len();
+
+        let offset = usize_arg(&req, "offset").unwrap_or(0) as u64;
+        let length = usize_arg(&req, "length")
+            .unwrap_or(self.sandbox.max_read_bytes)
+            .min(self.sandbox.max_read_bytes);
+        file.seek(std::io::SeekFrom::Start(offset)).await?;
+        let mut data = Vec::new();
+        file.take(length as u64).read_to_end(&mut data).await?;
+
+        let end = offset + data.len() as u64;
+        let mut content = String::from_utf8_lossy(&data).into_owned();
+        if end < size {
+            content.push_str(&format!(
+                "\n[read bytes {}-{} of {}; pass offset {} for more]",
+                offset, end, size, end
+            ));
+        }
+        Ok(ToolResponse {
+            content,
+            metadata: Some(HashMap::from([(
+                FILE_SIZE_KEY.to_string(),
+                size.to_string(),
+            )])),
+        })
+    }
+}
+
+/// Built-in tool that writes or appends to a file in the sandbox, creating
+/// missing directories
+pub struct FileWriteTool {
+    sandbox: FsSandbox,
+}
+
+impl FileWriteTool {
+    pub fn new(sandbox: FsSandbox) -> Self {
+        Self { sandbox }
+    }
+}
+
+#[async_trait]
+impl Tool for FileWriteTool {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: FILE_WRITE_TOOL.to_string(),
+            description: format!(
+                "Writes a text file of at most {} bytes, replacing it unless appending",
+                self.sandbox.max_write_bytes
+            ),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Path relative to the working directory"
+                    },
+                    "content": {
+                        "type": "string",
+                        "description": "Text to write"
+                    },
+                    "append": {
+                        "type": "boolean",
+                        "description": "Appends to the file instead of replacing it"
+                    }
+                },
+                "required": ["path", "content"]
+            }),
+            examples: None,
+            output_schema: None,
+        }
+    }
+
+    async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse> {
+        let content = string_arg(&req, "content")?;
+        if content.len() > self.sandbox.max_write_bytes {
+            return Err(AgentError::ToolError(format!(
+                "content of {} bytes exceeds the {} byte limit",
+                content.len(),
+                self.sandbox.max_write_bytes
+            )));
+        }
+        let path = self.sandbox.resolve(string_arg(&req, "path")?).await?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let append = req
+            .arguments
+            .get("append")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        let mut options = tokio::fs::OpenOptions::new();
+        options
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append);
+        // A symlink swapped in since resolving isn't followed
+        #[cfg(unix)]
+        options.custom_flags(libc::O_NOFOLLOW);
+        let mut file = options.open(&path).await?;
+        self.sandbox.check_opened(&path).await?;
+        file.write_all(content.as_bytes()).await?;
+        file.flush().await?;
+
+        Ok(ToolResponse {
+            content: format!("Wrote {} bytes", content.len()),
+            metadata: None,
+        })
+    }
+}
+
+/// Built-in tool that lists a directory in the sandbox, directories marked
+/// with a trailing `/`
+pub struct ListDirTool {
+    sandbox: FsSandbox,
+}
+
+impl ListDirTool {
+    pub fn new(sandbox: FsSandbox) -> Self {
+        Self { sandbox }
+    }
+}
+
+#[async_trait]
+impl Tool for ListDirTool {
+    fn spec(&self) -> ToolSpec {
+        ToolSpec {
+            name: LIST_DIR_TOOL.to_string(),
+            description: "Lists the files and directories in a directory".to_string(),
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "path": {
+                        "type": "string",
+                        "description": "Directory relative to the working directory; defaults to the working directory itself"
+                    }
+                }
+            }),
+            examples: None,
+            output_schema: None,
+        }
+    }
+
+    async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse> {
+        let path = req
+            .arguments
+            .get("path")
+            .and_then(Value::as_str)
+            .unwrap_or(".");
+        let dir = self.sandbox.resolve(path).await?;
+
+        let mut names = Vec::new();
+        let mut entries = tokio::fs::read_dir(&dir).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let mut name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type().await?.is_dir() {
+                name.push('/');
+            }
+            names.push(name);
+        }
+        names.sort();
+
+        let total = names.len();
+        names.truncate(self.sandbox.max_entries);
+        let mut content = names.join("\n");
+        if total > names.len() {
+            content.push_str(&format!(
+                "\n[{} more entries not shown]",
+                total - names.len()
+            ));
+        }
+        Ok(ToolResponse {
+            content,
+            metadata: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(arguments: Value) -> ToolRequest {
+        ToolRequest {
+            session_id: "test".to_string(),
+            arguments: serde_json::from_value(arguments).unwrap(),
+            tenant_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tools_stay_inside_the_sandbox() {
+        let root = std::env::temp_dir().join(format!("fs-sandbox-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        let sandbox = FsSandbox::new(&root)
+            .with_max_read_bytes(8)
+            .with_max_write_bytes(32);
+        let read = FileReadTool::new(sandbox.clone());
+        let write = FileWriteTool::new(sandbox.clone());
+        let list = ListDirTool::new(sandbox);
+
+        write
+            .invoke(request(
+                json!({"path": "src/main.rs", "content": "fn main() {}"}),
+            ))
+            .await
+            .unwrap();
+        let listed = list.invoke(request(json!({}))).await.unwrap();
+        assert_eq!(listed.content, "src/");
+
+        let first = read
+            .invoke(request(json!({"path": "src/./main.rs"})))
+            .await
+            .unwrap();
+        assert!(first.content.starts_with("fn main(\n[read bytes 0-8 of 12"));
+        let rest = read
+            .invoke(request(json!({"path": "src/main.rs", "offset": 8})))
+            .await
+            .unwrap();
+        assert_eq!(rest.content, ") {}");
+
+        for path in ["../outside.txt", "src/../../outside.txt", "/etc/passwd"] {
+            assert!(write
+                .invoke(request(json!({"path": path, "content": "x"})))
+                .await
+                .is_err());
+        }
+        assert!(write
+            .invoke(request(
+                json!({"path": "big.txt", "content": "x".repeat(33)})
+            ))
+            .await
+            .is_err());
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(std::env::temp_dir(), root.join("escape")).unwrap();
+            assert!(read
+                .invoke(request(json!({"path": "escape/anything"})))
+                .await
+                .is_err());
+
+            // A dangling link can't be used to create a file outside
+            let target = std::env::temp_dir().join(format!("fs-outside-{}", uuid::Uuid::new_v4()));
+            std::os::unix::fs::symlink(&target, root.join("dangling")).unwrap();
+            assert!(write
+                .invoke(request(json!({"path": "dangling", "content": "x"})))
+                .await
+                .is_err());
+            assert!(!target.exists());
+
+            // Links that stay inside are followed
+            std::os::unix::fs::symlink("src", root.join("code")).unwrap();
+            assert!(read
+                .invoke(request(json!({"path": "code/main.rs"})))
+                .await
+                .is_ok());
+        }
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+}
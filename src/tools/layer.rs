@@ -0,0 +1,545 @@
+//! Middleware around tools.
+//!
+//! A [`ToolLayer`] wraps a tool in another that adds behaviour around its
+//! calls, in the style of tower's layers: logging, injecting credentials,
+//! redacting arguments or bounding concurrency then live in one place instead
+//! of in every tool. Wrap a single tool with [`ToolLayer::layer`], or every
+//! tool of a catalog with `ToolCatalog::with_layer`. The built-in layers
+//! apply to streaming calls too, which they see finish when the stream ends.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde_json::Value;
+
+use crate::error::{AgentError, Result};
+use crate::secrets::SecretsProvider;
+use crate::tools::{Tool, ToolStream};
+use crate::types::{ToolRequest, ToolResponse, ToolSpec};
+
+/// Placeholder logged instead of a redacted argument
+pub const REDACTED: &str = "[redacted]";
+
+/// Wraps tools in middleware
+pub trait ToolLayer: Send + Sync {
+    /// Returns `tool` wrapped in this layer
+    fn layer(&self, tool: Arc<dyn Tool>) -> Arc<dyn Tool>;
+}
+
+/// Wraps `tool` in `layers`, the last one outermost
+pub fn apply_layers(tool: Arc<dyn Tool>, layers: &[Arc<dyn ToolLayer>]) -> Arc<dyn Tool> {
+    layers.iter().fold(tool, |tool, layer| layer.layer(tool))
+}
+
+/// Calls `done` once `chunks` end, with whether every chunk succeeded; a
+/// stream dropped early counts as failed
+fn on_finish(chunks: ToolStream, done: impl FnOnce(bool) + Send + 'static) -> ToolStream {
+    let finish = Finish {
+        done: Some(Box::new(done)),
+        failed: false,
+    };
+    futures::stream::unfold((chunks, finish), |(mut chunks, mut finish)| async move {
+        match chunks.next().await {
+            Some(item) => {
+                finish.failed |= item.is_err();
+                Some((item, (chunks, finish)))
+            }
+            None => {
+                finish.end();
+                None
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Reports the outcome of a stream once it ends, or a failure if it is
+/// dropped before
+struct Finish {
+    done: Option<Box<dyn FnOnce(bool) + Send>>,
+    failed: bool,
+}
+
+impl Finish {
+    /// Reports the ended stream, successful unless a chunk failed
+    fn end(mut self) {
+        if let Some(done) = self.done.take() {
+            done(!self.failed);
+        }
+    }
+}
+
+impl Drop for Finish {
+    fn drop(&mut self) {
+        if let Some(done) = self.done.take() {
+            done(false);
+        }
+    }
+}
+
+/// Logs every call with its arguments, outcome and duration
+#[derive(Debug, Clone, Default)]
+pub struct LoggingLayer {
+    redacted: HashSet<String>,
+}
+
+impl LoggingLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Logs [`REDACTED`] instead of the value of argument `key`
+    pub fn with_redacted(mut self, key: impl Into<String>) -> Self {
+        self.redacted.insert(key.into());
+        self
+    }
+}
+
+impl ToolLayer for LoggingLayer {
+    fn layer(&self, tool: Arc<dyn Tool>) -> Arc<dyn Tool> {
+        Arc::new(Logged {
+            inner: tool,
+            redacted: self.redacted.clone(),
+        })
+    }
+}
+
+struct Logged {
+    inner: Arc<dyn Tool>,
+    redacted: HashSet<String>,
+}
+
+#[async_trait]
+impl Tool for Logged {
+    fn spec(&self) -> ToolSpec {
+        self.inner.spec()
+    }
+
+    async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse> {
+        let name = self.log_call(&req);
+        let started = Instant::now();
+        let response = self.inner.invoke(req).await;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        match &response {
+            Ok(_) => tracing::info!(tool = %name, elapsed_ms, "Tool call succeeded"),
+            Err(e) => tracing::warn!(tool = %name, elapsed_ms, "Tool call failed: {}", e),
+        }
+        response
+    }
+
+    async fn invoke_stream(&self, req: ToolRequest) -> Result<ToolStream> {
+        let name = self.log_call(&req);
+        let started = Instant::now();
+        let chunks = match self.inner.invoke_stream(req).await {
+            Ok(chunks) => chunks,
+            Err(e) => {
+                let elapsed_ms = started.elapsed().as_millis() as u64;
+                tracing::warn!(tool = %name, elapsed_ms, "Tool call failed: {}", e);
+                return Err(e);
+            }
+        };
+        Ok(on_finish(chunks, move |success| {
+            let elapsed_ms = started.elapsed().as_millis() as u64;
+            if success {
+                tracing::info!(tool = %name, elapsed_ms, "Tool call succeeded");
+            } else {
+                tracing::warn!(tool = %name, elapsed_ms, "Tool call failed or was dropped");
+            }
+        }))
+    }
+}
+
+impl Logged {
+    /// Logs the call with its arguments, returning the tool's name
+    fn log_call(&self, req: &ToolRequest) -> String {
+        let name = self.inner.spec().name;
+        let arguments = Value::Object(
+            req.arguments
+                .iter()
+                .map(|(key, value)| {
+                    let value = if self.redacted.contains(key) {
+                        Value::from(REDACTED)
+                    } else {
+                        value.clone()
+                    };
+                    (key.clone(), value)
+                })
+                .collect(),
+        );
+        tracing::info!(
+            tool = %name,
+            session_id = %req.session_id,
+            arguments = %arguments,
+            "Tool call"
+        );
+        name
+    }
+}
+
+/// Adds fixed arguments, such as credentials, to every call.
+///
+/// Injected arguments replace any the caller passed under the same key,
+/// and are removed from the input schema the model sees, so it neither
+/// learns about them nor has to supply them.
+#[derive(Debug, Clone, Default)]
+pub struct InjectArgumentsLayer {
+    arguments: HashMap<String, Value>,
+}
+
+impl InjectArgumentsLayer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Injects `value` as argument `key`
+    pub fn with_argument(mut self, key: impl Into<String>, value: impl Into<Value>) -> Self {
+        self.arguments.insert(key.into(), value.into());
+        self
+    }
+}
+
+impl ToolLayer for InjectArgumentsLayer {
+    fn layer(&self, tool: Arc<dyn Tool>) -> Arc<dyn Tool> {
+        Arc::new(Injected {
+            inner: tool,
+            arguments: self.arguments.clone(),
+        })
+    }
+}
+
+struct Injected {
+    inner: Arc<dyn Tool>,
+    arguments: HashMap<String, Value>,
+}
+
+#[async_trait]
+impl Tool for Injected {
+    fn spec(&self) -> ToolSpec {
+        hide_arguments(self.inner.spec(), |key| self.arguments.contains_key(key))
+    }
+
+    async fn invoke(&self, mut req: ToolRequest) -> Result<ToolResponse> {
+        req.arguments.extend(self.arguments.clone());
+        self.inner.invoke(req).await
+    }
+
+    async fn invoke_stream(&self, mut req: ToolRequest) -> Result<ToolStream> {
+        req.arguments.extend(self.arguments.clone());
+        self.inner.invoke_stream(req).await
+    }
+}
+
+/// Removes the arguments `hidden` matches from the spec's input schema
+fn hide_arguments(mut spec: ToolSpec, hidden: impl Fn(&str) -> bool) -> ToolSpec {
+    if let Some(properties) = spec
+        .input_schema
+        .get_mut("properties")
+        .and_then(Value::as_object_mut)
+    {
+        properties.retain(|key, _| !hidden(key));
+    }
+    if let Some(required) = spec
+        .input_schema
+        .get_mut("required")
+        .and_then(Value::as_array_mut)
+    {
+        required.retain(|key| key.as_str().is_none_or(|key| !hidden(key)));
+    }
+    spec
+}
+
+/// Adds arguments looked up in a [`SecretsProvider`], such as the API key
+/// of an HTTP tool or the password of a database tool, to every call.
+///
+/// Secrets are looked up on each call, so rotated credentials apply
+/// without registering the tool again, and are hidden from the model like
+/// the arguments of [`InjectArgumentsLayer`]. A missing secret fails the
+/// call with [`AgentError::ConfigError`].
+#[derive(Clone)]
+pub struct SecretArgumentsLayer {
+    secrets: Arc<dyn SecretsProvider>,
+    // Argument -> secret name
+    arguments: HashMap<String, String>,
+}
+
+impl SecretArgumentsLayer {
+    pub fn new(secrets: Arc<dyn SecretsProvider>) -> Self {
+        Self {
+            secrets,
+            arguments: HashMap::new(),
+        }
+    }
+
+    /// Passes secret `secret` as argument `key`
+    pub fn with_argument(mut self, key: impl Into<String>, secret: impl Into<String>) -> Self {
+        self.arguments.insert(key.into(), secret.into());
+        self
+    }
+}
+
+impl ToolLayer for SecretArgumentsLayer {
+    fn layer(&self, tool: Arc<dyn Tool>) -> Arc<dyn Tool> {
+        Arc::new(WithSecrets {
+            inner: tool,
+            layer: self.clone(),
+        })
+    }
+}
+
+struct WithSecrets {
+    inner: Arc<dyn Tool>,
+    layer: SecretArgumentsLayer,
+}
+
+#[async_trait]
+impl Tool for WithSecrets {
+    fn spec(&self) -> ToolSpec {
+        hide_arguments(self.inner.spec(), |key| {
+            self.layer.arguments.contains_key(key)
+        })
+    }
+
+    async fn invoke(&self, mut req: ToolRequest) -> Result<ToolResponse> {
+        self.insert_secrets(&mut req).await?;
+        self.inner.invoke(req).await
+    }
+
+    async fn invoke_stream(&self, mut req: ToolRequest) -> Result<ToolStream> {
+        self.insert_secrets(&mut req).await?;
+        self.inner.invoke_stream(req).await
+    }
+}
+
+impl WithSecrets {
+    async fn insert_secrets(&self, req: &mut ToolRequest) -> Result<()> {
+        for (key, name) in &self.layer.arguments {
+            let secret = self.layer.secrets.require(name).await?;
+            req.arguments.insert(key.clone(), Value::String(secret));
+        }
+        Ok(())
+    }
+}
+
+/// Fails calls that run longer than a timeout with
+/// [`AgentError::ToolTimeout`]
+#[derive(Debug, Clone, Copy)]
+pub struct TimeoutLayer {
+    timeout: Duration,
+}
+
+impl TimeoutLayer {
+    pub fn new(timeout: Duration) -> Self {
+        Self { timeout }
+    }
+}
+
+impl ToolLayer for TimeoutLayer {
+    fn layer(&self, tool: Arc<dyn Tool>) -> Arc<dyn Tool> {
+        Arc::new(TimedOut {
+            inner: tool,
+            timeout: self.timeout,
+        })
+    }
+}
+
+struct TimedOut {
+    inner: Arc<dyn Tool>,
+    timeout: Duration,
+}
+
+#[async_trait]
+impl Tool for TimedOut {
+    fn spec(&self) -> ToolSpec {
+        self.inner.spec()
+    }
+
+    async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse> {
+        match tokio::time::timeout(self.timeout, self.inner.invoke(req)).await {
+            Ok(response) => response,
+            Err(_) => Err(AgentError::ToolTimeout(self.expired())),
+        }
+    }
+
+    /// Applies the timeout to the whole stream, up to its last chunk
+    async fn invoke_stream(&self, req: ToolRequest) -> Result<ToolStream> {
+        let deadline = tokio::time::Instant::now() + self.timeout;
+        let chunks = match tokio::time::timeout_at(deadline, self.inner.invoke_stream(req)).await {
+            Ok(chunks) => chunks?,
+            Err(_) => return Err(AgentError::ToolTimeout(self.expired())),
+        };
+        let expired = self.expired();
+        let state = Some((chunks, expired));
+        Ok(futures::stream::unfold(state, move |state| async move {
+            let (mut chunks, expired) = state?;
+            match tokio::time::timeout_at(deadline, chunks.next()).await {
+                Ok(Some(item)) => Some((item, Some((chunks, expired)))),
+                Ok(None) => None,
+                Err(_) => Some((Err(AgentError::ToolTimeout(expired)), None)),
+            }
+        })
+        .boxed())
+    }
+}
+
+impl TimedOut {
+    fn expired(&self) -> String {
+        format!(
+            "{} did not finish within {:?}",
+            self.inner.spec().name,
+            self.timeout
+        )
+    }
+}
+
+/// Lets at most `max` calls of each wrapped tool run at once; further calls
+/// wait for a free slot
+#[derive(Debug, Clone, Copy)]
+pub struct ConcurrencyLimitLayer {
+    max: usize,
+}
+
+impl ConcurrencyLimitLayer {
+    pub fn new(max: usize) -> Self {
+        Self { max: max.max(1) }
+    }
+}
+
+impl ToolLayer for ConcurrencyLimitLayer {
+    fn layer(&self, tool: Arc<dyn Tool>) -> Arc<dyn Tool> {
+        Arc::new(Limited {
+            inner: tool,
+            permits: Arc::new(tokio::sync::Semaphore::new(self.max)),
+        })
+    }
+}
+
+struct Limited {
+    inner: Arc<dyn Tool>,
+    permits: Arc<tokio::sync::Semaphore>,
+}
+
+#[async_trait]
+impl Tool for Limited {
+    fn spec(&self) -> ToolSpec {
+        self.inner.spec()
+    }
+
+    async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse> {
+        let _permit = self.acquire().await?;
+        self.inner.invoke(req).await
+    }
+
+    /// Holds the slot until the stream ends or is dropped
+    async fn invoke_stream(&self, req: ToolRequest) -> Result<ToolStream> {
+        let permit = self.acquire().await?;
+        let chunks = self.inner.invoke_stream(req).await?;
+        Ok(on_finish(chunks, move |_| drop(permit)))
+    }
+}
+
+impl Limited {
+    async fn acquire(&self) -> Result<tokio::sync::OwnedSemaphorePermit> {
+        Arc::clone(&self.permits)
+            .acquire_owned()
+            .await
+            .map_err(|e| AgentError::ToolError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tools::ToolCatalog;
+
+    /// Replies with the API key it was given
+    struct KeyedTool;
+
+    #[async_trait]
+    impl Tool for KeyedTool {
+        fn spec(&self) -> ToolSpec {
+            ToolSpec {
+                name: "keyed".to_string(),
+                description: "Calls an API".to_string(),
+                input_schema: serde_json::json!({
+                    "type": "object",
+                    "properties": {
+                        "query": {"type": "string"},
+                        "api_key": {"type": "string"}
+                    },
+                    "required": ["query", "api_key"]
+                }),
+                examples: None,
+                output_schema: None,
+            }
+        }
+
+        async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse> {
+            let key = req.arguments.get("api_key").and_then(Value::as_str);
+            Ok(ToolResponse {
+                content: key.unwrap_or_default().to_string(),
+                metadata: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_catalog_layers_wrap_every_tool() {
+        let catalog = ToolCatalog::new()
+            .with_layer(Arc::new(
+                InjectArgumentsLayer::new().with_argument("api_key", "secret"),
+            ))
+            .with_layer(Arc::new(LoggingLayer::new().with_redacted("api_key")));
+        catalog.register(Box::new(KeyedTool)).unwrap();
+
+        // The model never sees the injected key
+        let spec = catalog.lookup("keyed").unwrap();
+        assert!(spec.input_schema["properties"].get("api_key").is_none());
+        assert_eq!(spec.input_schema["required"], serde_json::json!(["query"]));
+
+        let request = ToolRequest {
+            session_id: "s".to_string(),
+            arguments: HashMap::from([
+                ("query".to_string(), Value::from("rust")),
+                ("api_key".to_string(), Value::from("spoofed")),
+            ]),
+            tenant_id: None,
+        };
+        let response = catalog.invoke("keyed", request).await.unwrap();
+        assert_eq!(response.content, "secret");
+
+        let stats = catalog.tool_stats("keyed").unwrap();
+        assert_eq!((stats.calls, stats.failures), (1, 0));
+    }
+
+    #[tokio::test]
+    async fn test_secret_arguments_are_resolved_per_call() {
+        let secrets = Arc::new(crate::secrets::StaticSecrets::new().with_secret("KEY", "rotated"));
+        let tool = SecretArgumentsLayer::new(secrets)
+            .with_argument("api_key", "KEY")
+            .layer(Arc::new(KeyedTool));
+        assert!(tool.spec().input_schema["properties"]
+            .get("api_key")
+            .is_none());
+
+        let request = ToolRequest {
+            session_id: "s".to_string(),
+            arguments: HashMap::from([("query".to_string(), Value::from("rust"))]),
+            tenant_id: None,
+        };
+        assert_eq!(
+            tool.invoke(request.clone()).await.unwrap().content,
+            "rotated"
+        );
+
+        let missing = SecretArgumentsLayer::new(Arc::new(crate::secrets::StaticSecrets::new()))
+            .with_argument("api_key", "KEY")
+            .layer(Arc::new(KeyedTool));
+        assert!(matches!(
+            missing.invoke(request).await,
+            Err(AgentError::ConfigError(_))
+        ));
+    }
+}
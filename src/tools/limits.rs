@@ -0,0 +1,194 @@
+//! Limits on tool calls.
+//!
+//! A model that keeps asking for tools, or for the same tool with the same
+//! arguments, burns tokens without making progress. [`ToolLoopLimits`] caps
+//! the calls `Agent::invoke_tool` accepts for a session between two user
+//! inputs and how often one identical call may repeat; past either limit
+//! the call fails with [`AgentError::ToolLoopDetected`] without running.
+//!
+//! A slow or flooded tool holds up the turn waiting on it. [`ToolLimits`]
+//! bound how long each call of one tool may take and how many may run at
+//! once; see `ToolCatalog::register_with_limits`.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::error::{AgentError, Result};
+use crate::tools::layer::{ConcurrencyLimitLayer, TimeoutLayer, ToolLayer};
+
+/// Timeout and concurrency cap of one tool
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ToolLimits {
+    timeout: Option<Duration>,
+    max_concurrency: Option<usize>,
+}
+
+impl ToolLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Fails calls with `AgentError::ToolTimeout` once they take longer
+    /// than `timeout`, including time spent waiting for a free slot
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Runs at most `max` calls at once; further calls wait
+    pub fn with_max_concurrency(mut self, max: usize) -> Self {
+        self.max_concurrency = Some(max.max(1));
+        self
+    }
+
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    pub fn max_concurrency(&self) -> Option<usize> {
+        self.max_concurrency
+    }
+
+    /// Returns the layers enforcing these limits, innermost first
+    pub(crate) fn layers(&self) -> Vec<Arc<dyn ToolLayer>> {
+        let mut layers: Vec<Arc<dyn ToolLayer>> = Vec::new();
+        if let Some(max) = self.max_concurrency {
+            layers.push(Arc::new(ConcurrencyLimitLayer::new(max)));
+        }
+        if let Some(timeout) = self.timeout {
+            layers.push(Arc::new(TimeoutLayer::new(timeout)));
+        }
+        layers
+    }
+}
+
+/// Per-turn caps on tool calls
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ToolLoopLimits {
+    max_tool_iterations: usize,
+    max_identical_calls: usize,
+}
+
+impl Default for ToolLoopLimits {
+    fn default() -> Self {
+        Self {
+            max_tool_iterations: 25,
+            max_identical_calls: 3,
+        }
+    }
+}
+
+impl ToolLoopLimits {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows at most `max` tool calls per turn
+    pub fn with_max_tool_iterations(mut self, max: usize) -> Self {
+        self.max_tool_iterations = max;
+        self
+    }
+
+    /// Allows the same tool with the same arguments at most `max` times per
+    /// turn
+    pub fn with_max_identical_calls(mut self, max: usize) -> Self {
+        self.max_identical_calls = max.max(1);
+        self
+    }
+
+    /// Returns the maximum tool calls per turn
+    pub fn max_tool_iterations(&self) -> usize {
+        self.max_tool_iterations
+    }
+
+    /// Returns the maximum repeats of one identical call per turn
+    pub fn max_identical_calls(&self) -> usize {
+        self.max_identical_calls
+    }
+}
+
+#[derive(Default)]
+struct TurnCalls {
+    total: usize,
+    // Keyed by tool name and `arguments_hash`
+    identical: HashMap<(String, String), usize>,
+}
+
+/// Counts each session's tool calls in its current turn
+pub(crate) struct ToolLoopTracker {
+    limits: ToolLoopLimits,
+    sessions: parking_lot::Mutex<HashMap<String, TurnCalls>>,
+}
+
+impl ToolLoopTracker {
+    pub(crate) fn new(limits: ToolLoopLimits) -> Self {
+        Self {
+            limits,
+            sessions: parking_lot::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a call of `tool_name`, failing if it exceeds a limit
+    pub(crate) fn check(&self, session_id: &str, tool_name: &str, args_hash: &str) -> Result<()> {
+        let mut sessions = self.sessions.lock();
+        let turn = sessions.entry(session_id.to_string()).or_default();
+        if turn.total >= self.limits.max_tool_iterations {
+            return Err(AgentError::ToolLoopDetected(format!(
+                "session {} reached the limit of {} tool calls in one turn",
+                session_id, self.limits.max_tool_iterations
+            )));
+        }
+
+        let key = (tool_name.to_string(), args_hash.to_string());
+        let repeats = turn.identical.get(&key).copied().unwrap_or(0);
+        if repeats >= self.limits.max_identical_calls {
+            return Err(AgentError::ToolLoopDetected(format!(
+                "session {} called {} {} times with identical arguments",
+                session_id,
+                tool_name,
+                repeats + 1
+            )));
+        }
+
+        turn.total += 1;
+        turn.identical.insert(key, repeats + 1);
+        Ok(())
+    }
+
+    /// Starts a new turn for `session_id`
+    pub(crate) fn reset(&self, session_id: &str) {
+        self.sessions.lock().remove(session_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tracker_stops_repeats_and_long_loops() {
+        let tracker = ToolLoopTracker::new(
+            ToolLoopLimits::new()
+                .with_max_tool_iterations(4)
+                .with_max_identical_calls(2),
+        );
+        tracker.check("s", "search", "a").unwrap();
+        tracker.check("s", "search", "a").unwrap();
+        assert!(matches!(
+            tracker.check("s", "search", "a"),
+            Err(AgentError::ToolLoopDetected(_))
+        ));
+
+        tracker.check("s", "search", "b").unwrap();
+        tracker.check("s", "fetch", "a").unwrap();
+        assert!(matches!(
+            tracker.check("s", "fetch", "c"),
+            Err(AgentError::ToolLoopDetected(_))
+        ));
+        tracker.check("other", "search", "a").unwrap();
+
+        tracker.reset("s");
+        tracker.check("s", "search", "a").unwrap();
+    }
+}
@@ -1,9 +1,121 @@
 use async_trait::async_trait;
-use std::collections::HashMap;
+use futures::stream::BoxStream;
+use futures::StreamExt;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
 
-use crate::error::Result;
+use crate::blob::content_hash;
+use crate::error::{AgentError, Result};
+use crate::schema::{schema_errors, SchemaError};
+use crate::tenant::TenantGuard;
 use crate::types::{ToolRequest, ToolResponse, ToolSpec};
 
+pub mod batch;
+#[cfg(feature = "browser")]
+pub mod browser;
+pub mod fs;
+pub mod guard;
+pub mod layer;
+pub mod limits;
+pub mod namespace;
+pub mod openapi;
+pub mod policy;
+pub mod postprocess;
+pub mod schemas;
+pub mod select;
+pub mod shell;
+pub mod stats;
+pub mod typed;
+
+pub use batch::{ToolBatch, ToolCall, ToolCallStatus, TOOL_CALLS_KEY, TOOL_STATUS_KEY};
+#[cfg(feature = "browser")]
+pub use browser::BrowserTool;
+pub use fs::{FileReadTool, FileWriteTool, FsSandbox, ListDirTool};
+pub use guard::{ToolOutputGuard, TrustLevel};
+pub use layer::{
+    apply_layers, ConcurrencyLimitLayer, InjectArgumentsLayer, LoggingLayer, SecretArgumentsLayer,
+    TimeoutLayer, ToolLayer,
+};
+pub use limits::{ToolLimits, ToolLoopLimits};
+pub use namespace::{in_namespace, ToolNamespaces};
+pub use openapi::{OpenApiLoader, OpenApiTool};
+pub use policy::{ArgumentConstraint, ToolPolicy, ToolRule, ToolTarget};
+pub use postprocess::{ToolOutputProcessor, TruncationStrategy};
+#[cfg(feature = "macros")]
+pub use rs_agent_macros::tool;
+pub use schemas::{catalog_hash, ToolSchemaFormat, ToolSchemaSnapshots};
+pub use select::{ToolSelection, ToolSelector, SELECTED_TOOLS_KEY};
+pub use shell::ShellTool;
+pub use stats::{ToolCallEvent, ToolUsage, DEFAULT_LATENCY_SAMPLES};
+pub use typed::{input_schema, parse_arguments, IntoToolResponse};
+
+/// Metadata key naming the tool behind a stored tool result
+pub const TOOL_NAME_KEY: &str = "tool_name";
+/// Metadata key holding [`arguments_hash`] of the invocation's arguments
+pub const TOOL_ARGS_HASH_KEY: &str = "tool_args_hash";
+/// Metadata key holding how long the invocation took, in milliseconds
+pub const TOOL_LATENCY_MS_KEY: &str = "tool_latency_ms";
+/// Metadata key naming where the tool runs, e.g. `utcp` or [`LOCAL_PROVIDER`]
+pub const TOOL_PROVIDER_KEY: &str = "tool_provider";
+/// Provider recorded for tools that don't report one
+pub const LOCAL_PROVIDER: &str = "local";
+
+/// Returns a hash of tool arguments that doesn't depend on key order, so
+/// identical calls can be recognized across memories
+pub fn arguments_hash(arguments: &HashMap<String, serde_json::Value>) -> String {
+    let sorted: BTreeMap<&String, &serde_json::Value> = arguments.iter().collect();
+    content_hash(&serde_json::to_vec(&sorted).unwrap_or_default())
+}
+
+/// Checks `arguments` against the spec's input schema, failing with
+/// [`AgentError::InvalidToolArguments`] that lists every field that failed
+pub fn validate_arguments(
+    spec: &ToolSpec,
+    arguments: &HashMap<String, serde_json::Value>,
+) -> Result<()> {
+    let arguments = serde_json::Value::Object(
+        arguments
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+    );
+    let errors = schema_errors(&arguments, &spec.input_schema);
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(AgentError::InvalidToolArguments {
+        tool: spec.name.clone(),
+        errors,
+    })
+}
+
+/// Checks a response against the spec's output schema, if it declares one,
+/// failing with [`AgentError::InvalidToolOutput`]
+pub fn validate_output(spec: &ToolSpec, response: &ToolResponse) -> Result<()> {
+    let Some(schema) = &spec.output_schema else {
+        return Ok(());
+    };
+    let errors = match serde_json::from_str(&response.content) {
+        Ok(output) => schema_errors(&output, schema),
+        Err(e) => vec![SchemaError {
+            path: "$".to_string(),
+            message: format!("output is not JSON: {}", e),
+        }],
+    };
+    if errors.is_empty() {
+        return Ok(());
+    }
+    Err(AgentError::InvalidToolOutput {
+        tool: spec.name.clone(),
+        errors,
+    })
+}
+
+/// Partial results of a streaming tool call, in order
+pub type ToolStream = BoxStream<'static, Result<ToolResponse>>;
+
 /// Tool trait for defining custom tools
 #[async_trait]
 pub trait Tool: Send + Sync {
@@ -12,62 +124,560 @@ pub trait Tool: Send + Sync {
 
     /// Invokes the tool with the given request
     async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse>;
+
+    /// Invokes the tool, yielding partial results as they arrive, for
+    /// long-running tools such as log tails or large downloads.
+    ///
+    /// The default implementation yields the response of
+    /// [`invoke`](Tool::invoke) as a single item. The built-in layers
+    /// forward streaming calls to the tool they wrap.
+    async fn invoke_stream(&self, req: ToolRequest) -> Result<ToolStream> {
+        let response = self.invoke(req).await?;
+        Ok(futures::stream::once(async move { Ok(response) }).boxed())
+    }
 }
 
+/// A registry of tools an agent can call.
+///
+/// [`ToolCatalog`] replaces a tool registered twice under one name;
+/// [`StaticToolCatalog`](crate::catalog::StaticToolCatalog) matches names
+/// case-insensitively and rejects duplicates. Both merge another registry
+/// all or nothing, refusing names they already have, list tools in
+/// registration order, and either can back an agent through
+/// [`Agent::with_tools`](crate::Agent::with_tools).
+#[async_trait]
+pub trait ToolRegistry: Send + Sync {
+    /// Registers a tool under the name in its spec
+    fn register(&self, tool: Box<dyn Tool>) -> Result<()>;
+
+    /// Removes a tool, returning whether it was registered
+    fn unregister(&self, name: &str) -> bool;
+
+    /// Swaps in `tool` for the one registered under its name, failing with
+    /// [`AgentError::ToolNotFound`] if there is none.
+    ///
+    /// The default implementation unregisters and registers again, moving
+    /// the tool to the end; both catalogs keep its position.
+    fn replace(&self, tool: Box<dyn Tool>) -> Result<()> {
+        let name = tool.spec().name;
+        if !self.unregister(&name) {
+            return Err(AgentError::ToolNotFound(name));
+        }
+        self.register(tool)
+    }
+
+    /// Registers every tool of `other`, in its order, returning how many.
+    /// If a name is already registered, or repeated in `other`, nothing is
+    /// merged.
+    fn merge(&self, other: &dyn ToolRegistry) -> Result<usize> {
+        let tools = other.tools();
+        check_merge(&tools, |name| self.get(name).is_some())?;
+        let count = tools.len();
+        for tool in tools {
+            self.register(Box::new(SharedTool(tool)))?;
+        }
+        Ok(count)
+    }
+
+    /// Returns the tool registered under `name`
+    fn get(&self, name: &str) -> Option<Arc<dyn Tool>>;
+
+    /// Returns all tools in registration order
+    fn tools(&self) -> Vec<Arc<dyn Tool>>;
+
+    /// Looks up a tool's specification by name
+    fn lookup(&self, name: &str) -> Option<ToolSpec> {
+        self.get(name).map(|tool| tool.spec())
+    }
+
+    /// Returns all tool specifications in registration order
+    fn specs(&self) -> Vec<ToolSpec> {
+        self.tools().iter().map(|tool| tool.spec()).collect()
+    }
+
+    /// Invokes a tool by name, checking the arguments with
+    /// [`validate_arguments`] before and the response with
+    /// [`validate_output`] after
+    async fn invoke(&self, name: &str, req: ToolRequest) -> Result<ToolResponse> {
+        let tool = self
+            .get(name)
+            .ok_or_else(|| AgentError::ToolNotFound(name.to_string()))?;
+        let spec = tool.spec();
+        validate_arguments(&spec, &req.arguments)?;
+        let response = tool.invoke(req).await?;
+        validate_output(&spec, &response)?;
+        Ok(response)
+    }
+
+    /// Invokes a tool by name, streaming its partial results, after
+    /// checking the arguments with [`validate_arguments`]. Once the stream
+    /// ends, the joined output is checked with [`validate_output`], which
+    /// ends the stream with an error if it fails.
+    async fn invoke_stream(&self, name: &str, req: ToolRequest) -> Result<ToolStream> {
+        let tool = self
+            .get(name)
+            .ok_or_else(|| AgentError::ToolNotFound(name.to_string()))?;
+        let spec = tool.spec();
+        validate_arguments(&spec, &req.arguments)?;
+        let chunks = tool.invoke_stream(req).await?;
+        Ok(checked_stream(spec, chunks, None))
+    }
+
+    /// Returns a number that changes whenever a tool is registered,
+    /// replaced or removed, and that no other catalog of the process has
+    /// had for different tools; registries that don't track one return
+    /// `None`, and callers compare their specs instead
+    fn generation(&self) -> Option<u64> {
+        None
+    }
+
+    /// Returns usage per tool called so far; registries that don't track
+    /// usage return none
+    fn stats(&self) -> HashMap<String, ToolUsage> {
+        HashMap::new()
+    }
+
+    /// Invokes a tool on behalf of `tenant_id`; registries without tenant
+    /// isolation reject the call
+    async fn invoke_as(
+        &self,
+        _tenant_id: &str,
+        _name: &str,
+        _req: ToolRequest,
+    ) -> Result<ToolResponse> {
+        Err(AgentError::ConfigError(
+            "Tool registry does not support tenant isolation".to_string(),
+        ))
+    }
+}
+
+/// Checks the joined output of `chunks` against `spec` once they end,
+/// finishing `call` with the outcome. A stream dropped early leaves `call`
+/// to record a failure.
+fn checked_stream(
+    spec: ToolSpec,
+    chunks: ToolStream,
+    call: Option<stats::PendingCall>,
+) -> ToolStream {
+    let joined = ToolResponse {
+        content: String::new(),
+        metadata: None,
+    };
+    let state = Some((chunks, joined, spec, call));
+    futures::stream::unfold(state, |state| async move {
+        let (mut chunks, mut joined, spec, call) = state?;
+        match chunks.next().await {
+            Some(Ok(chunk)) => {
+                joined.content.push_str(&chunk.content);
+                if chunk.metadata.is_some() {
+                    joined.metadata = chunk.metadata.clone();
+                }
+                Some((Ok(chunk), Some((chunks, joined, spec, call))))
+            }
+            Some(Err(e)) => {
+                if let Some(call) = call {
+                    call.finish(false);
+                }
+                Some((Err(e), None))
+            }
+            None => {
+                let checked = validate_output(&spec, &joined);
+                if let Some(call) = call {
+                    call.finish(checked.is_ok());
+                }
+                checked.err().map(|e| (Err(e), None))
+            }
+        }
+    })
+    .boxed()
+}
+
+/// Fails with [`AgentError::ToolError`] if a name of `tools` is repeated or
+/// already `registered`
+fn check_merge(tools: &[Arc<dyn Tool>], registered: impl Fn(&str) -> bool) -> Result<()> {
+    let mut seen = std::collections::HashSet::new();
+    for tool in tools {
+        let name = tool.spec().name;
+        if registered(&name) || !seen.insert(name.clone()) {
+            return Err(AgentError::ToolError(format!(
+                "tool {} already registered",
+                name
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Lends a tool held by another registry to [`ToolRegistry::register`]
+struct SharedTool(Arc<dyn Tool>);
+
+#[async_trait]
+impl Tool for SharedTool {
+    fn spec(&self) -> ToolSpec {
+        self.0.spec()
+    }
+
+    async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse> {
+        self.0.invoke(req).await
+    }
+
+    async fn invoke_stream(&self, req: ToolRequest) -> Result<ToolStream> {
+        self.0.invoke_stream(req).await
+    }
+}
+
+/// Returns a catalog generation unused by any catalog of the process
+pub(crate) fn next_generation() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+/// Registered tools keyed by name, plus their registration order
+#[derive(Default)]
+struct Entries {
+    tools: HashMap<String, Arc<dyn Tool>>,
+    order: Vec<String>,
+    /// Limits of tools registered with them, applied again on replace
+    limits: HashMap<String, ToolLimits>,
+    /// Changed by every registration, replacement and removal; empty
+    /// catalogs share generation 0
+    generation: u64,
+}
+
+/// Calls [`ToolCatalog::invoke_many`] runs at once unless configured
+pub(crate) const DEFAULT_TOOL_PARALLELISM: usize = 8;
+
 /// Tool catalog manages registered tools
 #[derive(Default)]
 pub struct ToolCatalog {
-    tools: parking_lot::RwLock<HashMap<String, Box<dyn Tool>>>,
+    entries: parking_lot::RwLock<Entries>,
+    tenant_guard: Option<Arc<TenantGuard>>,
+    layers: Vec<Arc<dyn ToolLayer>>,
+    parallelism: Option<usize>,
+    usage: Arc<stats::UsageTracker>,
 }
 
 impl ToolCatalog {
     /// Creates a new empty tool catalog
     pub fn new() -> Self {
-        Self {
-            tools: parking_lot::RwLock::new(HashMap::new()),
+        Self::default()
+    }
+
+    /// Enforces tenant isolation on every invocation.
+    ///
+    /// Requests run on behalf of their `tenant_id`, or else the current
+    /// [`tenant::scope`](crate::tenant::scope), and are stamped with it.
+    /// Share the guard with `SessionMemory` so sessions claimed there are
+    /// known here.
+    pub fn with_tenant_guard(mut self, guard: Arc<TenantGuard>) -> Self {
+        self.tenant_guard = Some(guard);
+        self
+    }
+
+    /// Bounds how many calls [`ToolCatalog::invoke_many`] runs at once
+    pub fn with_parallelism(mut self, limit: usize) -> Self {
+        self.parallelism = Some(limit.max(1));
+        self
+    }
+
+    /// Computes latency percentiles over each tool's last `samples` calls,
+    /// 1024 by default
+    pub fn with_latency_samples(self, samples: usize) -> Self {
+        self.usage.set_samples(samples);
+        self
+    }
+
+    /// Wraps every tool of the catalog, registered before or after, in
+    /// `layer`; layers added later wrap the earlier ones
+    pub fn with_layer(mut self, layer: Arc<dyn ToolLayer>) -> Self {
+        let entries = self.entries.get_mut();
+        for tool in entries.tools.values_mut() {
+            *tool = layer.layer(Arc::clone(tool));
+        }
+        if !entries.tools.is_empty() {
+            entries.generation = next_generation();
         }
+        self.layers.push(layer);
+        self
     }
 
-    /// Registers a tool in the catalog
+    /// Registers a tool in the catalog.
+    ///
+    /// Registering a name again replaces the tool but keeps its position.
     pub fn register(&self, tool: Box<dyn Tool>) -> Result<()> {
-        let spec = tool.spec();
-        let mut tools = self.tools.write();
-        tools.insert(spec.name.clone(), tool);
+        self.insert(Arc::from(tool), None)
+    }
+
+    /// Registers a tool whose calls are bounded by `limits`; they also bound
+    /// the tools that [`replace`](ToolCatalog::replace) it later
+    pub fn register_with_limits(&self, tool: Box<dyn Tool>, limits: ToolLimits) -> Result<()> {
+        self.insert(Arc::from(tool), Some(limits))
+    }
+
+    fn insert(&self, tool: Arc<dyn Tool>, limits: Option<ToolLimits>) -> Result<()> {
+        let layers = limits.map(|limits| limits.layers()).unwrap_or_default();
+        let tool = apply_layers(apply_layers(tool, &layers), &self.layers);
+        let name = tool.spec().name;
+        let mut entries = self.entries.write();
+        match limits {
+            Some(limits) => entries.limits.insert(name.clone(), limits),
+            None => entries.limits.remove(&name),
+        };
+        if entries.tools.insert(name.clone(), tool).is_none() {
+            entries.order.push(name);
+        }
+        entries.generation = next_generation();
         Ok(())
     }
 
+    /// Removes a tool from the catalog, returning whether it was registered
+    pub fn unregister(&self, name: &str) -> bool {
+        let mut entries = self.entries.write();
+        if entries.tools.remove(name).is_none() {
+            return false;
+        }
+        entries.limits.remove(name);
+        entries.order.retain(|registered| registered != name);
+        entries.generation = next_generation();
+        true
+    }
+
+    /// Swaps in `tool` for the one registered under its name, keeping its
+    /// position and limits; fails with [`AgentError::ToolNotFound`] if there
+    /// is none, unlike [`register`](ToolCatalog::register)
+    pub fn replace(&self, tool: Box<dyn Tool>) -> Result<()> {
+        let name = tool.spec().name;
+        let mut entries = self.entries.write();
+        let layers = entries
+            .limits
+            .get(&name)
+            .map(ToolLimits::layers)
+            .unwrap_or_default();
+        let tool = apply_layers(apply_layers(Arc::from(tool), &layers), &self.layers);
+        match entries.tools.get_mut(&name) {
+            Some(registered) => {
+                *registered = tool;
+                entries.generation = next_generation();
+                Ok(())
+            }
+            None => Err(AgentError::ToolNotFound(name)),
+        }
+    }
+
+    /// Registers every tool of `other`, in its order, returning how many.
+    ///
+    /// If a name is already registered, or repeated in `other`, nothing is
+    /// merged, so a catalog merged into itself is left as it is. The tools
+    /// keep any layers of `other` and gain this catalog's.
+    pub fn merge(&self, other: &dyn ToolRegistry) -> Result<usize> {
+        // Taken before locking, so merging a catalog into itself can't deadlock
+        let tools: Vec<Arc<dyn Tool>> = other
+            .tools()
+            .into_iter()
+            .map(|tool| apply_layers(tool, &self.layers))
+            .collect();
+        let mut entries = self.entries.write();
+        check_merge(&tools, |name| entries.tools.contains_key(name))?;
+        let count = tools.len();
+        for tool in tools {
+            let name = tool.spec().name;
+            entries.tools.insert(name.clone(), tool);
+            entries.order.push(name);
+        }
+        if count > 0 {
+            entries.generation = next_generation();
+        }
+        Ok(count)
+    }
+
     /// Looks up a tool by name
     pub fn lookup(&self, name: &str) -> Option<ToolSpec> {
-        let tools = self.tools.read();
-        tools.get(name).map(|tool| tool.spec())
+        let entries = self.entries.read();
+        entries.tools.get(name).map(|tool| tool.spec())
     }
 
-    /// Returns all tool specifications
+    /// Returns all tool specifications in registration order
     pub fn specs(&self) -> Vec<ToolSpec> {
-        let tools = self.tools.read();
-        tools.values().map(|tool| tool.spec()).collect()
+        self.tools().iter().map(|tool| tool.spec()).collect()
     }
 
-    /// Invokes a tool by name
-    pub async fn invoke(&self, name: &str, req: ToolRequest) -> Result<ToolResponse> {
-        let tool = {
-            let tools = self.tools.read();
-            tools.get(name).map(|t| t.spec().name.clone())
-        };
+    /// Returns all tools in registration order
+    pub fn tools(&self) -> Vec<Arc<dyn Tool>> {
+        let entries = self.entries.read();
+        entries
+            .order
+            .iter()
+            .filter_map(|name| entries.tools.get(name).map(Arc::clone))
+            .collect()
+    }
+
+    /// Invokes a tool by name.
+    ///
+    /// Arguments that don't match the tool's input schema fail with
+    /// [`AgentError::InvalidToolArguments`] before the tool runs, and output
+    /// that doesn't match its output schema with
+    /// [`AgentError::InvalidToolOutput`]. Calls that reach the tool count
+    /// towards its [`stats`](ToolCatalog::stats), failing ones included.
+    pub async fn invoke(&self, name: &str, mut req: ToolRequest) -> Result<ToolResponse> {
+        self.admit(&mut req)?;
+        let tool = self.entries.read().tools.get(name).cloned();
+        let tool = tool.ok_or_else(|| AgentError::ToolNotFound(name.to_string()))?;
+        let spec = tool.spec();
+        validate_arguments(&spec, &req.arguments)?;
+        let started = Instant::now();
+        let result = tool
+            .invoke(req)
+            .await
+            .and_then(|response| validate_output(&spec, &response).map(|()| response));
+        self.usage.record(name, started.elapsed(), result.is_ok());
+        result
+    }
+
+    /// Invokes a tool by name, streaming its partial results.
+    ///
+    /// Arguments are checked like [`invoke`](ToolCatalog::invoke) does, and
+    /// the joined output once the stream ends, which then ends with
+    /// [`AgentError::InvalidToolOutput`] if it doesn't match. The call
+    /// counts towards the tool's stats when the stream ends; a stream
+    /// dropped early counts as failed.
+    pub async fn invoke_stream(&self, name: &str, mut req: ToolRequest) -> Result<ToolStream> {
+        self.admit(&mut req)?;
+        let tool = self.entries.read().tools.get(name).cloned();
+        let tool = tool.ok_or_else(|| AgentError::ToolNotFound(name.to_string()))?;
+        let spec = tool.spec();
+        validate_arguments(&spec, &req.arguments)?;
+        // Dropped unfinished if the tool fails to start, counting a failure
+        let call = self.usage.start(name);
+        let chunks = tool.invoke_stream(req).await?;
+        Ok(checked_stream(spec, chunks, Some(call)))
+    }
+
+    /// Invokes independent tool calls concurrently, returning their results
+    /// in call order.
+    ///
+    /// At most [`with_parallelism`](ToolCatalog::with_parallelism) calls
+    /// run at once, eight by default. A failing call doesn't stop the others.
+    pub async fn invoke_many(
+        &self,
+        calls: Vec<(String, ToolRequest)>,
+    ) -> Vec<Result<ToolResponse>> {
+        let parallelism = self.parallelism.unwrap_or(DEFAULT_TOOL_PARALLELISM);
+        // Unordered so a slow call doesn't hold up later ones
+        let mut results: Vec<(usize, Result<ToolResponse>)> =
+            futures::stream::iter(calls.into_iter().enumerate())
+                .map(|(index, (name, req))| async move { (index, self.invoke(&name, req).await) })
+                .buffer_unordered(parallelism)
+                .collect()
+                .await;
+        results.sort_by_key(|(index, _)| *index);
+        results.into_iter().map(|(_, result)| result).collect()
+    }
+
+    /// Returns calls, error rate and latency percentiles per tool called
+    /// so far
+    pub fn stats(&self) -> HashMap<String, ToolUsage> {
+        self.usage.stats()
+    }
+
+    /// Returns the usage of the tool registered under `name`, if called
+    pub fn tool_stats(&self, name: &str) -> Option<ToolUsage> {
+        self.usage.get(name)
+    }
+
+    /// Forgets all usage recorded so far
+    pub fn reset_stats(&self) {
+        self.usage.reset();
+    }
+
+    /// Receives a [`ToolCallEvent`] for every call made after subscribing.
+    ///
+    /// Events are dropped for subscribers more than 256 calls behind.
+    pub fn subscribe_usage(&self) -> tokio::sync::broadcast::Receiver<ToolCallEvent> {
+        self.usage.subscribe()
+    }
+
+    /// Invokes a tool on behalf of `tenant_id`, stamping the request with it
+    pub async fn invoke_as(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        mut req: ToolRequest,
+    ) -> Result<ToolResponse> {
+        let guard = self.tenant_guard.as_deref().ok_or_else(|| {
+            AgentError::ConfigError("No tenant guard configured for tool catalog".to_string())
+        })?;
+        guard.stamp_request(tenant_id, &mut req)?;
+        self.invoke(name, req).await
+    }
 
-        if tool.is_none() {
-            return Err(crate::error::AgentError::ToolNotFound(name.to_string()));
+    /// Checks the request's tenant against the session's owner and stamps it
+    fn admit(&self, req: &mut ToolRequest) -> Result<()> {
+        let Some(guard) = &self.tenant_guard else {
+            return Ok(());
+        };
+        match req.tenant_id.clone().or_else(crate::tenant::current) {
+            Some(tenant_id) => guard.stamp_request(&tenant_id, req),
+            None => guard.check(None, &req.session_id),
         }
+    }
+}
 
-        let tools = self.tools.read();
-        let tool = tools.get(name).unwrap();
-        tool.invoke(req).await
+#[async_trait]
+impl ToolRegistry for ToolCatalog {
+    fn register(&self, tool: Box<dyn Tool>) -> Result<()> {
+        ToolCatalog::register(self, tool)
+    }
+
+    fn unregister(&self, name: &str) -> bool {
+        ToolCatalog::unregister(self, name)
+    }
+
+    fn replace(&self, tool: Box<dyn Tool>) -> Result<()> {
+        ToolCatalog::replace(self, tool)
+    }
+
+    fn merge(&self, other: &dyn ToolRegistry) -> Result<usize> {
+        ToolCatalog::merge(self, other)
+    }
+
+    fn get(&self, name: &str) -> Option<Arc<dyn Tool>> {
+        self.entries.read().tools.get(name).cloned()
+    }
+
+    fn generation(&self) -> Option<u64> {
+        Some(self.entries.read().generation)
+    }
+
+    fn tools(&self) -> Vec<Arc<dyn Tool>> {
+        ToolCatalog::tools(self)
+    }
+
+    fn stats(&self) -> HashMap<String, ToolUsage> {
+        ToolCatalog::stats(self)
+    }
+
+    async fn invoke(&self, name: &str, req: ToolRequest) -> Result<ToolResponse> {
+        ToolCatalog::invoke(self, name, req).await
+    }
+
+    async fn invoke_stream(&self, name: &str, req: ToolRequest) -> Result<ToolStream> {
+        ToolCatalog::invoke_stream(self, name, req).await
+    }
+
+    async fn invoke_as(
+        &self,
+        tenant_id: &str,
+        name: &str,
+        req: ToolRequest,
+    ) -> Result<ToolResponse> {
+        ToolCatalog::invoke_as(self, tenant_id, name, req).await
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::time::Duration;
 
     struct EchoTool;
 
@@ -88,6 +698,7 @@ mod tests {
                     "required": ["input"]
                 }),
                 examples: None,
+                output_schema: None,
             }
         }
 
@@ -122,6 +733,7 @@ mod tests {
                 ToolRequest {
                     session_id: "test".to_string(),
                     arguments: args,
+                    tenant_id: None,
                 },
             )
             .await
@@ -129,4 +741,418 @@ mod tests {
 
         assert_eq!(response.content, "hello");
     }
+
+    #[tokio::test]
+    async fn test_guarded_invoke_checks_the_tenant() {
+        let catalog = ToolCatalog::new().with_tenant_guard(Arc::new(TenantGuard::new()));
+        catalog.register(Box::new(EchoTool)).unwrap();
+        let request = |tenant_id: Option<&str>| ToolRequest {
+            session_id: "acme-chat".to_string(),
+            arguments: HashMap::from([("input".to_string(), serde_json::json!("hi"))]),
+            tenant_id: tenant_id.map(str::to_string),
+        };
+
+        catalog.invoke("echo", request(Some("acme"))).await.unwrap();
+        let other = catalog.invoke("echo", request(Some("globex"))).await;
+        assert!(matches!(other, Err(AgentError::TenantViolation(_))));
+        assert!(catalog.invoke("echo", request(None)).await.is_err());
+
+        let scoped = crate::tenant::scope(
+            Some("acme".to_string()),
+            catalog.invoke("echo", request(None)),
+        );
+        scoped.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_invoke_rejects_arguments_not_matching_the_schema() {
+        let catalog = ToolCatalog::new();
+        catalog.register(Box::new(EchoTool)).unwrap();
+        let request = |arguments| ToolRequest {
+            session_id: "test".to_string(),
+            arguments,
+            tenant_id: None,
+        };
+
+        let wrong_type = HashMap::from([("input".to_string(), serde_json::json!(5))]);
+        match catalog.invoke("echo", request(wrong_type)).await {
+            Err(AgentError::InvalidToolArguments { tool, errors }) => {
+                assert_eq!(tool, "echo");
+                assert_eq!(errors.len(), 1);
+                assert_eq!(errors[0].path, "$.input");
+            }
+            other => panic!("expected invalid arguments, got {:?}", other),
+        }
+
+        let missing = catalog.invoke("echo", request(HashMap::new())).await;
+        let message = missing.unwrap_err().to_string();
+        assert!(message.contains("missing required field `input`"));
+    }
+
+    /// Reports a fixed forecast and declares its shape
+    struct ForecastTool(serde_json::Value);
+
+    #[async_trait]
+    impl Tool for ForecastTool {
+        fn spec(&self) -> ToolSpec {
+            ToolSpec {
+                name: "forecast".to_string(),
+                description: "Returns the forecast".to_string(),
+                input_schema: serde_json::json!({"type": "object"}),
+                examples: None,
+                output_schema: Some(serde_json::json!({
+                    "type": "object",
+                    "properties": {"celsius": {"type": "number"}},
+                    "required": ["celsius"]
+                })),
+            }
+        }
+
+        async fn invoke(&self, _req: ToolRequest) -> Result<ToolResponse> {
+            Ok(ToolResponse::structured(self.0.clone()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invoke_rejects_output_not_matching_the_schema() {
+        let request = || ToolRequest {
+            session_id: "test".to_string(),
+            arguments: HashMap::new(),
+            tenant_id: None,
+        };
+
+        let valid = ToolCatalog::new();
+        valid
+            .register(Box::new(ForecastTool(serde_json::json!({"celsius": 21.5}))))
+            .unwrap();
+        let response = valid.invoke("forecast", request()).await.unwrap();
+        assert_eq!(response.content, r#"{"celsius":21.5}"#);
+
+        let invalid = ToolCatalog::new();
+        invalid
+            .register(Box::new(ForecastTool(
+                serde_json::json!({"celsius": "warm"}),
+            )))
+            .unwrap();
+        match invalid.invoke("forecast", request()).await {
+            Err(AgentError::InvalidToolOutput { tool, errors }) => {
+                assert_eq!(tool, "forecast");
+                assert_eq!(errors[0].path, "$.celsius");
+            }
+            other => panic!("expected invalid output, got {:?}", other),
+        }
+    }
+
+    /// Sleeps, tracking how many calls are in flight
+    #[derive(Clone, Default)]
+    struct SlowTool {
+        running: Arc<std::sync::atomic::AtomicUsize>,
+        peak: Arc<std::sync::atomic::AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl Tool for SlowTool {
+        fn spec(&self) -> ToolSpec {
+            ToolSpec {
+                name: "slow".to_string(),
+                description: "Takes a while".to_string(),
+                input_schema: serde_json::json!({}),
+                examples: None,
+                output_schema: None,
+            }
+        }
+
+        async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse> {
+            use std::sync::atomic::Ordering;
+            let running = self.running.fetch_add(1, Ordering::SeqCst) + 1;
+            self.peak.fetch_max(running, Ordering::SeqCst);
+            let millis = req.arguments.get("ms").and_then(|v| v.as_u64());
+            tokio::time::sleep(Duration::from_millis(millis.unwrap_or(10))).await;
+            self.running.fetch_sub(1, Ordering::SeqCst);
+            let label = req.arguments.get("label").and_then(|v| v.as_str());
+            Ok(ToolResponse {
+                content: label.unwrap_or("done").to_string(),
+                metadata: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_limits_bound_time_and_concurrency() {
+        let tool = SlowTool::default();
+        let catalog = ToolCatalog::new();
+        catalog
+            .register_with_limits(
+                Box::new(tool.clone()),
+                ToolLimits::new()
+                    .with_max_concurrency(2)
+                    .with_timeout(Duration::from_secs(5)),
+            )
+            .unwrap();
+        let request = |ms: u64| ToolRequest {
+            session_id: "test".to_string(),
+            arguments: HashMap::from([("ms".to_string(), serde_json::json!(ms))]),
+            tenant_id: None,
+        };
+
+        let calls = (0..5).map(|_| catalog.invoke("slow", request(10)));
+        for response in futures::future::join_all(calls).await {
+            assert_eq!(response.unwrap().content, "done");
+        }
+        assert_eq!(tool.peak.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        let strict = ToolCatalog::new();
+        strict
+            .register_with_limits(
+                Box::new(tool),
+                ToolLimits::new().with_timeout(Duration::from_millis(20)),
+            )
+            .unwrap();
+        assert!(matches!(
+            strict.invoke("slow", request(5_000)).await,
+            Err(AgentError::ToolTimeout(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_invoke_many_runs_calls_in_parallel_and_keeps_order() {
+        let tool = SlowTool::default();
+        let catalog = ToolCatalog::new().with_parallelism(3);
+        catalog.register(Box::new(tool.clone())).unwrap();
+        let request = |label: &str, ms: u64| ToolRequest {
+            session_id: "test".to_string(),
+            arguments: HashMap::from([
+                ("label".to_string(), serde_json::json!(label)),
+                ("ms".to_string(), serde_json::json!(ms)),
+            ]),
+            tenant_id: None,
+        };
+
+        // Later calls finish first, but results follow the calls
+        let mut calls: Vec<(String, ToolRequest)> = (0..6)
+            .map(|i| ("slow".to_string(), request(&i.to_string(), 60 - i * 10)))
+            .collect();
+        calls.insert(2, ("missing".to_string(), request("x", 0)));
+        let results = catalog.invoke_many(calls).await;
+
+        let contents: Vec<String> = results
+            .iter()
+            .filter_map(|result| result.as_ref().ok())
+            .map(|response| response.content.clone())
+            .collect();
+        assert_eq!(contents, ["0", "1", "2", "3", "4", "5"]);
+        assert!(matches!(results[2], Err(AgentError::ToolNotFound(_))));
+        assert_eq!(tool.peak.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    struct NamedTool(&'static str);
+
+    #[async_trait]
+    impl Tool for NamedTool {
+        fn spec(&self) -> ToolSpec {
+            ToolSpec {
+                name: self.0.to_string(),
+                description: "Named tool".to_string(),
+                input_schema: serde_json::json!({}),
+                examples: None,
+                output_schema: None,
+            }
+        }
+
+        async fn invoke(&self, _req: ToolRequest) -> Result<ToolResponse> {
+            Ok(ToolResponse {
+                content: self.0.to_string(),
+                metadata: None,
+            })
+        }
+    }
+
+    fn names(registry: &dyn ToolRegistry) -> Vec<String> {
+        registry.specs().into_iter().map(|spec| spec.name).collect()
+    }
+
+    #[tokio::test]
+    async fn test_registries_keep_order_and_duplicate_policies() {
+        let catalog = ToolCatalog::new();
+        let fixed = crate::catalog::StaticToolCatalog::new();
+        let registries: [&dyn ToolRegistry; 2] = [&catalog, &fixed];
+        for registry in registries {
+            for name in ["zeta", "alpha", "mid"] {
+                registry.register(Box::new(NamedTool(name))).unwrap();
+            }
+            assert_eq!(names(registry), ["zeta", "alpha", "mid"]);
+            assert!(registry.unregister("alpha"));
+            assert!(!registry.unregister("alpha"));
+        }
+
+        // ToolCatalog replaces in place; StaticToolCatalog rejects duplicates
+        catalog.register(Box::new(NamedTool("zeta"))).unwrap();
+        assert_eq!(names(&catalog), ["zeta", "mid"]);
+        assert!(ToolRegistry::register(&fixed, Box::new(NamedTool("ZETA"))).is_err());
+
+        let request = ToolRequest {
+            session_id: "test".to_string(),
+            arguments: HashMap::new(),
+            tenant_id: None,
+        };
+        let response = ToolRegistry::invoke(&fixed, "Mid", request).await.unwrap();
+        assert_eq!(response.content, "mid");
+    }
+
+    #[tokio::test]
+    async fn test_registries_replace_and_merge_in_place() {
+        let catalog = ToolCatalog::new();
+        let fixed = crate::catalog::StaticToolCatalog::new();
+        let registries: [&dyn ToolRegistry; 2] = [&catalog, &fixed];
+        for registry in registries {
+            for name in ["zeta", "alpha"] {
+                registry.register(Box::new(NamedTool(name))).unwrap();
+            }
+            registry.replace(Box::new(NamedTool("zeta"))).unwrap();
+            assert_eq!(names(registry), ["zeta", "alpha"]);
+            assert!(matches!(
+                registry.replace(Box::new(NamedTool("beta"))),
+                Err(AgentError::ToolNotFound(_))
+            ));
+        }
+
+        let upstream = ToolCatalog::new();
+        for name in ["beta", "gamma"] {
+            upstream.register(Box::new(NamedTool(name))).unwrap();
+        }
+        assert_eq!(catalog.merge(&upstream).unwrap(), 2);
+        assert_eq!(fixed.merge(&upstream).unwrap(), 2);
+        assert_eq!(names(&catalog), ["zeta", "alpha", "beta", "gamma"]);
+        assert_eq!(names(&fixed), names(&catalog));
+
+        // A shared name merges nothing, in either registry or into itself
+        upstream.register(Box::new(NamedTool("delta"))).unwrap();
+        for registry in registries {
+            assert!(registry.merge(&upstream).is_err());
+            assert!(registry.merge(registry).is_err());
+            assert_eq!(names(registry).len(), 4);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_replace_keeps_registered_limits() {
+        let catalog = ToolCatalog::new();
+        let slow = || {
+            Box::new(SlowTool {
+                running: Arc::default(),
+                peak: Arc::default(),
+            })
+        };
+        let limits = ToolLimits::new().with_timeout(Duration::from_millis(10));
+        catalog.register_with_limits(slow(), limits).unwrap();
+        catalog.replace(slow()).unwrap();
+        let request = ToolRequest {
+            session_id: "s".to_string(),
+            arguments: HashMap::from([("ms".to_string(), serde_json::json!(1000))]),
+            tenant_id: None,
+        };
+        assert!(matches!(
+            catalog.invoke("slow", request).await,
+            Err(AgentError::ToolTimeout(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_catalog_tracks_usage_of_calls_reaching_tools() {
+        let catalog = ToolCatalog::new();
+        catalog.register(Box::new(EchoTool)).unwrap();
+        catalog
+            .register(Box::new(ForecastTool(
+                serde_json::json!({"celsius": "warm"}),
+            )))
+            .unwrap();
+        let mut events = catalog.subscribe_usage();
+        let request = |input: Option<&str>| ToolRequest {
+            session_id: "test".to_string(),
+            arguments: input
+                .map(|input| HashMap::from([("input".to_string(), serde_json::json!(input))]))
+                .unwrap_or_default(),
+            tenant_id: None,
+        };
+
+        catalog.invoke("echo", request(Some("hi"))).await.unwrap();
+        let registry: &dyn ToolRegistry = &catalog;
+        registry
+            .invoke("echo", request(Some("again")))
+            .await
+            .unwrap();
+        // Invalid arguments never reach the tool
+        assert!(catalog.invoke("echo", request(None)).await.is_err());
+        assert!(catalog.invoke("forecast", request(None)).await.is_err());
+
+        let stats = registry.stats();
+        assert_eq!(stats["echo"].calls, 2);
+        assert_eq!(stats["echo"].error_rate(), 0.0);
+        assert_eq!(stats["forecast"].error_rate(), 1.0);
+        let event = events.recv().await.unwrap();
+        assert_eq!((event.tool.as_str(), event.success), ("echo", true));
+
+        catalog.reset_stats();
+        assert!(catalog.tool_stats("echo").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_catalog_checks_and_counts_streamed_calls() {
+        let catalog = ToolCatalog::new();
+        catalog.register(Box::new(EchoTool)).unwrap();
+        catalog
+            .register(Box::new(ForecastTool(
+                serde_json::json!({"celsius": "warm"}),
+            )))
+            .unwrap();
+        let request = |arguments: HashMap<String, serde_json::Value>| ToolRequest {
+            session_id: "test".to_string(),
+            arguments,
+            tenant_id: None,
+        };
+        let echo = || HashMap::from([("input".to_string(), serde_json::json!("hi"))]);
+
+        // The joined output is checked against the schema once the stream ends
+        let items: Vec<Result<ToolResponse>> = catalog
+            .invoke_stream("forecast", request(HashMap::new()))
+            .await
+            .unwrap()
+            .collect()
+            .await;
+        assert!(matches!(
+            items.last(),
+            Some(Err(AgentError::InvalidToolOutput { .. }))
+        ));
+
+        // A stream dropped before it ends counts as a failed call
+        drop(
+            catalog
+                .invoke_stream("echo", request(echo()))
+                .await
+                .unwrap(),
+        );
+        let chunks = catalog
+            .invoke_stream("echo", request(echo()))
+            .await
+            .unwrap();
+        assert_eq!(chunks.collect::<Vec<_>>().await.len(), 1);
+
+        let stats = catalog.stats();
+        assert_eq!((stats["echo"].calls, stats["echo"].failures), (2, 1));
+        assert_eq!(stats["forecast"].failures, 1);
+    }
+
+    #[test]
+    fn test_arguments_hash_ignores_key_order() {
+        let mut first = HashMap::new();
+        first.insert("city".to_string(), serde_json::json!("Oslo"));
+        first.insert("units".to_string(), serde_json::json!("metric"));
+        let mut second = HashMap::new();
+        second.insert("units".to_string(), serde_json::json!("metric"));
+        second.insert("city".to_string(), serde_json::json!("Oslo"));
+        assert_eq!(arguments_hash(&first), arguments_hash(&second));
+
+        second.insert("city".to_string(), serde_json::json!("Bergen"));
+        assert_ne!(arguments_hash(&first), arguments_hash(&second));
+    }
 }
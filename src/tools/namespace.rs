@@ -0,0 +1,153 @@
+//! Tool namespaces.
+//!
+//! Tools named with dotted prefixes, such as `fs.read` or `web.search`,
+//! form namespaces that can be switched on and off as a whole.
+//! [`ToolNamespaces`] holds which are enabled: by default, per session, and
+//! through `GenerateOptions` for a single call, so one catalog can serve
+//! callers with different privileges. Namespaces nest: `web` covers
+//! `web.search.images`, and the most specific setting wins.
+
+use std::collections::HashMap;
+
+use parking_lot::RwLock;
+
+/// Returns true if `tool` is in `namespace`, written `fs` or `fs.*`
+pub fn in_namespace(tool: &str, namespace: &str) -> bool {
+    let namespace = namespace.strip_suffix(".*").unwrap_or(namespace);
+    tool.strip_prefix(namespace)
+        .is_some_and(|rest| rest.starts_with('.'))
+}
+
+/// Setting of the most specific namespace among `settings` containing
+/// `tool`
+fn resolve<'a>(tool: &str, settings: impl IntoIterator<Item = (&'a str, bool)>) -> Option<bool> {
+    settings
+        .into_iter()
+        .filter(|(namespace, _)| in_namespace(tool, namespace))
+        .max_by_key(|(namespace, _)| namespace.trim_end_matches(".*").len())
+        .map(|(_, enabled)| enabled)
+}
+
+/// Which tool namespaces are enabled, by default and per session.
+///
+/// Every namespace is enabled unless disabled here; tools outside any
+/// configured namespace are always enabled. A session's settings override
+/// the defaults, and a call's override the session's.
+#[derive(Debug, Default)]
+pub struct ToolNamespaces {
+    defaults: HashMap<String, bool>,
+    sessions: RwLock<HashMap<String, HashMap<String, bool>>>,
+}
+
+impl ToolNamespaces {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Disables `namespace` unless a session or call enables it, e.g. for
+    /// privileged tools
+    pub fn with_disabled(mut self, namespace: impl Into<String>) -> Self {
+        self.defaults.insert(normalize(namespace.into()), false);
+        self
+    }
+
+    /// Enables `namespace` for `session_id`
+    pub fn enable(&self, session_id: &str, namespace: impl Into<String>) {
+        self.set(session_id, namespace.into(), true);
+    }
+
+    /// Disables `namespace` for `session_id`
+    pub fn disable(&self, session_id: &str, namespace: impl Into<String>) {
+        self.set(session_id, namespace.into(), false);
+    }
+
+    /// Drops the settings of `session_id`, leaving it with the defaults
+    pub fn reset(&self, session_id: &str) {
+        self.sessions.write().remove(session_id);
+    }
+
+    fn set(&self, session_id: &str, namespace: String, enabled: bool) {
+        self.sessions
+            .write()
+            .entry(session_id.to_string())
+            .or_default()
+            .insert(normalize(namespace), enabled);
+    }
+
+    /// Returns true if `tool` may be used in `session_id`
+    pub fn is_enabled(&self, session_id: &str, tool: &str) -> bool {
+        self.is_enabled_with(session_id, tool, None, None)
+    }
+
+    /// Returns true if `tool` may be used in `session_id` by a call that
+    /// enables and disables the given namespaces
+    pub fn is_enabled_with(
+        &self,
+        session_id: &str,
+        tool: &str,
+        enabled: Option<&[String]>,
+        disabled: Option<&[String]>,
+    ) -> bool {
+        let call = enabled
+            .into_iter()
+            .flatten()
+            .map(|namespace| (namespace.as_str(), true))
+            .chain(
+                disabled
+                    .into_iter()
+                    .flatten()
+                    .map(|namespace| (namespace.as_str(), false)),
+            );
+        if let Some(enabled) = resolve(tool, call) {
+            return enabled;
+        }
+        let sessions = self.sessions.read();
+        let session = sessions.get(session_id).into_iter().flatten();
+        if let Some(enabled) = resolve(tool, session.map(|(ns, on)| (ns.as_str(), *on))) {
+            return enabled;
+        }
+        resolve(
+            tool,
+            self.defaults.iter().map(|(ns, on)| (ns.as_str(), *on)),
+        )
+        .unwrap_or(true)
+    }
+}
+
+fn normalize(namespace: String) -> String {
+    match namespace.strip_suffix(".*") {
+        Some(namespace) => namespace.to_string(),
+        None => namespace,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_most_specific_namespace_setting_wins() {
+        assert!(in_namespace("fs.read", "fs.*"));
+        assert!(!in_namespace("fsck", "fs"));
+
+        let namespaces = ToolNamespaces::new().with_disabled("shell.*");
+        namespaces.disable("guest", "web");
+        namespaces.enable("guest", "web.search");
+        namespaces.enable("admin", "shell");
+
+        assert!(namespaces.is_enabled("guest", "fs.read"));
+        assert!(namespaces.is_enabled("guest", "calculator"));
+        assert!(!namespaces.is_enabled("guest", "shell.run"));
+        assert!(namespaces.is_enabled("admin", "shell.run"));
+        assert!(!namespaces.is_enabled("guest", "web.fetch"));
+        assert!(namespaces.is_enabled("guest", "web.search.images"));
+
+        let fs = ["fs".to_string()];
+        assert!(!namespaces.is_enabled_with("admin", "fs.write", None, Some(&fs[..])));
+        let web = ["web.*".to_string()];
+        assert!(namespaces.is_enabled_with("guest", "web.fetch", Some(&web[..]), None));
+
+        namespaces.reset("admin");
+        assert!(!namespaces.is_enabled("admin", "shell.run"));
+    }
+}
@@ -0,0 +1,638 @@
+//! Tools from OpenAPI specs.
+//!
+//! Most HTTP APIs already describe their operations in an OpenAPI 3
+//! document. [`load`] reads one, from a URL or a file, in JSON or, with the
+//! `yaml` feature, YAML, and turns every operation into an [`OpenApiTool`]
+//! that calls it over HTTP. The tool's input schema is built from the spec:
+//! one property per path, query and header parameter, plus `body` for a
+//! JSON request body, with `#/components/...` references resolved.
+//! [`OpenApiLoader`] adds a base URL override and auth headers, and
+//! registers the tools in bulk.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reqwest::{Method, Url};
+use serde_json::{json, Map, Value};
+
+use crate::error::{AgentError, Result};
+use crate::tools::{Tool, ToolCatalog, ToolRegistry};
+use crate::types::{ToolRequest, ToolResponse, ToolSpec};
+
+/// Metadata key holding the HTTP status of an operation's response
+pub const HTTP_STATUS_KEY: &str = "http_status";
+/// Bytes of a response body returned by default
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 256 * 1024;
+/// Longest tool name providers accept
+pub const MAX_TOOL_NAME_LEN: usize = 64;
+
+/// Argument holding the JSON request body of an operation
+const BODY_ARGUMENT: &str = "body";
+/// Nesting depth at which `$ref`s stop being resolved, for recursive schemas
+const MAX_REF_DEPTH: usize = 8;
+
+const METHODS: [&str; 8] = [
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// Loads the OpenAPI spec at `source`, a URL or file path, as tools
+pub async fn load(source: &str) -> Result<Vec<OpenApiTool>> {
+    OpenApiLoader::new(source).load().await
+}
+
+/// Where an operation parameter goes in the request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Location {
+    Path,
+    Query,
+    Header,
+}
+
+#[derive(Debug, Clone)]
+struct Parameter {
+    name: String,
+    location: Location,
+}
+
+/// Settings shared by the tools of one spec
+struct Endpoint {
+    client: reqwest::Client,
+    base_url: Url,
+    headers: HashMap<String, String>,
+    max_response: usize,
+}
+
+/// Tool calling one OpenAPI operation
+pub struct OpenApiTool {
+    spec: ToolSpec,
+    method: Method,
+    path: String,
+    parameters: Vec<Parameter>,
+    endpoint: Arc<Endpoint>,
+}
+
+impl OpenApiTool {
+    /// Returns the HTTP method and path template of the operation
+    pub fn operation(&self) -> (&Method, &str) {
+        (&self.method, &self.path)
+    }
+
+    /// Builds the URL of a call, filling path parameters and the query
+    fn url(&self, arguments: &HashMap<String, Value>) -> Result<Url> {
+        let mut url = self.endpoint.base_url.clone();
+        {
+            let mut segments = url.path_segments_mut().map_err(|_| {
+                AgentError::ConfigError(format!("Invalid base URL: {}", self.endpoint.base_url))
+            })?;
+            segments.pop_if_empty();
+            for segment in self.path.split('/').filter(|s| !s.is_empty()) {
+                let mut segment = segment.to_string();
+                for parameter in self.in_location(Location::Path) {
+                    let placeholder = format!("{{{}}}", parameter.name);
+                    if segment.contains(&placeholder) {
+                        let value = arguments.get(&parameter.name).ok_or_else(|| {
+                            AgentError::ToolError(format!("missing '{}'", parameter.name))
+                        })?;
+                        segment = segment.replace(&placeholder, &plain(value));
+                    }
+                }
+                segments.push(&segment);
+            }
+        }
+        {
+            let mut query = url.query_pairs_mut();
+            for parameter in self.in_location(Location::Query) {
+                if let Some(value) = arguments.get(&parameter.name) {
+                    query.append_pair(&parameter.name, &plain(value));
+                }
+            }
+        }
+        if url.query() == Some("") {
+            url.set_query(None);
+        }
+        Ok(url)
+    }
+
+    fn in_location(&self, location: Location) -> impl Iterator<Item = &Parameter> {
+        self.parameters
+            .iter()
+            .filter(move |parameter| parameter.location == location)
+    }
+}
+
+/// Renders an argument for a URL or header: strings as they are, arrays
+/// comma-separated, anything else as JSON
+fn plain(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Array(items) => items.iter().map(plain).collect::<Vec<_>>().join(","),
+        other => other.to_string(),
+    }
+}
+
+#[async_trait]
+impl Tool for OpenApiTool {
+    fn spec(&self) -> ToolSpec {
+        self.spec.clone()
+    }
+
+    async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse> {
+        let url = self.url(&req.arguments)?;
+        let mut request = self.endpoint.client.request(self.method.clone(), url);
+        for (name, value) in &self.endpoint.headers {
+            request = request.header(name, value);
+        }
+        for parameter in self.in_location(Location::Header) {
+            if let Some(value) = req.arguments.get(&parameter.name) {
+                request = request.header(&parameter.name, plain(value));
+            }
+        }
+        if let Some(body) = req.arguments.get(BODY_ARGUMENT) {
+            request = request.json(body);
+        }
+
+        let response = request.send().await.map_err(|e| {
+            AgentError::ToolError(format!("{} request failed: {}", self.spec.name, e))
+        })?;
+        let status = response.status();
+        let text = read_capped(response, self.endpoint.max_response)
+            .await
+            .map_err(|e| AgentError::ToolError(format!("{} response: {}", self.spec.name, e)))?;
+        if !status.is_success() {
+            return Err(AgentError::ToolError(format!(
+                "{} returned {}: {}",
+                self.spec.name, status, text
+            )));
+        }
+        Ok(ToolResponse {
+            content: text,
+            metadata: Some(HashMap::from([(
+                HTTP_STATUS_KEY.to_string(),
+                status.as_u16().to_string(),
+            )])),
+        })
+    }
+}
+
+/// Reads at most `max` bytes of a response body, stopping there rather
+/// than downloading the rest
+async fn read_capped(mut response: reqwest::Response, max: usize) -> reqwest::Result<String> {
+    let mut body = Vec::new();
+    let mut truncated = false;
+    while let Some(chunk) = response.chunk().await? {
+        let room = max - body.len();
+        if chunk.len() > room {
+            body.extend_from_slice(&chunk[..room]);
+            truncated = true;
+            break;
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let mut text = String::from_utf8_lossy(&body).into_owned();
+    if truncated {
+        text.push_str(&format!("\n[truncated after {} bytes]", max));
+    }
+    Ok(text)
+}
+
+/// Loads OpenAPI specs as tools, with settings for the calls they make
+pub struct OpenApiLoader {
+    source: String,
+    base_url: Option<String>,
+    headers: HashMap<String, String>,
+    max_response: usize,
+    client: reqwest::Client,
+}
+
+impl OpenApiLoader {
+    /// Loads the spec at `source`, a URL or file path
+    pub fn new(source: impl Into<String>) -> Self {
+        Self {
+            source: source.into(),
+            base_url: None,
+            headers: HashMap::new(),
+            max_response: DEFAULT_MAX_RESPONSE_BYTES,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Calls the API at `url` instead of the spec's first server
+    pub fn with_base_url(mut self, url: impl Into<String>) -> Self {
+        self.base_url = Some(url.into());
+        self
+    }
+
+    /// Sends a header with every call, e.g. `Authorization`
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    /// Truncates response bodies beyond `bytes`
+    pub fn with_max_response(mut self, bytes: usize) -> Self {
+        self.max_response = bytes;
+        self
+    }
+
+    /// Reads the spec and returns a tool per operation
+    pub async fn load(&self) -> Result<Vec<OpenApiTool>> {
+        let is_url = self.source.starts_with("http://") || self.source.starts_with("https://");
+        let text = if is_url {
+            self.client
+                .get(&self.source)
+                .send()
+                .await
+                .and_then(|r| r.error_for_status())
+                .map_err(|e| AgentError::ConfigError(format!("OpenAPI spec: {}", e)))?
+                .text()
+                .await
+                .map_err(|e| AgentError::ConfigError(format!("OpenAPI spec: {}", e)))?
+        } else {
+            tokio::fs::read_to_string(&self.source).await?
+        };
+        self.from_spec(&parse(&text)?, is_url.then_some(self.source.as_str()))
+    }
+
+    /// Loads the spec and registers its tools with `registry`, returning
+    /// how many were registered. Nothing is registered if any tool is
+    /// invalid or taken.
+    pub async fn register(&self, registry: &dyn ToolRegistry) -> Result<usize> {
+        let staged = ToolCatalog::new();
+        for tool in self.load().await? {
+            staged.register(Box::new(tool))?;
+        }
+        registry.merge(&staged)
+    }
+
+    /// Turns a parsed spec into tools; relative server URLs resolve against
+    /// `spec_url`
+    pub fn from_spec(&self, spec: &Value, spec_url: Option<&str>) -> Result<Vec<OpenApiTool>> {
+        let server = spec["servers"][0]["url"].as_str();
+        let base_url = match (&self.base_url, server) {
+            (Some(url), _) => Url::parse(url).ok(),
+            (None, Some(server)) => Url::parse(server).ok().or_else(|| {
+                spec_url
+                    .and_then(|spec_url| Url::parse(spec_url).ok())
+                    .and_then(|spec_url| spec_url.join(server).ok())
+            }),
+            (None, None) => spec_url
+                .and_then(|spec_url| Url::parse(spec_url).ok())
+                .and_then(|spec_url| spec_url.join("/").ok()),
+        }
+        .ok_or_else(|| {
+            AgentError::ConfigError(
+                "OpenAPI spec has no absolute server URL; set one with with_base_url".to_string(),
+            )
+        })?;
+        let endpoint = Arc::new(Endpoint {
+            client: self.client.clone(),
+            base_url,
+            headers: self.headers.clone(),
+            max_response: self.max_response,
+        });
+
+        let mut tools = Vec::new();
+        let mut names = HashSet::new();
+        let paths = spec["paths"].as_object().into_iter().flatten();
+        for (path, item) in paths {
+            for method in METHODS {
+                let Some(operation) = item.get(method) else {
+                    continue;
+                };
+                let shared = item["parameters"].as_array().into_iter().flatten();
+                let own = operation["parameters"].as_array().into_iter().flatten();
+                let mut tool = operation_tool(
+                    spec,
+                    path,
+                    method,
+                    operation,
+                    shared.chain(own),
+                    Arc::clone(&endpoint),
+                )?;
+                tool.spec.name = unique_name(&tool.spec.name, &mut names);
+                tools.push(tool);
+            }
+        }
+        Ok(tools)
+    }
+}
+
+fn operation_tool<'a>(
+    spec: &Value,
+    path: &str,
+    method: &str,
+    operation: &Value,
+    parameters: impl Iterator<Item = &'a Value>,
+    endpoint: Arc<Endpoint>,
+) -> Result<OpenApiTool> {
+    let name = operation["operationId"]
+        .as_str()
+        .map(tool_name)
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| tool_name(&format!("{}_{}", method, path)));
+    let description = ["summary", "description"]
+        .iter()
+        .find_map(|key| operation[key].as_str())
+        .map_or_else(
+            || format!("{} {}", method.to_uppercase(), path),
+            str::to_string,
+        );
+
+    let mut properties = Map::new();
+    let mut required: Vec<String> = Vec::new();
+    let mut bound: Vec<Parameter> = Vec::new();
+    for parameter in parameters {
+        let parameter = resolve(spec, parameter, 0);
+        let (Some(param_name), Some(location)) = (
+            parameter["name"].as_str(),
+            match parameter["in"].as_str() {
+                Some("path") => Some(Location::Path),
+                Some("query") => Some(Location::Query),
+                Some("header") => Some(Location::Header),
+                _ => None,
+            },
+        ) else {
+            continue;
+        };
+        let mut schema = resolve(spec, &parameter["schema"], 0);
+        if schema.is_null() {
+            schema = json!({"type": "string"});
+        }
+        if let (Some(fields), Some(text)) =
+            (schema.as_object_mut(), parameter["description"].as_str())
+        {
+            fields.insert("description".to_string(), json!(text));
+        }
+        // A later definition of a parameter overrides the path item's
+        bound.retain(|p| p.name != param_name);
+        properties.insert(param_name.to_string(), schema);
+        if location == Location::Path || parameter["required"].as_bool() == Some(true) {
+            required.retain(|r| r != param_name);
+            required.push(param_name.to_string());
+        }
+        bound.push(Parameter {
+            name: param_name.to_string(),
+            location,
+        });
+    }
+
+    let body = resolve(spec, &operation["requestBody"], 0);
+    let body_schema = &body["content"]["application/json"]["schema"];
+    if !body_schema.is_null() {
+        properties.insert(BODY_ARGUMENT.to_string(), resolve(spec, body_schema, 0));
+        if body["required"].as_bool() == Some(true) {
+            required.push(BODY_ARGUMENT.to_string());
+        }
+    }
+
+    Ok(OpenApiTool {
+        spec: ToolSpec {
+            name,
+            description,
+            input_schema: json!({
+                "type": "object",
+                "properties": properties,
+                "required": required,
+            }),
+            examples: None,
+            output_schema: None,
+        },
+        method: Method::from_bytes(method.to_uppercase().as_bytes())
+            .map_err(|e| AgentError::ConfigError(format!("OpenAPI method {}: {}", method, e)))?,
+        path: path.to_string(),
+        parameters: bound,
+        endpoint,
+    })
+}
+
+/// Parses a spec as JSON or YAML
+#[cfg(feature = "yaml")]
+fn parse(text: &str) -> Result<Value> {
+    serde_json::from_str(text).or_else(|_| {
+        serde_yaml::from_str(text).map_err(|e| {
+            AgentError::ConfigError(format!("OpenAPI spec is neither JSON nor YAML: {}", e))
+        })
+    })
+}
+
+/// Parses a spec as JSON; YAML needs the `yaml` feature
+#[cfg(not(feature = "yaml"))]
+fn parse(text: &str) -> Result<Value> {
+    serde_json::from_str(text).map_err(|e| {
+        AgentError::ConfigError(format!(
+            "OpenAPI spec is not JSON ({}); enable the yaml feature for YAML specs",
+            e
+        ))
+    })
+}
+
+/// Replaces local `$ref`s in `value` with what they point to
+fn resolve(spec: &Value, value: &Value, depth: usize) -> Value {
+    match value {
+        Value::Object(fields) => {
+            if let Some(reference) = fields.get("$ref").and_then(Value::as_str) {
+                let target = reference
+                    .strip_prefix('#')
+                    .and_then(|pointer| spec.pointer(pointer));
+                return match target {
+                    Some(target) if depth < MAX_REF_DEPTH => resolve(spec, target, depth + 1),
+                    // Unresolvable or too deep: accept anything rather than fail
+                    _ => json!({}),
+                };
+            }
+            Value::Object(
+                fields
+                    .iter()
+                    .map(|(key, value)| (key.clone(), resolve(spec, value, depth)))
+                    .collect(),
+            )
+        }
+        Value::Array(items) => {
+            Value::Array(items.iter().map(|v| resolve(spec, v, depth)).collect())
+        }
+        other => other.clone(),
+    }
+}
+
+/// Makes a tool name of letters, digits and underscores, as providers
+/// require
+fn tool_name(raw: &str) -> String {
+    let mut name = String::new();
+    for c in raw.chars() {
+        if c.is_ascii_alphanumeric() {
+            name.push(c);
+        } else if !name.is_empty() && !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    let name = name.trim_end_matches('_');
+    truncate_name(name, MAX_TOOL_NAME_LEN).to_string()
+}
+
+/// Cuts an ASCII tool name to at most `max` bytes
+fn truncate_name(name: &str, max: usize) -> &str {
+    &name[..name.len().min(max)]
+}
+
+/// Returns `name`, or `name` with the first free `_2`, `_3`, ... suffix,
+/// kept within [`MAX_TOOL_NAME_LEN`], and marks it taken
+fn unique_name(name: &str, taken: &mut HashSet<String>) -> String {
+    let mut candidate = name.to_string();
+    let mut n = 1;
+    while !taken.insert(candidate.clone()) {
+        n += 1;
+        let suffix = format!("_{}", n);
+        let base = truncate_name(name, MAX_TOOL_NAME_LEN - suffix.len());
+        candidate = format!("{}{}", base, suffix);
+    }
+    candidate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn petstore(server: &str) -> Value {
+        json!({
+            "openapi": "3.0.0",
+            "servers": [{"url": server}],
+            "paths": {
+                "/pets/{petId}": {
+                    "parameters": [{"name": "petId", "in": "path", "schema": {"type": "string"}}],
+                    "get": {
+                        "operationId": "getPet",
+                        "summary": "Finds a pet",
+                        "parameters": [{"$ref": "#/components/parameters/Verbose"}]
+                    }
+                },
+                "/pets": {
+                    "post": {
+                        "requestBody": {
+                            "required": true,
+                            "content": {"application/json": {"schema": {"$ref": "#/components/schemas/Pet"}}}
+                        }
+                    }
+                }
+            },
+            "components": {
+                "parameters": {"Verbose": {"name": "verbose", "in": "query", "schema": {"type": "boolean"}}},
+                "schemas": {"Pet": {"type": "object", "properties": {"name": {"type": "string"}}}}
+            }
+        })
+    }
+
+    #[tokio::test]
+    async fn test_operations_become_http_tools() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = format!("http://{}/v1", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = vec![0; 4096];
+            let read = socket.read(&mut request).await.unwrap();
+            let request = String::from_utf8_lossy(&request[..read]);
+            let line = request.lines().next().unwrap_or_default().to_string();
+            let reply = format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                line.len(),
+                line
+            );
+            socket.write_all(reply.as_bytes()).await.unwrap();
+        });
+
+        let loader = OpenApiLoader::new("inline").with_header("authorization", "Bearer t");
+        let tools = loader.from_spec(&petstore(&server), None).unwrap();
+        let find = |name: &str| tools.iter().find(|tool| tool.spec().name == name).unwrap();
+        let get_pet = find("getPet").spec();
+        assert_eq!(get_pet.description, "Finds a pet");
+        assert_eq!(get_pet.input_schema["required"], json!(["petId"]));
+        assert_eq!(
+            get_pet.input_schema["properties"]["verbose"]["type"],
+            "boolean"
+        );
+        let post_pets = find("post_pets").spec();
+        assert_eq!(
+            post_pets.input_schema["properties"]["body"]["properties"]["name"]["type"],
+            "string"
+        );
+        assert_eq!(post_pets.input_schema["required"], json!(["body"]));
+
+        let reply = find("getPet")
+            .invoke(ToolRequest {
+                session_id: "s".to_string(),
+                arguments: HashMap::from([
+                    ("petId".to_string(), json!("rex 1")),
+                    ("verbose".to_string(), json!(true)),
+                ]),
+                tenant_id: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(reply.content, "GET /v1/pets/rex%201?verbose=true HTTP/1.1");
+        assert_eq!(reply.metadata.unwrap()[HTTP_STATUS_KEY], "200");
+
+        let relative = loader.from_spec(&petstore("/api"), None);
+        assert!(matches!(relative, Err(AgentError::ConfigError(_))));
+    }
+
+    #[test]
+    fn test_names_are_capped_and_unique() {
+        let long = "x".repeat(80);
+        let spec = json!({
+            "servers": [{"url": "http://localhost"}],
+            "paths": {
+                "/a": {"get": {"operationId": long}, "post": {"operationId": long}},
+                "/b": {"get": {"operationId": "list-pets"}, "put": {"operationId": "list_pets"}}
+            }
+        });
+        let tools = OpenApiLoader::new("inline").from_spec(&spec, None).unwrap();
+        let names: Vec<String> = tools.iter().map(|tool| tool.spec().name).collect();
+        assert_eq!(names[0], "x".repeat(64));
+        assert_eq!(names[1], format!("{}_2", "x".repeat(62)));
+        assert_eq!(names[2..], ["list_pets", "list_pets_2"]);
+    }
+
+    #[tokio::test]
+    async fn test_response_bodies_are_capped() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            // Read up to the end of the request headers; a GET has no body
+            let (mut request, mut buf) = (Vec::new(), [0; 1024]);
+            while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                let read = socket.read(&mut buf).await.unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..read]);
+            }
+            let body = "y".repeat(100);
+            let reply = format!(
+                "HTTP/1.1 200 OK\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            socket.write_all(reply.as_bytes()).await.unwrap();
+        });
+
+        let spec = json!({
+            "servers": [{"url": server}],
+            "paths": {"/big": {"get": {"operationId": "big"}}}
+        });
+        let tools = OpenApiLoader::new("inline")
+            .with_max_response(10)
+            .from_spec(&spec, None)
+            .unwrap();
+        let reply = tools[0]
+            .invoke(ToolRequest {
+                session_id: "s".to_string(),
+                arguments: HashMap::new(),
+                tenant_id: None,
+            })
+            .await
+            .unwrap();
+        assert_eq!(reply.content, "yyyyyyyyyy\n[truncated after 10 bytes]");
+    }
+}
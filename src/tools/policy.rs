@@ -0,0 +1,361 @@
+//! Permissions for tool calls.
+//!
+//! An agent facing untrusted users shouldn't let every user reach every
+//! tool with any arguments. A [`ToolPolicy`] is checked before each call
+//! `Agent::invoke_tool` makes: [`ToolRule`]s allow or deny tools, by name or
+//! by tag, for everyone or for given users and sessions, and
+//! [`ArgumentConstraint`]s bound what the allowed calls may pass, such as a
+//! filesystem tool kept inside one directory. Denied calls fail with
+//! [`AgentError::ToolDenied`] without running.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+use regex::Regex;
+use serde_json::Value;
+
+use crate::error::{AgentError, Result};
+use crate::tools::namespace::in_namespace;
+
+/// Tools a rule or constraint applies to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolTarget {
+    Any,
+    Name(String),
+    /// Tools given this tag with [`ToolPolicy::with_tag`]
+    Tag(String),
+    /// Tools named with this dotted prefix, e.g. `fs` for `fs.read`
+    Namespace(String),
+}
+
+impl ToolTarget {
+    pub fn name(name: impl Into<String>) -> Self {
+        Self::Name(name.into())
+    }
+
+    pub fn tag(tag: impl Into<String>) -> Self {
+        Self::Tag(tag.into())
+    }
+
+    pub fn namespace(namespace: impl Into<String>) -> Self {
+        Self::Namespace(namespace.into())
+    }
+}
+
+/// Whether a rule lets matching calls through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    Allow,
+    Deny,
+}
+
+/// Allows or denies tools, for everyone unless limited to some users or
+/// sessions
+#[derive(Debug, Clone)]
+pub struct ToolRule {
+    effect: Effect,
+    target: ToolTarget,
+    users: Option<HashSet<String>>,
+    sessions: Option<HashSet<String>>,
+}
+
+impl ToolRule {
+    pub fn allow(target: ToolTarget) -> Self {
+        Self::new(Effect::Allow, target)
+    }
+
+    pub fn deny(target: ToolTarget) -> Self {
+        Self::new(Effect::Deny, target)
+    }
+
+    fn new(effect: Effect, target: ToolTarget) -> Self {
+        Self {
+            effect,
+            target,
+            users: None,
+            sessions: None,
+        }
+    }
+
+    /// Applies the rule to calls of `user_id`; call again to add users
+    pub fn for_user(mut self, user_id: impl Into<String>) -> Self {
+        self.users
+            .get_or_insert_with(HashSet::new)
+            .insert(user_id.into());
+        self
+    }
+
+    /// Applies the rule to calls in `session_id`; call again to add sessions
+    pub fn for_session(mut self, session_id: impl Into<String>) -> Self {
+        self.sessions
+            .get_or_insert_with(HashSet::new)
+            .insert(session_id.into());
+        self
+    }
+}
+
+/// Bounds the value of one argument
+#[derive(Debug, Clone)]
+pub enum ArgumentConstraint {
+    /// A path inside this directory. Paths are resolved against it and
+    /// normalized lexically, so `..` can't climb out. The check never
+    /// touches the disk, so a symlink inside the directory can still lead
+    /// outside; confine file access with an
+    /// [`FsSandbox`](crate::tools::FsSandbox) too.
+    WithinDirectory(PathBuf),
+    /// A string the regex matches
+    Matches(Regex),
+    /// One of these values
+    OneOf(Vec<Value>),
+}
+
+impl ArgumentConstraint {
+    pub fn within_directory(dir: impl Into<PathBuf>) -> Self {
+        Self::WithinDirectory(dir.into())
+    }
+
+    /// Fails with [`AgentError::ConfigError`] if `pattern` isn't a valid
+    /// regex
+    pub fn matches(pattern: &str) -> Result<Self> {
+        Regex::new(pattern)
+            .map(Self::Matches)
+            .map_err(|e| AgentError::ConfigError(format!("Invalid argument pattern: {}", e)))
+    }
+
+    fn admits(&self, value: &Value) -> bool {
+        match self {
+            Self::WithinDirectory(dir) => value.as_str().is_some_and(|path| {
+                normalize(&dir.join(path))
+                    .is_some_and(|path| normalize(dir).is_some_and(|dir| path.starts_with(dir)))
+            }),
+            Self::Matches(regex) => value.as_str().is_some_and(|text| regex.is_match(text)),
+            Self::OneOf(allowed) => allowed.contains(value),
+        }
+    }
+}
+
+/// Removes `.` and `..` components, or returns `None` if `..` climbs above
+/// the root
+pub(crate) fn normalize(path: &Path) -> Option<PathBuf> {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normal.pop() {
+                    return None;
+                }
+            }
+            other => normal.push(other),
+        }
+    }
+    Some(normal)
+}
+
+/// One tool call a policy is asked about
+#[derive(Debug, Clone, Copy)]
+pub struct ToolCallContext<'a> {
+    pub session_id: &'a str,
+    /// Owner of the session, see `SessionMemory::bind_user`
+    pub user_id: Option<&'a str>,
+    pub tool: &'a str,
+    pub arguments: &'a HashMap<String, Value>,
+}
+
+/// Which tools a caller may use, and with what arguments.
+///
+/// A call is denied if any matching rule denies it. Otherwise it is
+/// allowed if a matching rule allows it or, when none matches, if the
+/// policy allows by default, which it does unless built with
+/// [`deny_by_default`](ToolPolicy::deny_by_default). Allowed calls must
+/// then meet every constraint on their tool.
+#[derive(Debug, Clone)]
+pub struct ToolPolicy {
+    default: Effect,
+    rules: Vec<ToolRule>,
+    // Tag -> tools carrying it
+    tags: HashMap<String, HashSet<String>>,
+    constraints: Vec<(ToolTarget, String, ArgumentConstraint)>,
+}
+
+impl Default for ToolPolicy {
+    fn default() -> Self {
+        Self {
+            default: Effect::Allow,
+            rules: Vec::new(),
+            tags: HashMap::new(),
+            constraints: Vec::new(),
+        }
+    }
+}
+
+impl ToolPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Denies calls no rule allows
+    pub fn deny_by_default(mut self) -> Self {
+        self.default = Effect::Deny;
+        self
+    }
+
+    pub fn with_rule(mut self, rule: ToolRule) -> Self {
+        self.rules.push(rule);
+        self
+    }
+
+    /// Tags `tools` so rules and constraints can target them together
+    pub fn with_tag<I, S>(mut self, tag: impl Into<String>, tools: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.tags
+            .entry(tag.into())
+            .or_default()
+            .extend(tools.into_iter().map(Into::into));
+        self
+    }
+
+    /// Requires argument `argument` of the targeted tools, when passed, to
+    /// meet `constraint`
+    pub fn with_constraint(
+        mut self,
+        target: ToolTarget,
+        argument: impl Into<String>,
+        constraint: ArgumentConstraint,
+    ) -> Self {
+        self.constraints.push((target, argument.into(), constraint));
+        self
+    }
+
+    /// Checks `call`, failing with [`AgentError::ToolDenied`] if the policy
+    /// doesn't allow it
+    pub fn check(&self, call: &ToolCallContext<'_>) -> Result<()> {
+        let deny = |reason: String| Err(AgentError::ToolDenied(reason));
+
+        let matching: Vec<Effect> = self
+            .applicable(call.session_id, call.user_id)
+            .filter(|rule| self.targets(&rule.target, call.tool))
+            .map(|rule| rule.effect)
+            .collect();
+        let effect = if matching.contains(&Effect::Deny) {
+            Effect::Deny
+        } else if matching.contains(&Effect::Allow) {
+            Effect::Allow
+        } else {
+            self.default
+        };
+        if effect == Effect::Deny {
+            return deny(format!("{} is not allowed here", call.tool));
+        }
+
+        for (target, argument, constraint) in &self.constraints {
+            if !self.targets(target, call.tool) {
+                continue;
+            }
+            if let Some(value) = call.arguments.get(argument) {
+                if !constraint.admits(value) {
+                    return deny(format!("{} rejects `{}` = {}", call.tool, argument, value));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns true if no call in `session_id` by `user_id` can be denied,
+    /// whatever the tool and arguments.
+    ///
+    /// Callers that run tools the policy can't see one call at a time, such
+    /// as CodeMode scripts, should only run when this holds.
+    pub fn allows_everything(&self, session_id: &str, user_id: Option<&str>) -> bool {
+        if !self.constraints.is_empty() {
+            return false;
+        }
+        let mut allows_any = self.default == Effect::Allow;
+        for rule in self.applicable(session_id, user_id) {
+            match rule.effect {
+                Effect::Deny => return false,
+                Effect::Allow => allows_any |= matches!(rule.target, ToolTarget::Any),
+            }
+        }
+        allows_any
+    }
+
+    /// The rules that apply to calls in `session_id` by `user_id`
+    fn applicable<'a>(
+        &'a self,
+        session_id: &'a str,
+        user_id: Option<&'a str>,
+    ) -> impl Iterator<Item = &'a ToolRule> + 'a {
+        self.rules
+            .iter()
+            .filter(move |rule| {
+                rule.users
+                    .as_ref()
+                    .is_none_or(|users| user_id.is_some_and(|user| users.contains(user)))
+            })
+            .filter(move |rule| {
+                rule.sessions
+                    .as_ref()
+                    .is_none_or(|sessions| sessions.contains(session_id))
+            })
+    }
+
+    fn targets(&self, target: &ToolTarget, tool: &str) -> bool {
+        match target {
+            ToolTarget::Any => true,
+            ToolTarget::Name(name) => name == tool,
+            ToolTarget::Tag(tag) => self.tags.get(tag).is_some_and(|tools| tools.contains(tool)),
+            ToolTarget::Namespace(namespace) => in_namespace(tool, namespace),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_rules_and_constraints_gate_calls() {
+        let policy = ToolPolicy::new()
+            .deny_by_default()
+            .with_tag("filesystem", ["read_file", "write_file"])
+            .with_rule(ToolRule::allow(ToolTarget::tag("filesystem")))
+            .with_rule(ToolRule::deny(ToolTarget::name("write_file")).for_user("guest"))
+            .with_rule(ToolRule::allow(ToolTarget::namespace("math")))
+            .with_constraint(
+                ToolTarget::tag("filesystem"),
+                "path",
+                ArgumentConstraint::within_directory("/srv/data"),
+            );
+        let check = |user: Option<&str>, tool: &str, path: &str| {
+            let arguments = HashMap::from([("path".to_string(), json!(path))]);
+            policy.check(&ToolCallContext {
+                session_id: "s",
+                user_id: user,
+                tool,
+                arguments: &arguments,
+            })
+        };
+
+        assert!(check(Some("guest"), "read_file", "reports/q3.csv").is_ok());
+        assert!(check(Some("admin"), "write_file", "/srv/data/out.txt").is_ok());
+        assert!(matches!(
+            check(Some("guest"), "write_file", "out.txt"),
+            Err(AgentError::ToolDenied(_))
+        ));
+        assert!(check(None, "shell", "").is_err());
+        assert!(check(None, "math.add", "").is_ok());
+        assert!(check(Some("admin"), "read_file", "../../etc/passwd").is_err());
+        assert!(check(Some("admin"), "read_file", "/etc/passwd").is_err());
+        assert!(!policy.allows_everything("s", Some("admin")));
+
+        let policy = ToolPolicy::new()
+            .with_rule(ToolRule::deny(ToolTarget::name("shell")).for_user("guest"));
+        assert!(policy.allows_everything("s", Some("admin")));
+        assert!(!policy.allows_everything("s", Some("guest")));
+    }
+}
@@ -0,0 +1,282 @@
+//! Post-processing of oversized tool outputs.
+//!
+//! A single verbose API response can crowd the rest of the conversation out
+//! of the context window. A [`ToolOutputProcessor`] caps each tool result at a
+//! token budget, either by cutting it down or by asking a model to summarize
+//! it, before the result is fed back to the model.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::error::Result;
+use crate::models::LLM;
+use crate::types::{Message, Role, ToolResponse};
+
+/// Metadata key recording how an output was reduced (`truncated` or `summarized`)
+pub const OUTPUT_REDUCED_KEY: &str = "output_reduced";
+/// Metadata key holding the original size in bytes of a reduced output
+pub const ORIGINAL_SIZE_KEY: &str = "original_size";
+
+/// Token budget applied unless configured otherwise
+pub const DEFAULT_MAX_TOOL_TOKENS: usize = 1024;
+
+// Simple token estimation (4 chars ≈ 1 token), as in the agent's context budget
+const BYTES_PER_TOKEN: usize = 4;
+
+const SUMMARY_INSTRUCTIONS: &str = "Summarize the tool output below so it can replace \
+the original in a conversation. Keep identifiers, numbers, errors, and any values a follow-up \
+step may need. Write plain text without preamble.";
+
+/// Which part of an oversized output survives truncation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TruncationStrategy {
+    /// Keep the beginning
+    Head,
+    /// Keep the end, where logs and stack traces usually carry the outcome
+    Tail,
+    /// Keep the beginning and the end, dropping the middle
+    #[default]
+    HeadTail,
+}
+
+enum Reduction {
+    Truncate(TruncationStrategy),
+    Summarize(Arc<dyn LLM>),
+}
+
+/// Caps tool outputs at a token budget
+pub struct ToolOutputProcessor {
+    max_tokens: usize,
+    reduction: Reduction,
+}
+
+impl Default for ToolOutputProcessor {
+    fn default() -> Self {
+        Self::truncate(DEFAULT_MAX_TOOL_TOKENS)
+    }
+}
+
+impl ToolOutputProcessor {
+    /// Truncates outputs above `max_tokens`, keeping their head and tail
+    pub fn truncate(max_tokens: usize) -> Self {
+        Self {
+            max_tokens,
+            reduction: Reduction::Truncate(TruncationStrategy::default()),
+        }
+    }
+
+    /// Summarizes outputs above `max_tokens` with `model`.
+    ///
+    /// Falls back to truncation if the model fails, and truncates summaries
+    /// that still exceed the budget.
+    pub fn summarize(model: Arc<dyn LLM>, max_tokens: usize) -> Self {
+        Self {
+            max_tokens,
+            reduction: Reduction::Summarize(model),
+        }
+    }
+
+    /// Sets which part of an output truncation keeps
+    pub fn with_strategy(mut self, strategy: TruncationStrategy) -> Self {
+        if let Reduction::Truncate(current) = &mut self.reduction {
+            *current = strategy;
+        }
+        self
+    }
+
+    /// Returns the token budget per output
+    pub fn max_tokens(&self) -> usize {
+        self.max_tokens
+    }
+
+    /// Reduces `response` to the token budget; smaller responses are returned unchanged
+    pub async fn process(
+        &self,
+        tool_name: &str,
+        mut response: ToolResponse,
+    ) -> Result<ToolResponse> {
+        let size = response.content.len();
+        let max_bytes = self.max_tokens.saturating_mul(BYTES_PER_TOKEN);
+        if size <= max_bytes {
+            return Ok(response);
+        }
+
+        let (content, reduced) = match &self.reduction {
+            Reduction::Truncate(strategy) => (
+                truncate(&response.content, max_bytes, *strategy),
+                "truncated",
+            ),
+            Reduction::Summarize(model) => {
+                match summarize(model.as_ref(), tool_name, &response.content).await {
+                    Ok(summary) => (
+                        truncate(&summary, max_bytes, TruncationStrategy::Head),
+                        "summarized",
+                    ),
+                    Err(e) => {
+                        tracing::warn!("Summarizing {} output failed: {}", tool_name, e);
+                        (
+                            truncate(&response.content, max_bytes, TruncationStrategy::default()),
+                            "truncated",
+                        )
+                    }
+                }
+            }
+        };
+
+        response.content = content;
+        let metadata = response.metadata.get_or_insert_with(HashMap::new);
+        metadata.insert(OUTPUT_REDUCED_KEY.to_string(), reduced.to_string());
+        metadata.insert(ORIGINAL_SIZE_KEY.to_string(), size.to_string());
+        Ok(response)
+    }
+}
+
+async fn summarize(model: &dyn LLM, tool_name: &str, content: &str) -> Result<String> {
+    let messages = vec![
+        Message {
+            role: Role::System,
+            content: SUMMARY_INSTRUCTIONS.to_string(),
+            metadata: None,
+        },
+        Message {
+            role: Role::User,
+            content: format!("Output of {}:\n{}", tool_name, content),
+            metadata: None,
+        },
+    ];
+    let response = model.generate(messages, None).await?;
+    Ok(response.content.trim().to_string())
+}
+
+/// Cuts `content` to roughly `max_bytes`, marking how much was dropped
+fn truncate(content: &str, max_bytes: usize, strategy: TruncationStrategy) -> String {
+    if content.len() <= max_bytes {
+        return content.to_string();
+    }
+
+    let (head, tail) = match strategy {
+        TruncationStrategy::Head => (max_bytes, 0),
+        TruncationStrategy::Tail => (0, max_bytes),
+        TruncationStrategy::HeadTail => (max_bytes / 2, max_bytes - max_bytes / 2),
+    };
+    let head_end = floor_char_boundary(content, head);
+    let tail_start = ceil_char_boundary(content, content.len() - tail);
+    let omitted = tail_start - head_end;
+
+    let mut out = String::with_capacity(max_bytes + 48);
+    out.push_str(&content[..head_end]);
+    if head_end > 0 {
+        out.push('\n');
+    }
+    out.push_str(&format!("[... {} bytes omitted ...]", omitted));
+    if tail_start < content.len() {
+        out.push('\n');
+    }
+    out.push_str(&content[tail_start..]);
+    out
+}
+
+fn floor_char_boundary(s: &str, mut index: usize) -> usize {
+    while !s.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+fn ceil_char_boundary(s: &str, mut index: usize) -> usize {
+    while !s.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::AgentError;
+    use crate::types::{File, GenerationResponse};
+    use async_trait::async_trait;
+
+    fn response(content: &str) -> ToolResponse {
+        ToolResponse {
+            content: content.to_string(),
+            metadata: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_truncation_strategies() {
+        let content = format!("{}{}", "a".repeat(40), "z".repeat(40));
+
+        let small = ToolOutputProcessor::truncate(100)
+            .process("t", response(&content))
+            .await
+            .unwrap();
+        assert_eq!(small.content, content);
+        assert!(small.metadata.is_none());
+
+        let both = ToolOutputProcessor::truncate(4)
+            .process("t", response(&content))
+            .await
+            .unwrap();
+        assert_eq!(
+            both.content,
+            "aaaaaaaa\n[... 64 bytes omitted ...]\nzzzzzzzz"
+        );
+        let metadata = both.metadata.unwrap();
+        assert_eq!(metadata[OUTPUT_REDUCED_KEY], "truncated");
+        assert_eq!(metadata[ORIGINAL_SIZE_KEY], "80");
+
+        let tail = ToolOutputProcessor::truncate(2)
+            .with_strategy(TruncationStrategy::Tail)
+            .process("t", response(&content))
+            .await
+            .unwrap();
+        assert_eq!(tail.content, "[... 72 bytes omitted ...]\nzzzzzzzz");
+    }
+
+    struct FixedLLM(Option<&'static str>);
+
+    #[async_trait]
+    impl LLM for FixedLLM {
+        async fn generate(
+            &self,
+            _messages: Vec<Message>,
+            _files: Option<Vec<File>>,
+        ) -> Result<GenerationResponse> {
+            match self.0 {
+                Some(content) => Ok(GenerationResponse {
+                    content: content.to_string(),
+                    metadata: None,
+                }),
+                None => Err(AgentError::ModelError("unavailable".to_string())),
+            }
+        }
+
+        fn model_name(&self) -> &str {
+            "fixed"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_summarization_with_fallback() {
+        let content = "x".repeat(100);
+
+        let summarized = ToolOutputProcessor::summarize(Arc::new(FixedLLM(Some("42 rows"))), 8)
+            .process("db.query", response(&content))
+            .await
+            .unwrap();
+        assert_eq!(summarized.content, "42 rows");
+        assert_eq!(
+            summarized.metadata.unwrap()[OUTPUT_REDUCED_KEY],
+            "summarized"
+        );
+
+        let fallback = ToolOutputProcessor::summarize(Arc::new(FixedLLM(None)), 8)
+            .process("db.query", response(&content))
+            .await
+            .unwrap();
+        assert!(fallback.content.contains("bytes omitted"));
+        assert_eq!(fallback.metadata.unwrap()[OUTPUT_REDUCED_KEY], "truncated");
+    }
+}
@@ -0,0 +1,347 @@
+//! Tool definitions formatted for provider APIs, computed once per catalog.
+//!
+//! Every provider wants the tool catalog in its own shape, and Gemini only
+//! accepts a subset of JSON schema, so `$ref`s must be inlined and some
+//! keywords dropped. For a large catalog that conversion is too slow to
+//! repeat on every request. [`ToolSchemaSnapshots`] keeps the payload
+//! formatted for each provider, keyed by the catalog's
+//! [`generation`](ToolRegistry::generation), which registering, replacing
+//! or removing a tool changes, so an unchanged catalog costs a lookup
+//! rather than a pass over every spec. Only a new generation, or a registry
+//! without one, is compared by [`catalog_hash`]. Snapshots can also persist
+//! payloads to a directory under that hash, so a freshly started process
+//! loads them instead of converting again.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use serde_json::{json, Map, Value};
+
+use crate::blob::content_hash;
+use crate::tools::ToolRegistry;
+use crate::types::ToolSpec;
+
+/// Deepest `$ref` nesting inlined for Gemini; deeper references, such as
+/// those of recursive types, become plain objects
+const MAX_REF_DEPTH: usize = 16;
+
+/// Schema keywords Gemini's function declarations reject
+const GEMINI_UNSUPPORTED: [&str; 5] = [
+    "$schema",
+    "$defs",
+    "definitions",
+    "additionalProperties",
+    "title",
+];
+
+/// Provider API whose tool format a payload follows
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ToolSchemaFormat {
+    /// Chat Completions `tools`: `{"type": "function", "function": {...}}`
+    OpenAi,
+    /// Messages API `tools`: `{"name", "description", "input_schema"}`
+    Anthropic,
+    /// `tools` holding one entry of `functionDeclarations`
+    Gemini,
+}
+
+impl ToolSchemaFormat {
+    /// Formats `specs` as this provider's `tools` array
+    pub fn format(self, specs: &[ToolSpec]) -> Value {
+        match self {
+            Self::OpenAi => specs
+                .iter()
+                .map(|spec| {
+                    json!({
+                        "type": "function",
+                        "function": {
+                            "name": spec.name,
+                            "description": spec.description,
+                            "parameters": spec.input_schema,
+                        }
+                    })
+                })
+                .collect(),
+            Self::Anthropic => specs
+                .iter()
+                .map(|spec| {
+                    json!({
+                        "name": spec.name,
+                        "description": spec.description,
+                        "input_schema": spec.input_schema,
+                    })
+                })
+                .collect(),
+            Self::Gemini => {
+                let declarations: Vec<Value> = specs
+                    .iter()
+                    .map(|spec| {
+                        json!({
+                            "name": spec.name,
+                            "description": spec.description,
+                            "parameters": gemini_schema(&spec.input_schema),
+                        })
+                    })
+                    .collect();
+                json!([{ "functionDeclarations": declarations }])
+            }
+        }
+    }
+}
+
+impl fmt::Display for ToolSchemaFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::OpenAi => "openai",
+            Self::Anthropic => "anthropic",
+            Self::Gemini => "gemini",
+        })
+    }
+}
+
+/// Returns a hash of `specs` that changes whenever a tool is added, removed,
+/// reordered or redefined
+pub fn catalog_hash(specs: &[ToolSpec]) -> String {
+    content_hash(&serde_json::to_vec(specs).unwrap_or_default())
+}
+
+/// Payload of one format, with the catalog it was formatted from
+struct Snapshot {
+    generation: Option<u64>,
+    hash: String,
+    payload: Arc<Value>,
+}
+
+/// Provider-formatted tool payloads of one catalog, recomputed when the
+/// catalog changes
+#[derive(Default)]
+pub struct ToolSchemaSnapshots {
+    payloads: parking_lot::RwLock<HashMap<ToolSchemaFormat, Snapshot>>,
+    dir: Option<PathBuf>,
+}
+
+impl ToolSchemaSnapshots {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Persists payloads as `{dir}/{format}-{hash}.json` and loads them from
+    /// there on a cold start
+    pub fn with_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.dir = Some(dir.into());
+        self
+    }
+
+    /// Returns the tools of `registry` formatted for `format`.
+    ///
+    /// Reading or writing the snapshot directory only costs the cache, so
+    /// failures are logged and the payload is formatted in memory.
+    pub async fn payload(
+        &self,
+        format: ToolSchemaFormat,
+        registry: &dyn ToolRegistry,
+    ) -> Arc<Value> {
+        let generation = registry.generation();
+        if let Some(snapshot) = self.payloads.read().get(&format) {
+            if generation.is_some() && snapshot.generation == generation {
+                return Arc::clone(&snapshot.payload);
+            }
+        }
+
+        let specs = registry.specs();
+        let hash = catalog_hash(&specs);
+        if let Some(snapshot) = self.payloads.write().get_mut(&format) {
+            if snapshot.hash == hash {
+                snapshot.generation = generation;
+                return Arc::clone(&snapshot.payload);
+            }
+        }
+
+        let payload = match self.load(format, &hash).await {
+            Some(payload) => payload,
+            None => {
+                let payload = format.format(&specs);
+                self.save(format, &hash, &payload).await;
+                payload
+            }
+        };
+        let payload = Arc::new(payload);
+        let snapshot = Snapshot {
+            generation,
+            hash,
+            payload: Arc::clone(&payload),
+        };
+        self.payloads.write().insert(format, snapshot);
+        payload
+    }
+
+    fn path(&self, format: ToolSchemaFormat, hash: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        Some(dir.join(format!("{}-{}.json", format, hash)))
+    }
+
+    async fn load(&self, format: ToolSchemaFormat, hash: &str) -> Option<Value> {
+        let path = self.path(format, hash)?;
+        let data = match tokio::fs::read(&path).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                tracing::warn!("Reading tool schema snapshot failed: {}", e);
+                return None;
+            }
+        };
+        match serde_json::from_slice(&data) {
+            Ok(payload) => Some(payload),
+            Err(e) => {
+                tracing::warn!("Ignoring corrupt tool schema snapshot: {}", e);
+                None
+            }
+        }
+    }
+
+    async fn save(&self, format: ToolSchemaFormat, hash: &str, payload: &Value) {
+        let (Some(dir), Some(path)) = (self.dir.as_ref(), self.path(format, hash)) else {
+            return;
+        };
+        // Written to a temporary name and renamed, so a concurrent cold
+        // start never loads half a snapshot
+        let partial = dir.join(format!(".{}-{}.partial", format, hash));
+        let written = async {
+            tokio::fs::create_dir_all(dir).await?;
+            tokio::fs::write(&partial, serde_json::to_vec(payload)?).await?;
+            tokio::fs::rename(&partial, &path).await
+        }
+        .await;
+        if let Err(e) = written {
+            tracing::warn!("Writing tool schema snapshot failed: {}", e);
+        }
+    }
+}
+
+/// Rewrites `schema` into the subset Gemini accepts: `$ref`s inlined and
+/// unsupported keywords dropped
+fn gemini_schema(schema: &Value) -> Value {
+    strip(schema, schema, 0)
+}
+
+fn strip(schema: &Value, root: &Value, depth: usize) -> Value {
+    match schema {
+        Value::Object(fields) => {
+            if let Some(reference) = fields.get("$ref").and_then(Value::as_str) {
+                let target = reference
+                    .strip_prefix('#')
+                    .and_then(|pointer| root.pointer(pointer));
+                return match target {
+                    Some(target) if depth < MAX_REF_DEPTH => strip(target, root, depth + 1),
+                    _ => json!({"type": "object"}),
+                };
+            }
+            let fields: Map<String, Value> = fields
+                .iter()
+                .filter(|(key, _)| !GEMINI_UNSUPPORTED.contains(&key.as_str()))
+                .map(|(key, value)| {
+                    let value = match (key.as_str(), value) {
+                        // Keys of `properties` are field names, not keywords
+                        ("properties", Value::Object(properties)) => Value::Object(
+                            properties
+                                .iter()
+                                .map(|(name, field)| (name.clone(), strip(field, root, depth)))
+                                .collect(),
+                        ),
+                        _ => strip(value, root, depth),
+                    };
+                    (key.clone(), value)
+                })
+                .collect();
+            Value::Object(fields)
+        }
+        Value::Array(items) => items.iter().map(|item| strip(item, root, depth)).collect(),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Result;
+    use crate::tools::{Tool, ToolCatalog, ToolRegistry};
+    use crate::types::{ToolRequest, ToolResponse};
+    use async_trait::async_trait;
+
+    struct NamedTool(&'static str);
+
+    #[async_trait]
+    impl Tool for NamedTool {
+        fn spec(&self) -> ToolSpec {
+            ToolSpec {
+                name: self.0.to_string(),
+                description: "Looks things up".to_string(),
+                input_schema: json!({
+                    "type": "object",
+                    "additionalProperties": false,
+                    "properties": {
+                        "title": {"type": "string", "title": "Title"},
+                        "units": {"$ref": "#/definitions/Units"}
+                    },
+                    "definitions": {"Units": {"type": "string", "enum": ["metric"]}}
+                }),
+                examples: None,
+                output_schema: None,
+            }
+        }
+
+        async fn invoke(&self, _req: ToolRequest) -> Result<ToolResponse> {
+            Ok(ToolResponse {
+                content: String::new(),
+                metadata: None,
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_payloads_are_reused_until_the_catalog_changes() {
+        let dir = std::env::temp_dir().join(format!("tool-schemas-{}", uuid::Uuid::new_v4()));
+        let catalog = ToolCatalog::new();
+        catalog.register(Box::new(NamedTool("weather"))).unwrap();
+        let snapshots = ToolSchemaSnapshots::new().with_dir(&dir);
+
+        let gemini = snapshots.payload(ToolSchemaFormat::Gemini, &catalog).await;
+        let parameters = &gemini[0]["functionDeclarations"][0]["parameters"];
+        assert_eq!(parameters["properties"]["units"]["enum"], json!(["metric"]));
+        assert!(parameters.get("definitions").is_none());
+        assert!(parameters.get("additionalProperties").is_none());
+        assert_eq!(parameters["properties"]["title"], json!({"type": "string"}));
+        let again = snapshots.payload(ToolSchemaFormat::Gemini, &catalog).await;
+        assert!(Arc::ptr_eq(&gemini, &again));
+
+        // A new process starts from the persisted snapshot
+        let hash = catalog_hash(&catalog.specs());
+        assert!(dir.join(format!("gemini-{}.json", hash)).exists());
+        let cold = ToolSchemaSnapshots::new().with_dir(&dir);
+        let loaded = cold.payload(ToolSchemaFormat::Gemini, &catalog).await;
+        assert_eq!(*loaded, *gemini);
+
+        catalog.register(Box::new(NamedTool("stocks"))).unwrap();
+        let openai = snapshots.payload(ToolSchemaFormat::OpenAi, &catalog).await;
+        assert_eq!(openai[1]["function"]["name"], "stocks");
+        let gemini = snapshots.payload(ToolSchemaFormat::Gemini, &catalog).await;
+        assert_eq!(
+            gemini[0]["functionDeclarations"].as_array().unwrap().len(),
+            2
+        );
+
+        // Replacing a tool moves the generation; equal specs keep the payload
+        let generation = catalog.generation();
+        catalog.replace(Box::new(NamedTool("stocks"))).unwrap();
+        assert_ne!(catalog.generation(), generation);
+        let replaced = snapshots.payload(ToolSchemaFormat::Gemini, &catalog).await;
+        assert!(Arc::ptr_eq(&gemini, &replaced));
+        catalog.unregister("weather");
+        let openai = snapshots.payload(ToolSchemaFormat::OpenAi, &catalog).await;
+        assert_eq!(openai.as_array().unwrap().len(), 1);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,283 @@
+//! Choosing the tools relevant to a query.
+//!
+//! A catalog of hundreds of tools doesn't fit in a prompt, and a model
+//! offered too many picks worse among them. [`ToolSelector`] embeds each
+//! tool's name and description once, embeds the query on every turn, and
+//! keeps the `top_k` most similar tools scoring at least `min_score`, as
+//! configured by a [`ToolSelection`].
+//!
+//! The agent lists the selection in the system prompt and names it under
+//! [`SELECTED_TOOLS_KEY`] in the response metadata, so a caller building a
+//! provider's tool-call request can offer the model the same tools.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::error::Result;
+use crate::memory::{cosine_similarity, Embedder};
+use crate::tools::ToolRegistry;
+use crate::types::ToolSpec;
+
+/// Response metadata key holding the JSON array of tools a turn selected
+pub const SELECTED_TOOLS_KEY: &str = "selected_tools";
+
+/// Tools selected per query by default
+pub const DEFAULT_TOOL_TOP_K: usize = 8;
+/// Cosine similarity a tool needs by default to be selected
+pub const DEFAULT_TOOL_MIN_SCORE: f32 = 0.3;
+
+/// How many tools a query selects, and how relevant they must be
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ToolSelection {
+    /// Most tools selected per query
+    #[serde(default = "default_top_k")]
+    pub top_k: usize,
+    /// Cosine similarity below which a tool is left out
+    #[serde(default = "default_min_score")]
+    pub min_score: f32,
+}
+
+fn default_top_k() -> usize {
+    DEFAULT_TOOL_TOP_K
+}
+
+fn default_min_score() -> f32 {
+    DEFAULT_TOOL_MIN_SCORE
+}
+
+impl Default for ToolSelection {
+    fn default() -> Self {
+        Self {
+            top_k: DEFAULT_TOOL_TOP_K,
+            min_score: DEFAULT_TOOL_MIN_SCORE,
+        }
+    }
+}
+
+impl ToolSelection {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Selects at most `top_k` tools per query
+    pub fn with_top_k(mut self, top_k: usize) -> Self {
+        self.top_k = top_k;
+        self
+    }
+
+    /// Leaves out tools less similar to the query than `min_score`
+    pub fn with_min_score(mut self, min_score: f32) -> Self {
+        self.min_score = min_score;
+        self
+    }
+}
+
+/// Selects tools by the similarity of their descriptions to a query.
+///
+/// Tool embeddings are cached by name and recomputed only when a tool's
+/// name or description changes, so a large catalog is embedded once.
+/// [`ToolSelector::sync`] drops the embeddings of unregistered tools.
+pub struct ToolSelector {
+    embedder: Arc<dyn Embedder>,
+    // Embedded text and its embedding, by tool name
+    embeddings: parking_lot::Mutex<HashMap<String, (String, Vec<f32>)>>,
+    // Registry generation the cache was last pruned against
+    synced: parking_lot::Mutex<Option<u64>>,
+}
+
+impl ToolSelector {
+    pub fn new(embedder: Arc<dyn Embedder>) -> Self {
+        Self {
+            embedder,
+            embeddings: parking_lot::Mutex::new(HashMap::new()),
+            synced: parking_lot::Mutex::new(None),
+        }
+    }
+
+    /// Forgets the embeddings of tools no longer in `registry`; cheap while
+    /// the registry reports the generation it had last time
+    pub fn sync(&self, registry: &dyn ToolRegistry) {
+        let generation = registry.generation();
+        let mut synced = self.synced.lock();
+        if generation.is_some() && *synced == generation {
+            return;
+        }
+        *synced = generation;
+        let names: HashSet<String> = registry.specs().into_iter().map(|s| s.name).collect();
+        self.embeddings
+            .lock()
+            .retain(|name, _| names.contains(name));
+    }
+
+    /// Returns the specs most relevant to `query`, most relevant first
+    pub async fn select(
+        &self,
+        specs: &[ToolSpec],
+        query: &str,
+        selection: &ToolSelection,
+    ) -> Result<Vec<ToolSpec>> {
+        if specs.is_empty() || selection.top_k == 0 {
+            return Ok(Vec::new());
+        }
+        let texts: Vec<String> = specs.iter().map(embedded_text).collect();
+        let missing: Vec<String> = {
+            let embeddings = self.embeddings.lock();
+            specs
+                .iter()
+                .zip(&texts)
+                .filter(|(spec, text)| {
+                    embeddings
+                        .get(&spec.name)
+                        .is_none_or(|(embedded, _)| embedded != *text)
+                })
+                .map(|(_, text)| text.clone())
+                .collect()
+        };
+        let computed = if missing.is_empty() {
+            Vec::new()
+        } else {
+            self.embedder.embed_batch(&missing).await?
+        };
+        let query = self.embedder.embed_query(query).await?;
+
+        let mut embeddings = self.embeddings.lock();
+        let fresh: HashMap<String, Vec<f32>> = missing.into_iter().zip(computed).collect();
+        let mut scored = Vec::with_capacity(specs.len());
+        for (spec, text) in specs.iter().zip(texts) {
+            let embedding = match fresh.get(&text) {
+                Some(embedding) => {
+                    embeddings.insert(spec.name.clone(), (text, embedding.clone()));
+                    embedding
+                }
+                None => match embeddings.get(&spec.name) {
+                    Some((_, embedding)) => embedding,
+                    // Replaced by a concurrent selection over other specs
+                    None => continue,
+                },
+            };
+            let score = cosine_similarity(embedding, &query);
+            if score >= selection.min_score {
+                scored.push((score, spec));
+            }
+        }
+        scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+        scored.truncate(selection.top_k);
+        Ok(scored.into_iter().map(|(_, spec)| spec.clone()).collect())
+    }
+}
+
+fn embedded_text(spec: &ToolSpec) -> String {
+    format!("{}: {}", spec.name, spec.description)
+}
+
+/// Lists `specs` for the system prompt
+pub(crate) fn tools_instruction(specs: &[ToolSpec]) -> String {
+    let lines: Vec<String> = specs
+        .iter()
+        .map(|spec| format!("- {}: {}", spec.name, spec.description))
+        .collect();
+    format!("Tools relevant to this request:\n{}", lines.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Embeds texts by the topics they mention, counting embedded texts
+    #[derive(Default)]
+    struct TopicEmbedder {
+        embedded: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl Embedder for TopicEmbedder {
+        async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+            self.embedded.fetch_add(1, Ordering::SeqCst);
+            let text = text.to_lowercase();
+            Ok(["weather", "email", "invoice"]
+                .iter()
+                .map(|topic| text.matches(topic).count() as f32)
+                .collect())
+        }
+    }
+
+    fn spec(name: &str, description: &str) -> ToolSpec {
+        ToolSpec {
+            name: name.to_string(),
+            description: description.to_string(),
+            input_schema: serde_json::json!({"type": "object"}),
+            examples: None,
+            output_schema: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_selects_top_k_relevant_tools_and_caches_embeddings() {
+        let embedder = Arc::new(TopicEmbedder::default());
+        let selector = ToolSelector::new(embedder.clone());
+        let specs = vec![
+            spec("send_email", "Sends an email"),
+            spec("forecast", "Weather forecast for a city"),
+            spec("radar", "Weather radar and weather alerts"),
+            spec("billing", "Creates an invoice"),
+        ];
+        let selection = ToolSelection::new().with_top_k(2).with_min_score(0.5);
+
+        let names = |selected: Vec<ToolSpec>| -> Vec<String> {
+            selected.into_iter().map(|spec| spec.name).collect()
+        };
+        let selected = selector
+            .select(&specs, "What's the weather?", &selection)
+            .await
+            .unwrap();
+        assert_eq!(names(selected).len(), 2);
+        let selected = selector
+            .select(&specs, "Email the invoice", &selection)
+            .await
+            .unwrap();
+        assert_eq!(names(selected), ["send_email", "billing"]);
+        // Four tools and two queries; the tools were embedded once
+        assert_eq!(embedder.embedded.load(Ordering::SeqCst), 6);
+
+        let selected = selector.select(&specs, "Hello", &selection).await.unwrap();
+        assert!(selected.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sync_forgets_unregistered_tools() {
+        use crate::tools::{Tool, ToolCatalog};
+        use crate::types::{ToolRequest, ToolResponse};
+
+        struct Named(&'static str);
+
+        #[async_trait]
+        impl Tool for Named {
+            fn spec(&self) -> ToolSpec {
+                spec(self.0, "Weather forecast")
+            }
+
+            async fn invoke(&self, _req: ToolRequest) -> Result<ToolResponse> {
+                unreachable!()
+            }
+        }
+
+        let catalog = ToolCatalog::new();
+        catalog.register(Box::new(Named("forecast"))).unwrap();
+        catalog.register(Box::new(Named("radar"))).unwrap();
+        let selector = ToolSelector::new(Arc::new(TopicEmbedder::default()));
+        selector
+            .select(&catalog.specs(), "weather", &ToolSelection::new())
+            .await
+            .unwrap();
+        assert_eq!(selector.embeddings.lock().len(), 2);
+
+        assert!(catalog.unregister("radar"));
+        selector.sync(&catalog);
+        let cached: Vec<String> = selector.embeddings.lock().keys().cloned().collect();
+        assert_eq!(cached, ["forecast"]);
+    }
+}
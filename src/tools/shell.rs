@@ -0,0 +1,416 @@
+//! Built-in shell command tool.
+//!
+//! [`ShellTool`] runs commands for the model, under rules enforced at
+//! execution rather than suggested in the prompt. The command line is split
+//! into arguments and run directly, never through a shell, so shell
+//! operators that would chain or redirect commands are rejected instead of
+//! interpreted. The command must pass `helpers::is_valid_snippet`, start
+//! with an allowed prefix and no denied one. It then runs in its own process
+//! group with a scrubbed environment and a timeout, and no more of its
+//! output than returned is kept.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde_json::{json, Value};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+use crate::error::{AgentError, Result};
+use crate::helpers::is_valid_snippet;
+use crate::tools::Tool;
+use crate::types::{ToolRequest, ToolResponse, ToolSpec};
+
+/// Name under which [`ShellTool`] is registered
+pub const SHELL_TOOL: &str = "shell.run";
+/// Metadata key holding the exit code of a command
+pub const EXIT_CODE_KEY: &str = "exit_code";
+
+/// Time a command may run by default
+pub const DEFAULT_SHELL_TIMEOUT: Duration = Duration::from_secs(30);
+/// Bytes of output returned by default
+pub const DEFAULT_MAX_OUTPUT_BYTES: usize = 16 * 1024;
+
+/// Variables passed through to commands unless configured otherwise
+const INHERITED_ENV: [&str; 1] = ["PATH"];
+
+/// Built-in tool that runs allowed commands.
+///
+/// Allow and deny rules are command prefixes matched word by word: `"git"`
+/// covers every git command and `"git push"` only pushes. A command runs
+/// if it matches an allow rule and no deny rule; with no allow rules,
+/// nothing runs.
+///
+/// Allow rules are the boundary. Deny rules only trim them and are easy to
+/// step around: options before a subcommand, as in `git -c x=y push`, don't
+/// match `"git push"`. Allow the narrowest prefixes that do the job rather
+/// than a broad one minus denials.
+pub struct ShellTool {
+    allowed: Vec<Vec<String>>,
+    denied: Vec<Vec<String>>,
+    timeout: Duration,
+    max_output: usize,
+    working_dir: Option<PathBuf>,
+    inherited_env: Vec<String>,
+    env: HashMap<String, String>,
+}
+
+impl Default for ShellTool {
+    fn default() -> Self {
+        Self {
+            allowed: Vec::new(),
+            denied: Vec::new(),
+            timeout: DEFAULT_SHELL_TIMEOUT,
+            max_output: DEFAULT_MAX_OUTPUT_BYTES,
+            working_dir: None,
+            inherited_env: INHERITED_ENV.iter().map(|name| name.to_string()).collect(),
+            env: HashMap::new(),
+        }
+    }
+}
+
+impl ShellTool {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows commands starting with `prefix`, e.g. `"cargo test"`
+    pub fn with_allowed(mut self, prefix: &str) -> Self {
+        self.allowed.push(words(prefix));
+        self
+    }
+
+    /// Denies commands starting with `prefix`, even if allowed
+    pub fn with_denied(mut self, prefix: &str) -> Self {
+        self.denied.push(words(prefix));
+        self
+    }
+
+    /// Kills commands still running after `timeout`
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Truncates output beyond `bytes`
+    pub fn with_max_output(mut self, bytes: usize) -> Self {
+        self.max_output = bytes;
+        self
+    }
+
+    /// Runs commands in `dir` instead of the process's working directory
+    pub fn with_working_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.working_dir = Some(dir.into());
+        self
+    }
+
+    /// Passes variable `name` of this process through to commands; only
+    /// `PATH` is by default
+    pub fn with_inherited_env(mut self, name: impl Into<String>) -> Self {
+        self.inherited_env.push(name.into());
+        self
+    }
+
+    /// Sets variable `name` for every command
+    pub fn with_env(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.env.insert(name.into(), value.into());
+        self
+    }
+
+    /// Checks `command` against the rules, returning its arguments
+    pub fn check(&self, command: &str) -> Result<Vec<String>> {
+        let deny = |reason: &str| Err(AgentError::ToolDenied(format!("{}: {}", command, reason)));
+        if !is_valid_snippet(command) {
+            return deny("rejected as unsafe");
+        }
+        let argv = split_command_line(command)?;
+        if argv[0].contains(['/', '\\']) {
+            return deny("programs are run by name, not path");
+        }
+        let matches = |prefix: &Vec<String>| argv.starts_with(prefix);
+        if self.denied.iter().any(matches) {
+            return deny("denied");
+        }
+        if !self.allowed.iter().any(matches) {
+            return deny("not allowed");
+        }
+        Ok(argv)
+    }
+
+    /// Returns at most `max_output` bytes of `output`, cut at a char
+    /// boundary, with a note of what was cut, counting `dropped` bytes
+    /// never read into it
+    fn truncate(&self, mut output: String, dropped: u64) -> String {
+        if output.len() <= self.max_output && dropped == 0 {
+            return output;
+        }
+        let mut end = self.max_output.min(output.len());
+        while !output.is_char_boundary(end) {
+            end -= 1;
+        }
+        let cut = (output.len() - end) as u64 + dropped;
+        output.truncate(end);
+        output.push_str(&format!("\n[truncated {} bytes]", cut));
+        output
+    }
+}
+
+/// Reads `reader` to the end, keeping its first `max` bytes and counting
+/// the rest, so a chatty command can't fill memory or block on a full pipe
+async fn read_capped(reader: Option<impl AsyncRead + Unpin>, max: usize) -> Result<(Vec<u8>, u64)> {
+    let (mut kept, mut dropped) = (Vec::new(), 0);
+    let Some(mut reader) = reader else {
+        return Ok((kept, dropped));
+    };
+    let mut buf = [0u8; 8192];
+    loop {
+        let read = reader.read(&mut buf).await?;
+        if read == 0 {
+            return Ok((kept, dropped));
+        }
+        let keep = read.min(max - kept.len());
+        kept.extend_from_slice(&buf[..keep]);
+        dropped += (read - keep) as u64;
+    }
+}
+
+/// Kills a command's whole process group when dropped, so children it
+/// started don't outlive a timeout or a cancelled call
+struct ProcessGroup(Option<u32>);
+
+impl Drop for ProcessGroup {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(pid) = self.0 {
+            // SAFETY: kill takes no pointers; a group already gone is fine
+            unsafe {
+                libc::kill(-(pid as libc::pid_t), libc::SIGKILL);
+            }
+        }
+    }
+}
+
+fn words(prefix: &str) -> Vec<String> {
+    prefix.split_whitespace().map(str::to_string).collect()
+}
+
+/// Splits `command` into arguments the way a POSIX shell would for plain
+/// words and quotes, rejecting the operators this tool doesn't interpret
+fn split_command_line(command: &str) -> Result<Vec<String>> {
+    let invalid = |reason: &str| AgentError::ToolError(format!("{}: {}", command, reason));
+    let mut argv = Vec::new();
+    let mut word: Option<String> = None;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => argv.extend(word.take()),
+            '\'' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => word.push(c),
+                        None => return Err(invalid("unterminated quote")),
+                    }
+                }
+            }
+            '"' => {
+                let word = word.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\')) => word.push(c),
+                            Some(c) => {
+                                word.push('\\');
+                                word.push(c);
+                            }
+                            None => return Err(invalid("unterminated quote")),
+                        },
+                        Some(c) => word.push(c),
+                        None => return Err(invalid("unterminated quote")),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some(c) => word.get_or_insert_with(String::new).push(c),
+                None => return Err(invalid("trailing backslash")),
+            },
+            ';' | '|' | '&' | '<' | '>' | '`' | '$' | '(' | ')' => {
+                return Err(invalid("shell operators are not supported"));
+            }
+            c => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    argv.extend(word);
+    if argv.is_empty() {
+        return Err(invalid("empty command"));
+    }
+    Ok(argv)
+}
+
+#[async_trait]
+impl Tool for ShellTool {
+    fn spec(&self) -> ToolSpec {
+        let mut description = "Runs a command without a shell and returns its output".to_string();
+        if !self.allowed.is_empty() {
+            let allowed: Vec<String> = self.allowed.iter().map(|p| p.join(" ")).collect();
+            description.push_str(&format!("; allowed commands: {}", allowed.join(", ")));
+        }
+        ToolSpec {
+            name: SHELL_TOOL.to_string(),
+            description,
+            input_schema: json!({
+                "type": "object",
+                "properties": {
+                    "command": {
+                        "type": "string",
+                        "description": "Command line; quotes group words, pipes and redirects are not supported"
+                    }
+                },
+                "required": ["command"]
+            }),
+            examples: None,
+            output_schema: None,
+        }
+    }
+
+    async fn invoke(&self, req: ToolRequest) -> Result<ToolResponse> {
+        let command = req
+            .arguments
+            .get("command")
+            .and_then(Value::as_str)
+            .ok_or_else(|| AgentError::ToolError("missing 'command'".to_string()))?;
+        let argv = self.check(command)?;
+
+        let mut process = tokio::process::Command::new(&argv[0]);
+        process
+            .args(&argv[1..])
+            .env_clear()
+            .envs(
+                self.inherited_env
+                    .iter()
+                    .filter_map(|name| std::env::var(name).ok().map(|value| (name, value))),
+            )
+            .envs(&self.env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true);
+        #[cfg(unix)]
+        process.process_group(0);
+        if let Some(dir) = &self.working_dir {
+            process.current_dir(dir);
+        }
+        let mut child = process
+            .spawn()
+            .map_err(|e| AgentError::ToolError(format!("{}: {}", argv[0], e)))?;
+        let _group = ProcessGroup(child.id());
+        let (stdout, stderr) = (child.stdout.take(), child.stderr.take());
+        let run = async {
+            let (stdout, stderr, status) = tokio::join!(
+                read_capped(stdout, self.max_output),
+                read_capped(stderr, self.max_output),
+                child.wait(),
+            );
+            Ok::<_, AgentError>((stdout?, stderr?, status?))
+        };
+        let ((stdout, stdout_dropped), (stderr, stderr_dropped), status) =
+            tokio::time::timeout(self.timeout, run)
+                .await
+                .map_err(|_| {
+                    AgentError::ToolTimeout(format!(
+                        "{} exceeded {}ms",
+                        command,
+                        self.timeout.as_millis()
+                    ))
+                })??;
+
+        let mut content = String::from_utf8_lossy(&stdout).into_owned();
+        if !stderr.is_empty() {
+            content.push_str("\n[stderr]\n");
+            content.push_str(&String::from_utf8_lossy(&stderr));
+        }
+        let code = status
+            .code()
+            .map_or_else(|| "signal".to_string(), |code| code.to_string());
+        if !status.success() {
+            content.push_str(&format!("\n[exit code {}]", code));
+        }
+        Ok(ToolResponse {
+            content: self.truncate(content, stdout_dropped + stderr_dropped),
+            metadata: Some(HashMap::from([(EXIT_CODE_KEY.to_string(), code)])),
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn run(command: &str) -> ToolRequest {
+        ToolRequest {
+            session_id: "test".to_string(),
+            arguments: HashMap::from([("command".to_string(), json!(command))]),
+            tenant_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_commands_run_only_under_the_rules() {
+        let shell = ShellTool::new()
+            .with_allowed("echo")
+            .with_allowed("env")
+            .with_allowed("sleep")
+            .with_allowed("rm")
+            .with_denied("echo secret")
+            .with_env("GREETING", "hi")
+            .with_timeout(Duration::from_millis(200))
+            .with_max_output(16);
+
+        let echoed = shell.invoke(run("echo 'a  b' \"c\"")).await.unwrap();
+        assert_eq!(echoed.content, "a  b c\n");
+        assert_eq!(echoed.metadata.unwrap()[EXIT_CODE_KEY], "0");
+
+        let env = shell.invoke(run("env")).await.unwrap();
+        assert!(env.content.contains("GREETING=hi"));
+        assert!(!env.content.contains("HOME="));
+
+        let long = shell
+            .invoke(run("echo 0123456789abcdefghij"))
+            .await
+            .unwrap();
+        assert_eq!(long.content, "0123456789abcdef\n[truncated 5 bytes]");
+
+        for command in [
+            "echo secret plans",
+            "ls",
+            "/bin/echo hi",
+            "echo hi; ls",
+            "echo $HOME",
+            "rm -rf /tmp/x",
+        ] {
+            assert!(
+                matches!(
+                    shell.invoke(run(command)).await,
+                    Err(AgentError::ToolDenied(_)) | Err(AgentError::ToolError(_))
+                ),
+                "{} should be rejected",
+                command
+            );
+        }
+        assert!(matches!(
+            shell.invoke(run("sleep 5")).await,
+            Err(AgentError::ToolTimeout(_))
+        ));
+
+        // Nothing runs without allow rules
+        let bare = ShellTool::new().with_denied("rm");
+        assert!(matches!(
+            bare.invoke(run("echo hi")).await,
+            Err(AgentError::ToolDenied(_))
+        ));
+    }
+}
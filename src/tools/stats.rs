@@ -0,0 +1,209 @@
+//! Tool usage statistics.
+//!
+//! `ToolCatalog` records every call it runs: how often each tool was
+//! called, how often it failed, and how long its recent calls took, as
+//! percentiles. Operators read the totals with `ToolCatalog::stats` to see
+//! which tools are hot or flaky, or subscribe to a [`ToolCallEvent`] per
+//! call with `ToolCatalog::subscribe_usage`.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::broadcast;
+
+/// Latencies kept per tool for percentiles by default
+pub const DEFAULT_LATENCY_SAMPLES: usize = 1024;
+
+/// Call events buffered for slow subscribers
+const EVENT_BUFFER: usize = 256;
+
+/// One finished tool call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ToolCallEvent {
+    pub tool: String,
+    pub latency: Duration,
+    pub success: bool,
+}
+
+/// Usage of one tool. Latency percentiles cover its most recent calls.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ToolUsage {
+    pub calls: u64,
+    pub failures: u64,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub p99: Duration,
+    /// Slowest call ever recorded
+    pub max: Duration,
+}
+
+impl ToolUsage {
+    /// Share of calls that failed, 0 for a tool never called
+    pub fn error_rate(&self) -> f64 {
+        if self.calls == 0 {
+            return 0.0;
+        }
+        self.failures as f64 / self.calls as f64
+    }
+}
+
+#[derive(Default)]
+struct Usage {
+    calls: u64,
+    failures: u64,
+    max: Duration,
+    latencies: VecDeque<Duration>,
+}
+
+impl Usage {
+    fn snapshot(&self) -> ToolUsage {
+        let mut sorted: Vec<Duration> = self.latencies.iter().copied().collect();
+        sorted.sort_unstable();
+        ToolUsage {
+            calls: self.calls,
+            failures: self.failures,
+            p50: percentile(&sorted, 50),
+            p95: percentile(&sorted, 95),
+            p99: percentile(&sorted, 99),
+            max: self.max,
+        }
+    }
+}
+
+/// Nearest-rank percentile of `sorted`
+fn percentile(sorted: &[Duration], percent: usize) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+    sorted[rank - 1]
+}
+
+/// Records calls per tool for a catalog
+pub(crate) struct UsageTracker {
+    tools: parking_lot::Mutex<HashMap<String, Usage>>,
+    samples: AtomicUsize,
+    events: broadcast::Sender<ToolCallEvent>,
+}
+
+impl Default for UsageTracker {
+    fn default() -> Self {
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        Self {
+            tools: parking_lot::Mutex::new(HashMap::new()),
+            samples: AtomicUsize::new(DEFAULT_LATENCY_SAMPLES),
+            events,
+        }
+    }
+}
+
+impl UsageTracker {
+    pub(crate) fn set_samples(&self, samples: usize) {
+        self.samples.store(samples.max(1), Ordering::Relaxed);
+    }
+
+    /// Starts timing a call of `tool`, recorded once it finishes
+    pub(crate) fn start(self: &Arc<Self>, tool: &str) -> PendingCall {
+        PendingCall {
+            usage: Arc::clone(self),
+            tool: tool.to_string(),
+            started: Instant::now(),
+            done: false,
+        }
+    }
+
+    pub(crate) fn record(&self, tool: &str, latency: Duration, success: bool) {
+        {
+            let mut tools = self.tools.lock();
+            let usage = tools.entry(tool.to_string()).or_default();
+            usage.calls += 1;
+            usage.failures += u64::from(!success);
+            usage.max = usage.max.max(latency);
+            while usage.latencies.len() >= self.samples.load(Ordering::Relaxed) {
+                usage.latencies.pop_front();
+            }
+            usage.latencies.push_back(latency);
+        }
+        // Nobody listening is fine
+        let _ = self.events.send(ToolCallEvent {
+            tool: tool.to_string(),
+            latency,
+            success,
+        });
+    }
+
+    pub(crate) fn stats(&self) -> HashMap<String, ToolUsage> {
+        let tools = self.tools.lock();
+        tools
+            .iter()
+            .map(|(name, usage)| (name.clone(), usage.snapshot()))
+            .collect()
+    }
+
+    pub(crate) fn get(&self, tool: &str) -> Option<ToolUsage> {
+        self.tools.lock().get(tool).map(Usage::snapshot)
+    }
+
+    pub(crate) fn reset(&self) {
+        self.tools.lock().clear();
+    }
+
+    pub(crate) fn subscribe(&self) -> broadcast::Receiver<ToolCallEvent> {
+        self.events.subscribe()
+    }
+}
+
+/// A call in flight. Dropping it before [`finish`](PendingCall::finish),
+/// e.g. because the caller gave up on the call, records a failure.
+pub(crate) struct PendingCall {
+    usage: Arc<UsageTracker>,
+    tool: String,
+    started: Instant,
+    done: bool,
+}
+
+impl PendingCall {
+    pub(crate) fn finish(mut self, success: bool) {
+        self.done = true;
+        self.usage
+            .record(&self.tool, self.started.elapsed(), success);
+    }
+}
+
+impl Drop for PendingCall {
+    fn drop(&mut self) {
+        if !self.done {
+            self.usage.record(&self.tool, self.started.elapsed(), false);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_reports_error_rate_and_percentiles() {
+        let tracker = UsageTracker::default();
+        tracker.set_samples(100);
+        let mut events = tracker.subscribe();
+        for ms in 1..=200 {
+            tracker.record("search", Duration::from_millis(ms), ms % 4 != 0);
+        }
+
+        let usage = tracker.get("search").unwrap();
+        assert_eq!((usage.calls, usage.failures), (200, 50));
+        assert_eq!(usage.error_rate(), 0.25);
+        // Only the last 100 calls count towards percentiles
+        assert_eq!(usage.p50, Duration::from_millis(150));
+        assert_eq!(usage.p95, Duration::from_millis(195));
+        assert_eq!(usage.p99, Duration::from_millis(199));
+        assert_eq!(usage.max, Duration::from_millis(200));
+        assert_eq!(events.try_recv().unwrap().tool, "search");
+
+        tracker.reset();
+        assert!(tracker.stats().is_empty());
+    }
+}
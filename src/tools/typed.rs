@@ -0,0 +1,131 @@
+//! Tools from typed Rust functions.
+//!
+//! Hand-written JSON schemas drift from the code that parses the
+//! arguments. With the `macros` feature, the [`tool`](crate::tools::tool)
+//! attribute turns an async fn into a [`Tool`](crate::tools::Tool) whose
+//! schema is generated from the parameter types; this module holds what
+//! the generated code calls. [`input_schema`] and [`parse_arguments`] are
+//! just as usable in hand-written tools with a typed arguments struct.
+
+use std::collections::HashMap;
+
+use schemars::gen::SchemaSettings;
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::error::{AgentError, Result};
+use crate::types::ToolResponse;
+
+/// Returns the input schema of a tool taking arguments `T`.
+///
+/// Nested types are inlined rather than referenced, and the `$schema` and
+/// `title` keys are left out, as providers expect of tool parameters.
+pub fn input_schema<T: JsonSchema>() -> Value {
+    let schema = SchemaSettings::draft07()
+        .with(|settings| settings.inline_subschemas = true)
+        .into_generator()
+        .into_root_schema_for::<T>();
+    let mut schema = serde_json::to_value(schema).unwrap_or_default();
+    if let Value::Object(fields) = &mut schema {
+        fields.remove("$schema");
+        fields.remove("title");
+    }
+    schema
+}
+
+/// Parses tool call arguments into `T`, failing with a message the model
+/// can act on
+pub fn parse_arguments<T: DeserializeOwned>(arguments: HashMap<String, Value>) -> Result<T> {
+    serde_json::from_value(Value::Object(arguments.into_iter().collect()))
+        .map_err(|e| AgentError::ToolError(format!("Invalid arguments: {}", e)))
+}
+
+/// Return values a typed tool can produce
+pub trait IntoToolResponse {
+    fn into_tool_response(self) -> Result<ToolResponse>;
+}
+
+impl IntoToolResponse for ToolResponse {
+    fn into_tool_response(self) -> Result<ToolResponse> {
+        Ok(self)
+    }
+}
+
+impl IntoToolResponse for String {
+    fn into_tool_response(self) -> Result<ToolResponse> {
+        Ok(ToolResponse {
+            content: self,
+            metadata: None,
+        })
+    }
+}
+
+impl IntoToolResponse for &str {
+    fn into_tool_response(self) -> Result<ToolResponse> {
+        self.to_string().into_tool_response()
+    }
+}
+
+/// Structured replies are sent as JSON text
+impl IntoToolResponse for Value {
+    fn into_tool_response(self) -> Result<ToolResponse> {
+        self.to_string().into_tool_response()
+    }
+}
+
+impl<T: IntoToolResponse> IntoToolResponse for Result<T> {
+    fn into_tool_response(self) -> Result<ToolResponse> {
+        self?.into_tool_response()
+    }
+}
+
+#[cfg(all(test, feature = "macros"))]
+mod tests {
+    use super::*;
+    use crate::tools::{tool, Tool};
+    use crate::types::ToolRequest;
+    use serde_json::json;
+
+    /// Converts a temperature
+    #[tool]
+    async fn convert_temperature(celsius: f64, unit: Option<String>) -> Result<Value> {
+        match unit.as_deref().unwrap_or("fahrenheit") {
+            "fahrenheit" => Ok(json!({"value": celsius * 9.0 / 5.0 + 32.0})),
+            "kelvin" => Ok(json!({"value": celsius + 273.15})),
+            other => Err(AgentError::ToolError(format!("unknown unit {}", other))),
+        }
+    }
+
+    fn request(arguments: Value) -> ToolRequest {
+        ToolRequest {
+            session_id: "s".to_string(),
+            arguments: serde_json::from_value(arguments).unwrap(),
+            tenant_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tool_macro_generates_schema_and_invokes() {
+        let spec = ConvertTemperatureTool.spec();
+        assert_eq!(spec.name, "convert_temperature");
+        assert_eq!(spec.description, "Converts a temperature");
+        assert_eq!(spec.input_schema["type"], "object");
+        assert_eq!(spec.input_schema["required"], json!(["celsius"]));
+        assert!(spec.input_schema["properties"]["unit"].is_object());
+        assert!(spec.input_schema.get("$schema").is_none());
+
+        let tool = ConvertTemperatureTool;
+        let reply = tool.invoke(request(json!({"celsius": 100.0}))).await;
+        assert_eq!(reply.unwrap().content, r#"{"value":212.0}"#);
+        for arguments in [
+            json!({"celsius": "hot"}),
+            json!({"celsius": 1.0, "unit": "rankine"}),
+        ] {
+            assert!(matches!(
+                tool.invoke(request(arguments)).await,
+                Err(AgentError::ToolError(_))
+            ));
+        }
+    }
+}
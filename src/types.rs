@@ -1,6 +1,11 @@
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
+use crate::context::ContextStrategy;
+use crate::cost::CostBudget;
+use crate::models::ModelRoutingPolicy;
+use crate::tools::{ToolOutputGuard, ToolSelection};
+
 /// Tool specification describing how an agent presents a tool to the model
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ToolSpec {
@@ -9,13 +14,67 @@ pub struct ToolSpec {
     pub input_schema: serde_json::Value,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub examples: Option<Vec<serde_json::Value>>,
+    /// JSON schema the tool's output matches; registries reject responses
+    /// that don't parse as JSON or don't match it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_schema: Option<serde_json::Value>,
 }
 
-/// Tool request captures an invocation request
+impl ToolSpec {
+    /// Creates the spec of tool `name` taking arguments that match
+    /// `input_schema`
+    pub fn new(
+        name: impl Into<String>,
+        description: impl Into<String>,
+        input_schema: serde_json::Value,
+    ) -> Self {
+        Self {
+            name: name.into(),
+            description: description.into(),
+            input_schema,
+            examples: None,
+            output_schema: None,
+        }
+    }
+
+    /// Shows the model example arguments
+    pub fn with_examples(mut self, examples: Vec<serde_json::Value>) -> Self {
+        self.examples = Some(examples);
+        self
+    }
+
+    /// Declares the JSON schema the tool's output matches
+    pub fn with_output_schema(mut self, schema: serde_json::Value) -> Self {
+        self.output_schema = Some(schema);
+        self
+    }
+}
+
+/// Tool request captures an invocation request.
+///
+/// Build one with [`ToolRequest::new`]; fields may be added in minor
+/// releases.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct ToolRequest {
     pub session_id: String,
     pub arguments: HashMap<String, serde_json::Value>,
+    /// Tenant that issued the call, stamped by a `TenantGuard`
+    pub tenant_id: Option<String>,
+}
+
+impl ToolRequest {
+    /// Creates a request calling a tool with `arguments` in `session_id`
+    pub fn new(
+        session_id: impl Into<String>,
+        arguments: HashMap<String, serde_json::Value>,
+    ) -> Self {
+        Self {
+            session_id: session_id.into(),
+            arguments,
+            tenant_id: None,
+        }
+    }
 }
 
 /// Tool response represents the structured response from a tool
@@ -26,6 +85,17 @@ pub struct ToolResponse {
     pub metadata: Option<HashMap<String, String>>,
 }
 
+impl ToolResponse {
+    /// Creates a response whose content is `value` as JSON, for tools that
+    /// declare an `output_schema`
+    pub fn structured(value: serde_json::Value) -> Self {
+        Self {
+            content: value.to_string(),
+            metadata: None,
+        }
+    }
+}
+
 /// Message role in a conversation
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
@@ -59,11 +129,52 @@ pub struct GenerationResponse {
     pub metadata: Option<HashMap<String, String>>,
 }
 
-/// Configuration options for creating an agent
-#[derive(Debug, Clone)]
+/// Configuration options for creating an agent.
+///
+/// Also accepted by `Agent::update_options` at runtime, where fields left
+/// `None` keep their current value. Deserializes from JSON for
+/// `Agent::watch_options`; missing fields are `None`.
+#[derive(Clone, Serialize, Deserialize)]
 pub struct AgentOptions {
+    #[serde(default)]
     pub system_prompt: Option<String>,
+    #[serde(default)]
     pub context_limit: Option<usize>,
+    /// Prompt-injection screening for tool outputs
+    #[serde(default)]
+    pub tool_guard: Option<ToolOutputGuard>,
+    /// How memories are packed into the prompt; the agent's
+    /// `ContextComposer` if unset. Not read from options files.
+    #[serde(skip)]
+    pub context_strategy: Option<Arc<dyn ContextStrategy>>,
+    /// Fast and strong models chosen per input; the agent's model if
+    /// unset. Not read from options files.
+    #[serde(skip)]
+    pub model_routing: Option<Arc<ModelRoutingPolicy>>,
+    /// Soft and hard spending limits per session and for the agent
+    #[serde(default)]
+    pub cost_budget: Option<CostBudget>,
+    /// Lists only the tools relevant to each input in the prompt; needs
+    /// `Agent::with_tool_selector`
+    #[serde(default)]
+    pub tool_selection: Option<ToolSelection>,
+}
+
+impl std::fmt::Debug for AgentOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AgentOptions")
+            .field("system_prompt", &self.system_prompt)
+            .field("context_limit", &self.context_limit)
+            .field("tool_guard", &self.tool_guard)
+            .field(
+                "context_strategy",
+                &self.context_strategy.as_ref().map(|s| s.name()),
+            )
+            .field("model_routing", &self.model_routing)
+            .field("cost_budget", &self.cost_budget)
+            .field("tool_selection", &self.tool_selection)
+            .finish()
+    }
 }
 
 impl Default for AgentOptions {
@@ -71,15 +182,59 @@ impl Default for AgentOptions {
         Self {
             system_prompt: None,
             context_limit: Some(8192),
+            tool_guard: None,
+            context_strategy: None,
+            model_routing: None,
+            cost_budget: None,
+            tool_selection: None,
         }
     }
 }
 
+/// Per-call overrides for `Agent::generate_with_options`.
+///
+/// Fields left `None` keep the agent's configuration for that turn; nothing
+/// set here outlives the call.
+#[derive(Debug, Clone, Default)]
+pub struct GenerateOptions {
+    /// Replaces the system prompt, including any registry or experiment prompt
+    pub system_prompt: Option<String>,
+    /// Sampling temperature, for models that support it
+    pub temperature: Option<f32>,
+    pub context_limit: Option<usize>,
+    /// Tools the turn may use. CodeMode orchestration picks among all
+    /// registered tools, so it is skipped unless every one is allowed.
+    pub allowed_tools: Option<Vec<String>>,
+    /// Tool namespaces enabled for the turn, over the session's settings;
+    /// see `ToolNamespaces`
+    pub enabled_namespaces: Option<Vec<String>>,
+    /// Tool namespaces disabled for the turn, over the session's settings
+    pub disabled_namespaces: Option<Vec<String>>,
+    /// How many memories retrieval recalls; 0 skips retrieval
+    pub retrieval_limit: Option<usize>,
+    /// Thread path to retrieve from instead of the session, e.g. `"org"` or
+    /// `"org/thread"`; needs `SessionMemory::with_threads`
+    pub retrieval_scope: Option<String>,
+    /// Stops the turn with `AgentError::Cancelled` once cancelled
+    pub cancel: Option<crate::cancel::CancellationToken>,
+    /// Stops the turn with `AgentError::DeadlineExceeded` once passed
+    pub deadline: Option<std::time::Instant>,
+    /// Runs the turn once per session and key; a replay returns the first
+    /// response instead of storing and calling tools again
+    pub idempotency_key: Option<String>,
+    /// JSON schema the reply must match; invalid replies are sent back with
+    /// the error, see `Agent::generate_typed`
+    pub response_schema: Option<serde_json::Value>,
+    /// Tenant the turn runs on behalf of, checked by a `TenantGuard` on
+    /// every memory and tool call of the turn
+    pub tenant_id: Option<String>,
+}
+
 // ============================================================================
 // SubAgent System (matching go-agent's types.go)
 // ============================================================================
 
-use crate::error::Result;
+use crate::error::{AgentError, Result};
 use crate::memory::MemoryRecord;
 use async_trait::async_trait;
 use chrono::{DateTime, Utc};
@@ -110,12 +265,101 @@ pub trait SubAgentDirectory: Send + Sync {
     fn all(&self) -> Vec<Arc<dyn SubAgent>>;
 }
 
+/// Name and description of a sub-agent, as recorded in checkpoints
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SubAgentInfo {
+    pub name: String,
+    pub description: String,
+}
+
+impl SubAgentInfo {
+    /// Describes `subagent`
+    pub fn of(subagent: &dyn SubAgent) -> Self {
+        Self {
+            name: subagent.name(),
+            description: subagent.description(),
+        }
+    }
+}
+
+/// Layout version of [`AgentState`] written by this build.
+///
+/// Bump it whenever the layout changes, and append the migration from the
+/// previous version to `STATE_MIGRATIONS`.
+pub const AGENT_STATE_VERSION: u32 = 2;
+
+/// Upgrades checkpoint JSON one version at a time: entry `n` turns version
+/// `n` into version `n + 1`
+const STATE_MIGRATIONS: &[fn(&mut serde_json::Map<String, serde_json::Value>)] = &[
+    // 0 -> 1: checkpoints from before versioning already have the v1 layout
+    |_| {},
+    // 1 -> 2: adds `parent`; every v1 checkpoint is a full one
+    |_| {},
+];
+
+/// Checkpoint an incremental checkpoint builds on
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CheckpointParent {
+    /// Id of the parent in its `CheckpointStore`
+    pub id: String,
+    /// `content_hash` of the parent's bytes, so a replaced or corrupted
+    /// parent is detected instead of restored
+    pub hash: String,
+}
+
 /// AgentState represents the serializable state of an agent for checkpointing.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AgentState {
+    /// Layout version, see [`AGENT_STATE_VERSION`]; 0 for checkpoints taken
+    /// before states were versioned
+    #[serde(default)]
+    pub version: u32,
     pub system_prompt: String,
     pub short_term: Vec<MemoryRecord>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub joined_spaces: Option<Vec<String>>,
+    /// Sub-agents registered when the checkpoint was taken
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subagents: Option<Vec<SubAgentInfo>>,
     pub timestamp: DateTime<Utc>,
+    /// Set on incremental checkpoints, whose `short_term` holds only the
+    /// records added or updated since `parent`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<CheckpointParent>,
+    /// Ids of all the session's records, in order, on incremental
+    /// checkpoints; records of the parents left out were deleted since
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub record_ids: Option<Vec<uuid::Uuid>>,
+}
+
+impl AgentState {
+    /// Parses a checkpoint, migrating older layouts to the current one.
+    ///
+    /// Checkpoints written by a newer rs-agent fail with
+    /// [`AgentError::UnsupportedVersion`].
+    pub fn from_checkpoint(data: &[u8]) -> Result<Self> {
+        let mut state: serde_json::Value = serde_json::from_slice(data)?;
+        let fields = state.as_object_mut().ok_or_else(|| {
+            AgentError::InvalidState("Checkpoint is not a JSON object".to_string())
+        })?;
+
+        let version = match fields.get("version") {
+            None => 0,
+            Some(version) => version.as_u64().ok_or_else(|| {
+                AgentError::InvalidState(format!("Invalid checkpoint version: {}", version))
+            })?,
+        };
+        if version > u64::from(AGENT_STATE_VERSION) {
+            return Err(AgentError::UnsupportedVersion(format!(
+                "checkpoint has state version {}, this build reads up to {}",
+                version, AGENT_STATE_VERSION
+            )));
+        }
+
+        for migrate in &STATE_MIGRATIONS[version as usize..] {
+            migrate(fields);
+        }
+        fields.insert("version".to_string(), AGENT_STATE_VERSION.into());
+        Ok(serde_json::from_value(state)?)
+    }
 }
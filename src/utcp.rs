@@ -1,12 +1,17 @@
-use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 
 use async_trait::async_trait;
+use futures::StreamExt;
+use rs_utcp::providers::base::Provider;
 use rs_utcp::tools::Tool as UtcpTool;
+use rs_utcp::transports::stream::StreamResult;
 use rs_utcp::UtcpClientInterface;
 
+use crate::agent::Agent;
 use crate::error::{AgentError, Result};
-use crate::tools::Tool;
+use crate::tools::{Tool, ToolRegistry, ToolStream};
 use crate::types::{ToolRequest, ToolResponse, ToolSpec};
 
 /// Adapter that exposes a UTCP tool through the rs-agent `Tool` trait.
@@ -24,11 +29,19 @@ impl UtcpToolAdapter {
         let input_schema = serde_json::to_value(&self.tool.inputs)
             .unwrap_or_else(|_| serde_json::json!({"type": "object"}));
 
-        ToolSpec {
-            name: self.tool.name.clone(),
-            description: self.tool.description.clone(),
+        let spec = ToolSpec::new(
+            self.tool.name.clone(),
+            self.tool.description.clone(),
             input_schema,
-            examples: None,
+        );
+        // Outputs without properties or items only say "some object",
+        // which text results would fail
+        let outputs = &self.tool.outputs;
+        let structured =
+            outputs.properties.as_ref().is_some_and(|p| !p.is_empty()) || outputs.items.is_some();
+        match serde_json::to_value(outputs) {
+            Ok(schema) if structured => spec.with_output_schema(schema),
+            _ => spec,
         }
     }
 }
@@ -47,25 +60,84 @@ impl Tool for UtcpToolAdapter {
             .await
             .map_err(|e| AgentError::UtcpError(e.to_string()))?;
 
-        // Preserve string outputs as-is; serialize other payloads to JSON text
-        let content = match result {
-            serde_json::Value::String(s) => s,
-            other => serde_json::to_string(&other).unwrap_or_else(|_| format!("{other:?}")),
+        Ok(utcp_response(result))
+    }
+
+    /// Streams the tool's results through `call_tool_stream`, closing the
+    /// UTCP stream once it ends or fails, or when it is dropped early
+    async fn invoke_stream(&self, req: ToolRequest) -> Result<ToolStream> {
+        let results = self
+            .client
+            .call_tool_stream(&self.tool.name, req.arguments)
+            .await
+            .map_err(|e| AgentError::UtcpError(e.to_string()))?;
+
+        let stream = futures::stream::unfold(OpenStream(Some(results)), |mut open| async move {
+            let next = open.0.as_mut()?.next().await;
+            if !matches!(next, Ok(Some(_))) {
+                open.close().await;
+            }
+            match next {
+                Ok(Some(value)) => Some((Ok(utcp_response(value)), open)),
+                Ok(None) => None,
+                Err(e) => Some((Err(AgentError::UtcpError(e.to_string())), open)),
+            }
+        });
+        Ok(stream.boxed())
+    }
+}
+
+/// UTCP stream of a tool call, closed in the background if it is dropped
+/// before being read to the end
+struct OpenStream(Option<Box<dyn StreamResult>>);
+
+impl OpenStream {
+    async fn close(&mut self) {
+        if let Some(mut results) = self.0.take() {
+            if let Err(e) = results.close().await {
+                tracing::warn!("Closing UTCP stream failed: {}", e);
+            }
+        }
+    }
+}
+
+impl Drop for OpenStream {
+    fn drop(&mut self) {
+        let Some(mut results) = self.0.take() else {
+            return;
+        };
+        // Abandoned mid-stream: release the connection behind it
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
         };
+        runtime.spawn(async move {
+            if let Err(e) = results.close().await {
+                tracing::warn!("Closing UTCP stream failed: {}", e);
+            }
+        });
+    }
+}
 
-        Ok(ToolResponse {
-            content,
-            metadata: Some(HashMap::from([(
-                "provider".to_string(),
-                "utcp".to_string(),
-            )])),
-        })
+/// Wraps a UTCP result as a tool response
+fn utcp_response(result: serde_json::Value) -> ToolResponse {
+    // Preserve string outputs as-is; serialize other payloads to JSON text
+    let content = match result {
+        serde_json::Value::String(s) => s,
+        other => serde_json::to_string(&other).unwrap_or_else(|_| format!("{other:?}")),
+    };
+
+    ToolResponse {
+        content,
+        metadata: Some(HashMap::from([(
+            "provider".to_string(),
+            "utcp".to_string(),
+        )])),
     }
 }
 
 /// Registers UTCP tools into the agent's tool catalog.
 pub fn register_utcp_tools(
-    catalog: &crate::tools::ToolCatalog,
+    catalog: &dyn ToolRegistry,
     client: Arc<dyn UtcpClientInterface>,
     tools: Vec<UtcpTool>,
 ) -> Result<()> {
@@ -76,28 +148,359 @@ pub fn register_utcp_tools(
     Ok(())
 }
 
+/// Matches registered per search by default
+pub const DEFAULT_DISCOVERY_LIMIT: usize = 3;
+
+/// Decides which tools found in a UTCP repository discovery may use
+pub type DiscoveryFilter = Arc<dyn Fn(&UtcpTool) -> bool + Send + Sync>;
+
+/// Finds tools missing from a catalog in a UTCP repository.
+///
+/// [`search`](UtcpDiscovery::search) looks tools up with `search_tools`
+/// without touching the catalog; [`discover_exact`](UtcpDiscovery::discover_exact)
+/// registers the one tool a call names, and
+/// [`discover`](UtcpDiscovery::discover) every match. Anything the
+/// repository returns can end up in the catalog, so restrict it with
+/// [`with_filter`](UtcpDiscovery::with_filter) when the repository isn't
+/// fully trusted. See
+/// [`Agent::with_utcp_discovery`](crate::Agent::with_utcp_discovery).
+pub struct UtcpDiscovery {
+    client: Arc<dyn UtcpClientInterface>,
+    limit: usize,
+    filter: Option<DiscoveryFilter>,
+}
+
+impl UtcpDiscovery {
+    pub fn new(client: Arc<dyn UtcpClientInterface>) -> Self {
+        Self {
+            client,
+            limit: DEFAULT_DISCOVERY_LIMIT,
+            filter: None,
+        }
+    }
+
+    /// Returns at most `limit` matches per search
+    pub fn with_limit(mut self, limit: usize) -> Self {
+        self.limit = limit.max(1);
+        self
+    }
+
+    /// Only uses the tools `filter` accepts, e.g. those of an allowlist of
+    /// providers
+    pub fn with_filter(
+        mut self,
+        filter: impl Fn(&UtcpTool) -> bool + Send + Sync + 'static,
+    ) -> Self {
+        self.filter = Some(Arc::new(filter));
+        self
+    }
+
+    /// Searches the repository for `query`, returning the matches missing
+    /// from `catalog` that the filter accepts, without registering them
+    pub async fn search(&self, catalog: &dyn ToolRegistry, query: &str) -> Result<Vec<UtcpTool>> {
+        let found = self
+            .client
+            .search_tools(query, self.limit)
+            .await
+            .map_err(|e| AgentError::UtcpError(e.to_string()))?;
+        Ok(found
+            .into_iter()
+            .filter(|tool| catalog.get(&tool.name).is_none())
+            .filter(|tool| self.filter.as_ref().is_none_or(|accepts| accepts(tool)))
+            .take(self.limit)
+            .collect())
+    }
+
+    /// Returns the specs of [`search`](UtcpDiscovery::search)'s matches
+    pub async fn search_specs(
+        &self,
+        catalog: &dyn ToolRegistry,
+        query: &str,
+    ) -> Result<Vec<ToolSpec>> {
+        let found = self.search(catalog, query).await?;
+        Ok(found
+            .into_iter()
+            .map(|tool| UtcpToolAdapter::new(Arc::clone(&self.client), tool).spec())
+            .collect())
+    }
+
+    /// Registers the tool named `name` if the repository has it and the
+    /// filter accepts it, returning whether it did
+    pub async fn discover_exact(&self, catalog: &dyn ToolRegistry, name: &str) -> Result<bool> {
+        let found = self.search(catalog, name).await?;
+        let Some(tool) = found.into_iter().find(|tool| tool.name == name) else {
+            return Ok(false);
+        };
+        register_utcp_tools(catalog, Arc::clone(&self.client), vec![tool])?;
+        Ok(true)
+    }
+
+    /// Searches the repository for `query` and registers the matches
+    /// missing from `catalog`, returning their names
+    pub async fn discover(&self, catalog: &dyn ToolRegistry, query: &str) -> Result<Vec<String>> {
+        let missing = self.search(catalog, query).await?;
+        let names = missing.iter().map(|tool| tool.name.clone()).collect();
+        register_utcp_tools(catalog, Arc::clone(&self.client), missing)?;
+        Ok(names)
+    }
+}
+
+/// A provider registered through a [`UtcpHub`]
+struct HubProvider {
+    tools: Vec<UtcpTool>,
+    // Agent ids using the provider
+    owners: HashSet<String>,
+}
+
+/// Shares one UTCP client between the agents of a process.
+///
+/// Each provider is registered with the client once, however many agents use
+/// it; later agents get the cached tools added to their catalog. The hub
+/// counts which agents hold each provider and only deregisters it from the
+/// client when the last one lets go.
+pub struct UtcpHub {
+    client: Arc<dyn UtcpClientInterface>,
+    // Held across client calls so concurrent registrations of one provider
+    // don't race
+    providers: tokio::sync::Mutex<HashMap<String, HubProvider>>,
+}
+
+impl UtcpHub {
+    pub fn new(client: Arc<dyn UtcpClientInterface>) -> Self {
+        Self {
+            client,
+            providers: tokio::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the shared client
+    pub fn client(&self) -> Arc<dyn UtcpClientInterface> {
+        Arc::clone(&self.client)
+    }
+
+    /// Registers `provider` for `agent_id` and adds its tools to `catalog`.
+    ///
+    /// The provider is only discovered through the client the first time it
+    /// is registered.
+    pub async fn register_provider(
+        &self,
+        agent_id: &str,
+        catalog: &dyn ToolRegistry,
+        provider: Arc<dyn Provider>,
+    ) -> Result<Vec<UtcpTool>> {
+        self.register(agent_id, catalog, provider, None).await
+    }
+
+    /// Like [`UtcpHub::register_provider`], with a predefined set of tools
+    pub async fn register_provider_with_tools(
+        &self,
+        agent_id: &str,
+        catalog: &dyn ToolRegistry,
+        provider: Arc<dyn Provider>,
+        tools: Vec<UtcpTool>,
+    ) -> Result<Vec<UtcpTool>> {
+        self.register(agent_id, catalog, provider, Some(tools))
+            .await
+    }
+
+    async fn register(
+        &self,
+        agent_id: &str,
+        catalog: &dyn ToolRegistry,
+        provider: Arc<dyn Provider>,
+        tools: Option<Vec<UtcpTool>>,
+    ) -> Result<Vec<UtcpTool>> {
+        let name = provider.name();
+        let mut providers = self.providers.lock().await;
+        let entry = match providers.entry(name) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let registered = match tools {
+                    Some(tools) => {
+                        self.client
+                            .register_tool_provider_with_tools(provider, tools)
+                            .await
+                    }
+                    None => self.client.register_tool_provider(provider).await,
+                }
+                .map_err(|e| AgentError::UtcpError(e.to_string()))?;
+                entry.insert(HubProvider {
+                    tools: registered,
+                    owners: HashSet::new(),
+                })
+            }
+        };
+
+        register_utcp_tools(catalog, Arc::clone(&self.client), entry.tools.clone())?;
+        entry.owners.insert(agent_id.to_string());
+        Ok(entry.tools.clone())
+    }
+
+    /// Exposes `agent` to the other agents as a UTCP provider named after the
+    /// prefix of `name`, owned by `agent_id`.
+    ///
+    /// Fails if another agent already registered a provider of that name, as
+    /// both would answer the same tool calls.
+    pub async fn register_agent(
+        &self,
+        agent_id: &str,
+        agent: Arc<Agent>,
+        name: &str,
+        description: &str,
+    ) -> Result<()> {
+        let tool = agent.as_utcp_tool(name, description);
+        let provider_name = name
+            .split('.')
+            .next()
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .unwrap_or("agent")
+            .to_string();
+
+        let mut providers = self.providers.lock().await;
+        if let Some(existing) = providers.get(&provider_name) {
+            if existing.owners.len() == 1 && existing.owners.contains(agent_id) {
+                return Ok(());
+            }
+            return Err(AgentError::ConfigError(format!(
+                "UTCP provider {} is already registered",
+                provider_name
+            )));
+        }
+
+        agent
+            .register_as_utcp_provider(self.client.as_ref(), name, description)
+            .await?;
+        providers.insert(
+            provider_name,
+            HubProvider {
+                tools: vec![tool],
+                owners: HashSet::from([agent_id.to_string()]),
+            },
+        );
+        Ok(())
+    }
+
+    /// Releases `agent_id`'s hold on a provider and removes its tools from
+    /// `catalog`.
+    ///
+    /// Returns true if this was the last holder and the provider was
+    /// deregistered from the client.
+    pub async fn deregister_provider(
+        &self,
+        agent_id: &str,
+        catalog: &dyn ToolRegistry,
+        provider_name: &str,
+    ) -> Result<bool> {
+        let mut providers = self.providers.lock().await;
+        let Some(entry) = providers.get_mut(provider_name) else {
+            return Ok(false);
+        };
+        if !entry.owners.remove(agent_id) {
+            return Ok(false);
+        }
+        for tool in &entry.tools {
+            catalog.unregister(&tool.name);
+        }
+        if !entry.owners.is_empty() {
+            return Ok(false);
+        }
+
+        if let Err(e) = self.client.deregister_tool_provider(provider_name).await {
+            // Keep the entry so the provider isn't registered a second time
+            entry.owners.insert(agent_id.to_string());
+            return Err(AgentError::UtcpError(e.to_string()));
+        }
+        providers.remove(provider_name);
+        Ok(true)
+    }
+
+    /// Releases every provider held by `agent_id`, returning how many were
+    /// deregistered from the client
+    pub async fn release_agent(&self, agent_id: &str, catalog: &dyn ToolRegistry) -> Result<usize> {
+        let held: Vec<String> = {
+            let providers = self.providers.lock().await;
+            providers
+                .iter()
+                .filter(|(_, entry)| entry.owners.contains(agent_id))
+                .map(|(name, _)| name.clone())
+                .collect()
+        };
+
+        let mut released = 0;
+        for name in held {
+            if self.deregister_provider(agent_id, catalog, &name).await? {
+                released += 1;
+            }
+        }
+        Ok(released)
+    }
+
+    /// Returns the names of the registered providers
+    pub async fn providers(&self) -> Vec<String> {
+        self.providers.lock().await.keys().cloned().collect()
+    }
+
+    /// Returns the agent ids holding `provider_name`
+    pub async fn owners(&self, provider_name: &str) -> Vec<String> {
+        self.providers
+            .lock()
+            .await
+            .get(provider_name)
+            .map(|entry| entry.owners.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::agent::Agent;
     use crate::memory::{InMemoryStore, SessionMemory};
     use crate::models::LLM;
+    use crate::tools::ToolCatalog;
     use crate::types::{AgentOptions, File, GenerationResponse, Message};
     use anyhow::anyhow;
     use rs_utcp::providers::base::Provider;
     use rs_utcp::tools::ToolInputOutputSchema;
-    use rs_utcp::transports::stream::StreamResult;
+    use rs_utcp::transports::stream::VecStreamResult;
     use rs_utcp::transports::CommunicationProtocol;
+    use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Mutex;
 
+    /// Endless stream recording whether it was closed
+    struct FollowStream {
+        closed: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl StreamResult for FollowStream {
+        async fn next(&mut self) -> anyhow::Result<Option<serde_json::Value>> {
+            Ok(Some(serde_json::json!("line\n")))
+        }
+
+        async fn close(&mut self) -> anyhow::Result<()> {
+            self.closed.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
     struct MockUtcpClient {
         calls: Mutex<Vec<(String, HashMap<String, serde_json::Value>)>>,
+        registered: Mutex<Vec<String>>,
+        deregistered: Mutex<Vec<String>>,
+        searchable: Vec<UtcpTool>,
+        closed: Arc<AtomicBool>,
     }
 
     impl MockUtcpClient {
         fn new() -> Self {
             Self {
                 calls: Mutex::new(Vec::new()),
+                registered: Mutex::new(Vec::new()),
+                deregistered: Mutex::new(Vec::new()),
+                searchable: Vec::new(),
+                closed: Arc::new(AtomicBool::new(false)),
             }
         }
     }
@@ -113,13 +516,18 @@ mod tests {
 
         async fn register_tool_provider_with_tools(
             &self,
-            _prov: Arc<dyn Provider>,
+            prov: Arc<dyn Provider>,
             tools: Vec<UtcpTool>,
         ) -> anyhow::Result<Vec<UtcpTool>> {
+            self.registered.lock().unwrap().push(prov.name());
             Ok(tools)
         }
 
-        async fn deregister_tool_provider(&self, _provider_name: &str) -> anyhow::Result<()> {
+        async fn deregister_tool_provider(&self, provider_name: &str) -> anyhow::Result<()> {
+            self.deregistered
+                .lock()
+                .unwrap()
+                .push(provider_name.to_string());
             Ok(())
         }
 
@@ -135,8 +543,14 @@ mod tests {
             Ok(serde_json::json!({"ok": true}))
         }
 
-        async fn search_tools(&self, _query: &str, _limit: usize) -> anyhow::Result<Vec<UtcpTool>> {
-            Ok(vec![])
+        async fn search_tools(&self, query: &str, limit: usize) -> anyhow::Result<Vec<UtcpTool>> {
+            Ok(self
+                .searchable
+                .iter()
+                .filter(|tool| tool.name.contains(query) || tool.description.contains(query))
+                .take(limit)
+                .cloned()
+                .collect())
         }
 
         fn get_transports(&self) -> HashMap<String, Arc<dyn CommunicationProtocol>> {
@@ -145,10 +559,19 @@ mod tests {
 
         async fn call_tool_stream(
             &self,
-            _tool_name: &str,
+            tool_name: &str,
             _args: HashMap<String, serde_json::Value>,
         ) -> anyhow::Result<Box<dyn StreamResult>> {
-            Err(anyhow!("not implemented"))
+            if tool_name == "dummy.follow" {
+                return Ok(Box::new(FollowStream {
+                    closed: Arc::clone(&self.closed),
+                }));
+            }
+            if tool_name != "dummy.tail" {
+                return Err(anyhow!("not implemented"));
+            }
+            let lines = vec![serde_json::json!("line 1\n"), serde_json::json!("line 2\n")];
+            Ok(Box::new(VecStreamResult::new(lines, None)))
         }
     }
 
@@ -173,13 +596,8 @@ mod tests {
         }
     }
 
-    #[tokio::test]
-    async fn registers_and_invokes_utcp_tool() {
-        let client = Arc::new(MockUtcpClient::new());
-        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
-        let agent = Agent::new(Arc::new(MockLLM), memory, AgentOptions::default());
-
-        let tool = UtcpTool {
+    fn echo_tool() -> UtcpTool {
+        UtcpTool {
             name: "dummy.echo".to_string(),
             description: "Echo via UTCP".to_string(),
             inputs: ToolInputOutputSchema {
@@ -212,7 +630,16 @@ mod tests {
             tags: vec![],
             average_response_size: None,
             provider: None,
-        };
+        }
+    }
+
+    #[tokio::test]
+    async fn registers_and_invokes_utcp_tool() {
+        let client = Arc::new(MockUtcpClient::new());
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(Arc::new(MockLLM), memory, AgentOptions::default());
+
+        let tool = echo_tool();
 
         register_utcp_tools(agent.tools().as_ref(), client.clone(), vec![tool]).unwrap();
 
@@ -226,4 +653,167 @@ mod tests {
         assert_eq!(calls.len(), 1);
         assert_eq!(calls[0].0, "dummy.echo");
     }
+
+    #[test]
+    fn declares_structured_outputs() {
+        let client = Arc::new(MockUtcpClient::new());
+        let plain = UtcpToolAdapter::new(client.clone(), echo_tool());
+        assert!(plain.spec().output_schema.is_none());
+
+        let mut tool = echo_tool();
+        tool.outputs.properties = Some(HashMap::from([(
+            "ok".to_string(),
+            serde_json::json!({"type": "boolean"}),
+        )]));
+        let spec = UtcpToolAdapter::new(client, tool).spec();
+        assert_eq!(
+            spec.output_schema.unwrap()["properties"]["ok"]["type"],
+            "boolean"
+        );
+    }
+
+    #[tokio::test]
+    async fn hub_shares_providers_between_agents() {
+        let client = Arc::new(MockUtcpClient::new());
+        let hub = UtcpHub::new(client.clone());
+        let planner = ToolCatalog::new();
+        let writer = ToolCatalog::new();
+        let provider = || -> Arc<dyn Provider> {
+            Arc::new(rs_utcp::providers::cli::CliProvider::new(
+                "dummy".to_string(),
+                "dummy".to_string(),
+                None,
+            ))
+        };
+
+        for (agent_id, catalog) in [("planner", &planner), ("writer", &writer)] {
+            hub.register_provider_with_tools(agent_id, catalog, provider(), vec![echo_tool()])
+                .await
+                .unwrap();
+            assert!(catalog.lookup("dummy.echo").is_some());
+        }
+        assert_eq!(client.registered.lock().unwrap().len(), 1);
+
+        assert!(!hub
+            .deregister_provider("planner", &planner, "dummy")
+            .await
+            .unwrap());
+        assert!(planner.lookup("dummy.echo").is_none());
+        assert!(client.deregistered.lock().unwrap().is_empty());
+
+        assert_eq!(hub.release_agent("writer", &writer).await.unwrap(), 1);
+        assert_eq!(
+            *client.deregistered.lock().unwrap(),
+            vec!["dummy".to_string()]
+        );
+        assert!(hub.providers().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn discovers_missing_tools_before_calling_them() {
+        let client = Arc::new(MockUtcpClient {
+            searchable: vec![echo_tool()],
+            ..MockUtcpClient::new()
+        });
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(Arc::new(MockLLM), memory, AgentOptions::default())
+            .with_utcp_discovery(UtcpDiscovery::new(client.clone()));
+
+        let args = HashMap::from([("text".to_string(), serde_json::json!("hi"))]);
+        let result = agent.invoke_tool("s", "dummy.echo", args).await.unwrap();
+        assert_eq!(result, r#"{"ok":true}"#);
+        assert_eq!(client.calls.lock().unwrap()[0].0, "dummy.echo");
+
+        // Registered tools aren't discovered again
+        assert!(agent.discover_tools("Echo").await.unwrap().is_empty());
+        assert!(matches!(
+            agent.invoke_tool("s", "weather", HashMap::new()).await,
+            Err(AgentError::ToolNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn discovery_registers_only_the_called_tool_it_accepts() {
+        let loud = UtcpTool {
+            name: "dummy.echo_loud".to_string(),
+            ..echo_tool()
+        };
+        let untrusted = UtcpTool {
+            name: "other.echo".to_string(),
+            ..echo_tool()
+        };
+        let client = Arc::new(MockUtcpClient {
+            searchable: vec![loud, echo_tool(), untrusted],
+            ..MockUtcpClient::new()
+        });
+        let discovery =
+            UtcpDiscovery::new(client.clone()).with_filter(|tool| tool.name.starts_with("dummy."));
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(Arc::new(MockLLM), memory, AgentOptions::default())
+            .with_utcp_discovery(discovery);
+
+        let args = HashMap::from([("text".to_string(), serde_json::json!("hi"))]);
+        agent.invoke_tool("s", "dummy.echo", args).await.unwrap();
+        assert!(agent.tools().get("dummy.echo").is_some());
+        assert!(agent.tools().get("dummy.echo_loud").is_none());
+
+        assert!(matches!(
+            agent.invoke_tool("s", "other.echo", HashMap::new()).await,
+            Err(AgentError::ToolNotFound(_))
+        ));
+        assert_eq!(client.calls.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn streams_utcp_tool_results() {
+        let client = Arc::new(MockUtcpClient::new());
+        let memory = Arc::new(SessionMemory::new(Box::new(InMemoryStore::new()), 4));
+        let agent = Agent::new(Arc::new(MockLLM), memory, AgentOptions::default());
+        let tail = UtcpTool {
+            name: "dummy.tail".to_string(),
+            ..echo_tool()
+        };
+        register_utcp_tools(agent.tools().as_ref(), client, vec![tail, echo_tool()]).unwrap();
+
+        let args = HashMap::from([("text".to_string(), serde_json::json!("log"))]);
+        let chunks: Vec<String> = agent
+            .invoke_tool_stream("s", "dummy.tail", args.clone())
+            .await
+            .unwrap()
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        assert_eq!(chunks, ["line 1\n", "line 2\n"]);
+
+        // The mock only streams dummy.tail
+        assert!(matches!(
+            agent.invoke_tool_stream("s", "dummy.echo", args).await,
+            Err(AgentError::UtcpError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn closes_utcp_streams_dropped_early() {
+        let client = Arc::new(MockUtcpClient::new());
+        let follow = UtcpTool {
+            name: "dummy.follow".to_string(),
+            ..echo_tool()
+        };
+        let adapter = UtcpToolAdapter::new(client.clone(), follow);
+
+        let mut stream = adapter
+            .invoke_stream(ToolRequest::new("s", HashMap::new()))
+            .await
+            .unwrap();
+        assert_eq!(stream.next().await.unwrap().unwrap().content, "line\n");
+        drop(stream);
+
+        for _ in 0..10 {
+            if client.closed.load(Ordering::SeqCst) {
+                break;
+            }
+            tokio::task::yield_now().await;
+        }
+        assert!(client.closed.load(Ordering::SeqCst));
+    }
 }